@@ -0,0 +1,178 @@
+//! Diagnostics for assessing how lossy a clustering is. Every cluster member is compared to its
+//! representative using a user-provided feature and distance, so that clustering thresholds can be
+//! tuned systematically.
+
+use parsimon_core::{
+    cluster::Cluster,
+    network::{types::FlowChannel, EdgeIndex, Flow, SimNetwork},
+    routing::RoutingAlgo,
+};
+use rayon::prelude::*;
+
+/// Computes quality metrics for the clusters currently set on `network`. `feature` extracts
+/// features from a link, and `distance` measures how far a member's features are from its
+/// representative's (e.g., [`DistsAndLoad::max_wmape`](crate::feature::DistsAndLoad::max_wmape)).
+pub fn diagnose<R, F, D, X>(network: &SimNetwork<R>, feature: F, distance: D) -> ClusterDiagnostics
+where
+    R: RoutingAlgo + Sync,
+    F: Fn(&FlowChannel, &[Flow]) -> X + Sync,
+    D: Fn(&X, &X) -> f64 + Sync,
+    X: Send,
+{
+    let extract = |eidx: EdgeIndex| {
+        let chan = network.edge(eidx).expect("invalid `eidx` in `diagnose`");
+        let flows = network
+            .flows_on(eidx)
+            .expect("invalid `eidx` in `diagnose`");
+        feature(chan, &flows)
+    };
+    let clusters = network
+        .clusters()
+        .par_iter()
+        .map(|cluster| {
+            let representative = cluster.representative();
+            let rfeat = extract(representative);
+            let distances = cluster
+                .members()
+                .filter(|&&member| member != representative)
+                .map(|&member| distance(&rfeat, &extract(member)))
+                .collect::<Vec<_>>();
+            ClusterQuality::new(cluster, network, &distances)
+        })
+        .collect::<Vec<_>>();
+    let summary = ClusteringSummary::new(&clusters);
+    ClusterDiagnostics { clusters, summary }
+}
+
+/// Quality metrics for a clustering.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ClusterDiagnostics {
+    /// Per-cluster metrics.
+    pub clusters: Vec<ClusterQuality>,
+    /// Metrics aggregated over all clusters.
+    pub summary: ClusteringSummary,
+}
+
+/// Quality metrics for a single cluster.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ClusterQuality {
+    /// The edge index of the representative.
+    pub representative: usize,
+    /// The number of members, including the representative.
+    pub nr_members: usize,
+    /// The number of flows traversing the representative.
+    pub nr_flows: usize,
+    /// The maximum distance between a member and the representative.
+    pub max_distance: f64,
+    /// The mean distance between a member and the representative.
+    pub mean_distance: f64,
+}
+
+impl ClusterQuality {
+    fn new<R>(cluster: &Cluster, network: &SimNetwork<R>, distances: &[f64]) -> Self
+    where
+        R: RoutingAlgo + Sync,
+    {
+        let representative = cluster.representative();
+        let (max_distance, mean_distance) = if distances.is_empty() {
+            (0.0, 0.0)
+        } else {
+            (
+                distances.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                distances.iter().sum::<f64>() / distances.len() as f64,
+            )
+        };
+        Self {
+            representative: representative.index(),
+            nr_members: cluster.members().count(),
+            nr_flows: network
+                .edge(representative)
+                .map_or(0, |chan| chan.nr_flows()),
+            max_distance,
+            mean_distance,
+        }
+    }
+}
+
+/// Metrics aggregated over all clusters.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ClusteringSummary {
+    /// The number of clusters, i.e., the number of link simulations.
+    pub nr_clusters: usize,
+    /// The number of clustered edges.
+    pub nr_edges: usize,
+    /// The number of edges per cluster.
+    pub reduction: f64,
+    /// The size of the largest cluster.
+    pub max_cluster_size: usize,
+    /// The largest distance between any member and its representative.
+    pub max_distance: f64,
+    /// The mean of the per-cluster maximum distances.
+    pub mean_max_distance: f64,
+}
+
+impl ClusteringSummary {
+    fn new(clusters: &[ClusterQuality]) -> Self {
+        let nr_clusters = clusters.len();
+        let nr_edges = clusters.iter().map(|c| c.nr_members).sum::<usize>();
+        let mean_max_distance = if nr_clusters == 0 {
+            0.0
+        } else {
+            clusters.iter().map(|c| c.max_distance).sum::<f64>() / nr_clusters as f64
+        };
+        Self {
+            nr_clusters,
+            nr_edges,
+            reduction: if nr_clusters == 0 {
+                0.0
+            } else {
+                nr_edges as f64 / nr_clusters as f64
+            },
+            max_cluster_size: clusters.iter().map(|c| c.nr_members).max().unwrap_or(0),
+            max_distance: clusters.iter().map(|c| c.max_distance).fold(0.0, f64::max),
+            mean_max_distance,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parsimon_core::{
+        cluster::Cluster,
+        network::{FlowId, Network, NodeId},
+        testing,
+        units::{Bytes, Nanosecs},
+    };
+
+    use super::*;
+
+    #[test]
+    fn diagnose_reports_cluster_sizes() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 2),
+                dst: NodeId::new(2 + i % 2),
+                size: Bytes::new(1000 * (i as u64 + 1)),
+                start: Nanosecs::new(1000 * i as u64),
+            })
+            .collect();
+        let mut network = Network::new(&nodes, &links)?.into_simulations(flows);
+        let edges = network.edge_indices().collect::<Vec<_>>();
+        network.set_clusters(vec![
+            Cluster::new(edges[0], edges[..4].iter().copied().collect()),
+            Cluster::new(edges[4], edges[4..].iter().copied().collect()),
+        ])?;
+        let diagnostics = diagnose(
+            &network,
+            |chan, _| chan.nr_flows() as f64,
+            |a, b| (a - b).abs(),
+        );
+        assert_eq!(diagnostics.summary.nr_clusters, 2);
+        assert_eq!(diagnostics.summary.nr_edges, edges.len());
+        assert_eq!(diagnostics.summary.max_cluster_size, edges.len() - 4);
+        assert!(diagnostics.summary.max_distance > 0.0);
+        Ok(())
+    }
+}
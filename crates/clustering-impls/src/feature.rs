@@ -1,8 +1,18 @@
 //! Routines for extracting features from links. Features are compared to determine whether links
 //! should be clustered together.
 
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+};
+
 use parsimon_core::{
-    network::{types::FlowChannel, Channel, Flow},
+    metrics,
+    network::{
+        types::{FlowChannel, Link, Node, NodeId},
+        Channel, Flow, LinkLoad, NodeKind,
+    },
+    oversubscription::OversubscriptionReport,
     units::{Bytes, Nanosecs},
 };
 
@@ -11,16 +21,13 @@ use crate::utils;
 /// Extracts flow size distribution, inter-arrival time distribution, and link load. Distributions
 /// are returned as a vector of 1000 quantiles.
 ///
-/// `flows` must contain at least two elements, otherwise this routine will return `None`.
+/// `flows` must contain at least two elements, otherwise this routine will return `None`. Loads of
+/// degenerate channels follow [`LinkLoad::value`], so features are never NaN.
 pub fn dists_and_load(chan: &FlowChannel, flows: &[Flow]) -> Option<DistsAndLoad> {
     (flows.len() >= 2).then(|| {
         let sizes = utils::percentiles(flows, |f| f.size);
         let deltas = utils::percentiles(&utils::deltas(flows), |&x| x);
-        let nr_bytes = flows.iter().map(|f| f.size).sum::<Bytes>();
-        let duration =
-            flows.last().map(|f| f.start).unwrap() - flows.first().map(|f| f.start).unwrap();
-        let bps = nr_bytes.into_f64() * 8.0 * 1e9 / duration.into_f64();
-        let load = bps / chan.bandwidth().into_f64();
+        let load = load(chan, flows);
         DistsAndLoad {
             sizes,
             deltas,
@@ -29,6 +36,13 @@ pub fn dists_and_load(chan: &FlowChannel, flows: &[Flow]) -> Option<DistsAndLoad
     })
 }
 
+/// Like [`dists_and_load`], but returns the features as a vector (see
+/// [`DistsAndLoad::to_vector`]), e.g., for use with
+/// [`KMeansClustering`](crate::kmeans::KMeansClustering).
+pub fn dists_and_load_vector(chan: &FlowChannel, flows: &[Flow]) -> Option<Vec<f64>> {
+    dists_and_load(chan, flows).map(|x| x.to_vector())
+}
+
 /// Flow size distribution, inter-arrival time distribution, and link load.
 #[derive(Debug, Clone)]
 pub struct DistsAndLoad {
@@ -39,3 +53,522 @@ pub struct DistsAndLoad {
     /// The link load.
     pub load: f64,
 }
+
+impl DistsAndLoad {
+    /// Embeds the features in a vector: every tenth quantile of the log-scaled size and
+    /// inter-arrival time distributions, followed by the load.
+    pub fn to_vector(&self) -> Vec<f64> {
+        log_subsample(&self.sizes)
+            .into_iter()
+            .chain(log_subsample(&self.deltas))
+            .chain(std::iter::once(self.load))
+            .collect()
+    }
+
+    /// Returns the largest of the WMAPEs between the size distributions, the inter-arrival time
+    /// distributions, and the loads of `self` and `other`, using `self` as the reference.
+    pub fn max_wmape(&self, other: &Self) -> f64 {
+        let sizes = utils::wmape(&self.sizes, &other.sizes);
+        let deltas = utils::wmape(&self.deltas, &other.deltas);
+        let load = utils::wmape(&[self.load], &[other.load]);
+        sizes.max(deltas).max(load)
+    }
+}
+
+/// Like [`DistsAndLoad::max_wmape`], but for links whose features may be missing. Links without
+/// features are only close to each other.
+pub fn dists_and_load_distance(a: &Option<DistsAndLoad>, b: &Option<DistsAndLoad>) -> f64 {
+    match (a, b) {
+        (Some(a), Some(b)) => a.max_wmape(b),
+        (None, None) => 0.0,
+        _ => f64::INFINITY,
+    }
+}
+
+/// A feature extractor embeds a link and the flows traversing it in a vector, e.g., for use with
+/// [`KMeansClustering`](crate::kmeans::KMeansClustering). Extractors can be combined with
+/// [`CompositeFeatures`].
+///
+/// Closures with the right signature are extractors too.
+pub trait FeatureExtractor: Sync {
+    /// Returns the features of `chan`, or `None` if they can't be extracted (e.g., too few
+    /// flows).
+    fn extract(&self, chan: &FlowChannel, flows: &[Flow]) -> Option<Vec<f64>>;
+
+    /// Converts the extractor into a closure accepted by the clustering algorithms.
+    fn into_fn(self) -> impl Fn(&FlowChannel, &[Flow]) -> Option<Vec<f64>> + Sync
+    where
+        Self: Sized,
+    {
+        move |chan: &FlowChannel, flows: &[Flow]| self.extract(chan, flows)
+    }
+}
+
+impl<F> FeatureExtractor for F
+where
+    F: Fn(&FlowChannel, &[Flow]) -> Option<Vec<f64>> + Sync,
+{
+    fn extract(&self, chan: &FlowChannel, flows: &[Flow]) -> Option<Vec<f64>> {
+        self(chan, flows)
+    }
+}
+
+/// Extracts the link load. Links without flows have zero load.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Load;
+
+impl FeatureExtractor for Load {
+    fn extract(&self, chan: &FlowChannel, flows: &[Flow]) -> Option<Vec<f64>> {
+        Some(vec![load(chan, flows)])
+    }
+}
+
+/// Extracts every tenth of 1000 quantiles of the log-scaled flow size distribution. Requires at
+/// least one flow.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SizeCdf;
+
+impl FeatureExtractor for SizeCdf {
+    fn extract(&self, _: &FlowChannel, flows: &[Flow]) -> Option<Vec<f64>> {
+        (!flows.is_empty()).then(|| log_subsample(&utils::percentiles(flows, |f| f.size)))
+    }
+}
+
+/// Extracts the link load followed by every tenth of 1000 quantiles of the log-scaled
+/// inter-arrival time distribution. Requires at least two flows.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadAndInterarrival;
+
+impl FeatureExtractor for LoadAndInterarrival {
+    fn extract(&self, chan: &FlowChannel, flows: &[Flow]) -> Option<Vec<f64>> {
+        (flows.len() >= 2).then(|| {
+            let deltas = utils::percentiles(&utils::deltas(flows), |&x| x);
+            std::iter::once(load(chan, flows))
+                .chain(log_subsample(&deltas))
+                .collect()
+        })
+    }
+}
+
+/// Extracts the tiers of a link's endpoints followed by its load. A node's tier is its distance
+/// in hops from the nearest host, so host links are between tiers 0 and 1, ToR-to-aggregation
+/// links are between tiers 1 and 2, and so on. Links with an endpoint unknown to the extractor
+/// are skipped.
+#[derive(Debug, Clone)]
+pub struct TierAndLoad {
+    tiers: HashMap<NodeId, usize>,
+}
+
+impl TierAndLoad {
+    /// Creates an extractor for a topology with the given nodes and links.
+    pub fn new(nodes: &[Node], links: &[Link]) -> Self {
+        let mut neighbors = HashMap::<NodeId, Vec<NodeId>>::new();
+        for link in links {
+            neighbors.entry(link.a).or_default().push(link.b);
+            neighbors.entry(link.b).or_default().push(link.a);
+        }
+        let mut tiers = HashMap::new();
+        let mut queue = VecDeque::new();
+        for node in nodes.iter().filter(|n| matches!(n.kind, NodeKind::Host)) {
+            tiers.insert(node.id, 0);
+            queue.push_back(node.id);
+        }
+        while let Some(id) = queue.pop_front() {
+            let tier = tiers[&id];
+            for &next in neighbors.get(&id).into_iter().flatten() {
+                tiers.entry(next).or_insert_with(|| {
+                    queue.push_back(next);
+                    tier + 1
+                });
+            }
+        }
+        Self { tiers }
+    }
+
+    /// Returns the tier of a node, or `None` if the node is unknown or unreachable from any host.
+    pub fn tier(&self, id: NodeId) -> Option<usize> {
+        self.tiers.get(&id).copied()
+    }
+}
+
+impl FeatureExtractor for TierAndLoad {
+    fn extract(&self, chan: &FlowChannel, flows: &[Flow]) -> Option<Vec<f64>> {
+        let src = self.tier(chan.src())?;
+        let dst = self.tier(chan.dst())?;
+        Some(vec![src as f64, dst as f64, load(chan, flows)])
+    }
+}
+
+/// Extracts the oversubscription of a link: the larger of its endpoints' oversubscription ratios,
+/// or 1 if neither endpoint is oversubscribable (see [`OversubscriptionReport`]). Links in
+/// oversubscribed tiers queue differently from otherwise similar links in non-blocking tiers.
+#[derive(Debug, Clone)]
+pub struct Oversubscription {
+    report: OversubscriptionReport,
+}
+
+impl Oversubscription {
+    /// Creates an extractor for a topology with the given nodes and links.
+    pub fn new(nodes: &[Node], links: &[Link]) -> Self {
+        Self {
+            report: OversubscriptionReport::new(nodes, links),
+        }
+    }
+}
+
+impl FeatureExtractor for Oversubscription {
+    fn extract(&self, chan: &FlowChannel, _: &[Flow]) -> Option<Vec<f64>> {
+        let ratio = [chan.src(), chan.dst()]
+            .into_iter()
+            .filter_map(|id| self.report.of(id))
+            .fold(1.0, f64::max);
+        Some(vec![ratio])
+    }
+}
+
+/// Extracts the deciles (from minimum to maximum) of a link's offered-load time series: the load
+/// offered by its flows in each consecutive interval of length `interval`, measured from the first
+/// flow's start (see [`metrics::offered_loads`]). Unlike [`Load`], this tells steady links from links
+/// with the same average load in bursts. Requires at least one flow, and an interval long enough
+/// for the link to carry at least one byte. Links whose flows carry no bytes have zero deciles.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadSeries {
+    interval: Nanosecs,
+}
+
+impl LoadSeries {
+    /// Creates an extractor with intervals of length `interval`.
+    pub fn new(interval: Nanosecs) -> Self {
+        assert!(
+            interval > Nanosecs::ZERO,
+            "load series intervals must not be empty"
+        );
+        Self { interval }
+    }
+}
+
+impl FeatureExtractor for LoadSeries {
+    fn extract(&self, chan: &FlowChannel, flows: &[Flow]) -> Option<Vec<f64>> {
+        if metrics::bdp(chan.bandwidth(), self.interval) == Bytes::ZERO {
+            return None;
+        }
+        let first = flows.first()?.start;
+        let shifted = flows
+            .iter()
+            .map(|f| Flow {
+                start: f.start - first,
+                ..*f
+            })
+            .collect::<Vec<_>>();
+        let mut loads = metrics::offered_loads(chan.bandwidth(), self.interval, &shifted);
+        // Zero-byte flows offer no load
+        if loads.is_empty() {
+            return Some(vec![0.0; 11]);
+        }
+        loads.sort_by(f64::total_cmp);
+        let last = loads.len() - 1;
+        Some((0..=10).map(|decile| loads[decile * last / 10]).collect())
+    }
+}
+
+/// Extracts the burstiness of a link's flow arrivals: the index of dispersion (variance over
+/// mean) of the number of flows starting in each interval of length `interval`. Poisson arrivals
+/// have an index of one, and burstier arrivals a larger one. Requires at least two flows.
+#[derive(Debug, Clone, Copy)]
+pub struct Burstiness {
+    interval: Nanosecs,
+}
+
+impl Burstiness {
+    /// Creates an extractor with intervals of length `interval`.
+    pub fn new(interval: Nanosecs) -> Self {
+        assert!(
+            interval > Nanosecs::ZERO,
+            "burstiness intervals must not be empty"
+        );
+        Self { interval }
+    }
+}
+
+impl FeatureExtractor for Burstiness {
+    fn extract(&self, _: &FlowChannel, flows: &[Flow]) -> Option<Vec<f64>> {
+        if flows.len() < 2 {
+            return None;
+        }
+        let first = flows.iter().map(|f| f.start).min()?;
+        let bin = |f: &Flow| ((f.start - first).into_u64() / self.interval.into_u64()) as usize;
+        let mut counts = vec![0.0; flows.iter().map(bin).max()? + 1];
+        for flow in flows {
+            counts[bin(flow)] += 1.0;
+        }
+        let (mean, variance) = mean_and_variance(&counts);
+        Some(vec![variance / mean])
+    }
+}
+
+/// Extracts the number of distinct sources and of distinct destinations among a link's flows.
+/// Links carrying the same load for many senders and for a few heavy ones see different incast.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FanIn;
+
+impl FeatureExtractor for FanIn {
+    fn extract(&self, _: &FlowChannel, flows: &[Flow]) -> Option<Vec<f64>> {
+        let srcs = flows.iter().map(|f| f.src).collect::<HashSet<_>>();
+        let dsts = flows.iter().map(|f| f.dst).collect::<HashSet<_>>();
+        Some(vec![srcs.len() as f64, dsts.len() as f64])
+    }
+}
+
+/// Extracts the mean, standard deviation, and skewness of a link's log-scaled flow sizes, a
+/// compact alternative to [`SizeCdf`]. Requires at least one flow.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SizeMoments;
+
+impl FeatureExtractor for SizeMoments {
+    fn extract(&self, _: &FlowChannel, flows: &[Flow]) -> Option<Vec<f64>> {
+        if flows.is_empty() {
+            return None;
+        }
+        let sizes = flows
+            .iter()
+            .map(|f| f.size.into_f64().ln_1p())
+            .collect::<Vec<_>>();
+        let (mean, variance) = mean_and_variance(&sizes);
+        let std = variance.sqrt();
+        let skewness = if std > 0.0 {
+            sizes
+                .iter()
+                .map(|x| ((x - mean) / std).powi(3))
+                .sum::<f64>()
+                / sizes.len() as f64
+        } else {
+            0.0
+        };
+        Some(vec![mean, std, skewness])
+    }
+}
+
+/// A weighted combination of feature extractors. Features are concatenated in the order the
+/// extractors were added, each scaled by its extractor's weight. Features are only extracted if
+/// every extractor succeeds.
+///
+/// Weights only matter if features are compared without rescaling: with
+/// [`KMeansClustering`](crate::kmeans::KMeansClustering), disable
+/// [standardization](crate::kmeans::KMeansClustering::with_standardization).
+#[derive(Default)]
+pub struct CompositeFeatures {
+    parts: Vec<(f64, Box<dyn FeatureExtractor>)>,
+}
+
+impl CompositeFeatures {
+    /// Creates an empty combination.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an extractor whose features are scaled by `weight`.
+    pub fn with<E>(mut self, weight: f64, extractor: E) -> Self
+    where
+        E: FeatureExtractor + 'static,
+    {
+        assert!(
+            weight.is_finite() && weight >= 0.0,
+            "feature weights must be finite and non-negative"
+        );
+        self.parts.push((weight, Box::new(extractor)));
+        self
+    }
+}
+
+impl fmt::Debug for CompositeFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let weights = self.parts.iter().map(|&(w, _)| w).collect::<Vec<_>>();
+        f.debug_struct("CompositeFeatures")
+            .field("weights", &weights)
+            .finish()
+    }
+}
+
+impl FeatureExtractor for CompositeFeatures {
+    fn extract(&self, chan: &FlowChannel, flows: &[Flow]) -> Option<Vec<f64>> {
+        let mut features = Vec::new();
+        for (weight, extractor) in &self.parts {
+            let part = extractor.extract(chan, flows)?;
+            features.extend(part.into_iter().map(|x| weight * x));
+        }
+        Some(features)
+    }
+}
+
+// The load offered by `flows` to `chan` over the span of their start times.
+fn load(chan: &FlowChannel, flows: &[Flow]) -> f64 {
+    let nr_bytes = flows.iter().map(|f| f.size).sum::<Bytes>();
+    let first = flows.iter().map(|f| f.start).min();
+    let last = flows.iter().map(|f| f.start).max();
+    let duration = match (first, last) {
+        (Some(first), Some(last)) => last - first,
+        _ => Nanosecs::ZERO,
+    };
+    LinkLoad::from_window(nr_bytes, duration, chan.bandwidth()).value()
+}
+
+// The mean and population variance of `xs`, which must not be empty
+fn mean_and_variance(xs: &[f64]) -> (f64, f64) {
+    let n = xs.len() as f64;
+    let mean = xs.iter().sum::<f64>() / n;
+    let variance = xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+    (mean, variance)
+}
+
+// Every tenth of the given quantiles, log-scaled.
+fn log_subsample<T>(quantiles: &[T]) -> Vec<f64>
+where
+    T: Copy + Into<f64>,
+{
+    quantiles
+        .iter()
+        .step_by(10)
+        .map(|&x| x.into().ln_1p())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use parsimon_core::{
+        network::{FlowId, Network, NodeId},
+        testing,
+        units::Gbps,
+    };
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn composite_features_concatenate_weighted_parts() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (0..4)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(3),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let network = Network::new(&nodes, &links)?.into_simulations(flows.clone());
+        let tiers = TierAndLoad::new(&nodes, &links);
+        assert_eq!(tiers.tier(NodeId::new(0)), Some(0));
+        assert_eq!(tiers.tier(NodeId::new(4)), Some(1));
+        assert_eq!(tiers.tier(NodeId::new(6)), Some(2));
+        assert_eq!(tiers.tier(NodeId::new(100)), None);
+
+        let eidx = network
+            .edge_indices()
+            .find(|&e| network.edge(e).unwrap().src() == NodeId::new(0))
+            .unwrap();
+        let chan = network.edge(eidx).unwrap();
+        let load = Load.extract(chan, &flows).unwrap()[0];
+        // 4000 bytes over 3us on a 10 Gbps link
+        assert!((load - 32e3 / 3e3 / 10.0).abs() < 1e-9);
+        assert_eq!(
+            TierAndLoad::new(&nodes, &links).extract(chan, &flows),
+            Some(vec![0.0, 1.0, load])
+        );
+        assert_eq!(
+            Oversubscription::new(&nodes, &links).extract(chan, &flows),
+            Some(vec![1.0])
+        );
+        assert_eq!(SizeCdf.extract(chan, &flows).unwrap().len(), 100);
+        assert_eq!(LoadAndInterarrival.extract(chan, &flows[..1]), None);
+
+        let interval = Nanosecs::new(1_000);
+        // Each 1us interval carries 1250 bytes at 10 Gbps and is offered one 1000-byte flow
+        assert_eq!(
+            LoadSeries::new(interval).extract(chan, &flows),
+            Some(vec![0.8; 11])
+        );
+        assert_eq!(LoadSeries::new(interval).extract(chan, &[]), None);
+        let empty = Flow {
+            size: Bytes::ZERO,
+            ..flows[0]
+        };
+        assert_eq!(
+            LoadSeries::new(interval).extract(chan, &[empty]),
+            Some(vec![0.0; 11])
+        );
+        // A 1 Gbps link carries no whole byte in a nanosecond
+        let slow = links
+            .iter()
+            .map(|&l| Link {
+                bandwidth: Gbps::new(1).into(),
+                ..l
+            })
+            .collect::<Vec<_>>();
+        let slow = Network::new(&nodes, &slow)?.into_simulations(flows.clone());
+        let slow_chan = slow.edge(eidx).unwrap();
+        assert_eq!(
+            LoadSeries::new(Nanosecs::new(1)).extract(slow_chan, &flows),
+            None
+        );
+        // One arrival per interval is perfectly regular
+        assert_eq!(
+            Burstiness::new(interval).extract(chan, &flows),
+            Some(vec![0.0])
+        );
+        // Three flows arriving at once, and one later
+        let bursty = flows
+            .iter()
+            .enumerate()
+            .map(|(i, f)| Flow {
+                start: Nanosecs::new(if i < 3 { 0 } else { 3_000 }),
+                ..*f
+            })
+            .collect::<Vec<_>>();
+        let burstiness = Burstiness::new(interval).extract(chan, &bursty).unwrap()[0];
+        assert!((burstiness - 1.5).abs() < 1e-9);
+        assert_eq!(FanIn.extract(chan, &flows), Some(vec![1.0, 1.0]));
+        let moments = SizeMoments.extract(chan, &flows).unwrap();
+        assert!((moments[0] - 1000_f64.ln_1p()).abs() < 1e-9);
+        assert_eq!(&moments[1..], &[0.0, 0.0]);
+        assert_eq!(SizeMoments.extract(chan, &[]), None);
+
+        let composite = CompositeFeatures::new()
+            .with(2.0, Load)
+            .with(1.0, |_: &FlowChannel, flows: &[Flow]| {
+                Some(vec![flows.len() as f64])
+            });
+        assert_eq!(composite.extract(chan, &flows), Some(vec![2.0 * load, 4.0]));
+        let composite = composite.with(1.0, LoadAndInterarrival);
+        assert_eq!(composite.extract(chan, &flows[..1]), None);
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn features_are_never_nan(
+            flows in prop::collection::vec((0..10_000_u64, 0..3_u64), 2..50),
+        ) {
+            let (nodes, links) = testing::three_node_config();
+            let network = Network::new(&nodes, &links).unwrap().into_simulations(Vec::new());
+            let chan = network.edge(network.edge_indices().next().unwrap()).unwrap();
+            let mut start = Nanosecs::ZERO;
+            let flows = flows
+                .into_iter()
+                .enumerate()
+                .map(|(i, (size, delta))| {
+                    start += Nanosecs::new(delta);
+                    Flow {
+                        id: FlowId::new(i),
+                        src: NodeId::new(0),
+                        dst: NodeId::new(1),
+                        size: Bytes::new(size),
+                        start,
+                    }
+                })
+                .collect::<Vec<_>>();
+            let features = dists_and_load(chan, &flows).unwrap();
+            prop_assert!(features.to_vector().iter().all(|x| x.is_finite()));
+            prop_assert!(!features.max_wmape(&features).is_nan());
+        }
+    }
+}
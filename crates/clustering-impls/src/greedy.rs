@@ -2,90 +2,136 @@
 
 use std::collections::HashSet;
 
-use dashmap::DashMap;
 use parsimon_core::{
     cluster::{Cluster, ClusteringAlgo},
     network::{types::FlowChannel, EdgeIndex, Flow, SimNetwork},
+    opts::RngStream,
     routing::RoutingAlgo,
 };
+use rand::prelude::*;
 use rayon::prelude::*;
-use rustc_hash::FxHashSet;
 
-/// Greedy clustering. This algorithm arbitrarily selects a link and clusters it with all links
-/// that are "close" to it. Then, it repeats the process with another arbitrary unclustered link,
-/// and so on.
+/// Greedy clustering. This algorithm selects a link and clusters it with all links that are
+/// "close" to it. Then, it repeats the process with the next unclustered link, and so on.
+///
+/// Clustering is deterministic: links are visited in order of edge index, or in a seeded random
+/// order if a seed is set with [`GreedyClustering::with_seed`] or the network is
+/// [seeded](SimNetwork::seed_for). Features are extracted and
+/// compared in parallel.
 #[derive(Debug, derive_new::new)]
 pub struct GreedyClustering<F, G> {
     feature: F,
     is_close_enough: G,
+    #[new(default)]
+    seed: Option<u64>,
+}
+
+impl<F, G> GreedyClustering<F, G> {
+    /// Visits links in a random order determined by `seed` instead of in order of edge index.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
 }
 
 impl<F, G, X> ClusteringAlgo for GreedyClustering<F, G>
 where
     F: Fn(&FlowChannel, &[Flow]) -> X + Sync,
     G: Fn(&X, &X) -> bool + Sync,
-    X: Clone + Send + Sync,
+    X: Send + Sync,
 {
     fn cluster<R>(&self, network: &SimNetwork<R>) -> Vec<Cluster>
     where
         R: RoutingAlgo + Sync,
     {
-        let features = Features::new(network, &self.feature);
-        let mut unclustered_edges = network.edge_indices().collect::<FxHashSet<_>>();
+        let mut order = network.edge_indices().collect::<Vec<_>>();
+        if let Some(seed) = self.seed.or(network.seed_for(RngStream::Clustering)) {
+            order.shuffle(&mut StdRng::seed_from_u64(seed));
+        }
+        let features = order
+            .par_iter()
+            .map(|&eidx| {
+                let chan = network
+                    .edge(eidx)
+                    .expect("invalid `eidx` in `GreedyClustering`");
+                let flows = network
+                    .flows_on(eidx)
+                    .expect("invalid `eidx` in `GreedyClustering`");
+                (self.feature)(chan, &flows)
+            })
+            .collect::<Vec<_>>();
+        // Positions into `order` and `features` of the links that are not yet clustered, in
+        // visiting order.
+        let mut unclustered = (0..order.len()).collect::<Vec<_>>();
         let mut clusters = Vec::new();
-        // Every time we remove an element, it becomes the next cluster representative.
-        while let Some(&representative) = unclustered_edges.iter().next() {
-            unclustered_edges.remove(&representative);
-            let rfeat = features.get(representative);
-            let mut members = [representative].into_iter().collect::<HashSet<_>>();
+        // The first unclustered link always becomes the next cluster representative.
+        while let Some((&representative, rest)) = unclustered.split_first() {
+            let rfeat = &features[representative];
             // Check all other unclustered edges to see if they're within epsilon of the current
-            // representative.
-            let candidates = unclustered_edges
+            // representative. The partition preserves visiting order.
+            let (candidates, rest): (Vec<_>, Vec<_>) = rest
                 .par_iter()
-                .filter_map(|&candidate| {
-                    let cfeat = features.get(candidate);
-                    (self.is_close_enough)(&rfeat, &cfeat).then_some(candidate)
-                })
-                .collect::<Vec<_>>();
-            for candidate in candidates {
-                members.insert(candidate);
-                unclustered_edges.remove(&candidate);
-            }
+                .partition(|&&candidate| (self.is_close_enough)(rfeat, &features[candidate]));
+            let members = std::iter::once(representative)
+                .chain(candidates)
+                .map(|i| order[i])
+                .collect::<HashSet<EdgeIndex>>();
             // We're done with this cluster.
-            clusters.push(Cluster::new(representative, members));
+            clusters.push(Cluster::new(order[representative], members));
+            unclustered = rest;
         }
         clusters
     }
 }
 
-#[derive(derive_new::new)]
-struct Features<'a, F, X, R> {
-    network: &'a SimNetwork<R>,
-    feature: F,
-    #[new(default)]
-    cache: DashMap<EdgeIndex, X>,
-}
+#[cfg(test)]
+mod tests {
+    use parsimon_core::{
+        network::{FlowId, Network, NodeId},
+        testing,
+        units::{Bytes, Nanosecs},
+    };
 
-impl<'a, F, X, R> Features<'a, F, X, R>
-where
-    F: Fn(&FlowChannel, &[Flow]) -> X + Sync,
-    X: Clone + Send + Sync,
-    R: RoutingAlgo + Sync,
-{
-    fn get(&self, eidx: EdgeIndex) -> X {
-        self.cache
-            .entry(eidx)
-            .or_insert_with(|| {
-                let chan = self
-                    .network
-                    .edge(eidx)
-                    .expect("invalid `eidx` in `Features::get`");
-                let flows = self
-                    .network
-                    .flows_on(eidx)
-                    .expect("invalid `eidx` in `Features::get`");
-                (self.feature)(chan, &flows)
+    use super::*;
+
+    fn snapshot(clusters: &[Cluster]) -> Vec<(EdgeIndex, Vec<EdgeIndex>)> {
+        clusters
+            .iter()
+            .map(|c| {
+                let mut members = c.members().copied().collect::<Vec<_>>();
+                members.sort();
+                (c.representative(), members)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn greedy_is_deterministic() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (0..20)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 4),
+                dst: NodeId::new((i + 1) % 4),
+                size: Bytes::new(1000 * (i as u64 % 3 + 1)),
+                start: Nanosecs::new(1000 * i as u64),
             })
-            .clone()
+            .collect();
+        let network = Network::new(&nodes, &links)?.into_simulations(flows);
+        let clusterer = GreedyClustering::new(
+            |chan: &FlowChannel, _: &[Flow]| chan.nr_flows(),
+            |a: &usize, b: &usize| a == b,
+        );
+        let first = snapshot(&clusterer.cluster(&network));
+        for _ in 0..10 {
+            assert_eq!(snapshot(&clusterer.cluster(&network)), first);
+        }
+        // Representatives are visited in order of edge index by default.
+        assert_eq!(first[0].0, EdgeIndex::new(0));
+
+        let clusterer = clusterer.with_seed(42);
+        let seeded = snapshot(&clusterer.cluster(&network));
+        assert_eq!(snapshot(&clusterer.cluster(&network)), seeded);
+        Ok(())
     }
 }
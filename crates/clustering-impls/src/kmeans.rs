@@ -0,0 +1,249 @@
+//! A k-means link clustering algorithm. Unlike [greedy clustering](crate::greedy), which bounds
+//! the distance between members and representatives, this bounds the number of clusters (and
+//! therefore the number of link simulations) directly.
+
+use std::collections::HashSet;
+
+use parsimon_core::{
+    cluster::{Cluster, ClusteringAlgo},
+    network::{types::FlowChannel, EdgeIndex, Flow, SimNetwork},
+    routing::RoutingAlgo,
+};
+use rand::prelude::*;
+use rayon::prelude::*;
+
+/// K-means clustering over feature vectors. Features are standardized per dimension (unless
+/// [disabled](KMeansClustering::with_standardization)), centroids are initialized with
+/// k-means++, and each cluster's representative is the member closest to its centroid.
+///
+/// Edges for which `feature` returns `None` (e.g., links with too few flows to extract features)
+/// are not clustered; each one forms its own cluster.
+#[derive(Debug, derive_new::new)]
+pub struct KMeansClustering<F> {
+    /// The target number of clusters.
+    k: usize,
+    feature: F,
+    /// The maximum number of Lloyd iterations.
+    #[new(value = "100")]
+    max_iters: usize,
+    /// The seed used for centroid initialization.
+    #[new(value = "0")]
+    seed: u64,
+    /// Whether to standardize every dimension before clustering.
+    #[new(value = "true")]
+    standardize: bool,
+}
+
+impl<F> KMeansClustering<F> {
+    /// Sets the maximum number of Lloyd iterations.
+    pub fn with_max_iters(mut self, max_iters: usize) -> Self {
+        self.max_iters = max_iters;
+        self
+    }
+
+    /// Sets the seed used for centroid initialization.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Sets whether every dimension is standardized before clustering (the default). Disable
+    /// standardization if features are already on comparable scales or are deliberately weighted,
+    /// e.g., with [`CompositeFeatures`](crate::feature::CompositeFeatures).
+    pub fn with_standardization(mut self, standardize: bool) -> Self {
+        self.standardize = standardize;
+        self
+    }
+}
+
+impl<F> ClusteringAlgo for KMeansClustering<F>
+where
+    F: Fn(&FlowChannel, &[Flow]) -> Option<Vec<f64>> + Sync,
+{
+    fn cluster<R>(&self, network: &SimNetwork<R>) -> Vec<Cluster>
+    where
+        R: RoutingAlgo + Sync,
+    {
+        let features = network
+            .edge_indices()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|eidx| {
+                let chan = network
+                    .edge(eidx)
+                    .expect("invalid `eidx` in `KMeansClustering`");
+                let flows = network
+                    .flows_on(eidx)
+                    .expect("invalid `eidx` in `KMeansClustering`");
+                (eidx, (self.feature)(chan, &flows))
+            })
+            .collect::<Vec<_>>();
+        let (mut points, mut unclustered) = (Vec::new(), Vec::new());
+        for (eidx, feature) in features {
+            match feature {
+                Some(feature) => points.push((eidx, feature)),
+                None => unclustered.push(eidx),
+            }
+        }
+        let mut clusters = unclustered
+            .into_iter()
+            .map(|eidx| Cluster::new(eidx, [eidx].into_iter().collect()))
+            .collect::<Vec<_>>();
+        if points.is_empty() {
+            return clusters;
+        }
+        let (edges, mut vectors): (Vec<EdgeIndex>, Vec<Vec<f64>>) = points.into_iter().unzip();
+        if self.standardize {
+            standardize(&mut vectors);
+        }
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let assignments = kmeans(&vectors, self.k, self.max_iters, &mut rng);
+        clusters.extend(assignments.into_iter().map(|(centroid, members)| {
+            let representative = *members
+                .iter()
+                .min_by(|&&a, &&b| {
+                    let da = sq_dist(&vectors[a], &centroid);
+                    let db = sq_dist(&vectors[b], &centroid);
+                    da.total_cmp(&db)
+                })
+                .unwrap(); // clusters are never empty
+            let members = members.iter().map(|&i| edges[i]).collect::<HashSet<_>>();
+            Cluster::new(edges[representative], members)
+        }));
+        clusters
+    }
+}
+
+// Runs k-means and returns the nonempty clusters as (centroid, member indices) pairs.
+fn kmeans(
+    vectors: &[Vec<f64>],
+    k: usize,
+    max_iters: usize,
+    rng: &mut impl Rng,
+) -> Vec<(Vec<f64>, Vec<usize>)> {
+    let mut centroids = init_centroids(vectors, k, rng);
+    let mut labels = vec![usize::MAX; vectors.len()];
+    for _ in 0..max_iters {
+        let new_labels = vectors
+            .par_iter()
+            .map(|v| nearest(v, &centroids))
+            .collect::<Vec<_>>();
+        let converged = new_labels == labels;
+        labels = new_labels;
+        if converged {
+            break;
+        }
+        // Recompute centroids. Empty clusters keep their previous centroid.
+        let dim = vectors[0].len();
+        let mut sums = vec![vec![0.0; dim]; centroids.len()];
+        let mut counts = vec![0_usize; centroids.len()];
+        for (v, &label) in vectors.iter().zip(&labels) {
+            counts[label] += 1;
+            for (s, x) in sums[label].iter_mut().zip(v) {
+                *s += x;
+            }
+        }
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            if count > 0 {
+                *centroid = sum.into_iter().map(|s| s / count as f64).collect();
+            }
+        }
+    }
+    let mut members = vec![Vec::new(); centroids.len()];
+    for (i, &label) in labels.iter().enumerate() {
+        members[label].push(i);
+    }
+    centroids
+        .into_iter()
+        .zip(members)
+        .filter(|(_, members)| !members.is_empty())
+        .collect()
+}
+
+// k-means++ initialization.
+fn init_centroids(vectors: &[Vec<f64>], k: usize, rng: &mut impl Rng) -> Vec<Vec<f64>> {
+    let k = k.clamp(1, vectors.len());
+    let mut centroids = vec![vectors.choose(rng).unwrap().clone()];
+    while centroids.len() < k {
+        let weights = vectors
+            .iter()
+            .map(|v| sq_dist(v, &centroids[nearest(v, &centroids)]))
+            .collect::<Vec<_>>();
+        let total = weights.iter().sum::<f64>();
+        if total <= 0.0 {
+            // All remaining points coincide with existing centroids.
+            break;
+        }
+        let mut target = rng.gen::<f64>() * total;
+        let next = weights
+            .iter()
+            .position(|&w| {
+                target -= w;
+                target <= 0.0
+            })
+            .unwrap_or(vectors.len() - 1);
+        centroids.push(vectors[next].clone());
+    }
+    centroids
+}
+
+fn nearest(v: &[f64], centroids: &[Vec<f64>]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| sq_dist(v, a).total_cmp(&sq_dist(v, b)))
+        .map(|(i, _)| i)
+        .unwrap()
+}
+
+fn sq_dist(a: &[f64], b: &[f64]) -> f64 {
+    assert_eq!(
+        a.len(),
+        b.len(),
+        "feature vectors must have the same length"
+    );
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+// Rescales every dimension to zero mean and unit variance. Constant dimensions are zeroed.
+fn standardize(vectors: &mut [Vec<f64>]) {
+    let n = vectors.len() as f64;
+    let dim = vectors[0].len();
+    for d in 0..dim {
+        let mean = vectors.iter().map(|v| v[d]).sum::<f64>() / n;
+        let var = vectors.iter().map(|v| (v[d] - mean).powi(2)).sum::<f64>() / n;
+        let std = var.sqrt();
+        for v in vectors.iter_mut() {
+            v[d] = if std > 0.0 { (v[d] - mean) / std } else { 0.0 };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parsimon_core::{
+        network::{Channel, Network},
+        testing,
+    };
+
+    use super::*;
+
+    #[test]
+    fn kmeans_caps_nr_clusters() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let mut network = Network::new(&nodes, &links)?.into_simulations(Vec::new());
+        // Two well-separated groups of links: those leaving hosts and those leaving switches.
+        let clusterer = KMeansClustering::new(2, |chan: &FlowChannel, _: &[Flow]| {
+            let x = if chan.src().inner() < 4 { 0.0 } else { 10.0 };
+            Some(vec![x])
+        });
+        network.cluster(&clusterer);
+        assert_eq!(network.nr_clusters(), 2);
+        for cluster in network.clusters() {
+            let from_host = |eidx: &EdgeIndex| network.edge(*eidx).unwrap().src().inner() < 4;
+            let rep = from_host(&cluster.representative());
+            assert!(cluster.members().all(|m| from_host(m) == rep));
+        }
+        Ok(())
+    }
+}
@@ -2,6 +2,8 @@
 
 #![warn(unreachable_pub, missing_debug_implementations, missing_docs)]
 
+pub mod diagnostics;
 pub mod feature;
 pub mod greedy;
+pub mod kmeans;
 pub mod utils;
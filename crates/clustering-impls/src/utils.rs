@@ -34,7 +34,7 @@ pub fn deltas(flows: &[Flow]) -> Vec<Nanosecs> {
         .collect()
 }
 
-/// Rescales the data in `a` to [0, 1].
+/// Rescales the data in `a` to [0, 1]. If all values are equal, they are all rescaled to 0.
 pub fn rescale<T>(a: &[T]) -> Vec<f64>
 where
     T: Clone + Copy + Into<f64>,
@@ -45,21 +45,41 @@ where
     let min = iter.clone().min_by(|&x, &y| cmp(x, y)).unwrap();
     let max = iter.clone().max_by(|&x, &y| cmp(x, y)).unwrap();
     let range = max - min;
+    if range == 0.0 {
+        return vec![0.0; a.len()];
+    }
     iter.map(|x| (x - min) / range).collect()
 }
 
-/// Weighted mean absolute percentage error (WMAPE)
-/// PRECONDITION: `a` cannot be all zeroes.
+/// Weighted mean absolute percentage error (WMAPE), using `a` as the reference. If `a` and `b`
+/// are equal the error is 0, even when `a` is all zeroes; otherwise, if the error cannot be
+/// expressed relative to `a` (e.g., `a` is all zeroes), it is infinite.
 pub fn wmape<T>(a: &[T], b: &[T]) -> f64
 where
     T: Clone + Copy + Into<f64>,
 {
     assert!(a.len() == b.len());
-    a.iter()
+    let error = a
+        .iter()
         .zip(b.iter())
-        .map(|(&x, &y)| (x.into() - y.into()).abs())
-        .sum::<f64>()
-        / a.iter().map(|&x| Into::<f64>::into(x).abs()).sum::<f64>()
+        .map(|(&x, &y)| {
+            let (x, y) = (x.into(), y.into());
+            if x == y {
+                0.0
+            } else {
+                (x - y).abs()
+            }
+        })
+        .sum::<f64>();
+    if error == 0.0 {
+        return 0.0;
+    }
+    let wmape = error / a.iter().map(|&x| Into::<f64>::into(x).abs()).sum::<f64>();
+    if wmape.is_nan() {
+        f64::INFINITY
+    } else {
+        wmape
+    }
 }
 
 /// Mean absolute error.
@@ -85,9 +105,8 @@ mod tests {
     fn percentiles_sorts_and_indexes_correctly() {
         assert_eq!(
             percentiles(&[2, 1], |&x| x),
-            iter::repeat(1)
-                .take(500)
-                .chain(iter::repeat(2).take(500))
+            iter::repeat_n(1, 500)
+                .chain(iter::repeat_n(2, 500))
                 .collect::<Vec<_>>()
         )
     }
@@ -113,4 +132,13 @@ mod tests {
         let wmape = (wmape(a, b) * 100.).round() as u32;
         assert_eq!(wmape, 67);
     }
+
+    #[test]
+    fn degenerate_inputs_are_not_nan() {
+        assert_eq!(rescale(&[3., 3., 3.]), vec![0., 0., 0.]);
+        assert_eq!(wmape(&[0., 0.], &[0., 0.]), 0.0);
+        assert_eq!(wmape(&[0., 0.], &[1., 0.]), f64::INFINITY);
+        assert_eq!(wmape(&[f64::INFINITY], &[f64::INFINITY]), 0.0);
+        assert_eq!(wmape(&[f64::INFINITY], &[1.0]), f64::INFINITY);
+    }
 }
@@ -0,0 +1,310 @@
+//! A native fluid-model link simulator with DCTCP dynamics. It needs no external simulator, so
+//! the whole pipeline can run with nothing else installed, e.g., in CI or for quick ballpark
+//! estimates. It is much coarser than Minim or ns-3.
+//!
+//! Every bottleneck is modeled as a fluid queue, advanced in fixed time steps. Active flows send
+//! at a rate capped by their access link and window, and the queue serves them in proportion to
+//! their rates. Whenever the queue exceeds the ECN marking threshold, DCTCP senders cut their
+//! rates in proportion to their estimate of the fraction of marked packets; otherwise, they
+//! increase their rates additively. Both updates are applied once per base RTT, smoothed over the
+//! steps within it. Packet loss is not modeled, and bottlenecks are simulated independently.
+
+use std::sync::Arc;
+
+use parsimon_core::{
+    linksim::{
+        params_of, DynLinkSim, LinkSim, LinkSimLink, LinkSimResult, LinkSimSpec, LinkSimTopo,
+        Window,
+    },
+    metrics,
+    network::FctRecord,
+    units::{BitsPerSec, Bytes, Nanosecs},
+};
+
+/// A DCTCP fluid-model link simulation.
+#[derive(Debug, Clone, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+pub struct FluidLink {
+    /// The sending window, either fixed or derived from each simulation's bandwidth-delay product.
+    #[builder(setter(into))]
+    pub window: Window,
+    /// DCTCP gain.
+    pub dctcp_gain: f64,
+    /// DCTCP additive increase.
+    #[builder(setter(into))]
+    pub dctcp_ai: BitsPerSec,
+    /// The ECN marking threshold. Defaults to 3 KB per Gbps of bottleneck bandwidth, as for Minim.
+    #[builder(default, setter(strip_option))]
+    #[serde(default)]
+    pub marking_threshold: Option<Bytes>,
+    /// The length of a time step.
+    #[builder(default = FluidLink::DEFAULT_STEP)]
+    #[serde(default = "FluidLink::default_step")]
+    pub step: Nanosecs,
+}
+
+impl LinkSim for FluidLink {
+    fn name(&self) -> String {
+        "fluid".into()
+    }
+
+    fn params(&self) -> Option<String> {
+        params_of(self)
+    }
+
+    fn detach(&self) -> Option<DynLinkSim> {
+        Some(Arc::new(self.clone()))
+    }
+
+    fn simulate(&self, spec: LinkSimSpec) -> LinkSimResult {
+        spec.validate()?;
+        self.validate(&spec)?;
+        let topo = LinkSimTopo::new(&spec);
+        let window = self.window.resolve(&spec);
+        let packets = &spec.packets;
+        let bottlenecks = spec.bottlenecks().collect::<Vec<_>>();
+        let mut queues = vec![Vec::new(); bottlenecks.len()];
+        for f in &spec.flows {
+            let path = topo.path(f.src, f.dst).ok_or_else(|| {
+                anyhow::anyhow!("flow {} has no path from {} to {}", f.id, f.src, f.dst)
+            })?;
+            let q = bottlenecks
+                .iter()
+                .position(|b| path.iter().any(|l| (l.from, l.to) == (b.from, b.to)))
+                .ok_or_else(|| anyhow::anyhow!("flow {} crosses no bottleneck", f.id))?;
+            let btl = &bottlenecks[q];
+            // Sources at the bottleneck inject at its rate
+            let access = if f.src == btl.from {
+                btl.available_bandwidth
+            } else {
+                path[0].available_bandwidth
+            };
+            let prop = path.iter().map(|l| l.delay).sum::<Nanosecs>();
+            let xmit = btl.total_bandwidth.length(packets.full_pkt());
+            let rtt = (prop.scale_by(2.0) + xmit).max(self.step);
+            let window_rate = window.into_f64() * 8.0 / rtt.into_f64();
+            queues[q].push(FluidFlow {
+                record: FctRecord {
+                    id: f.id,
+                    size: f.size,
+                    start: f.start,
+                    fct: Nanosecs::ZERO,
+                    ideal: metrics::ideal_fct(f.size, &path, packets),
+                },
+                prop,
+                rtt: rtt.into_f64(),
+                max_rate: (access.into_f64() / 1e9).min(window_rate),
+                min_rate: packets.full_pkt().into_f64() * 8.0 / rtt.into_f64(),
+            });
+        }
+        let mut records = bottlenecks
+            .iter()
+            .zip(queues)
+            .flat_map(|(btl, flows)| self.simulate_queue(btl, flows))
+            .collect::<Vec<_>>();
+        records.sort_by_key(|r| (r.start, r.id));
+        Ok(records)
+    }
+}
+
+impl FluidLink {
+    /// The default length of a time step.
+    pub const DEFAULT_STEP: Nanosecs = Nanosecs::new(1_000);
+
+    fn default_step() -> Nanosecs {
+        Self::DEFAULT_STEP
+    }
+
+    // Time has to advance, and queues have to drain and be fed
+    fn validate(&self, spec: &LinkSimSpec) -> anyhow::Result<()> {
+        if self.step == Nanosecs::ZERO {
+            anyhow::bail!("time step must be positive");
+        }
+        if let Some(l) = spec
+            .links()
+            .find(|l| l.available_bandwidth == BitsPerSec::ZERO)
+        {
+            anyhow::bail!(
+                "link from {} to {} has no available bandwidth",
+                l.from,
+                l.to
+            );
+        }
+        Ok(())
+    }
+
+    // Rates are in bits per nanosecond, and sizes are in bits
+    fn simulate_queue(&self, btl: &LinkSimLink, flows: Vec<FluidFlow>) -> Vec<FctRecord> {
+        let capacity = btl.available_bandwidth.into_f64() / 1e9;
+        let threshold = self
+            .marking_threshold
+            .unwrap_or_else(|| {
+                Bytes::new((btl.total_bandwidth.into_f64() / 1e9 * 3.0).round() as u64 * 1_000)
+            })
+            .into_f64()
+            * 8.0;
+        let (gain, ai) = (self.dctcp_gain, self.dctcp_ai.into_f64() / 1e9);
+        let dt = self.step.into_f64();
+        let mut records = Vec::with_capacity(flows.len());
+        let mut pending = flows.into_iter().peekable();
+        let mut active = Vec::<Active>::new();
+        let mut queue = 0.0;
+        let mut now = 0.0;
+        loop {
+            if active.is_empty() {
+                match pending.peek() {
+                    Some(f) => now = f64::max(now, f.record.start.into_f64()),
+                    None => break,
+                }
+                queue = 0.0;
+            }
+            while let Some(f) = pending.next_if(|f| f.record.start.into_f64() <= now) {
+                active.push(Active {
+                    remaining: f.record.size.into_f64() * 8.0,
+                    rate: f.max_rate,
+                    alpha: 1.0,
+                    flow: f,
+                });
+            }
+            let arrivals = active.iter().map(|a| a.rate).sum::<f64>() * dt;
+            let backlog = queue + arrivals;
+            let served = backlog.min(capacity * dt);
+            queue = backlog - served;
+            let marked = queue > threshold;
+            now += dt;
+            for a in &mut active {
+                a.remaining -= served * a.rate * dt / arrivals;
+                let frac = (dt / a.flow.rtt).min(1.0);
+                a.alpha += gain * frac * (f64::from(u8::from(marked)) - a.alpha);
+                if marked {
+                    a.rate *= 1.0 - a.alpha / 2.0 * frac;
+                } else {
+                    a.rate += ai * frac;
+                }
+                a.rate = a
+                    .rate
+                    .clamp(a.flow.min_rate.min(a.flow.max_rate), a.flow.max_rate);
+            }
+            active.retain(|a| {
+                if a.remaining > 0.0 {
+                    return true;
+                }
+                // The last bits wait for the queue ahead of them and then propagate
+                let done = now + queue / capacity + a.flow.prop.into_f64();
+                let fct = Nanosecs::new((done - a.flow.record.start.into_f64()).round() as u64);
+                records.push(FctRecord {
+                    fct: fct.max(a.flow.record.ideal),
+                    ..a.flow.record
+                });
+                false
+            });
+        }
+        records
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FluidFlow {
+    // The flow's record, with a placeholder FCT
+    record: FctRecord,
+    prop: Nanosecs,
+    rtt: f64,
+    max_rate: f64,
+    min_rate: f64,
+}
+
+#[derive(Debug)]
+struct Active {
+    flow: FluidFlow,
+    remaining: f64,
+    rate: f64,
+    alpha: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use parsimon_core::{
+        constants::PacketProfile,
+        linksim::simulate_one,
+        network::{Flow, FlowId, NodeId},
+        units::Gbps,
+    };
+
+    use super::*;
+
+    fn sim() -> FluidLink {
+        FluidLink::builder()
+            .window(Bytes::new(18_000))
+            .dctcp_gain(0.0625)
+            .dctcp_ai(BitsPerSec::new(615_000_000))
+            .build()
+    }
+
+    fn spec(nr_flows: usize) -> anyhow::Result<LinkSimSpec> {
+        let id = NodeId::new;
+        let bandwidth = BitsPerSec::from(Gbps::new(10));
+        let delay = Nanosecs::new(1_000);
+        let flows = (0..nr_flows).map(|i| Flow {
+            id: FlowId::new(i),
+            src: id(2 + i % 2),
+            dst: id(4),
+            size: Bytes::new(100_000),
+            start: Nanosecs::ZERO,
+        });
+        Ok(
+            LinkSimSpec::builder(LinkSimLink::new(id(0), id(1), bandwidth, delay))
+                .source(id(2), bandwidth, delay)
+                .source(id(3), bandwidth, delay)
+                .destination(id(4), bandwidth, delay)
+                .flows(flows)
+                .build()?,
+        )
+    }
+
+    #[test]
+    fn lone_flows_finish_near_ideal() -> anyhow::Result<()> {
+        let records = simulate_one(spec(1)?, &sim())?;
+        assert_eq!(records.len(), 1);
+        let r = records[0];
+        assert!(r.fct >= r.ideal);
+        assert!(r.fct.into_f64() < r.ideal.into_f64() * 1.1, "{r:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn contending_flows_share_the_bottleneck() -> anyhow::Result<()> {
+        let lone = simulate_one(spec(1)?, &sim())?[0];
+        let records = simulate_one(spec(8)?, &sim())?;
+        assert_eq!(records.len(), 8);
+        let slowest = records.iter().map(|r| r.fct).max().unwrap();
+        // Eight flows take about eight times as long as one
+        assert!(slowest.into_f64() > lone.fct.into_f64() * 4.0, "{slowest}");
+        Ok(())
+    }
+
+    #[test]
+    fn degenerate_options_fail() -> anyhow::Result<()> {
+        let zero_step = FluidLink {
+            step: Nanosecs::ZERO,
+            ..sim()
+        };
+        assert!(simulate_one(spec(1)?, &zero_step).is_err());
+        let mut idle = spec(1)?;
+        idle.bottleneck.available_bandwidth = BitsPerSec::ZERO;
+        assert!(simulate_one(idle, &sim()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn packets_come_from_the_spec() -> anyhow::Result<()> {
+        let default = simulate_one(spec(1)?, &sim())?[0];
+        let mut jumbo = spec(1)?;
+        jumbo.packets = PacketProfile {
+            max_payload: Bytes::new(9_000),
+            ..PacketProfile::default()
+        };
+        let jumbo = simulate_one(jumbo, &sim())?[0];
+        // Larger packets take longer to store and forward, but carry fewer headers
+        assert!(jumbo.ideal > default.ideal);
+        Ok(())
+    }
+}
@@ -3,8 +3,22 @@
 
 #![warn(unreachable_pub, missing_debug_implementations, missing_docs)]
 
+pub mod fluid;
 pub mod minim;
 pub mod ns3;
 
+pub use crate::fluid::FluidLink;
 pub use crate::minim::MinimLink;
 pub use crate::ns3::Ns3Link;
+
+use parsimon_core::linksim::LinkSimRegistry;
+
+/// Returns a registry of every link simulator in this crate, by name.
+pub fn registry() -> LinkSimRegistry {
+    let mut registry = LinkSimRegistry::new();
+    registry
+        .register::<MinimLink>("minim")
+        .register::<Ns3Link>("ns3")
+        .register::<FluidLink>("fluid");
+    registry
+}
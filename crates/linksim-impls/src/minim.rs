@@ -1,19 +1,26 @@
 //! An interface to the Minim link-level simulator.
 
+use std::sync::Arc;
+
 use parsimon_core::{
-    constants::{SZ_PKTHDR, SZ_PKTMAX},
-    linksim::{LinkSim, LinkSimError, LinkSimNodeKind, LinkSimResult, LinkSimSpec, LinkSimTopo},
+    linksim::{
+        params_of, DynLinkSim, LinkSim, LinkSimError, LinkSimNodeKind, LinkSimResult, LinkSimSpec,
+        LinkSimTopo, Window,
+    },
     network::{FctRecord, FlowId},
     units::{BitsPerSec, Bytes, Kilobytes, Nanosecs},
 };
 use rustc_hash::{FxHashMap, FxHashSet};
 
 /// A Minim link simulation.
-#[derive(Debug, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+///
+/// Minim doesn't model packet loss, so link loss rates are ignored. It also models a single
+/// queue, so specs with multiple bottlenecks are rejected.
+#[derive(Debug, Clone, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
 pub struct MinimLink {
-    /// The sending window.
+    /// The sending window, either fixed or derived from each simulation's bandwidth-delay product.
     #[builder(setter(into))]
-    pub window: Bytes,
+    pub window: Window,
     /// DCTCP gain.
     pub dctcp_gain: f64,
     /// DCTCP additive increase.
@@ -26,6 +33,14 @@ impl LinkSim for MinimLink {
         "minim".into()
     }
 
+    fn params(&self) -> Option<String> {
+        params_of(self)
+    }
+
+    fn detach(&self) -> Option<DynLinkSim> {
+        Some(Arc::new(self.clone()))
+    }
+
     fn simulate(&self, spec: LinkSimSpec) -> LinkSimResult {
         let cfg = self.build_config(spec)?;
         let records = minim::run(cfg).map_err(|e| anyhow::anyhow!(e))?;
@@ -46,6 +61,10 @@ impl LinkSim for MinimLink {
 
 impl MinimLink {
     fn build_config(&self, spec: LinkSimSpec) -> Result<minim::Config, LinkSimError> {
+        if !spec.other_bottlenecks.is_empty() {
+            // Minim models a single queue
+            return Err(anyhow::anyhow!("Minim can't simulate multiple bottlenecks").into());
+        }
         let src_ids = spec
             .nodes
             .iter()
@@ -55,6 +74,8 @@ impl MinimLink {
             })
             .collect::<FxHashSet<_>>();
         let topo = LinkSimTopo::new(&spec);
+        let window = self.window.resolve(&spec);
+        let packets = spec.packets;
 
         let srcs = src_ids
             .iter()
@@ -120,12 +141,12 @@ impl MinimLink {
             .quanta(vec![minim::units::Bytes::new(1024)])
             .sources(srcs)
             .flows(flows)
-            .window(minim::units::Bytes::new(self.window.into_u64()))
+            .window(minim::units::Bytes::new(window.into_u64()))
             .dctcp_marking_threshold(minim::units::Kilobytes::new(marking_threshold.into_u64()))
             .dctcp_gain(self.dctcp_gain)
             .dctcp_ai(minim::units::BitsPerSec::new(self.dctcp_ai.into_u64()))
-            .sz_pktmax(minim::units::Bytes::new(SZ_PKTMAX.into_u64()))
-            .sz_pkthdr(minim::units::Bytes::new(SZ_PKTHDR.into_u64()))
+            .sz_pktmax(minim::units::Bytes::new(packets.max_payload.into_u64()))
+            .sz_pkthdr(minim::units::Bytes::new(packets.header.into_u64()))
             .build();
         Ok(cfg)
     }
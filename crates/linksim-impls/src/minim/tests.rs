@@ -68,7 +68,7 @@ fn eight_node_config_snapshots(flows: Vec<Flow>) -> anyhow::Result<Snapshot> {
         .build();
     let snapshot = network
         .edge_indices()
-        .filter_map(|eidx| network.link_sim_desc(eidx))
+        .filter_map(|eidx| network.link_sim_desc(eidx).unwrap())
         .map(|desc| {
             let flows = desc
                 .flows
@@ -78,9 +78,11 @@ fn eight_node_config_snapshots(flows: Vec<Flow>) -> anyhow::Result<Snapshot> {
             let spec = LinkSimSpec {
                 edge: desc.edge,
                 bottleneck: desc.bottleneck,
+                other_bottlenecks: desc.other_bottlenecks,
                 other_links: desc.other_links,
                 nodes: desc.nodes,
                 flows,
+                packets: network.packet_profile(),
             };
             let (bsrc, bdst) = (spec.bottleneck.from, spec.bottleneck.to);
             let cfg = linksim.build_config(spec)?;
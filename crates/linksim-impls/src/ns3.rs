@@ -1,17 +1,36 @@
 //! An interface to a link-level simulator built atop ns-3. This hooks into the ns-3 implementation
 //! at <https://github.com/kwzhao/High-Precision-Congestion-Control>, which is assumed to be
 //! downloaded and compiled prior to the use of this type.
+//!
+//! Where ns-3 jobs are scheduled outside of Parsimon,
+//! [`SimNetwork::export_simulations`](parsimon_core::network::SimNetwork::export_simulations) with
+//! an [`Ns3Link`] only writes each link simulation's `topology.txt` and `flows.txt` to
+//! `{root_dir}/{bsrc}-{bdst}` and returns those directories. Once ns-3 has run in them, the results
+//! are read back by a link simulation created with [`Ns3Link::from_results_dir`], locally or on
+//! workers.
 
-use std::path::PathBuf;
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex, Weak},
+};
 
 use ns3_frontend::{CcKind, Ns3Simulation};
 use parsimon_core::{
-    linksim::{LinkSim, LinkSimResult, LinkSimSpec},
-    units::{Bytes, Nanosecs},
+    constants::PacketProfile,
+    distribute::JobId,
+    linksim::{
+        params_of, DynLinkSim, ExportLinkSim, LinkSim, LinkSimError, LinkSimResult, LinkSimSpec,
+        Window,
+    },
+    units::Nanosecs,
 };
+use rustc_hash::FxHashMap;
 
 /// An ns-3 link simulation.
-#[derive(Debug, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
+///
+/// The ns-3 implementation splits flows into packets of the default [`PacketProfile`], so specs
+/// with other profiles are rejected.
+#[derive(Debug, Clone, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize)]
 pub struct Ns3Link {
     /// The top-level directory where data files will be written.
     #[builder(setter(into))]
@@ -19,15 +38,48 @@ pub struct Ns3Link {
     /// The path to the ns-3 simulator (`{path_to}/High-Precision-Congestion-Control/simulation`)
     #[builder(setter(into))]
     pub ns3_dir: PathBuf,
-    /// The sending window.
+    /// The sending window, either fixed or derived from each simulation's bandwidth-delay product.
     #[builder(setter(into))]
-    pub window: Bytes,
+    pub window: Window,
     /// The base round-trip time.
     #[builder(setter(into))]
     pub base_rtt: Nanosecs,
     /// The congestion control algorithm.
     #[builder(default)]
     pub cc_kind: CcKind,
+    /// Whether to run ns-3 or only ingest its results.
+    #[builder(default)]
+    #[serde(default)]
+    pub mode: Ns3Mode,
+}
+
+impl Ns3Link {
+    /// Creates a link simulation which reads the results of ns-3 runs under `root_dir`, e.g., of
+    /// configurations written by
+    /// [`SimNetwork::export_simulations`](parsimon_core::network::SimNetwork::export_simulations),
+    /// instead of running ns-3. `cc_kind` must match the congestion control protocol the results
+    /// were produced with.
+    pub fn from_results_dir(root_dir: impl Into<PathBuf>, cc_kind: CcKind) -> Self {
+        Self {
+            root_dir: root_dir.into(),
+            ns3_dir: PathBuf::new(),
+            window: Window::BDP,
+            base_rtt: Nanosecs::ZERO,
+            cc_kind,
+            mode: Ns3Mode::Ingest,
+        }
+    }
+}
+
+/// What an [`Ns3Link`] does with each link simulation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Ns3Mode {
+    /// Write the configuration, run ns-3, and read the results.
+    #[default]
+    Run,
+    /// Only read the results of an earlier run from `{root_dir}/{bsrc}-{bdst}`, even on workers.
+    Ingest,
 }
 
 impl LinkSim for Ns3Link {
@@ -35,24 +87,207 @@ impl LinkSim for Ns3Link {
         "ns3".into()
     }
 
+    fn params(&self) -> Option<String> {
+        params_of(self)
+    }
+
+    fn detach(&self) -> Option<DynLinkSim> {
+        Some(Arc::new(self.clone()))
+    }
+
+    fn for_job(&self, job: JobId) -> Option<DynLinkSim> {
+        match self.mode {
+            Ns3Mode::Run => {
+                let link = Self {
+                    root_dir: self.root_dir.join(job.to_string()),
+                    ..self.clone()
+                };
+                let dir = JobDir::acquire(link.root_dir.clone());
+                Some(Arc::new(Ns3Job { link, _dir: dir }))
+            }
+            // Ingested results are found where they were exported, whatever the job
+            Ns3Mode::Ingest => None,
+        }
+    }
+
     fn simulate(&self, spec: LinkSimSpec) -> LinkSimResult {
+        let sim = self.simulation(spec)?;
+        let records = match self.mode {
+            Ns3Mode::Run => sim.run(),
+            Ns3Mode::Ingest => sim.read_results(),
+        }
+        .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(records)
+    }
+}
+
+impl ExportLinkSim for Ns3Link {
+    fn export(&self, spec: LinkSimSpec) -> Result<PathBuf, LinkSimError> {
+        let sim = self.simulation(spec)?;
+        sim.write_config().map_err(|e| anyhow::anyhow!(e))?;
+        Ok(sim.data_dir)
+    }
+}
+
+impl Ns3Link {
+    // The ns-3 simulation of `spec`, in `{root_dir}/{bsrc}-{bdst}`
+    fn simulation(&self, spec: LinkSimSpec) -> Result<Ns3Simulation, LinkSimError> {
+        if spec.packets != PacketProfile::default() {
+            return Err(anyhow::anyhow!("ns-3 only simulates the default packet profile").into());
+        }
         let (bsrc, bdst) = (spec.bottleneck.from, spec.bottleneck.to);
+        let window = self.window.resolve(&spec);
         let (spec, _) = spec.contiguousify();
-
-        // Set up and run simulation
         let mut data_dir = PathBuf::from(&self.root_dir);
         data_dir.push(format!("{bsrc}-{bdst}"));
-        let sim = Ns3Simulation::builder()
+        Ok(Ns3Simulation::builder()
             .ns3_dir(&self.ns3_dir)
             .data_dir(data_dir)
             .nodes(spec.generic_nodes().collect())
             .links(spec.generic_links().collect())
-            .window(self.window)
+            .window(window)
             .base_rtt(self.base_rtt)
             .cc_kind(self.cc_kind)
             .flows(spec.flows)
+            .build())
+    }
+}
+
+/// An [`Ns3Link`] which runs one job's simulations in its own directory.
+#[derive(Debug)]
+struct Ns3Job {
+    link: Ns3Link,
+    _dir: Arc<JobDir>,
+}
+
+impl LinkSim for Ns3Job {
+    fn name(&self) -> String {
+        self.link.name()
+    }
+
+    fn params(&self) -> Option<String> {
+        self.link.params()
+    }
+
+    fn simulate(&self, spec: LinkSimSpec) -> LinkSimResult {
+        self.link.simulate(spec)
+    }
+}
+
+// The job directories in use by this process. Batches of the same job share a directory, which
+// is removed once the last of them finishes, including simulations left running after a timeout.
+static JOB_DIRS: Mutex<Option<FxHashMap<PathBuf, Weak<JobDir>>>> = Mutex::new(None);
+
+#[derive(Debug)]
+struct JobDir(PathBuf);
+
+impl JobDir {
+    fn acquire(path: PathBuf) -> Arc<Self> {
+        let mut dirs = JOB_DIRS.lock().unwrap();
+        let dirs = dirs.get_or_insert_with(FxHashMap::default);
+        dirs.retain(|_, dir| dir.strong_count() > 0);
+        if let Some(dir) = dirs.get(&path).and_then(Weak::upgrade) {
+            return dir;
+        }
+        let dir = Arc::new(Self(path.clone()));
+        dirs.insert(path, Arc::downgrade(&dir));
+        dir
+    }
+}
+
+impl Drop for JobDir {
+    fn drop(&mut self) {
+        // Hold the lock so that no batch of the job acquires the directory while it is removed
+        let mut dirs = JOB_DIRS.lock().unwrap();
+        if let Some(dirs) = dirs.as_mut() {
+            if dirs.get(&self.0).is_some_and(|dir| dir.strong_count() > 0) {
+                return;
+            }
+            dirs.remove(&self.0);
+        }
+        // The directory doesn't exist if no simulation ran
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parsimon_core::{
+        network::{Channel, Flow, FlowId, Network, NodeId},
+        opts::SimOpts,
+        testing,
+        units::Bytes,
+    };
+
+    use super::*;
+
+    fn link(root_dir: &std::path::Path, mode: Ns3Mode) -> Ns3Link {
+        Ns3Link::builder()
+            .root_dir(root_dir)
+            .ns3_dir("")
+            .window(Window::BDP)
+            .base_rtt(Nanosecs::ZERO)
+            .mode(mode)
+            .build()
+    }
+
+    #[test]
+    fn job_dirs_are_removed_when_jobs_finish() -> anyhow::Result<()> {
+        let root = tempfile::tempdir()?;
+        let job = JobId::random();
+        let job_dir = root.path().join(job.to_string());
+        let link = link(root.path(), Ns3Mode::Run);
+        let first = link.for_job(job).unwrap();
+        let second = link.for_job(job).unwrap();
+        std::fs::create_dir_all(job_dir.join("0-1"))?;
+        drop(first);
+        assert!(job_dir.exists());
+        drop(second);
+        assert!(!job_dir.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn exported_simulations_are_ingested() -> anyhow::Result<()> {
+        let root = tempfile::tempdir()?;
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1_000),
+                start: Nanosecs::new(i as u64 * 1_000),
+            })
+            .collect();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows);
+        let export = SimOpts::builder()
+            .link_sim(link(root.path(), Ns3Mode::Run))
             .build();
-        let records = sim.run().map_err(|e| anyhow::anyhow!(e))?;
-        Ok(records)
+        let dirs = sims.export_simulations(&export)?;
+        assert!(!dirs.is_empty());
+        for (edge, dir) in &dirs {
+            let chan = sims.edge(*edge).unwrap();
+            assert_eq!(
+                dir,
+                &root.path().join(format!("{}-{}", chan.src(), chan.dst()))
+            );
+            assert!(dir.join("topology.txt").exists());
+            // Stand in for an external ns-3 run
+            let results = ns3_frontend::read_flows(dir.join("flows.txt"))?
+                .iter()
+                .map(|f| format!("{} 0 0 0 0 {} {} 5000 4000\n", f.id, f.size, f.start))
+                .collect::<String>();
+            std::fs::write(dir.join("fct_topology_flows_dctcp.txt"), results)?;
+        }
+
+        // Jobs read the exported directories too
+        let ingest = Ns3Link::from_results_dir(root.path(), CcKind::Dctcp);
+        assert!(ingest.for_job(JobId::random()).is_none());
+        let delays = sims.into_delays(SimOpts::builder().link_sim(ingest).build())?;
+        let (src, dst) = (NodeId::new(0), NodeId::new(1));
+        let delay = delays.predict(Bytes::new(1_000), (src, dst), delays.rng());
+        assert!(delay.is_some_and(|d| d > Nanosecs::ZERO));
+        Ok(())
     }
 }
@@ -5,7 +5,7 @@
 #![warn(unreachable_pub, missing_debug_implementations, missing_docs)]
 
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::{fmt::Write, io};
 
@@ -49,6 +49,14 @@ impl Ns3Simulation {
     ///
     /// This routine can fail due to IO errors or errors parsing ns-3 data.
     pub fn run(&self) -> Result<Vec<FctRecord>, Error> {
+        self.write_config()?;
+        self.invoke_ns3()?;
+        self.read_results()
+    }
+
+    /// Writes the simulation's `topology.txt` and `flows.txt` to the data directory without
+    /// running ns-3, e.g., so the simulation can be run by an external job scheduler.
+    pub fn write_config(&self) -> Result<(), Error> {
         // Set up directory
         let mk_path = |dir, file| [dir, file].into_iter().collect::<PathBuf>();
         fs::create_dir_all(&self.data_dir)?;
@@ -66,15 +74,16 @@ impl Ns3Simulation {
             mk_path(self.data_dir.as_path(), "flows.txt".as_ref()),
             flows,
         )?;
+        Ok(())
+    }
 
-        // Run ns-3
-        self.invoke_ns3()?;
-
-        // Parse and return results
-        let s = fs::read_to_string(mk_path(
-            self.data_dir.as_path(),
-            format!("fct_topology_flows_{}.txt", self.cc_kind.as_str()).as_ref(),
-        ))?;
+    /// Reads the [FctRecord]s ns-3 wrote to the data directory, whether by [Ns3Simulation::run]
+    /// or by a run started outside of Parsimon.
+    pub fn read_results(&self) -> Result<Vec<FctRecord>, Error> {
+        let path = self
+            .data_dir
+            .join(format!("fct_topology_flows_{}.txt", self.cc_kind.as_str()));
+        let s = fs::read_to_string(path)?;
         let records = parse_ns3_records(&s)?;
         Ok(records)
     }
@@ -116,6 +125,19 @@ pub enum Error {
     Io(#[from] std::io::Error),
 }
 
+/// Reads [`Flow`]s from a trace in the HPCC `flows.txt` format, e.g., one prepared for a direct
+/// ns-3 experiment. Priority groups and destination ports are ignored.
+pub fn read_flows(path: impl AsRef<Path>) -> Result<Vec<Flow>, Error> {
+    let s = fs::read_to_string(path)?;
+    Ok(parse_flows(&s)?)
+}
+
+/// Writes [`Flow`]s to a trace in the HPCC `flows.txt` format, which ns-3 can run directly.
+pub fn write_flows(path: impl AsRef<Path>, flows: &[Flow]) -> Result<(), Error> {
+    fs::write(path, translate_flows(flows))?;
+    Ok(())
+}
+
 fn translate_topology(nodes: &[Node], links: &[Link]) -> String {
     let mut s = String::new();
     let switches = nodes
@@ -137,8 +159,8 @@ fn translate_topology(nodes: &[Node], links: &[Link]) -> String {
     for link in links {
         writeln!(
             s,
-            "{} {} {} {} 0",
-            link.a, link.b, link.bandwidth, link.delay
+            "{} {} {} {} {}",
+            link.a, link.b, link.bandwidth, link.delay, link.loss_rate
         )
         .unwrap();
     }
@@ -165,6 +187,48 @@ fn translate_flows(flows: &[Flow]) -> String {
     lines.join("\n")
 }
 
+fn parse_flows(s: &str) -> Result<Vec<Flow>, ParseNs3Error> {
+    let mut lines = s.lines().filter(|l| !l.trim().is_empty());
+    let nr_flows: usize = lines
+        .next()
+        .ok_or(ParseNs3Error::MissingFlowCount)?
+        .trim()
+        .parse()?;
+    let flows = lines.map(parse_flow).collect::<Result<Vec<_>, _>>()?;
+    if flows.len() != nr_flows {
+        return Err(ParseNs3Error::WrongNrFlows {
+            expected: nr_flows,
+            got: flows.len(),
+        });
+    }
+    Ok(flows)
+}
+
+fn parse_flow(s: &str) -> Result<Flow, ParseNs3Error> {
+    // id, src, dst, pg, dport, size (B), start_time (s)
+    const NR_FLOW_FIELDS: usize = 7;
+    let fields = s.split_whitespace().collect::<Vec<_>>();
+    let nr_fields = fields.len();
+    if nr_fields != NR_FLOW_FIELDS {
+        return Err(ParseNs3Error::WrongNrFields {
+            expected: NR_FLOW_FIELDS,
+            got: nr_fields,
+        });
+    }
+    let start = fields[6].parse::<f64>()?;
+    let start_ns = (start * 1e9).round();
+    if !(0.0..u64::MAX as f64).contains(&start_ns) {
+        return Err(ParseNs3Error::InvalidStartTime(start));
+    }
+    Ok(Flow {
+        id: fields[0].parse()?,
+        src: fields[1].parse()?,
+        dst: fields[2].parse()?,
+        size: Bytes::new(fields[5].parse()?),
+        start: Nanosecs::new(start_ns as u64),
+    })
+}
+
 fn parse_ns3_records(s: &str) -> Result<Vec<FctRecord>, ParseNs3Error> {
     s.lines().map(parse_ns3_record).collect()
 }
@@ -201,9 +265,34 @@ pub enum ParseNs3Error {
         got: usize,
     },
 
+    /// A flow trace is missing its first line, the number of flows.
+    #[error("Missing number of flows")]
+    MissingFlowCount,
+
+    /// A flow trace has a different number of flows than declared.
+    #[error("Wrong number of flows (expected {expected}, got {got})")]
+    WrongNrFlows {
+        /// Declared number of flows.
+        expected: usize,
+        /// Actual number of flows.
+        got: usize,
+    },
+
+    /// A flow's start time is negative, not finite, or too late to represent.
+    #[error("Invalid start time {0}s")]
+    InvalidStartTime(f64),
+
     /// Error parsing field value.
     #[error("Failed to parse field")]
     ParseInt(#[from] std::num::ParseIntError),
+
+    /// Error parsing a floating-point field value.
+    #[error("Failed to parse field")]
+    ParseFloat(#[from] std::num::ParseFloatError),
+
+    /// Error parsing a field with units.
+    #[error("Failed to parse field")]
+    ParseUnit(#[from] parsimon_core::units::ParseUnitError),
 }
 
 /// Congestion control protocol.
@@ -284,4 +373,67 @@ mod tests {
         "###);
         Ok(())
     }
+
+    #[test]
+    fn configs_write_and_results_read() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let dir = tempfile::tempdir()?;
+        let sim = Ns3Simulation::builder()
+            .ns3_dir("ns-3")
+            .data_dir(dir.path().join("4-6"))
+            .nodes(nodes)
+            .links(links)
+            .window(Bytes::new(18_000))
+            .base_rtt(Nanosecs::new(8_000))
+            .flows(Vec::new())
+            .build();
+        sim.write_config()?;
+        assert!(sim.data_dir.join("topology.txt").exists());
+        assert_eq!(read_flows(sim.data_dir.join("flows.txt"))?, Vec::new());
+        assert!(matches!(sim.read_results(), Err(Error::Io(..))));
+
+        fs::write(
+            sim.data_dir.join("fct_topology_flows_dctcp.txt"),
+            "0 0b000101 0b000201 10000 100 1234 1000000000 5000 4000\n",
+        )?;
+        let records = sim.read_results()?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].fct, Nanosecs::new(5_000));
+        Ok(())
+    }
+
+    #[test]
+    fn flows_round_trip() -> anyhow::Result<()> {
+        let flows = (0..100)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 4),
+                dst: NodeId::new(3 - i % 4),
+                size: Bytes::new(1_000 + i as u64),
+                start: Nanosecs::new(2_000_000_000 + i as u64 * 1_234_567),
+            })
+            .collect::<Vec<_>>();
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("flows.txt");
+        write_flows(&path, &flows)?;
+        assert_eq!(read_flows(&path)?, flows);
+        assert!(matches!(
+            parse_flows("3\n0 0 1 3 100 1234 1"),
+            Err(ParseNs3Error::WrongNrFlows {
+                expected: 3,
+                got: 1
+            })
+        ));
+        assert!(matches!(
+            parse_flows(""),
+            Err(ParseNs3Error::MissingFlowCount)
+        ));
+        for start in ["-1", "NaN", "inf", "1e300"] {
+            assert!(matches!(
+                parse_flows(&format!("1\n0 0 1 3 100 1234 {start}")),
+                Err(ParseNs3Error::InvalidStartTime(_))
+            ));
+        }
+        Ok(())
+    }
 }
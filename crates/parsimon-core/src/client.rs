@@ -1,9 +1,20 @@
-use crate::network::Flow;
+//! This module defines clients (or tenants) of a network. Flows can be tagged with the client
+//! they belong to using a [`ClientMap`], in which case a
+//! [`DelayNetwork`](crate::network::DelayNetwork) keeps separate delay distributions for each
+//! client, and clients can be [analyzed separately](crate::network::DelayNetwork::for_client).
+
+use std::collections::BTreeSet;
+
+use rustc_hash::FxHashMap;
+
+use crate::network::{Flow, FlowId};
 
 identifier!(ClientId, usize);
 
+/// A client of the network and its flows.
 #[derive(Debug, derive_new::new)]
 pub struct Client {
+    /// The client ID.
     pub id: ClientId,
     name: String,
     flows: Vec<Flow>,
@@ -25,3 +36,68 @@ impl Client {
         &mut self.flows
     }
 }
+
+/// A mapping from flows to the clients they belong to. Flows without a client are only accounted
+/// for in the aggregate delay distributions.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ClientMap {
+    flow2client: FxHashMap<FlowId, ClientId>,
+}
+
+impl ClientMap {
+    /// Creates an empty client map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a client map tagging every flow of every client with the client's ID.
+    pub fn from_clients(clients: &[Client]) -> Self {
+        clients
+            .iter()
+            .flat_map(|c| c.flows().iter().map(|f| (f.id, c.id)))
+            .collect()
+    }
+
+    /// Tags `flow` as belonging to `client`, returning the flow's previous client, if any.
+    pub fn tag(&mut self, flow: FlowId, client: ClientId) -> Option<ClientId> {
+        self.flow2client.insert(flow, client)
+    }
+
+    /// Returns the client of a flow, or `None` if the flow is untagged.
+    pub fn client_of(&self, flow: FlowId) -> Option<ClientId> {
+        self.flow2client.get(&flow).copied()
+    }
+
+    /// Returns the IDs of all clients with at least one flow, in ascending order.
+    pub fn clients(&self) -> Vec<ClientId> {
+        self.flow2client
+            .values()
+            .copied()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Returns an iterator over the tagged flows and their clients, in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (FlowId, ClientId)> + '_ {
+        self.flow2client.iter().map(|(&f, &c)| (f, c))
+    }
+
+    /// Returns the number of tagged flows.
+    pub fn len(&self) -> usize {
+        self.flow2client.len()
+    }
+
+    /// Returns true if no flows are tagged.
+    pub fn is_empty(&self) -> bool {
+        self.flow2client.is_empty()
+    }
+}
+
+impl FromIterator<(FlowId, ClientId)> for ClientMap {
+    fn from_iter<T: IntoIterator<Item = (FlowId, ClientId)>>(iter: T) -> Self {
+        Self {
+            flow2client: iter.into_iter().collect(),
+        }
+    }
+}
@@ -3,11 +3,12 @@
 use std::collections::HashSet;
 
 use petgraph::graph::EdgeIndex;
+use rustc_hash::FxHashSet;
 
-use crate::{network::SimNetwork, routing::RoutingAlgo};
+use crate::{linksim::LinkSimDescError, network::SimNetwork, routing::RoutingAlgo};
 
 /// A cluster of edges with a representative member.
-#[derive(Debug, Clone, derive_new::new)]
+#[derive(Debug, Clone, derive_new::new, serde::Serialize, serde::Deserialize)]
 pub struct Cluster {
     representative: EdgeIndex,
     members: HashSet<EdgeIndex>,
@@ -31,6 +32,168 @@ impl Cluster {
     }
 }
 
+/// A set of clusters tagged with the fingerprint of the topology they were computed for. Saving one
+/// of these allows clusters to be reused across runs which only differ in their link simulation
+/// configuration. See [`SimNetwork::save_clusters`] and [`SimNetwork::load_clusters`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SavedClusters {
+    /// The fingerprint of the topology the clusters were computed for.
+    pub fingerprint: u64,
+    /// The clusters.
+    pub clusters: Vec<Cluster>,
+}
+
+/// Checks that `clusters` partition the edges `0..nr_edges`, and that every representative is a
+/// member of its own cluster.
+pub(crate) fn validate(clusters: &[Cluster], nr_edges: usize) -> Result<(), ClusterError> {
+    let mut seen = FxHashSet::default();
+    for cluster in clusters {
+        if !cluster.contains(&cluster.representative) {
+            return Err(ClusterError::RepresentativeNotMember(
+                cluster.representative,
+            ));
+        }
+        for &member in cluster.members() {
+            if member.index() >= nr_edges {
+                return Err(ClusterError::UnknownEdge(member));
+            }
+            if !seen.insert(member) {
+                return Err(ClusterError::DuplicateEdge(member));
+            }
+        }
+    }
+    if let Some(missing) = (0..nr_edges)
+        .map(EdgeIndex::new)
+        .find(|eidx| !seen.contains(eidx))
+    {
+        return Err(ClusterError::MissingEdge(missing));
+    }
+    Ok(())
+}
+
+/// Splits every edge in `isolated` out of its cluster into a cluster of its own. If a
+/// representative is isolated, the smallest remaining member represents the rest of its cluster.
+pub(crate) fn isolate(clusters: Vec<Cluster>, isolated: &FxHashSet<EdgeIndex>) -> Vec<Cluster> {
+    if isolated.is_empty() {
+        return clusters;
+    }
+    let mut out = Vec::with_capacity(clusters.len());
+    for cluster in clusters {
+        let (alone, rest): (HashSet<_>, HashSet<_>) = cluster
+            .members
+            .into_iter()
+            .partition(|eidx| isolated.contains(eidx));
+        let mut alone = alone.into_iter().collect::<Vec<_>>();
+        alone.sort();
+        out.extend(
+            alone
+                .into_iter()
+                .map(|eidx| Cluster::new(eidx, [eidx].into_iter().collect())),
+        );
+        let representative = if rest.contains(&cluster.representative) {
+            Some(cluster.representative)
+        } else {
+            rest.iter().min().copied()
+        };
+        if let Some(representative) = representative {
+            out.push(Cluster::new(representative, rest));
+        }
+    }
+    out
+}
+
+/// The result of checking, before simulating, that every cluster representative can be simulated.
+/// See [`SimNetwork::check_clusters`] and [`SimNetwork::repair_clusters`].
+#[derive(Debug, Default)]
+pub struct ClusterCheck {
+    /// Problems found with representatives, sorted by representative.
+    pub issues: Vec<RepresentativeIssue>,
+    /// Representatives that were replaced by another member of their cluster, sorted by the
+    /// replaced representative.
+    pub promotions: Vec<Promotion>,
+}
+
+impl ClusterCheck {
+    /// Returns true if every issue was resolved by a promotion (or there were no issues).
+    pub fn is_ok(&self) -> bool {
+        self.issues.iter().all(|issue| {
+            self.promotions
+                .iter()
+                .any(|p| p.from == issue.representative())
+        })
+    }
+}
+
+/// A problem with a cluster representative.
+#[derive(Debug)]
+pub enum RepresentativeIssue {
+    /// The representative has no flows, but some members do. Without a promotion, the members'
+    /// delay distributions would be left empty.
+    Empty {
+        /// The representative.
+        representative: EdgeIndex,
+        /// The number of members with flows.
+        nr_starved: usize,
+    },
+    /// A link-level topology can't be constructed for the representative, so its simulation
+    /// would fail.
+    Invalid {
+        /// The representative.
+        representative: EdgeIndex,
+        /// The reason.
+        error: LinkSimDescError,
+    },
+}
+
+impl RepresentativeIssue {
+    /// Returns the representative with the issue.
+    pub fn representative(&self) -> EdgeIndex {
+        match *self {
+            Self::Empty { representative, .. } | Self::Invalid { representative, .. } => {
+                representative
+            }
+        }
+    }
+}
+
+/// A change of cluster representative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Promotion {
+    /// The previous representative.
+    pub from: EdgeIndex,
+    /// The new representative.
+    pub to: EdgeIndex,
+}
+
+/// Errors which can be encountered setting a network's clusters.
+#[derive(Debug, thiserror::Error)]
+pub enum ClusterError {
+    /// The clusters were computed for a different topology.
+    #[error("Topology fingerprint mismatch (expected {expected:#x}, got {actual:#x})")]
+    FingerprintMismatch {
+        /// The fingerprint of the current topology.
+        expected: u64,
+        /// The fingerprint the clusters were saved with.
+        actual: u64,
+    },
+
+    /// A cluster contains an edge which doesn't exist.
+    #[error("Edge {0:?} does not exist")]
+    UnknownEdge(EdgeIndex),
+
+    /// An edge belongs to more than one cluster.
+    #[error("Edge {0:?} is in more than one cluster")]
+    DuplicateEdge(EdgeIndex),
+
+    /// An edge doesn't belong to any cluster.
+    #[error("Edge {0:?} is not in any cluster")]
+    MissingEdge(EdgeIndex),
+
+    /// A cluster's representative isn't one of its members.
+    #[error("Representative {0:?} is not a member of its cluster")]
+    RepresentativeNotMember(EdgeIndex),
+}
+
 /// The trait that must be implemented by all clustering algorithms.
 pub trait ClusteringAlgo {
     /// Given a [`SimNetwork`], run a clustering algorithm and return a vector of
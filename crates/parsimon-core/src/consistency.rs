@@ -0,0 +1,72 @@
+//! This module defines optional consistency checks between predicted and ideal FCTs.
+//!
+//! `Parsimon` predicts the delay a flow experiences on top of the ideal FCT of its path, so a
+//! predicted FCT should never be below ideal. Link simulators can nonetheless report FCTs below
+//! ideal because of rounding and normalization, and corrupt distributions can produce negative
+//! delay samples. Either usually points to a modeling bug. Violations are always clamped to the
+//! ideal FCT; a [`ConsistencyCheck`] controls whether they are also counted or rejected.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use petgraph::graph::EdgeIndex;
+
+/// How to handle predictions below the ideal FCT.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ConsistencyCheck {
+    /// Clamp violations without counting them.
+    #[default]
+    Off,
+    /// Clamp violations and count them in the delay network's [`ConsistencyReport`].
+    Clamp,
+    /// Like `Clamp`, but simulation records below ideal are an error when filling delay
+    /// distributions. Violations at query time are still clamped and counted.
+    Deny,
+}
+
+impl ConsistencyCheck {
+    /// Returns true if violations are counted.
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, Self::Off)
+    }
+}
+
+/// Counts of consistency violations detected while building and querying a delay network.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConsistencyReport {
+    /// For every simulated edge with violations, the number of FCT records below ideal.
+    pub fill_violations: Vec<(EdgeIndex, usize)>,
+    /// The number of predictions whose sampled delay was negative or not a number.
+    pub query_violations: usize,
+}
+
+impl ConsistencyReport {
+    /// Returns the total number of FCT records below ideal.
+    pub fn nr_fill_violations(&self) -> usize {
+        self.fill_violations.iter().map(|&(_, n)| n).sum()
+    }
+
+    /// Returns true if no violations were detected.
+    pub fn is_clean(&self) -> bool {
+        self.fill_violations.is_empty() && self.query_violations == 0
+    }
+}
+
+/// A thread-safe counter of query-time violations.
+#[derive(Debug, Default)]
+pub(crate) struct ViolationCounter(AtomicUsize);
+
+impl ViolationCounter {
+    pub(crate) fn incr(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Clone for ViolationCounter {
+    fn clone(&self) -> Self {
+        Self(AtomicUsize::new(self.get()))
+    }
+}
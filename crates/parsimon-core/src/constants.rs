@@ -1,4 +1,9 @@
 //! Simulation constants. These are set to match the ns-3 implementation's default behavior.
+//!
+//! Every component that accounts for packets (flow accounting, ideal FCTs, delay normalization,
+//! and link simulators) takes its sizes from a [`PacketProfile`], whose default is made of the
+//! constants below. Networks with different MTUs or header overheads can override the profile in
+//! one place with [`Network::with_packet_profile`](crate::network::Network::with_packet_profile).
 
 use crate::units::Bytes;
 
@@ -10,3 +15,56 @@ pub const SZ_PKTHDR: Bytes = Bytes::new(48);
 
 /// The ACK size.
 pub const SZ_ACK: Bytes = Bytes::new(60);
+
+/// The sizes of the packets flows are split into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct PacketProfile {
+    /// The maximum payload of a packet.
+    pub max_payload: Bytes,
+    /// The header size added to every packet.
+    pub header: Bytes,
+    /// The ACK size.
+    pub ack: Bytes,
+}
+
+impl PacketProfile {
+    /// Returns the number of packets a flow of `size` bytes is split into.
+    pub fn nr_pkts(&self, size: Bytes) -> u64 {
+        size.into_u64().div_ceil(self.max_payload.into_u64())
+    }
+
+    /// Returns the size of a full packet, including its header.
+    pub fn full_pkt(&self) -> Bytes {
+        self.max_payload + self.header
+    }
+}
+
+impl Default for PacketProfile {
+    fn default() -> Self {
+        Self {
+            max_payload: SZ_PKTMAX,
+            header: SZ_PKTHDR,
+            ack: SZ_ACK,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_are_counted_by_payload() {
+        let profile = PacketProfile::default();
+        assert_eq!(profile.nr_pkts(Bytes::ZERO), 0);
+        assert_eq!(profile.nr_pkts(Bytes::new(1)), 1);
+        assert_eq!(profile.nr_pkts(Bytes::new(1000)), 1);
+        assert_eq!(profile.nr_pkts(Bytes::new(1001)), 2);
+        assert_eq!(profile.full_pkt(), Bytes::new(1048));
+        let jumbo = PacketProfile {
+            max_payload: Bytes::new(9000),
+            ..profile
+        };
+        assert_eq!(jumbo.nr_pkts(Bytes::new(10_000)), 2);
+    }
+}
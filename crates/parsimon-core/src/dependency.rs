@@ -0,0 +1,361 @@
+//! Flow dependencies, e.g., the request-response chains of RPCs and the stages of coflows. A
+//! [`FlowDependency`] makes a flow start only after another one finishes. Since finish times depend
+//! on predicted FCTs, which in turn depend on when flows start, [`run_dependent`] iterates: it
+//! predicts the FCT of every flow with dependents, reschedules dependent flows accordingly,
+//! re-simulates the links carrying rescheduled flows, and repeats until start times settle.
+//!
+//! A flow's start time in the [`Spec`] is its release time, the earliest it can start. With
+//! dependencies, it starts at the later of its release time and the time every flow it depends on
+//! has finished, plus the dependency's gap.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{
+    cluster::ClusteringAlgo,
+    linksim::LinkSim,
+    network::{DelayNetwork, Flow, FlowId},
+    opts::SimOpts,
+    run::Error,
+    spec::{Spec, SpecError},
+    units::Nanosecs,
+};
+
+/// A dependency of one flow on another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FlowDependency {
+    /// The dependent flow.
+    pub flow: FlowId,
+    /// The flow which must finish before `flow` starts.
+    pub after: FlowId,
+    /// The time between `after` finishing and `flow` starting, e.g., to process a request.
+    #[serde(default)]
+    pub gap: Nanosecs,
+}
+
+/// Options for [`run_dependent`].
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder)]
+pub struct DependencyOpts {
+    /// The largest number of times flows are rescheduled.
+    #[builder(default = 10)]
+    pub max_iterations: usize,
+    /// Iteration stops once no flow's start time moves by more than this.
+    #[builder(default = Nanosecs::new(1_000))]
+    pub tolerance: Nanosecs,
+}
+
+impl Default for DependencyOpts {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// The result of [`run_dependent`].
+#[derive(Debug)]
+pub struct DependentRun {
+    /// The delay network of the last iteration.
+    pub delays: DelayNetwork,
+    /// The flows with their resolved start times, in specification order. Start times are
+    /// absolute, like those of the specification.
+    pub flows: Vec<Flow>,
+    /// The number of times flows were rescheduled.
+    pub iterations: usize,
+    /// The largest change of a flow's start time in the last iteration.
+    pub max_shift: Nanosecs,
+    /// Whether start times settled within [`DependencyOpts::tolerance`].
+    pub converged: bool,
+}
+
+/// Runs a specification with [flow dependencies](Spec::dependencies), as described in the
+/// [module documentation](self). Only the links carrying rescheduled flows are re-simulated in
+/// each iteration, each as its own cluster.
+///
+/// FCTs are predicted with the delay network's [RNG](DelayNetwork::rng), restarted every
+/// iteration. Flows whose FCT can't be predicted are treated as finishing when they start. As with
+/// [`run`](crate::run::run), delay distributions are also kept for each of the specification's
+/// [clients](Spec::clients).
+pub fn run_dependent<S, C>(
+    spec: Spec,
+    opts: SimOpts<S>,
+    clusterer: C,
+    dep_opts: DependencyOpts,
+) -> Result<DependentRun, Error>
+where
+    S: LinkSim + Clone + Sync,
+    C: ClusteringAlgo,
+{
+    let spec = spec.validate()?;
+    if !spec.schedule.is_empty() {
+        return Err(Error::Scheduled);
+    }
+    let order = order(&spec.flows, &spec.dependencies)?;
+    let mut flows = spec.collect_flows();
+    let network = spec.network.seeded(opts.seed);
+    let simulate = |flows: Vec<Flow>| {
+        let mut sims = network.clone().into_simulations(flows);
+        sims.set_origin(spec.origin);
+        sims
+    };
+    let mut sims = simulate(flows.clone());
+    sims.cluster(clusterer);
+    let mut delays = if spec.clients.is_empty() {
+        sims.into_delays(opts.clone())?
+    } else {
+        sims.into_delays_by_client(opts.clone(), &spec.clients)?
+    };
+    let mut iterations = 0;
+    let mut max_shift = Nanosecs::ZERO;
+    let mut converged = spec.dependencies.is_empty();
+    while !converged && iterations < dep_opts.max_iterations {
+        let scheduled = schedule(&spec.flows, &order, &spec.dependencies, &delays);
+        iterations += 1;
+        let moved = flows
+            .iter()
+            .zip(&scheduled)
+            .filter(|(old, new)| old.start != new.start)
+            .map(|(old, _)| old.id)
+            .collect::<HashSet<_>>();
+        max_shift = flows
+            .iter()
+            .zip(&scheduled)
+            .map(|(old, new)| old.start.max(new.start) - old.start.min(new.start))
+            .max()
+            .unwrap_or_default();
+        converged = max_shift <= dep_opts.tolerance;
+        if moved.is_empty() {
+            break;
+        }
+        flows = scheduled;
+        let sims = simulate(flows.clone());
+        let affected = sims
+            .edge_indices()
+            .filter(|&eidx| {
+                sims.flows_on(eidx)
+                    .is_some_and(|on| on.iter().any(|f| moved.contains(&f.id)))
+            })
+            .collect::<Vec<_>>();
+        delays =
+            sims.into_delays_reusing_by_client(&delays, affected, opts.clone(), &spec.clients)?;
+    }
+    let flows = flows
+        .into_iter()
+        .map(|f| Flow {
+            start: f.start + spec.origin,
+            ..f
+        })
+        .collect();
+    Ok(DependentRun {
+        delays,
+        flows,
+        iterations,
+        max_shift,
+        converged,
+    })
+}
+
+/// Checks that every dependency refers to existing flows and that dependencies are acyclic.
+pub(crate) fn validate(flows: &[Flow], dependencies: &[FlowDependency]) -> Result<(), SpecError> {
+    order(flows, dependencies).map(drop)
+}
+
+// Returns the positions of flows in an order in which every flow comes after the flows it depends
+// on
+fn order(flows: &[Flow], dependencies: &[FlowDependency]) -> Result<Vec<usize>, SpecError> {
+    let positions = flows
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (f.id, i))
+        .collect::<HashMap<_, _>>();
+    let position = |id| {
+        positions
+            .get(&id)
+            .copied()
+            .ok_or(SpecError::UnknownDependencyFlow { flow: id })
+    };
+    let mut dependents = vec![Vec::new(); flows.len()];
+    let mut nr_pending = vec![0_usize; flows.len()];
+    for dep in dependencies {
+        let (flow, after) = (position(dep.flow)?, position(dep.after)?);
+        dependents[after].push(flow);
+        nr_pending[flow] += 1;
+    }
+    let mut ready = (0..flows.len())
+        .filter(|&i| nr_pending[i] == 0)
+        .collect::<VecDeque<_>>();
+    let mut order = Vec::with_capacity(flows.len());
+    while let Some(i) = ready.pop_front() {
+        order.push(i);
+        for &j in &dependents[i] {
+            nr_pending[j] -= 1;
+            if nr_pending[j] == 0 {
+                ready.push_back(j);
+            }
+        }
+    }
+    match nr_pending.iter().position(|&n| n > 0) {
+        Some(i) => Err(SpecError::CyclicDependency { flow: flows[i].id }),
+        None => Ok(order),
+    }
+}
+
+// Resolves start times from the release times of `released`, visiting flows in `order`
+fn schedule(
+    released: &[Flow],
+    order: &[usize],
+    dependencies: &[FlowDependency],
+    delays: &DelayNetwork,
+) -> Vec<Flow> {
+    let positions = released
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (f.id, i))
+        .collect::<HashMap<_, _>>();
+    let mut after = vec![Vec::new(); released.len()];
+    for dep in dependencies {
+        after[positions[&dep.flow]].push((positions[&dep.after], dep.gap));
+    }
+    let mut rng = delays.rng();
+    let mut flows = released.to_vec();
+    let mut finish = vec![Nanosecs::ZERO; flows.len()];
+    for &i in order {
+        let start = after[i]
+            .iter()
+            .map(|&(j, gap)| finish[j] + gap)
+            .fold(released[i].start, Nanosecs::max);
+        let f = &mut flows[i];
+        f.start = start;
+        let fct = delays
+            .predict_breakdown(f.size, (f.src, f.dst), &mut rng)
+            .map_or(Nanosecs::ZERO, |b| b.total());
+        finish[i] = start + fct;
+    }
+    flows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::{ClientId, ClientMap},
+        cluster::DefaultClustering,
+        network::NodeId,
+        testing::{self, FixedDelaySim},
+        units::Bytes,
+    };
+
+    fn flow(id: usize, src: usize, dst: usize) -> Flow {
+        Flow {
+            id: FlowId::new(id),
+            src: NodeId::new(src),
+            dst: NodeId::new(dst),
+            size: Bytes::new(10_000),
+            start: Nanosecs::ZERO,
+        }
+    }
+
+    #[test]
+    fn responses_start_after_requests_finish() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let dependencies = vec![
+            FlowDependency {
+                flow: FlowId::new(1),
+                after: FlowId::new(0),
+                gap: Nanosecs::new(500),
+            },
+            FlowDependency {
+                flow: FlowId::new(2),
+                after: FlowId::new(1),
+                gap: Nanosecs::ZERO,
+            },
+        ];
+        let spec = Spec::builder()
+            .nodes(nodes)
+            .links(links)
+            .flows(vec![
+                flow(0, 0, 2),
+                flow(1, 2, 0),
+                flow(2, 0, 3),
+                flow(3, 1, 3),
+            ])
+            .dependencies(dependencies)
+            .build();
+        let opts = SimOpts::builder()
+            .link_sim(FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let run = run_dependent(spec, opts, DefaultClustering, DependencyOpts::default())?;
+        assert!(run.converged);
+        let start = |i: usize| run.flows[i].start;
+        let fct = |i: usize| {
+            let f = &run.flows[i];
+            run.delays
+                .predict_breakdown(f.size, (f.src, f.dst), run.delays.rng())
+                .unwrap()
+                .total()
+        };
+        assert_eq!(start(0), Nanosecs::ZERO);
+        assert!(start(1) >= start(0) + fct(0) + Nanosecs::new(500));
+        assert!(start(2) >= start(1) + fct(1));
+        assert_eq!(start(3), Nanosecs::ZERO);
+        Ok(())
+    }
+
+    #[test]
+    fn clients_keep_their_distributions() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = vec![flow(0, 0, 2), flow(1, 2, 0), flow(2, 0, 3), flow(3, 1, 3)];
+        let clients = flows
+            .iter()
+            .map(|f| (f.id, ClientId::new(f.id.inner() % 2)))
+            .collect::<ClientMap>();
+        let spec = Spec::builder()
+            .nodes(nodes)
+            .links(links)
+            .flows(flows)
+            .dependencies(vec![FlowDependency {
+                flow: FlowId::new(1),
+                after: FlowId::new(0),
+                gap: Nanosecs::ZERO,
+            }])
+            .clients(clients.clone())
+            .build();
+        let opts = SimOpts::builder()
+            .link_sim(FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let run = run_dependent(spec, opts, DefaultClustering, DependencyOpts::default())?;
+        assert!(run.iterations > 0);
+        assert_eq!(
+            run.delays.clients(),
+            vec![ClientId::new(0), ClientId::new(1)]
+        );
+        for f in &run.flows {
+            let client = clients.client_of(f.id).unwrap();
+            let delays = run.delays.for_client(client).unwrap();
+            assert!(delays
+                .predict(f.size, (f.src, f.dst), delays.rng())
+                .is_some());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn cyclic_dependencies_fail() {
+        let flows = vec![flow(0, 0, 1), flow(1, 1, 0)];
+        let dep = |flow, after| FlowDependency {
+            flow: FlowId::new(flow),
+            after: FlowId::new(after),
+            gap: Nanosecs::ZERO,
+        };
+        assert!(matches!(
+            validate(&flows, &[dep(0, 1), dep(1, 0)]),
+            Err(SpecError::CyclicDependency { .. })
+        ));
+        assert!(matches!(
+            validate(&flows, &[dep(0, 5)]),
+            Err(SpecError::UnknownDependencyFlow { .. })
+        ));
+        assert!(validate(&flows, &[dep(1, 0)]).is_ok());
+    }
+}
@@ -1,17 +1,82 @@
 //! Types for distributed simulations.
 
-use std::net::SocketAddr;
+use std::{
+    collections::VecDeque,
+    fmt,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
 
+use crossbeam_channel::{Receiver, TryRecvError};
+use rustc_hash::FxHashSet;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
+    task::JoinSet,
 };
+use tracing::Instrument;
 
 use crate::{
+    constants::PacketProfile,
     linksim::LinkSimDesc,
-    network::{FctRecord, Flow, SimNetworkError},
+    network::{fallback::FailurePolicy, FctRecord, Flow, SimNetworkError},
+    telemetry::{self, TraceContext},
 };
 
+/// Identifies a distributed run, or job, on the workers it uses. Every batch of a run carries the
+/// run's job ID, so that workers shared by several coordinators can keep jobs apart.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(transparent)]
+pub struct JobId(u64);
+
+impl JobId {
+    /// Returns a random job ID.
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+}
+
+impl fmt::Display for JobId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// A request to a worker node.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum WorkerRequest {
+    /// Simulate a batch of links and respond with their [`WorkerOut`].
+    Simulate(WorkerParams),
+    /// Respond with the worker's [`WorkerStatus`].
+    Status,
+}
+
+/// The status of a worker node, obtained with [`worker_status`].
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct WorkerStatus {
+    /// The maximum number of jobs the worker runs batches for at once, if limited.
+    pub max_jobs: Option<usize>,
+    /// The jobs the worker has seen recently, sorted by ID.
+    pub jobs: Vec<JobStatus>,
+}
+
+/// The status of a job on a worker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct JobStatus {
+    /// The job's ID.
+    pub id: JobId,
+    /// The number of the job's batches waiting for the job to be admitted.
+    pub nr_waiting: usize,
+    /// The number of the job's batches being simulated.
+    pub nr_running: usize,
+    /// The number of the job's batches which completed successfully.
+    pub nr_done: usize,
+    /// The number of the job's batches which failed.
+    pub nr_failed: usize,
+}
+
 /// Input parameters for worker nodes.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct WorkerParams {
@@ -21,17 +86,195 @@ pub struct WorkerParams {
     pub descs: Vec<LinkSimDesc>,
     /// All flows referenced by the descriptors.
     pub flows: Vec<Flow>,
+    /// The sizes of the packets flows are split into.
+    #[serde(default)]
+    pub packets: PacketProfile,
+    /// The context of the coordinator's span for this batch. See [`crate::telemetry`].
+    #[serde(default)]
+    pub trace_context: TraceContext,
+    /// The job the batch belongs to. Workers treat batches without one as separate jobs.
+    #[serde(default)]
+    pub job: Option<JobId>,
+    /// If set, workers give up on link simulations which take longer than this and leave them
+    /// out of their output.
+    #[serde(default)]
+    pub timeout: Option<Duration>,
 }
 
 /// The output of a worker.
 pub type WorkerOut = Vec<(usize, Vec<FctRecord>)>;
 
+/// An update to the set of workers used by a distributed run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerUpdate {
+    /// A worker has become available.
+    Add(SocketAddr),
+    /// A worker should stop receiving work. Its in-flight work is allowed to finish, and if that
+    /// fails (e.g., because the worker was preempted), it is rescheduled on another worker.
+    Remove(SocketAddr),
+}
+
+// How often the scheduler checks for worker updates while waiting for results.
+const UPDATE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The outcome of [`schedule`].
+#[derive(Debug)]
+pub(crate) struct Scheduled<T> {
+    pub(crate) results: WorkerOut,
+    // The edges whose simulations timed out on their workers
+    pub(crate) timed_out: Vec<usize>,
+    // Batches which failed every attempt, if the failure policy tolerates failures
+    pub(crate) failed: Vec<FailedBatch<T>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct FailedBatch<T> {
+    pub(crate) batch: Vec<T>,
+    pub(crate) attempts: usize,
+    pub(crate) error: SimNetworkError,
+}
+
+/// Runs `batches` on `workers`, one batch per worker at a time, as a single job. The worker set
+/// can change while batches are running via `updates`. If batches are queued with no worker left
+/// to run them, the run waits up to `grace` for a worker to be added, and fails if none is or
+/// `updates` is closed. Failed batches are retried as `policy` allows. Every
+/// batch sent to a worker is traced as a child of `parent`.
+pub(crate) async fn schedule<T, F>(
+    workers: &[SocketAddr],
+    updates: Option<&Receiver<WorkerUpdate>>,
+    grace: Duration,
+    batches: Vec<Vec<T>>,
+    mut make_params: F,
+    policy: &FailurePolicy,
+    parent: &tracing::Span,
+) -> Result<Scheduled<T>, SimNetworkError>
+where
+    T: Send + 'static,
+    F: FnMut(&[T]) -> Result<WorkerParams, SimNetworkError>,
+{
+    let mut active = workers.to_vec();
+    let mut idle = workers.iter().copied().collect::<VecDeque<_>>();
+    // Every batch is queued with its number of failed attempts
+    let mut queue = batches
+        .into_iter()
+        .map(|batch| (batch, 0))
+        .collect::<VecDeque<_>>();
+    let mut in_flight = JoinSet::new();
+    let mut updates = updates;
+    let mut results = Vec::new();
+    let mut failed = Vec::new();
+    let mut timed_out = Vec::new();
+    let job = JobId::random();
+    // When the run started waiting for a worker to be added
+    let mut waiting_since = None;
+    loop {
+        while let Some(rx) = updates {
+            match rx.try_recv() {
+                Ok(WorkerUpdate::Add(worker)) => {
+                    if !active.contains(&worker) {
+                        active.push(worker);
+                        idle.push_back(worker);
+                    }
+                }
+                Ok(WorkerUpdate::Remove(worker)) => {
+                    // Busy workers are drained: they are simply not returned to the idle set.
+                    active.retain(|&w| w != worker);
+                    idle.retain(|&w| w != worker);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => updates = None,
+            }
+        }
+        while !queue.is_empty() {
+            let Some(worker) = idle.pop_front() else {
+                break;
+            };
+            let (batch, nr_failures) = queue.pop_front().unwrap();
+            let mut params = make_params(&batch)?;
+            let batch_span = tracing::debug_span!(parent: parent, "batch", %worker, %job, nr_edges = batch.len());
+            params.trace_context = telemetry::context_of(&batch_span);
+            params.job = Some(job);
+            let expected = params.descs.iter().map(|d| d.edge).collect::<Vec<_>>();
+            in_flight.spawn(
+                async move {
+                    let out = work_remote(worker, params).await;
+                    (worker, batch, nr_failures, expected, out)
+                }
+                .instrument(batch_span),
+            );
+        }
+        if in_flight.is_empty() {
+            if queue.is_empty() {
+                break;
+            }
+            // Every active worker is idle, so none are left for the queued batches. A replacement
+            // may still be added, but waiting on `updates` without a deadline could block forever.
+            let since = *waiting_since.get_or_insert_with(Instant::now);
+            if updates.is_none() || since.elapsed() >= grace {
+                return Err(SimNetworkError::NoWorkers);
+            }
+            tokio::time::sleep(UPDATE_POLL_INTERVAL).await;
+            continue;
+        }
+        waiting_since = None;
+        if let Ok(Some(res)) =
+            tokio::time::timeout(UPDATE_POLL_INTERVAL, in_flight.join_next()).await
+        {
+            let (worker, batch, nr_failures, expected, out) = res?;
+            let is_active = active.contains(&worker);
+            match out {
+                Ok(mut out) => {
+                    // Workers leave out the simulations which timed out
+                    let returned = out.iter().map(|(edge, _)| *edge).collect::<FxHashSet<_>>();
+                    timed_out.extend(expected.into_iter().filter(|e| !returned.contains(e)));
+                    results.append(&mut out);
+                }
+                // A removed worker may have been shut down before finishing its batch
+                Err(e) if !is_active => {
+                    tracing::warn!(%worker, error = %e, "rescheduling batch of removed worker");
+                    queue.push_back((batch, nr_failures));
+                }
+                Err(e) if nr_failures < policy.retries => {
+                    tracing::warn!(%worker, error = %e, "retrying failed batch");
+                    queue.push_back((batch, nr_failures + 1));
+                }
+                Err(error) if policy.tolerates_failures() => failed.push(FailedBatch {
+                    batch,
+                    attempts: nr_failures + 1,
+                    error,
+                }),
+                Err(e) => return Err(e),
+            }
+            if is_active {
+                idle.push_back(worker);
+            }
+        }
+    }
+    Ok(Scheduled {
+        results,
+        timed_out,
+        failed,
+    })
+}
+
 pub(crate) async fn work_remote(
     worker: SocketAddr,
     params: WorkerParams,
 ) -> Result<WorkerOut, SimNetworkError> {
-    // Serialize the params and send them.
-    let buf = rmp_serde::encode::to_vec(&params)?;
+    request(worker, &WorkerRequest::Simulate(params)).await
+}
+
+/// Queries the status of a worker, i.e., the jobs it is running or ran recently.
+pub async fn worker_status(worker: SocketAddr) -> Result<WorkerStatus, SimNetworkError> {
+    request(worker, &WorkerRequest::Status).await
+}
+
+async fn request<T>(worker: SocketAddr, req: &WorkerRequest) -> Result<T, SimNetworkError>
+where
+    T: serde::de::DeserializeOwned,
+{
+    // Serialize the request and send it.
+    let buf = rmp_serde::encode::to_vec(req)?;
     let mut stream = TcpStream::connect(worker).await?;
     stream.write_all(&buf).await?;
 
@@ -45,3 +288,220 @@ pub(crate) async fn work_remote(
 
     Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use crossbeam_channel::unbounded;
+
+    use super::*;
+    use crate::{
+        cluster::DefaultClustering,
+        network::{Flow, FlowId, Network, NodeId},
+        opts::SimOpts,
+        records::Retention,
+        testing::{self, worker::FakeWorker, FixedDelaySim},
+        units::{Bytes, Nanosecs},
+    };
+
+    const SIM: FixedDelaySim = FixedDelaySim {
+        delay_per_pkt: Nanosecs::new(100),
+    };
+
+    fn flows() -> Vec<Flow> {
+        many_flows(20)
+    }
+
+    fn many_flows(n: usize) -> Vec<Flow> {
+        (0..n)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 4),
+                dst: NodeId::new((i + 1) % 4),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn workers_can_be_replaced_during_a_run() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let mut sims = Network::new(&nodes, &links)?.into_simulations(flows());
+        sims.cluster(DefaultClustering);
+        // Nothing listens on the initial worker's address, so it must be replaced before any
+        // work is sent to it.
+        let dead = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let (tx, rx) = unbounded();
+        let worker = FakeWorker::spawn(SIM);
+        tx.send(WorkerUpdate::Add(worker.addr()))?;
+        tx.send(WorkerUpdate::Remove(dead))?;
+        drop(tx);
+        let opts = SimOpts::builder()
+            .link_sim(FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .workers(vec![dead])
+            .worker_updates(rx)
+            .build();
+        let delays = sims.into_delays(opts)?;
+        let mut rng = rand::thread_rng();
+        let delay = delays.predict(Bytes::new(1000), (NodeId::new(0), NodeId::new(1)), &mut rng);
+        assert_eq!(delay, Some(Nanosecs::new(200)));
+        Ok(())
+    }
+
+    #[test]
+    fn distributed_runs_match_local_runs() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        // Enough flows that requests span many reads
+        let sims = Network::new(&nodes, &links)?.into_simulations(many_flows(5000));
+        let run = |workers| {
+            let opts = SimOpts::builder()
+                .link_sim(SIM)
+                .workers(workers)
+                .retention(Retention::Memory)
+                .build();
+            sims.clone().into_delays(opts)
+        };
+        let workers = [FakeWorker::spawn(SIM), FakeWorker::spawn(SIM)];
+        let remote = run(workers.iter().map(|w| w.addr()).collect())?;
+        let local = run(vec![SocketAddr::from(([127, 0, 0, 1], 8080))])?;
+        assert!(workers.iter().all(|w| w.nr_requests() > 0));
+        let (remote, local) = (remote.records().unwrap(), local.records().unwrap());
+        let fcts = |records: Option<Vec<FctRecord>>| {
+            let mut fcts = records
+                .unwrap_or_default()
+                .iter()
+                .map(|r| (r.id, r.fct))
+                .collect::<Vec<_>>();
+            fcts.sort();
+            fcts
+        };
+        for eidx in sims.edge_indices() {
+            assert_eq!(fcts(remote.get(eidx)?), fcts(local.get(eidx)?));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn batches_of_a_run_share_a_job() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows());
+        let workers = [FakeWorker::spawn(SIM), FakeWorker::spawn(SIM)];
+        let opts = SimOpts::builder()
+            .link_sim(SIM)
+            .workers(workers.iter().map(|w| w.addr()).collect())
+            .build();
+        sims.clone().into_delays(opts.clone())?;
+        sims.into_delays(opts)?;
+        let jobs = workers
+            .iter()
+            .flat_map(|w| w.jobs())
+            .collect::<FxHashSet<_>>();
+        assert_eq!(jobs.len(), 2);
+
+        let rt = tokio::runtime::Runtime::new()?;
+        let status = rt.block_on(worker_status(workers[0].addr()))?;
+        assert_eq!(
+            status.jobs.iter().map(|j| j.id).collect::<Vec<_>>(),
+            workers[0].jobs()
+        );
+        assert!(status.jobs.iter().all(|j| j.nr_done > 0));
+        Ok(())
+    }
+
+    #[test]
+    fn failed_workers_fail_runs() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows());
+        let (failing, healthy) = (FakeWorker::spawn_failing(SIM, 1), FakeWorker::spawn(SIM));
+        let run = || {
+            let opts = SimOpts::builder()
+                .link_sim(SIM)
+                .workers(vec![failing.addr(), healthy.addr()])
+                .build();
+            sims.clone().into_delays(opts)
+        };
+        assert!(run().is_err());
+        assert_eq!(failing.nr_requests(), 1);
+        // The worker recovers, e.g., after a restart
+        assert!(run().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn failed_batches_are_retried() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows());
+        let (failing, healthy) = (FakeWorker::spawn_failing(SIM, 1), FakeWorker::spawn(SIM));
+        let opts = SimOpts::builder()
+            .link_sim(SIM)
+            .workers(vec![failing.addr(), healthy.addr()])
+            .on_failure(FailurePolicy::builder().retries(1).build())
+            .build();
+        let delays = sims.into_delays(opts)?;
+        assert!(delays.link_failures().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn no_workers_fails() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows());
+        let opts = SimOpts::builder()
+            .link_sim(FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .workers(Vec::new())
+            .build();
+        let res = sims.into_delays(opts);
+        assert!(matches!(res, Err(SimNetworkError::NoWorkers)));
+        Ok(())
+    }
+
+    #[test]
+    fn no_workers_fails_with_open_updates() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows());
+        let (_tx, rx) = unbounded();
+        let opts = SimOpts::builder()
+            .link_sim(SIM)
+            .workers(Vec::new())
+            .worker_updates(rx)
+            .worker_grace(Duration::from_millis(300))
+            .build();
+        let res = sims.into_delays(opts);
+        assert!(matches!(res, Err(SimNetworkError::NoWorkers)));
+        Ok(())
+    }
+
+    #[test]
+    fn runs_wait_for_replacement_workers() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows());
+        // The only worker is removed before its replacement is added
+        let dead = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let (tx, rx) = unbounded();
+        tx.send(WorkerUpdate::Remove(dead))?;
+        let worker = FakeWorker::spawn(SIM);
+        let addr = worker.addr();
+        let adder = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            tx.send(WorkerUpdate::Add(addr))
+        });
+        let opts = SimOpts::builder()
+            .link_sim(SIM)
+            .workers(vec![dead])
+            .worker_updates(rx)
+            .build();
+        let delays = sims.into_delays(opts)?;
+        adder.join().unwrap()?;
+        assert!(worker.nr_requests() > 0);
+        let mut rng = rand::thread_rng();
+        let delay = delays.predict(Bytes::new(1000), (NodeId::new(0), NodeId::new(1)), &mut rng);
+        assert_eq!(delay, Some(Nanosecs::new(200)));
+        Ok(())
+    }
+}
@@ -1,21 +1,40 @@
 //! Types for building empirical distributions
 
-use std::{collections::VecDeque, ops::Range};
+use std::{
+    collections::VecDeque,
+    hash::{Hash, Hasher},
+    ops::Range,
+};
 
+use itertools::Itertools;
 use rand::prelude::*;
+use rustc_hash::FxHashMap;
 
-use crate::units::Bytes;
+use crate::{
+    network::NodeId,
+    units::{BitsPerSec, Bytes, Nanosecs},
+};
 
-/// Empirical distributions bucketed by size ranges (in bytes).
-#[derive(Debug, Clone)]
+/// Empirical distributions bucketed by size ranges (in bytes). Buckets can optionally be keyed by
+/// a secondary source group (e.g., the rack flows come from), so flows from different groups
+/// sharing a link can see systematically different delays, or by the load offered to the link when
+/// flows start (see [`BucketStrategy::SizeAndLoad`]).
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct EDistBuckets {
     inner: Vec<(Range<Bytes>, EDist)>,
+    // Distributions of the flows from each source group, by the group's node ID
+    groups: FxHashMap<NodeId, EDistBuckets>,
+    // Distributions of the flows starting under each range of offered load, in ascending order
+    #[serde(default)]
+    loads: Vec<(Range<f64>, EDistBuckets)>,
 }
 
 impl EDistBuckets {
     pub(crate) fn new_empty() -> Self {
         Self {
             inner: vec![(Bytes::ZERO..Bytes::MAX, EDist::new())],
+            groups: FxHashMap::default(),
+            loads: Vec::new(),
         }
     }
 
@@ -41,34 +60,278 @@ impl EDistBuckets {
             })
             .collect::<Result<Vec<_>, _>>()?;
         self.inner = inner;
+        self.groups.clear();
+        self.loads.clear();
         Ok(())
     }
 
+    pub(crate) fn set_groups(&mut self, groups: FxHashMap<NodeId, EDistBuckets>) {
+        self.groups = groups;
+    }
+
+    pub(crate) fn set_loads(&mut self, loads: Vec<(Range<f64>, EDistBuckets)>) {
+        self.loads = loads;
+    }
+
+    // Multiplies every sample, including those of source groups, by `factor`
+    pub(crate) fn scale(&mut self, factor: f64) {
+        for (_, dist) in &mut self.inner {
+            dist.scale(factor);
+        }
+        for dists in self.groups.values_mut() {
+            dists.scale(factor);
+        }
+        for (_, dists) in &mut self.loads {
+            dists.scale(factor);
+        }
+    }
+
+    /// Returns the distributions of the flows which started while the offered load was `load`,
+    /// or `None` if the distributions aren't bucketed by load or `load` is negative or NaN.
+    pub fn for_load(&self, load: f64) -> Option<&EDistBuckets> {
+        if load.is_nan() || load < 0.0 {
+            return None;
+        }
+        // Only an infinite load lies past the last range
+        self.loads
+            .iter()
+            .find(|(range, _)| range.contains(&load))
+            .or(self.loads.last())
+            .map(|(_, dists)| dists)
+    }
+
+    // Merges distributions over the same link, each weighted by the share of samples it should
+    // contribute. Size buckets are split at every part's boundaries, so each merged bucket mixes
+    // the parts' distributions for the same sizes. Source groups are merged, with parts lacking a
+    // group contributing their distributions over all groups. Load distributions are dropped,
+    // since their ranges differ between parts. Returns `None` if no part has samples.
+    pub(crate) fn merge(parts: &[(&EDistBuckets, f64)]) -> Option<Self> {
+        let mut bounds = parts
+            .iter()
+            .flat_map(|(dists, _)| dists.bucket_ranges().flat_map(|r| [r.start, r.end]))
+            .collect::<Vec<_>>();
+        bounds.sort();
+        bounds.dedup();
+        let inner = bounds
+            .windows(2)
+            .filter_map(|w| {
+                let dists = parts
+                    .iter()
+                    .filter_map(|&(dists, weight)| Some((dists.for_size(w[0])?, weight)))
+                    .collect::<Vec<_>>();
+                let dist = EDist::mix(&dists).ok()?;
+                Some((w[0]..w[1], dist))
+            })
+            .collect::<Vec<_>>();
+        if inner.is_empty() {
+            return None;
+        }
+        let mut groups = parts
+            .iter()
+            .flat_map(|(dists, _)| dists.groups.keys().copied())
+            .collect::<Vec<_>>();
+        groups.sort();
+        groups.dedup();
+        let groups = groups
+            .into_iter()
+            .filter_map(|group| {
+                let parts = parts
+                    .iter()
+                    .map(|&(dists, weight)| (dists.group_or_all(Some(group)), weight))
+                    .collect::<Vec<_>>();
+                Some((group, Self::merge(&parts)?))
+            })
+            .collect();
+        Some(Self {
+            inner,
+            groups,
+            loads: Vec::new(),
+        })
+    }
+
+    /// Returns an iterator over the ranges of offered load with distributions of their own, in
+    /// ascending order.
+    pub fn loads(&self) -> impl Iterator<Item = (&Range<f64>, &EDistBuckets)> {
+        self.loads.iter().map(|(range, dists)| (range, dists))
+    }
+
+    /// Returns the distributions of the flows from a source group, or `None` if the group has no
+    /// distributions of its own.
+    pub fn for_group(&self, group: NodeId) -> Option<&EDistBuckets> {
+        self.groups.get(&group)
+    }
+
+    /// Returns an iterator over all source groups with distributions of their own.
+    pub fn groups(&self) -> impl Iterator<Item = (NodeId, &EDistBuckets)> {
+        self.groups.iter().map(|(&group, dists)| (group, dists))
+    }
+
+    // Returns the distributions of `group` if it has its own, falling back to these
+    pub(crate) fn group_or_all(&self, group: Option<NodeId>) -> &EDistBuckets {
+        group.and_then(|g| self.for_group(g)).unwrap_or(self)
+    }
+
     /// Returns an iterator over all size ranges.
     pub fn bucket_ranges(&self) -> impl Iterator<Item = &Range<Bytes>> {
         self.inner.iter().map(|(range, _)| range)
     }
 
+    /// Returns an iterator over all buckets' size ranges and empirical distributions.
+    pub fn iter(&self) -> impl Iterator<Item = (&Range<Bytes>, &EDist)> {
+        self.inner.iter().map(|(range, dist)| (range, dist))
+    }
+
     /// Returns the empirical distribution for a particular size.
     pub fn for_size(&self, size: Bytes) -> Option<&EDist> {
+        self.bucket_for_size(size).map(|(_, dist)| dist)
+    }
+
+    /// Returns the size range and empirical distribution of the bucket containing `size`.
+    pub fn bucket_for_size(&self, size: Bytes) -> Option<(&Range<Bytes>, &EDist)> {
         self.inner
             .iter()
-            .find_map(|(bkt, dist)| bkt.contains(&size).then_some(dist))
+            .find(|(bkt, _)| bkt.contains(&size))
+            .map(|(bkt, dist)| (bkt, dist))
+    }
+
+    // Feeds every bucket, including those of source groups in order of node ID and those of load
+    // ranges in ascending order, into `state`
+    pub(crate) fn hash_into<H: Hasher>(&self, state: &mut H) {
+        for (range, dist) in &self.inner {
+            range.hash(state);
+            for sample in dist.samples() {
+                sample.to_bits().hash(state);
+            }
+        }
+        for (group, dists) in self.groups.iter().sorted_by_key(|&(&group, _)| group) {
+            group.hash(state);
+            dists.hash_into(state);
+        }
+        for (range, dists) in &self.loads {
+            range.start.to_bits().hash(state);
+            range.end.to_bits().hash(state);
+            dists.hash_into(state);
+        }
     }
 }
 
 /// Parameters for the bucketing algorithm.
-#[derive(Debug, Clone, Copy, derive_new::new)]
+#[derive(Debug, Clone, Copy, derive_new::new, serde::Serialize, serde::Deserialize)]
 pub struct BucketOpts {
     /// For each bucket `B`, `B.max() >= x * B.min()`.
     pub x: u8,
     /// For each bucket `B`, `B.max() >= b`.
     pub b: usize,
+    /// What flows are bucketed by besides their size.
+    #[new(default)]
+    #[serde(default)]
+    pub strategy: BucketStrategy,
 }
 
 impl Default for BucketOpts {
     fn default() -> Self {
-        Self { x: 2, b: 100 }
+        Self {
+            x: 2,
+            b: 100,
+            strategy: BucketStrategy::Size,
+        }
+    }
+}
+
+/// What flows are bucketed by, tagged by `kind`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BucketStrategy {
+    /// Flow size only.
+    #[default]
+    Size,
+    /// Flow size, and the load offered to the link when flows start, so predictions can be
+    /// conditioned on congestion (see
+    /// [`DelayNetwork::predict_under_load`](crate::network::DelayNetwork::predict_under_load)).
+    /// Each link's flows are split into up to `nr_loads` ranges of offered load with equally many
+    /// flows, at least `b` each, and each range is bucketed by size. The distributions over all
+    /// flows are kept as well.
+    SizeAndLoad {
+        /// The most ranges of offered load.
+        nr_loads: usize,
+        /// The offered load at a flow's start is the number of bytes of the link's flows which
+        /// started within this window before it, relative to what the link can carry in the
+        /// window.
+        window: Nanosecs,
+    },
+}
+
+impl EDistBuckets {
+    // Buckets `data` by offered load as configured in `opts`, if at all. Each datum is a record
+    // of a flow on a link with the given bandwidth.
+    pub(crate) fn by_load<T, F, G, H>(
+        data: &[T],
+        bandwidth: BitsPerSec,
+        fns: (F, G, H),
+        opts: BucketOpts,
+    ) -> Result<Vec<(Range<f64>, EDistBuckets)>, EDistError>
+    where
+        T: Clone + Copy,
+        F: Fn(T) -> Bytes,
+        G: Fn(T) -> Nanosecs,
+        H: Fn(T) -> f64,
+    {
+        Self::by_load_of(data, |_| true, bandwidth, fns, opts)
+    }
+
+    // Like `by_load`, but only the data satisfying `keep` are bucketed, while the offered load
+    // still counts every datum, e.g., to bucket one client's flows by the load of all flows.
+    pub(crate) fn by_load_of<T, K, F, G, H>(
+        data: &[T],
+        keep: K,
+        bandwidth: BitsPerSec,
+        (size, start, sample): (F, G, H),
+        opts: BucketOpts,
+    ) -> Result<Vec<(Range<f64>, EDistBuckets)>, EDistError>
+    where
+        T: Clone + Copy,
+        K: Fn(T) -> bool,
+        F: Fn(T) -> Bytes,
+        G: Fn(T) -> Nanosecs,
+        H: Fn(T) -> f64,
+    {
+        let BucketStrategy::SizeAndLoad { nr_loads, window } = opts.strategy else {
+            return Ok(Vec::new());
+        };
+        let nr_kept = data.iter().filter(|&&d| keep(d)).count();
+        let nr_loads = nr_loads.min(nr_kept / opts.b.max(1));
+        if nr_loads < 2 || window == Nanosecs::ZERO || bandwidth == BitsPerSec::ZERO {
+            return Ok(Vec::new());
+        }
+        let capacity = bandwidth.width(window).into_f64();
+        let mut by_start = data.to_vec();
+        by_start.sort_by_key(|&d| start(d));
+        // Offered loads over a trailing window, with a two-pointer sweep
+        let mut loaded = Vec::with_capacity(by_start.len());
+        let (mut first, mut bytes) = (0, 0.0);
+        for (i, &d) in by_start.iter().enumerate() {
+            bytes += size(d).into_f64();
+            while start(by_start[first]) + window <= start(d) {
+                bytes -= size(by_start[first]).into_f64();
+                first += 1;
+            }
+            if keep(d) {
+                loaded.push((bytes / capacity, i));
+            }
+        }
+        loaded.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        let chunk_size = loaded.len().div_ceil(nr_loads);
+        let chunks = loaded.chunks(chunk_size).collect::<Vec<_>>();
+        let mut loads = Vec::with_capacity(chunks.len());
+        for (i, chunk) in chunks.iter().enumerate() {
+            let lo = if i == 0 { 0.0 } else { chunk[0].0 };
+            let hi = chunks.get(i + 1).map_or(f64::INFINITY, |next| next[0].0);
+            let records = chunk.iter().map(|&(_, j)| by_start[j]).collect::<Vec<_>>();
+            let mut dists = EDistBuckets::new_empty();
+            dists.fill(&records, &size, &sample, opts)?;
+            loads.push((lo..hi, dists));
+        }
+        Ok(loads)
     }
 }
 
@@ -82,7 +345,7 @@ where
     F: Fn(T) -> Bytes,
 {
     let mut data = Vec::from(data);
-    data.sort_by(|&a, &b| f(a).cmp(&f(b)));
+    data.sort_by_key(|&a| f(a));
     let mut data = VecDeque::from(data);
     let mut buckets = Vec::new();
     let mut acc = Vec::new();
@@ -112,8 +375,9 @@ where
 }
 
 /// An empirical distribution.
-#[derive(Debug, Clone, derive_new::new)]
+#[derive(Debug, Clone, derive_new::new, serde::Serialize, serde::Deserialize)]
 pub struct EDist {
+    // Sorted in ascending order
     #[new(default)]
     samples: Vec<f64>,
 }
@@ -124,9 +388,9 @@ impl EDist {
         if values.is_empty() {
             return Err(EDistError::NoValues);
         }
-        Ok(Self {
-            samples: values.to_owned(),
-        })
+        let mut samples = values.to_owned();
+        samples.sort_by(|a, b| a.total_cmp(b));
+        Ok(Self { samples })
     }
 
     /// Returns the mean of the distribution.
@@ -134,6 +398,92 @@ impl EDist {
         let total = self.samples.iter().sum::<f64>();
         total / self.samples.len() as f64
     }
+
+    /// Returns the `q`-th quantile of the distribution, or `None` if the distribution is empty or
+    /// `q` isn't in [0, 1].
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        sorted_quantile(&self.samples, q)
+    }
+
+    /// Returns the sample at relative position `u` in [0, 1) of the sorted samples, or zero if the
+    /// distribution is empty. For a uniformly distributed `u`, this is a sample from the
+    /// distribution; equal values of `u` produce comonotonic samples across distributions.
+    pub fn inverse_cdf(&self, u: f64) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let i = (u * self.samples.len() as f64) as usize;
+        self.samples[i.min(self.samples.len() - 1)]
+    }
+
+    /// Returns a bootstrap replicate of the distribution, i.e., a distribution of the same size
+    /// whose samples are drawn from this one with replacement.
+    pub fn resample<R: Rng>(&self, rng: &mut R) -> Self {
+        let mut samples = (0..self.samples.len())
+            .map(|_| self.inverse_cdf(rng.gen()))
+            .collect::<Vec<_>>();
+        samples.sort_by(|a, b| a.total_cmp(b));
+        Self { samples }
+    }
+
+    pub(crate) fn samples(&self) -> &[f64] {
+        &self.samples
+    }
+
+    // Mixes distributions so that each contributes a share of the samples proportional to its
+    // weight. The mixture has as many samples as the distributions combined, and each
+    // distribution's share is drawn at evenly spaced quantiles, so mixing is deterministic.
+    pub(crate) fn mix(parts: &[(&EDist, f64)]) -> Result<Self, EDistError> {
+        let parts = parts
+            .iter()
+            .filter(|(dist, weight)| !dist.is_empty() && *weight > 0.0)
+            .collect::<Vec<_>>();
+        let nr_samples = parts.iter().map(|(dist, _)| dist.len()).sum::<usize>();
+        let total_weight = parts.iter().map(|&&(_, weight)| weight).sum::<f64>();
+        let mut samples = Vec::with_capacity(nr_samples);
+        for &&(dist, weight) in &parts {
+            let n = ((nr_samples as f64 * weight / total_weight).round() as usize).max(1);
+            samples.extend((0..n).map(|i| dist.inverse_cdf((i as f64 + 0.5) / n as f64)));
+        }
+        Self::from_values(&samples)
+    }
+
+    // Multiplies every sample by `factor`, which must be non-negative to keep samples sorted
+    pub(crate) fn scale(&mut self, factor: f64) {
+        debug_assert!(factor >= 0.0);
+        for sample in &mut self.samples {
+            *sample *= factor;
+        }
+    }
+
+    /// Returns the number of samples in the distribution.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Returns true if the distribution has no samples.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+}
+
+// Nearest-rank quantile of unsorted `values`, or `None` if there are none or `q` isn't in [0, 1].
+pub(crate) fn quantile(values: &[f64], q: f64) -> Option<f64> {
+    if values.is_empty() || !(0.0..=1.0).contains(&q) {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    sorted_quantile(&sorted, q)
+}
+
+// Nearest-rank quantile of `sorted`, or `None` if it is empty or `q` isn't in [0, 1].
+pub(crate) fn sorted_quantile(sorted: &[f64], q: f64) -> Option<f64> {
+    if !(0.0..=1.0).contains(&q) {
+        return None;
+    }
+    let i = ((q * sorted.len() as f64).ceil() as usize).saturating_sub(1);
+    sorted.get(i).copied()
 }
 
 /// Error type for creating empirical distributions.
@@ -149,3 +499,31 @@ impl Distribution<f64> for EDist {
         self.samples.choose(rng).unwrap_or(&0_f64).to_owned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantile_nearest_rank() {
+        let dist = EDist::from_values(&[5.0, 1.0, 4.0, 2.0, 3.0]).unwrap();
+        assert_eq!(dist.quantile(0.0), Some(1.0));
+        assert_eq!(dist.quantile(0.5), Some(3.0));
+        assert_eq!(dist.quantile(0.99), Some(5.0));
+        assert_eq!(dist.quantile(1.0), Some(5.0));
+    }
+
+    #[test]
+    fn quantile_empty_is_none() {
+        assert_eq!(EDist::new().quantile(0.5), None);
+    }
+
+    #[test]
+    fn quantile_out_of_range_is_none() {
+        let dist = EDist::from_values(&[1.0, 2.0]).unwrap();
+        for q in [-0.1, 1.1, f64::NAN] {
+            assert_eq!(dist.quantile(q), None);
+            assert_eq!(quantile(&[1.0, 2.0], q), None);
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! This module defines [`evaluate`], which compares the estimates of a
+//! [`DelayNetwork`] against ground-truth FCTs, e.g., from a full ns-3 simulation of the same
+//! workload.
+//!
+//! Flows are grouped into size buckets. For every bucket, the report compares the p50, p95, and
+//! p99 of the ground-truth slowdowns to those of slowdowns sampled from the delay network, one
+//! sample per ground-truth flow.
+
+use std::ops::Range;
+
+use rand::Rng;
+use rustc_hash::FxHashMap;
+
+use crate::{
+    edist,
+    network::{DelayNetwork, FctRecord, Flow},
+    routing::RoutingAlgo,
+    units::{Bytes, Kilobytes, Nanosecs},
+};
+
+/// Evaluation options.
+#[derive(Debug, Clone, typed_builder::TypedBuilder)]
+pub struct EvalOpts {
+    /// The boundaries between size buckets, in increasing order. The first bucket starts at zero
+    /// and the last one is unbounded.
+    #[builder(default = default_size_boundaries())]
+    pub size_boundaries: Vec<Bytes>,
+}
+
+impl Default for EvalOpts {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+fn default_size_boundaries() -> Vec<Bytes> {
+    [1, 10, 100, 1000]
+        .into_iter()
+        .map(|kb| Kilobytes::new(kb).into())
+        .collect()
+}
+
+/// Slowdown quantiles.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SlowdownQuantiles {
+    /// The median.
+    pub p50: f64,
+    /// The 95th percentile.
+    pub p95: f64,
+    /// The 99th percentile.
+    pub p99: f64,
+}
+
+impl SlowdownQuantiles {
+    pub(crate) fn of(values: &[f64]) -> Option<Self> {
+        Some(Self {
+            p50: edist::quantile(values, 0.50)?,
+            p95: edist::quantile(values, 0.95)?,
+            p99: edist::quantile(values, 0.99)?,
+        })
+    }
+}
+
+/// The comparison for a single size bucket.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BucketReport {
+    /// The flow sizes in the bucket.
+    pub sizes: Range<Bytes>,
+    /// The number of ground-truth flows in the bucket.
+    pub nr_flows: usize,
+    /// Ground-truth slowdown quantiles.
+    pub actual: SlowdownQuantiles,
+    /// Estimated slowdown quantiles.
+    pub predicted: SlowdownQuantiles,
+    /// The absolute error of each estimated quantile, relative to ground truth.
+    pub errors: SlowdownQuantiles,
+}
+
+/// An evaluation report, produced by [`evaluate`].
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EvalReport {
+    /// Per-bucket comparisons, in increasing size order. Empty buckets are omitted.
+    pub buckets: Vec<BucketReport>,
+    /// The weighted mean absolute percentage error of each quantile across buckets, i.e., the
+    /// sum of absolute errors divided by the sum of ground-truth values.
+    pub wmape: Option<SlowdownQuantiles>,
+    /// The number of ground-truth records which couldn't be evaluated, because they don't match
+    /// any flow, have no ideal FCT, or the delay network has no estimate for them.
+    pub nr_skipped: usize,
+}
+
+/// Compares the slowdowns estimated by `delays` to the ground-truth FCT `records` of `flows`.
+/// Records are matched to flows by ID.
+pub fn evaluate<R, RNG>(
+    delays: &DelayNetwork<R>,
+    flows: &[Flow],
+    records: &[FctRecord],
+    opts: &EvalOpts,
+    mut rng: RNG,
+) -> EvalReport
+where
+    R: RoutingAlgo,
+    RNG: Rng,
+{
+    let id2flow = flows.iter().map(|f| (f.id, f)).collect::<FxHashMap<_, _>>();
+    let mut buckets = std::iter::once(Bytes::ZERO)
+        .chain(opts.size_boundaries.iter().copied())
+        .zip(
+            opts.size_boundaries
+                .iter()
+                .copied()
+                .chain(std::iter::once(Bytes::MAX)),
+        )
+        .map(|(lo, hi)| (lo..hi, Vec::new(), Vec::new()))
+        .collect::<Vec<_>>();
+    let mut nr_skipped = 0;
+    for rec in records {
+        let predicted = id2flow
+            .get(&rec.id)
+            .filter(|_| rec.ideal > Nanosecs::ZERO)
+            .and_then(|f| delays.slowdown(f.size, (f.src, f.dst), &mut rng));
+        let bucket = buckets
+            .iter_mut()
+            .find(|(sizes, _, _)| sizes.contains(&rec.size));
+        match (predicted, bucket) {
+            (Some(predicted), Some((_, actuals, predictions))) => {
+                actuals.push(rec.slowdown());
+                predictions.push(predicted);
+            }
+            _ => nr_skipped += 1,
+        }
+    }
+    let buckets = buckets
+        .into_iter()
+        .filter_map(|(sizes, actuals, predictions)| {
+            let actual = SlowdownQuantiles::of(&actuals)?;
+            let predicted = SlowdownQuantiles::of(&predictions)?;
+            let error = |a: f64, p: f64| (p - a).abs() / a;
+            Some(BucketReport {
+                sizes,
+                nr_flows: actuals.len(),
+                actual,
+                predicted,
+                errors: SlowdownQuantiles {
+                    p50: error(actual.p50, predicted.p50),
+                    p95: error(actual.p95, predicted.p95),
+                    p99: error(actual.p99, predicted.p99),
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+    let wmape = (!buckets.is_empty()).then(|| {
+        let wmape = |f: fn(&SlowdownQuantiles) -> f64| {
+            let abs_errors = buckets
+                .iter()
+                .map(|b| (f(&b.predicted) - f(&b.actual)).abs())
+                .sum::<f64>();
+            abs_errors / buckets.iter().map(|b| f(&b.actual)).sum::<f64>()
+        };
+        SlowdownQuantiles {
+            p50: wmape(|q| q.p50),
+            p95: wmape(|q| q.p95),
+            p99: wmape(|q| q.p99),
+        }
+    });
+    EvalReport {
+        buckets,
+        wmape,
+        nr_skipped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::{FlowId, Network, NodeId},
+        opts::SimOpts,
+        testing::{self, FixedDelaySim},
+    };
+
+    #[test]
+    fn report_compares_slowdowns() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..20)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(if i % 2 == 0 { 500 } else { 50_000 }),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        // Without any delay, every estimated slowdown is one
+        let opts = SimOpts::builder()
+            .link_sim(FixedDelaySim {
+                delay_per_pkt: Nanosecs::ZERO,
+            })
+            .build();
+        let delays = Network::new(&nodes, &links)?
+            .into_simulations(flows.clone())
+            .into_delays(opts)?;
+        let mut records = flows
+            .iter()
+            .map(|f| FctRecord {
+                id: f.id,
+                size: f.size,
+                start: f.start,
+                fct: Nanosecs::new(2000),
+                ideal: Nanosecs::new(1000),
+            })
+            .collect::<Vec<_>>();
+        records[0].id = FlowId::new(100);
+        let report = evaluate(
+            &delays,
+            &flows,
+            &records,
+            &EvalOpts::default(),
+            rand::thread_rng(),
+        );
+        assert_eq!(report.nr_skipped, 1);
+        let sizes = report
+            .buckets
+            .iter()
+            .map(|b| (b.sizes.clone(), b.nr_flows))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sizes,
+            vec![
+                (Bytes::ZERO..Bytes::new(1000), 9),
+                (Bytes::new(10_000)..Bytes::new(100_000), 10)
+            ]
+        );
+        let half = SlowdownQuantiles {
+            p50: 0.5,
+            p95: 0.5,
+            p99: 0.5,
+        };
+        assert_eq!(report.buckets[0].errors, half);
+        assert_eq!(report.wmape, Some(half));
+        Ok(())
+    }
+}
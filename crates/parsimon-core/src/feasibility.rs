@@ -0,0 +1,430 @@
+//! This module defines workload feasibility checks. A workload that offers more bytes to a host
+//! or link than it can carry for a sustained period has no steady state, so delay distributions
+//! estimated from it (especially their tails) are meaningless. A [`FeasibilityReport`]
+//! summarizes the offered load on every host's NIC and flags links and hosts that are overloaded
+//! for sustained periods.
+//!
+//! Offered load is computed over fixed windows, attributing all of a flow's bytes to the window in
+//! which it starts. This overestimates the load of windows in which large flows start, which is why
+//! only sustained overloads are flagged. Only windows in which flows start are recorded, so sparse
+//! workloads spanning long periods can be checked with short windows.
+
+use std::{collections::BTreeMap, fmt};
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    network::{EdgeIndex, Flow, Network, NodeId, TraversableNetwork},
+    routing::RoutingAlgo,
+    spec::{Spec, SpecError},
+    units::{BitsPerSec, Bytes, Nanosecs},
+};
+
+/// Parameters of a feasibility check.
+#[derive(Debug, Clone, Copy, PartialEq, typed_builder::TypedBuilder)]
+pub struct FeasibilityOpts {
+    /// The length of the windows over which offered load is computed.
+    #[builder(default = Nanosecs::new(1_000_000), setter(into))]
+    pub window: Nanosecs,
+    /// The load above which a window is overloaded. Windows in which no flow starts are never
+    /// overloaded.
+    #[builder(default = 1.0)]
+    pub threshold: f64,
+    /// The number of consecutive overloaded windows that make a sustained overload.
+    #[builder(default = 3)]
+    pub sustained: usize,
+    /// Whether sustained overloads fail [validation](Spec::check). Otherwise, they are only logged
+    /// as warnings.
+    #[builder(default)]
+    pub enforce: bool,
+}
+
+impl Default for FeasibilityOpts {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// The result of a feasibility check.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FeasibilityReport {
+    /// The offered load of every host, sorted by host ID.
+    pub hosts: Vec<HostLoad>,
+    /// Every sustained overload, sorted by resource and then by start time.
+    pub warnings: Vec<Overload>,
+}
+
+impl FeasibilityReport {
+    /// Returns true if nothing is overloaded for a sustained period.
+    pub fn is_feasible(&self) -> bool {
+        self.warnings.is_empty()
+    }
+}
+
+impl fmt::Display for FeasibilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_feasible() {
+            return write!(f, "workload is feasible");
+        }
+        write!(f, "{} sustained overload(s)", self.warnings.len())?;
+        for warning in &self.warnings {
+            write!(f, "\n  {warning}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The load a host offers to, and receives from, its NIC.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct HostLoad {
+    /// The host ID.
+    pub host: NodeId,
+    /// The total bandwidth of the host's links.
+    pub nic_rate: BitsPerSec,
+    /// The load of flows sourced by the host.
+    pub tx: WindowedLoad,
+    /// The load of flows destined to the host.
+    pub rx: WindowedLoad,
+}
+
+/// Offered load as a fraction of capacity.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct WindowedLoad {
+    /// The load over the whole workload.
+    pub mean: f64,
+    /// The load of the busiest window.
+    pub peak: f64,
+}
+
+/// A resource whose offered load exceeds the threshold for a sustained period.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Overload {
+    /// The overloaded resource.
+    pub resource: Resource,
+    /// The start of the first overloaded window, relative to the earliest flow.
+    pub start: Nanosecs,
+    /// The end of the last overloaded window, relative to the earliest flow.
+    pub end: Nanosecs,
+    /// The load of the busiest window in the period.
+    pub peak: f64,
+}
+
+impl fmt::Display for Overload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is offered {:.2}x its capacity between {} and {}",
+            self.resource, self.peak, self.start, self.end
+        )
+    }
+}
+
+/// A resource with finite capacity.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub enum Resource {
+    /// The sending side of a host's NIC.
+    HostTx(NodeId),
+    /// The receiving side of a host's NIC.
+    HostRx(NodeId),
+    /// A directed link.
+    Link {
+        /// The link's edge.
+        edge: EdgeIndex,
+        /// The link's source.
+        src: NodeId,
+        /// The link's destination.
+        dst: NodeId,
+    },
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HostTx(host) => write!(f, "host {host} (tx)"),
+            Self::HostRx(host) => write!(f, "host {host} (rx)"),
+            Self::Link { src, dst, .. } => write!(f, "link {src}->{dst}"),
+        }
+    }
+}
+
+impl Spec {
+    /// Checks whether the specification's workload is feasible, without failing on overloads. To
+    /// reject infeasible workloads instead, set [`FeasibilityOpts::enforce`] in
+    /// [`Spec::feasibility`].
+    pub fn check_feasibility(
+        &self,
+        opts: &FeasibilityOpts,
+    ) -> Result<FeasibilityReport, SpecError> {
+        let links = self.scenario.apply(&self.links)?;
+        let network = Network::new_with_layout(&self.nodes, &links, self.routing)?;
+        Ok(check(&network, &self.flows, opts))
+    }
+}
+
+// Flows are routed exactly as in `Network::into_simulations`, so link loads match the ones the
+// link simulations will see.
+pub(crate) fn check<R>(
+    network: &Network<R>,
+    flows: &[Flow],
+    opts: &FeasibilityOpts,
+) -> FeasibilityReport
+where
+    R: RoutingAlgo + Sync,
+{
+    assert!(
+        opts.window > Nanosecs::ZERO,
+        "feasibility window must be positive"
+    );
+    let origin = flows
+        .iter()
+        .map(|f| f.start)
+        .min()
+        .unwrap_or(Nanosecs::ZERO);
+    let window_of = |f: &Flow| (f.start - origin).into_u64() / opts.window.into_u64();
+    let nr_windows = flows.iter().map(window_of).max().map_or(0, |w| w + 1);
+
+    // Windows are keyed by index, so empty windows take no space.
+    let mut offered = FxHashMap::<Resource, BTreeMap<u64, Bytes>>::default();
+    let mut offer = |resource, w: u64, size| {
+        *offered
+            .entry(resource)
+            .or_default()
+            .entry(w)
+            .or_insert(Bytes::ZERO) += size;
+    };
+    for f in flows {
+        let w = window_of(f);
+        offer(Resource::HostTx(f.src), w, f.size);
+        offer(Resource::HostRx(f.dst), w, f.size);
+        let hops = network.ecmp().flow_hops(f, network.seed());
+        for edge in network.edge_indices_between(f.src, f.dst, hops) {
+            let chan = &network.topology().graph[edge];
+            let link = Resource::Link {
+                edge,
+                src: chan.src,
+                dst: chan.dst,
+            };
+            offer(link, w, f.size);
+        }
+    }
+
+    let mut nic_rates = FxHashMap::<NodeId, (BitsPerSec, BitsPerSec)>::default();
+    for chan in network.topology().graph.edge_weights() {
+        nic_rates.entry(chan.src).or_default().0 += chan.bandwidth;
+        nic_rates.entry(chan.dst).or_default().1 += chan.bandwidth;
+    }
+    let capacity_of = |resource: &Resource| match *resource {
+        Resource::HostTx(host) => nic_rates.get(&host).map_or(BitsPerSec::ZERO, |r| r.0),
+        Resource::HostRx(host) => nic_rates.get(&host).map_or(BitsPerSec::ZERO, |r| r.1),
+        Resource::Link { edge, .. } => network.topology().graph[edge].bandwidth,
+    };
+    let loads = offered
+        .iter()
+        .map(|(&resource, bytes)| {
+            let capacity = capacity_of(&resource);
+            let loads = bytes
+                .iter()
+                .map(|(&w, &b)| (w, load(b, opts.window, capacity)))
+                .collect::<Vec<_>>();
+            (resource, loads)
+        })
+        .collect::<FxHashMap<_, _>>();
+
+    let mut hosts = network
+        .host_ids()
+        .map(|host| {
+            let summarize = |resource| {
+                loads
+                    .get(&resource)
+                    .map_or(WindowedLoad::default(), |loads| WindowedLoad {
+                        mean: loads.iter().map(|&(_, l)| l).sum::<f64>() / nr_windows as f64,
+                        peak: loads.iter().map(|&(_, l)| l).fold(0.0, f64::max),
+                    })
+            };
+            HostLoad {
+                host,
+                nic_rate: nic_rates.get(&host).map_or(BitsPerSec::ZERO, |r| r.0),
+                tx: summarize(Resource::HostTx(host)),
+                rx: summarize(Resource::HostRx(host)),
+            }
+        })
+        .collect::<Vec<_>>();
+    hosts.sort_by_key(|h| h.host);
+
+    let mut warnings = loads
+        .iter()
+        .flat_map(|(&resource, loads)| sustained_overloads(resource, loads, opts))
+        .collect::<Vec<_>>();
+    warnings.sort_by_key(|o| (o.resource, o.start));
+    FeasibilityReport { hosts, warnings }
+}
+
+fn load(bytes: Bytes, window: Nanosecs, capacity: BitsPerSec) -> f64 {
+    if bytes == Bytes::ZERO {
+        0.0
+    } else if capacity == BitsPerSec::ZERO {
+        f64::INFINITY
+    } else {
+        bytes.into_f64() * 8.0 * 1e9 / window.into_f64() / capacity.into_f64()
+    }
+}
+
+// `loads` holds the load of every non-empty window, sorted by window index. Overloaded windows
+// only form a sustained overload if their indices are consecutive.
+fn sustained_overloads(
+    resource: Resource,
+    loads: &[(u64, f64)],
+    opts: &FeasibilityOpts,
+) -> Vec<Overload> {
+    let mut overloads = Vec::new();
+    let mut i = 0;
+    while i < loads.len() {
+        let first = loads[i].0;
+        let len = loads[i..]
+            .iter()
+            .zip(first..)
+            .take_while(|&(&(w, load), expected)| w == expected && load > opts.threshold)
+            .count();
+        if len > 0 && len >= opts.sustained {
+            overloads.push(Overload {
+                resource,
+                start: opts.window.scale_by(first as f64),
+                end: opts.window.scale_by((first + len as u64) as f64),
+                peak: loads[i..i + len]
+                    .iter()
+                    .map(|&(_, l)| l)
+                    .fold(0.0, f64::max),
+            });
+        }
+        i += len.max(1);
+    }
+    overloads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{network::FlowId, testing};
+
+    fn flow(id: usize, src: usize, dst: usize, size: u64, start: u64) -> Flow {
+        Flow {
+            id: FlowId::new(id),
+            src: NodeId::new(src),
+            dst: NodeId::new(dst),
+            size: Bytes::new(size),
+            start: Nanosecs::new(start),
+        }
+    }
+
+    // Every 1us window, host 0 sends `size` bytes to host 2 over 10 Gbps links.
+    fn spec(size: u64, nr_windows: usize) -> Spec {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (0..nr_windows)
+            .map(|i| flow(i, 0, 2, size, 1_000 * i as u64))
+            .collect();
+        Spec::builder()
+            .nodes(nodes)
+            .links(links)
+            .flows(flows)
+            .build()
+    }
+
+    fn opts() -> FeasibilityOpts {
+        FeasibilityOpts::builder()
+            .window(Nanosecs::new(1_000))
+            .sustained(3)
+            .build()
+    }
+
+    #[test]
+    fn light_workloads_are_feasible() -> anyhow::Result<()> {
+        // 1000 bytes per microsecond is 8 Gbps
+        let report = spec(1_000, 10).check_feasibility(&opts())?;
+        assert!(report.is_feasible());
+        let host = report
+            .hosts
+            .iter()
+            .find(|h| h.host == NodeId::new(0))
+            .unwrap();
+        assert!((host.tx.peak - 0.8).abs() < 1e-9);
+        assert_eq!(host.rx, WindowedLoad::default());
+        Ok(())
+    }
+
+    #[test]
+    fn sustained_overloads_are_flagged() -> anyhow::Result<()> {
+        // 2000 bytes per microsecond is 16 Gbps
+        let report = spec(2_000, 5).check_feasibility(&opts())?;
+        assert!(!report.is_feasible());
+        let tx = report
+            .warnings
+            .iter()
+            .find(|o| o.resource == Resource::HostTx(NodeId::new(0)))
+            .unwrap();
+        assert_eq!((tx.start, tx.end), (Nanosecs::ZERO, Nanosecs::new(5_000)));
+        assert!((tx.peak - 1.6).abs() < 1e-9);
+        // Every link on the path is overloaded too
+        assert!(report
+            .warnings
+            .iter()
+            .any(|o| matches!(o.resource, Resource::Link { src, .. } if src == NodeId::new(0))));
+        Ok(())
+    }
+
+    #[test]
+    fn transient_overloads_are_not_flagged() -> anyhow::Result<()> {
+        let report = spec(2_000, 2).check_feasibility(&opts())?;
+        assert!(report.is_feasible());
+        Ok(())
+    }
+
+    #[test]
+    fn gaps_break_sustained_overloads() -> anyhow::Result<()> {
+        let mut spec = spec(2_000, 5);
+        // Leave window 2 empty
+        spec.flows[2].start = Nanosecs::new(1_000_000);
+        let report = spec.check_feasibility(&opts())?;
+        assert!(report.is_feasible());
+        Ok(())
+    }
+
+    #[test]
+    fn sparse_workloads_use_short_windows() -> anyhow::Result<()> {
+        let mut spec = spec(1_000, 2);
+        // An hour apart, with 1ns windows
+        spec.flows[1].start = Nanosecs::new(3_600_000_000_000);
+        let opts = FeasibilityOpts::builder()
+            .window(Nanosecs::new(1))
+            .sustained(1)
+            .build();
+        let report = spec.check_feasibility(&opts)?;
+        let tx = report
+            .warnings
+            .iter()
+            .filter(|o| o.resource == Resource::HostTx(NodeId::new(0)))
+            .collect::<Vec<_>>();
+        assert_eq!(tx.len(), 2);
+        assert_eq!(tx[1].start, Nanosecs::new(3_600_000_000_000));
+        Ok(())
+    }
+
+    #[test]
+    fn validation_rejects_infeasible_workloads() {
+        let mut spec = spec(2_000, 5);
+        spec.feasibility = Some(FeasibilityOpts {
+            enforce: true,
+            ..opts()
+        });
+        assert!(matches!(spec.validate(), Err(SpecError::Infeasible(_))));
+    }
+
+    #[test]
+    fn feasibility_is_advisory_by_default() {
+        let mut spec = spec(2_000, 5);
+        assert!(spec.feasibility.is_some_and(|opts| !opts.enforce));
+        spec.feasibility = Some(opts());
+        assert!(spec.validate().is_ok());
+    }
+}
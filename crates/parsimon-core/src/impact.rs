@@ -0,0 +1,70 @@
+//! This module supports decommissioning studies: it estimates how link loads and predicted delays
+//! change when a group of hosts goes away, re-simulating only the links that are affected.
+
+use crate::{
+    linksim::LinkSim,
+    network::{DelayNetwork, EdgeIndex, LoadChange, NodeId, SimNetwork, SimNetworkError},
+    opts::SimOpts,
+    routing::RoutingAlgo,
+    units::{Bytes, Nanosecs},
+};
+
+/// The result of a host removal analysis.
+#[derive(Debug)]
+pub struct HostRemovalImpact<R> {
+    /// The links whose load changed by more than the requested threshold. Only these links were
+    /// re-simulated.
+    pub changes: Vec<LoadChange>,
+    /// The delay network after removing the hosts.
+    pub delays: DelayNetwork<R>,
+}
+
+impl<R> HostRemovalImpact<R>
+where
+    R: RoutingAlgo,
+{
+    /// Reports, for every changed link, the `q`-th delay quantile predicted for a flow of size
+    /// `size` before (using `base`) and after the hosts were removed.
+    pub fn report(&self, base: &DelayNetwork<R>, size: Bytes, q: f64) -> Vec<LinkImpact> {
+        self.changes
+            .iter()
+            .map(|&change| LinkImpact {
+                change,
+                delay_before: base.edge_quantile(change.edge, size, q),
+                delay_after: self.delays.edge_quantile(change.edge, size, q),
+            })
+            .collect()
+    }
+}
+
+/// The impact of a host removal on a single link.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct LinkImpact {
+    /// The change in the link's load.
+    pub change: LoadChange,
+    /// The delay quantile before the removal, if there was data.
+    pub delay_before: Option<Nanosecs>,
+    /// The delay quantile after the removal, if there is data.
+    pub delay_after: Option<Nanosecs>,
+}
+
+/// Removes all flows to or from `hosts` from `sims`, identifies the links whose load changed by
+/// more than `threshold`, and re-simulates only those. All other links reuse their delay
+/// distributions from `base`, which must have been computed from `sims`.
+pub fn host_removal_impact<R, S>(
+    sims: &SimNetwork<R>,
+    base: &DelayNetwork<R>,
+    hosts: &[NodeId],
+    threshold: f64,
+    opts: SimOpts<S>,
+) -> Result<HostRemovalImpact<R>, SimNetworkError>
+where
+    R: RoutingAlgo + Clone + Sync,
+    S: LinkSim + Sync,
+{
+    let after = sims.without_hosts(hosts);
+    let changes = sims.load_changes(&after, threshold);
+    let edges = changes.iter().map(|c| c.edge).collect::<Vec<EdgeIndex>>();
+    let delays = after.into_delays_reusing(base, edges, opts)?;
+    Ok(HostRemovalImpact { changes, delays })
+}
@@ -0,0 +1,339 @@
+//! This module defines incast detection. An incast is a burst of flows from many sources to a
+//! single destination within a short window. Incasts overwhelm the destination's down-links, and
+//! it is on those links that decomposition accuracy matters most, so a detected incast's links
+//! can be [isolated](crate::network::SimNetwork::isolate_edges) to keep clustering from
+//! approximating them with ordinary links.
+//!
+//! Flows are considered concurrent if they start within [`IncastOpts::window`] of each other.
+
+use std::fmt;
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    network::{Channel, EdgeIndex, Flow, NodeId, SimNetwork},
+    routing::RoutingAlgo,
+    units::Nanosecs,
+};
+
+/// Parameters of incast detection.
+#[derive(Debug, Clone, Copy, PartialEq, typed_builder::TypedBuilder)]
+pub struct IncastOpts {
+    /// The window within which flows must start to be concurrent. Must be positive.
+    #[builder(default = Nanosecs::new(10_000), setter(into))]
+    pub window: Nanosecs,
+    /// The minimum number of distinct sources sending to one destination within a window.
+    #[builder(default = 8)]
+    pub min_sources: usize,
+}
+
+impl Default for IncastOpts {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl IncastOpts {
+    /// Checks that the options are valid.
+    pub fn validate(&self) -> Result<(), IncastError> {
+        if self.window == Nanosecs::ZERO {
+            return Err(IncastError::EmptyWindow);
+        }
+        Ok(())
+    }
+}
+
+/// Error type for incast detection.
+#[derive(Debug, thiserror::Error)]
+pub enum IncastError {
+    /// The window is empty.
+    #[error("Incast windows must not be empty")]
+    EmptyWindow,
+}
+
+/// The result of incast detection.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct IncastReport {
+    /// Every detected incast, sorted by destination and then by start time.
+    pub incasts: Vec<Incast>,
+}
+
+impl IncastReport {
+    /// Returns true if no incast was detected.
+    pub fn is_empty(&self) -> bool {
+        self.incasts.is_empty()
+    }
+
+    /// Returns the destination links affected by any incast, sorted and deduplicated.
+    pub fn edges(&self) -> Vec<EdgeIndex> {
+        let mut edges = self
+            .incasts
+            .iter()
+            .flat_map(|i| i.edges.iter().copied())
+            .collect::<Vec<_>>();
+        edges.sort();
+        edges.dedup();
+        edges
+    }
+}
+
+impl fmt::Display for IncastReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no incast detected");
+        }
+        write!(f, "{} incast(s)", self.incasts.len())?;
+        for incast in &self.incasts {
+            write!(f, "\n  {incast}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A burst of flows from many sources to one destination.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Incast {
+    /// The destination host.
+    pub dst: NodeId,
+    /// The destination's down-links, i.e., the links into it which carry flows.
+    pub edges: Vec<EdgeIndex>,
+    /// The start time of the first flow in the burst.
+    pub start: Nanosecs,
+    /// The start time of the last flow in the burst.
+    pub end: Nanosecs,
+    /// The number of flows in the burst.
+    pub nr_flows: usize,
+    /// The largest number of distinct sources within a single window.
+    pub peak_sources: usize,
+}
+
+impl fmt::Display for Incast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "host {} receives {} flows from up to {} sources between {} and {}",
+            self.dst, self.nr_flows, self.peak_sources, self.start, self.end
+        )
+    }
+}
+
+impl<R> SimNetwork<R>
+where
+    R: RoutingAlgo + Sync,
+{
+    /// Detects incasts in the network's workload.
+    pub fn detect_incast(&self, opts: &IncastOpts) -> Result<IncastReport, IncastError> {
+        opts.validate()?;
+        let mut by_dst = FxHashMap::<NodeId, Vec<&Flow>>::default();
+        for flow in self.flows() {
+            by_dst.entry(flow.dst).or_default().push(flow);
+        }
+        let mut dsts = by_dst.into_iter().collect::<Vec<_>>();
+        dsts.sort_by_key(|&(dst, _)| dst);
+        let mut incasts = Vec::new();
+        for (dst, mut flows) in dsts {
+            flows.sort_by_key(|f| (f.start, f.id));
+            let bursts = bursts(&flows, opts);
+            if bursts.is_empty() {
+                continue;
+            }
+            let edges = self.down_links(dst);
+            incasts.extend(bursts.into_iter().map(|b| Incast {
+                dst,
+                edges: edges.clone(),
+                ..b
+            }));
+        }
+        Ok(IncastReport { incasts })
+    }
+
+    /// Detects incasts and [isolates](Self::isolate_edges) their destination links, returning
+    /// the report.
+    pub fn isolate_incast(&mut self, opts: &IncastOpts) -> Result<IncastReport, IncastError> {
+        let report = self.detect_incast(opts)?;
+        self.isolate_edges(report.edges());
+        Ok(report)
+    }
+
+    // The links into `dst` which carry flows
+    fn down_links(&self, dst: NodeId) -> Vec<EdgeIndex> {
+        self.edge_indices()
+            .filter(|&eidx| {
+                self.edge(eidx)
+                    .is_some_and(|chan| chan.dst() == dst && chan.nr_flows() > 0)
+            })
+            .collect()
+    }
+}
+
+// Finds bursts in `flows`, which all share a destination and are sorted by start time. Windows
+// with enough sources that overlap are merged into one burst.
+fn bursts(flows: &[&Flow], opts: &IncastOpts) -> Vec<Incast> {
+    let mut bursts: Vec<Incast> = Vec::new();
+    let mut sources = FxHashMap::<NodeId, usize>::default();
+    let mut lo = 0;
+    for (i, flow) in flows.iter().enumerate() {
+        *sources.entry(flow.src).or_default() += 1;
+        // A window always contains its last flow
+        while lo < i && flow.start - flows[lo].start >= opts.window {
+            let src = flows[lo].src;
+            let count = sources.get_mut(&src).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                sources.remove(&src);
+            }
+            lo += 1;
+        }
+        if sources.len() < opts.min_sources.max(1) {
+            continue;
+        }
+        match bursts.last_mut() {
+            Some(burst) if flows[lo].start <= burst.end => {
+                burst.end = flow.start;
+                burst.peak_sources = burst.peak_sources.max(sources.len());
+            }
+            _ => bursts.push(Incast {
+                dst: flow.dst,
+                edges: Vec::new(),
+                start: flows[lo].start,
+                end: flow.start,
+                nr_flows: 0,
+                peak_sources: sources.len(),
+            }),
+        }
+    }
+    for burst in &mut bursts {
+        burst.nr_flows = flows
+            .iter()
+            .filter(|f| (burst.start..=burst.end).contains(&f.start))
+            .count();
+    }
+    bursts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cluster::{Cluster, ClusteringAlgo},
+        network::{FlowId, Network},
+        opts::SimOpts,
+        testing,
+        units::Bytes,
+    };
+
+    struct MergeAll(std::collections::HashSet<EdgeIndex>);
+
+    impl ClusteringAlgo for MergeAll {
+        fn cluster<R>(&self, _network: &SimNetwork<R>) -> Vec<Cluster>
+        where
+            R: RoutingAlgo + Sync,
+        {
+            let representative = *self.0.iter().min().unwrap();
+            vec![Cluster::new(representative, self.0.clone())]
+        }
+    }
+
+    fn network(flows: Vec<Flow>) -> anyhow::Result<SimNetwork> {
+        let (nodes, links) = testing::eight_node_config();
+        Ok(Network::new(&nodes, &links)?.into_simulations(flows))
+    }
+
+    fn flow(id: usize, src: usize, dst: usize, start: u64) -> Flow {
+        Flow {
+            id: FlowId::new(id),
+            src: NodeId::new(src),
+            dst: NodeId::new(dst),
+            size: Bytes::new(1000),
+            start: Nanosecs::new(start),
+        }
+    }
+
+    #[test]
+    fn bursts_are_detected() -> anyhow::Result<()> {
+        // Three sources send to host 3 twice, far apart; host 0 sends to host 1 steadily
+        let mut flows = Vec::new();
+        for (i, start) in [0, 100, 200, 1_000_000, 1_000_050, 1_000_100]
+            .into_iter()
+            .enumerate()
+        {
+            flows.push(flow(flows.len(), i % 3, 3, start));
+        }
+        for i in 0..10 {
+            flows.push(flow(flows.len(), 0, 1, i * 100));
+        }
+        let network = network(flows)?;
+        let opts = IncastOpts::builder()
+            .window(Nanosecs::new(1000))
+            .min_sources(3)
+            .build();
+        let report = network.detect_incast(&opts)?;
+        let down_link = network.find_edge(NodeId::new(5), NodeId::new(3)).unwrap();
+        assert_eq!(report.incasts.len(), 2);
+        assert_eq!(report.edges(), [down_link]);
+        let first = &report.incasts[0];
+        assert_eq!(first.dst, NodeId::new(3));
+        assert_eq!(
+            (first.start, first.end),
+            (Nanosecs::ZERO, Nanosecs::new(200))
+        );
+        assert_eq!((first.nr_flows, first.peak_sources), (3, 3));
+        assert_eq!(report.incasts[1].start, Nanosecs::new(1_000_000));
+
+        // A smaller window sees fewer concurrent sources
+        let opts = IncastOpts::builder()
+            .window(Nanosecs::new(150))
+            .min_sources(3)
+            .build();
+        assert_eq!(network.detect_incast(&opts)?.incasts.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn incast_links_are_never_merged() -> anyhow::Result<()> {
+        let flows = (0..3).map(|i| flow(i, i, 3, i as u64)).collect();
+        let mut network = network(flows)?;
+        let opts = IncastOpts::builder().min_sources(3).build();
+        let report = network.isolate_incast(&opts)?;
+        let down_link = report.edges()[0];
+        // Clusters that merge everything still leave the incast link on its own, whether they are
+        // set directly or produced by a clustering algorithm
+        let all = network
+            .edge_indices()
+            .collect::<std::collections::HashSet<_>>();
+        let is_isolated = |network: &SimNetwork| {
+            let clusters = network.clusters();
+            let isolated = clusters.iter().find(|c| c.contains(&down_link)).unwrap();
+            clusters.len() == 2 && isolated.members().count() == 1
+        };
+        network.set_clusters(vec![Cluster::new(down_link, all.clone())])?;
+        assert!(is_isolated(&network));
+        network.cluster(MergeAll(all));
+        assert!(is_isolated(&network));
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        assert!(network.check_clusters(&opts).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn empty_windows_are_rejected() -> anyhow::Result<()> {
+        let network = network(vec![flow(0, 0, 3, 0)])?;
+        let opts = IncastOpts::builder()
+            .window(Nanosecs::ZERO)
+            .min_sources(1)
+            .build();
+        assert!(matches!(
+            network.detect_incast(&opts),
+            Err(IncastError::EmptyWindow)
+        ));
+        // Bursts never shrink a window past its last flow
+        let flows = [flow(0, 0, 3, 0), flow(1, 1, 3, 0)];
+        let flows = flows.iter().collect::<Vec<_>>();
+        assert_eq!(bursts(&flows, &opts).len(), 1);
+        Ok(())
+    }
+}
@@ -6,17 +6,36 @@
 #[macro_use]
 mod ident;
 
+pub mod client;
 pub mod cluster;
+pub mod consistency;
 pub mod constants;
+pub mod dependency;
 pub mod distribute;
 pub mod edist;
+pub mod eval;
+pub mod feasibility;
+pub mod impact;
+pub mod incast;
 pub mod linksim;
+pub mod metrics;
 pub mod network;
 pub mod opts;
+pub mod oversubscription;
+pub mod records;
+pub mod replay;
+pub mod routing;
 pub mod run;
+pub mod scenario;
+pub mod segment;
 pub mod spec;
+#[cfg(feature = "sqlite")]
+pub mod store;
+pub mod telemetry;
+pub mod timeline;
+pub mod transform;
 pub mod units;
-pub mod routing;
+pub mod upgrade;
 
 pub(crate) mod utils;
 
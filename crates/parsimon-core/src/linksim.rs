@@ -1,59 +1,340 @@
 //! This module defines the [`LinkSim`] trait that every link simulator must implement as well as
 //! related types.
+//!
+//! Link simulations can also be run standalone with [`simulate_one`], e.g., to study the
+//! decomposition itself. [`LinkSimSpec::builder`] builds specs by hand, without a full network.
+//!
+//! [`LinkSim`] is object safe, so simulators can be chosen at runtime as [`DynLinkSim`]s.
+//! Simulators are sent to remote workers as their name and [parameters](LinkSim::params), and
+//! workers recreate them by name with a [`LinkSimRegistry`].
 
-use std::iter;
+use std::{fmt, iter, path::PathBuf, sync::Arc};
 
 use petgraph::prelude::*;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 
 use crate::{
+    constants::PacketProfile,
+    distribute::JobId,
+    metrics,
     network::{
         types::{Link, Node},
-        FctRecord, Flow, FlowId, NodeId, NodeKind, TopologyError,
+        Channel, FctRecord, Flow, FlowId, NodeId, NodeKind, TopologyError,
     },
-    units::{BitsPerSec, Nanosecs},
+    units::{BitsPerSec, Bytes, Nanosecs},
 };
 
 /// The return type of a link simulation.
 pub type LinkSimResult = Result<Vec<FctRecord>, LinkSimError>;
 
 /// An interface for link simulators.
-pub trait LinkSim: serde::Serialize + serde::de::DeserializeOwned {
+pub trait LinkSim {
     /// Returns the name of the link level simulator.
     fn name(&self) -> String;
 
     /// Given [`LinkSimSpec`], simulate it and return a collection of FCT records.
     fn simulate(&self, spec: LinkSimSpec) -> LinkSimResult;
+
+    /// Returns the simulator's parameters, from which a [`LinkSimRegistry`] recreates it on a
+    /// remote worker, or `None` if it can only run locally, the default. Serializable simulators
+    /// can return [`params_of`]`(self)`.
+    fn params(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns a copy of the simulator which keeps its files apart from other jobs', e.g., in a
+    /// per-job working directory, or `None` if it writes no files, the default. Workers shared by
+    /// several coordinators run every job's batches with its own copy.
+    fn for_job(&self, _job: JobId) -> Option<DynLinkSim> {
+        None
+    }
+
+    /// Returns an owned copy of the simulator, or `None` if it can't be copied, the default. Local
+    /// runs with a [timeout](crate::opts::SimOpts::timeout) require one, since they run every
+    /// simulation on a thread of its own and abandon the thread once the simulation times out.
+    /// `Clone` simulators can return `Some(Arc::new(self.clone()))`.
+    fn detach(&self) -> Option<DynLinkSim> {
+        None
+    }
+}
+
+/// Serializes a simulator's parameters as JSON, as expected by [`LinkSimRegistry::register`].
+pub fn params_of<S>(sim: &S) -> Option<String>
+where
+    S: serde::Serialize + ?Sized,
+{
+    serde_json::to_string(sim).ok()
+}
+
+/// A link simulator chosen at runtime.
+pub type DynLinkSim = Arc<dyn LinkSim + Send + Sync>;
+
+impl fmt::Debug for dyn LinkSim + Send + Sync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("LinkSim").field(&self.name()).finish()
+    }
+}
+
+macro_rules! forward_link_sim {
+    ($($ty:ty),*) => {
+        $(
+            impl<S: LinkSim + ?Sized> LinkSim for $ty {
+                fn name(&self) -> String {
+                    (**self).name()
+                }
+
+                fn simulate(&self, spec: LinkSimSpec) -> LinkSimResult {
+                    (**self).simulate(spec)
+                }
+
+                fn params(&self) -> Option<String> {
+                    (**self).params()
+                }
+
+                fn for_job(&self, job: JobId) -> Option<DynLinkSim> {
+                    (**self).for_job(job)
+                }
+
+                fn detach(&self) -> Option<DynLinkSim> {
+                    (**self).detach()
+                }
+            }
+        )*
+    };
+}
+
+forward_link_sim!(&S, Box<S>, Arc<S>);
+
+/// A link simulator whose simulations can be written out instead of run, e.g., to run them on a
+/// cluster which schedules jobs outside of Parsimon. See
+/// [`SimNetwork::export_simulations`](crate::network::SimNetwork::export_simulations).
+pub trait ExportLinkSim: LinkSim {
+    /// Writes the inputs of the simulation `spec` describes without running it, and returns the
+    /// path they were written to.
+    fn export(&self, spec: LinkSimSpec) -> Result<PathBuf, LinkSimError>;
+}
+
+type LinkSimFactory = Box<dyn Fn(&str) -> Result<DynLinkSim, serde_json::Error> + Send + Sync>;
+
+/// A registry of link simulators by name, from which workers recreate the simulators they're
+/// sent. See [`LinkSim::params`].
+#[derive(Default)]
+pub struct LinkSimRegistry {
+    factories: FxHashMap<String, LinkSimFactory>,
+}
+
+impl LinkSimRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a simulator under `name`, which should match its [`LinkSim::name`]. It is
+    /// recreated by deserializing its parameters from JSON. An earlier registration under the
+    /// same name is replaced.
+    pub fn register<S>(&mut self, name: impl Into<String>) -> &mut Self
+    where
+        S: LinkSim + serde::de::DeserializeOwned + Send + Sync + 'static,
+    {
+        self.register_with(name, |params| {
+            let sim: S = serde_json::from_str(params)?;
+            Ok(Arc::new(sim))
+        })
+    }
+
+    /// Registers a simulator under `name` with a custom factory, which recreates it from its
+    /// parameters.
+    pub fn register_with<F>(&mut self, name: impl Into<String>, factory: F) -> &mut Self
+    where
+        F: Fn(&str) -> Result<DynLinkSim, serde_json::Error> + Send + Sync + 'static,
+    {
+        self.factories.insert(name.into(), Box::new(factory));
+        self
+    }
+
+    /// Recreates the simulator registered under `name` from its parameters.
+    pub fn instantiate(
+        &self,
+        name: &str,
+        params: &str,
+    ) -> Result<DynLinkSim, LinkSimRegistryError> {
+        let factory = self
+            .factories
+            .get(name)
+            .ok_or_else(|| LinkSimRegistryError::Unknown(name.to_owned()))?;
+        factory(params).map_err(|source| LinkSimRegistryError::InvalidParams {
+            name: name.to_owned(),
+            source,
+        })
+    }
+
+    /// Returns the registered names, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self
+            .factories
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+}
+
+impl fmt::Debug for LinkSimRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LinkSimRegistry")
+            .field("names", &self.names())
+            .finish()
+    }
+}
+
+/// Errors which can be encountered instantiating a simulator from a [`LinkSimRegistry`].
+#[derive(Debug, thiserror::Error)]
+pub enum LinkSimRegistryError {
+    /// No simulator is registered under the name.
+    #[error("Unknown link simulator {0:?}")]
+    Unknown(String),
+
+    /// The parameters don't describe the simulator.
+    #[error("Invalid parameters for link simulator {name:?}")]
+    InvalidParams {
+        /// The simulator's name.
+        name: String,
+        /// The deserialization error.
+        source: serde_json::Error,
+    },
+}
+
+/// Validates `spec` and runs a single link simulation with `sim`.
+pub fn simulate_one<S: LinkSim>(spec: LinkSimSpec, sim: &S) -> LinkSimResult {
+    spec.validate()?;
+    sim.simulate(spec)
 }
 
 /// A full specification for a link-level simulation.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LinkSimSpec {
     /// The edge index of the isolated link.
     pub edge: usize,
     /// The bottleneck.
     pub bottleneck: LinkSimLink,
-    /// The links other than the bottleneck.
+    /// Further bottlenecks simulated together with `bottleneck`, e.g., the other host
+    /// down-channels of a ToR in a [rack-level](crate::network::decompose) simulation. Empty for
+    /// single-link simulations.
+    pub other_bottlenecks: Vec<LinkSimLink>,
+    /// The links other than the bottlenecks.
     pub other_links: Vec<LinkSimLink>,
     /// The nodes.
     pub nodes: Vec<LinkSimNode>,
     /// The flows.
     pub flows: Vec<Flow>,
+    /// The sizes of the packets flows are split into, from the simulated network's
+    /// [profile](crate::network::Network::with_packet_profile).
+    pub packets: PacketProfile,
 }
 
 impl LinkSimSpec {
+    /// Returns a builder for a spec with the given bottleneck. Hand-built specs have edge index 0
+    /// and the default [`PacketProfile`].
+    pub fn builder(bottleneck: LinkSimLink) -> LinkSimSpecBuilder {
+        LinkSimSpecBuilder {
+            bottleneck,
+            packets: PacketProfile::default(),
+            local_source: false,
+            local_destination: false,
+            nodes: Vec::new(),
+            other_links: Vec::new(),
+            flows: Vec::new(),
+        }
+    }
+
+    /// Checks that the spec is well-formed.
+    ///
+    /// Correctness properties:
+    ///
+    /// - Every node must have a unique ID.
+    /// - Every link must connect declared nodes.
+    /// - Every source must be a bottleneck's source or be linked to one.
+    /// - Every destination must be a bottleneck's destination or be linked from one.
+    /// - Every flow must go from a source to a destination.
+    /// - Flows must be sorted by start time.
+    pub fn validate(&self) -> Result<(), LinkSimSpecError> {
+        let mut kinds = FxHashMap::default();
+        for n in self.nodes() {
+            // CORRECTNESS: Every node must have a unique ID.
+            if kinds.insert(n.id, n.kind).is_some() {
+                return Err(LinkSimSpecError::DuplicateNode(n.id));
+            }
+        }
+        // CORRECTNESS: Every link must connect declared nodes.
+        for l in self.links() {
+            for id in [l.from, l.to] {
+                if !kinds.contains_key(&id) {
+                    return Err(LinkSimSpecError::UndeclaredNode(id));
+                }
+            }
+        }
+        let bottlenecks = self.bottlenecks().collect::<Vec<_>>();
+        let links = self
+            .links()
+            .map(|l| (l.from, l.to))
+            .collect::<FxHashSet<_>>();
+        for (&id, &kind) in &kinds {
+            let connected = match kind {
+                // CORRECTNESS: Every source must be a bottleneck's source or be linked to one.
+                LinkSimNodeKind::Source => bottlenecks
+                    .iter()
+                    .any(|b| id == b.from || links.contains(&(id, b.from))),
+                // CORRECTNESS: Every destination must be a bottleneck's destination or be linked
+                // from one.
+                LinkSimNodeKind::Destination => bottlenecks
+                    .iter()
+                    .any(|b| id == b.to || links.contains(&(b.to, id))),
+                LinkSimNodeKind::Switch => true,
+            };
+            if !connected {
+                return Err(LinkSimSpecError::Disconnected(id));
+            }
+        }
+        // CORRECTNESS: Every flow must go from a source to a destination.
+        for f in &self.flows {
+            if kinds.get(&f.src) != Some(&LinkSimNodeKind::Source) {
+                return Err(LinkSimSpecError::InvalidFlowSrc {
+                    flow: f.id,
+                    src: f.src,
+                });
+            }
+            if kinds.get(&f.dst) != Some(&LinkSimNodeKind::Destination) {
+                return Err(LinkSimSpecError::InvalidFlowDst {
+                    flow: f.id,
+                    dst: f.dst,
+                });
+            }
+        }
+        // CORRECTNESS: Flows must be sorted by start time.
+        if let Some(w) = self.flows.windows(2).find(|w| w[0].start > w[1].start) {
+            return Err(LinkSimSpecError::UnsortedFlows(w[1].id));
+        }
+        Ok(())
+    }
+
     /// Returns the nodes in the spec;
     pub fn nodes(&self) -> impl Iterator<Item = LinkSimNode> + '_ {
         self.nodes.iter().copied()
     }
 
-    /// Returns the links in the spec.
-    pub fn links(&self) -> impl Iterator<Item = LinkSimLink> + '_ {
+    /// Returns the bottlenecks in the spec, starting with [`LinkSimSpec::bottleneck`].
+    pub fn bottlenecks(&self) -> impl Iterator<Item = LinkSimLink> + '_ {
         iter::once(&self.bottleneck)
-            .chain(self.other_links.iter())
+            .chain(self.other_bottlenecks.iter())
             .copied()
     }
 
+    /// Returns the links in the spec.
+    pub fn links(&self) -> impl Iterator<Item = LinkSimLink> + '_ {
+        self.bottlenecks().chain(self.other_links.iter().copied())
+    }
+
     /// Returns the nodes in the spec, erasing any `LinkSim`-specific information.
     pub fn generic_nodes(&self) -> impl Iterator<Item = Node> + '_ {
         self.nodes().map(|n| {
@@ -68,8 +349,9 @@ impl LinkSimSpec {
     /// Returns the links in the spec, erasing any `LinkSim`-specific information. Bandwidths are
     /// translated using the `available_bandwidth` field of `LinkSimLink`.
     pub fn generic_links(&self) -> impl Iterator<Item = Link> + '_ {
-        self.links()
-            .map(|l| Link::new(l.from, l.to, l.available_bandwidth, l.delay))
+        self.links().map(|l| {
+            Link::new(l.from, l.to, l.available_bandwidth, l.delay).with_loss_rate(l.loss_rate)
+        })
     }
 
     /// Creates a copy of a `LinkSimSpec` in which all node IDs are contiguous and returns the
@@ -81,23 +363,17 @@ impl LinkSimSpec {
             .enumerate()
             .map(|(i, n)| (n.id, NodeId::new(i)))
             .collect::<FxHashMap<_, _>>();
+        let relabel = |l: &LinkSimLink| LinkSimLink {
+            from: *old2new.get(&l.from).unwrap(),
+            to: *old2new.get(&l.to).unwrap(),
+            ..*l
+        };
         (
             Self {
                 edge: self.edge,
-                bottleneck: LinkSimLink {
-                    from: *old2new.get(&self.bottleneck.from).unwrap(),
-                    to: *old2new.get(&self.bottleneck.to).unwrap(),
-                    ..self.bottleneck
-                },
-                other_links: self
-                    .other_links
-                    .iter()
-                    .map(|&l| LinkSimLink {
-                        from: *old2new.get(&l.from).unwrap(),
-                        to: *old2new.get(&l.to).unwrap(),
-                        ..l
-                    })
-                    .collect::<Vec<_>>(),
+                bottleneck: relabel(&self.bottleneck),
+                other_bottlenecks: self.other_bottlenecks.iter().map(relabel).collect(),
+                other_links: self.other_links.iter().map(relabel).collect(),
                 nodes: self
                     .nodes
                     .iter()
@@ -115,25 +391,266 @@ impl LinkSimSpec {
                         ..f
                     })
                     .collect::<Vec<_>>(),
+                packets: self.packets,
             },
             old2new,
         )
     }
 }
 
+/// A builder for [`LinkSimSpec`]s, created with [`LinkSimSpec::builder`].
+///
+/// Sources are connected to the bottleneck's source, and destinations are connected from the
+/// bottleneck's destination. The bottleneck's endpoints are switches unless they are marked as a
+/// local source or destination.
+#[derive(Debug)]
+pub struct LinkSimSpecBuilder {
+    bottleneck: LinkSimLink,
+    packets: PacketProfile,
+    local_source: bool,
+    local_destination: bool,
+    nodes: Vec<LinkSimNode>,
+    other_links: Vec<LinkSimLink>,
+    flows: Vec<Flow>,
+}
+
+impl LinkSimSpecBuilder {
+    /// Adds a source connected to the bottleneck by a link with the given bandwidth and delay.
+    pub fn source(mut self, id: NodeId, bandwidth: BitsPerSec, delay: Nanosecs) -> Self {
+        self.nodes.push(LinkSimNode {
+            id,
+            kind: LinkSimNodeKind::Source,
+        });
+        let link = LinkSimLink::new(id, self.bottleneck.from, bandwidth, delay);
+        self.other_links.push(link);
+        self
+    }
+
+    /// Adds a destination connected from the bottleneck by a link with the given bandwidth and
+    /// delay.
+    pub fn destination(mut self, id: NodeId, bandwidth: BitsPerSec, delay: Nanosecs) -> Self {
+        self.nodes.push(LinkSimNode {
+            id,
+            kind: LinkSimNodeKind::Destination,
+        });
+        let link = LinkSimLink::new(self.bottleneck.to, id, bandwidth, delay);
+        self.other_links.push(link);
+        self
+    }
+
+    /// Makes the bottleneck's source a source itself, as for a host's up-channel.
+    pub fn local_source(mut self) -> Self {
+        self.local_source = true;
+        self
+    }
+
+    /// Makes the bottleneck's destination a destination itself, as for a host's down-channel.
+    pub fn local_destination(mut self) -> Self {
+        self.local_destination = true;
+        self
+    }
+
+    /// Adds flows to the spec.
+    pub fn flows(mut self, flows: impl IntoIterator<Item = Flow>) -> Self {
+        self.flows.extend(flows);
+        self
+    }
+
+    /// Sets the sizes of the packets flows are split into.
+    pub fn packets(mut self, packets: PacketProfile) -> Self {
+        self.packets = packets;
+        self
+    }
+
+    /// Builds and validates the spec. Flows are sorted by start time.
+    pub fn build(self) -> Result<LinkSimSpec, LinkSimSpecError> {
+        let kind = |local, kind| if local { kind } else { LinkSimNodeKind::Switch };
+        let endpoints = [
+            LinkSimNode {
+                id: self.bottleneck.from,
+                kind: kind(self.local_source, LinkSimNodeKind::Source),
+            },
+            LinkSimNode {
+                id: self.bottleneck.to,
+                kind: kind(self.local_destination, LinkSimNodeKind::Destination),
+            },
+        ];
+        let mut flows = self.flows;
+        flows.sort_by_key(|f| f.start);
+        let spec = LinkSimSpec {
+            edge: 0,
+            bottleneck: self.bottleneck,
+            other_bottlenecks: Vec::new(),
+            other_links: self.other_links,
+            nodes: endpoints.into_iter().chain(self.nodes).collect(),
+            flows,
+            packets: self.packets,
+        };
+        spec.validate()?;
+        Ok(spec)
+    }
+}
+
+/// Errors which can be encountered validating a [`LinkSimSpec`].
+#[derive(Debug, thiserror::Error)]
+pub enum LinkSimSpecError {
+    /// Two nodes have the same ID.
+    #[error("Duplicate node ID {0}")]
+    DuplicateNode(NodeId),
+
+    /// A link refers to a node which isn't declared.
+    #[error("Node {0} is not declared")]
+    UndeclaredNode(NodeId),
+
+    /// A source or destination isn't connected to the bottleneck.
+    #[error("Node {0} is not connected to the bottleneck")]
+    Disconnected(NodeId),
+
+    /// A flow's source isn't a source node.
+    #[error("Flow {flow} has an invalid source ({src})")]
+    InvalidFlowSrc {
+        /// The flow ID.
+        flow: FlowId,
+        /// The invalid source.
+        src: NodeId,
+    },
+
+    /// A flow's destination isn't a destination node.
+    #[error("Flow {flow} has an invalid destination ({dst})")]
+    InvalidFlowDst {
+        /// The flow ID.
+        flow: FlowId,
+        /// The invalid destination.
+        dst: NodeId,
+    },
+
+    /// Flows are not sorted by start time.
+    #[error("Flow {0} starts before the flow preceding it")]
+    UnsortedFlows(FlowId),
+}
+
 /// A descriptor for a link-level simulation.
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct LinkSimDesc {
     /// The edge index of the isolated link.
     pub edge: usize,
     /// The bottleneck.
     pub bottleneck: LinkSimLink,
-    /// The links other than the bottleneck.
+    /// The links other than the bottlenecks.
     pub other_links: Vec<LinkSimLink>,
     /// The nodes.
     pub nodes: Vec<LinkSimNode>,
-    /// The flow IDs.
+    /// The flow IDs, sorted by start time.
     pub flows: Vec<FlowId>,
+    /// Further bottlenecks. See [`LinkSimSpec::other_bottlenecks`]. Last, so that it can be
+    /// skipped when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub other_bottlenecks: Vec<LinkSimLink>,
+}
+
+/// Errors which can be encountered constructing a [`LinkSimDesc`].
+#[derive(Debug, thiserror::Error)]
+pub enum LinkSimDescError {
+    /// Tried to describe a link that doesn't exist.
+    #[error("Edge {} does not exist", .0.index())]
+    UnknownEdge(EdgeIndex),
+
+    /// A node is both a source and a destination of flows on the same edge.
+    #[error("Node {node} is both a source and a destination on edge {}", .edge.index())]
+    SourceIsDestination {
+        /// The edge.
+        edge: EdgeIndex,
+        /// The offending node.
+        node: NodeId,
+    },
+
+    /// A host is an endpoint of the edge without sourcing or sinking any of its flows, which
+    /// means it would have to forward traffic.
+    #[error("Host {node} would be a transit node on edge {}", .edge.index())]
+    TransitHost {
+        /// The edge.
+        edge: EdgeIndex,
+        /// The offending host.
+        node: NodeId,
+    },
+
+    /// There is no route between two nodes of the link-level topology.
+    #[error("No route from {from} to {to}")]
+    Unreachable {
+        /// The source node.
+        from: NodeId,
+        /// The destination node.
+        to: NodeId,
+    },
+}
+
+/// The sending window used by a link simulator.
+///
+/// A fixed window is (de)serialized as a plain number of bytes, so configurations written before
+/// windows could be computed automatically remain valid.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Window {
+    /// The same window for every link simulation.
+    Fixed(Bytes),
+    /// The bandwidth-delay product of each link simulation, scaled by `bdp_scale`. See
+    /// [`LinkSimSpec::bdp`].
+    Bdp {
+        /// The factor the bandwidth-delay product is multiplied by.
+        bdp_scale: f64,
+    },
+}
+
+impl Window {
+    /// A window of exactly one bandwidth-delay product.
+    pub const BDP: Self = Self::Bdp { bdp_scale: 1.0 };
+
+    /// Returns the window to use for a particular link simulation.
+    pub fn resolve(&self, spec: &LinkSimSpec) -> Bytes {
+        match *self {
+            Self::Fixed(window) => window,
+            Self::Bdp { bdp_scale } => spec.bdp().scale_by(bdp_scale),
+        }
+    }
+}
+
+impl From<Bytes> for Window {
+    fn from(window: Bytes) -> Self {
+        Self::Fixed(window)
+    }
+}
+
+impl LinkSimSpec {
+    /// Returns the largest base round-trip time of any flow: twice the propagation delay of its
+    /// path plus the time to serialize a full-sized packet and an ACK at every hop.
+    pub fn max_base_rtt(&self) -> Nanosecs {
+        let sz_xmit = self.packets.max_payload + self.packets.ack;
+        let topo = LinkSimTopo::new(self);
+        let mut rtts = FxHashMap::default();
+        self.flows
+            .iter()
+            .filter_map(|f| {
+                *rtts.entry((f.src, f.dst)).or_insert_with(|| {
+                    topo.path(f.src, f.dst).map(|path| {
+                        path.iter()
+                            .map(|l| {
+                                let bw = l.total_bandwidth.into_f64();
+                                let xmit = sz_xmit.into_f64() * 8.0 * 1e9 / bw;
+                                l.delay.scale_by(2.0) + Nanosecs::new(xmit.round() as u64)
+                            })
+                            .sum::<Nanosecs>()
+                    })
+                })
+            })
+            .max()
+            .unwrap_or(Nanosecs::ZERO)
+    }
+
+    /// Returns the bandwidth-delay product: the [largest base RTT](Self::max_base_rtt) times the
+    /// bottleneck's bandwidth.
+    pub fn bdp(&self) -> Bytes {
+        metrics::bdp(self.bottleneck.total_bandwidth, self.max_base_rtt())
+    }
 }
 
 /// A link-level topology.
@@ -175,10 +692,7 @@ impl LinkSimTopo {
             {
                 Some(l) => *l.weight(),
                 None => {
-                    let eix = match self.graph.first_edge(nix, Direction::Outgoing) {
-                        Some(eidx) => eidx,
-                        None => return None,
-                    };
+                    let eix = self.graph.first_edge(nix, Direction::Outgoing)?;
                     self.graph[eix]
                 }
             };
@@ -190,7 +704,7 @@ impl LinkSimTopo {
 }
 
 /// A node in a link-level simulation.
-#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct LinkSimNode {
     /// The node ID.
     pub id: NodeId,
@@ -199,7 +713,7 @@ pub struct LinkSimNode {
 }
 
 /// A link in a link-level simulation.
-#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct LinkSimLink {
     /// The first endpoint.
     pub from: NodeId,
@@ -211,10 +725,50 @@ pub struct LinkSimLink {
     pub available_bandwidth: BitsPerSec,
     /// The propagation delay.
     pub delay: Nanosecs,
+    /// The packet loss rate.
+    #[serde(default, skip_serializing_if = "crate::network::types::is_lossless")]
+    pub loss_rate: f64,
+}
+
+// Link-level paths are channels too, e.g., for computing their ideal FCTs
+impl Channel for LinkSimLink {
+    fn src(&self) -> NodeId {
+        self.from
+    }
+
+    fn dst(&self) -> NodeId {
+        self.to
+    }
+
+    fn bandwidth(&self) -> BitsPerSec {
+        self.total_bandwidth
+    }
+
+    fn delay(&self) -> Nanosecs {
+        self.delay
+    }
+
+    fn loss_rate(&self) -> f64 {
+        self.loss_rate
+    }
+}
+
+impl LinkSimLink {
+    /// Creates a lossless link whose total and available bandwidths are both `bandwidth`.
+    pub fn new(from: NodeId, to: NodeId, bandwidth: BitsPerSec, delay: Nanosecs) -> Self {
+        Self {
+            from,
+            to,
+            total_bandwidth: bandwidth,
+            available_bandwidth: bandwidth,
+            delay,
+            loss_rate: 0.0,
+        }
+    }
 }
 
 /// The types of nodes in a link-level simulation.
-#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum LinkSimNodeKind {
     /// A source node.
     Source,
@@ -239,7 +793,136 @@ pub enum LinkSimError {
     #[error(transparent)]
     Topology(#[from] TopologyError),
 
+    /// The spec is malformed.
+    #[error(transparent)]
+    InvalidSpec(#[from] LinkSimSpecError),
+
     /// Arbitrary catch-all.
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FixedDelaySim;
+
+    fn flow(id: usize, src: usize, dst: usize, start: u64) -> Flow {
+        Flow {
+            id: FlowId::new(id),
+            src: NodeId::new(src),
+            dst: NodeId::new(dst),
+            size: Bytes::new(2000),
+            start: Nanosecs::new(start),
+        }
+    }
+
+    #[test]
+    fn hand_built_specs_simulate() -> anyhow::Result<()> {
+        let bw = BitsPerSec::new(10_000_000_000);
+        let delay = Nanosecs::new(1000);
+        let spec =
+            LinkSimSpec::builder(LinkSimLink::new(NodeId::new(0), NodeId::new(1), bw, delay))
+                .local_source()
+                .source(NodeId::new(2), bw, delay)
+                .destination(NodeId::new(3), bw, delay)
+                .flows([flow(0, 2, 3, 10), flow(1, 0, 3, 0)])
+                .build()?;
+        assert_eq!(spec.links().count(), 3);
+        assert_eq!(spec.flows[0].id, FlowId::new(1));
+        let sim = FixedDelaySim {
+            delay_per_pkt: Nanosecs::new(100),
+        };
+        let records = simulate_one(spec, &sim)?;
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| r.fct == Nanosecs::new(200)));
+        Ok(())
+    }
+
+    #[test]
+    fn registries_recreate_simulators_by_name() -> anyhow::Result<()> {
+        let mut registry = LinkSimRegistry::new();
+        registry.register::<FixedDelaySim>("fixed-delay");
+        assert_eq!(registry.names(), ["fixed-delay"]);
+        let sim: DynLinkSim = Arc::new(FixedDelaySim {
+            delay_per_pkt: Nanosecs::new(100),
+        });
+        let params = sim.params().unwrap();
+        let recreated = registry.instantiate(&sim.name(), &params)?;
+        assert_eq!(recreated.params().unwrap(), params);
+        assert!(matches!(
+            registry.instantiate("minim", &params),
+            Err(LinkSimRegistryError::Unknown(_))
+        ));
+        assert!(matches!(
+            registry.instantiate("fixed-delay", "{}"),
+            Err(LinkSimRegistryError::InvalidParams { .. })
+        ));
+
+        let bw = BitsPerSec::new(10_000_000_000);
+        let delay = Nanosecs::new(1000);
+        let spec =
+            LinkSimSpec::builder(LinkSimLink::new(NodeId::new(0), NodeId::new(1), bw, delay))
+                .local_source()
+                .local_destination()
+                .flows([flow(0, 0, 1, 0)])
+                .build()?;
+        let records = simulate_one(spec, &recreated)?;
+        assert_eq!(records[0].fct, Nanosecs::new(200));
+        Ok(())
+    }
+
+    #[test]
+    fn bdp_windows_follow_longest_path() -> anyhow::Result<()> {
+        let bw = BitsPerSec::new(10_000_000_000);
+        let delay = Nanosecs::new(1000);
+        let spec =
+            LinkSimSpec::builder(LinkSimLink::new(NodeId::new(0), NodeId::new(1), bw, delay))
+                .local_source()
+                .source(NodeId::new(2), bw, delay)
+                .destination(NodeId::new(3), bw, delay)
+                .flows([flow(0, 2, 3, 10), flow(1, 0, 3, 0)])
+                .build()?;
+        // Three hops, each with 2us of propagation and 848ns to serialize a packet and an ACK
+        assert_eq!(spec.max_base_rtt(), Nanosecs::new(3 * 2848));
+        assert_eq!(Window::BDP.resolve(&spec), Bytes::new(10_680));
+        assert_eq!(
+            Window::Bdp { bdp_scale: 2.0 }.resolve(&spec),
+            Bytes::new(21_360)
+        );
+        let fixed = Window::from(Bytes::new(18_000));
+        assert_eq!(fixed.resolve(&spec), Bytes::new(18_000));
+        assert_eq!(serde_json::to_string(&fixed)?, "18000");
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_specs_fail() {
+        let bw = BitsPerSec::new(10_000_000_000);
+        let delay = Nanosecs::new(1000);
+        let builder =
+            || LinkSimSpec::builder(LinkSimLink::new(NodeId::new(0), NodeId::new(1), bw, delay));
+        // The bottleneck's source is a switch unless marked otherwise
+        let res = builder()
+            .destination(NodeId::new(3), bw, delay)
+            .flows([flow(0, 0, 3, 0)])
+            .build();
+        assert!(matches!(res, Err(LinkSimSpecError::InvalidFlowSrc { .. })));
+        let res = builder()
+            .source(NodeId::new(2), bw, delay)
+            .destination(NodeId::new(2), bw, delay)
+            .build();
+        assert!(matches!(res, Err(LinkSimSpecError::DuplicateNode(_))));
+        let mut spec = builder()
+            .source(NodeId::new(2), bw, delay)
+            .local_destination()
+            .flows([flow(0, 2, 1, 0)])
+            .build()
+            .unwrap();
+        spec.other_links.clear();
+        assert!(matches!(
+            spec.validate(),
+            Err(LinkSimSpecError::Disconnected(_))
+        ));
+    }
+}
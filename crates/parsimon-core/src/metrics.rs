@@ -0,0 +1,235 @@
+//! Public network metrics: bandwidth-delay products, offered loads over time, and ideal flow
+//! completion times. These are the quantities Parsimon itself uses to size windows, compare links,
+//! and normalize delays, exposed so that link simulators, clustering features, and analyses
+//! compute them the same way. Functions over paths accept any [`Channel`], including the
+//! [`LinkSimLink`](crate::linksim::LinkSimLink)s of link-level topologies.
+
+use crate::{
+    constants::PacketProfile,
+    network::{Channel, Flow},
+    units::{BitsPerSec, Bytes, Nanosecs},
+};
+
+/// Returns the bandwidth-delay product of a path with the given `bandwidth` and `delay`, rounded to
+/// the nearest byte, e.g., a window that keeps the path busy.
+pub fn bdp(bandwidth: impl Into<BitsPerSec>, delay: impl Into<Nanosecs>) -> Bytes {
+    let bytes_per_nanosec = bandwidth.into().into_f64() / 8.0 / 1e9;
+    let nanosecs = delay.into().into_f64();
+    Bytes::new((bytes_per_nanosec * nanosecs).round() as u64)
+}
+
+/// Returns the loads offered by `flows` to a channel with the given `bandwidth` in consecutive
+/// intervals of length `interval`, starting at time zero. Each load is the fraction of an
+/// interval's capacity ([`bdp`]) the flows starting in it offer. Bytes beyond an interval's
+/// capacity carry over to the next interval, so no load exceeds one, and intervals are appended
+/// until every byte is offered.
+///
+/// PRECONDITION: `flows` must be sorted by start time, and an interval must carry at least one
+/// byte.
+pub fn offered_loads(
+    bandwidth: impl Into<BitsPerSec>,
+    interval: impl Into<Nanosecs>,
+    flows: &[Flow],
+) -> Vec<f64> {
+    let interval: Nanosecs = interval.into();
+    let max_bytes = bdp(bandwidth, interval);
+    assert!(
+        max_bytes > Bytes::ZERO,
+        "offered_loads: an interval must carry at least one byte"
+    );
+    let load = |bytes: Bytes| bytes.into_f64() / max_bytes.into_f64();
+    let mut loads = Vec::new();
+    let mut count = Bytes::ZERO;
+    let mut next = interval;
+    let mut push_load = |count: &mut Bytes, next: &mut Nanosecs| {
+        let offered_bytes = std::cmp::min(max_bytes, *count);
+        let load = load(offered_bytes);
+        *count -= offered_bytes;
+        *next += interval;
+        loads.push(load);
+    };
+    for flow in flows {
+        while flow.start >= next {
+            push_load(&mut count, &mut next);
+        }
+        count += flow.size;
+    }
+    while count > Bytes::ZERO {
+        push_load(&mut count, &mut next);
+    }
+    loads
+}
+
+/// Returns the ideal FCT of a flow of `size` bytes alone on a path of `hops`, in order: its first
+/// packet is stored and forwarded at every hop, the rest are serialized at the slowest hop, and
+/// every hop adds its propagation delay. On lossy paths, transmissions are scaled by the expected
+/// number of retransmissions.
+///
+/// PRECONDITION: `hops` must not be empty.
+pub fn ideal_fct<T>(size: Bytes, hops: &[T], packets: &PacketProfile) -> Nanosecs
+where
+    T: Channel,
+{
+    let (tx_delay, prop_delay) = ideal_fct_components(size, hops, packets);
+    tx_delay + prop_delay
+}
+
+/// Splits the [ideal FCT](ideal_fct) into its transmission (store-and-forward and serialization,
+/// including expected retransmissions) and propagation delays, in that order.
+///
+/// PRECONDITION: `hops` must not be empty; this function panics otherwise.
+pub fn ideal_fct_components<T>(
+    size: Bytes,
+    hops: &[T],
+    packets: &PacketProfile,
+) -> (Nanosecs, Nanosecs)
+where
+    T: Channel,
+{
+    assert!(!hops.is_empty());
+    let (sz_pktmax, sz_pkthdr) = (packets.max_payload, packets.header);
+    let bandwidths = hops.iter().map(|c| c.bandwidth()).collect::<Vec<_>>();
+    let min_bw = bandwidths.iter().min().unwrap();
+    let sz_head_ = std::cmp::min(sz_pktmax, size);
+    let sz_head = if sz_head_ != Bytes::ZERO {
+        sz_head_ + sz_pkthdr
+    } else {
+        Bytes::ZERO
+    };
+    let sz_rest_ = size - sz_head_;
+    let head_delay = bandwidths
+        .iter()
+        .map(|bw| bw.length(sz_head))
+        .sum::<Nanosecs>();
+    let rest_delay = {
+        let nr_full_pkts = sz_rest_.into_usize() / sz_pktmax.into_usize();
+        let sz_full_pkt = packets.full_pkt();
+        let sz_partial_pkt_ = Bytes::new(sz_rest_.into_u64() % sz_pktmax.into_u64());
+        let sz_partial_pkt = if sz_partial_pkt_ != Bytes::ZERO {
+            sz_partial_pkt_ + sz_pkthdr
+        } else {
+            Bytes::ZERO
+        };
+        min_bw.length(sz_full_pkt).scale_by(nr_full_pkts as f64) + min_bw.length(sz_partial_pkt)
+    };
+    let prop_delay = hops.iter().map(|c| c.delay()).sum::<Nanosecs>();
+    // Every lost packet is retransmitted, so on a lossy path each packet is transmitted
+    // 1 / (1 - loss) times in expectation.
+    let delivery_rate = hops.iter().map(|c| 1.0 - c.loss_rate()).product::<f64>();
+    let tx_delay = head_delay + rest_delay;
+    let tx_delay = if delivery_rate < 1.0 {
+        tx_delay.scale_by(1.0 / delivery_rate)
+    } else {
+        tx_delay
+    };
+    (tx_delay, prop_delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        linksim::LinkSimLink,
+        network::{BasicChannel, NodeId},
+        units::{Gbps, Gigabytes, Microsecs},
+    };
+
+    const BANDWIDTH: Gbps = Gbps::new(100);
+    const INTERVAL: Microsecs = Microsecs::new(10);
+
+    // Scale a slice of floats in [0, 1] to a slice of integers in [0, 100].
+    fn integerify(vals: &[f64]) -> Vec<u32> {
+        vals.iter()
+            .map(|&val| (val * 100.0).round() as u32)
+            .collect()
+    }
+
+    #[test]
+    fn bdp_correct() {
+        let bdp = bdp(BANDWIDTH, INTERVAL);
+        assert_eq!(bdp, Bytes::new(125_000));
+    }
+
+    #[test]
+    fn offered_loads_time_advances() {
+        let flows = &[
+            Flow {
+                size: Bytes::new(12_500),
+                start: INTERVAL.into(),
+                ..Default::default()
+            },
+            Flow {
+                size: Bytes::new(12_500),
+                start: (INTERVAL + INTERVAL).into(),
+                ..Default::default()
+            },
+        ];
+        let offered_loads = integerify(&offered_loads(BANDWIDTH, INTERVAL, flows));
+        assert_eq!(offered_loads, vec![0, 10, 10]);
+    }
+
+    #[test]
+    fn offered_loads_overflows_correctly() {
+        let flow = Flow {
+            size: Gigabytes::ONE.into(),
+            start: INTERVAL.into(),
+            ..Default::default()
+        };
+        let offered_loads = integerify(&offered_loads(BANDWIDTH, INTERVAL, &[flow]));
+        assert_eq!(offered_loads[0], 0);
+        let bdp = bdp(BANDWIDTH, INTERVAL);
+        let nr_expected_ones = Into::<Bytes>::into(Gigabytes::ONE).into_u64() / bdp.into_u64();
+        assert_eq!(offered_loads[1..].len(), nr_expected_ones as usize);
+        assert!(offered_loads[1..].iter().all(|&load| load == 100));
+    }
+
+    #[test]
+    fn metrics_are_channel_generic() {
+        let chan = BasicChannel::new(
+            NodeId::new(0),
+            NodeId::new(1),
+            Gbps::new(10).into(),
+            Nanosecs::new(1000),
+        );
+        let link = LinkSimLink::new(chan.src, chan.dst, chan.bandwidth, chan.delay);
+        let packets = PacketProfile::default();
+        for size in [
+            Bytes::ZERO,
+            Bytes::new(1),
+            Bytes::new(1000),
+            Bytes::new(100_000),
+        ] {
+            assert_eq!(
+                ideal_fct(size, &[&chan, &chan], &packets),
+                ideal_fct(size, &[&link, &link], &packets)
+            );
+        }
+        assert_eq!(
+            bdp(chan.bandwidth, chan.delay),
+            bdp(Gbps::new(10), Microsecs::ONE)
+        );
+    }
+
+    #[test]
+    fn ideal_fct_accounts_for_retransmissions() {
+        let chan = BasicChannel::new(
+            NodeId::new(0),
+            NodeId::new(1),
+            Gbps::new(10).into(),
+            Nanosecs::new(1000),
+        );
+        let packets = PacketProfile::default();
+        assert_eq!(
+            ideal_fct(Bytes::new(1000), &[&chan], &packets),
+            Nanosecs::new(1838)
+        );
+        let lossy = BasicChannel {
+            loss_rate: 0.5,
+            ..chan
+        };
+        assert_eq!(
+            ideal_fct(Bytes::new(1000), &[&lossy], &packets),
+            Nanosecs::new(2676)
+        );
+    }
+}
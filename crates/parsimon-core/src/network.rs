@@ -5,10 +5,42 @@
 //! Finally, the simulations are run to produce a [`DelayNetwork`], which can be queried for FCT
 //! delay estimates.
 
+pub mod adaptive;
+pub mod affinity;
+mod arena;
+pub mod attribution;
+pub mod bootstrap;
+pub mod crossval;
+pub mod decompose;
+pub mod dedup;
+pub mod diff;
+pub mod distribution;
+pub mod ecmp;
+pub mod edit;
+pub mod fallback;
+pub mod flat;
+pub mod flowlets;
+pub mod flowpaths;
+pub mod measured;
+pub mod merge;
+pub mod pfc;
+pub mod querylog;
+mod rack;
+pub mod rpc;
+pub mod saved;
+pub mod sink;
+pub mod stats;
+pub mod summary;
 pub mod topology;
 pub mod types;
 
-use std::{collections::HashMap, net::SocketAddr};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    ops::Range,
+    path::PathBuf,
+};
 
 use itertools::Itertools;
 use petgraph::graph::NodeIndex;
@@ -21,34 +53,91 @@ pub use topology::TopologyError;
 pub use types::*;
 
 use crate::{
-    cluster::{Cluster, ClusteringAlgo},
-    constants::SZ_PKTMAX,
+    client::{ClientId, ClientMap},
+    cluster::{
+        self, Cluster, ClusterCheck, ClusterError, ClusteringAlgo, Promotion, RepresentativeIssue,
+        SavedClusters,
+    },
+    consistency::{ConsistencyCheck, ConsistencyReport, ViolationCounter},
+    constants::PacketProfile,
     distribute::{self, WorkerParams},
-    edist::EDistError,
+    edist::{self, BucketOpts, BucketStrategy, EDist, EDistBuckets, EDistError},
     linksim::{
-        LinkSim, LinkSimDesc, LinkSimError, LinkSimLink, LinkSimNode, LinkSimNodeKind, LinkSimSpec,
+        ExportLinkSim, LinkSim, LinkSimDesc, LinkSimDescError, LinkSimError, LinkSimLink,
+        LinkSimNode, LinkSimNodeKind, LinkSimSpec,
     },
-    opts::SimOpts,
-    routing::{BfsRoutes, RoutingAlgo},
+    metrics,
+    opts::{AckModel, RngStream, SimOpts, SimOptsError},
+    records::{FctRecords, RecordsError},
+    routing::{BfsRoutes, RouteLayout, RoutingAlgo},
+    telemetry::TraceContext,
     units::{BitsPerSec, Bytes, Nanosecs},
-    utils,
+    utils::StableHasher,
 };
 
+use self::affinity::{AffinityError, PathAffinity};
+use self::arena::{FlowArena, FlowIdx};
+use self::attribution::{FctPrediction, FlowBreakdown, LatencyBreakdown};
+use self::decompose::RackUnits;
+use self::dedup::{DedupStats, Duplicate};
+use self::ecmp::EcmpModel;
+use self::fallback::{LinkFailure, Resolution};
+use self::querylog::{QueryLog, QueryLogError, QueryLogger, QueryOutput};
+use self::rack::RackIndex;
+use self::sink::{PredictionSink, SinkError, StreamStats};
+use self::summary::{BucketSummary, ChannelSummary, HopSummary, PathSummary, Quantiles};
 use self::topology::Topology;
 
+// The number of end-to-end samples drawn by `DelayNetwork::summarize_path`.
+const NR_SUMMARY_SAMPLES: usize = 10_000;
+
+// The number of batches of link simulations created per worker for distributed runs.
+const NR_BATCHES_PER_WORKER: usize = 4;
+
+// The records of every simulated edge, and the edges whose simulations failed
+type SimResults = (HashMap<EdgeIndex, Vec<FctRecord>>, Vec<LinkFailure>);
+
+// The number of flows routed per round by `Network::into_simulations`. A round's path assignments
+// are the only ones that exist outside of channels at any time.
+const NR_FLOWS_PER_ROUND: usize = 1 << 22;
+
 /// A `Network` is a collection of nodes, links, and routes.
 #[derive(Debug, Clone)]
 pub struct Network<R = BfsRoutes> {
     topology: Topology<BasicChannel>,
     routes: R,
+    packets: PacketProfile,
+    ecmp: EcmpModel,
+    // The seed of runs on this network, if set
+    seed: Option<u64>,
 }
 
 impl Network<BfsRoutes> {
     /// Creates a new network with default [BFS routing](`BfsRoutes`).
     pub fn new(nodes: &[Node], links: &[Link]) -> Result<Self, TopologyError> {
+        Self::new_with_layout(nodes, links, RouteLayout::Dense)
+    }
+
+    /// Creates a new network with [BFS routing](`BfsRoutes`) whose table is laid out as given.
+    pub fn new_with_layout(
+        nodes: &[Node],
+        links: &[Link],
+        layout: RouteLayout,
+    ) -> Result<Self, TopologyError> {
         let topology = Topology::new(nodes, links)?;
-        let routes = BfsRoutes::new(&topology);
-        Ok(Self { topology, routes })
+        let routes = BfsRoutes::with_layout(&topology, layout);
+        Ok(Self {
+            topology,
+            routes,
+            packets: PacketProfile::default(),
+            ecmp: EcmpModel::default(),
+            seed: None,
+        })
+    }
+
+    /// Returns the layout of the network's routing table.
+    pub fn route_layout(&self) -> RouteLayout {
+        self.routes.layout()
     }
 }
 
@@ -63,7 +152,62 @@ where
         routes: R,
     ) -> Result<Self, TopologyError> {
         let topology = Topology::new(nodes, links)?;
-        Ok(Self { topology, routes })
+        Ok(Self {
+            topology,
+            routes,
+            packets: PacketProfile::default(),
+            ecmp: EcmpModel::default(),
+            seed: None,
+        })
+    }
+
+    /// Returns the network with flows split into packets of the given profile, which carries over
+    /// to the simulations and delay networks built from it. Fails if the profile's maximum
+    /// payload is zero.
+    pub fn with_packet_profile(mut self, packets: PacketProfile) -> Result<Self, TopologyError> {
+        if packets.max_payload == Bytes::ZERO {
+            return Err(TopologyError::EmptyPacketPayload);
+        }
+        self.packets = packets;
+        Ok(self)
+    }
+
+    /// Returns the [packet profile](PacketProfile) of the network.
+    pub fn packet_profile(&self) -> PacketProfile {
+        self.packets
+    }
+
+    /// Returns the network with flows split among equal-cost next hops by the given model, which
+    /// carries over to the simulations and delay networks built from it.
+    pub fn with_ecmp(mut self, ecmp: EcmpModel) -> Self {
+        self.ecmp = ecmp;
+        self
+    }
+
+    /// Returns the [ECMP model](EcmpModel) of the network.
+    pub fn ecmp(&self) -> &EcmpModel {
+        &self.ecmp
+    }
+
+    /// Returns the network with every random choice made for it derived from `seed`: ECMP
+    /// hashing of flows onto paths here, and the [streams](RngStream) of the simulations and
+    /// delay networks built from it. [`SimOpts::seed`] takes precedence when set.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Returns the seed of the network, if set.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    // Sets the seed if `seed` is set
+    pub(crate) fn seeded(self, seed: Option<u64>) -> Self {
+        match seed {
+            Some(seed) => self.with_seed(seed),
+            None => self,
+        }
     }
 
     /// Creates a `SimNetwork`.
@@ -71,45 +215,26 @@ where
     /// PRECONDITIONS: For each flow in `flows`, `flow.src` and `flow.dst` must be valid hosts in
     /// `network`, and there must be a path between them.
     /// POSTCONDITION: The flows populating each link will be sorted by start time.
+    ///
+    /// If the network has a [`FlowletModel`](flowlets::FlowletModel), large flows are replaced by
+    /// their flowlets (see [`Network::flowlets`] and [`SimNetwork::parent_of`]).
     pub fn into_simulations(self, flows: Vec<Flow>) -> SimNetwork<R> {
-        let mut topology = Topology::new_traced(&self.topology);
-        let assignments = utils::par_chunks(&flows, |flows| {
-            let mut assignments = Vec::new();
-            for &f @ Flow { id, src, dst, .. } in flows {
-                let hash = utils::calculate_hash(&id);
-                let path = self.edge_indices_between(src, dst, |choices| {
-                    assert!(!choices.is_empty(), "missing path from {src} to {dst}");
-                    let idx = hash as usize % choices.len();
-                    Some(&choices[idx])
-                });
-                for eidx in path {
-                    assignments.push((eidx, f));
-                }
-            }
-            assignments
-        })
-        .fold(
-            FxHashMap::default(),
-            |mut map: FxHashMap<_, Vec<_>>, (e, f)| {
-                map.entry(e).or_default().push(f);
-                map
-            },
-        );
-        let assignments = assignments
-            .into_par_iter()
-            .map(|(eidx, mut flows)| {
-                let mut chan = FlowChannel::new_from(&self.topology.graph[eidx]);
-                // POSTCONDITION: The flows populating each link will be sorted by start time.
-                flows.sort_by_key(|f| f.start);
-                for f in flows {
-                    chan.push_flow(&f);
-                }
-                (eidx, chan)
-            })
-            .collect::<Vec<_>>();
-        for (eidx, chan) in assignments {
-            topology.graph[eidx] = chan;
-        }
+        let mut flowlet_parents = FxHashMap::default();
+        let flows = match self.ecmp.flowlets {
+            Some(_) => self
+                .flowlets(&flows)
+                .into_iter()
+                .map(|f| {
+                    if f.flow.id != f.parent {
+                        flowlet_parents.insert(f.flow.id, f.parent);
+                    }
+                    f.flow
+                })
+                .collect(),
+            None => flows,
+        };
+        let flows = FlowArena::new(flows);
+        let topology = self.trace_flows(&flows, NR_FLOWS_PER_ROUND);
         // The default clustering uses a 1:1 mapping between edges and clusters.
         // CORRECTNESS: The code below assumes edge indices start at zero.
         let clusters = topology
@@ -121,8 +246,86 @@ where
             topology,
             routes: self.routes,
             clusters,
-            flows: flows.into_iter().map(|f| (f.id, f)).collect(),
+            isolated: FxHashSet::default(),
+            flows,
+            flowlet_parents,
+            origin: Nanosecs::ZERO,
+            packets: self.packets,
+            ecmp: self.ecmp,
+            seed: self.seed,
+        }
+    }
+
+    // Populates channels with flows. Flows are routed in rounds of `round_size` flows in order of
+    // start time. Within a round, every thread routes a contiguous shard of flows into its own
+    // per-edge buffers, and the buffers are appended to channels in shard order before the next
+    // round starts. Channels are therefore filled in order of start time without being sorted, and
+    // only one round's assignments exist outside of channels at a time.
+    //
+    // If flow IDs already follow start times, as they do for traces renumbered in start order,
+    // rounds are streamed straight from the arena. Otherwise, the order of all flows is sorted up
+    // front, which takes one `FlowIdx` per flow.
+    #[tracing::instrument(skip_all, fields(nr_flows = flows.len()))]
+    fn trace_flows(&self, flows: &FlowArena, round_size: usize) -> Topology<FlowChannel> {
+        let mut topology = Topology::new_traced(&self.topology);
+        let in_start_order = flows
+            .as_slice()
+            .par_windows(2)
+            .all(|w| w[0].start <= w[1].start);
+        // POSTCONDITION: The flows populating each link will be sorted by start time.
+        let order = (!in_start_order).then(|| {
+            let mut order = (0..flows.len() as FlowIdx).collect::<Vec<_>>();
+            order.par_sort_unstable_by_key(|&idx| (flows.get(idx).start, idx));
+            order
+        });
+        let round_size = round_size.max(1);
+        let shard_size = std::cmp::max(round_size / num_cpus::get(), 1);
+        for lo in (0..flows.len()).step_by(round_size) {
+            let hi = lo.saturating_add(round_size).min(flows.len());
+            let round: Cow<'_, [FlowIdx]> = match &order {
+                Some(order) => Cow::Borrowed(&order[lo..hi]),
+                None => Cow::Owned((lo as FlowIdx..hi as FlowIdx).collect()),
+            };
+            let shards = round
+                .par_chunks(shard_size)
+                .map(|shard| {
+                    let mut buffers = FxHashMap::<EdgeIndex, Vec<FlowIdx>>::default();
+                    for &idx in shard {
+                        let flow = flows.get(idx);
+                        let (src, dst) = (flow.src, flow.dst);
+                        let mut hop = self.ecmp.flow_hops(flow, self.seed);
+                        let path = self.edge_indices_between(src, dst, |choices| {
+                            assert!(!choices.is_empty(), "missing path from {src} to {dst}");
+                            hop(choices)
+                        });
+                        for eidx in path {
+                            buffers.entry(eidx).or_default().push(idx);
+                        }
+                    }
+                    buffers
+                })
+                .collect::<Vec<_>>();
+            let mut by_edge = FxHashMap::<EdgeIndex, Vec<&[FlowIdx]>>::default();
+            for buffers in &shards {
+                for (&eidx, buffer) in buffers {
+                    by_edge.entry(eidx).or_default().push(buffer);
+                }
+            }
+            topology
+                .graph
+                .edge_weights_mut()
+                .enumerate()
+                .par_bridge()
+                .for_each(|(i, chan)| {
+                    let Some(buffers) = by_edge.get(&EdgeIndex::new(i)) else {
+                        return;
+                    };
+                    for &idx in buffers.iter().copied().flatten() {
+                        chan.push_flow(flows, idx, &self.packets);
+                    }
+                });
         }
+        topology
     }
 
     /// Returns the [NodeId]s of all hosts in the network.
@@ -152,9 +355,8 @@ where
     }
 
     delegate::delegate! {
-        to self.topology.graph {
+        to self.topology {
             /// Returns an iterator over all nodes in the network.
-            #[call(node_weights)]
             pub fn nodes(&self) -> impl Iterator<Item = &Node>;
         }
 
@@ -179,6 +381,30 @@ where
     }
 }
 
+/// Appends the results of a run to the [store](crate::store) configured in `opts`, if any.
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+fn persist<S, R>(
+    opts: &SimOpts<S>,
+    delays: &DelayNetwork<R>,
+    clusters: &[Cluster],
+    eidx2data: &HashMap<EdgeIndex, Vec<FctRecord>>,
+) -> Result<(), SimNetworkError>
+where
+    S: LinkSim,
+    R: RoutingAlgo,
+{
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &opts.store {
+        crate::store::Store::open(path)?.append(
+            &opts.link_sim.name(),
+            delays,
+            clusters,
+            eidx2data,
+        )?;
+    }
+    Ok(())
+}
+
 /// A `SimNetwork` is similar to a [`Network`], except each link is augmented with a sequence of
 /// flows traversing it. These links can be simulated to produce a [`DelayNetwork`]. Optionally,
 /// they can also be clustered to reduce the number of simulations.
@@ -189,21 +415,37 @@ pub struct SimNetwork<R = BfsRoutes> {
 
     // Channel clustering
     clusters: Vec<Cluster>,
-    // Each channel references these flows by ID
-    flows: HashMap<FlowId, Flow>,
+    // Edges which are never clustered with other edges
+    isolated: FxHashSet<EdgeIndex>,
+    // Each channel references these flows by position
+    flows: FlowArena,
+    // The flows split into flowlets, by flowlet ID
+    flowlet_parents: FxHashMap<FlowId, FlowId>,
+    // The absolute time flow start times are relative to
+    origin: Nanosecs,
+    packets: PacketProfile,
+    ecmp: EcmpModel,
+    seed: Option<u64>,
 }
 
 impl<R> SimNetwork<R>
 where
     R: RoutingAlgo + Sync,
 {
-    /// Clusters the links in the network with the given clustering algorithm.
+    /// Clusters the links in the network with the given clustering algorithm. Edges tagged with
+    /// [`SimNetwork::isolate_edges`] are split out into clusters of their own, whatever the
+    /// algorithm returns.
+    #[tracing::instrument(
+        skip_all,
+        fields(nr_edges = self.topology.graph.edge_count(), nr_clusters)
+    )]
     pub fn cluster<C>(&mut self, algorithm: C)
     where
         C: ClusteringAlgo,
     {
         let clusters = algorithm.cluster(self);
-        self.clusters = clusters;
+        self.clusters = cluster::isolate(clusters, &self.isolated);
+        tracing::Span::current().record("nr_clusters", self.clusters.len());
     }
 
     /// Converts the `SimNetwork` into a [`DelayNetwork`] by performing link simulations and
@@ -213,155 +455,895 @@ where
         S: LinkSim + Sync,
     {
         let mut topology = Topology::new_edist(&self.topology);
+        let (eidx2data, dedup, clusters, failures) = self.simulate(&opts, &self.clusters)?;
+        let fill_violations = self.fill_delays(&mut topology, &clusters, &eidx2data, &opts)?;
+        let records = FctRecords::retain(&opts.retention, &clusters, &eidx2data, self.origin)?;
+        let delays = self.finish_delays(topology, &opts, fill_violations, records, dedup, failures);
+        persist(&opts, &delays, &clusters, &eidx2data)?;
+        Ok(delays)
+    }
+
+    /// Like [`SimNetwork::into_delays`], but only flows starting within `window` contribute to
+    /// the delay distributions. Flows outside of the window are still simulated, so they can warm
+    /// up (or keep busy) the links around the window's boundaries.
+    pub fn into_delays_within<S>(
+        self,
+        opts: SimOpts<S>,
+        window: Range<Nanosecs>,
+    ) -> Result<DelayNetwork<R>, SimNetworkError>
+    where
+        S: LinkSim + Sync,
+    {
+        let mut topology = Topology::new_edist(&self.topology);
+        let (mut eidx2data, dedup, clusters, failures) = self.simulate(&opts, &self.clusters)?;
+        for records in eidx2data.values_mut() {
+            records.retain(|rec| window.contains(&rec.start));
+        }
+        let fill_violations = self.fill_delays(&mut topology, &clusters, &eidx2data, &opts)?;
+        let records = FctRecords::retain(&opts.retention, &clusters, &eidx2data, self.origin)?;
+        let delays = self.finish_delays(topology, &opts, fill_violations, records, dedup, failures);
+        persist(&opts, &delays, &clusters, &eidx2data)?;
+        Ok(delays)
+    }
+
+    /// Like [`SimNetwork::into_delays`], but also keeps separate delay distributions for the flows
+    /// of each client in `clients`. See [`DelayNetwork::for_client`].
+    pub fn into_delays_by_client<S>(
+        self,
+        opts: SimOpts<S>,
+        clients: &ClientMap,
+    ) -> Result<DelayNetwork<R>, SimNetworkError>
+    where
+        S: LinkSim + Sync,
+    {
+        let mut topology = Topology::new_edist(&self.topology);
+        let (eidx2data, dedup, clusters, failures) = self.simulate(&opts, &self.clusters)?;
+        let fill_violations = self.fill_delays(&mut topology, &clusters, &eidx2data, &opts)?;
+        self.fill_client_delays(&mut topology, &clusters, &eidx2data, clients, &opts)?;
+        let records = FctRecords::retain(&opts.retention, &clusters, &eidx2data, self.origin)?;
+        let delays = self.finish_delays(topology, &opts, fill_violations, records, dedup, failures);
+        persist(&opts, &delays, &clusters, &eidx2data)?;
+        Ok(delays)
+    }
+
+    /// Converts the `SimNetwork` into a [`DelayNetwork`], simulating only the given `edges`. Every
+    /// other edge reuses its delay distributions from `base`, which must have been produced from a
+    /// network with the same topology. Each simulated edge is its own cluster. Only records of the
+    /// simulated edges are [retained](crate::records::Retention).
+    pub fn into_delays_reusing<S>(
+        self,
+        base: &DelayNetwork<R>,
+        edges: impl IntoIterator<Item = EdgeIndex>,
+        opts: SimOpts<S>,
+    ) -> Result<DelayNetwork<R>, SimNetworkError>
+    where
+        S: LinkSim + Sync,
+    {
+        self.into_delays_reusing_by_client(base, edges, opts, &ClientMap::new())
+    }
+
+    /// Like [`SimNetwork::into_delays_reusing`], but also keeps separate delay distributions for
+    /// the flows of each client in `clients` on the simulated edges. Every other edge reuses its
+    /// client distributions from `base`.
+    pub fn into_delays_reusing_by_client<S>(
+        self,
+        base: &DelayNetwork<R>,
+        edges: impl IntoIterator<Item = EdgeIndex>,
+        opts: SimOpts<S>,
+        clients: &ClientMap,
+    ) -> Result<DelayNetwork<R>, SimNetworkError>
+    where
+        S: LinkSim + Sync,
+    {
+        if base.topology.nr_edges() != self.topology.nr_edges() {
+            return Err(SimNetworkError::TopologyMismatch);
+        }
+        let edges = edges.into_iter().collect::<FxHashSet<_>>();
+        let clusters = edges
+            .iter()
+            .map(|&eidx| Cluster::new(eidx, [eidx].into_iter().collect()))
+            .collect::<Vec<_>>();
+        let mut topology = Topology::new_edist(&self.topology);
+        for eidx in topology.graph.edge_indices() {
+            if !edges.contains(&eidx) {
+                topology.graph[eidx].dists = base.topology.graph[eidx].dists.clone();
+                topology.graph[eidx].client_dists = base.topology.graph[eidx].client_dists.clone();
+                topology.graph[eidx].time_dists = base.topology.graph[eidx].time_dists.clone();
+            }
+        }
+        let (eidx2data, dedup, clusters, failures) = self.simulate(&opts, &clusters)?;
+        let mut fill_violations = self.fill_delays(&mut topology, &clusters, &eidx2data, &opts)?;
+        self.fill_client_delays(&mut topology, &clusters, &eidx2data, clients, &opts)?;
+        fill_violations.extend(
+            base.fill_violations
+                .iter()
+                .filter(|(eidx, _)| !edges.contains(eidx)),
+        );
+        fill_violations.sort();
+        let mut failures = failures;
+        failures.extend(
+            base.failures
+                .iter()
+                .filter(|f| !edges.contains(&f.edge))
+                .cloned(),
+        );
+        failures.sort_by_key(|f| f.edge);
+        let records = FctRecords::retain(&opts.retention, &clusters, &eidx2data, self.origin)?;
+        let delays = self.finish_delays(topology, &opts, fill_violations, records, dedup, failures);
+        persist(&opts, &delays, &clusters, &eidx2data)?;
+        Ok(delays)
+    }
+
+    /// Converts the `SimNetwork` into a [`DelayNetwork`] without running any link simulations,
+    /// filling delay distributions from externally computed FCT `records`, e.g., from a batch
+    /// cluster run or from cached results. Records are keyed by cluster representative, and every
+    /// member of a cluster is filled with its representative's records. Clusters without records
+    /// are left empty, and records keyed by other edges are rejected. `opts.link_sim` is never
+    /// invoked, so the network's [deduplication statistics](DelayNetwork::dedup_stats) are empty.
+    pub fn into_delays_from_records<S>(
+        self,
+        records: HashMap<EdgeIndex, Vec<FctRecord>>,
+        opts: SimOpts<S>,
+    ) -> Result<DelayNetwork<R>, SimNetworkError>
+    where
+        S: LinkSim + Sync,
+    {
+        opts.validate()?;
+        if let Some(&edge) = records
+            .keys()
+            .find(|eidx| eidx.index() >= self.topology.nr_edges())
+        {
+            return Err(SimNetworkError::UnknownEdge(edge));
+        }
+        let representatives = self
+            .clusters
+            .iter()
+            .map(|c| c.representative())
+            .collect::<FxHashSet<_>>();
+        if let Some(&edge) = records
+            .keys()
+            .filter(|eidx| !representatives.contains(eidx))
+            .min()
+        {
+            return Err(SimNetworkError::NotRepresentative(edge));
+        }
+        let mut topology = Topology::new_edist(&self.topology);
+        let clusters = self.clusters.clone();
+        let fill_violations = self.fill_delays(&mut topology, &clusters, &records, &opts)?;
+        let retained = FctRecords::retain(&opts.retention, &clusters, &records, self.origin)?;
+        // Nothing was simulated, so nothing was deduplicated
+        let delays = self.finish_delays(
+            topology,
+            &opts,
+            fill_violations,
+            retained,
+            DedupStats::default(),
+            Vec::new(),
+        );
+        persist(&opts, &delays, &clusters, &records)?;
+        Ok(delays)
+    }
+
+    // Wraps `topology`, filled with delays by a run with `opts`, into a delay network
+    fn finish_delays<S>(
+        self,
+        topology: Topology<EDistChannel>,
+        opts: &SimOpts<S>,
+        fill_violations: Vec<(EdgeIndex, usize)>,
+        records: Option<FctRecords>,
+        dedup: DedupStats,
+        failures: Vec<LinkFailure>,
+    ) -> DelayNetwork<R>
+    where
+        S: LinkSim,
+    {
+        let parts = DelayParts {
+            consistency: opts.consistency,
+            fill_violations,
+            origin: self.origin,
+            packets: self.packets,
+            ecmp: self.ecmp,
+            seed: opts.seed.or(self.seed),
+            records,
+            dedup,
+            failures,
+        };
+        DelayNetwork::new(topology, self.routes, parts)
+    }
+
+    /// Writes the inputs of every link simulation [`SimNetwork::into_delays`] would run with
+    /// `opts` without running them, e.g., to schedule them outside of Parsimon, and returns where
+    /// each was written, sorted by the edge the simulation is the representative or rack unit lead
+    /// of. Their results can be ingested by a simulator which reads them back.
+    pub fn export_simulations<S>(
+        &self,
+        opts: &SimOpts<S>,
+    ) -> Result<Vec<(EdgeIndex, PathBuf)>, SimNetworkError>
+    where
+        S: ExportLinkSim + Sync,
+    {
+        let (units, _, simulated, _) = self.plan_simulations(opts, &self.clusters)?;
+        let ack = opts.ack_model(&self.packets);
+        let mut paths = simulated
+            .par_iter()
+            .map(|c| {
+                let edge = c.representative();
+                match self.local_spec(edge, &units, ack)? {
+                    Some(spec) => Ok(Some((edge, opts.link_sim.export(spec)?))),
+                    None => Ok(None),
+                }
+            })
+            .filter_map(Result::transpose)
+            .collect::<Result<Vec<_>, SimNetworkError>>()?;
+        paths.sort_by_key(|&(edge, _)| edge);
+        Ok(paths)
+    }
+
+    // Returns the rack units of `opts`, the clusters to fill delays with, which differ from
+    // `clusters` with a rack-level decomposition, the clusters whose representatives are
+    // simulated, and the duplicates which share their results.
+    #[allow(clippy::type_complexity)]
+    fn plan_simulations<S>(
+        &self,
+        opts: &SimOpts<S>,
+        clusters: &[Cluster],
+    ) -> Result<(RackUnits, Vec<Cluster>, Vec<Cluster>, Vec<Duplicate>), SimNetworkError>
+    where
+        S: LinkSim,
+    {
+        opts.validate()?;
+        let ack = opts.ack_model(&self.packets);
+        let units = self.rack_units(&opts.decomposition);
+        let clusters = units.split_clusters(clusters);
+        let (per_link, unit_leads) = units.partition(&clusters);
+        let (mut simulated, duplicates) = if opts.dedup {
+            self.dedup_clusters(&per_link, ack)?
+        } else {
+            (per_link, Vec::new())
+        };
+        simulated.extend(unit_leads);
+        Ok((units, clusters, simulated, duplicates))
+    }
 
-        let eidx2data = if opts.is_local() {
-            self.simulate_clusters_locally(opts.link_sim)?
+    // Simulates the representatives of `clusters`, returning their results and the clusters to
+    // fill delays with, which differ from `clusters` with a rack-level decomposition.
+    #[allow(clippy::type_complexity)]
+    #[tracing::instrument(
+        skip_all,
+        fields(backend = %opts.link_sim.name(), nr_clusters = clusters.len(), nr_simulated)
+    )]
+    fn simulate<S>(
+        &self,
+        opts: &SimOpts<S>,
+        clusters: &[Cluster],
+    ) -> Result<
+        (
+            HashMap<EdgeIndex, Vec<FctRecord>>,
+            DedupStats,
+            Vec<Cluster>,
+            Vec<LinkFailure>,
+        ),
+        SimNetworkError,
+    >
+    where
+        S: LinkSim + Sync,
+    {
+        let (units, clusters, simulated, duplicates) = self.plan_simulations(opts, clusters)?;
+        let nr_simulated = simulated.len();
+        tracing::Span::current().record("nr_simulated", nr_simulated);
+        let (mut eidx2data, failures) = if opts.is_local() {
+            self.simulate_clusters_locally(opts, &units, &simulated)?
         } else {
-            self.simulate_clusters(opts.link_sim, &opts.workers)?
+            self.simulate_clusters(opts, &units, &simulated)?
         };
+        units.split_results(self, &mut eidx2data);
+        dedup::share_results(&mut eidx2data, &duplicates)?;
+        let stats = DedupStats {
+            nr_representatives: clusters.len(),
+            nr_simulated,
+        };
+        Ok((eidx2data, stats, clusters, failures))
+    }
+
+    // Returns the descriptor of the simulation `edge` is the representative or unit lead of.
+    fn sim_desc(
+        &self,
+        edge: EdgeIndex,
+        units: &RackUnits,
+        ack: AckModel,
+    ) -> Result<Option<LinkSimDesc>, LinkSimDescError> {
+        match units.unit(edge) {
+            Some(edges) => self.rack_sim_desc(edges, ack),
+            None => self.link_sim_desc_with(edge, ack),
+        }
+    }
+
+    // Fills `topology` with the delay distributions of each client's flows
+    fn fill_client_delays<S>(
+        &self,
+        topology: &mut Topology<EDistChannel>,
+        clusters: &[Cluster],
+        eidx2data: &HashMap<EdgeIndex, Vec<FctRecord>>,
+        clients: &ClientMap,
+        opts: &SimOpts<S>,
+    ) -> Result<(), SimNetworkError>
+    where
+        S: LinkSim,
+    {
+        if clients.is_empty() {
+            return Ok(());
+        }
+        for cluster in clusters {
+            let Some(data) = eidx2data.get(&cluster.representative()) else {
+                continue;
+            };
+            let mut client2data = FxHashMap::<ClientId, Vec<FctRecord>>::default();
+            for rec in data {
+                if let Some(client) = clients.client_of(self.parent_of(rec.id)) {
+                    client2data.entry(client).or_default().push(*rec);
+                }
+            }
+            for (client, client_data) in client2data {
+                let mut dists = EDistBuckets::new_empty();
+                dists.fill(
+                    &client_data,
+                    |rec| rec.size,
+                    |rec| rec.pktnorm_delay_with(&self.packets),
+                    opts.bucket_opts,
+                )?;
+                // The load offered to the link counts every client's flows
+                dists.set_loads(EDistBuckets::by_load_of(
+                    data,
+                    |rec: FctRecord| clients.client_of(self.parent_of(rec.id)) == Some(client),
+                    self.topology.graph[cluster.representative()].bandwidth,
+                    (
+                        |rec: FctRecord| rec.size,
+                        |rec: FctRecord| rec.start,
+                        |rec: FctRecord| rec.pktnorm_delay_with(&self.packets),
+                    ),
+                    opts.bucket_opts,
+                )?);
+                for &member in cluster.members() {
+                    topology.graph[member]
+                        .client_dists
+                        .insert(client, dists.clone());
+                }
+            }
+        }
+        Ok(())
+    }
 
+    /// Fills `topology` with delay distributions, returning the number of FCT records below ideal
+    /// for every representative with violations if consistency checks are enabled.
+    #[tracing::instrument(skip_all, fields(nr_clusters = clusters.len()))]
+    fn fill_delays<S>(
+        &self,
+        topology: &mut Topology<EDistChannel>,
+        clusters: &[Cluster],
+        eidx2data: &HashMap<EdgeIndex, Vec<FctRecord>>,
+        opts: &SimOpts<S>,
+    ) -> Result<Vec<(EdgeIndex, usize)>, SimNetworkError>
+    where
+        S: LinkSim,
+    {
+        let mut violations = Vec::new();
         // Every channel gets filled with delay distributions. All channels in the same cluster get
         // filled using the cluster representative's data.
-        for cluster in &self.clusters {
+        for cluster in clusters {
             let representative = cluster.representative();
+            let data = match eidx2data.get(&representative) {
+                Some(data) => &data[..],
+                None => &[],
+            };
+            if data.is_empty() {
+                let nr_starved = cluster
+                    .members()
+                    .filter(|&&m| !self.topology.graph[m].flows.is_empty())
+                    .count();
+                if nr_starved > 0 {
+                    tracing::warn!(
+                        edge = representative.index(),
+                        nr_starved,
+                        "cluster has no delays for members with flows"
+                    );
+                }
+            }
+            if opts.consistency.is_enabled() {
+                // Records below ideal are clamped by `FctRecord::delay`
+                let nr_violations = data.iter().filter(|rec| rec.fct < rec.ideal).count();
+                if nr_violations > 0 {
+                    if opts.consistency == ConsistencyCheck::Deny {
+                        return Err(SimNetworkError::BelowIdeal {
+                            edge: representative,
+                            nr_violations,
+                        });
+                    }
+                    tracing::warn!(
+                        edge = representative.index(),
+                        nr_violations,
+                        "FCT records below ideal"
+                    );
+                    violations.push((representative, nr_violations));
+                }
+            }
+            let time_dists = match opts.time_buckets {
+                Some(length) if !data.is_empty() => {
+                    time_dists(data, length, opts.bucket_opts, &self.packets)?
+                }
+                _ => Vec::new(),
+            };
+            let rack_dists = if opts.source_racks && !data.is_empty() {
+                self.rack_dists(data, opts.bucket_opts)?
+            } else {
+                FxHashMap::default()
+            };
+            let load_dists = EDistBuckets::by_load(
+                data,
+                self.topology.graph[representative].bandwidth,
+                (
+                    |rec: FctRecord| rec.size,
+                    |rec: FctRecord| rec.start,
+                    |rec: FctRecord| rec.pktnorm_delay_with(&self.packets),
+                ),
+                opts.bucket_opts,
+            )?;
             for &member in cluster.members() {
                 // Fill channel with packet-normalized delay predictions
-                let data = match eidx2data.get(&representative) {
-                    Some(data) => &data[..],
-                    None => &[],
-                };
                 if !data.is_empty() {
-                    topology.graph[member].dists.fill(
+                    let dists = &mut topology.graph[member].dists;
+                    dists.fill(
                         data,
                         |rec| rec.size,
-                        |rec| rec.pktnorm_delay(),
+                        |rec| rec.pktnorm_delay_with(&self.packets),
                         opts.bucket_opts,
                     )?;
+                    dists.set_groups(rack_dists.clone());
+                    dists.set_loads(load_dists.clone());
                 }
+                topology.graph[member].time_dists = time_dists.clone();
             }
         }
-        Ok(DelayNetwork {
-            topology,
-            routes: self.routes,
-        })
+        violations.sort();
+        Ok(violations)
+    }
+
+    // Builds delay distributions for the flows from each source rack with at least `opts.b`
+    // records. Flows from other racks fall back to the distributions over all records.
+    fn rack_dists(
+        &self,
+        data: &[FctRecord],
+        opts: BucketOpts,
+    ) -> Result<FxHashMap<NodeId, EDistBuckets>, EDistError> {
+        let mut by_rack = FxHashMap::<NodeId, Vec<FctRecord>>::default();
+        for rec in data {
+            let rack = self
+                .flows
+                .find(rec.id)
+                .and_then(|flow| self.topology.rack_of(flow.src));
+            if let Some(rack) = rack {
+                by_rack.entry(rack).or_default().push(*rec);
+            }
+        }
+        by_rack
+            .into_iter()
+            .filter(|(_, data)| data.len() >= opts.b)
+            .map(|(rack, data)| {
+                let mut dists = EDistBuckets::new_empty();
+                dists.fill(
+                    &data,
+                    |rec| rec.size,
+                    |rec| rec.pktnorm_delay_with(&self.packets),
+                    opts,
+                )?;
+                Ok((rack, dists))
+            })
+            .collect()
     }
 
     fn simulate_clusters_locally<S>(
         &self,
-        sim: S,
-    ) -> Result<HashMap<EdgeIndex, Vec<FctRecord>>, SimNetworkError>
+        opts: &SimOpts<S>,
+        units: &RackUnits,
+        clusters: &[Cluster],
+    ) -> Result<SimResults, SimNetworkError>
     where
         S: LinkSim + Sync,
     {
+        let (sim, policy) = (&opts.link_sim, &opts.on_failure);
+        let ack = opts.ack_model(&self.packets);
+        // Rayon threads don't inherit the current span
+        let parent = tracing::Span::current();
+        // Timed-out simulations can't be interrupted, so they're left running on detached threads
+        let detached = match opts.timeout {
+            Some(_) => Some(sim.detach().ok_or(SimOptsError::Undetachable)?),
+            None => None,
+        };
         let (s, r) = crossbeam_channel::unbounded();
-        // Simulate all cluster representatives in parallel.
-        self.clusters.par_iter().try_for_each_with(s, |s, c| {
+        // Simulate all cluster representatives in parallel
+        clusters.par_iter().try_for_each_with(s, |s, c| {
             let edge = c.representative();
-            let data = match self.link_sim_desc(edge) {
-                Some(desc) => {
-                    let flows = desc
-                        .flows
-                        .iter()
-                        .map(|id| self.flows.get(id).unwrap().to_owned())
-                        .collect::<Vec<_>>();
-                    let spec = LinkSimSpec {
-                        edge: desc.edge,
-                        bottleneck: desc.bottleneck,
-                        other_links: desc.other_links,
-                        nodes: desc.nodes,
-                        flows,
-                    };
-                    sim.simulate(spec)?
+            let (data, failure) = match self.local_spec(edge, units, ack)? {
+                Some(spec) => {
+                    let _entered = tracing::debug_span!(
+                        parent: &parent,
+                        "link_sim",
+                        edge = edge.index(),
+                        nr_flows = spec.flows.len(),
+                    )
+                    .entered();
+                    let mut attempts = 0;
+                    loop {
+                        attempts += 1;
+                        let Some(result) = fallback::simulate_within(
+                            sim,
+                            detached.as_ref(),
+                            spec.clone(),
+                            opts.timeout,
+                        ) else {
+                            let failure = LinkFailure::timed_out(
+                                edge,
+                                attempts,
+                                opts.timeout.unwrap_or_default(),
+                            );
+                            break (Vec::new(), Some(failure));
+                        };
+                        match result {
+                            Ok(data) => break (data, None),
+                            Err(e) if attempts <= policy.retries => {
+                                tracing::warn!(
+                                    edge = edge.index(),
+                                    error = %e,
+                                    "retrying failed link simulation"
+                                );
+                            }
+                            Err(e) if !policy.tolerates_failures() => return Err(e.into()),
+                            Err(e) => {
+                                let error = fallback::describe(&e);
+                                let (data, failure) =
+                                    policy.fall_back(edge, attempts, error, |fallback| {
+                                        fallback.simulate(spec)
+                                    })?;
+                                break (data, Some(failure));
+                            }
+                        }
+                    }
                 }
-                None => Vec::new(),
+                None => (Vec::new(), None),
             };
-            s.send((edge, data)).unwrap(); // the channel should never become disconnected
+            s.send((edge, data, failure)).unwrap(); // the channel should never become disconnected
             Result::<(), SimNetworkError>::Ok(())
         })?;
-        Ok(r.iter().collect())
+        let mut eidx2data = HashMap::new();
+        let mut failures = Vec::new();
+        for (edge, data, failure) in r {
+            eidx2data.insert(edge, data);
+            failures.extend(failure);
+        }
+        failures.sort_by_key(|f| f.edge);
+        Ok((eidx2data, failures))
+    }
+
+    // Returns the spec of the simulation `edge` is the representative or unit lead of.
+    fn local_spec(
+        &self,
+        edge: EdgeIndex,
+        units: &RackUnits,
+        ack: AckModel,
+    ) -> Result<Option<LinkSimSpec>, LinkSimDescError> {
+        let Some(desc) = self.sim_desc(edge, units, ack)? else {
+            return Ok(None);
+        };
+        let flows = match units.unit(edge) {
+            Some(edges) => self.flows.collect(&self.unit_flows(edges)),
+            None => self.flows.collect(&self.topology.graph[edge].flows),
+        };
+        Ok(Some(LinkSimSpec {
+            edge: desc.edge,
+            bottleneck: desc.bottleneck,
+            other_bottlenecks: desc.other_bottlenecks,
+            other_links: desc.other_links,
+            nodes: desc.nodes,
+            flows,
+            packets: self.packets,
+        }))
     }
 
+    #[tracing::instrument(skip_all, fields(nr_workers = opts.workers.len()))]
     fn simulate_clusters<S>(
         &self,
-        sim: S,
-        workers: &[SocketAddr],
-    ) -> Result<HashMap<EdgeIndex, Vec<FctRecord>>, SimNetworkError>
+        opts: &SimOpts<S>,
+        units: &RackUnits,
+        clusters: &[Cluster],
+    ) -> Result<SimResults, SimNetworkError>
     where
         S: LinkSim + Sync,
     {
-        let sim = (sim.name(), serde_json::to_string(&sim)?);
-        let assignments = self.assign_work_randomly(workers);
-        let assignments = assignments
-            .iter()
-            .par_bridge()
-            .map(|(worker, edges)| {
-                let descs = edges
-                    .par_iter()
-                    .filter_map(|&edge| self.link_sim_desc(edge))
-                    .collect::<Vec<_>>();
-                let flows = descs
-                    .iter()
-                    .flat_map(|d| d.flows.iter())
-                    .collect::<FxHashSet<_>>()
-                    .into_iter()
-                    .collect::<Vec<_>>();
-                let flows = utils::par_chunks(&flows, |flows| {
-                    flows
-                        .iter()
-                        .map(|&id| self.flows.get(id).unwrap().to_owned())
-                        .collect()
+        let ack = opts.ack_model(&self.packets);
+        let name = opts.link_sim.name();
+        let params = opts
+            .link_sim
+            .params()
+            .ok_or_else(|| SimNetworkError::LocalOnly(name.clone()))?;
+        let sim = (name, params);
+        let seed = RngStream::WorkAssignment.seed(opts.seed.or(self.seed).unwrap_or_default());
+        let batches = batch_work_randomly(clusters, opts.workers.len(), seed);
+        let make_params = |edges: &[EdgeIndex]| {
+            let descs = edges
+                .par_iter()
+                .filter_map(|&edge| self.sim_desc(edge, units, ack).transpose())
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut idxs = edges
+                .iter()
+                .flat_map(|&edge| match units.unit(edge) {
+                    Some(edges) => edges.to_vec(),
+                    None => vec![edge],
                 })
-                .collect();
-                let params = WorkerParams {
-                    link_sim: sim.clone(),
-                    descs,
-                    flows,
-                };
-                (worker, params)
-            })
-            .collect::<Vec<_>>();
-        let rt = tokio::runtime::Runtime::new()?;
-        let results = rt.block_on(async {
-            let handles = assignments
+                .flat_map(|edge| self.topology.graph[edge].flows.iter().copied())
+                .collect::<FxHashSet<_>>()
                 .into_iter()
-                .map(|(&worker, params)| tokio::spawn(distribute::work_remote(worker, params)))
                 .collect::<Vec<_>>();
-            let mut results = Vec::new();
-            for handle in handles {
-                results.append(&mut handle.await??);
-            }
-            Result::<_, SimNetworkError>::Ok(results)
-        })?;
-        Ok(results
+            idxs.par_sort_unstable();
+            let flows = self.flows.collect(&idxs);
+            Ok(WorkerParams {
+                link_sim: sim.clone(),
+                descs,
+                flows,
+                packets: self.packets,
+                trace_context: TraceContext::new(),
+                job: None,
+                timeout: opts.timeout,
+            })
+        };
+        let rt = tokio::runtime::Runtime::new()?;
+        let scheduled = rt.block_on(distribute::schedule(
+            &opts.workers,
+            opts.worker_updates.as_ref(),
+            opts.worker_grace,
+            batches,
+            make_params,
+            &opts.on_failure,
+            &tracing::Span::current(),
+        ))?;
+        let mut eidx2data = scheduled
+            .results
             .into_iter()
             .map(|(edge, records)| (EdgeIndex::new(edge), records))
-            .collect())
+            .collect::<HashMap<_, _>>();
+        let mut failures = scheduled
+            .timed_out
+            .into_iter()
+            .map(|edge| {
+                eidx2data.insert(EdgeIndex::new(edge), Vec::new());
+                LinkFailure::timed_out(EdgeIndex::new(edge), 1, opts.timeout.unwrap_or_default())
+            })
+            .collect::<Vec<_>>();
+        // Fallback simulators run locally
+        for failed in scheduled.failed {
+            let error = fallback::describe(&failed.error);
+            for edge in failed.batch {
+                let Some(spec) = self.local_spec(edge, units, ack)? else {
+                    continue;
+                };
+                let (data, failure) = opts.on_failure.fall_back(
+                    edge,
+                    failed.attempts,
+                    error.clone(),
+                    |fallback| fallback.simulate(spec),
+                )?;
+                eidx2data.insert(edge, data);
+                failures.push(failure);
+            }
+        }
+        failures.sort_by_key(|f| f.edge);
+        Ok((eidx2data, failures))
     }
 
-    fn assign_work_randomly(&self, workers: &[SocketAddr]) -> Vec<(SocketAddr, Vec<EdgeIndex>)> {
-        assert!(!workers.is_empty());
-        let mut edges = self
-            .clusters
+    /// Returns a copy of this network in which all flows to or from any of the given `hosts` have
+    /// been removed. Clusters are preserved.
+    pub fn without_hosts(&self, hosts: &[NodeId]) -> Self
+    where
+        R: Clone,
+    {
+        let hosts = hosts.iter().copied().collect::<FxHashSet<_>>();
+        let is_kept = |f: &Flow| !hosts.contains(&f.src) && !hosts.contains(&f.dst);
+        // Kept flows stay sorted by ID, so their new positions are their ranks among kept flows
+        let mut kept = Vec::new();
+        let remap = self
+            .flows
+            .as_slice()
             .iter()
-            .map(|c| c.representative())
+            .map(|f| {
+                is_kept(f).then(|| {
+                    kept.push(*f);
+                    (kept.len() - 1) as FlowIdx
+                })
+            })
             .collect::<Vec<_>>();
-        let mut rng = StdRng::seed_from_u64(0);
-        edges.shuffle(&mut rng);
-        let chunk_size = edges.len() / workers.len();
-        workers
+        let flows = FlowArena::new(kept);
+        let mut topology = self.topology.clone();
+        topology
+            .graph
+            .edge_weights_mut()
+            .par_bridge()
+            .for_each(|chan| {
+                let basic = BasicChannel {
+                    loss_rate: chan.loss_rate,
+                    ..BasicChannel::new(chan.src, chan.dst, chan.bandwidth, chan.delay)
+                };
+                let mut new_chan = FlowChannel::new_from(&basic);
+                // Flow order is preserved, so flows remain sorted by start time.
+                for &idx in &chan.flows {
+                    if let Some(new_idx) = remap[idx as usize] {
+                        new_chan.push_flow(&flows, new_idx, &self.packets);
+                    }
+                }
+                *chan = new_chan;
+            });
+        Self {
+            topology,
+            routes: self.routes.clone(),
+            clusters: self.clusters.clone(),
+            isolated: self.isolated.clone(),
+            origin: self.origin,
+            packets: self.packets,
+            ecmp: self.ecmp.clone(),
+            seed: self.seed,
+            flows,
+            flowlet_parents: self.flowlet_parents.clone(),
+        }
+    }
+
+    /// Returns a copy of this network in which every flow `f` is replicated or thinned by
+    /// `factor(f)`, e.g., 1.3 for 30% more traffic. A flow is kept `floor(factor)` times, plus
+    /// once more with probability `factor - floor(factor)`, so a factor below one thins flows.
+    /// Replicas follow the same path as the original flow and start within one mean inter-arrival
+    /// time of it. Clusters are preserved, and the result is determined by `seed`.
+    ///
+    /// The factor can depend on anything about a flow, such as its source or whether it traverses
+    /// a particular channel (see [`SimNetwork::flows_on`]).
+    pub fn scale_flows<F>(&self, factor: F, seed: u64) -> Self
+    where
+        F: Fn(&Flow) -> f64,
+        R: Clone,
+    {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let originals = self.flows.as_slice();
+        let mut next_id = originals
+            .last()
+            .map(|f| f.id + FlowId::ONE)
+            .unwrap_or_default();
+        let (first, last) = originals
+            .iter()
+            .map(|f| f.start)
+            .minmax()
+            .into_option()
+            .unwrap_or_default();
+        let spread = (last - first).into_f64() / originals.len().max(1) as f64;
+        let nr_copies = originals
+            .iter()
+            .map(|f| {
+                let k = factor(f);
+                assert!(
+                    k.is_finite() && k >= 0.0,
+                    "flow scaling factors must be finite and non-negative"
+                );
+                k.floor() as usize + usize::from(rng.gen::<f64>() < k.fract())
+            })
+            .collect::<Vec<_>>();
+        // Kept originals come first, followed by replicas with fresh IDs, so the new flows are
+        // sorted by ID in the order they are created.
+        let nr_kept = nr_copies.iter().filter(|&&n| n > 0).count();
+        let mut kept = Vec::with_capacity(nr_kept);
+        let mut extra = Vec::new();
+        let replicas = originals
             .iter()
-            .zip(edges.chunks(chunk_size))
-            .map(|(&w, es)| (w, es.to_vec()))
+            .zip(nr_copies)
+            .map(|(&f, nr_copies)| {
+                (0..nr_copies)
+                    .map(|i| {
+                        if i == 0 {
+                            kept.push(f);
+                            (kept.len() - 1) as FlowIdx
+                        } else {
+                            extra.push(Flow {
+                                id: next_id,
+                                start: f.start + Nanosecs::new((rng.gen::<f64>() * spread) as u64),
+                                ..f
+                            });
+                            next_id += FlowId::ONE;
+                            (nr_kept + extra.len() - 1) as FlowIdx
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        kept.extend(extra);
+        let flows = FlowArena::new(kept);
+        let mut topology = self.topology.clone();
+        topology
+            .graph
+            .edge_weights_mut()
+            .par_bridge()
+            .for_each(|chan| {
+                let basic = BasicChannel {
+                    loss_rate: chan.loss_rate,
+                    ..BasicChannel::new(chan.src, chan.dst, chan.bandwidth, chan.delay)
+                };
+                let mut new_chan = FlowChannel::new_from(&basic);
+                let mut idxs = chan
+                    .flows
+                    .iter()
+                    .flat_map(|&idx| replicas[idx as usize].iter().copied())
+                    .collect::<Vec<_>>();
+                idxs.sort_by_key(|&idx| (flows.get(idx).start, idx));
+                for idx in idxs {
+                    new_chan.push_flow(&flows, idx, &self.packets);
+                }
+                *chan = new_chan;
+            });
+        Self {
+            topology,
+            routes: self.routes.clone(),
+            clusters: self.clusters.clone(),
+            isolated: self.isolated.clone(),
+            origin: self.origin,
+            packets: self.packets,
+            ecmp: self.ecmp.clone(),
+            seed: self.seed,
+            flows,
+            flowlet_parents: self.flowlet_parents.clone(),
+        }
+    }
+
+    /// Returns a copy of this network in which all flows are [scaled](Self::scale_flows) by the
+    /// same `factor`.
+    pub fn scale_load(&self, factor: f64, seed: u64) -> Self
+    where
+        R: Clone,
+    {
+        self.scale_flows(|_| factor, seed)
+    }
+
+    /// Compares the per-link loads of this network with those of `other`, which must have the
+    /// same topology, and returns the links whose load changed by more than `threshold`.
+    pub fn load_changes(&self, other: &SimNetwork<R>, threshold: f64) -> Vec<LoadChange> {
+        self.edge_indices()
+            .filter_map(|eidx| {
+                let chan = self.edge(eidx)?;
+                let before = self.load_of(eidx)?;
+                let after = other.load_of(eidx)?;
+                ((after - before).abs() > threshold).then_some(LoadChange {
+                    edge: eidx,
+                    src: chan.src(),
+                    dst: chan.dst(),
+                    before,
+                    after,
+                })
+            })
             .collect()
     }
 
-    /// Returns the flows traversing a given edge, or `None` if the edge doesn't exist.
+    /// Returns an iterator over all flows in the network, sorted by ID.
+    pub fn flows(&self) -> impl Iterator<Item = &Flow> + '_ {
+        self.flows.as_slice().iter()
+    }
+
+    /// Returns the number of flows in the network.
+    pub fn nr_flows(&self) -> usize {
+        self.flows.len()
+    }
+
+    /// Returns the ID of the flow that the flow with the given ID was split from, if it is a
+    /// [flowlet](flowlets), and `id` otherwise. Networks with flowlets simulate them in place of
+    /// the flows they were split from, so FCT records carry flowlet IDs.
+    pub fn parent_of(&self, id: FlowId) -> FlowId {
+        self.flowlet_parents.get(&id).copied().unwrap_or(id)
+    }
+
+    /// Returns the flows traversing a given edge, sorted by start time, or `None` if the edge
+    /// doesn't exist.
     pub fn flows_on(&self, edge: EdgeIndex) -> Option<Vec<Flow>> {
-        self.edge(edge).map(|chan| {
-            chan.flow_ids()
-                .map(|id| self.flows.get(&id).unwrap().to_owned().to_owned())
-                .collect()
-        })
+        self.edge(edge).map(|chan| self.flows.collect(&chan.flows))
     }
 
     /// Returns the node with the given ID, or `None` if no such node exists.
@@ -371,21 +1353,180 @@ where
             .map(|&idx| &self.topology.graph[idx])
     }
 
-    /// Returns the edge connecting two nodes, if any.
+    /// Returns the edge connecting two nodes, if any. Edges from a host with a virtual NIC (see
+    /// [`Node::nic_rate`]) leave from the NIC.
     pub fn find_edge(&self, a: NodeId, b: NodeId) -> Option<EdgeIndex> {
-        let a = *self.topology.idx_of(&a)?;
-        let b = *self.topology.idx_of(&b)?;
-        self.topology.graph.find_edge(a, b)
+        self.topology.channel_between(a, b)
     }
 
-    /// Gets a reference to the `SimNetwork`'s clusters.
+    /// Returns the absolute time flow start times are relative to. Zero unless set with
+    /// [`SimNetwork::set_origin`].
+    pub fn origin(&self) -> Nanosecs {
+        self.origin
+    }
+
+    /// Returns the [packet profile](PacketProfile) flows are split into. See
+    /// [`Network::with_packet_profile`].
+    pub fn packet_profile(&self) -> PacketProfile {
+        self.packets
+    }
+
+    /// Returns the [ECMP model](EcmpModel) flows are assigned to paths with. See
+    /// [`Network::with_ecmp`].
+    pub fn ecmp(&self) -> &EcmpModel {
+        &self.ecmp
+    }
+
+    /// Returns the seed of the network, if set. See [`Network::with_seed`].
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Returns the seed of a random stream derived from the network's seed, if set, e.g., for
+    /// clustering algorithms to visit links in a seeded order.
+    pub fn seed_for(&self, stream: RngStream) -> Option<u64> {
+        self.seed.map(|seed| stream.seed(seed))
+    }
+
+    /// Sets the absolute time (e.g., an epoch timestamp) flow start times are relative to. Start
+    /// times stay run-relative internally; exports of delay networks built from this network,
+    /// such as [retained records](FctRecords::write_csv), restore absolute times by adding the
+    /// origin. [`Spec`](crate::spec::Spec)s set it automatically.
+    pub fn set_origin(&mut self, origin: Nanosecs) {
+        self.origin = origin;
+    }
+
+    /// Tags `edges` so that they are never clustered with other edges, e.g., the destination links
+    /// of an [incast](crate::incast). Tagged edges are split out of the current clusters and out of
+    /// the clusters of every later call to [`SimNetwork::cluster`]. Unknown edges are ignored.
+    pub fn isolate_edges(&mut self, edges: impl IntoIterator<Item = EdgeIndex>) {
+        let nr_edges = self.topology.nr_edges();
+        self.isolated
+            .extend(edges.into_iter().filter(|eidx| eidx.index() < nr_edges));
+        self.clusters = cluster::isolate(std::mem::take(&mut self.clusters), &self.isolated);
+    }
+
+    /// Returns the edges tagged with [`SimNetwork::isolate_edges`], in no particular order.
+    pub fn isolated_edges(&self) -> impl Iterator<Item = EdgeIndex> + '_ {
+        self.isolated.iter().copied()
+    }
+
+    /// Gets a reference to the `SimNetwork`'s clusters.
     pub fn clusters(&self) -> &[Cluster] {
         self.clusters.as_ref()
     }
 
-    /// Sets the `SimNetwork`'s clusters.
-    pub fn set_clusters(&mut self, clusters: Vec<Cluster>) {
-        self.clusters = clusters;
+    /// Sets the `SimNetwork`'s clusters. The clusters must partition the network's edges, and
+    /// every representative must be a member of its own cluster. Edges tagged with
+    /// [`SimNetwork::isolate_edges`] are split out into clusters of their own.
+    pub fn set_clusters(&mut self, clusters: Vec<Cluster>) -> Result<(), ClusterError> {
+        cluster::validate(&clusters, self.topology.nr_edges())?;
+        self.clusters = cluster::isolate(clusters, &self.isolated);
+        Ok(())
+    }
+
+    /// Checks, without simulating anything, that every cluster representative can be simulated
+    /// under `opts`: its link-level topology must be valid, and it must have flows if any other
+    /// member does.
+    pub fn check_clusters<S: LinkSim>(&self, opts: &SimOpts<S>) -> ClusterCheck {
+        let (issues, _) = self.check_representatives(opts.ack_model(&self.packets), false);
+        ClusterCheck {
+            issues,
+            promotions: Vec::new(),
+        }
+    }
+
+    /// Like [`SimNetwork::check_clusters`], but also replaces every problematic representative
+    /// with the simulable member that has the most flows, if there is one.
+    pub fn repair_clusters<S: LinkSim>(&mut self, opts: &SimOpts<S>) -> ClusterCheck {
+        let (issues, promotions) = self.check_representatives(opts.ack_model(&self.packets), true);
+        for &Promotion { from, to } in &promotions {
+            let cluster = self
+                .clusters
+                .iter_mut()
+                .find(|c| c.representative() == from)
+                .unwrap();
+            *cluster = Cluster::new(to, cluster.members().copied().collect());
+        }
+        ClusterCheck { issues, promotions }
+    }
+
+    fn check_representatives(
+        &self,
+        ack: AckModel,
+        promote: bool,
+    ) -> (Vec<RepresentativeIssue>, Vec<Promotion>) {
+        let is_simulable = |eidx| matches!(self.link_sim_desc_with(eidx, ack), Ok(Some(_)));
+        let nr_flows = |eidx| self.edge(eidx).map(|chan| chan.nr_flows()).unwrap_or(0);
+        let mut results = self
+            .clusters
+            .par_iter()
+            .filter_map(|cluster| {
+                let representative = cluster.representative();
+                let issue = match self.link_sim_desc_with(representative, ack) {
+                    Ok(Some(_)) => return None,
+                    Ok(None) => {
+                        let nr_starved = cluster.members().filter(|&&m| nr_flows(m) > 0).count();
+                        if nr_starved == 0 {
+                            return None;
+                        }
+                        RepresentativeIssue::Empty {
+                            representative,
+                            nr_starved,
+                        }
+                    }
+                    Err(error) => RepresentativeIssue::Invalid {
+                        representative,
+                        error,
+                    },
+                };
+                let promotion = promote
+                    .then(|| {
+                        cluster
+                            .members()
+                            .copied()
+                            .filter(|&m| m != representative && is_simulable(m))
+                            .max_by_key(|&m| (nr_flows(m), std::cmp::Reverse(m)))
+                    })
+                    .flatten()
+                    .map(|to| Promotion {
+                        from: representative,
+                        to,
+                    });
+                Some((issue, promotion))
+            })
+            .collect::<Vec<_>>();
+        results.sort_by_key(|(issue, _)| issue.representative());
+        let (issues, promotions): (Vec<_>, Vec<_>) = results.into_iter().unzip();
+        (issues, promotions.into_iter().flatten().collect())
+    }
+
+    /// Returns a fingerprint of the network's topology. Two networks with the same nodes and
+    /// links, specified in the same order, have the same fingerprint.
+    pub fn topology_fingerprint(&self) -> u64 {
+        self.topology.fingerprint()
+    }
+
+    /// Returns the network's clusters tagged with its topology fingerprint, for reuse in later
+    /// runs with [`SimNetwork::load_clusters`].
+    pub fn save_clusters(&self) -> SavedClusters {
+        SavedClusters {
+            fingerprint: self.topology_fingerprint(),
+            clusters: self.clusters.clone(),
+        }
+    }
+
+    /// Sets the network's clusters from a previous run. Fails if the clusters were computed for a
+    /// different topology or don't partition the network's edges.
+    pub fn load_clusters(&mut self, saved: SavedClusters) -> Result<(), ClusterError> {
+        let expected = self.topology_fingerprint();
+        if saved.fingerprint != expected {
+            return Err(ClusterError::FingerprintMismatch {
+                expected,
+                actual: saved.fingerprint,
+            });
+        }
+        self.set_clusters(saved.clusters)
     }
 
     /// Returns a path from `src` to `dst`, using `choose` to select a path when there are multiple
@@ -395,7 +1536,7 @@ where
         src: NodeId,
         dst: NodeId,
         choose: impl FnMut(&[NodeId]) -> Option<&NodeId>,
-    ) -> Path<FlowChannel> {
+    ) -> Path<'_, FlowChannel> {
         <Self as TraversableNetwork<FlowChannel, R>>::path(self, src, dst, choose)
     }
 
@@ -404,32 +1545,42 @@ where
         self.edge_indices().filter_map(|eidx| self.load_of(eidx))
     }
 
-    /// Returns the load of a particular link, or `None` if the link doesn't exist.
+    /// Returns the load of a particular link, or `None` if the link doesn't exist. Degenerate
+    /// links are handled as described in [`LinkLoad::value`].
     pub fn load_of(&self, eidx: EdgeIndex) -> Option<f64> {
-        let chan = self.edge(eidx)?;
-        let flows = self.flows_on(eidx)?;
-        let nr_bytes = flows.iter().map(|f| f.size).sum::<Bytes>();
-        let duration = self.duration_of(eidx)?;
-        (duration != Nanosecs::ZERO)
-            .then(|| {
-                assert!(chan.bandwidth() != BitsPerSec::ZERO);
-                let bps = nr_bytes.into_f64() * 8.0 * 1e9 / duration.into_f64();
-                bps / chan.bandwidth().into_f64()
-            })
-            .or(Some(0.0))
+        self.link_load(eidx).map(|load| load.value())
+    }
+
+    /// Returns the [load](LinkLoad) of a particular link, or `None` if the link doesn't exist.
+    pub fn link_load(&self, eidx: EdgeIndex) -> Option<LinkLoad> {
+        self.edge(eidx).map(FlowChannel::load)
     }
 
-    /// Returns the rate of the ACKs on a given link, or `None` if the link doesn't exist.
+    /// Returns the rate of the ACKs on a given link under the default [`AckModel`], or `None` if
+    /// the link doesn't exist.
     pub fn ack_rate_of(&self, eidx: EdgeIndex) -> Option<BitsPerSec> {
-        let chan = self.edge(eidx)?;
-        // TODO: Make finding a reverse edge more efficient
-        let reverse_edge = self.find_edge(chan.dst(), chan.src()).unwrap();
-        let reverse_chan = self.edge(reverse_edge)?;
-        let duration = self.duration_of(reverse_edge)?;
-        if duration == Nanosecs::ZERO {
-            return Some(BitsPerSec::ZERO);
-        }
-        let inner = reverse_chan.nr_ack_bytes.into_f64() * 8.0 * 1e9 / duration.into_f64();
+        self.ack_rate_with(eidx, AckModel::for_profile(&self.packets))
+    }
+
+    /// Returns the rate of the ACKs on a given link under `ack`, or `None` if the link doesn't
+    /// exist.
+    pub fn ack_rate_with(&self, eidx: EdgeIndex, ack: AckModel) -> Option<BitsPerSec> {
+        self.edge(eidx)?;
+        // ACKs for a unidirectional link return along some other path
+        let inner = self
+            .topology
+            .ack_channels(eidx)
+            .into_iter()
+            .filter_map(|reverse_edge| {
+                let reverse_chan = self.edge(reverse_edge)?;
+                let duration = self.duration_of(reverse_edge)?;
+                if duration == Nanosecs::ZERO {
+                    return None;
+                }
+                let nr_ack_bytes = ack.ack_bytes(reverse_chan.nr_pkts);
+                Some(nr_ack_bytes.into_f64() * 8.0 * 1e9 / duration.into_f64())
+            })
+            .sum::<f64>();
         Some(BitsPerSec::new(inner.round() as u64))
     }
 
@@ -438,20 +1589,33 @@ where
         Some(chan.duration())
     }
 
-    /// Returns a link-level descriptor for a given edge.
-    pub fn link_sim_desc(&self, edge: EdgeIndex) -> Option<LinkSimDesc> {
-        let chan = self.edge(edge)?;
+    /// Returns a link-level descriptor for a given edge under the default [`AckModel`], or `None`
+    /// if the edge has no flows.
+    pub fn link_sim_desc(&self, edge: EdgeIndex) -> Result<Option<LinkSimDesc>, LinkSimDescError> {
+        self.link_sim_desc_with(edge, AckModel::for_profile(&self.packets))
+    }
+
+    /// Returns a link-level descriptor for a given edge, or `None` if the edge has no flows. ACK
+    /// bandwidth is deducted from available bandwidths according to `ack`.
+    pub fn link_sim_desc_with(
+        &self,
+        edge: EdgeIndex,
+        ack: AckModel,
+    ) -> Result<Option<LinkSimDesc>, LinkSimDescError> {
+        let chan = self.edge(edge).ok_or(LinkSimDescError::UnknownEdge(edge))?;
         if chan.nr_flows() == 0 {
             // Sources and destinations for link-level topologies are extracted from flows, so if
             // there are no flows, there is no link-level topology.
-            return None;
+            return Ok(None);
         }
 
         // NOTE: `bsrc` and `bdst` may be in `srcs` and `dsts`, respectively
         let (srcs, dsts) = (&chan.flow_srcs, &chan.flow_dsts);
         let (bsrc, bdst) = (chan.src(), chan.dst());
 
-        assert!(srcs.intersection(dsts).count() == 0);
+        if let Some(&node) = srcs.intersection(dsts).next() {
+            return Err(LinkSimDescError::SourceIsDestination { edge, node });
+        }
         let nodes = srcs
             .iter()
             .chain(dsts.iter())
@@ -463,78 +1627,111 @@ where
                     NodeKind::Switch => LinkSimNodeKind::Switch,
                     NodeKind::Host if srcs.contains(&id) => LinkSimNodeKind::Source,
                     NodeKind::Host if dsts.contains(&id) => LinkSimNodeKind::Destination,
-                    _ => unreachable!("`link_sim_desc`: unknown node kind"),
+                    NodeKind::Host => return Err(LinkSimDescError::TransitHost { edge, node: id }),
                 };
-                LinkSimNode { id, kind }
+                Ok(LinkSimNode { id, kind })
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>, _>>()?;
 
         let mut other_links = Vec::new();
-        // Connect sources to the bottleneck. If `bsrc` is in `srcs`, then the
-        // bottleneck channel is assumed to be a host-ToR up-channel.
-        if srcs.contains(&bsrc) {
-            assert!(srcs.len() == 1);
-        } else {
-            for &src in srcs {
-                // CORRECTNESS: assumes all paths from `src` to `bsrc` have the
-                // same min bandwidth and delay
-                let path = self.path(src, bsrc, |choices| choices.first());
-                let &(eidx, chan) = path.iter().next().unwrap();
-                let link = LinkSimLink {
+        // Connect sources to the bottleneck. If `bsrc` is in `srcs`, then the bottleneck channel
+        // is a host up-channel, and `bsrc` itself needs no access link. Any other sources sharing
+        // the bottleneck are connected to `bsrc` like all other sources.
+        for &src in srcs.iter().filter(|&&src| src != bsrc) {
+            // CORRECTNESS: assumes all paths from `src` to `bsrc` have the
+            // same min bandwidth and delay
+            let path = self.path(src, bsrc, |choices| choices.first());
+            if path.iter().next().is_none() {
+                return Err(LinkSimDescError::Unreachable {
                     from: src,
                     to: bsrc,
-                    total_bandwidth: chan.bandwidth(),
-                    available_bandwidth: chan.bandwidth() - self.ack_rate_of(eidx).unwrap(),
-                    delay: path.delay(),
-                };
-                other_links.push(link);
+                });
             }
+            let (total_bandwidth, available_bandwidth) = self.access_bandwidth(src, bsrc, ack);
+            let link = LinkSimLink {
+                from: src,
+                to: bsrc,
+                total_bandwidth,
+                available_bandwidth,
+                delay: path.delay(),
+                loss_rate: path.loss_rate(),
+            };
+            other_links.push(link);
         }
-        // Connect the bottleneck to destinations with _fat links_. If `bdst`
-        // is in `dsts`, then the bottleneck channel is assumed to be a
-        // ToR-host down-channel.
-        if dsts.contains(&bdst) {
-            assert!(dsts.len() == 1);
-        } else {
-            for &dst in dsts {
-                // CORRECTNESS: assumes all paths from `bdst` to `dst` have the
-                // same min bandwidth and delay
-                let path = self.path(bdst, dst, |choices| choices.first());
-                let bandwidth = path.bandwidths().min().unwrap().scale_by(10.0);
-                let link = LinkSimLink {
+        // Connect the bottleneck to destinations with _fat links_. If `bdst` is in `dsts`, then
+        // the bottleneck channel is a host down-channel, and `bdst` itself needs no link.
+        for &dst in dsts.iter().filter(|&&dst| dst != bdst) {
+            // CORRECTNESS: assumes all paths from `bdst` to `dst` have the
+            // same min bandwidth and delay
+            let path = self.path(bdst, dst, |choices| choices.first());
+            let bandwidth = path
+                .bandwidths()
+                .min()
+                .ok_or(LinkSimDescError::Unreachable {
                     from: bdst,
                     to: dst,
-                    total_bandwidth: bandwidth,
-                    available_bandwidth: bandwidth,
-                    delay: path.delay(),
-                };
-                other_links.push(link);
-            }
+                })?
+                .scale_by(10.0);
+            let link = LinkSimLink {
+                from: bdst,
+                to: dst,
+                total_bandwidth: bandwidth,
+                available_bandwidth: bandwidth,
+                delay: path.delay(),
+                loss_rate: path.loss_rate(),
+            };
+            other_links.push(link);
         }
         // Now include the bottleneck channel
         let bottleneck = LinkSimLink {
             from: bsrc,
             to: bdst,
             total_bandwidth: chan.bandwidth(),
-            available_bandwidth: chan.bandwidth() - self.ack_rate_of(edge).unwrap(),
+            available_bandwidth: chan.bandwidth() - self.ack_rate_with(edge, ack).unwrap(),
             delay: chan.delay(),
+            loss_rate: chan.loss_rate(),
         };
 
-        Some(LinkSimDesc {
+        Ok(Some(LinkSimDesc {
             edge: edge.index(),
             bottleneck,
+            other_bottlenecks: Vec::new(),
             other_links,
             nodes,
-            flows: chan.flows.clone(),
-        })
+            flows: chan
+                .flows
+                .iter()
+                .map(|&idx| self.flows.get(idx).id)
+                .collect(),
+        }))
+    }
+
+    /// Returns the total and available bandwidth from `src` towards `to`, summed over all of the
+    /// first-hop channels on a shortest path. A multi-homed source can spread its flows over
+    /// several uplinks, unless they share a virtual NIC.
+    fn access_bandwidth(&self, src: NodeId, to: NodeId, ack: AckModel) -> (BitsPerSec, BitsPerSec) {
+        self.routes
+            .next_hops(src, to)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|hop| self.find_edge(src, hop))
+            .fold(
+                (BitsPerSec::ZERO, BitsPerSec::ZERO),
+                |(total, avail), eidx| {
+                    let bandwidth = self.topology.graph[eidx].bandwidth();
+                    let ack_rate = self.ack_rate_with(eidx, ack).unwrap();
+                    (total + bandwidth, avail + (bandwidth - ack_rate))
+                },
+            )
     }
 
     delegate::delegate! {
-        to self.topology.graph {
+        to self.topology {
             /// Returns an iterator over all nodes in the network.
-            #[call(node_weights)]
             pub fn nodes(&self) -> impl Iterator<Item = &Node>;
+        }
 
+        to self.topology.graph {
             /// Returns the `FlowChannel` at the given index, if any.
             #[call(edge_weight)]
             pub fn edge(&self, idx: EdgeIndex) -> Option<&FlowChannel>;
@@ -575,6 +1772,66 @@ where
     }
 }
 
+/// Builds delay distributions for each window of `length` in which records start, sorted by time.
+fn time_dists(
+    data: &[FctRecord],
+    length: Nanosecs,
+    opts: BucketOpts,
+    packets: &PacketProfile,
+) -> Result<Vec<(Range<Nanosecs>, EDistBuckets)>, EDistError> {
+    assert!(length > Nanosecs::ZERO, "time buckets must be nonempty");
+    let mut windows = std::collections::BTreeMap::<u64, Vec<FctRecord>>::new();
+    for rec in data {
+        windows
+            .entry(rec.start.into_u64() / length.into_u64())
+            .or_default()
+            .push(*rec);
+    }
+    windows
+        .into_iter()
+        .map(|(i, data)| {
+            let start = length.checked_mul(i).unwrap();
+            let mut dists = EDistBuckets::new_empty();
+            dists.fill(
+                &data,
+                |rec| rec.size,
+                |rec| rec.pktnorm_delay_with(packets),
+                opts,
+            )?;
+            Ok((start..start.saturating_add(length), dists))
+        })
+        .collect()
+}
+
+/// Splits the cluster representatives into randomly assigned batches, a few per worker, so that
+/// work can be rebalanced as workers come and go.
+fn batch_work_randomly(clusters: &[Cluster], nr_workers: usize, seed: u64) -> Vec<Vec<EdgeIndex>> {
+    let mut edges = clusters
+        .iter()
+        .map(|c| c.representative())
+        .collect::<Vec<_>>();
+    let mut rng = StdRng::seed_from_u64(seed);
+    edges.shuffle(&mut rng);
+    let nr_batches = nr_workers.max(1) * NR_BATCHES_PER_WORKER;
+    let batch_size = edges.len().div_ceil(nr_batches).max(1);
+    edges.chunks(batch_size).map(|es| es.to_vec()).collect()
+}
+
+/// A change in the load of a link.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct LoadChange {
+    /// The edge index of the link.
+    pub edge: EdgeIndex,
+    /// The link's source node.
+    pub src: NodeId,
+    /// The link's destination node.
+    pub dst: NodeId,
+    /// The load before the change.
+    pub before: f64,
+    /// The load after the change.
+    pub after: f64,
+}
+
 /// Errors which can be encountered running link-level simulations.
 #[derive(Debug, thiserror::Error)]
 pub enum SimNetworkError {
@@ -582,6 +1839,10 @@ pub enum SimNetworkError {
     #[error("Failed to simulate link")]
     LinkSim(#[from] LinkSimError),
 
+    /// Error constructing a link-level descriptor.
+    #[error("Failed to construct link-level descriptor")]
+    LinkSimDesc(#[from] LinkSimDescError),
+
     /// Error constructing empirical distribution.
     #[error("Failed to construct empirical distribution")]
     EDist(#[from] EDistError),
@@ -605,6 +1866,88 @@ pub enum SimNetworkError {
     /// Tokio join error.
     #[error("Tokio join error.")]
     TokioJoin(#[from] tokio::task::JoinError),
+
+    /// Error retaining FCT records.
+    #[error("Failed to retain FCT records")]
+    Records(#[from] RecordsError),
+
+    /// FCT records weren't retained, so distributions can't be rebuilt.
+    #[error("FCT records were not retained")]
+    RecordsNotRetained,
+
+    /// Invalid simulation options.
+    #[error("Invalid simulation options")]
+    InvalidOpts(#[from] SimOptsError),
+
+    /// Per-source-rack distributions can't be rebuilt from FCT records.
+    #[error("Per-source-rack distributions can't be rebucketed")]
+    RebucketSourceRacks,
+
+    /// Per-client distributions can't be rebuilt from FCT records, so they can't be bucketed by
+    /// offered load.
+    #[error("Per-client distributions can't be rebucketed by load")]
+    RebucketClients,
+
+    /// There are no workers left to run simulations on.
+    #[error("No workers available")]
+    NoWorkers,
+
+    /// A link simulation failed, and the [failure policy](fallback::FailurePolicy) has no
+    /// fallback.
+    #[error("Simulation of edge {} failed: {error}", edge.index())]
+    LinkFailed {
+        /// The simulated edge.
+        edge: EdgeIndex,
+        /// The error.
+        error: String,
+    },
+
+    /// The link simulator has no parameters to send to remote workers.
+    #[error("Link simulator {0:?} can't run on remote workers")]
+    LocalOnly(String),
+
+    /// Error persisting results.
+    #[cfg(feature = "sqlite")]
+    #[error("Failed to persist results")]
+    Store(#[from] crate::store::StoreError),
+
+    /// The networks being combined have different topologies.
+    #[error("Networks have different topologies")]
+    TopologyMismatch,
+
+    /// Records were supplied for an edge which isn't in the network.
+    #[error("Edge {} is not in the network", .0.index())]
+    UnknownEdge(EdgeIndex),
+
+    /// Records were supplied for an edge which isn't a cluster representative.
+    #[error("Edge {} is not a cluster representative", .0.index())]
+    NotRepresentative(EdgeIndex),
+
+    /// A link simulation returned a record for a flow it wasn't given.
+    #[error("Simulation of edge {} returned a record for unknown flow {id}", edge.index())]
+    UnknownFlow {
+        /// The simulated edge.
+        edge: EdgeIndex,
+        /// The unknown flow ID.
+        id: FlowId,
+    },
+
+    /// A link simulation reported FCTs below ideal, and consistency checks deny violations.
+    #[error("Simulation of edge {} has {nr_violations} FCTs below ideal", edge.index())]
+    BelowIdeal {
+        /// The simulated edge.
+        edge: EdgeIndex,
+        /// The number of FCT records below ideal.
+        nr_violations: usize,
+    },
+}
+
+/// Error type for [`DelayNetwork::set_hop_correlation`].
+#[derive(Debug, thiserror::Error)]
+pub enum HopCorrelationError {
+    /// The correlation isn't in [0, 1].
+    #[error("Invalid hop correlation {0}: must be in [0, 1]")]
+    InvalidCorrelation(f64),
 }
 
 /// A `DelayNetwork` is a network in which all edges contain empirical distributions of FCT delay
@@ -614,6 +1957,96 @@ pub enum SimNetworkError {
 pub struct DelayNetwork<R = BfsRoutes> {
     topology: Topology<EDistChannel>,
     routes: R,
+    // Shortcut for paths within a rack, if the routes allow it
+    racks: Option<RackIndex>,
+
+    // Consistency checking
+    consistency: ConsistencyCheck,
+    fill_violations: Vec<(EdgeIndex, usize)>,
+    query_violations: ViolationCounter,
+
+    // Sampling
+    hop_correlation: f64,
+    affinity: Option<PathAffinity>,
+
+    // The absolute time flow start times are relative to
+    origin: Nanosecs,
+    packets: PacketProfile,
+    ecmp: EcmpModel,
+    seed: Option<u64>,
+
+    // Raw link simulation results, if retained
+    records: Option<FctRecords>,
+    dedup: DedupStats,
+    // Edges whose simulations failed and fell back
+    failures: Vec<LinkFailure>,
+}
+
+// Everything a delay network records about how it was built, besides its delays and routes
+#[derive(Debug, Default)]
+struct DelayParts {
+    consistency: ConsistencyCheck,
+    fill_violations: Vec<(EdgeIndex, usize)>,
+    origin: Nanosecs,
+    packets: PacketProfile,
+    ecmp: EcmpModel,
+    seed: Option<u64>,
+    records: Option<FctRecords>,
+    dedup: DedupStats,
+    failures: Vec<LinkFailure>,
+}
+
+impl<R> DelayNetwork<R>
+where
+    R: RoutingAlgo,
+{
+    // Builds a network from its filled `topology`, indexing paths within racks if the routes
+    // allow it. Hops are sampled independently, without path affinities.
+    fn new(topology: Topology<EDistChannel>, routes: R, parts: DelayParts) -> Self {
+        Self {
+            racks: routes
+                .is_shortest_path_ecmp()
+                .then(|| RackIndex::new(&topology)),
+            topology,
+            routes,
+            consistency: parts.consistency,
+            fill_violations: parts.fill_violations,
+            query_violations: ViolationCounter::default(),
+            hop_correlation: 0.0,
+            affinity: None,
+            origin: parts.origin,
+            packets: parts.packets,
+            ecmp: parts.ecmp,
+            seed: parts.seed,
+            records: parts.records,
+            dedup: parts.dedup,
+            failures: parts.failures,
+        }
+    }
+}
+
+// Which of a channel's delay distributions predictions sample from
+#[derive(Debug, Clone, Copy)]
+enum DistChoice {
+    // The distributions over all records
+    All,
+    // The distributions of the time window containing a start time, if any
+    At(Option<Nanosecs>),
+    // The distributions of the range of offered load containing a load, if any
+    UnderLoad(f64),
+}
+
+impl DistChoice {
+    fn select(self, chan: &EDistChannel, rack: Option<NodeId>) -> &EDistBuckets {
+        match self {
+            Self::All => chan.dists.group_or_all(rack),
+            Self::At(start) => chan.dists_at(start).group_or_all(rack),
+            Self::UnderLoad(load) => chan
+                .dists
+                .for_load(load)
+                .unwrap_or_else(|| chan.dists.group_or_all(rack)),
+        }
+    }
 }
 
 impl<R> DelayNetwork<R>
@@ -621,7 +2054,8 @@ where
     R: RoutingAlgo,
 {
     /// Predict a point estimate of delay for a flow of a particular `size` going from `src` to
-    /// `dst`.
+    /// `dst`. With a [`FlowletModel`](flowlets::FlowletModel), flows which are split into
+    /// flowlets are predicted as the sum of their flowlets' delays, each on a path of its own.
     pub fn predict<RNG>(
         &self,
         size: Bytes,
@@ -631,22 +2065,62 @@ where
     where
         RNG: Rng,
     {
-        let channels = self
-            .edge_indices_between(src, dst, |choices| choices.choose(&mut rng))
-            .map(|e| &self.topology.graph[e])
-            .collect::<Vec<_>>();
+        let channels = self.channels_between(src, dst, &mut rng);
         if channels.is_empty() {
             return None;
         }
-        channels
-            .iter()
-            .map(|&chan| chan.dists.for_size(size).map(|dist| dist.sample(&mut rng)))
-            .sum::<Option<f64>>()
-            .map(|pktnorm_delay| {
-                let nr_pkts = (size.into_f64() / SZ_PKTMAX.into_f64()).ceil();
-                let delay = nr_pkts * pktnorm_delay;
-                Nanosecs::new(delay as u64)
-            })
+        self.sample_delay(&channels, size, (src, dst), DistChoice::All, &mut rng)
+    }
+
+    /// Like [`DelayNetwork::predict`], but conditioned on the flow's `start` time, relative to the
+    /// network's [origin](Self::origin). Each hop samples from the delay distribution of the time
+    /// window containing `start` if the network was built with
+    /// [time buckets](crate::opts::SimOpts::time_buckets), and from its distribution over all
+    /// start times otherwise. Flows are split into flowlets as by [`DelayNetwork::predict`].
+    pub fn predict_at<RNG>(
+        &self,
+        size: Bytes,
+        (src, dst): (NodeId, NodeId),
+        start: Option<Nanosecs>,
+        mut rng: RNG,
+    ) -> Option<Nanosecs>
+    where
+        RNG: Rng,
+    {
+        let channels = self.channels_between(src, dst, &mut rng);
+        if channels.is_empty() {
+            return None;
+        }
+        self.sample_delay(&channels, size, (src, dst), DistChoice::At(start), &mut rng)
+    }
+
+    /// Like [`DelayNetwork::predict`], but conditioned on the `load` offered to every hop when the
+    /// flow starts, as a fraction of the hop's capacity. Hops sample from the delay distribution
+    /// of the range of offered load containing `load` if the network was built with
+    /// [`BucketStrategy::SizeAndLoad`](crate::edist::BucketStrategy::SizeAndLoad), and as
+    /// [`DelayNetwork::predict`] does otherwise. Flows are split into flowlets as by
+    /// [`DelayNetwork::predict`].
+    pub fn predict_under_load<RNG>(
+        &self,
+        size: Bytes,
+        (src, dst): (NodeId, NodeId),
+        load: f64,
+        mut rng: RNG,
+    ) -> Option<Nanosecs>
+    where
+        RNG: Rng,
+    {
+        let channels = self.channels_between(src, dst, &mut rng);
+        if channels.is_empty() {
+            return None;
+        }
+        self.sample_delay(
+            &channels,
+            size,
+            (src, dst),
+            DistChoice::UnderLoad(load),
+            &mut rng,
+        )
     }
 
     /// Compute the ideal FCT on an unloaded network for a flow of `size` bytes going from `src` to
@@ -660,14 +2134,11 @@ where
     where
         RNG: Rng,
     {
-        let channels = self
-            .edge_indices_between(src, dst, |choices| choices.choose(&mut rng))
-            .map(|e| &self.topology.graph[e])
-            .collect::<Vec<_>>();
+        let channels = self.channels_between(src, dst, &mut rng);
         if channels.is_empty() {
             return None;
         }
-        Some(utils::ideal_fct(size, &channels))
+        Some(metrics::ideal_fct(size, &channels, &self.packets))
     }
 
     /// Predict a point estimate of slowdown for a flow of a particular `size` going from `src` to
@@ -683,160 +2154,2034 @@ where
     where
         RNG: Rng,
     {
-        let channels = self
-            .edge_indices_between(src, dst, |choices| choices.choose(&mut rng))
-            .map(|e| &self.topology.graph[e])
-            .collect::<Vec<_>>();
+        let channels = self.channels_between(src, dst, &mut rng);
         if channels.is_empty() {
             return None;
         }
-        let ideal_fct = utils::ideal_fct(size, &channels);
-        let delay = channels
-            .iter()
-            .map(|&chan| chan.dists.for_size(size).map(|dist| dist.sample(&mut rng)))
-            .sum::<Option<f64>>()
-            .map(|pktnorm_delay| {
-                let nr_pkts = (size.into_f64() / SZ_PKTMAX.into_f64()).ceil();
-                let delay = nr_pkts * pktnorm_delay;
-                Nanosecs::new(delay as u64)
-            })?;
+        let ideal_fct = metrics::ideal_fct(size, &channels, &self.packets);
+        let delay = self.sample_delay(&channels, size, (src, dst), DistChoice::All, &mut rng)?;
         let real_fct = ideal_fct + delay;
         Some(real_fct.into_f64() / ideal_fct.into_f64())
     }
 
-    delegate::delegate! {
-        to self.topology.graph {
-            /// Returns an iterator over the [nodes](Node) in the network.
-            #[call(node_weights)]
-            pub fn nodes(&self) -> impl Iterator<Item = &Node>;
-        }
-
-        to self.topology.links {
-            /// Returns an iterator over the [links](Link) in the network.
-            #[call(iter)]
-            pub fn links(&self) -> impl Iterator<Item = &Link>;
+    /// Predicts the FCT of a flow of a particular `size` going from `src` to `dst`, along with the
+    /// ideal FCT and the delay, which are computed on the same path. This is equivalent to, but
+    /// cheaper than, calling [`DelayNetwork::ideal_fct`] and [`DelayNetwork::predict`] and adding
+    /// their results.
+    pub fn predict_fct<RNG>(
+        &self,
+        size: Bytes,
+        (src, dst): (NodeId, NodeId),
+        mut rng: RNG,
+    ) -> Option<FctPrediction>
+    where
+        RNG: Rng,
+    {
+        let channels = self.channels_between(src, dst, &mut rng);
+        if channels.is_empty() {
+            return None;
         }
+        let ideal = metrics::ideal_fct(size, &channels, &self.packets);
+        let delay = self.sample_delay(&channels, size, (src, dst), DistChoice::All, &mut rng)?;
+        Some(FctPrediction {
+            ideal,
+            delay,
+            fct: ideal + delay,
+        })
     }
-}
 
-impl<R> TraversableNetwork<EDistChannel, R> for DelayNetwork<R>
-where
-    R: RoutingAlgo,
-{
-    fn topology(&self) -> &Topology<EDistChannel> {
-        &self.topology
+    /// Predicts FCTs for a batch of `(size, (src, dst))` queries, in order. Each entry is `None`
+    /// if no prediction can be made for its query. See [`DelayNetwork::predict_fct`] and
+    /// [`FctPrediction::slowdown`].
+    pub fn predict_slowdown_batch<RNG>(
+        &self,
+        queries: &[(Bytes, (NodeId, NodeId))],
+        mut rng: RNG,
+    ) -> Vec<Option<FctPrediction>>
+    where
+        RNG: Rng,
+    {
+        queries
+            .iter()
+            .map(|&(size, pair)| self.predict_fct(size, pair, &mut rng))
+            .collect()
     }
 
-    fn routes(&self) -> &R {
-        &self.routes
+    /// Predicts the FCT of a flow of a particular `size` going from `src` to `dst`, broken down
+    /// into propagation, transmission, and queueing delays.
+    pub fn predict_breakdown<RNG>(
+        &self,
+        size: Bytes,
+        (src, dst): (NodeId, NodeId),
+        mut rng: RNG,
+    ) -> Option<LatencyBreakdown>
+    where
+        RNG: Rng,
+    {
+        let channels = self.channels_between(src, dst, &mut rng);
+        if channels.is_empty() {
+            return None;
+        }
+        let (transmission, propagation) =
+            metrics::ideal_fct_components(size, &channels, &self.packets);
+        let queueing = self.sample_delay(&channels, size, (src, dst), DistChoice::All, &mut rng)?;
+        Some(LatencyBreakdown {
+            propagation,
+            transmission,
+            queueing,
+        })
     }
-}
 
-pub(crate) trait TraversableNetwork<C: Clone + Channel, R: RoutingAlgo> {
-    fn topology(&self) -> &Topology<C>;
-
-    fn routes(&self) -> &R;
-
-    #[allow(dead_code)]
-    fn nr_edges(&self) -> usize {
-        self.topology().nr_edges()
+    /// Predicts FCT breakdowns for every flow in `flows`, in order, skipping flows for which no
+    /// prediction can be made. Each flow is predicted on the path the network's
+    /// [`EcmpPolicy`](ecmp::EcmpPolicy) assigns it to. See [`attribution::aggregate`] to
+    /// aggregate the results per traffic class.
+    pub fn predict_breakdowns<RNG>(&self, flows: &[Flow], mut rng: RNG) -> Vec<FlowBreakdown>
+    where
+        RNG: Rng,
+    {
+        flows
+            .iter()
+            .filter_map(|&flow| {
+                let breakdown = self.flow_breakdown(&flow, &mut rng)?;
+                Some(FlowBreakdown { flow, breakdown })
+            })
+            .collect()
     }
 
-    fn edge_indices_between(
+    /// Like [`DelayNetwork::predict_breakdowns`], but streams the breakdowns into `sink` in
+    /// batches of at most `batch_size` instead of collecting them. Flows are drawn from `flows`
+    /// lazily, so memory use is bounded by the batch size regardless of the number of flows. If
+    /// the sink fails, prediction stops and the error is returned. See [`sink`] for the available
+    /// sinks.
+    pub fn predict_into<I, RNG, S>(
         &self,
-        src: NodeId,
-        dst: NodeId,
-        mut choose: impl FnMut(&[NodeId]) -> Option<&NodeId>,
-    ) -> std::vec::IntoIter<EdgeIndex> {
-        let mut acc = Vec::new();
-        let mut cur = src;
-        while cur != dst {
-            let next_hop_choices = match self.routes().next_hops(cur, dst) {
-                Some(hops) => hops,
-                None => break,
-            };
-            match choose(&next_hop_choices) {
-                Some(&next_hop) => {
-                    // These indices are all guaranteed to exist because we have a valid topology
-                    let i = *self.topology().idx_of(&cur).unwrap();
-                    let j = *self.topology().idx_of(&next_hop).unwrap();
-                    let e = self.topology().find_edge(i, j).unwrap();
-                    acc.push(e);
-                    cur = next_hop;
-                }
-                // There is no choice of next hop, and therefore no path
-                None => return Vec::new().into_iter(),
+        flows: I,
+        mut rng: RNG,
+        mut sink: S,
+        batch_size: usize,
+    ) -> Result<StreamStats, SinkError>
+    where
+        I: IntoIterator<Item = Flow>,
+        RNG: Rng,
+        S: PredictionSink,
+    {
+        let batch_size = batch_size.max(1);
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut stats = StreamStats::default();
+        for flow in flows {
+            match self.flow_breakdown(&flow, &mut rng) {
+                Some(breakdown) => batch.push(FlowBreakdown { flow, breakdown }),
+                None => stats.nr_skipped += 1,
+            }
+            if batch.len() == batch_size {
+                sink.write(&batch)?;
+                stats.nr_predicted += batch.len();
+                stats.nr_batches += 1;
+                batch.clear();
             }
         }
-        acc.into_iter()
+        if !batch.is_empty() {
+            sink.write(&batch)?;
+            stats.nr_predicted += batch.len();
+            stats.nr_batches += 1;
+        }
+        sink.finish()?;
+        Ok(stats)
     }
 
-    fn path(
+    /// Summarizes the delay distributions along a path for a flow of a particular `size` going
+    /// from `src` to `dst`. For every hop, the summary reports the bucket used, its sample count,
+    /// and its p50/p95/p99; it also reports the same quantiles for the composed end-to-end delay.
+    /// The result implements `Display` and is meant for quick sanity checks in a terminal.
+    pub fn summarize_path<RNG>(
         &self,
-        src: NodeId,
-        dst: NodeId,
-        choose: impl FnMut(&[NodeId]) -> Option<&NodeId>,
-    ) -> Path<C> {
+        size: Bytes,
+        (src, dst): (NodeId, NodeId),
+        mut rng: RNG,
+    ) -> Option<PathSummary>
+    where
+        RNG: Rng,
+    {
+        let channels = self.channels_between(src, dst, &mut rng);
+        if channels.is_empty() {
+            return None;
+        }
+        let nr_pkts = self.packets.nr_pkts(size) as f64;
+        let to_delay = |pktnorm_delay: f64| Nanosecs::new((nr_pkts * pktnorm_delay) as u64);
+        let (transmission, propagation) =
+            metrics::ideal_fct_components(size, &channels, &self.packets);
+        let rack = self.topology.rack_of(src);
+        let hops = channels
+            .iter()
+            .map(|&chan| {
+                let bucket = chan.dists.group_or_all(rack).bucket_for_size(size);
+                HopSummary {
+                    src: chan.src,
+                    dst: chan.dst,
+                    bucket: bucket.map(|(bkt, _)| bkt.clone()),
+                    nr_samples: bucket.map_or(0, |(_, dist)| dist.len()),
+                    quantiles: bucket.and_then(|(_, dist)| {
+                        Quantiles::from_fn(|q| dist.quantile(q).map(to_delay))
+                    }),
+                }
+            })
+            .collect();
+        let samples = (0..NR_SUMMARY_SAMPLES)
+            .map(|_| self.sample_path(&channels, src, size, DistChoice::All, &mut rng))
+            .collect::<Option<Vec<_>>>();
+        let end_to_end = samples
+            .and_then(|samples| Quantiles::from_fn(|q| edist::quantile(&samples, q).map(to_delay)));
+        Some(PathSummary {
+            src,
+            dst,
+            size,
+            propagation,
+            transmission,
+            hops,
+            end_to_end,
+            nr_samples: NR_SUMMARY_SAMPLES,
+        })
+    }
+
+    /// Returns the `q`-th quantile of the delay predicted for a flow of size `size` on edge
+    /// `eidx`, or `None` if `q` isn't in [0, 1], the edge doesn't exist, or it has no data for that
+    /// size.
+    pub fn edge_quantile(&self, eidx: EdgeIndex, size: Bytes, q: f64) -> Option<Nanosecs> {
+        if !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+        let chan = self.topology.graph.edge_weight(eidx)?;
+        chan.dists
+            .for_size(size)?
+            .quantile(q)
+            .map(|pktnorm_delay| self.to_delay(size, pktnorm_delay))
+    }
+
+    /// Returns summaries of every channel's delay distributions, in ascending order of
+    /// `(src, dst)`. The order doesn't depend on how the network was built, so results can be
+    /// joined with external data keyed by link endpoints.
+    pub fn channels(&self) -> impl Iterator<Item = ChannelSummary> + '_ {
+        let mut edges = self.topology.graph.edge_indices().collect::<Vec<_>>();
+        edges.sort_by_key(|&e| {
+            let chan = &self.topology.graph[e];
+            (chan.src, chan.dst)
+        });
+        edges.into_iter().map(|e| self.summarize_channel(e))
+    }
+
+    /// Returns a summary of the delay distributions of the channel from `src` to `dst`, or `None`
+    /// if there is no such channel.
+    pub fn channel(&self, src: NodeId, dst: NodeId) -> Option<ChannelSummary> {
+        let e = self.topology.channel_between(src, dst)?;
+        Some(self.summarize_channel(e))
+    }
+
+    pub(crate) fn summarize_channel(&self, eidx: EdgeIndex) -> ChannelSummary {
+        let chan = &self.topology.graph[eidx];
+        ChannelSummary {
+            src: chan.src,
+            dst: chan.dst,
+            bandwidth: chan.bandwidth,
+            delay: chan.delay,
+            loss_rate: chan.loss_rate,
+            buckets: BucketSummary::from_dists(&chan.dists),
+        }
+    }
+
+    /// Returns the consistency violations detected so far. Query-time violations are only counted
+    /// if consistency checks were enabled in the [`SimOpts`] used to build this network.
+    pub fn consistency(&self) -> ConsistencyReport {
+        ConsistencyReport {
+            fill_violations: self.fill_violations.clone(),
+            query_violations: self.query_violations.get(),
+        }
+    }
+
+    /// Returns statistics about how many link simulations were shared between cluster
+    /// representatives with identical simulations.
+    pub fn dedup_stats(&self) -> DedupStats {
+        self.dedup
+    }
+
+    /// Returns the edges whose link simulations failed and were resolved by the
+    /// [failure policy](fallback::FailurePolicy) or timed out, sorted by edge index. Their delay
+    /// distributions are less accurate or empty.
+    pub fn link_failures(&self) -> &[LinkFailure] {
+        &self.failures
+    }
+
+    /// Returns the edges whose link simulations [timed out](SimOpts::timeout), sorted, e.g., to
+    /// simulate them again with [`SimNetwork::into_delays_reusing`].
+    pub fn timed_out_edges(&self) -> Vec<EdgeIndex> {
+        self.failures
+            .iter()
+            .filter(|f| f.resolution == Resolution::TimedOut)
+            .map(|f| f.edge)
+            .collect()
+    }
+
+    /// Returns the FCT records retained from link simulations, or `None` if records were
+    /// discarded. See [`Retention`](crate::records::Retention).
+    pub fn records(&self) -> Option<&FctRecords> {
+        self.records.as_ref()
+    }
+
+    /// Rebuilds every delay distribution from the [retained](crate::records::Retention) FCT
+    /// records using new bucketing parameters, without re-simulating. Distributions by offered
+    /// load and by [time bucket](crate::opts::SimOpts::time_buckets) are rebuilt too. Returns an
+    /// error if records weren't retained, or if the network has [per-source-rack
+    /// distributions](crate::opts::SimOpts::source_racks), which records don't carry enough
+    /// information to rebuild. Per-client distributions are left unchanged, so networks with
+    /// them can't be rebucketed by offered load either.
+    pub fn rebucket(&mut self, opts: BucketOpts) -> Result<(), SimNetworkError> {
+        let records = self
+            .records
+            .as_ref()
+            .ok_or(SimNetworkError::RecordsNotRetained)?;
+        let channels = || self.topology.graph.edge_weights();
+        if channels().any(|chan| chan.dists.groups().next().is_some()) {
+            return Err(SimNetworkError::RebucketSourceRacks);
+        }
+        if opts.strategy != BucketStrategy::Size
+            && channels().any(|chan| !chan.client_dists.is_empty())
+        {
+            return Err(SimNetworkError::RebucketClients);
+        }
+        // Every time bucket has the run's length
+        let time_bucket = channels().find_map(|chan| {
+            let (window, _) = chan.time_dists.first()?;
+            Some(window.end - window.start)
+        });
+        let filled = records
+            .simulated_edges()
+            .into_par_iter()
+            .map(|eidx| {
+                let data = records.get(eidx)?.unwrap_or_default();
+                let mut dists = EDistBuckets::new_empty();
+                dists.fill(
+                    &data,
+                    |rec| rec.size,
+                    |rec| rec.pktnorm_delay_with(&self.packets),
+                    opts,
+                )?;
+                dists.set_loads(EDistBuckets::by_load(
+                    &data,
+                    self.topology.graph[eidx].bandwidth,
+                    (
+                        |rec: FctRecord| rec.size,
+                        |rec: FctRecord| rec.start,
+                        |rec: FctRecord| rec.pktnorm_delay_with(&self.packets),
+                    ),
+                    opts,
+                )?);
+                let time_dists = match time_bucket {
+                    Some(length) if !data.is_empty() => {
+                        time_dists(&data, length, opts, &self.packets)?
+                    }
+                    _ => Vec::new(),
+                };
+                Ok((eidx, (dists, time_dists)))
+            })
+            .collect::<Result<FxHashMap<_, _>, SimNetworkError>>()?;
+        for (member, representative) in records.assignments() {
+            let (dists, time_dists) = &filled[&representative];
+            let chan = &mut self.topology.graph[member];
+            chan.dists = dists.clone();
+            chan.time_dists = time_dists.clone();
+        }
+        Ok(())
+    }
+
+    /// Returns the absolute time flow start times are relative to. See
+    /// [`SimNetwork::set_origin`].
+    pub fn origin(&self) -> Nanosecs {
+        self.origin
+    }
+
+    /// Returns the [packet profile](PacketProfile) flows are split into. See
+    /// [`Network::with_packet_profile`].
+    pub fn packet_profile(&self) -> PacketProfile {
+        self.packets
+    }
+
+    /// Returns the [ECMP model](EcmpModel) flows are assigned to paths with. See
+    /// [`Network::with_ecmp`].
+    pub fn ecmp(&self) -> &EcmpModel {
+        &self.ecmp
+    }
+
+    /// Returns the seed of the run that built the network, if set. See [`SimOpts::seed`].
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Returns an RNG for predictions derived from the seed of the run that built the network,
+    /// or from zero if unset. Networks built from the same inputs with the same seed yield the
+    /// same predictions with RNGs from this method.
+    pub fn rng(&self) -> StdRng {
+        RngStream::Prediction.rng(self.seed.unwrap_or_default())
+    }
+
+    /// Sets the correlation between the delays of consecutive hops, which must be in [0, 1].
+    ///
+    /// Consecutive hops share a switch, and hops through the same congested switch tend to see
+    /// high delays together. When sampling a path, each hop reuses the random variate of the
+    /// previous hop with probability `correlation`, and draws a fresh one otherwise. Zero (the
+    /// default) samples hops independently, and one samples them comonotonically.
+    ///
+    /// Fails, leaving the network unchanged, if `correlation` isn't in [0, 1].
+    pub fn set_hop_correlation(&mut self, correlation: f64) -> Result<(), HopCorrelationError> {
+        if !(0.0..=1.0).contains(&correlation) {
+            return Err(HopCorrelationError::InvalidCorrelation(correlation));
+        }
+        self.hop_correlation = correlation;
+        Ok(())
+    }
+
+    /// Sets the probabilities with which paths between host pairs are sampled, e.g., those of the
+    /// trace the network was built from (see [`SimNetwork::path_affinity`]). Paths between host
+    /// pairs without recorded probabilities are sampled as usual. `None` (the default) samples
+    /// every path as usual. Fails, leaving the network unchanged, if a path doesn't lead along
+    /// the network's edges from its source to its destination, e.g., because it was recorded on
+    /// another topology.
+    pub fn set_path_affinity(
+        &mut self,
+        affinity: Option<PathAffinity>,
+    ) -> Result<(), AffinityError> {
+        if let Some(affinity) = &affinity {
+            let graph = &self.topology.graph;
+            affinity.validate(|e| graph.edge_weight(e).map(|chan| (chan.src, chan.dst)))?;
+        }
+        self.affinity = affinity;
+        Ok(())
+    }
+
+    /// Returns the path probabilities set with [`DelayNetwork::set_path_affinity`], if any.
+    pub fn path_affinity(&self) -> Option<&PathAffinity> {
+        self.affinity.as_ref()
+    }
+
+    /// Returns a fingerprint of the network's topology, equal to that of the [`SimNetwork`] it was
+    /// built from. See [`SimNetwork::topology_fingerprint`].
+    pub fn topology_fingerprint(&self) -> u64 {
+        self.topology.fingerprint()
+    }
+
+    /// Returns a fingerprint of everything query outputs depend on: the links, their delay
+    /// distributions, and the sampling parameters. Equal networks have equal fingerprints within
+    /// a version of this crate, on any platform.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+        for chan in self.topology.graph.edge_weights() {
+            chan.src.hash(&mut hasher);
+            chan.dst.hash(&mut hasher);
+            chan.bandwidth.hash(&mut hasher);
+            chan.delay.hash(&mut hasher);
+            chan.loss_rate.to_bits().hash(&mut hasher);
+            chan.dists.hash_into(&mut hasher);
+            for (client, dists) in chan.client_dists.iter().sorted_by_key(|&(&c, _)| c) {
+                client.hash(&mut hasher);
+                dists.hash_into(&mut hasher);
+            }
+            for (window, dists) in &chan.time_dists {
+                window.hash(&mut hasher);
+                dists.hash_into(&mut hasher);
+            }
+        }
+        self.hop_correlation.to_bits().hash(&mut hasher);
+        if let Some(affinity) = &self.affinity {
+            for pair in affinity.pairs() {
+                (pair.src, pair.dst).hash(&mut hasher);
+                for path in &pair.paths {
+                    path.edges.hash(&mut hasher);
+                    path.probability.to_bits().hash(&mut hasher);
+                }
+            }
+        }
+        // The rack shortcut draws from the RNG differently than the routing table
+        self.racks.is_some().hash(&mut hasher);
+        self.origin.hash(&mut hasher);
+        self.packets.hash(&mut hasher);
+        self.ecmp.hash_into(&mut hasher);
+        self.seed.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Returns a [`QueryLogger`] whose queries draw from RNG streams derived from `seed` and are
+    /// logged for [replay](Self::replay_queries).
+    pub fn logged_queries(&self, seed: u64) -> QueryLogger<'_, R> {
+        QueryLogger::new(self, seed)
+    }
+
+    /// Replays the queries in `log`, returning their outputs in order. Fails if the log was made
+    /// against a different network or any output differs from the logged one.
+    pub fn replay_queries(&self, log: &QueryLog) -> Result<Vec<QueryOutput>, QueryLogError> {
+        let actual = self.fingerprint();
+        if log.fingerprint() != actual {
+            return Err(QueryLogError::NetworkMismatch {
+                logged: log.fingerprint(),
+                actual,
+            });
+        }
+        log.queries()
+            .iter()
+            .map(|logged| {
+                let output = querylog::run(self, log.seed(), logged.stream, logged.query);
+                if output != logged.output {
+                    return Err(QueryLogError::Diverged {
+                        stream: logged.stream,
+                    });
+                }
+                Ok(output)
+            })
+            .collect()
+    }
+
+    /// Returns the IDs of all clients with delay distributions, in ascending order. This is empty
+    /// unless the network was built with [`SimNetwork::into_delays_by_client`].
+    pub fn clients(&self) -> Vec<ClientId> {
+        self.topology
+            .graph
+            .edge_weights()
+            .flat_map(|chan| chan.client_dists.keys().copied())
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Returns a network whose delay distributions only account for the flows of `client`, or
+    /// `None` if the client has no distributions. All queries on the returned network are
+    /// filtered by the client. Links that none of the client's flows traverse keep the
+    /// distributions of all flows.
+    pub fn for_client(&self, client: ClientId) -> Option<DelayNetwork<R>>
+    where
+        R: Clone,
+    {
+        let mut network = self.clone();
+        let mut found = false;
+        for chan in network.topology.graph.edge_weights_mut() {
+            if let Some(dists) = chan.client_dists.remove(&client) {
+                chan.dists = dists;
+                found = true;
+            }
+            chan.client_dists.clear();
+        }
+        found.then_some(network)
+    }
+
+    // Like `predict_breakdown`, but on the path `flow` is assigned to
+    fn flow_breakdown<RNG>(&self, flow: &Flow, rng: &mut RNG) -> Option<LatencyBreakdown>
+    where
+        RNG: Rng,
+    {
+        let channels = self
+            .edge_indices_between(flow.src, flow.dst, self.ecmp.flow_hops(flow, self.seed))
+            .map(|e| &self.topology.graph[e])
+            .collect::<Vec<_>>();
+        if channels.is_empty() {
+            return None;
+        }
+        let (transmission, propagation) =
+            metrics::ideal_fct_components(flow.size, &channels, &self.packets);
+        let queueing = self.sample_delay(
+            &channels,
+            flow.size,
+            (flow.src, flow.dst),
+            DistChoice::All,
+            rng,
+        )?;
+        Some(LatencyBreakdown {
+            propagation,
+            transmission,
+            queueing,
+        })
+    }
+
+    // The sizes of the flowlets a flow is split into, or `None` if it isn't split
+    fn flowlet_sizes(&self, size: Bytes, (src, dst): (NodeId, NodeId)) -> Option<Vec<Bytes>> {
+        let model = self.ecmp.flowlets.as_ref()?;
+        let (rtt, bandwidth) = flowlets::rtt_and_bandwidth(self, src, dst);
+        let sizes = model.split(size, rtt, bandwidth);
+        (sizes.len() > 1).then_some(sizes)
+    }
+
+    // Samples the delay of a flow of `size` bytes from `src` to `dst` from the distributions
+    // `choice` selects. Flows which are split into flowlets sum their flowlets' delays, each on a
+    // path of its own, and other flows are sampled on `channels`.
+    fn sample_delay<RNG>(
+        &self,
+        channels: &[&EDistChannel],
+        size: Bytes,
+        (src, dst): (NodeId, NodeId),
+        choice: DistChoice,
+        rng: &mut RNG,
+    ) -> Option<Nanosecs>
+    where
+        RNG: Rng,
+    {
+        match self.flowlet_sizes(size, (src, dst)) {
+            Some(sizes) => self.sample_flowlets(&sizes, (src, dst), choice, rng),
+            None => self
+                .sample_path(channels, src, size, choice, rng)
+                .map(|pktnorm_delay| self.to_delay(size, pktnorm_delay)),
+        }
+    }
+
+    // Sums the delays of flowlets with the given sizes, each sampled on a path of its own
+    fn sample_flowlets<RNG>(
+        &self,
+        sizes: &[Bytes],
+        (src, dst): (NodeId, NodeId),
+        choice: DistChoice,
+        rng: &mut RNG,
+    ) -> Option<Nanosecs>
+    where
+        RNG: Rng,
+    {
+        sizes
+            .iter()
+            .map(|&size| {
+                let channels = self.channels_between(src, dst, rng);
+                if channels.is_empty() {
+                    return None;
+                }
+                self.sample_path(&channels, src, size, choice, rng)
+                    .map(|pktnorm_delay| self.to_delay(size, pktnorm_delay))
+            })
+            .sum()
+    }
+
+    /// Chooses a path from `src` to `dst` and returns its channels, which is empty if there is no
+    /// path. Paths within a rack are looked up directly when possible.
+    fn channels_between<RNG>(&self, src: NodeId, dst: NodeId, rng: &mut RNG) -> Vec<&EDistChannel>
+    where
+        RNG: Rng,
+    {
+        if let Some(path) = self
+            .affinity
+            .as_ref()
+            .and_then(|affinity| affinity.sample(src, dst, rng))
+        {
+            return path.iter().map(|&e| &self.topology.graph[e]).collect();
+        }
+        if !self.ecmp.is_uniform() {
+            // Skewed splits are sampled by hashing a random flow, bypassing the rack index
+            return self
+                .edge_indices_between(src, dst, self.ecmp.hops_from(src, rng.gen()))
+                .map(|e| &self.topology.graph[e])
+                .collect();
+        }
+        if let Some(path) = self.racks.as_ref().and_then(|r| r.sample(src, dst, rng)) {
+            return path.iter().map(|&e| &self.topology.graph[e]).collect();
+        }
+        self.edge_indices_between(src, dst, |choices| choices.choose(rng))
+            .map(|e| &self.topology.graph[e])
+            .collect()
+    }
+
+    /// Samples a packet-normalized delay from each channel's distributions selected by `choice`
+    /// and returns their sum, or `None` if some channel has no distribution for `size`. Channels
+    /// with distributions for the rack of `src` sample from those.
+    fn sample_path<RNG>(
+        &self,
+        channels: &[&EDistChannel],
+        src: NodeId,
+        size: Bytes,
+        choice: DistChoice,
+        rng: &mut RNG,
+    ) -> Option<f64>
+    where
+        RNG: Rng,
+    {
+        let rack = self.topology.rack_of(src);
+        let dists = channels
+            .iter()
+            .map(|&chan| choice.select(chan, rack).for_size(size))
+            .collect::<Option<Vec<_>>>()?;
+        Some(self.sample_dists(&dists, rng))
+    }
+
+    /// Samples a packet-normalized delay from each distribution along a path and returns their
+    /// sum, honoring the hop correlation.
+    fn sample_dists<RNG>(&self, dists: &[&EDist], rng: &mut RNG) -> f64
+    where
+        RNG: Rng,
+    {
+        if self.hop_correlation == 0.0 {
+            return dists.iter().map(|dist| dist.sample(rng)).sum();
+        }
+        let mut u = rng.gen::<f64>();
+        dists
+            .iter()
+            .enumerate()
+            .map(|(i, dist)| {
+                if i > 0 && !rng.gen_bool(self.hop_correlation) {
+                    u = rng.gen();
+                }
+                dist.inverse_cdf(u)
+            })
+            .sum()
+    }
+
+    /// Scales a packet-normalized delay to a flow of `size` bytes. Negative or NaN delays would
+    /// put the predicted FCT below ideal, so they are clamped to zero.
+    fn to_delay(&self, size: Bytes, pktnorm_delay: f64) -> Nanosecs {
+        let pktnorm_delay = if pktnorm_delay >= 0.0 {
+            pktnorm_delay
+        } else {
+            if self.consistency.is_enabled() {
+                self.query_violations.incr();
+            }
+            0.0
+        };
+        let nr_pkts = self.packets.nr_pkts(size) as f64;
+        Nanosecs::new((nr_pkts * pktnorm_delay) as u64)
+    }
+
+    delegate::delegate! {
+        to self.topology {
+            /// Returns an iterator over the [nodes](Node) in the network.
+            pub fn nodes(&self) -> impl Iterator<Item = &Node>;
+        }
+
+        to self.topology.links {
+            /// Returns an iterator over the [links](Link) in the network.
+            #[call(iter)]
+            pub fn links(&self) -> impl Iterator<Item = &Link>;
+        }
+    }
+}
+
+impl<R> TraversableNetwork<EDistChannel, R> for DelayNetwork<R>
+where
+    R: RoutingAlgo,
+{
+    fn topology(&self) -> &Topology<EDistChannel> {
+        &self.topology
+    }
+
+    fn routes(&self) -> &R {
+        &self.routes
+    }
+}
+
+pub(crate) trait TraversableNetwork<C: Clone + Channel, R: RoutingAlgo> {
+    fn topology(&self) -> &Topology<C>;
+
+    fn routes(&self) -> &R;
+
+    #[allow(dead_code)]
+    fn nr_edges(&self) -> usize {
+        self.topology().nr_edges()
+    }
+
+    fn edge_indices_between(
+        &self,
+        src: NodeId,
+        dst: NodeId,
+        mut choose: impl FnMut(&[NodeId]) -> Option<&NodeId>,
+    ) -> std::vec::IntoIter<EdgeIndex> {
+        let mut acc = Vec::new();
+        let mut cur = src;
+        while cur != dst {
+            let next_hop_choices = match self.routes().next_hops(cur, dst) {
+                Some(hops) => hops,
+                None => break,
+            };
+            match choose(&next_hop_choices) {
+                Some(&next_hop) => {
+                    // These indices are all guaranteed to exist because we have a valid topology
+                    let i = *self.topology().idx_of(&cur).unwrap();
+                    let j = *self.topology().idx_of(&next_hop).unwrap();
+                    let e = self.topology().find_edge(i, j).unwrap();
+                    acc.push(e);
+                    cur = next_hop;
+                }
+                // There is no choice of next hop, and therefore no path
+                None => return Vec::new().into_iter(),
+            }
+        }
+        acc.into_iter()
+    }
+
+    fn path(
+        &self,
+        src: NodeId,
+        dst: NodeId,
+        choose: impl FnMut(&[NodeId]) -> Option<&NodeId>,
+    ) -> Path<'_, C> {
         let channels = self
             .edge_indices_between(src, dst, choose)
             .map(|eidx| (eidx, &self.topology().graph[eidx]))
             .collect();
         Path::new(channels)
     }
-}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use anyhow::Context;
+
+    use crate::{
+        constants::SZ_ACK,
+        edist::{BucketOpts, BucketStrategy},
+        records::Retention,
+        testing,
+    };
+
+    use super::*;
+
+    fn find_edge<C: Clone>(topo: &Topology<C>, src: NodeId, dst: NodeId) -> Option<EdgeIndex> {
+        topo.idx_of(&src)
+            .and_then(|&a| topo.idx_of(&dst).map(|&b| (a, b)))
+            .and_then(|(a, b)| topo.graph.find_edge(a, b))
+    }
+
+    // This test creates an eight-node topology and sends some flows with the
+    // same source and destination across racks. All flows will traverse
+    // exactly one ECMP group in the upwards direction. While we don't know
+    // exactly how many flows should traverse each link in the group, we can
+    // check that the final counts are close to equal. If a different hashing
+    // algorithm is used, the exact counts will change, and this test will need
+    // to be updated to use the new snapshot.
+    #[test]
+    fn ecmp_replication_works() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let network = Network::new(&nodes, &links).context("failed to create topology")?;
+        let flows = (0..100)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(3),
+                size: Bytes::ZERO,
+                start: Nanosecs::ZERO,
+            })
+            .collect::<Vec<_>>();
+        let network = network.into_simulations(flows);
+
+        // The ECMP group contains edges (4, 6) and (4, 7)
+        let e1 = find_edge(&network.topology, NodeId::new(4), NodeId::new(6)).unwrap();
+        let e2 = find_edge(&network.topology, NodeId::new(4), NodeId::new(7)).unwrap();
+
+        // Flow counts for the links should be close to each other
+        insta::assert_yaml_snapshot!((
+            network.topology.graph[e1].flows.len(),
+            network.topology.graph[e2].flows.len(),
+        ), @r###"
+        ---
+        - 52
+        - 48
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn seeds_change_path_assignments() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let network = Network::new(&nodes, &links)?;
+        let flows = (0..100)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(3),
+                size: Bytes::ZERO,
+                start: Nanosecs::ZERO,
+            })
+            .collect::<Vec<_>>();
+        let e = find_edge(&network.topology, NodeId::new(4), NodeId::new(6)).unwrap();
+        let assigned = |seed| {
+            let sims = network
+                .clone()
+                .with_seed(seed)
+                .into_simulations(flows.clone());
+            sims.topology.graph[e].flows.clone()
+        };
+        assert_eq!(assigned(1), assigned(1));
+        assert_ne!(assigned(1), assigned(2));
+        Ok(())
+    }
+
+    #[test]
+    fn tracing_rounds_preserve_start_order() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let network = Network::new(&nodes, &links)?;
+        let mut rng = StdRng::seed_from_u64(0);
+        let flows = (0..200)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 4),
+                dst: NodeId::new((i + 1 + i / 4 % 3) % 4),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(rng.gen_range(0..10_000)),
+            })
+            .collect::<Vec<_>>();
+        let arena = FlowArena::new(flows);
+        let whole = network.trace_flows(&arena, usize::MAX);
+        let rounds = network.trace_flows(&arena, 7);
+        assert_eq!(
+            whole.graph.edge_weights().collect::<Vec<_>>(),
+            rounds.graph.edge_weights().collect::<Vec<_>>()
+        );
+        for chan in rounds.graph.edge_weights() {
+            let flows = arena.collect(&chan.flows);
+            assert!(flows
+                .iter()
+                .tuple_windows()
+                .all(|(a, b)| a.start <= b.start));
+            assert!(chan.flow_ids().eq(flows.iter().map(|f| f.id)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tracing_streams_flows_in_start_order() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let network = Network::new(&nodes, &links)?;
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut starts = (0..200)
+            .map(|_| rng.gen_range(0..10_000))
+            .collect::<Vec<_>>();
+        starts.sort();
+        let flows = starts
+            .into_iter()
+            .enumerate()
+            .map(|(i, start)| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 4),
+                dst: NodeId::new((i + 1 + i / 4 % 3) % 4),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(start),
+            })
+            .collect::<Vec<_>>();
+        let arena = FlowArena::new(flows);
+        let whole = network.trace_flows(&arena, usize::MAX);
+        let rounds = network.trace_flows(&arena, 7);
+        assert_eq!(
+            whole.graph.edge_weights().collect::<Vec<_>>(),
+            rounds.graph.edge_weights().collect::<Vec<_>>()
+        );
+        for chan in rounds.graph.edge_weights() {
+            assert!(chan.flows.iter().tuple_windows().all(|(a, b)| a < b));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn removing_hosts_changes_only_their_links() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let network = Network::new(&nodes, &links).context("failed to create topology")?;
+        let flows = (0..100)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 2),
+                dst: NodeId::new(2 + i % 2),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let before = network.into_simulations(flows);
+        let after = before.without_hosts(&[NodeId::new(0)]);
+        assert_eq!(after.nr_flows(), 50);
+        assert!(after.flows().all(|f| f.src != NodeId::new(0)));
+
+        let changes = before.load_changes(&after, 0.0);
+        let e = find_edge(&before.topology, NodeId::new(0), NodeId::new(4)).unwrap();
+        let change = changes.iter().find(|c| c.edge == e).unwrap();
+        assert!(change.before > 0.0);
+        assert_eq!(change.after, 0.0);
+        // Host 1's uplink carries the same flows before and after
+        let e = find_edge(&before.topology, NodeId::new(1), NodeId::new(4)).unwrap();
+        assert!(changes.iter().all(|c| c.edge != e));
+        Ok(())
+    }
+
+    #[test]
+    fn scaling_flows_replicates_and_thins() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let network = Network::new(&nodes, &links)?;
+        let flows = (0..100)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 2),
+                dst: NodeId::new(2 + i % 2),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let sims = network.into_simulations(flows);
+        let e = find_edge(&sims.topology, NodeId::new(0), NodeId::new(4)).unwrap();
+
+        let doubled = sims.scale_load(2.0, 0);
+        assert_eq!(doubled.nr_flows(), 200);
+        assert_eq!(doubled.edge(e).unwrap().nr_flows(), 100);
+        assert!(doubled.load_of(e).unwrap() > 1.9 * sims.load_of(e).unwrap());
+        let starts = doubled.flows_on(e).unwrap();
+        assert!(starts.windows(2).all(|w| w[0].start <= w[1].start));
+        assert_eq!(sims.scale_load(1.0, 0).flows, sims.flows);
+        assert_eq!(sims.scale_load(0.0, 0).nr_flows(), 0);
+
+        let grown = sims.scale_flows(|f| if f.src == NodeId::new(0) { 1.3 } else { 1.0 }, 7);
+        let other = find_edge(&sims.topology, NodeId::new(1), NodeId::new(4)).unwrap();
+        assert_eq!(grown.edge(other).unwrap().nr_flows(), 50);
+        assert!(grown.edge(e).unwrap().nr_flows() > 50);
+        assert_eq!(
+            grown.flows_on(e),
+            sims.scale_flows(|f| if f.src == NodeId::new(0) { 1.3 } else { 1.0 }, 7)
+                .flows_on(e)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn records_are_retained() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let mut sims = Network::new(&nodes, &links)?.into_simulations(flows);
+        let e1 = find_edge(&sims.topology, NodeId::new(0), NodeId::new(2)).unwrap();
+        let e2 = find_edge(&sims.topology, NodeId::new(2), NodeId::new(1)).unwrap();
+        let rest = sims.edge_indices().filter(|&e| e != e1 && e != e2);
+        let mut clusters = vec![Cluster::new(e1, [e1, e2].into_iter().collect())];
+        clusters.extend(rest.map(|e| Cluster::new(e, [e].into_iter().collect())));
+        sims.set_clusters(clusters)?;
+        let opts = |retention| {
+            SimOpts::builder()
+                .link_sim(IdDelaySim)
+                .retention(retention)
+                .build()
+        };
+
+        let delays = sims.clone().into_delays(opts(Retention::Discard))?;
+        assert!(delays.records().is_none());
+
+        let delays = sims.clone().into_delays(opts(Retention::Memory))?;
+        let records = delays.records().unwrap();
+        assert_eq!(records.simulated_edges(), vec![e1]);
+        assert_eq!(records.representative_of(e2), Some(e1));
+        assert_eq!(records.get(e2)?.unwrap().len(), 10);
+        let mut csv = Vec::new();
+        records.write_csv(&mut csv)?;
+        assert_eq!(String::from_utf8(csv)?.lines().count(), 11);
+
+        // Record files of earlier runs are removed, other files are left alone
+        let dir = tempfile::tempdir()?;
+        let records_dir = dir.path().join("records");
+        std::fs::create_dir(&records_dir)?;
+        std::fs::write(records_dir.join("edge-999.msgpack"), b"stale")?;
+        std::fs::write(records_dir.join("notes.txt"), b"keep")?;
+        let spilled = sims.into_delays(opts(Retention::Disk(records_dir.clone())))?;
+        assert!(!records_dir.join("edge-999.msgpack").exists());
+        assert!(records_dir.join("notes.txt").exists());
+        let on_disk = spilled.records().unwrap().get(e2)?.unwrap();
+        let in_memory = records.get(e2)?.unwrap();
+        assert_eq!(
+            on_disk.iter().map(|r| (r.id, r.fct)).collect::<Vec<_>>(),
+            in_memory.iter().map(|r| (r.id, r.fct)).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn delays_can_be_built_from_records() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows);
+        let opts = |retention| {
+            SimOpts::builder()
+                .link_sim(IdDelaySim)
+                .retention(retention)
+                .build()
+        };
+        let simulated = sims.clone().into_delays(opts(Retention::Memory))?;
+        let retained = simulated.records().unwrap();
+        let records = retained
+            .simulated_edges()
+            .into_iter()
+            .map(|e| Ok((e, retained.get(e)?.unwrap())))
+            .collect::<Result<HashMap<_, _>, RecordsError>>()?;
+
+        let ingested = sims
+            .clone()
+            .into_delays_from_records(records, opts(Retention::Discard))?;
+        for e in sims.edge_indices() {
+            assert_eq!(
+                serde_json::to_value(&ingested.topology.graph[e].dists)?,
+                serde_json::to_value(&simulated.topology.graph[e].dists)?
+            );
+        }
+        assert_eq!(ingested.dedup, DedupStats::default());
+
+        let unknown = EdgeIndex::new(sims.topology.nr_edges());
+        assert!(matches!(
+            sims.clone().into_delays_from_records(
+                [(unknown, Vec::new())].into_iter().collect(),
+                opts(Retention::Discard)
+            ),
+            Err(SimNetworkError::UnknownEdge(e)) if e == unknown
+        ));
+
+        let mut sims = sims;
+        let (e0, e1) = (EdgeIndex::new(0), EdgeIndex::new(1));
+        sims.set_clusters(vec![
+            Cluster::new(e0, [e0, e1].into_iter().collect()),
+            Cluster::new(EdgeIndex::new(2), [EdgeIndex::new(2)].into_iter().collect()),
+            Cluster::new(EdgeIndex::new(3), [EdgeIndex::new(3)].into_iter().collect()),
+        ])?;
+        assert!(matches!(
+            sims.into_delays_from_records(
+                [(e1, Vec::new())].into_iter().collect(),
+                opts(Retention::Discard)
+            ),
+            Err(SimNetworkError::NotRepresentative(e)) if e == e1
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn rebucketing_uses_retained_records() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..100)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1000 * (i as u64 + 1)),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows);
+        let e = find_edge(&sims.topology, NodeId::new(0), NodeId::new(2)).unwrap();
+        let opts = |retention| {
+            SimOpts::builder()
+                .link_sim(testing::FixedDelaySim {
+                    delay_per_pkt: Nanosecs::new(100),
+                })
+                .retention(retention)
+                .build()
+        };
+        let nr_buckets =
+            |delays: &DelayNetwork| delays.topology.graph[e].dists.bucket_ranges().count();
+
+        let mut delays = sims.clone().into_delays(opts(Retention::Discard))?;
+        assert!(matches!(
+            delays.rebucket(BucketOpts::default()),
+            Err(SimNetworkError::RecordsNotRetained)
+        ));
+
+        let mut delays = sims.clone().into_delays(opts(Retention::Memory))?;
+        assert_eq!(nr_buckets(&delays), 1);
+        delays.rebucket(BucketOpts::new(2, 10))?;
+        assert!(nr_buckets(&delays) > 1);
+
+        // Time buckets are rebuilt with the run's length
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .retention(Retention::Memory)
+            .time_buckets(Nanosecs::new(50_000))
+            .build();
+        let mut delays = sims.into_delays(opts)?;
+        let time_buckets = |delays: &DelayNetwork| {
+            let time_dists = &delays.topology.graph[e].time_dists;
+            let windows = time_dists
+                .iter()
+                .map(|(w, _)| w.clone())
+                .collect::<Vec<_>>();
+            let nr_buckets = time_dists[0].1.bucket_ranges().count();
+            (windows, nr_buckets)
+        };
+        let (windows, nr_before) = time_buckets(&delays);
+        assert_eq!(windows.len(), 2);
+        delays.rebucket(BucketOpts::new(2, 10))?;
+        let (rebuilt, nr_after) = time_buckets(&delays);
+        assert_eq!(rebuilt, windows);
+        assert!(nr_after > nr_before);
+
+        // Records don't identify source racks
+        let mut groups = FxHashMap::default();
+        groups.insert(NodeId::new(0), EDistBuckets::new_empty());
+        delays.topology.graph[e].dists.set_groups(groups);
+        assert!(matches!(
+            delays.rebucket(BucketOpts::default()),
+            Err(SimNetworkError::RebucketSourceRacks)
+        ));
+        Ok(())
+    }
+
+    /// Reports every flow as finishing 100ns before its ideal FCT.
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    struct BelowIdealSim;
+
+    impl LinkSim for BelowIdealSim {
+        fn name(&self) -> String {
+            "below-ideal".into()
+        }
+
+        fn simulate(&self, spec: LinkSimSpec) -> crate::linksim::LinkSimResult {
+            Ok(spec
+                .flows
+                .iter()
+                .map(|f| FctRecord {
+                    id: f.id,
+                    size: f.size,
+                    start: f.start,
+                    fct: Nanosecs::new(1000),
+                    ideal: Nanosecs::new(1100),
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn below_ideal_fcts_are_counted() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows);
+        let path = (NodeId::new(0), NodeId::new(1));
+        let e = find_edge(&sims.topology, NodeId::new(0), NodeId::new(2)).unwrap();
+
+        let opts = |consistency| {
+            SimOpts::builder()
+                .link_sim(BelowIdealSim)
+                .consistency(consistency)
+                .build()
+        };
+        let delays = sims.clone().into_delays(opts(ConsistencyCheck::Off))?;
+        assert!(delays.consistency().is_clean());
+
+        let delays = sims.clone().into_delays(opts(ConsistencyCheck::Clamp))?;
+        let report = delays.consistency();
+        assert!(report.fill_violations.contains(&(e, 10)));
+        assert_eq!(report.nr_fill_violations(), 20);
+        let delay = delays.predict(Bytes::new(1000), path, rand::thread_rng());
+        assert_eq!(delay, Some(Nanosecs::ZERO));
+        assert_eq!(delays.to_delay(Bytes::new(1000), -1.0), Nanosecs::ZERO);
+        assert_eq!(delays.to_delay(Bytes::new(1000), f64::NAN), Nanosecs::ZERO);
+        assert_eq!(delays.consistency().query_violations, 2);
+
+        let res = sims.into_delays(opts(ConsistencyCheck::Deny));
+        assert!(matches!(res, Err(SimNetworkError::BelowIdeal { .. })));
+        Ok(())
+    }
+
+    /// Gives each flow an FCT proportional to its ID.
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    struct IdDelaySim;
+
+    impl LinkSim for IdDelaySim {
+        fn name(&self) -> String {
+            "id-delay".into()
+        }
+
+        fn simulate(&self, spec: LinkSimSpec) -> crate::linksim::LinkSimResult {
+            Ok(spec
+                .flows
+                .iter()
+                .map(|f| FctRecord {
+                    id: f.id,
+                    size: f.size,
+                    start: f.start,
+                    fct: Nanosecs::new(f.id.inner() as u64 * 1000),
+                    ideal: Nanosecs::ZERO,
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn query_logs_replay_exactly() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..100)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows);
+        let opts = SimOpts::builder().link_sim(IdDelaySim).build();
+        let delays = sims.clone().into_delays(opts)?;
+        let path = (NodeId::new(0), NodeId::new(1));
+        let mut logger = delays.logged_queries(7);
+        let predictions = (0..20)
+            .map(|_| logger.predict(Bytes::new(1000), path))
+            .collect::<Vec<_>>();
+        logger.slowdown(Bytes::new(1000), path);
+        logger.predict_breakdown(Bytes::new(1000), path);
+        logger.ideal_fct(Bytes::new(1000), (NodeId::new(0), NodeId::new(0)));
+        let log = logger.into_log();
+        assert_eq!(log.queries().len(), 23);
+        assert!(predictions.iter().unique().count() > 1);
+
+        // Logs survive serialization and replay bit-for-bit
+        let log: QueryLog = serde_json::from_str(&serde_json::to_string(&log)?)?;
+        let outputs = delays.replay_queries(&log)?;
+        assert!(outputs
+            .iter()
+            .zip(&predictions)
+            .all(|(output, &p)| *output == QueryOutput::Nanosecs(p)));
+
+        // Subsets replay on their own streams
+        let mut subset = log.clone();
+        subset.retain(|q| q.stream % 2 == 1);
+        assert_eq!(delays.replay_queries(&subset)?.len(), 11);
+
+        let mut tampered = log.clone();
+        tampered.retain(|q| q.stream == 3);
+        let mut json = serde_json::to_value(&tampered)?;
+        json["queries"][0]["output"]["nanosecs"] = 1.into();
+        let tampered: QueryLog = serde_json::from_value(json)?;
+        assert!(matches!(
+            delays.replay_queries(&tampered),
+            Err(QueryLogError::Diverged { stream: 3 })
+        ));
+
+        let mut correlated = delays.clone();
+        correlated.set_hop_correlation(0.5)?;
+        assert!(matches!(
+            correlated.replay_queries(&log),
+            Err(QueryLogError::NetworkMismatch { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn clients_have_separate_distributions() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let clients = flows
+            .iter()
+            .map(|f| (f.id, ClientId::new(f.id.inner() % 2)))
+            .collect::<ClientMap>();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows);
+        let e = find_edge(&sims.topology, NodeId::new(0), NodeId::new(2)).unwrap();
+        let opts = SimOpts::builder().link_sim(IdDelaySim).build();
+        let delays = sims.into_delays_by_client(opts, &clients)?;
+        assert_eq!(delays.clients(), vec![ClientId::new(0), ClientId::new(1)]);
+
+        let max = |delays: &DelayNetwork| delays.edge_quantile(e, Bytes::new(1000), 1.0);
+        assert_eq!(max(&delays), Some(Nanosecs::new(9000)));
+        let even = delays.for_client(ClientId::new(0)).unwrap();
+        assert_eq!(max(&even), Some(Nanosecs::new(8000)));
+        assert!(even.clients().is_empty());
+        let odd = delays.for_client(ClientId::new(1)).unwrap();
+        assert_eq!(max(&odd), Some(Nanosecs::new(9000)));
+        assert!(delays.for_client(ClientId::new(2)).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn edge_quantiles_reject_invalid_q() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows);
+        let e = find_edge(&sims.topology, NodeId::new(0), NodeId::new(2)).unwrap();
+        let opts = SimOpts::builder().link_sim(IdDelaySim).build();
+        let delays = sims.into_delays(opts)?;
+        let size = Bytes::new(1000);
+        assert_eq!(delays.edge_quantile(e, size, 0.0), Some(Nanosecs::ZERO));
+        for q in [-0.1, 1.1, f64::NAN, f64::INFINITY] {
+            assert_eq!(delays.edge_quantile(e, size, q), None);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprints_cover_sampling_inputs() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let clients = flows
+            .iter()
+            .map(|f| (f.id, ClientId::new(f.id.inner() % 2)))
+            .collect::<ClientMap>();
+        let network = Network::new(&nodes, &links)?;
+        let sims = || network.clone().into_simulations(flows.clone());
+        let opts = || SimOpts::builder().link_sim(IdDelaySim);
+        let base = sims().into_delays(opts().build())?.fingerprint();
+        assert_eq!(sims().into_delays(opts().build())?.fingerprint(), base);
+
+        let by_client = sims().into_delays_by_client(opts().build(), &clients)?;
+        let windowed = opts().time_buckets(Nanosecs::new(5000)).build();
+        let seeded = opts().seed(1).build();
+        let jumbo = PacketProfile {
+            max_payload: Bytes::new(9000),
+            ..PacketProfile::default()
+        };
+        let variants = [
+            by_client.fingerprint(),
+            sims().into_delays(windowed)?.fingerprint(),
+            sims().into_delays(seeded)?.fingerprint(),
+            network
+                .clone()
+                .with_ecmp(EcmpModel::with_imbalance(2.0))
+                .into_simulations(flows.clone())
+                .into_delays(opts().build())?
+                .fingerprint(),
+            network
+                .clone()
+                .with_packet_profile(jumbo)?
+                .into_simulations(flows.clone())
+                .into_delays(opts().build())?
+                .fingerprint(),
+        ];
+        assert!(variants.iter().all(|&f| f != base), "{variants:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn channels_are_keyed_by_endpoints() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = vec![Flow {
+            id: FlowId::new(0),
+            src: NodeId::new(0),
+            dst: NodeId::new(1),
+            size: Bytes::new(1000),
+            start: Nanosecs::ZERO,
+        }];
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let delays = Network::new(&nodes, &links)?
+            .into_simulations(flows)
+            .into_delays(opts)?;
+        let channels = delays.channels().collect::<Vec<_>>();
+        assert_eq!(channels.len(), 2 * links.len());
+        assert!(channels
+            .windows(2)
+            .all(|w| (w[0].src, w[0].dst) < (w[1].src, w[1].dst)));
+        let path = delays
+            .edge_indices_between(NodeId::new(0), NodeId::new(1), |c| c.first())
+            .map(|e| {
+                let chan = &delays.topology.graph[e];
+                (chan.src, chan.dst)
+            })
+            .collect::<Vec<_>>();
+        for chan in &channels {
+            let on_path = path.contains(&(chan.src, chan.dst));
+            assert_eq!(chan.nr_samples(), usize::from(on_path));
+            assert_eq!(delays.channel(chan.src, chan.dst).as_ref(), Some(chan));
+        }
+        assert!(delays.channel(NodeId::new(0), NodeId::new(100)).is_none());
+        Ok(())
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::collections::BTreeMap;
+    #[test]
+    fn breakdowns_split_predictions() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(2000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let delays = Network::new(&nodes, &links)?
+            .into_simulations(flows.clone())
+            .into_delays(opts)?;
+        let mut rng = StdRng::seed_from_u64(0);
+        let path = (NodeId::new(0), NodeId::new(1));
+        let breakdown = delays
+            .predict_breakdown(Bytes::new(2000), path, &mut rng)
+            .unwrap();
+        let ideal = delays.ideal_fct(Bytes::new(2000), path, &mut rng).unwrap();
+        let delay = delays.predict(Bytes::new(2000), path, &mut rng).unwrap();
+        assert_eq!(breakdown.propagation + breakdown.transmission, ideal);
+        assert_eq!(breakdown.queueing, delay);
+        assert!(breakdown.propagation > Nanosecs::ZERO);
 
-    use anyhow::Context;
+        let fct = delays
+            .predict_fct(Bytes::new(2000), path, &mut rng)
+            .unwrap();
+        assert_eq!((fct.ideal, fct.delay), (ideal, delay));
+        assert_eq!(fct.fct, breakdown.total());
+        assert!(fct.slowdown() > 1.0);
+        let batch = delays.predict_slowdown_batch(
+            &[
+                (Bytes::new(2000), path),
+                (Bytes::new(2000), (NodeId::new(0), NodeId::new(0))),
+            ],
+            &mut rng,
+        );
+        assert_eq!(batch, vec![Some(fct), None]);
 
-    use crate::testing;
+        let breakdowns = delays.predict_breakdowns(&flows, &mut rng);
+        assert_eq!(breakdowns.len(), flows.len());
+        assert!(breakdowns.iter().all(|b| b.breakdown == breakdown));
+        let classes = attribution::aggregate(&breakdowns, |f| f.src);
+        assert_eq!(classes[&NodeId::new(0)].mean, breakdown);
+        assert_eq!(classes[&NodeId::new(0)].p99, breakdown);
+        Ok(())
+    }
 
-    use super::*;
+    #[test]
+    fn path_summaries_cover_every_hop() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(2000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let delays = Network::new(&nodes, &links)?
+            .into_simulations(flows)
+            .into_delays(opts)?;
+        let mut rng = StdRng::seed_from_u64(0);
+        let path = (NodeId::new(0), NodeId::new(1));
+        let summary = delays
+            .summarize_path(Bytes::new(2000), path, &mut rng)
+            .unwrap();
+        let ideal = delays.ideal_fct(Bytes::new(2000), path, &mut rng).unwrap();
+        assert_eq!(summary.propagation + summary.transmission, ideal);
+        let hops = summary
+            .hops
+            .iter()
+            .map(|hop| (hop.src, hop.dst))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            hops,
+            vec![
+                (NodeId::new(0), NodeId::new(2)),
+                (NodeId::new(2), NodeId::new(1))
+            ]
+        );
+        for hop in &summary.hops {
+            assert_eq!(hop.nr_samples, 10);
+            assert_eq!(hop.quantiles.unwrap().p99, Nanosecs::new(200));
+        }
+        assert_eq!(summary.end_to_end.unwrap().p50, Nanosecs::new(400));
 
-    fn find_edge<C: Clone>(topo: &Topology<C>, src: NodeId, dst: NodeId) -> Option<EdgeIndex> {
-        topo.idx_of(&src)
-            .and_then(|&a| topo.idx_of(&dst).map(|&b| (a, b)))
-            .and_then(|(a, b)| topo.graph.find_edge(a, b))
+        // Pairs without a path have no summary
+        let unrouted = (NodeId::new(0), NodeId::new(0));
+        assert!(delays
+            .summarize_path(Bytes::new(2000), unrouted, &mut rng)
+            .is_none());
+        Ok(())
+    }
+
+    // Delays flows starting before 1us ten times as much as later ones
+    #[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+    struct BusyHourSim;
+
+    impl LinkSim for BusyHourSim {
+        fn name(&self) -> String {
+            "busy-hour".into()
+        }
+
+        fn simulate(&self, spec: LinkSimSpec) -> crate::linksim::LinkSimResult {
+            Ok(spec
+                .flows
+                .iter()
+                .map(|f| FctRecord {
+                    id: f.id,
+                    size: f.size,
+                    start: f.start,
+                    fct: Nanosecs::new(if f.start < Nanosecs::new(1000) {
+                        100
+                    } else {
+                        10
+                    }),
+                    ideal: Nanosecs::ZERO,
+                })
+                .collect())
+        }
     }
 
-    // This test creates an eight-node topology and sends some flows with the
-    // same source and destination across racks. All flows will traverse
-    // exactly one ECMP group in the upwards direction. While we don't know
-    // exactly how many flows should traverse each link in the group, we can
-    // check that the final counts are close to equal. If a different hashing
-    // algorithm is used, the exact counts will change, and this test will need
-    // to be updated to use the new snapshot.
     #[test]
-    fn ecmp_replication_works() -> anyhow::Result<()> {
+    fn predictions_depend_on_start_times() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..20)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 100),
+            })
+            .collect::<Vec<_>>();
+        let network = Network::new(&nodes, &links)?;
+        let opts = SimOpts::builder()
+            .link_sim(BusyHourSim)
+            .time_buckets(Nanosecs::new(1000))
+            .build();
+        let delays = network.into_simulations(flows).into_delays(opts)?;
+        let mut rng = StdRng::seed_from_u64(0);
+        let path = (NodeId::new(0), NodeId::new(1));
+        let mut predict = |start| delays.predict_at(Bytes::new(1000), path, start, &mut rng);
+        for _ in 0..10 {
+            assert_eq!(predict(Some(Nanosecs::new(500))), Some(Nanosecs::new(200)));
+            assert_eq!(predict(Some(Nanosecs::new(1500))), Some(Nanosecs::new(20)));
+        }
+        // Without a start time, or outside of every window, hops sample over all start times
+        let unconditioned = (0..200)
+            .filter_map(|i| predict((i % 2 == 0).then_some(Nanosecs::new(5000))))
+            .collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(
+            unconditioned.into_iter().collect::<Vec<_>>(),
+            [20, 110, 200].map(Nanosecs::new)
+        );
+
+        let opts = SimOpts::builder()
+            .link_sim(BusyHourSim)
+            .time_buckets(Nanosecs::ZERO)
+            .build();
+        let network = Network::new(&nodes, &links)?.into_simulations(Vec::new());
+        assert!(matches!(
+            network.into_delays(opts),
+            Err(SimNetworkError::InvalidOpts(SimOptsError::EmptyTimeBuckets))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn predictions_depend_on_offered_load() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        // A burst of large flows followed by a trickle of small ones
+        let flows = (0..40)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(if i < 20 { 1000 } else { 100 }),
+                start: Nanosecs::new(if i < 20 {
+                    i as u64 * 50
+                } else {
+                    i as u64 * 10_000
+                }),
+            })
+            .collect::<Vec<_>>();
+        let network = Network::new(&nodes, &links)?;
+        let strategy = BucketStrategy::SizeAndLoad {
+            nr_loads: 2,
+            window: Nanosecs::new(1000),
+        };
+        let bucket_opts = BucketOpts {
+            b: 10,
+            strategy,
+            ..BucketOpts::default()
+        };
+        let opts = || {
+            SimOpts::builder()
+                .link_sim(BusyHourSim)
+                .bucket_opts(bucket_opts)
+                .retention(Retention::Memory)
+                .build()
+        };
+        let sims = || network.clone().into_simulations(flows.clone());
+        let delays = sims().into_delays(opts())?;
+        let mut rng = StdRng::seed_from_u64(0);
+        let path = (NodeId::new(0), NodeId::new(1));
+        for _ in 0..10 {
+            let predict = |size, load, rng: &mut StdRng| {
+                delays.predict_under_load(Bytes::new(size), path, load, rng)
+            };
+            assert_eq!(predict(1000, 5.0, &mut rng), Some(Nanosecs::new(200)));
+            assert_eq!(predict(100, 0.01, &mut rng), Some(Nanosecs::new(20)));
+        }
+        let chan = delays.topology.graph.edge_weights().next().unwrap();
+        assert_eq!(chan.dists.loads().count(), 2);
+        // Invalid loads don't select the most loaded range
+        assert!(chan.dists.for_load(-1.0).is_none());
+        assert!(chan.dists.for_load(f64::NAN).is_none());
+        // Load distributions are fingerprinted
+        let mut by_size = delays.clone();
+        by_size.rebucket(BucketOpts {
+            strategy: BucketStrategy::Size,
+            ..bucket_opts
+        })?;
+        assert_ne!(by_size.fingerprint(), delays.fingerprint());
+
+        // Each client's flows are bucketed by the load all flows offer
+        let clients = flows
+            .iter()
+            .map(|f| (f.id, ClientId::new(f.id.inner() % 2)))
+            .collect::<ClientMap>();
+        let mut by_client = sims().into_delays_by_client(opts(), &clients)?;
+        let even = by_client.for_client(ClientId::new(0)).unwrap();
+        let chan = even.topology.graph.edge_weights().next().unwrap();
+        assert_eq!(chan.dists.loads().count(), 2);
+        for _ in 0..10 {
+            let predict = |size, load, rng: &mut StdRng| {
+                even.predict_under_load(Bytes::new(size), path, load, rng)
+            };
+            assert_eq!(predict(1000, 5.0, &mut rng), Some(Nanosecs::new(200)));
+            assert_eq!(predict(100, 0.01, &mut rng), Some(Nanosecs::new(20)));
+        }
+        assert!(matches!(
+            by_client.rebucket(bucket_opts),
+            Err(SimNetworkError::RebucketClients)
+        ));
+        Ok(())
+    }
+
+    // Delays flows from host 0 ten times as much as flows from other hosts
+    #[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+    struct NoisyHostSim;
+
+    impl LinkSim for NoisyHostSim {
+        fn name(&self) -> String {
+            "noisy-host".into()
+        }
+
+        fn simulate(&self, spec: LinkSimSpec) -> crate::linksim::LinkSimResult {
+            Ok(spec
+                .flows
+                .iter()
+                .map(|f| FctRecord {
+                    id: f.id,
+                    size: f.size,
+                    start: f.start,
+                    fct: Nanosecs::new(if f.src == NodeId::new(0) { 100 } else { 10 }),
+                    ideal: Nanosecs::ZERO,
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn predictions_depend_on_source_racks() -> anyhow::Result<()> {
+        // Hosts 0 and 2 are in different racks, and both send to host 1 in host 0's rack
         let (nodes, links) = testing::eight_node_config();
-        let network = Network::new(&nodes, &links).context("failed to create topology")?;
+        let flows = (0..40)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(if i % 2 == 0 { 0 } else { 2 }),
+                dst: NodeId::new(1),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 100),
+            })
+            .collect::<Vec<_>>();
+        let network = Network::new(&nodes, &links)?.into_simulations(flows);
+        let build = |source_racks| {
+            let opts = SimOpts::builder()
+                .link_sim(NoisyHostSim)
+                .bucket_opts(BucketOpts::new(2, 10))
+                .source_racks(source_racks)
+                .build();
+            network.clone().into_delays(opts)
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let size = Bytes::new(1000);
+        let (near, far) = (
+            (NodeId::new(0), NodeId::new(1)),
+            (NodeId::new(2), NodeId::new(1)),
+        );
+
+        let down_link = network.find_edge(NodeId::new(4), NodeId::new(1)).unwrap();
+        let delays = build(true)?;
+        let dists = &delays.topology.graph[down_link].dists;
+        let mut racks = dists.groups().map(|(rack, _)| rack).collect::<Vec<_>>();
+        racks.sort();
+        assert_eq!(racks, [NodeId::new(4), NodeId::new(5)]);
+        for _ in 0..10 {
+            assert_eq!(
+                delays.predict(size, near, &mut rng),
+                Some(Nanosecs::new(200))
+            );
+            assert_eq!(delays.predict(size, far, &mut rng), Some(Nanosecs::new(40)));
+        }
+
+        // Without source racks, the shared down-link mixes both racks' delays
+        let delays = build(false)?;
+        let near = (0..100)
+            .filter_map(|_| delays.predict(size, near, &mut rng))
+            .collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(
+            near.into_iter().collect::<Vec<_>>(),
+            [110, 200].map(Nanosecs::new)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn predictions_stream_into_sinks() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
         let flows = (0..100)
             .map(|i| Flow {
                 id: FlowId::new(i),
-                src: NodeId::new(0),
-                dst: NodeId::new(3),
-                size: Bytes::ZERO,
-                start: Nanosecs::ZERO,
+                src: NodeId::new(i % 2),
+                dst: NodeId::new(2),
+                size: Bytes::new(1000 * (i as u64 + 1)),
+                start: Nanosecs::new(i as u64 * 1000),
             })
             .collect::<Vec<_>>();
-        let network = network.into_simulations(flows);
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let delays = Network::new(&nodes, &links)?
+            .into_simulations(flows.clone())
+            .into_delays(opts)?;
+        let expected = delays.predict_breakdowns(&flows, StdRng::seed_from_u64(0));
 
-        // The ECMP group contains edges (4, 6) and (4, 7)
-        let e1 = find_edge(&network.topology, NodeId::new(4), NodeId::new(6)).unwrap();
-        let e2 = find_edge(&network.topology, NodeId::new(4), NodeId::new(7)).unwrap();
+        // A bounded channel holding one batch blocks prediction until the consumer catches up
+        let (tx, rx) = sink::ChannelSink::bounded(1);
+        let consumer = std::thread::spawn(move || rx.iter().flatten().collect::<Vec<_>>());
+        let unreachable = Flow {
+            dst: NodeId::new(100),
+            ..flows[0]
+        };
+        let stats = delays.predict_into(
+            flows.iter().copied().chain([unreachable]),
+            StdRng::seed_from_u64(0),
+            tx,
+            16,
+        )?;
+        assert_eq!(consumer.join().unwrap(), expected);
+        assert_eq!(
+            stats,
+            sink::StreamStats {
+                nr_predicted: 100,
+                nr_skipped: 1,
+                nr_batches: 7,
+            }
+        );
 
-        // Flow counts for the links should be close to each other
-        insta::assert_yaml_snapshot!((
-            network.topology.graph[e1].flows.len(),
-            network.topology.graph[e2].flows.len(),
-        ), @r###"
-        ---
-        - 55
-        - 45
-        "###);
+        let mut csv = sink::CsvSink::new(Vec::new());
+        delays.predict_into(
+            flows.iter().copied(),
+            StdRng::seed_from_u64(0),
+            &mut csv,
+            16,
+        )?;
+        let csv = String::from_utf8(csv.into_inner())?;
+        assert_eq!(csv.lines().count(), 101);
+        let b = expected[99];
+        assert_eq!(
+            csv.lines().last(),
+            Some(
+                format!(
+                    "99,1,2,100000,99000,{},{},{}",
+                    b.breakdown.propagation.into_u64(),
+                    b.breakdown.transmission.into_u64(),
+                    b.breakdown.queueing.into_u64()
+                )
+                .as_str()
+            )
+        );
+
+        // Sink errors stop prediction
+        let mut nr_calls = 0;
+        let res = delays.predict_into(
+            flows.iter().copied(),
+            StdRng::seed_from_u64(0),
+            sink::CallbackSink(|_: &[_]| {
+                nr_calls += 1;
+                Err(sink::SinkError::Disconnected)
+            }),
+            16,
+        );
+        assert!(matches!(res, Err(sink::SinkError::Disconnected)));
+        assert_eq!(nr_calls, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn rack_shortcut_matches_routes() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = [(0, 1), (1, 0), (0, 3)]
+            .into_iter()
+            .flat_map(|(src, dst)| (0..10).map(move |i| (src, dst, i)))
+            .enumerate()
+            .map(|(i, (src, dst, j))| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(src),
+                dst: NodeId::new(dst),
+                size: Bytes::new(2000),
+                start: Nanosecs::new(j as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let delays = Network::new(&nodes, &links)?
+            .into_simulations(flows.clone())
+            .into_delays(opts)?;
+        assert!(delays.racks.is_some());
+        let mut slow = delays.clone();
+        slow.racks = None;
+        let mut rng = StdRng::seed_from_u64(0);
+        for flow in flows {
+            let path = (flow.src, flow.dst);
+            assert_eq!(
+                delays.predict(flow.size, path, &mut rng),
+                slow.predict(flow.size, path, &mut rng)
+            );
+            assert_eq!(
+                delays.ideal_fct(flow.size, path, &mut rng),
+                slow.ideal_fct(flow.size, path, &mut rng)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn correlated_hops_are_comonotonic() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = vec![Flow {
+            id: FlowId::new(0),
+            src: NodeId::new(0),
+            dst: NodeId::new(1),
+            size: Bytes::new(1000),
+            start: Nanosecs::ZERO,
+        }];
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let mut delays = Network::new(&nodes, &links)?
+            .into_simulations(flows)
+            .into_delays(opts)?;
+        // Give both hops the delays 0, 1, ..., 99
+        let values = (0..100).map(|i| i as f64).collect::<Vec<_>>();
+        for chan in delays.topology.graph.edge_weights_mut() {
+            chan.dists
+                .fill(&values, |_| Bytes::new(1000), |v| v, BucketOpts::default())?;
+        }
+        let path = (NodeId::new(0), NodeId::new(1));
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sample = |delays: &DelayNetwork| {
+            (0..100)
+                .map(|_| delays.predict(Bytes::new(1000), path, &mut rng).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let is_even = |d: &Nanosecs| d.into_u64().is_multiple_of(2);
+        assert!(!sample(&delays).iter().all(is_even));
+        delays.set_hop_correlation(1.0)?;
+        assert!(sample(&delays).iter().all(is_even));
+        Ok(())
+    }
 
+    #[test]
+    fn invalid_hop_correlations_are_rejected() -> anyhow::Result<()> {
+        let mut delays = testing::fixed_delay_network(Nanosecs::new(100))?;
+        for correlation in [-0.1, 1.1, f64::NAN] {
+            assert!(matches!(
+                delays.set_hop_correlation(correlation),
+                Err(HopCorrelationError::InvalidCorrelation(_))
+            ));
+        }
+        assert_eq!(delays.hop_correlation, 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn saved_clusters_roundtrip() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let mut network = Network::new(&nodes, &links)?.into_simulations(Vec::new());
+        let edges = network.edge_indices().collect::<Vec<_>>();
+        network.set_clusters(vec![
+            Cluster::new(edges[0], edges[..4].iter().copied().collect()),
+            Cluster::new(edges[4], edges[4..].iter().copied().collect()),
+        ])?;
+        let saved = serde_json::to_string(&network.save_clusters())?;
+
+        let mut other = Network::new(&nodes, &links)?.into_simulations(Vec::new());
+        other.load_clusters(serde_json::from_str(&saved)?)?;
+        assert_eq!(other.nr_clusters(), 2);
+
+        let (nodes, links) = testing::three_node_config();
+        let mut other = Network::new(&nodes, &links)?.into_simulations(Vec::new());
+        let res = other.load_clusters(serde_json::from_str(&saved)?);
+        assert!(matches!(res, Err(ClusterError::FingerprintMismatch { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn set_clusters_validates_edges() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let mut network = Network::new(&nodes, &links)?.into_simulations(Vec::new());
+        let edges = network.edge_indices().collect::<Vec<_>>();
+        let res = network.set_clusters(vec![Cluster::new(
+            edges[0],
+            edges[1..].iter().copied().collect(),
+        )]);
+        assert!(matches!(res, Err(ClusterError::RepresentativeNotMember(_))));
+        let res = network.set_clusters(vec![Cluster::new(
+            edges[0],
+            edges[..4].iter().copied().collect(),
+        )]);
+        assert!(matches!(res, Err(ClusterError::MissingEdge(e)) if e == edges[4]));
+        let res = network.set_clusters(vec![
+            Cluster::new(edges[0], edges.iter().copied().collect()),
+            Cluster::new(edges[1], [edges[1]].into_iter().collect()),
+        ]);
+        assert!(matches!(res, Err(ClusterError::DuplicateEdge(_))));
+        assert_eq!(network.nr_clusters(), edges.len());
         Ok(())
     }
 
@@ -854,6 +4199,272 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn link_sim_desc_multi_homed() -> anyhow::Result<()> {
+        let (nodes, links) = testing::dual_homed_config();
+        let flows = (0..4)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i),
+                dst: NodeId::new((i + 1) % 4),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let network = Network::new(&nodes, &links)?.into_simulations(flows);
+        let descs = network
+            .edge_indices()
+            .filter_map(|eidx| network.link_sim_desc(eidx).transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+        assert!(!descs.is_empty());
+        // Every source reaches its bottleneck through exactly one of its two uplinks
+        for desc in &descs {
+            for link in desc
+                .other_links
+                .iter()
+                .filter(|l| l.to == desc.bottleneck.from)
+            {
+                assert_eq!(link.total_bandwidth, BitsPerSec::new(10_000_000_000));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn multi_homed_hosts_share_their_nic() -> anyhow::Result<()> {
+        let (mut nodes, links) = testing::dual_homed_config();
+        let nic_rate = BitsPerSec::new(5_000_000_000);
+        nodes[0] = nodes[0].clone().with_nic_rate(nic_rate);
+        let flows = (0..20)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(if i < 3 { 0 } else { i % 3 + 1 }),
+                dst: NodeId::new(if i < 3 { i + 1 } else { 0 }),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let network = Network::new(&nodes, &links)?.into_simulations(flows);
+        assert_eq!(network.nodes().count(), nodes.len());
+
+        // The virtual link carries every flow from the host and the ACKs of every flow to it
+        let nic = NodeId::new(nodes.len());
+        let virtual_link = network.find_edge(NodeId::new(0), nic).unwrap();
+        assert_eq!(network.edge(virtual_link).unwrap().nr_flows(), 3);
+        assert!(network.ack_rate_of(virtual_link).unwrap() > BitsPerSec::ZERO);
+
+        // Downstream simulations see the host inject at its NIC rate
+        let descs = network
+            .edge_indices()
+            .filter_map(|eidx| network.link_sim_desc(eidx).transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+        let access = descs
+            .iter()
+            .flat_map(|desc| &desc.other_links)
+            .filter(|l| l.from == NodeId::new(0))
+            .collect::<Vec<_>>();
+        assert!(!access.is_empty());
+        assert!(access.iter().all(|l| l.total_bandwidth == nic_rate));
+
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let delays = network.into_delays(opts)?;
+        let path = delays
+            .summarize_path(
+                Bytes::new(1000),
+                (NodeId::new(0), NodeId::new(1)),
+                delays.rng(),
+            )
+            .unwrap();
+        assert_eq!(path.hops.len(), 3);
+        assert_eq!((path.hops[0].src, path.hops[0].dst), (NodeId::new(0), nic));
+        assert!(path.end_to_end.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn link_sim_desc_co_located_sources() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flow = |id, src, dst| Flow {
+            id: FlowId::new(id),
+            src: NodeId::new(src),
+            dst: NodeId::new(dst),
+            size: Bytes::new(1000),
+            start: Nanosecs::ZERO,
+        };
+        let mut network = Network::new(&nodes, &links)?.into_simulations(vec![flow(0, 0, 3)]);
+        let e = find_edge(&network.topology, NodeId::new(0), NodeId::new(4)).unwrap();
+
+        // A second source sharing host 0's up-channel gets connected to host 0
+        let extra = flow(1, 1, 3);
+        network.flows = FlowArena::new(vec![flow(0, 0, 3), extra]);
+        network.topology.graph[e].push_flow(&network.flows, 1, &PacketProfile::default());
+        let desc = network.link_sim_desc(e)?.unwrap();
+        assert_eq!(desc.other_links.len(), 2);
+        assert!(desc
+            .other_links
+            .iter()
+            .any(|l| l.from == NodeId::new(1) && l.to == NodeId::new(0)));
+
+        // A node can't be both a source and a destination
+        let bad = flow(2, 2, 0);
+        network.flows = FlowArena::new(vec![flow(0, 0, 3), extra, bad]);
+        network.topology.graph[e].push_flow(&network.flows, 2, &PacketProfile::default());
+        assert!(matches!(
+            network.link_sim_desc(e),
+            Err(LinkSimDescError::SourceIsDestination { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn ack_models_scale_ack_rates() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 2),
+                dst: NodeId::new(1 - i % 2),
+                size: Bytes::new(4000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let network = Network::new(&nodes, &links)?.into_simulations(flows);
+        let e = find_edge(&network.topology, NodeId::new(0), NodeId::new(2)).unwrap();
+        let rate = network.ack_rate_of(e).unwrap();
+        assert!(rate > BitsPerSec::ZERO);
+        assert_eq!(network.ack_rate_with(e, AckModel::default()), Some(rate));
+        let delayed = AckModel::new(SZ_ACK, 2.0, true);
+        assert_eq!(
+            network.ack_rate_with(e, delayed).unwrap().into_f64(),
+            (rate.into_f64() / 2.0).round()
+        );
+        let bigger = AckModel::new(SZ_ACK.scale_by(2.0), 1.0, true);
+        assert_eq!(
+            network.ack_rate_with(e, bigger).unwrap().into_f64(),
+            rate.into_f64() * 2.0
+        );
+
+        let none = AckModel::none();
+        assert_eq!(network.ack_rate_with(e, none), Some(BitsPerSec::ZERO));
+        let desc = network.link_sim_desc_with(e, none)?.unwrap();
+        assert_eq!(
+            desc.bottleneck.available_bandwidth,
+            desc.bottleneck.total_bandwidth
+        );
+        let desc = network.link_sim_desc(e)?.unwrap();
+        assert_eq!(
+            desc.bottleneck.available_bandwidth,
+            desc.bottleneck.total_bandwidth - rate
+        );
+
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .ack(AckModel::new(SZ_ACK, 0.0, true))
+            .build();
+        assert!(matches!(
+            network.into_delays(opts),
+            Err(SimNetworkError::InvalidOpts(SimOptsError::PktsPerAck(_)))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn packet_profiles_reach_link_sims() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flow = Flow {
+            id: FlowId::new(0),
+            src: NodeId::new(0),
+            dst: NodeId::new(1),
+            size: Bytes::new(9000),
+            start: Nanosecs::ZERO,
+        };
+        let jumbo = PacketProfile {
+            max_payload: Bytes::new(9000),
+            ..PacketProfile::default()
+        };
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .retention(Retention::Memory)
+            .build();
+        let sims = Network::new(&nodes, &links)?
+            .with_packet_profile(jumbo)?
+            .into_simulations(vec![flow]);
+        let e = find_edge(&sims.topology, NodeId::new(0), NodeId::new(2)).unwrap();
+        let delays = sims.into_delays(opts)?;
+        let records = delays.records().unwrap().get(e)?.unwrap();
+        assert_eq!(records[0].fct, Nanosecs::new(100));
+
+        let empty = PacketProfile {
+            max_payload: Bytes::ZERO,
+            ..PacketProfile::default()
+        };
+        assert!(matches!(
+            Network::new(&nodes, &links)?.with_packet_profile(empty),
+            Err(TopologyError::EmptyPacketPayload)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn empty_representatives_are_promoted() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flow = Flow {
+            id: FlowId::new(0),
+            src: NodeId::new(0),
+            dst: NodeId::new(3),
+            size: Bytes::new(1000),
+            start: Nanosecs::ZERO,
+        };
+        let mut network = Network::new(&nodes, &links)?.into_simulations(vec![flow]);
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        assert!(network.check_clusters(&opts).issues.is_empty());
+
+        let busy = find_edge(&network.topology, NodeId::new(0), NodeId::new(4)).unwrap();
+        let idle = find_edge(&network.topology, NodeId::new(1), NodeId::new(4)).unwrap();
+        let mut clusters = vec![Cluster::new(idle, [idle, busy].into_iter().collect())];
+        clusters.extend(
+            network
+                .edge_indices()
+                .filter(|&e| e != idle && e != busy)
+                .map(|e| Cluster::new(e, [e].into_iter().collect())),
+        );
+        network.set_clusters(clusters)?;
+
+        let check = network.check_clusters(&opts);
+        assert!(!check.is_ok());
+        assert!(matches!(
+            check.issues[..],
+            [RepresentativeIssue::Empty { representative, nr_starved: 1 }] if representative == idle
+        ));
+        let check = network.repair_clusters(&opts);
+        assert!(check.is_ok());
+        assert_eq!(
+            check.promotions,
+            vec![Promotion {
+                from: idle,
+                to: busy
+            }]
+        );
+        assert!(network
+            .clusters()
+            .iter()
+            .any(|c| c.representative() == busy));
+        assert!(network.check_clusters(&opts).issues.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn link_sim_desc_correct() -> anyhow::Result<()> {
         let (nodes, links) = testing::eight_node_config();
@@ -880,7 +4491,7 @@ mod tests {
             .edge_indices()
             .filter_map(|eidx| {
                 let chan = network.edge(eidx).unwrap();
-                let desc = network.link_sim_desc(eidx)?;
+                let desc = network.link_sim_desc(eidx).unwrap()?;
                 Some(((chan.src(), chan.dst()), desc))
             })
             .collect::<BTreeMap<_, _>>();
@@ -889,4 +4500,55 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn degenerate_links_have_defined_loads() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flow = |id, size, start| Flow {
+            id: FlowId::new(id),
+            src: NodeId::new(0),
+            dst: NodeId::new(1),
+            size: Bytes::new(size),
+            start: Nanosecs::new(start),
+        };
+        let network = Network::new(&nodes, &links)?;
+        let loads = |flows| {
+            let sims = network.clone().into_simulations(flows);
+            let first = find_edge(&sims.topology, NodeId::new(0), NodeId::new(2)).unwrap();
+            let unused = find_edge(&sims.topology, NodeId::new(2), NodeId::new(0)).unwrap();
+            (
+                sims.link_load(first).unwrap(),
+                sims.link_load(unused).unwrap(),
+                sims.load_of(first).unwrap(),
+            )
+        };
+        let (load, unused, value) = loads(vec![flow(0, 1000, 0)]);
+        assert_eq!(load, LinkLoad::Instantaneous);
+        assert_eq!(unused, LinkLoad::Idle);
+        assert_eq!(value, 0.0);
+        let (load, _, value) = loads(vec![flow(0, 0, 0), flow(1, 0, 1000)]);
+        assert_eq!(load, LinkLoad::Idle);
+        assert_eq!(value, 0.0);
+        let (load, _, _) = loads(vec![flow(0, 1000, 0), flow(1, 1000, 1000)]);
+        assert!(matches!(load, LinkLoad::Rate(x) if x > 0.0));
+        Ok(())
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn loads_are_never_nan(
+            nr_bytes in proptest::prelude::any::<u32>(),
+            duration in proptest::prelude::any::<u32>(),
+            bandwidth in proptest::prelude::any::<u32>(),
+        ) {
+            let load = LinkLoad::from_window(
+                Bytes::new(nr_bytes as u64),
+                Nanosecs::new(duration as u64),
+                BitsPerSec::new(bandwidth as u64),
+            );
+            let value = load.value();
+            proptest::prop_assert!(!value.is_nan() && value >= 0.0);
+            proptest::prop_assert_eq!(value == 0.0, matches!(load, LinkLoad::Idle | LinkLoad::Instantaneous));
+        }
+    }
 }
@@ -0,0 +1,260 @@
+//! Two-phase clustering. A one-shot clustering fixes the trade-off between accuracy and the
+//! number of link simulations up front. [`SimNetwork::into_delays_adaptive`] instead clusters
+//! aggressively, simulates the representatives, and then refines the clusters which matter most
+//! under a budget of simulations.
+//!
+//! In every round, each cluster is scored by the largest feature distance between a member and
+//! its representative, scaled by the representative's mean simulated delay relative to the
+//! largest one. Links whose representative sees little delay contribute little error however
+//! different they are, so only clusters that are both diverse and delayed get split. The
+//! highest-scoring clusters are split by promoting their farthest member to a new representative
+//! and moving every member closer to it, and the new representatives are simulated in the next
+//! round. Refinement stops when the budget is spent or no score exceeds the tolerance.
+
+use std::collections::HashMap;
+
+use rayon::prelude::*;
+
+use crate::{
+    cluster::{Cluster, ClusteringAlgo},
+    linksim::LinkSim,
+    network::{
+        dedup::DedupStats, types::FlowChannel, DelayNetwork, EdgeIndex, FctRecord, Flow,
+        SimNetwork, SimNetworkError,
+    },
+    opts::SimOpts,
+    routing::RoutingAlgo,
+};
+
+/// Options for [`SimNetwork::into_delays_adaptive`].
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder)]
+pub struct AdaptiveOpts {
+    /// The maximum number of representatives simulated, including those of the initial
+    /// clustering, which are always simulated. Unlimited if `None`.
+    #[builder(default, setter(strip_option))]
+    pub budget: Option<usize>,
+    /// The maximum number of clusters split per round.
+    #[builder(default = 8)]
+    pub splits_per_round: usize,
+    /// Clusters are only split if their score exceeds this.
+    #[builder(default)]
+    pub tolerance: f64,
+}
+
+impl Default for AdaptiveOpts {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl<R> SimNetwork<R>
+where
+    R: RoutingAlgo + Sync,
+{
+    /// Clusters the network with `initial`, then refines the clusters on simulated results and
+    /// converts the network into a [`DelayNetwork`]. Links are compared by the distance between
+    /// their features, which are extracted once per link. Representatives are simulated once, and
+    /// the [`DedupStats`] of the result count every simulation across rounds. See
+    /// [`crate::network::adaptive`].
+    pub fn into_delays_adaptive<C, S, F, G, X>(
+        mut self,
+        initial: C,
+        feature: F,
+        distance: G,
+        opts: SimOpts<S>,
+        adaptive: &AdaptiveOpts,
+    ) -> Result<DelayNetwork<R>, SimNetworkError>
+    where
+        C: ClusteringAlgo,
+        S: LinkSim + Sync,
+        F: Fn(&FlowChannel, &[Flow]) -> X + Sync,
+        G: Fn(&X, &X) -> f64,
+        X: Send,
+    {
+        let features = self
+            .edge_indices()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|eidx| {
+                let flows = self.flows_on(eidx).unwrap();
+                feature(&self.topology.graph[eidx], &flows)
+            })
+            .collect::<Vec<_>>();
+        let dist =
+            |a: EdgeIndex, b: EdgeIndex| distance(&features[a.index()], &features[b.index()]);
+        self.cluster(initial);
+        let mut clusters = self
+            .clusters
+            .iter()
+            .map(|c| {
+                let mut members = c.members().copied().collect::<Vec<_>>();
+                members.sort();
+                (c.representative(), members)
+            })
+            .collect::<Vec<_>>();
+
+        let mut eidx2data = HashMap::<EdgeIndex, Vec<FctRecord>>::new();
+        let mut failures = Vec::new();
+        let (mut nr_representatives, mut nr_simulated) = (0, 0);
+        let mut pending = clusters.iter().map(|&(rep, _)| rep).collect::<Vec<_>>();
+        while !pending.is_empty() {
+            let singletons = pending
+                .iter()
+                .map(|&e| Cluster::new(e, [e].into_iter().collect()))
+                .collect::<Vec<_>>();
+            let (data, stats, _, failed) = self.simulate(&opts, &singletons)?;
+            eidx2data.extend(data);
+            failures.extend(failed);
+            nr_representatives += pending.len();
+            nr_simulated += stats.nr_simulated;
+
+            let remaining = adaptive.budget.map_or(usize::MAX, |budget| {
+                budget.saturating_sub(nr_representatives)
+            });
+            let delays = clusters
+                .iter()
+                .map(|&(rep, _)| self.mean_delay(eidx2data.get(&rep).map(|data| &data[..])))
+                .collect::<Vec<_>>();
+            let max_delay = delays.iter().copied().fold(0.0, f64::max);
+            let mut scored = clusters
+                .iter()
+                .zip(&delays)
+                .enumerate()
+                .filter(|&(_, (_, &delay))| max_delay > 0.0 && delay > 0.0)
+                .filter_map(|(i, ((rep, members), &delay))| {
+                    let (far, d) = members
+                        .iter()
+                        .map(|&m| (m, dist(*rep, m)))
+                        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+                    let score = d * delay / max_delay;
+                    (score > adaptive.tolerance).then_some((score, i, far))
+                })
+                .collect::<Vec<_>>();
+            scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+            scored.truncate(adaptive.splits_per_round.min(remaining));
+
+            pending.clear();
+            for (_, i, far) in scored {
+                let rep = clusters[i].0;
+                let (moved, kept) = clusters[i].1.iter().partition::<Vec<_>, _>(|&&m| {
+                    m == far || (m != rep && dist(far, m) < dist(rep, m))
+                });
+                clusters[i].1 = kept;
+                clusters.push((far, moved));
+                pending.push(far);
+            }
+        }
+
+        self.clusters = clusters
+            .into_iter()
+            .map(|(rep, members)| Cluster::new(rep, members.into_iter().collect()))
+            .collect();
+        let mut delays = self.into_delays_from_records(eidx2data, opts)?;
+        delays.dedup = DedupStats {
+            nr_representatives,
+            nr_simulated,
+        };
+        failures.sort_by_key(|f| f.edge);
+        delays.failures = failures;
+        Ok(delays)
+    }
+
+    // The mean packet-normalized delay of a representative's records
+    fn mean_delay(&self, data: Option<&[FctRecord]>) -> f64 {
+        match data {
+            Some(data) if !data.is_empty() => {
+                data.iter()
+                    .map(|rec| rec.pktnorm_delay_with(&self.packets))
+                    .sum::<f64>()
+                    / data.len() as f64
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::{FlowId, Network, NodeId},
+        testing::{self, FixedDelaySim},
+        units::{Bytes, Nanosecs},
+    };
+
+    // Clusters every link together
+    struct OneCluster;
+
+    impl ClusteringAlgo for OneCluster {
+        fn cluster<R>(&self, network: &SimNetwork<R>) -> Vec<Cluster>
+        where
+            R: RoutingAlgo + Sync,
+        {
+            vec![Cluster::new(
+                EdgeIndex::new(0),
+                network.edge_indices().collect(),
+            )]
+        }
+    }
+
+    fn sims() -> anyhow::Result<SimNetwork> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (0..60)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 3),
+                dst: NodeId::new(3),
+                size: Bytes::new(1_000 * (i as u64 % 5 + 1)),
+                start: Nanosecs::new(i as u64 * 1_000),
+            })
+            .collect();
+        Ok(Network::new(&nodes, &links)?.into_simulations(flows))
+    }
+
+    #[test]
+    fn adaptive_clustering_refines_under_budget() -> anyhow::Result<()> {
+        let opts = || {
+            SimOpts::builder()
+                .link_sim(FixedDelaySim {
+                    delay_per_pkt: Nanosecs::new(100),
+                })
+                .build()
+        };
+        let feature = |chan: &FlowChannel, _: &[Flow]| chan.nr_flows() as f64;
+        let distance = |a: &f64, b: &f64| (a - b).abs();
+
+        let adaptive = AdaptiveOpts::builder()
+            .budget(3)
+            .splits_per_round(1)
+            .build();
+        let delays =
+            sims()?.into_delays_adaptive(OneCluster, feature, distance, opts(), &adaptive)?;
+        assert_eq!(delays.dedup_stats().nr_representatives, 3);
+
+        // Without a budget, clusters are split until members look like their representative
+        let sims = sims()?;
+        let delays = sims.clone().into_delays_adaptive(
+            OneCluster,
+            feature,
+            distance,
+            opts(),
+            &AdaptiveOpts::default(),
+        )?;
+        let nr_representatives = delays.dedup_stats().nr_representatives;
+        assert!(nr_representatives > 3);
+        let mut distinct = sims
+            .channels()
+            .map(|chan| chan.nr_flows())
+            .filter(|&n| n > 0)
+            .collect::<Vec<_>>();
+        distinct.sort();
+        distinct.dedup();
+        assert!(nr_representatives <= distinct.len() + 1);
+        let busy = sims
+            .edge_indices()
+            .find(|&e| sims.edge(e).unwrap().nr_flows() > 0)
+            .unwrap();
+        assert!(delays.edge_quantile(busy, Bytes::new(1_000), 0.5).is_some());
+        Ok(())
+    }
+}
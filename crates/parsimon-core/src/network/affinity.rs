@@ -0,0 +1,265 @@
+//! Empirical path probabilities per host pair. By default,
+//! [`DelayNetwork`](crate::network::DelayNetwork) samples one of the paths between two hosts
+//! uniformly, but the flows of a trace may be spread unevenly over those paths, e.g., with few
+//! flows per host pair or a skewed [`EcmpModel`](crate::network::ecmp::EcmpModel). A
+//! [`PathAffinity`] records how often each path carried the trace's flows, so that prediction
+//! picks paths as simulation routed flows. See [`DelayNetwork::set_path_affinity`].
+//!
+//! [`DelayNetwork::set_path_affinity`]: crate::network::DelayNetwork::set_path_affinity
+
+use rand::Rng;
+use rustc_hash::FxHashMap;
+
+use crate::{
+    network::{flowpaths::FlowPaths, EdgeIndex, NodeId, SimNetwork},
+    routing::RoutingAlgo,
+};
+
+/// The probability of each path between every host pair of a trace.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PathAffinity {
+    // Sorted by host pair
+    pairs: Vec<HostPairPaths>,
+}
+
+/// The paths between a pair of hosts.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct HostPairPaths {
+    /// The source host.
+    pub src: NodeId,
+    /// The destination host.
+    pub dst: NodeId,
+    /// The paths which carried flows from `src` to `dst`, sorted by decreasing probability.
+    pub paths: Vec<WeightedPath>,
+}
+
+/// A path and the fraction of its host pair's flows it carried.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct WeightedPath {
+    /// The path's edges, from source to destination.
+    pub edges: Vec<EdgeIndex>,
+    /// The fraction of flows between the host pair routed on the path.
+    pub probability: f64,
+}
+
+impl PathAffinity {
+    /// Computes path probabilities from the paths flows were routed on.
+    pub fn new(paths: &FlowPaths) -> Self {
+        let mut counts = vec![0_usize; paths.paths.len()];
+        for &(_, i) in &paths.assignments {
+            counts[i] += 1;
+        }
+        let mut by_pair = FxHashMap::<(NodeId, NodeId), Vec<(usize, usize)>>::default();
+        for (i, hops) in paths.paths.iter().enumerate() {
+            let (Some(first), Some(last)) = (hops.first(), hops.last()) else {
+                continue;
+            };
+            by_pair
+                .entry((first.src, last.dst))
+                .or_default()
+                .push((i, counts[i]));
+        }
+        let mut pairs = by_pair
+            .into_iter()
+            .map(|((src, dst), mut counts)| {
+                counts.sort_by_key(|&(i, count)| (std::cmp::Reverse(count), i));
+                let total = counts.iter().map(|&(_, count)| count).sum::<usize>() as f64;
+                let paths = counts
+                    .into_iter()
+                    .map(|(i, count)| WeightedPath {
+                        edges: paths.paths[i].iter().map(|hop| hop.edge).collect(),
+                        probability: count as f64 / total,
+                    })
+                    .collect();
+                HostPairPaths { src, dst, paths }
+            })
+            .collect::<Vec<_>>();
+        pairs.sort_by_key(|p| (p.src, p.dst));
+        Self { pairs }
+    }
+
+    /// Returns the paths from `src` to `dst`, or `None` if no flow went from `src` to `dst`.
+    pub fn paths(&self, src: NodeId, dst: NodeId) -> Option<&HostPairPaths> {
+        self.pairs
+            .binary_search_by_key(&(src, dst), |p| (p.src, p.dst))
+            .ok()
+            .map(|i| &self.pairs[i])
+    }
+
+    /// Returns every host pair, sorted.
+    pub fn pairs(&self) -> &[HostPairPaths] {
+        &self.pairs
+    }
+
+    /// Samples a path from `src` to `dst` with the recorded probabilities, or returns `None` if no
+    /// flow went from `src` to `dst`.
+    pub fn sample<RNG>(&self, src: NodeId, dst: NodeId, rng: &mut RNG) -> Option<&[EdgeIndex]>
+    where
+        RNG: Rng,
+    {
+        let paths = &self.paths(src, dst)?.paths;
+        let mut u = rng.gen::<f64>();
+        for path in paths {
+            if u < path.probability {
+                return Some(&path.edges);
+            }
+            u -= path.probability;
+        }
+        // Rounding can leave `u` just past the last path
+        paths.last().map(|path| path.edges.as_slice())
+    }
+
+    // Checks that every path is a chain of known edges from its source to its destination, given
+    // the endpoints of each edge of the topology.
+    pub(crate) fn validate<F>(&self, endpoints: F) -> Result<(), AffinityError>
+    where
+        F: Fn(EdgeIndex) -> Option<(NodeId, NodeId)>,
+    {
+        for pair in &self.pairs {
+            for path in &pair.paths {
+                let mut at = pair.src;
+                for &edge in &path.edges {
+                    let (from, to) = endpoints(edge).ok_or(AffinityError::UnknownEdge(edge))?;
+                    if from != at {
+                        return Err(AffinityError::Discontiguous {
+                            src: pair.src,
+                            dst: pair.dst,
+                        });
+                    }
+                    at = to;
+                }
+                if at != pair.dst {
+                    return Err(AffinityError::Discontiguous {
+                        src: pair.src,
+                        dst: pair.dst,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Errors which can be encountered setting a network's path probabilities.
+#[derive(Debug, thiserror::Error)]
+pub enum AffinityError {
+    /// A path contains an edge which doesn't exist.
+    #[error("Edge {0:?} does not exist")]
+    UnknownEdge(EdgeIndex),
+
+    /// A path doesn't lead from its source to its destination.
+    #[error("A path from {src} to {dst} is not contiguous")]
+    Discontiguous {
+        /// The source host.
+        src: NodeId,
+        /// The destination host.
+        dst: NodeId,
+    },
+}
+
+impl<R> SimNetwork<R>
+where
+    R: RoutingAlgo + Sync,
+{
+    /// Computes the probability of each path between every host pair from the network's flows.
+    pub fn path_affinity(&self) -> PathAffinity {
+        PathAffinity::new(&self.flow_paths())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        network::{
+            ecmp::{EcmpGroup, EcmpModel},
+            Flow, FlowId, Network,
+        },
+        opts::SimOpts,
+        testing,
+        units::{Bytes, Nanosecs},
+    };
+
+    #[test]
+    fn affinity_follows_assignments() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (0..400)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(2),
+                size: Bytes::new(1_000),
+                start: Nanosecs::new(i as u64),
+            })
+            .collect();
+        let ecmp = EcmpModel {
+            groups: vec![EcmpGroup {
+                switch: NodeId::new(4),
+                weights: vec![(NodeId::new(6), 3.0), (NodeId::new(7), 1.0)],
+            }],
+            ..EcmpModel::default()
+        };
+        let sims = Network::new(&nodes, &links)?
+            .with_ecmp(ecmp)
+            .into_simulations(flows);
+        let affinity = sims.path_affinity();
+        assert_eq!(affinity.pairs().len(), 1);
+        let pair = affinity.paths(NodeId::new(0), NodeId::new(2)).unwrap();
+        assert_eq!(pair.paths.len(), 2);
+        assert!((pair.paths[0].probability - 0.75).abs() < 0.05);
+        let total = pair.paths.iter().map(|p| p.probability).sum::<f64>();
+        assert!((total - 1.0).abs() < 1e-9);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let nr_first = (0..1000)
+            .filter(|_| {
+                affinity.sample(NodeId::new(0), NodeId::new(2), &mut rng)
+                    == Some(pair.paths[0].edges.as_slice())
+            })
+            .count();
+        assert!((nr_first as f64 / 1000.0 - pair.paths[0].probability).abs() < 0.05);
+        assert!(affinity
+            .sample(NodeId::new(2), NodeId::new(0), &mut rng)
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn foreign_affinities_are_rejected() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flow = Flow {
+            id: FlowId::new(0),
+            src: NodeId::new(0),
+            dst: NodeId::new(2),
+            size: Bytes::new(1_000),
+            start: Nanosecs::ZERO,
+        };
+        let sims = Network::new(&nodes, &links)?.into_simulations(vec![flow]);
+        let affinity = sims.path_affinity();
+        let mut delays = sims.into_delays(
+            SimOpts::builder()
+                .link_sim(testing::FixedDelaySim {
+                    delay_per_pkt: Nanosecs::new(100),
+                })
+                .build(),
+        )?;
+        delays.set_path_affinity(Some(affinity.clone()))?;
+
+        let mut unknown = affinity.clone();
+        unknown.pairs[0].paths[0].edges.push(EdgeIndex::new(1_000));
+        assert!(matches!(
+            delays.set_path_affinity(Some(unknown)),
+            Err(AffinityError::UnknownEdge(_))
+        ));
+        let mut broken = affinity;
+        broken.pairs[0].paths[0].edges.remove(0);
+        assert!(matches!(
+            delays.set_path_affinity(Some(broken)),
+            Err(AffinityError::Discontiguous { .. })
+        ));
+        assert!(delays.path_affinity().is_some());
+        Ok(())
+    }
+}
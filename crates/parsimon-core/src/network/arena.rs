@@ -0,0 +1,96 @@
+//! Flow storage for a [`SimNetwork`](super::SimNetwork). Every flow is stored exactly once, in an
+//! arena sorted by flow ID, and channels refer to their flows by 32-bit position in the arena
+//! rather than by ID. The arena is reference-counted, so cloning a network doesn't copy its flows.
+
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use crate::network::types::{Flow, FlowId};
+
+/// The position of a flow in a [`FlowArena`].
+pub(crate) type FlowIdx = u32;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct FlowArena {
+    // Sorted by flow ID
+    flows: Arc<[Flow]>,
+}
+
+impl FlowArena {
+    /// Creates an arena holding `flows`.
+    ///
+    /// PANICS: Panics if there are more flows than can be indexed by a [`FlowIdx`].
+    pub(crate) fn new(mut flows: Vec<Flow>) -> Self {
+        assert!(
+            flows.len() <= FlowIdx::MAX as usize,
+            "too many flows for a single network"
+        );
+        flows.par_sort_by_key(|f| f.id);
+        Self {
+            flows: flows.into(),
+        }
+    }
+
+    /// Returns the flow at position `idx`.
+    pub(crate) fn get(&self, idx: FlowIdx) -> &Flow {
+        &self.flows[idx as usize]
+    }
+
+    /// Returns the flow with the given ID, if any.
+    pub(crate) fn find(&self, id: FlowId) -> Option<&Flow> {
+        self.flows
+            .binary_search_by_key(&id, |f| f.id)
+            .ok()
+            .map(|i| &self.flows[i])
+    }
+
+    /// Returns the flows at the given positions, in order.
+    pub(crate) fn collect(&self, idxs: &[FlowIdx]) -> Vec<Flow> {
+        idxs.iter().map(|&idx| *self.get(idx)).collect()
+    }
+
+    /// Returns all flows, sorted by ID.
+    pub(crate) fn as_slice(&self) -> &[Flow] {
+        &self.flows
+    }
+
+    /// Returns the number of flows in the arena.
+    pub(crate) fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    /// Returns true if both arenas share the same flows.
+    pub(crate) fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.flows, &other.flows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::NodeId,
+        units::{Bytes, Nanosecs},
+    };
+
+    #[test]
+    fn arenas_are_sorted_by_id() {
+        let flow = |id| Flow {
+            id: FlowId::new(id),
+            src: NodeId::new(0),
+            dst: NodeId::new(1),
+            size: Bytes::new(1000),
+            start: Nanosecs::new(id as u64),
+        };
+        let arena = FlowArena::new(vec![flow(7), flow(2), flow(5)]);
+        let ids = arena.as_slice().iter().map(|f| f.id).collect::<Vec<_>>();
+        assert_eq!(ids, [FlowId::new(2), FlowId::new(5), FlowId::new(7)]);
+        assert_eq!(arena.get(1).id, FlowId::new(5));
+        assert_eq!(arena.find(FlowId::new(7)), Some(&flow(7)));
+        assert_eq!(arena.find(FlowId::new(3)), None);
+        assert_eq!(arena.collect(&[2, 0]), [flow(7), flow(2)]);
+        // Clones share their flows
+        assert!(arena.ptr_eq(&arena.clone()));
+    }
+}
@@ -0,0 +1,176 @@
+//! Attribution of predicted FCTs to propagation, transmission, and queueing. Propagation and
+//! transmission make up a flow's ideal FCT; queueing is the delay sampled from the delay
+//! network on top of it. Breakdowns are obtained per flow with
+//! [`DelayNetwork::predict_breakdown`](crate::network::DelayNetwork::predict_breakdown) or in bulk
+//! with [`DelayNetwork::predict_breakdowns`](crate::network::DelayNetwork::predict_breakdowns),
+//! and bulk results can be [aggregated](aggregate) per traffic class or
+//! [streamed](crate::network::DelayNetwork::predict_into) to a [sink](crate::network::sink).
+
+use std::collections::BTreeMap;
+
+use crate::{network::types::Flow, units::Nanosecs};
+
+/// A predicted FCT broken down into its components.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct LatencyBreakdown {
+    /// The propagation delay along the path.
+    pub propagation: Nanosecs,
+    /// The transmission delay, i.e., store-and-forward of the first packet on every hop plus
+    /// serialization of the rest at the bottleneck, including expected retransmissions.
+    pub transmission: Nanosecs,
+    /// The queueing delay sampled from the delay network.
+    pub queueing: Nanosecs,
+}
+
+impl LatencyBreakdown {
+    /// Returns the predicted FCT.
+    pub fn total(&self) -> Nanosecs {
+        self.propagation + self.transmission + self.queueing
+    }
+
+    /// Returns the fraction of the predicted FCT spent queueing, or 0 if the FCT is zero.
+    pub fn queueing_fraction(&self) -> f64 {
+        let total = self.total().into_f64();
+        if total == 0.0 {
+            0.0
+        } else {
+            self.queueing.into_f64() / total
+        }
+    }
+}
+
+/// A predicted FCT along with the ideal FCT and the delay it is made of. See
+/// [`DelayNetwork::predict_fct`](crate::network::DelayNetwork::predict_fct).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FctPrediction {
+    /// The ideal FCT on an unloaded network.
+    pub ideal: Nanosecs,
+    /// The delay sampled from the delay network.
+    pub delay: Nanosecs,
+    /// The predicted FCT, i.e., the ideal FCT plus the delay.
+    pub fct: Nanosecs,
+}
+
+impl FctPrediction {
+    /// Returns the predicted slowdown, i.e., the predicted FCT divided by the ideal FCT.
+    pub fn slowdown(&self) -> f64 {
+        self.fct.into_f64() / self.ideal.into_f64()
+    }
+}
+
+/// The breakdown of a single flow's predicted FCT. Flow and breakdown fields are flattened when
+/// serialized, so a list of breakdowns exports as one row per flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FlowBreakdown {
+    /// The flow.
+    #[serde(flatten)]
+    pub flow: Flow,
+    /// The breakdown of its predicted FCT.
+    #[serde(flatten)]
+    pub breakdown: LatencyBreakdown,
+}
+
+/// Aggregate breakdowns of the flows in a traffic class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ClassBreakdown {
+    /// The number of flows in the class.
+    pub nr_flows: usize,
+    /// The mean of each component.
+    pub mean: LatencyBreakdown,
+    /// The breakdown of the flow whose predicted FCT is the class's 99th percentile.
+    pub p99: LatencyBreakdown,
+}
+
+/// Aggregates `breakdowns` per traffic class, where `class_of` maps each flow to its class (e.g.,
+/// a size bucket or a client).
+pub fn aggregate<K, F>(breakdowns: &[FlowBreakdown], class_of: F) -> BTreeMap<K, ClassBreakdown>
+where
+    K: Ord,
+    F: Fn(&Flow) -> K,
+{
+    let mut classes = BTreeMap::<K, Vec<LatencyBreakdown>>::new();
+    for b in breakdowns {
+        classes
+            .entry(class_of(&b.flow))
+            .or_default()
+            .push(b.breakdown);
+    }
+    classes
+        .into_iter()
+        .map(|(class, mut members)| {
+            let n = members.len();
+            let mean = |component: fn(&LatencyBreakdown) -> Nanosecs| {
+                let sum = members.iter().map(|b| component(b).into_u64()).sum::<u64>();
+                Nanosecs::new(sum / n as u64)
+            };
+            let mean = LatencyBreakdown {
+                propagation: mean(|b| b.propagation),
+                transmission: mean(|b| b.transmission),
+                queueing: mean(|b| b.queueing),
+            };
+            // Same nearest-rank convention as `edist::quantile`
+            members.sort_by_key(|b| b.total());
+            let rank = ((0.99 * n as f64).ceil() as usize).clamp(1, n);
+            let summary = ClassBreakdown {
+                nr_flows: n,
+                mean,
+                p99: members[rank - 1],
+            };
+            (class, summary)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::{FlowId, NodeId},
+        units::Bytes,
+    };
+
+    fn breakdown(id: usize, size: u64, queueing: u64) -> FlowBreakdown {
+        FlowBreakdown {
+            flow: Flow {
+                id: FlowId::new(id),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(size),
+                start: Nanosecs::ZERO,
+            },
+            breakdown: LatencyBreakdown {
+                propagation: Nanosecs::new(1000),
+                transmission: Nanosecs::new(size),
+                queueing: Nanosecs::new(queueing),
+            },
+        }
+    }
+
+    #[test]
+    fn classes_are_aggregated_separately() {
+        let mut breakdowns = (0..100)
+            .map(|i| breakdown(i, 100, i as u64))
+            .collect::<Vec<_>>();
+        breakdowns.push(breakdown(100, 10_000, 0));
+        let classes = aggregate(&breakdowns, |f| f.size > Bytes::new(1000));
+        assert_eq!(classes.len(), 2);
+        let small = classes[&false];
+        assert_eq!(small.nr_flows, 100);
+        assert_eq!(small.mean.queueing, Nanosecs::new(49));
+        assert_eq!(small.p99.queueing, Nanosecs::new(98));
+        assert_eq!(small.p99.propagation, Nanosecs::new(1000));
+        let large = classes[&true];
+        assert_eq!(large.nr_flows, 1);
+        assert_eq!(large.p99.transmission, Nanosecs::new(10_000));
+        assert_eq!(large.p99.queueing_fraction(), 0.0);
+    }
+
+    #[test]
+    fn breakdowns_export_flat_rows() -> anyhow::Result<()> {
+        let row = serde_json::to_value(breakdown(7, 100, 5))?;
+        assert_eq!(row["id"], 7);
+        assert_eq!(row["size"], 100);
+        assert_eq!(row["queueing"], 5);
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+//! Bootstrap confidence intervals for delay quantiles. Link-level delay distributions are built
+//! from a finite number of simulated flows, so quantiles estimated from them are uncertain,
+//! especially in the tail. Resampling every hop's distribution with replacement and recomputing
+//! the end-to-end quantile gives a sense of that uncertainty.
+
+use std::fmt;
+
+use rand::prelude::*;
+use rayon::prelude::*;
+
+use crate::{
+    edist::{self, EDist},
+    network::{types::NodeId, DelayNetwork, TraversableNetwork},
+    routing::RoutingAlgo,
+    units::{Bytes, Nanosecs},
+};
+
+/// Bootstrap options.
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder)]
+pub struct BootstrapOpts {
+    /// The number of bootstrap replicates.
+    #[builder(default = 200)]
+    pub nr_resamples: usize,
+    /// The number of end-to-end samples drawn to estimate the quantile of each replicate.
+    #[builder(default = 1000)]
+    pub nr_samples: usize,
+    /// The confidence level of the interval, in (0, 1).
+    #[builder(default = 0.95)]
+    pub confidence: f64,
+}
+
+impl Default for BootstrapOpts {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// A confidence interval for a delay quantile, obtained with
+/// [`DelayNetwork::quantile_ci`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ConfidenceInterval {
+    /// The quantile estimated from the original distributions.
+    pub estimate: Nanosecs,
+    /// The lower bound of the interval.
+    pub lower: Nanosecs,
+    /// The upper bound of the interval.
+    pub upper: Nanosecs,
+}
+
+impl fmt::Display for ConfidenceInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}, {}]", self.estimate, self.lower, self.upper)
+    }
+}
+
+impl<R> DelayNetwork<R>
+where
+    R: RoutingAlgo,
+{
+    /// Returns a percentile bootstrap confidence interval for the `q`-th quantile of the delay of
+    /// a flow of a particular `size` going from `src` to `dst`, or `None` if there is no path,
+    /// some hop has no data for that size, `q` isn't in [0, 1], or the confidence level isn't in
+    /// (0, 1). A single path is chosen and used for all replicates.
+    pub fn quantile_ci<RNG>(
+        &self,
+        size: Bytes,
+        (src, dst): (NodeId, NodeId),
+        q: f64,
+        opts: &BootstrapOpts,
+        mut rng: RNG,
+    ) -> Option<ConfidenceInterval>
+    where
+        RNG: Rng,
+    {
+        if !is_valid(q, opts) {
+            return None;
+        }
+        let dists = self
+            .edge_indices_between(src, dst, |choices| choices.choose(&mut rng))
+            .map(|e| self.topology.graph[e].dists.for_size(size))
+            .collect::<Option<Vec<_>>>()?;
+        if dists.is_empty() {
+            return None;
+        }
+        let path_quantile = |dists: &[&EDist], rng: &mut RNG| {
+            let samples = (0..opts.nr_samples)
+                .map(|_| self.sample_dists(dists, rng))
+                .collect::<Vec<_>>();
+            edist::quantile(&samples, q)
+        };
+        let estimate = path_quantile(&dists, &mut rng)?;
+        let replicates = (0..opts.nr_resamples)
+            .map(|_| {
+                let resampled = dists
+                    .iter()
+                    .map(|dist| dist.resample(&mut rng))
+                    .collect::<Vec<_>>();
+                path_quantile(&resampled.iter().collect::<Vec<_>>(), &mut rng)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let alpha = 1.0 - opts.confidence;
+        let lower = edist::quantile(&replicates, alpha / 2.0)?;
+        let upper = edist::quantile(&replicates, 1.0 - alpha / 2.0)?;
+        Some(ConfidenceInterval {
+            estimate: self.to_delay(size, estimate),
+            lower: self.to_delay(size, lower),
+            upper: self.to_delay(size, upper),
+        })
+    }
+
+    /// Computes [confidence intervals](Self::quantile_ci) for a batch of `(size, (src, dst))`
+    /// queries in parallel. Each query gets its own random number generator derived from `seed`,
+    /// so results are reproducible and independent of scheduling. Every interval is `None` if `q` or
+    /// the confidence level is invalid.
+    pub fn quantile_cis(
+        &self,
+        queries: &[(Bytes, (NodeId, NodeId))],
+        q: f64,
+        opts: &BootstrapOpts,
+        seed: u64,
+    ) -> Vec<Option<ConfidenceInterval>>
+    where
+        R: Sync,
+    {
+        if !is_valid(q, opts) {
+            return vec![None; queries.len()];
+        }
+        queries
+            .par_iter()
+            .enumerate()
+            .map(|(i, &(size, path))| {
+                let rng = StdRng::seed_from_u64(seed.wrapping_add(i as u64));
+                self.quantile_ci(size, path, q, opts, rng)
+            })
+            .collect()
+    }
+}
+
+fn is_valid(q: f64, opts: &BootstrapOpts) -> bool {
+    (0.0..=1.0).contains(&q) && opts.confidence > 0.0 && opts.confidence < 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        edist::BucketOpts,
+        network::{Flow, FlowId, Network},
+        opts::SimOpts,
+        testing,
+    };
+
+    #[test]
+    fn intervals_bracket_the_estimate() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = vec![Flow {
+            id: FlowId::new(0),
+            src: NodeId::new(0),
+            dst: NodeId::new(1),
+            size: Bytes::new(1000),
+            start: Nanosecs::ZERO,
+        }];
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let mut delays = Network::new(&nodes, &links)?
+            .into_simulations(flows)
+            .into_delays(opts)?;
+        let values = (0..100).map(|i| i as f64).collect::<Vec<_>>();
+        for chan in delays.topology.graph.edge_weights_mut() {
+            chan.dists
+                .fill(&values, |_| Bytes::new(1000), |v| v, BucketOpts::default())?;
+        }
+        let path = (NodeId::new(0), NodeId::new(1));
+        let opts = BootstrapOpts::builder().nr_resamples(50).build();
+        let ci = delays
+            .quantile_ci(
+                Bytes::new(1000),
+                path,
+                0.99,
+                &opts,
+                StdRng::seed_from_u64(0),
+            )
+            .unwrap();
+        assert!(ci.lower <= ci.estimate && ci.estimate <= ci.upper);
+        assert!(ci.lower < ci.upper);
+        assert!(ci.upper <= Nanosecs::new(198));
+
+        let queries = [(Bytes::new(1000), path), (Bytes::new(1000), path)];
+        let cis = delays.quantile_cis(&queries, 0.99, &opts, 7);
+        assert_eq!(cis, delays.quantile_cis(&queries, 0.99, &opts, 7));
+        assert!(cis.iter().all(Option::is_some));
+        assert_eq!(
+            delays.quantile_ci(
+                Bytes::new(1000),
+                (NodeId::new(0), NodeId::new(0)),
+                0.5,
+                &opts,
+                StdRng::seed_from_u64(0)
+            ),
+            None
+        );
+        for (q, confidence) in [(1.1, 0.95), (f64::NAN, 0.95), (0.99, 1.0), (0.99, f64::NAN)] {
+            let opts = BootstrapOpts::builder()
+                .nr_resamples(5)
+                .confidence(confidence)
+                .build();
+            let rng = StdRng::seed_from_u64(0);
+            assert_eq!(
+                delays.quantile_ci(Bytes::new(1000), path, q, &opts, rng),
+                None
+            );
+            assert_eq!(delays.quantile_cis(&queries, q, &opts, 7), [None, None]);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+//! Cross-validation of clusterings. Clustering saves link simulations by filling every member of a
+//! cluster with its representative's delays. [`SimNetwork::cross_validate`] estimates what that
+//! costs in accuracy: it simulates a random sample of non-representative members as well, and
+//! compares the FCT slowdowns of each member's own flows to those of its representative's flows.
+//!
+//! Comparing reports across clustering thresholds shows how far a workload can be clustered before
+//! the error from clustering outweighs the simulations saved.
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::{
+    cluster::Cluster,
+    eval::SlowdownQuantiles,
+    linksim::LinkSim,
+    network::{EdgeIndex, FctRecord, SimNetwork, SimNetworkError},
+    opts::SimOpts,
+    routing::RoutingAlgo,
+    units::Nanosecs,
+};
+
+/// Options for [`SimNetwork::cross_validate`].
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder)]
+pub struct CrossValOpts {
+    /// The maximum number of non-representative members simulated.
+    #[builder(default = 100)]
+    pub nr_members: usize,
+    /// The seed of the random number generator members are sampled with.
+    #[builder(default)]
+    pub seed: u64,
+}
+
+impl Default for CrossValOpts {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// The comparison of a single member with its representative.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct MemberReport {
+    /// The sampled member.
+    pub member: EdgeIndex,
+    /// The representative of the member's cluster.
+    pub representative: EdgeIndex,
+    /// The number of flows on the member.
+    pub nr_flows: usize,
+    /// Slowdown quantiles of the member's own flows.
+    pub actual: SlowdownQuantiles,
+    /// Slowdown quantiles of the representative's flows, which the member is filled with.
+    pub predicted: SlowdownQuantiles,
+    /// The absolute error of each representative quantile, relative to the member's own.
+    pub errors: SlowdownQuantiles,
+}
+
+/// A cross-validation report, produced by [`SimNetwork::cross_validate`].
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CrossValReport {
+    /// Per-member comparisons, in order of member. Members whose simulations yield no slowdowns
+    /// are omitted.
+    pub members: Vec<MemberReport>,
+    /// The weighted mean absolute percentage error of each quantile across members, i.e., the
+    /// sum of absolute errors divided by the sum of the members' own values.
+    pub wmape: Option<SlowdownQuantiles>,
+    /// The number of non-representative members with flows, which members are sampled from.
+    pub nr_candidates: usize,
+}
+
+impl<R> SimNetwork<R>
+where
+    R: RoutingAlgo + Sync,
+{
+    /// Simulates up to `cv.nr_members` randomly chosen non-representative cluster members along
+    /// with their representatives, and compares each member's slowdowns to its representative's.
+    /// See [`crate::network::crossval`].
+    pub fn cross_validate<S>(
+        &self,
+        opts: &SimOpts<S>,
+        cv: &CrossValOpts,
+    ) -> Result<CrossValReport, SimNetworkError>
+    where
+        S: LinkSim + Sync,
+    {
+        let mut candidates = self
+            .clusters
+            .iter()
+            .flat_map(|c| {
+                let representative = c.representative();
+                c.members()
+                    .filter(move |&&m| m != representative)
+                    .map(move |&m| (m, representative))
+            })
+            .filter(|&(m, _)| !self.topology.graph[m].flows.is_empty())
+            .collect::<Vec<_>>();
+        candidates.sort();
+        let mut rng = StdRng::seed_from_u64(cv.seed);
+        let mut sampled = candidates
+            .choose_multiple(&mut rng, cv.nr_members)
+            .copied()
+            .collect::<Vec<_>>();
+        sampled.sort();
+        let mut edges = sampled
+            .iter()
+            .flat_map(|&(m, r)| [m, r])
+            .collect::<Vec<_>>();
+        edges.sort();
+        edges.dedup();
+        let clusters = edges
+            .iter()
+            .map(|&e| Cluster::new(e, [e].into_iter().collect()))
+            .collect::<Vec<_>>();
+        let (eidx2data, ..) = self.simulate(opts, &clusters)?;
+        let slowdowns = |e: EdgeIndex| {
+            let data = eidx2data.get(&e).map(|data| &data[..]).unwrap_or_default();
+            SlowdownQuantiles::of(&slowdowns(data))
+        };
+        let members = sampled
+            .into_iter()
+            .filter_map(|(member, representative)| {
+                let actual = slowdowns(member)?;
+                let predicted = slowdowns(representative)?;
+                let error = |a: f64, p: f64| (p - a).abs() / a;
+                Some(MemberReport {
+                    member,
+                    representative,
+                    nr_flows: self.topology.graph[member].flows.len(),
+                    actual,
+                    predicted,
+                    errors: SlowdownQuantiles {
+                        p50: error(actual.p50, predicted.p50),
+                        p95: error(actual.p95, predicted.p95),
+                        p99: error(actual.p99, predicted.p99),
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+        let wmape = (!members.is_empty()).then(|| {
+            let wmape = |f: fn(&SlowdownQuantiles) -> f64| {
+                let abs_errors = members
+                    .iter()
+                    .map(|m| (f(&m.predicted) - f(&m.actual)).abs())
+                    .sum::<f64>();
+                abs_errors / members.iter().map(|m| f(&m.actual)).sum::<f64>()
+            };
+            SlowdownQuantiles {
+                p50: wmape(|q| q.p50),
+                p95: wmape(|q| q.p95),
+                p99: wmape(|q| q.p99),
+            }
+        });
+        Ok(CrossValReport {
+            members,
+            wmape,
+            nr_candidates: candidates.len(),
+        })
+    }
+}
+
+// Slowdowns of the records with an ideal FCT
+fn slowdowns(data: &[FctRecord]) -> Vec<f64> {
+    data.iter()
+        .filter(|rec| rec.ideal > Nanosecs::ZERO)
+        .map(FctRecord::slowdown)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        linksim::{LinkSimResult, LinkSimSpec},
+        network::{Flow, FlowId, Network, NodeId},
+        testing,
+        units::Bytes,
+    };
+
+    // Slowdowns grow with the number of flows on the link
+    struct LoadSim;
+
+    impl LinkSim for LoadSim {
+        fn name(&self) -> String {
+            "load".into()
+        }
+
+        fn simulate(&self, spec: LinkSimSpec) -> LinkSimResult {
+            let slowdown = 1.0 + spec.flows.len() as f64 / 10.0;
+            Ok(spec
+                .flows
+                .iter()
+                .map(|f| FctRecord {
+                    id: f.id,
+                    size: f.size,
+                    start: f.start,
+                    fct: Nanosecs::new(1_000).scale_by(slowdown),
+                    ideal: Nanosecs::new(1_000),
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn cross_validation_compares_members_to_representatives() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        // Host 0 sends twice as many flows as host 1
+        let flows = (0..30)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(usize::from(i % 3 == 2)),
+                dst: NodeId::new(2),
+                size: Bytes::new(1_000),
+                start: Nanosecs::new(i as u64 * 1_000),
+            })
+            .collect();
+        let mut sims = Network::new(&nodes, &links)?.into_simulations(flows);
+        let opts = SimOpts::builder().link_sim(LoadSim).build();
+        let cv = CrossValOpts::default();
+
+        // Without clustering, there is nothing to validate
+        let report = sims.cross_validate(&opts, &cv)?;
+        assert_eq!(report.nr_candidates, 0);
+        assert_eq!(report.wmape, None);
+
+        // Cluster the uplinks of both hosts, represented by host 0's
+        let uplink = |src| {
+            sims.edge_indices()
+                .find(|&e| sims.topology.graph[e].src == NodeId::new(src))
+                .unwrap()
+        };
+        let (e0, e1) = (uplink(0), uplink(1));
+        let mut clusters = sims
+            .edge_indices()
+            .filter(|&e| e != e0 && e != e1)
+            .map(|e| Cluster::new(e, [e].into_iter().collect()))
+            .collect::<Vec<_>>();
+        clusters.push(Cluster::new(e0, [e0, e1].into_iter().collect()));
+        sims.set_clusters(clusters)?;
+        let report = sims.cross_validate(&opts, &cv)?;
+        assert_eq!(report.nr_candidates, 1);
+        let member = &report.members[0];
+        assert_eq!((member.member, member.representative), (e1, e0));
+        assert_eq!(member.nr_flows, 10);
+        assert!((member.actual.p50 - 2.0).abs() < 1e-9);
+        assert!((member.predicted.p50 - 3.0).abs() < 1e-9);
+        assert!((member.errors.p99 - 0.5).abs() < 1e-9);
+        assert_eq!(report.wmape.map(|q| q.p50), Some(member.errors.p50));
+        Ok(())
+    }
+}
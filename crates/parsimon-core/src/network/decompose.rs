@@ -0,0 +1,345 @@
+//! Rack-level decomposition. By default, every link is simulated on its own, which misses the
+//! correlation between the host down-channels of a ToR under incast: flows converging on one rack
+//! contend for the ToR's buffer and its upstream links together. With a coarser
+//! [`Decomposition`], the host down-channels of a ToR are simulated together as one unit, in a
+//! single link-level simulation with one bottleneck per down-channel (see
+//! [`LinkSimSpec::other_bottlenecks`](crate::linksim::LinkSimSpec::other_bottlenecks)). The
+//! results are then split among the down-channels by flow destination.
+//!
+//! Rack-level units bypass clustering and deduplication: every down-channel in a unit gets its
+//! own results. A ToR whose hosts both send and receive flows through it can't be described as a
+//! single simulation, so such ToRs fall back to per-link simulations.
+
+use std::collections::{HashMap, HashSet};
+
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{
+    cluster::Cluster,
+    linksim::{LinkSimDesc, LinkSimDescError},
+    network::{arena::FlowIdx, EdgeIndex, FctRecord, NodeKind, SimNetwork},
+    opts::AckModel,
+    routing::RoutingAlgo,
+};
+
+/// The granularity of link-level simulations.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Decomposition {
+    /// Simulate every link on its own.
+    #[default]
+    PerLink,
+    /// Simulate the host down-channels of every ToR together.
+    PerRack,
+    /// Simulate the host down-channels of the ToRs of the given edges together, and every other
+    /// link on its own. Edges other than host down-channels are ignored.
+    Edges(Vec<EdgeIndex>),
+}
+
+/// The rack-level units of a network. Each unit is identified by its lead edge, whose descriptor
+/// describes the whole unit.
+#[derive(Debug, Default)]
+pub(crate) struct RackUnits {
+    // Lead edge -> all edges of the unit, lead first
+    units: FxHashMap<EdgeIndex, Vec<EdgeIndex>>,
+    lead_of: FxHashMap<EdgeIndex, EdgeIndex>,
+}
+
+impl RackUnits {
+    /// Returns the edges of the unit led by `lead`, if `lead` leads one.
+    pub(crate) fn unit(&self, lead: EdgeIndex) -> Option<&[EdgeIndex]> {
+        self.units.get(&lead).map(|edges| &edges[..])
+    }
+
+    /// Splits `clusters` so that every edge of a unit is a singleton cluster, and its results
+    /// aren't overwritten by its former representative's. Other members keep their
+    /// representative, even if it's now simulated as part of a unit.
+    pub(crate) fn split_clusters(&self, clusters: &[Cluster]) -> Vec<Cluster> {
+        if self.units.is_empty() {
+            return clusters.to_vec();
+        }
+        let mut split = Vec::new();
+        for c in clusters {
+            let (in_units, rest): (Vec<_>, Vec<_>) =
+                c.members().partition(|m| self.lead_of.contains_key(m));
+            split.extend(
+                in_units
+                    .into_iter()
+                    .map(|&m| Cluster::new(m, [m].into_iter().collect())),
+            );
+            if !rest.is_empty() {
+                let rest = rest.into_iter().copied().collect::<HashSet<_>>();
+                split.push(Cluster::new(c.representative(), rest));
+            }
+        }
+        split
+    }
+
+    /// Partitions clusters into those simulated per link and one singleton cluster per unit
+    /// needed by the others, led by the unit's lead.
+    pub(crate) fn partition(&self, clusters: &[Cluster]) -> (Vec<Cluster>, Vec<Cluster>) {
+        let (in_units, per_link): (Vec<_>, Vec<_>) = clusters
+            .iter()
+            .cloned()
+            .partition(|c| self.lead_of.contains_key(&c.representative()));
+        let mut leads = in_units
+            .iter()
+            .map(|c| self.lead_of[&c.representative()])
+            .collect::<Vec<_>>();
+        leads.sort();
+        leads.dedup();
+        let units = leads
+            .into_iter()
+            .map(|lead| Cluster::new(lead, [lead].into_iter().collect()))
+            .collect();
+        (per_link, units)
+    }
+
+    /// Splits the results of each unit's lead among the unit's edges by flow destination.
+    pub(crate) fn split_results<R>(
+        &self,
+        network: &SimNetwork<R>,
+        eidx2data: &mut HashMap<EdgeIndex, Vec<FctRecord>>,
+    ) {
+        for (lead, edges) in &self.units {
+            let Some(records) = eidx2data.remove(lead) else {
+                continue;
+            };
+            let edge_to = edges
+                .iter()
+                .map(|&e| (network.topology.graph[e].dst, e))
+                .collect::<FxHashMap<_, _>>();
+            for &e in edges {
+                eidx2data.insert(e, Vec::new());
+            }
+            for rec in records {
+                let dst = network.flows.find(rec.id).map(|f| f.dst);
+                if let Some(e) = dst.and_then(|dst| edge_to.get(&dst)) {
+                    eidx2data.get_mut(e).unwrap().push(rec);
+                }
+            }
+        }
+    }
+}
+
+impl<R> SimNetwork<R>
+where
+    R: RoutingAlgo + Sync,
+{
+    /// Returns the rack-level units of the network under `decomposition`. Units with a single
+    /// edge are simulated per link.
+    pub(crate) fn rack_units(&self, decomposition: &Decomposition) -> RackUnits {
+        let g = &self.topology.graph;
+        let is_down_channel = |e: EdgeIndex| {
+            let chan = &g[e];
+            let kind = |id| self.node(id).map(|n| n.kind);
+            kind(chan.src) == Some(NodeKind::Switch) && kind(chan.dst) == Some(NodeKind::Host)
+        };
+        let tors = match decomposition {
+            Decomposition::PerLink => return RackUnits::default(),
+            Decomposition::PerRack => g
+                .edge_indices()
+                .filter(|&e| is_down_channel(e))
+                .map(|e| g[e].src)
+                .collect::<FxHashSet<_>>(),
+            Decomposition::Edges(edges) => edges
+                .iter()
+                .filter(|&&e| e.index() < g.edge_count() && is_down_channel(e))
+                .map(|&e| g[e].src)
+                .collect(),
+        };
+        let mut units = RackUnits::default();
+        for tor in tors {
+            let mut edges = g
+                .edge_indices()
+                .filter(|&e| g[e].src == tor && is_down_channel(e) && g[e].nr_flows() > 0)
+                .collect::<Vec<_>>();
+            if edges.len() < 2 {
+                continue;
+            }
+            edges.sort();
+            let srcs = edges
+                .iter()
+                .flat_map(|&e| g[e].flow_srcs.iter())
+                .collect::<FxHashSet<_>>();
+            if edges.iter().any(|&e| srcs.contains(&g[e].dst)) {
+                continue;
+            }
+            let lead = edges[0];
+            for &e in &edges {
+                units.lead_of.insert(e, lead);
+            }
+            units.units.insert(lead, edges);
+        }
+        units
+    }
+
+    /// Returns a descriptor for the rack-level simulation of `edges`, which must be host
+    /// down-channels of the same ToR. The first edge is the bottleneck, and the others are
+    /// further bottlenecks.
+    pub(crate) fn rack_sim_desc(
+        &self,
+        edges: &[EdgeIndex],
+        ack: AckModel,
+    ) -> Result<Option<LinkSimDesc>, LinkSimDescError> {
+        let mut descs = Vec::with_capacity(edges.len());
+        for &e in edges {
+            if let Some(desc) = self.link_sim_desc_with(e, ack)? {
+                descs.push(desc);
+            }
+        }
+        let mut descs = descs.into_iter();
+        let Some(mut unit) = descs.next() else {
+            return Ok(None);
+        };
+        let mut nodes = unit.nodes.iter().map(|n| n.id).collect::<FxHashSet<_>>();
+        let mut links = unit
+            .other_links
+            .iter()
+            .map(|l| (l.from, l.to))
+            .collect::<FxHashSet<_>>();
+        for desc in descs {
+            unit.other_bottlenecks.push(desc.bottleneck);
+            for n in desc.nodes {
+                if nodes.insert(n.id) {
+                    unit.nodes.push(n);
+                }
+            }
+            // Sources shared between edges get the same access link from each
+            for l in desc.other_links {
+                if links.insert((l.from, l.to)) {
+                    unit.other_links.push(l);
+                }
+            }
+        }
+        unit.flows = self
+            .unit_flows(edges)
+            .into_iter()
+            .map(|idx| self.flows.get(idx).id)
+            .collect();
+        Ok(Some(unit))
+    }
+
+    /// Returns the flows of `edges`, sorted by start time.
+    pub(crate) fn unit_flows(&self, edges: &[EdgeIndex]) -> Vec<FlowIdx> {
+        let mut idxs = edges
+            .iter()
+            .flat_map(|&e| self.topology.graph[e].flows.iter().copied())
+            .collect::<Vec<_>>();
+        idxs.sort_by_key(|&idx| (self.flows.get(idx).start, idx));
+        idxs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        linksim::{LinkSim, LinkSimResult, LinkSimSpec},
+        network::{Flow, FlowId, Network, NodeId},
+        opts::SimOpts,
+        testing,
+        units::{Bytes, Nanosecs},
+    };
+
+    /// Delays every flow by 1 us per bottleneck in its simulation.
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    struct BottleneckCountSim;
+
+    impl LinkSim for BottleneckCountSim {
+        fn name(&self) -> String {
+            "bottleneck-count".into()
+        }
+
+        fn simulate(&self, spec: LinkSimSpec) -> LinkSimResult {
+            spec.validate()?;
+            let nr_bottlenecks = spec.bottlenecks().count() as u64;
+            Ok(spec
+                .flows
+                .iter()
+                .map(|f| FctRecord {
+                    id: f.id,
+                    size: f.size,
+                    start: f.start,
+                    fct: Nanosecs::new(nr_bottlenecks * 1000),
+                    ideal: Nanosecs::ZERO,
+                })
+                .collect())
+        }
+    }
+
+    // Hosts 2 and 3 send to hosts 0 and 1, which are under ToR 4
+    fn incast(extra: &[(usize, usize)]) -> Vec<Flow> {
+        [(2, 0), (3, 0), (2, 1), (3, 1)]
+            .iter()
+            .chain(extra)
+            .enumerate()
+            .map(|(i, &(src, dst))| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(src),
+                dst: NodeId::new(dst),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn down_channels_of_a_tor_form_a_unit() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let sims = Network::new(&nodes, &links)?.into_simulations(incast(&[]));
+        let id = NodeId::new;
+        let (e40, e41) = (
+            sims.find_edge(id(4), id(0)).unwrap(),
+            sims.find_edge(id(4), id(1)).unwrap(),
+        );
+        assert!(sims.rack_units(&Decomposition::PerLink).units.is_empty());
+        let units = sims.rack_units(&Decomposition::Edges(vec![e41]));
+        let lead = e40.min(e41);
+        assert_eq!(units.unit(lead).unwrap(), [e40.min(e41), e40.max(e41)]);
+        assert_eq!(units.units.len(), 1);
+
+        let desc = sims
+            .rack_sim_desc(units.unit(lead).unwrap(), AckModel::default())?
+            .unwrap();
+        assert_eq!(desc.other_bottlenecks.len(), 1);
+        assert_eq!(desc.flows.len(), 4);
+        assert_eq!(desc.nodes.len(), 5);
+        // Both bottlenecks share the access links from the sources
+        assert_eq!(desc.other_links.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn unit_results_are_split_by_destination() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let sims = Network::new(&nodes, &links)?.into_simulations(incast(&[]));
+        let id = NodeId::new;
+        let e40 = sims.find_edge(id(4), id(0)).unwrap();
+        let delays = |decomposition| {
+            let opts = SimOpts::builder()
+                .link_sim(BottleneckCountSim)
+                .decomposition(decomposition)
+                .build();
+            sims.clone().into_delays(opts)
+        };
+        let per_link = delays(Decomposition::PerLink)?;
+        let per_rack = delays(Decomposition::PerRack)?;
+        let quantile =
+            |d: &crate::network::DelayNetwork, e| d.edge_quantile(e, Bytes::new(1000), 1.0);
+        assert_eq!(quantile(&per_link, e40), Some(Nanosecs::new(1000)));
+        assert_eq!(quantile(&per_rack, e40), Some(Nanosecs::new(2000)));
+        // Only the down-channels of ToR 4 are simulated together
+        let e25 = sims.find_edge(id(2), id(5)).unwrap();
+        assert_eq!(quantile(&per_rack, e25), Some(Nanosecs::new(1000)));
+        Ok(())
+    }
+
+    #[test]
+    fn intra_rack_senders_fall_back_to_per_link() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        // Host 0 sends to host 1 through ToR 4
+        let sims = Network::new(&nodes, &links)?.into_simulations(incast(&[(0, 1)]));
+        assert!(sims.rack_units(&Decomposition::PerRack).units.is_empty());
+        Ok(())
+    }
+}
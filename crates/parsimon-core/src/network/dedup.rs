@@ -0,0 +1,296 @@
+//! Deduplication of link simulations. On symmetric topologies with symmetric workloads, distinct
+//! cluster representatives often have link-level simulations that are identical up to node and
+//! flow IDs. Such simulations are run once, and their results are shared.
+
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+};
+
+use rayon::prelude::*;
+use rustc_hash::{FxHashMap, FxHasher};
+
+use crate::{
+    cluster::Cluster,
+    linksim::{LinkSimDesc, LinkSimLink, LinkSimNodeKind},
+    network::{EdgeIndex, FctRecord, FlowId, NodeId, SimNetwork, SimNetworkError},
+    opts::AckModel,
+    routing::RoutingAlgo,
+};
+
+/// Statistics about the deduplication of link simulations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DedupStats {
+    /// The number of cluster representatives.
+    pub nr_representatives: usize,
+    /// The number of link simulations actually run.
+    pub nr_simulated: usize,
+}
+
+impl DedupStats {
+    /// Returns the number of representatives whose simulation was shared with another one.
+    pub fn nr_deduplicated(&self) -> usize {
+        self.nr_representatives - self.nr_simulated
+    }
+
+    /// Returns the fraction of representatives whose simulation was shared with another one.
+    pub fn ratio(&self) -> f64 {
+        if self.nr_representatives == 0 {
+            0.0
+        } else {
+            self.nr_deduplicated() as f64 / self.nr_representatives as f64
+        }
+    }
+}
+
+// A representative whose simulation is identical to that of `original`.
+#[derive(Debug)]
+pub(super) struct Duplicate {
+    edge: EdgeIndex,
+    original: EdgeIndex,
+    // Maps flows of `original` to the corresponding flows of `edge`
+    flows: FxHashMap<FlowId, FlowId>,
+}
+
+impl<R> SimNetwork<R>
+where
+    R: RoutingAlgo + Sync,
+{
+    // Splits `clusters` into those whose representatives must be simulated and the duplicates of
+    // those.
+    pub(super) fn dedup_clusters(
+        &self,
+        clusters: &[Cluster],
+        ack: AckModel,
+    ) -> Result<(Vec<Cluster>, Vec<Duplicate>), SimNetworkError> {
+        let descs = clusters
+            .par_iter()
+            .map(|c| {
+                let desc = self.link_sim_desc_with(c.representative(), ack)?;
+                let hash = desc.as_ref().map(|desc| {
+                    let mut hasher = FxHasher::default();
+                    self.canonical_key(desc).hash(&mut hasher);
+                    hasher.finish()
+                });
+                Ok((desc, hash))
+            })
+            .collect::<Result<Vec<_>, SimNetworkError>>()?;
+        // Representatives whose descriptors hash the same are compared in full, in cluster order
+        let mut by_hash = HashMap::<u64, Vec<usize>>::new();
+        for (i, (_, hash)) in descs.iter().enumerate() {
+            if let Some(hash) = hash {
+                by_hash.entry(*hash).or_default().push(i);
+            }
+        }
+        let mut is_duplicate = vec![false; clusters.len()];
+        let mut duplicates = Vec::new();
+        for group in by_hash.into_values().filter(|g| g.len() > 1) {
+            let mut originals = Vec::<(usize, Vec<u64>)>::new();
+            for i in group {
+                let desc = descs[i].0.as_ref().unwrap();
+                let key = self.canonical_key(desc);
+                match originals.iter().find(|(_, k)| *k == key) {
+                    Some(&(j, _)) => {
+                        let original = descs[j].0.as_ref().unwrap();
+                        is_duplicate[i] = true;
+                        duplicates.push(Duplicate {
+                            edge: clusters[i].representative(),
+                            original: clusters[j].representative(),
+                            flows: original
+                                .flows
+                                .iter()
+                                .copied()
+                                .zip(desc.flows.iter().copied())
+                                .collect(),
+                        });
+                    }
+                    None => originals.push((i, key)),
+                }
+            }
+        }
+        let unique = clusters
+            .iter()
+            .zip(is_duplicate)
+            .filter(|(_, is_duplicate)| !is_duplicate)
+            .map(|(c, _)| c.clone())
+            .collect();
+        Ok((unique, duplicates))
+    }
+
+    // Encodes a descriptor with nodes relabeled in order of first appearance and without flow IDs.
+    // Two descriptors with the same key describe the same simulation.
+    fn canonical_key(&self, desc: &LinkSimDesc) -> Vec<u64> {
+        let mut labels = FxHashMap::<NodeId, u64>::default();
+        let mut label = |id: NodeId| {
+            let next = labels.len() as u64;
+            *labels.entry(id).or_insert(next)
+        };
+        let mut key = Vec::with_capacity(4 * desc.flows.len() + 6 * (desc.other_links.len() + 1));
+        key.extend(encode_link(&desc.bottleneck, &mut label));
+        for id in &desc.flows {
+            let f = self.flows.find(*id).unwrap();
+            key.extend([
+                label(f.src),
+                label(f.dst),
+                f.size.into_u64(),
+                f.start.into_u64(),
+            ]);
+        }
+        let mut others = desc
+            .other_links
+            .iter()
+            .map(|l| encode_link(l, &mut label))
+            .collect::<Vec<_>>();
+        others.sort();
+        key.extend(others.into_iter().flatten());
+        let mut nodes = desc
+            .nodes
+            .iter()
+            .map(|n| {
+                let kind = match n.kind {
+                    LinkSimNodeKind::Source => 0,
+                    LinkSimNodeKind::Destination => 1,
+                    LinkSimNodeKind::Switch => 2,
+                };
+                [label(n.id), kind]
+            })
+            .collect::<Vec<_>>();
+        nodes.sort();
+        key.extend(nodes.into_iter().flatten());
+        key
+    }
+}
+
+fn encode_link(l: &LinkSimLink, label: &mut impl FnMut(NodeId) -> u64) -> [u64; 6] {
+    [
+        label(l.from),
+        label(l.to),
+        l.total_bandwidth.into_u64(),
+        l.available_bandwidth.into_u64(),
+        l.delay.into_u64(),
+        l.loss_rate.to_bits(),
+    ]
+}
+
+// Fills in the results of duplicate simulations from those of their originals. Originals without
+// results, e.g., because their simulations failed, leave their duplicates without results too.
+pub(super) fn share_results(
+    eidx2data: &mut HashMap<EdgeIndex, Vec<FctRecord>>,
+    duplicates: &[Duplicate],
+) -> Result<(), SimNetworkError> {
+    for dup in duplicates {
+        let Some(original) = eidx2data.get(&dup.original) else {
+            continue;
+        };
+        let records = original
+            .iter()
+            .map(|rec| {
+                let id = dup.flows.get(&rec.id).ok_or(SimNetworkError::UnknownFlow {
+                    edge: dup.original,
+                    id: rec.id,
+                })?;
+                Ok(FctRecord { id: *id, ..*rec })
+            })
+            .collect::<Result<_, SimNetworkError>>()?;
+        eidx2data.insert(dup.edge, records);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        linksim::{LinkSim, LinkSimResult, LinkSimSpec},
+        network::{Flow, Network},
+        opts::SimOpts,
+        records::Retention,
+        testing,
+        units::{Bytes, Nanosecs},
+    };
+
+    // Returns a record for a flow which isn't in the simulation
+    struct StraySim;
+
+    impl LinkSim for StraySim {
+        fn name(&self) -> String {
+            "stray".into()
+        }
+
+        fn simulate(&self, spec: LinkSimSpec) -> LinkSimResult {
+            Ok(spec
+                .flows
+                .iter()
+                .map(|f| FctRecord {
+                    id: FlowId::new(999),
+                    size: f.size,
+                    start: f.start,
+                    fct: Nanosecs::new(1000),
+                    ideal: Nanosecs::ZERO,
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn symmetric_links_are_simulated_once() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flow = |id, src, dst| Flow {
+            id: FlowId::new(id),
+            src: NodeId::new(src),
+            dst: NodeId::new(dst),
+            size: Bytes::new(1000),
+            start: Nanosecs::new(1000),
+        };
+        let sims =
+            Network::new(&nodes, &links)?.into_simulations(vec![flow(0, 0, 2), flow(1, 1, 3)]);
+        let opts = |dedup| {
+            SimOpts::builder()
+                .link_sim(testing::FixedDelaySim {
+                    delay_per_pkt: Nanosecs::new(100),
+                })
+                .retention(Retention::Memory)
+                .dedup(dedup)
+                .build()
+        };
+
+        let delays = sims.clone().into_delays(opts(true))?;
+        let stats = delays.dedup_stats();
+        assert_eq!(stats.nr_representatives, sims.edge_indices().count());
+        assert!(stats.nr_deduplicated() >= 2);
+        assert!(stats.ratio() > 0.0);
+        // Both host uplinks have results for their own flows
+        let records = delays.records().unwrap();
+        for (src, id) in [(0, 0), (1, 1)] {
+            let e = sims.find_edge(NodeId::new(src), NodeId::new(4)).unwrap();
+            let recs = records.get(e)?.unwrap();
+            assert_eq!(recs.len(), 1);
+            assert_eq!(recs[0].id, FlowId::new(id));
+        }
+
+        let delays = sims.into_delays(opts(false))?;
+        assert_eq!(delays.dedup_stats().nr_deduplicated(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn records_of_unknown_flows_are_rejected() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (0..2)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i),
+                dst: NodeId::new(i + 2),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(1000),
+            })
+            .collect();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows);
+        let opts = SimOpts::builder().link_sim(StraySim).dedup(true).build();
+        assert!(matches!(
+            sims.into_delays(opts),
+            Err(SimNetworkError::UnknownFlow { .. })
+        ));
+        Ok(())
+    }
+}
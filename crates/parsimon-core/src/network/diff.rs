@@ -0,0 +1,302 @@
+//! Comparisons of two delay networks, e.g., before and after a proposed topology change. A
+//! [`DelayDiff`] reports a delay quantile on every link and for every queried path in both
+//! networks, so regressions can be spotted without querying each network by hand.
+//!
+//! Links are matched by their endpoints. Links present in only one network, such as added or
+//! removed links, are reported with a quantile on one side only.
+
+use rand::{rngs::StdRng, SeedableRng};
+use rustc_hash::FxHashMap;
+
+use crate::{
+    network::{DelayNetwork, NodeId},
+    routing::RoutingAlgo,
+    units::{Bytes, Nanosecs},
+};
+
+/// Options for [`DelayNetwork::diff`].
+#[derive(Debug, Clone, typed_builder::TypedBuilder)]
+pub struct DiffOpts {
+    /// The delay quantile compared, in [0, 1].
+    #[builder(default = 0.99)]
+    pub quantile: f64,
+    /// The flow sizes links are compared at.
+    #[builder(default = vec![Bytes::new(1_000), Bytes::new(100_000), Bytes::new(10_000_000)])]
+    pub sizes: Vec<Bytes>,
+    /// The number of end-to-end samples drawn per path and network.
+    #[builder(default = 1_000)]
+    pub nr_samples: usize,
+    /// The seed of the random number generator both networks sample paths with.
+    #[builder(default)]
+    pub seed: u64,
+}
+
+impl Default for DiffOpts {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl DiffOpts {
+    /// Checks that the options are valid.
+    pub fn validate(&self) -> Result<(), DiffError> {
+        if !(0.0..=1.0).contains(&self.quantile) {
+            return Err(DiffError::InvalidQuantile(self.quantile));
+        }
+        Ok(())
+    }
+}
+
+/// Error type for [`DelayNetwork::diff`].
+#[derive(Debug, thiserror::Error)]
+pub enum DiffError {
+    /// The compared quantile isn't in [0, 1].
+    #[error("Invalid quantile {0}: must be in [0, 1]")]
+    InvalidQuantile(f64),
+}
+
+/// A comparison of two delay networks, obtained with [`DelayNetwork::diff`].
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DelayDiff {
+    /// The compared quantile.
+    pub quantile: f64,
+    /// Per-link comparisons, sorted by endpoints and then by size.
+    pub links: Vec<LinkDiff>,
+    /// Per-path comparisons, in query order.
+    pub paths: Vec<PathDiff>,
+}
+
+/// The delay quantile of a link for flows of a particular size, before and after.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LinkDiff {
+    /// The link source.
+    pub src: NodeId,
+    /// The link destination.
+    pub dst: NodeId,
+    /// The flow size.
+    pub size: Bytes,
+    /// The quantile in the first network, or `None` if the link is missing or has no data.
+    pub before: Option<Nanosecs>,
+    /// The quantile in the second network, or `None` if the link is missing or has no data.
+    pub after: Option<Nanosecs>,
+}
+
+/// The end-to-end delay quantile of flows of a particular size between two hosts, before and
+/// after.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PathDiff {
+    /// The source host.
+    pub src: NodeId,
+    /// The destination host.
+    pub dst: NodeId,
+    /// The flow size.
+    pub size: Bytes,
+    /// The quantile in the first network, or `None` if no prediction can be made.
+    pub before: Option<Nanosecs>,
+    /// The quantile in the second network, or `None` if no prediction can be made.
+    pub after: Option<Nanosecs>,
+}
+
+macro_rules! diff_impl {
+    ($name: ident) => {
+        impl $name {
+            /// Returns the change in delay, in nanoseconds, or `None` if either side is missing.
+            pub fn delta(&self) -> Option<f64> {
+                Some(self.after?.into_f64() - self.before?.into_f64())
+            }
+
+            /// Returns the ratio of the delay after to the delay before, or `None` if either
+            /// side is missing or the delay before is zero.
+            pub fn ratio(&self) -> Option<f64> {
+                let (before, after) = (self.before?.into_f64(), self.after?.into_f64());
+                (before > 0.0).then(|| after / before)
+            }
+        }
+    };
+}
+
+diff_impl!(LinkDiff);
+diff_impl!(PathDiff);
+
+impl DelayDiff {
+    /// Returns the paths whose delay grew by more than a fraction `tolerance`, from the worst
+    /// regression to the least.
+    pub fn path_regressions(&self, tolerance: f64) -> Vec<&PathDiff> {
+        regressions(&self.paths, tolerance, PathDiff::ratio)
+    }
+
+    /// Returns the links whose delay grew by more than a fraction `tolerance`, from the worst
+    /// regression to the least.
+    pub fn link_regressions(&self, tolerance: f64) -> Vec<&LinkDiff> {
+        regressions(&self.links, tolerance, LinkDiff::ratio)
+    }
+}
+
+fn regressions<T>(diffs: &[T], tolerance: f64, ratio: impl Fn(&T) -> Option<f64>) -> Vec<&T> {
+    let mut regressed = diffs
+        .iter()
+        .filter_map(|d| Some((ratio(d)?, d)))
+        .filter(|&(ratio, _)| ratio > 1.0 + tolerance)
+        .collect::<Vec<_>>();
+    regressed.sort_by(|a, b| b.0.total_cmp(&a.0));
+    regressed.into_iter().map(|(_, d)| d).collect()
+}
+
+impl<R> DelayNetwork<R>
+where
+    R: RoutingAlgo,
+{
+    /// Compares this network (before) with `after` on every link at the sizes in `opts` and on
+    /// every `(size, (src, dst))` query in `paths`. Both networks sample paths with the same seed.
+    /// Returns an error if `opts` are invalid.
+    pub fn diff<S>(
+        &self,
+        after: &DelayNetwork<S>,
+        paths: &[(Bytes, (NodeId, NodeId))],
+        opts: &DiffOpts,
+    ) -> Result<DelayDiff, DiffError>
+    where
+        S: RoutingAlgo,
+    {
+        opts.validate()?;
+        let mut endpoints = self
+            .topology
+            .graph
+            .edge_weights()
+            .chain(after.topology.graph.edge_weights())
+            .map(|chan| (chan.src, chan.dst))
+            .collect::<Vec<_>>();
+        endpoints.sort();
+        endpoints.dedup();
+        let ours = self.link_quantiles(opts);
+        let theirs = after.link_quantiles(opts);
+        let links = endpoints
+            .into_iter()
+            .flat_map(|(src, dst)| {
+                let (ours, theirs) = (ours.get(&(src, dst)), theirs.get(&(src, dst)));
+                opts.sizes
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, &size)| LinkDiff {
+                        src,
+                        dst,
+                        size,
+                        before: ours.and_then(|q| q[i]),
+                        after: theirs.and_then(|q| q[i]),
+                    })
+            })
+            .collect::<Vec<_>>();
+        let paths = paths
+            .iter()
+            .enumerate()
+            .map(|(i, &(size, (src, dst)))| {
+                let seed = opts.seed.wrapping_add(i as u64);
+                PathDiff {
+                    src,
+                    dst,
+                    size,
+                    before: self.path_quantile(size, (src, dst), opts, seed),
+                    after: after.path_quantile(size, (src, dst), opts, seed),
+                }
+            })
+            .collect();
+        Ok(DelayDiff {
+            quantile: opts.quantile,
+            links,
+            paths,
+        })
+    }
+
+    // The quantile of every link at each size in `opts`, by endpoints
+    fn link_quantiles(
+        &self,
+        opts: &DiffOpts,
+    ) -> FxHashMap<(NodeId, NodeId), Vec<Option<Nanosecs>>> {
+        self.topology
+            .graph
+            .edge_weights()
+            .map(|chan| {
+                let quantiles = opts
+                    .sizes
+                    .iter()
+                    .map(|&size| {
+                        let pktnorm = chan.dists.for_size(size)?.quantile(opts.quantile)?;
+                        Some(self.to_delay(size, pktnorm))
+                    })
+                    .collect();
+                ((chan.src, chan.dst), quantiles)
+            })
+            .collect()
+    }
+
+    fn path_quantile(
+        &self,
+        size: Bytes,
+        pair: (NodeId, NodeId),
+        opts: &DiffOpts,
+        seed: u64,
+    ) -> Option<Nanosecs> {
+        let rng = StdRng::seed_from_u64(seed);
+        let dist = self.distribution_with(size, pair, opts.nr_samples, rng)?;
+        dist.quantile(opts.quantile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixed_delay_network;
+
+    #[test]
+    fn diffs_report_regressions() -> anyhow::Result<()> {
+        let (before, after) = (
+            fixed_delay_network(Nanosecs::new(100))?,
+            fixed_delay_network(Nanosecs::new(200))?,
+        );
+        let opts = DiffOpts::builder()
+            .sizes(vec![Bytes::new(1_000)])
+            .nr_samples(100)
+            .build();
+        let queries = [
+            (Bytes::new(1_000), (NodeId::new(0), NodeId::new(2))),
+            (Bytes::new(1_000), (NodeId::new(0), NodeId::new(0))),
+        ];
+        let diff = before.diff(&after, &queries, &opts)?;
+        assert_eq!(diff.links.len(), before.topology.graph.edge_count());
+        assert!(diff
+            .links
+            .windows(2)
+            .all(|w| (w[0].src, w[0].dst) < (w[1].src, w[1].dst)));
+        let busy = diff.links.iter().filter(|l| l.delta().is_some()).count();
+        assert!(busy > 0);
+        assert_eq!(diff.link_regressions(0.5).len(), busy);
+        assert!(diff.link_regressions(1.5).is_empty());
+
+        let path = &diff.paths[0];
+        assert!(path.delta().unwrap() > 0.0);
+        assert!((path.ratio().unwrap() - 2.0).abs() < 0.01);
+        assert_eq!(diff.path_regressions(0.1), vec![path]);
+        assert_eq!(diff.paths[1].before, None);
+
+        let same = before.diff(&before, &queries, &opts)?;
+        assert!(same.path_regressions(0.0).is_empty());
+        assert!(same.paths[0].delta() == Some(0.0));
+
+        let json = serde_json::to_string(&diff)?;
+        assert_eq!(serde_json::from_str::<DelayDiff>(&json)?, diff);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_quantiles_are_rejected() -> anyhow::Result<()> {
+        let delays = fixed_delay_network(Nanosecs::new(100))?;
+        for quantile in [-0.1, 1.5, f64::NAN] {
+            let opts = DiffOpts::builder().quantile(quantile).build();
+            assert!(matches!(
+                delays.diff(&delays, &[], &opts),
+                Err(DiffError::InvalidQuantile(_))
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+//! Delay distributions of host pairs. [`DelayNetwork::distribution`] composes the per-hop delay
+//! distributions between two hosts into one end-to-end distribution for a given flow size, so
+//! callers can draw many samples or compute quantiles without walking the routing tables for every
+//! sample.
+//!
+//! The composition is a sampled approximation: it draws a fixed number of predictions, each on a
+//! path chosen as [`DelayNetwork::predict`] would, so it reflects ECMP, hop correlations, and
+//! flowlets, and its accuracy grows with the number of samples.
+
+use rand::{distributions::Distribution, Rng};
+
+use crate::{
+    network::{DelayNetwork, NodeId},
+    routing::RoutingAlgo,
+    units::{Bytes, Nanosecs},
+};
+
+/// The number of samples [`DelayNetwork::distribution`] draws.
+pub const DEFAULT_NR_SAMPLES: usize = 10_000;
+
+/// The end-to-end delay distribution of flows of a particular size between two hosts.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DelayDistribution {
+    /// The source host.
+    pub src: NodeId,
+    /// The destination host.
+    pub dst: NodeId,
+    /// The flow size.
+    pub size: Bytes,
+    // Sorted and never empty
+    samples: Vec<Nanosecs>,
+}
+
+impl DelayDistribution {
+    /// Returns the `q`-th quantile of the distribution, or `None` if `q` isn't in [0, 1].
+    pub fn quantile(&self, q: f64) -> Option<Nanosecs> {
+        if !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+        let i = ((q * self.samples.len() as f64).ceil() as usize).saturating_sub(1);
+        Some(self.samples[i])
+    }
+
+    /// Returns the mean of the distribution.
+    pub fn mean(&self) -> Nanosecs {
+        let total = self.samples.iter().map(|d| d.into_f64()).sum::<f64>();
+        Nanosecs::new((total / self.samples.len() as f64).round() as u64)
+    }
+
+    /// Returns the distribution's samples, sorted.
+    pub fn samples(&self) -> &[Nanosecs] {
+        &self.samples
+    }
+
+    /// Returns the number of samples in the distribution.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Returns false. Distributions always have at least one sample.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+}
+
+impl Distribution<Nanosecs> for DelayDistribution {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Nanosecs {
+        self.samples[rng.gen_range(0..self.samples.len())]
+    }
+}
+
+impl<R> DelayNetwork<R>
+where
+    R: RoutingAlgo,
+{
+    /// Returns the distribution of the delay predicted for flows of a particular `size` going
+    /// from `src` to `dst`, from [`DEFAULT_NR_SAMPLES`] predictions drawn with
+    /// [`DelayNetwork::rng`]. Returns `None` if no prediction can be made for some path.
+    pub fn distribution(
+        &self,
+        size: Bytes,
+        (src, dst): (NodeId, NodeId),
+    ) -> Option<DelayDistribution> {
+        self.distribution_with(size, (src, dst), DEFAULT_NR_SAMPLES, self.rng())
+    }
+
+    /// Like [`DelayNetwork::distribution`], but from `nr_samples` predictions drawn with `rng`.
+    /// `nr_samples` must be positive.
+    pub fn distribution_with<RNG>(
+        &self,
+        size: Bytes,
+        (src, dst): (NodeId, NodeId),
+        nr_samples: usize,
+        mut rng: RNG,
+    ) -> Option<DelayDistribution>
+    where
+        RNG: Rng,
+    {
+        assert!(
+            nr_samples > 0,
+            "distribution: `nr_samples` must be positive"
+        );
+        let mut samples = (0..nr_samples)
+            .map(|_| self.predict(size, (src, dst), &mut rng))
+            .collect::<Option<Vec<_>>>()?;
+        samples.sort();
+        Some(DelayDistribution {
+            src,
+            dst,
+            size,
+            samples,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        network::{Flow, FlowId, Network},
+        opts::SimOpts,
+        testing::{self, FixedDelaySim},
+    };
+
+    #[test]
+    fn distributions_match_predictions() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (0..100)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 2),
+                dst: NodeId::new(2 + i % 2),
+                size: Bytes::new(1_000 * (1 + i as u64 % 5)),
+                start: Nanosecs::new(i as u64 * 1_000),
+            })
+            .collect();
+        let sim = FixedDelaySim {
+            delay_per_pkt: Nanosecs::new(100),
+        };
+        let delays = Network::new(&nodes, &links)?
+            .into_simulations(flows)
+            .into_delays(SimOpts::builder().link_sim(sim).build())?;
+        let pair = (NodeId::new(0), NodeId::new(2));
+        let dist = delays
+            .distribution_with(Bytes::new(1_000), pair, 500, StdRng::seed_from_u64(0))
+            .unwrap();
+        assert_eq!(dist.len(), 500);
+        assert!(dist.samples().windows(2).all(|w| w[0] <= w[1]));
+        let quantile = |q| dist.quantile(q).unwrap();
+        assert!(quantile(0.0) <= quantile(0.5));
+        assert!(quantile(0.5) <= quantile(1.0));
+        let mut rng = StdRng::seed_from_u64(1);
+        let sample = dist.sample(&mut rng);
+        assert!(quantile(0.0) <= sample && sample <= quantile(1.0));
+        assert!(quantile(0.0) <= dist.mean() && dist.mean() <= quantile(1.0));
+        for q in [-0.1, 1.1, f64::NAN] {
+            assert_eq!(dist.quantile(q), None);
+        }
+
+        assert_eq!(
+            delays.distribution(Bytes::new(1_000), pair),
+            delays.distribution(Bytes::new(1_000), pair)
+        );
+        assert!(delays
+            .distribution(Bytes::new(1_000), (NodeId::new(0), NodeId::new(0)))
+            .is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,503 @@
+//! Models of ECMP hash imbalance. Switches split flows among equal-cost next hops by hashing
+//! flow headers, and real hash functions are far from uniform: they polarize under some traffic
+//! patterns and load some next hops much more than others. An [`EcmpModel`] describes the split,
+//! either with explicit per-switch weights or with an empirical imbalance factor, and is applied
+//! both when flows are assigned to paths and when prediction queries sample paths.
+//!
+//! An [`EcmpPolicy`] decides how flows are hashed. Except with the ns-3-compatible
+//! [5-tuple hash](EcmpPolicy::FiveTuple), every switch on a flow's path uses the same hash, so
+//! choices at consecutive hops are correlated, as with polarized hashing. An [`EcmpReport`]
+//! summarizes the resulting split of bytes across every switch's uplinks.
+
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    mem,
+    sync::Arc,
+};
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    network::{flowlets::FlowletModel, EdgeIndex, Flow, NodeId, SimNetwork},
+    opts::RngStream,
+    oversubscription,
+    routing::RoutingAlgo,
+    utils,
+};
+
+/// How switches split flows among equal-cost next hops. The default splits flows uniformly.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EcmpModel {
+    /// Explicit next-hop weights of particular switches. These take precedence over
+    /// `imbalance`.
+    #[serde(default)]
+    pub groups: Vec<EcmpGroup>,
+    /// The ratio of the share of flows sent to the most loaded next hop to the mean share, for
+    /// switches without explicit weights. Every switch sends this larger share to one of its next
+    /// hops and splits the rest evenly. The factor is clamped to `[1, n]` for `n` next hops, so
+    /// `1.0` is a uniform split.
+    #[serde(default = "uniform")]
+    pub imbalance: f64,
+    /// How flows are hashed onto next hops.
+    #[serde(default)]
+    pub policy: EcmpPolicy,
+    /// If set, switches balance load by flowlets rather than by flows, so large flows are split
+    /// across paths. See [`crate::network::flowlets`].
+    #[serde(default)]
+    pub flowlets: Option<FlowletModel>,
+}
+
+fn uniform() -> f64 {
+    1.0
+}
+
+impl Default for EcmpModel {
+    fn default() -> Self {
+        Self {
+            groups: Vec::new(),
+            imbalance: uniform(),
+            policy: EcmpPolicy::default(),
+            flowlets: None,
+        }
+    }
+}
+
+/// How flows are hashed onto next hops. The same policy is used when flows are assigned to paths
+/// and when [`DelayNetwork`](crate::network::DelayNetwork) predicts the delays of particular
+/// flows. Seeded networks (see [`SimOpts::seed`](crate::opts::SimOpts::seed)) derive their hashes
+/// from the seed, except with [`EcmpPolicy::RoundRobin`].
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EcmpPolicy {
+    /// Hash flow IDs.
+    #[default]
+    FlowId,
+    /// Hash flows' 5-tuples with 32-bit MurmurHash3 as the switches of the HPCC ns-3 simulator do,
+    /// with each switch's node ID as the hash seed. Addresses are those ns-3 gives the flow's
+    /// endpoints, and since traces don't include ports, the source port is derived from the flow
+    /// ID.
+    FiveTuple,
+    /// Assign flows to next hops in turn, in order of flow ID. Weighted next hops receive flows
+    /// in proportion to their weights.
+    RoundRobin,
+    /// Hash flows with a custom function. Networks with custom policies can't be serialized.
+    #[serde(skip)]
+    Custom(EcmpHashFn),
+}
+
+/// A custom flow hash for [`EcmpPolicy::Custom`].
+#[derive(Clone)]
+pub struct EcmpHashFn(Arc<dyn Fn(&Flow) -> u64 + Send + Sync>);
+
+impl EcmpHashFn {
+    /// Creates a custom hash from a function of a flow.
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&Flow) -> u64 + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for EcmpHashFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EcmpHashFn")
+    }
+}
+
+impl PartialEq for EcmpHashFn {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl EcmpPolicy {
+    /// Returns the hash with which `switch` splits `flow`.
+    pub fn hash(&self, flow: &Flow, switch: NodeId, seed: Option<u64>) -> u64 {
+        let seed = seed.map(|seed| RngStream::Ecmp.seed(seed));
+        match self {
+            Self::FlowId => {
+                let hash = utils::splitmix64(flow.id.inner() as u64);
+                match seed {
+                    Some(seed) => utils::splitmix64(hash ^ seed),
+                    None => hash,
+                }
+            }
+            Self::FiveTuple => {
+                let sport = 10_000 + (flow.id.inner() % 50_000) as u32;
+                let dport = 100;
+                let mut key = [0; 12];
+                key[..4].copy_from_slice(&ns3_address(flow.src).to_le_bytes());
+                key[4..8].copy_from_slice(&ns3_address(flow.dst).to_le_bytes());
+                key[8..].copy_from_slice(&(sport | dport << 16).to_le_bytes());
+                let seed = switch.inner() as u32 ^ seed.unwrap_or_default() as u32;
+                utils::murmur3_32(&key, seed).into()
+            }
+            Self::RoundRobin => flow.id.inner() as u64,
+            Self::Custom(f) => match seed {
+                Some(seed) => utils::splitmix64((f.0)(flow) ^ seed),
+                None => (f.0)(flow),
+            },
+        }
+    }
+
+    /// Returns true if every switch splits a flow with the same hash.
+    fn is_shared(&self) -> bool {
+        !matches!(self, Self::FiveTuple)
+    }
+
+    // Maps a hash to `[0, 1]`, for choosing among weighted next hops
+    fn fraction(&self, hash: u64) -> f64 {
+        match self {
+            Self::FiveTuple => hash as f64 / u32::MAX as f64,
+            // Consecutive flows are spread evenly by a golden-ratio sequence
+            Self::RoundRobin => (hash as f64 * 0.618_033_988_749_895).fract(),
+            Self::FlowId | Self::Custom(_) => hash as f64 / u64::MAX as f64,
+        }
+    }
+}
+
+// The IPv4 address of a node in ns-3 simulations, as a host-order integer
+fn ns3_address(node: NodeId) -> u32 {
+    let id = node.inner() as u32;
+    0x0b00_0001 + (id / 256) * 0x0001_0000 + (id % 256) * 0x0000_0100
+}
+
+/// The next-hop weights of a switch.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EcmpGroup {
+    /// The switch.
+    pub switch: NodeId,
+    /// Relative weights of the switch's next hops. Next hops without a weight are never chosen,
+    /// and negative or NaN weights count as zero. If no next hop has a positive weight, flows
+    /// are split uniformly.
+    pub weights: Vec<(NodeId, f64)>,
+}
+
+impl EcmpModel {
+    /// Creates a model in which every switch without explicit weights has the given imbalance
+    /// factor.
+    pub fn with_imbalance(imbalance: f64) -> Self {
+        Self {
+            imbalance,
+            ..Self::default()
+        }
+    }
+
+    /// Creates a uniform model with the given hashing policy.
+    pub fn with_policy(policy: EcmpPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    /// Returns true if flows are split uniformly.
+    pub fn is_uniform(&self) -> bool {
+        self.groups.is_empty() && self.imbalance.partial_cmp(&1.0) != Some(Ordering::Greater)
+    }
+
+    // Feeds the model into `state`. Custom policies are identified by their function's address.
+    pub(crate) fn hash_into<H: Hasher>(&self, state: &mut H) {
+        for group in &self.groups {
+            group.switch.hash(state);
+            for &(next, weight) in &group.weights {
+                next.hash(state);
+                weight.to_bits().hash(state);
+            }
+        }
+        self.imbalance.to_bits().hash(state);
+        mem::discriminant(&self.policy).hash(state);
+        if let EcmpPolicy::Custom(f) = &self.policy {
+            (Arc::as_ptr(&f.0) as *const () as usize).hash(state);
+        }
+        if let Some(flowlets) = &self.flowlets {
+            flowlets.gap.hash(state);
+            flowlets.window.hash(state);
+            flowlets.max_flowlets.hash(state);
+        }
+    }
+
+    /// Chooses among `choices` at `switch` for a flow with the given hash.
+    pub(crate) fn choose<'a>(
+        &self,
+        switch: NodeId,
+        choices: &'a [NodeId],
+        hash: u64,
+    ) -> Option<&'a NodeId> {
+        let n = choices.len();
+        if n <= 1 || self.is_uniform() {
+            return choices.get(hash as usize % n.max(1));
+        }
+        let weights = match self.groups.iter().find(|g| g.switch == switch) {
+            Some(group) => choices
+                .iter()
+                .map(|c| {
+                    group
+                        .weights
+                        .iter()
+                        .filter(|(hop, w)| hop == c && *w > 0.0)
+                        .map(|&(_, w)| w)
+                        .sum::<f64>()
+                })
+                .collect::<Vec<_>>(),
+            None => {
+                let imbalance = self.imbalance.clamp(1.0, n as f64);
+                let hot = utils::splitmix64(switch.inner() as u64) as usize % n;
+                let rest = (n as f64 - imbalance) / (n - 1) as f64;
+                (0..n)
+                    .map(|i| if i == hot { imbalance } else { rest })
+                    .collect()
+            }
+        };
+        let total = weights.iter().sum::<f64>();
+        if total.partial_cmp(&0.0) != Some(Ordering::Greater) {
+            return choices.get(hash as usize % n);
+        }
+        let mut u = self.policy.fraction(hash) * total;
+        for (choice, &w) in choices.iter().zip(&weights) {
+            if u < w {
+                return Some(choice);
+            }
+            u -= w;
+        }
+        // Rounding can leave `u` just past the last positive weight
+        choices
+            .iter()
+            .zip(&weights)
+            .rev()
+            .find(|(_, &w)| w > 0.0)
+            .map(|(choice, _)| choice)
+    }
+
+    /// Returns a function choosing next hops along a path from `src` for a flow with the given
+    /// hash, for use with [`TraversableNetwork::edge_indices_between`]. This samples paths of
+    /// unknown flows; see [`EcmpModel::flow_hops`] for the paths of particular flows.
+    ///
+    /// [`TraversableNetwork::edge_indices_between`]: crate::network::TraversableNetwork
+    pub(crate) fn hops_from(
+        &self,
+        src: NodeId,
+        hash: u64,
+    ) -> impl FnMut(&[NodeId]) -> Option<&NodeId> + '_ {
+        let mut cur = src;
+        move |choices| {
+            let next = self.choose(cur, choices, hash)?;
+            cur = *next;
+            Some(next)
+        }
+    }
+
+    /// Like [`EcmpModel::hops_from`], but for the path `flow` is assigned to by the model's
+    /// policy in a network with the given seed.
+    pub(crate) fn flow_hops<'a>(
+        &'a self,
+        flow: &'a Flow,
+        seed: Option<u64>,
+    ) -> impl FnMut(&[NodeId]) -> Option<&NodeId> + 'a {
+        let shared = self
+            .policy
+            .is_shared()
+            .then(|| self.policy.hash(flow, flow.src, seed));
+        let mut cur = flow.src;
+        move |choices| {
+            let hash = shared.unwrap_or_else(|| self.policy.hash(flow, cur, seed));
+            let next = self.choose(cur, choices, hash)?;
+            cur = *next;
+            Some(next)
+        }
+    }
+}
+
+/// The split of bytes across every switch's uplinks, i.e., its links to the tier above. Tiers
+/// are as in [`crate::oversubscription`].
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EcmpReport {
+    /// Every switch with at least two uplinks and flows on any of them, sorted by switch.
+    pub groups: Vec<EcmpGroupStats>,
+}
+
+impl EcmpReport {
+    /// Returns the group with the largest imbalance, if any.
+    pub fn most_imbalanced(&self) -> Option<&EcmpGroupStats> {
+        self.groups
+            .iter()
+            .max_by(|a, b| a.imbalance.total_cmp(&b.imbalance))
+    }
+}
+
+impl fmt::Display for EcmpReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.groups.is_empty() {
+            return write!(f, "no ECMP groups carry flows");
+        }
+        let mut first = true;
+        for group in &self.groups {
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+            write!(f, "{group}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The split of bytes across a switch's uplinks.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EcmpGroupStats {
+    /// The switch.
+    pub switch: NodeId,
+    /// The switch's uplinks, sorted by edge index.
+    pub edges: Vec<EdgeIndex>,
+    /// The fraction of the group's bytes on each uplink, in the order of `edges`.
+    pub shares: Vec<f64>,
+    /// The largest share divided by the mean share. A uniform split has an imbalance of 1.
+    pub imbalance: f64,
+}
+
+impl fmt::Display for EcmpGroupStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "switch {}: {:.2}x imbalance over {} uplinks",
+            self.switch,
+            self.imbalance,
+            self.edges.len()
+        )
+    }
+}
+
+impl<R> SimNetwork<R>
+where
+    R: RoutingAlgo + Sync,
+{
+    /// Reports the split of bytes across every switch's uplinks, e.g., to find hotspots created
+    /// by the network's [`EcmpModel`].
+    pub fn ecmp_report(&self) -> EcmpReport {
+        let nodes = self.nodes().cloned().collect::<Vec<_>>();
+        let links = self.links().copied().collect::<Vec<_>>();
+        let tiers = oversubscription::tiers(&nodes, &links);
+        let mut uplinks = FxHashMap::<NodeId, Vec<EdgeIndex>>::default();
+        for eidx in self.topology.graph.edge_indices() {
+            let chan = &self.topology.graph[eidx];
+            let (Some(&ts), Some(&td)) = (tiers.get(&chan.src), tiers.get(&chan.dst)) else {
+                continue;
+            };
+            if ts > 0 && td > ts {
+                uplinks.entry(chan.src).or_default().push(eidx);
+            }
+        }
+        let mut groups = uplinks
+            .into_iter()
+            .filter_map(|(switch, mut edges)| {
+                edges.sort();
+                let bytes = edges
+                    .iter()
+                    .map(|&e| self.topology.graph[e].nr_bytes.into_f64())
+                    .collect::<Vec<_>>();
+                let total = bytes.iter().sum::<f64>();
+                if edges.len() < 2 || total == 0.0 {
+                    return None;
+                }
+                let shares = bytes.iter().map(|b| b / total).collect::<Vec<_>>();
+                let mean = 1.0 / edges.len() as f64;
+                let imbalance = shares.iter().copied().fold(0.0, f64::max) / mean;
+                Some(EcmpGroupStats {
+                    switch,
+                    edges,
+                    shares,
+                    imbalance,
+                })
+            })
+            .collect::<Vec<_>>();
+        groups.sort_by_key(|g| g.switch);
+        EcmpReport { groups }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::{Flow, FlowId, Network},
+        testing,
+        units::{Bytes, Nanosecs},
+    };
+
+    fn network(ecmp: EcmpModel) -> anyhow::Result<SimNetwork> {
+        let (nodes, links) = testing::eight_node_config();
+        // Host 0 sends to host 2 in the other rack, through either agg
+        let flows = (0..1000)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(2),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect();
+        Ok(Network::new(&nodes, &links)?
+            .with_ecmp(ecmp)
+            .into_simulations(flows))
+    }
+
+    fn share_of_first_agg(report: &EcmpReport) -> f64 {
+        let tor = &report.groups[0];
+        assert_eq!(tor.switch, NodeId::new(4));
+        tor.shares[0]
+    }
+
+    #[test]
+    fn imbalance_skews_flow_assignment() -> anyhow::Result<()> {
+        let report = network(EcmpModel::default())?.ecmp_report();
+        assert_eq!(report.groups.len(), 1);
+        assert!((share_of_first_agg(&report) - 0.5).abs() < 0.05);
+        assert!(report.groups[0].imbalance < 1.1);
+
+        let weighted = EcmpModel {
+            groups: vec![EcmpGroup {
+                switch: NodeId::new(4),
+                weights: vec![(NodeId::new(6), 3.0), (NodeId::new(7), 1.0)],
+            }],
+            ..EcmpModel::default()
+        };
+        let report = network(weighted)?.ecmp_report();
+        assert!((share_of_first_agg(&report) - 0.75).abs() < 0.05);
+        assert!((report.groups[0].imbalance - 1.5).abs() < 0.1);
+
+        // With two next hops, a factor of 2 sends everything to one of them
+        let report = network(EcmpModel::with_imbalance(2.0))?.ecmp_report();
+        assert_eq!(report.most_imbalanced().unwrap().imbalance, 2.0);
+        Ok(())
+    }
+
+    #[test]
+    fn policies_choose_paths() -> anyhow::Result<()> {
+        let report = network(EcmpModel::with_policy(EcmpPolicy::RoundRobin))?.ecmp_report();
+        assert_eq!(share_of_first_agg(&report), 0.5);
+
+        let report = network(EcmpModel::with_policy(EcmpPolicy::FiveTuple))?.ecmp_report();
+        assert!((share_of_first_agg(&report) - 0.5).abs() < 0.05);
+
+        let constant = EcmpPolicy::Custom(EcmpHashFn::new(|_| 0));
+        let report = network(EcmpModel::with_policy(constant))?.ecmp_report();
+        assert_eq!(report.groups[0].imbalance, 2.0);
+
+        let json = serde_json::to_string(&EcmpModel::with_policy(EcmpPolicy::FiveTuple))?;
+        assert!(json.contains(r#""policy":"five_tuple""#));
+        let ecmp: EcmpModel = serde_json::from_str(r#"{"imbalance": 1.0}"#)?;
+        assert_eq!(ecmp.policy, EcmpPolicy::FlowId);
+        Ok(())
+    }
+
+    #[test]
+    fn murmur3_matches_reference() {
+        assert_eq!(utils::murmur3_32(b"", 0), 0);
+        assert_eq!(utils::murmur3_32(b"", 1), 0x514e_28b7);
+        assert_eq!(utils::murmur3_32(b"hello", 0), 0x248b_fa47);
+        assert_eq!(utils::murmur3_32(b"hello, world", 0), 0x149b_bb7f);
+    }
+}
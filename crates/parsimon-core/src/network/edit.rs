@@ -0,0 +1,237 @@
+//! Programmatic topology edits. Planning tools evaluating candidate changes, e.g., adding a
+//! spine or upgrading uplinks, can edit a [`Network`] in place instead of regenerating its
+//! specification.
+//!
+//! Every edit is checked against the rules of [`Topology::new`] and either applies entirely or
+//! leaves the network unchanged. Bandwidth changes are applied to the affected channels only, since
+//! they don't change routes. Edits which add or remove nodes or links rebuild the topology and
+//! recompute routes.
+
+use crate::{
+    network::{
+        topology::Topology,
+        types::{Link, Node, NodeId, ReverseDirection},
+        BfsRoutes, Network, TopologyError,
+    },
+    units::BitsPerSec,
+};
+
+impl Network<BfsRoutes> {
+    /// Adds `node`, along with `links` connecting it to the rest of the network. Node IDs must
+    /// stay contiguous, so `node` must have the next unused ID.
+    pub fn add_node(&mut self, node: Node, links: &[Link]) -> Result<(), EditError> {
+        if node.id.inner() != self.nodes().count() {
+            return Err(TopologyError::HoleBeforeId(node.id).into());
+        }
+        let mut nodes = self.nodes().cloned().collect::<Vec<_>>();
+        nodes.push(node);
+        let mut all_links = self.topology.links.clone();
+        all_links.extend_from_slice(links);
+        self.rebuild(&nodes, &all_links)
+    }
+
+    /// Removes the node with the given ID and every link attached to it, and returns the node.
+    /// Node IDs must stay contiguous, so only the node with the highest ID can be removed.
+    pub fn remove_node(&mut self, id: NodeId) -> Result<Node, EditError> {
+        let nr_nodes = self.nodes().count();
+        let Some(&idx) = self.topology.idx_of(&id) else {
+            return Err(TopologyError::UndeclaredNode(id).into());
+        };
+        if id.inner() + 1 != nr_nodes {
+            return Err(EditError::NotLastNode(id));
+        }
+        let node = self.topology.graph[idx].clone();
+        let nodes = self
+            .nodes()
+            .filter(|n| n.id != id)
+            .cloned()
+            .collect::<Vec<_>>();
+        let links = self
+            .links()
+            .filter(|l| l.a != id && l.b != id)
+            .copied()
+            .collect::<Vec<_>>();
+        self.rebuild(&nodes, &links)?;
+        Ok(node)
+    }
+
+    /// Adds a link between two existing nodes.
+    pub fn add_link(&mut self, link: Link) -> Result<(), EditError> {
+        if self.link_position(link.a, link.b).is_some() {
+            return Err(TopologyError::DuplicateLink {
+                n1: link.a,
+                n2: link.b,
+            }
+            .into());
+        }
+        let nodes = self.nodes().cloned().collect::<Vec<_>>();
+        let mut links = self.topology.links.clone();
+        links.push(link);
+        self.rebuild(&nodes, &links)
+    }
+
+    /// Removes the link between `a` and `b`, in either order, and returns it. Removing a node's
+    /// last link fails, since nodes can't be isolated.
+    pub fn remove_link(&mut self, a: NodeId, b: NodeId) -> Result<Link, EditError> {
+        let i = self
+            .link_position(a, b)
+            .ok_or(EditError::UnknownLink { a, b })?;
+        let nodes = self.nodes().cloned().collect::<Vec<_>>();
+        let mut links = self.topology.links.clone();
+        let link = links.remove(i);
+        self.rebuild(&nodes, &links)?;
+        Ok(link)
+    }
+
+    /// Sets the bandwidth of the link between `a` and `b`, in either order, in both directions.
+    /// As when building a topology, the only up-channel of a host is capped at its NIC rate.
+    pub fn set_link_bandwidth(
+        &mut self,
+        a: NodeId,
+        b: NodeId,
+        bandwidth: BitsPerSec,
+    ) -> Result<(), EditError> {
+        let i = self
+            .link_position(a, b)
+            .ok_or(EditError::UnknownLink { a, b })?;
+        let link = &mut self.topology.links[i];
+        link.bandwidth = bandwidth;
+        if let ReverseDirection::Asymmetric {
+            bandwidth: reverse, ..
+        } = &mut link.reverse
+        {
+            *reverse = bandwidth;
+        }
+        let (a, b) = (link.a, link.b);
+        for (src, dst) in [(a, b), (b, a)] {
+            let Some(edge) = self.topology.channel_between(src, dst) else {
+                continue;
+            };
+            let &i = self.topology.idx_of(&src).unwrap();
+            let cap = match self.topology.nics.contains_key(&src) {
+                true => BitsPerSec::MAX,
+                false => self.topology.graph[i].nic_rate.unwrap_or(BitsPerSec::MAX),
+            };
+            self.topology.graph[edge].bandwidth = bandwidth.min(cap);
+        }
+        Ok(())
+    }
+
+    // The position of the link between `a` and `b`, in either order
+    fn link_position(&self, a: NodeId, b: NodeId) -> Option<usize> {
+        self.links()
+            .position(|l| (l.a, l.b) == (a, b) || (l.a, l.b) == (b, a))
+    }
+
+    // Replaces the topology and routes, leaving both unchanged if the new topology is invalid
+    fn rebuild(&mut self, nodes: &[Node], links: &[Link]) -> Result<(), EditError> {
+        let topology = Topology::new(nodes, links)?;
+        self.routes = BfsRoutes::new(&topology);
+        self.topology = topology;
+        Ok(())
+    }
+}
+
+/// Error type for topology edits.
+#[derive(Debug, thiserror::Error)]
+pub enum EditError {
+    /// The edited topology is invalid.
+    #[error("invalid topology")]
+    InvalidTopology(#[from] TopologyError),
+
+    /// There is no link between two nodes.
+    #[error("no link between {a} and {b}")]
+    UnknownLink {
+        /// The first endpoint.
+        a: NodeId,
+        /// The second endpoint.
+        b: NodeId,
+    },
+
+    /// A node other than the one with the highest ID was to be removed.
+    #[error("node {0} does not have the highest ID")]
+    NotLastNode(NodeId),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::TraversableNetwork,
+        testing,
+        units::{Gbps, Nanosecs},
+    };
+
+    #[test]
+    fn edits_revalidate_and_reroute() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let mut network = Network::new(&nodes, &links)?;
+        let (h0, h2) = (NodeId::new(0), NodeId::new(2));
+        let path_len =
+            |network: &Network| network.edge_indices_between(h0, h2, |c| c.first()).count();
+        assert_eq!(path_len(&network), 4);
+
+        // A direct link between the ToRs shortens the path
+        network.add_link(Link::new(
+            NodeId::new(4),
+            NodeId::new(5),
+            Gbps::new(10),
+            Nanosecs::new(1_000),
+        ))?;
+        assert_eq!(path_len(&network), 3);
+        assert!(matches!(
+            network.add_link(Link::new(
+                NodeId::new(5),
+                NodeId::new(4),
+                Gbps::new(1),
+                Nanosecs::ZERO
+            )),
+            Err(EditError::InvalidTopology(
+                TopologyError::DuplicateLink { .. }
+            ))
+        ));
+        network.remove_link(NodeId::new(5), NodeId::new(4))?;
+        assert_eq!(path_len(&network), 4);
+        assert!(matches!(
+            network.remove_link(h0, NodeId::new(4)),
+            Err(EditError::InvalidTopology(TopologyError::IsolatedNode(..)))
+        ));
+        assert_eq!(network.links().count(), links.len());
+
+        let host = Node::new_host(NodeId::new(8));
+        let link = Link::new(host.id, NodeId::new(5), Gbps::new(10), Nanosecs::new(1_000));
+        assert!(matches!(
+            network.add_node(Node::new_host(NodeId::new(9)), &[link]),
+            Err(EditError::InvalidTopology(TopologyError::HoleBeforeId(..)))
+        ));
+        network.add_node(host.clone(), &[link])?;
+        assert_eq!(network.host_ids().count(), 5);
+        assert_eq!(
+            network
+                .edge_indices_between(h0, host.id, |c| c.first())
+                .count(),
+            4
+        );
+        assert!(matches!(
+            network.remove_node(h0),
+            Err(EditError::NotLastNode(..))
+        ));
+        assert_eq!(network.remove_node(host.id)?, host);
+        assert_eq!(network.nodes().count(), nodes.len());
+
+        let upgraded = BitsPerSec::new(40_000_000_000);
+        network.set_link_bandwidth(NodeId::new(6), NodeId::new(4), upgraded)?;
+        let nr_upgraded = network
+            .topology
+            .graph
+            .edge_weights()
+            .filter(|chan| chan.bandwidth == upgraded)
+            .count();
+        assert_eq!(nr_upgraded, 2);
+        assert!(matches!(
+            network.set_link_bandwidth(h0, h2, upgraded),
+            Err(EditError::UnknownLink { .. })
+        ));
+        Ok(())
+    }
+}
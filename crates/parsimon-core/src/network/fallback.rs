@@ -0,0 +1,319 @@
+//! Handling of failed link simulations. By default, a single failed link simulation (e.g., a
+//! crashed ns-3 process) fails the whole run. A [`FailurePolicy`] can instead retry failed
+//! simulations and, if they keep failing, fall back to another link simulator or to an empty
+//! delay distribution. Every edge which needed a fallback is recorded as a [`LinkFailure`], see
+//! [`DelayNetwork::link_failures`](crate::network::DelayNetwork::link_failures).
+//!
+//! Link simulations can also be given a [timeout](crate::opts::SimOpts::timeout), after which the
+//! edge is skipped and recorded as [timed out](Resolution::TimedOut). Simulations can't be
+//! interrupted, so a timed-out simulation keeps running in the background; its results are
+//! discarded. Local runs don't wait for such simulations, so their simulators must be
+//! [detachable](LinkSim::detach).
+
+use std::{error::Error, sync::Arc, thread, time::Duration};
+
+use crate::{
+    linksim::{DynLinkSim, LinkSim, LinkSimResult, LinkSimSpec},
+    network::{EdgeIndex, FctRecord, SimNetworkError},
+};
+
+/// What to do when the link simulation of an edge fails.
+#[derive(Debug, Clone, Default, typed_builder::TypedBuilder)]
+pub struct FailurePolicy {
+    /// The number of times a failed simulation is retried. In distributed runs, the whole batch
+    /// of simulations sent to a worker is retried.
+    #[builder(default)]
+    pub retries: usize,
+    /// What to do once every retry has failed.
+    #[builder(default)]
+    pub fallback: Fallback,
+}
+
+/// The last resort for an edge whose simulation keeps failing.
+#[derive(Debug, Clone, Default)]
+pub enum Fallback {
+    /// Fail the run, the default.
+    #[default]
+    Abort,
+    /// Simulate the edge with another, typically cheaper, link simulator. The run fails if that
+    /// fails too.
+    Simulator(DynLinkSim),
+    /// Leave the edge's delay distributions empty.
+    Empty,
+}
+
+impl FailurePolicy {
+    /// Returns true if failed simulations can be recovered from.
+    pub(crate) fn tolerates_failures(&self) -> bool {
+        !matches!(self.fallback, Fallback::Abort)
+    }
+
+    // Applies the fallback to `edge`, which failed `attempts` times with `error` (see
+    // `describe`). `simulate` runs the edge's simulation with a given simulator.
+    pub(crate) fn fall_back<F>(
+        &self,
+        edge: EdgeIndex,
+        attempts: usize,
+        error: String,
+        simulate: F,
+    ) -> Result<(Vec<FctRecord>, LinkFailure), SimNetworkError>
+    where
+        F: FnOnce(&DynLinkSim) -> LinkSimResult,
+    {
+        let (records, resolution) = match &self.fallback {
+            Fallback::Abort => return Err(SimNetworkError::LinkFailed { edge, error }),
+            Fallback::Simulator(sim) => (simulate(sim)?, Resolution::Simulator(sim.name())),
+            Fallback::Empty => (Vec::new(), Resolution::Empty),
+        };
+        let failure = LinkFailure {
+            edge,
+            attempts,
+            error,
+            resolution,
+        };
+        tracing::warn!(
+            edge = edge.index(),
+            attempts,
+            error = %failure.error,
+            resolution = ?failure.resolution,
+            "link simulation failed"
+        );
+        Ok((records, failure))
+    }
+}
+
+/// An edge whose link simulation failed, and how the failure was resolved.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct LinkFailure {
+    /// The edge index of the simulated link.
+    pub edge: EdgeIndex,
+    /// The number of failed attempts.
+    pub attempts: usize,
+    /// The last error.
+    pub error: String,
+    /// How the failure was resolved.
+    pub resolution: Resolution,
+}
+
+impl LinkFailure {
+    pub(crate) fn timed_out(edge: EdgeIndex, attempts: usize, timeout: Duration) -> Self {
+        let failure = Self {
+            edge,
+            attempts,
+            error: format!("timed out after {timeout:?}"),
+            resolution: Resolution::TimedOut,
+        };
+        tracing::warn!(edge = edge.index(), ?timeout, "link simulation timed out");
+        failure
+    }
+}
+
+/// How a [`LinkFailure`] was resolved.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    /// The edge was simulated with the named fallback simulator.
+    Simulator(String),
+    /// The edge's delay distributions were left empty.
+    Empty,
+    /// The simulation timed out, and the edge's delay distributions were left empty.
+    TimedOut,
+}
+
+// Simulates `spec` on a detached thread, or returns `None` if that takes longer than `timeout`.
+// Without a timeout, it simulates on the current thread.
+pub(crate) fn simulate_within<S>(
+    sim: &S,
+    detached: Option<&DynLinkSim>,
+    spec: LinkSimSpec,
+    timeout: Option<Duration>,
+) -> Option<LinkSimResult>
+where
+    S: LinkSim + Sync,
+{
+    let (Some(timeout), Some(detached)) = (timeout, detached) else {
+        return Some(sim.simulate(spec));
+    };
+    let (s, r) = crossbeam_channel::bounded(1);
+    let sim = Arc::clone(detached);
+    thread::spawn(move || {
+        // The receiver is gone if the simulation timed out
+        let _ = s.send(sim.simulate(spec));
+    });
+    r.recv_timeout(timeout).ok()
+}
+
+// Formats an error with all of its sources.
+pub(crate) fn describe(error: &dyn Error) -> String {
+    let mut msg = error.to_string();
+    let mut source = error.source();
+    while let Some(e) = source {
+        msg.push_str(": ");
+        msg.push_str(&e.to_string());
+        source = e.source();
+    }
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{
+        linksim::{LinkSim, LinkSimSpec},
+        network::{DelayNetwork, Flow, FlowId, Network, NodeId},
+        opts::{SimOpts, SimOptsError},
+        testing::{self, FixedDelaySim},
+        units::{Bytes, Nanosecs},
+    };
+
+    // Fails its first `nr_failures` simulations
+    #[derive(Debug)]
+    struct FlakySim {
+        nr_failures: usize,
+        nr_calls: AtomicUsize,
+    }
+
+    impl LinkSim for FlakySim {
+        fn name(&self) -> String {
+            "flaky".into()
+        }
+
+        fn simulate(&self, spec: LinkSimSpec) -> LinkSimResult {
+            if self.nr_calls.fetch_add(1, Ordering::SeqCst) < self.nr_failures {
+                return Err(std::io::Error::other("crashed").into());
+            }
+            FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            }
+            .simulate(spec)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct SlowSim(Duration);
+
+    impl LinkSim for SlowSim {
+        fn name(&self) -> String {
+            "slow".into()
+        }
+
+        fn detach(&self) -> Option<DynLinkSim> {
+            Some(Arc::new(*self))
+        }
+
+        fn simulate(&self, spec: LinkSimSpec) -> LinkSimResult {
+            if self.0 == Duration::MAX {
+                // The simulator hangs
+                loop {
+                    thread::park();
+                }
+            }
+            thread::sleep(self.0);
+            FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            }
+            .simulate(spec)
+        }
+    }
+
+    fn run(nr_failures: usize, policy: FailurePolicy) -> Result<DelayNetwork, SimNetworkError> {
+        let (nodes, links) = testing::three_node_config();
+        let opts = SimOpts::builder()
+            .link_sim(FlakySim {
+                nr_failures,
+                nr_calls: AtomicUsize::new(0),
+            })
+            .on_failure(policy)
+            .build();
+        Network::new(&nodes, &links)
+            .unwrap()
+            .into_simulations(flows())
+            .into_delays(opts)
+    }
+
+    fn flows() -> Vec<Flow> {
+        vec![Flow {
+            id: FlowId::new(0),
+            src: NodeId::new(0),
+            dst: NodeId::new(1),
+            size: Bytes::new(10_000),
+            start: Nanosecs::ZERO,
+        }]
+    }
+
+    #[test]
+    fn slow_simulations_time_out() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows());
+        let run = |timeout| {
+            let opts = SimOpts::builder()
+                .link_sim(SlowSim(Duration::from_millis(200)))
+                .timeout(timeout)
+                .build();
+            sims.clone().into_delays(opts)
+        };
+        let delays = run(Duration::from_millis(1))?;
+        let timed_out = delays.timed_out_edges();
+        assert_eq!(timed_out.len(), 2);
+        assert!(run(Duration::from_secs(60))?.timed_out_edges().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn hung_simulations_time_out() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows());
+        let opts = SimOpts::builder()
+            .link_sim(SlowSim(Duration::MAX))
+            .timeout(Duration::from_millis(10))
+            .build();
+        let delays = sims.clone().into_delays(opts)?;
+        assert_eq!(delays.timed_out_edges().len(), 2);
+
+        let undetachable = SimOpts::builder()
+            .link_sim(FlakySim {
+                nr_failures: 0,
+                nr_calls: AtomicUsize::new(0),
+            })
+            .timeout(Duration::from_millis(10))
+            .build();
+        assert!(matches!(
+            sims.into_delays(undetachable),
+            Err(SimNetworkError::InvalidOpts(SimOptsError::Undetachable))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn failed_simulations_fall_back() {
+        // Both links with flows fail once, and the simulations run in parallel
+        assert!(run(1, FailurePolicy::default()).is_err());
+        let retried = run(2, FailurePolicy::builder().retries(2).build()).unwrap();
+        assert!(retried.link_failures().is_empty());
+
+        let fallback = Arc::new(FixedDelaySim {
+            delay_per_pkt: Nanosecs::new(1_000),
+        });
+        let policy = FailurePolicy::builder()
+            .fallback(Fallback::Simulator(fallback))
+            .build();
+        let delays = run(usize::MAX, policy).unwrap();
+        let failures = delays.link_failures();
+        assert_eq!(failures.len(), 2);
+        assert!(failures.iter().all(|f| f.attempts == 1
+            && f.error.contains("crashed")
+            && f.resolution == Resolution::Simulator("fixed-delay".into())));
+
+        let policy = FailurePolicy::builder()
+            .retries(1)
+            .fallback(Fallback::Empty)
+            .build();
+        let delays = run(usize::MAX, policy).unwrap();
+        assert!(delays
+            .link_failures()
+            .iter()
+            .all(|f| f.attempts == 2 && f.resolution == Resolution::Empty));
+    }
+}
@@ -0,0 +1,433 @@
+//! A flattened representation of a [`DelayNetwork`] for large batches of queries. Walking the
+//! routing tables and bucket maps of a delay network for every sample dominates the cost of
+//! predicting hundreds of millions of flows. A [`FlatDelayNetwork`] stores every edge's samples in
+//! one contiguous array, indexed by per-edge bucket tables, and compiles the paths between the
+//! host pairs of a batch into CSR-style index arrays once per batch, so that sampling is a few
+//! array lookups per hop and allocates nothing.
+//!
+//! Flattened networks sample as [`DelayNetwork::predict`] does with uniform ECMP and independent
+//! hops, from the distributions over all of a channel's flows. Networks whose predictions depend on
+//! more, i.e., on source-rack distributions, skewed [ECMP
+//! models](crate::network::ecmp::EcmpModel), flowlets, hop correlations, or path affinities, can't
+//! be flattened; use [`DelayNetwork::predict`] for those. Client, time, and load distributions
+//! aren't used by [`DelayNetwork::predict`] either, and are ignored.
+
+use std::ops::Range;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use typed_builder::TypedBuilder;
+
+use crate::{
+    constants::PacketProfile,
+    network::{DelayNetwork, NodeId},
+    routing::RoutingAlgo,
+    units::{Bytes, Nanosecs},
+    utils,
+};
+
+// The number of queries sampled with the same RNG. Each chunk's RNG is derived from the batch's
+// seed and the chunk's position, so results don't depend on the number of threads.
+const CHUNK_SIZE: usize = 4096;
+
+/// A flattened [`DelayNetwork`]. See the [module documentation](self).
+#[derive(Debug)]
+pub struct FlatDelayNetwork<'a, R> {
+    network: &'a DelayNetwork<R>,
+    packets: PacketProfile,
+    // The buckets of edge `e` are `buckets[edge_buckets[e]..edge_buckets[e + 1]]`
+    edge_buckets: Vec<usize>,
+    buckets: Vec<FlatBucket>,
+    // Packet-normalized delays of every bucket, contiguously
+    samples: Vec<f64>,
+}
+
+#[derive(Debug, Clone)]
+struct FlatBucket {
+    sizes: Range<Bytes>,
+    samples: Range<usize>,
+}
+
+/// Options for [`FlatDelayNetwork::predict_batch`].
+#[derive(Debug, Clone, Copy, TypedBuilder)]
+pub struct BatchOpts {
+    /// The seed results are determined by.
+    #[builder(default)]
+    pub seed: u64,
+    /// Whether to compile paths and sample queries in parallel. Otherwise, the calling thread does
+    /// both. Results are the same either way.
+    #[builder(default)]
+    pub parallel: bool,
+}
+
+impl Default for BatchOpts {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// Error type for [`DelayNetwork::flatten`].
+#[derive(Debug, thiserror::Error)]
+pub enum FlattenError {
+    /// The network has per-source-rack distributions.
+    #[error("Per-source-rack distributions can't be flattened")]
+    SourceRacks,
+
+    /// The network's ECMP model splits flows unevenly.
+    #[error("Skewed ECMP models can't be flattened")]
+    SkewedEcmp,
+
+    /// The network's ECMP model splits flows into flowlets.
+    #[error("Flowlet models can't be flattened")]
+    Flowlets,
+
+    /// The network has a nonzero hop correlation.
+    #[error("Hop correlation {0} can't be flattened")]
+    HopCorrelation(f64),
+
+    /// The network has path affinities.
+    #[error("Path affinities can't be flattened")]
+    PathAffinity,
+}
+
+// The paths between the host pairs of a batch, in CSR form
+#[derive(Debug, Default)]
+struct PathTable {
+    // The paths of pair `p` are `paths[pair_paths[p]..pair_paths[p + 1]]`
+    pair_paths: Vec<usize>,
+    // The cumulative probability of each path within its pair
+    cdf: Vec<f64>,
+    // The edges of path `i` are `edges[path_edges[i]..path_edges[i + 1]]`
+    path_edges: Vec<usize>,
+    edges: Vec<usize>,
+}
+
+impl<R> DelayNetwork<R>
+where
+    R: RoutingAlgo + Sync,
+{
+    /// Flattens the network for batch queries, or returns an error if flattened predictions
+    /// would differ from [`DelayNetwork::predict`]. See [`crate::network::flat`].
+    pub fn flatten(&self) -> Result<FlatDelayNetwork<'_, R>, FlattenError> {
+        if self
+            .topology
+            .graph
+            .edge_weights()
+            .any(|chan| chan.dists.groups().next().is_some())
+        {
+            return Err(FlattenError::SourceRacks);
+        }
+        if !self.ecmp.is_uniform() {
+            return Err(FlattenError::SkewedEcmp);
+        }
+        if self.ecmp.flowlets.is_some() {
+            return Err(FlattenError::Flowlets);
+        }
+        if self.hop_correlation != 0.0 {
+            return Err(FlattenError::HopCorrelation(self.hop_correlation));
+        }
+        if self.affinity.is_some() {
+            return Err(FlattenError::PathAffinity);
+        }
+        let mut edge_buckets = Vec::with_capacity(self.topology.graph.edge_count() + 1);
+        let mut buckets = Vec::new();
+        let mut samples = Vec::new();
+        edge_buckets.push(0);
+        for chan in self.topology.graph.edge_weights() {
+            for (sizes, dist) in chan.dists.iter() {
+                let start = samples.len();
+                samples.extend_from_slice(dist.samples());
+                buckets.push(FlatBucket {
+                    sizes: sizes.clone(),
+                    samples: start..samples.len(),
+                });
+            }
+            edge_buckets.push(buckets.len());
+        }
+        Ok(FlatDelayNetwork {
+            network: self,
+            packets: self.packets,
+            edge_buckets,
+            buckets,
+            samples,
+        })
+    }
+}
+
+impl<'a, R> FlatDelayNetwork<'a, R>
+where
+    R: RoutingAlgo + Sync,
+{
+    /// Returns the number of samples stored.
+    pub fn nr_samples(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Predicts the delays of a batch of `(size, (src, dst))` queries, in order. Each entry is
+    /// `None` if no prediction can be made for its query. Results are determined by
+    /// [`BatchOpts::seed`].
+    pub fn predict_batch(
+        &self,
+        queries: &[(Bytes, (NodeId, NodeId))],
+        opts: &BatchOpts,
+    ) -> Vec<Option<Nanosecs>> {
+        let mut pairs = FxHashMap::default();
+        let pair_ids = queries
+            .iter()
+            .map(|&(_, pair)| {
+                let next = pairs.len();
+                *pairs.entry(pair).or_insert(next)
+            })
+            .collect::<Vec<_>>();
+        let mut ordered = vec![(NodeId::ZERO, NodeId::ZERO); pairs.len()];
+        for (pair, i) in pairs {
+            ordered[i] = pair;
+        }
+        let table = self.compile_paths(&ordered, opts.parallel);
+        let mut out = vec![None; queries.len()];
+        let sample_chunk =
+            |(chunk, ((out, queries), pair_ids)): (usize, ((&mut [_], &[_]), &[usize]))| {
+                let mut rng = StdRng::seed_from_u64(utils::splitmix64(opts.seed ^ chunk as u64));
+                for ((out, &(size, _)), &pair) in out.iter_mut().zip(queries).zip(pair_ids) {
+                    *out = self.sample(&table, pair, size, &mut rng);
+                }
+            };
+        if opts.parallel {
+            out.par_chunks_mut(CHUNK_SIZE)
+                .zip(queries.par_chunks(CHUNK_SIZE))
+                .zip(pair_ids.par_chunks(CHUNK_SIZE))
+                .enumerate()
+                .for_each(sample_chunk);
+        } else {
+            out.chunks_mut(CHUNK_SIZE)
+                .zip(queries.chunks(CHUNK_SIZE))
+                .zip(pair_ids.chunks(CHUNK_SIZE))
+                .enumerate()
+                .for_each(sample_chunk);
+        }
+        out
+    }
+
+    fn sample(
+        &self,
+        table: &PathTable,
+        pair: usize,
+        size: Bytes,
+        rng: &mut StdRng,
+    ) -> Option<Nanosecs> {
+        let paths = table.pair_paths[pair]..table.pair_paths[pair + 1];
+        if paths.is_empty() {
+            return None;
+        }
+        let u = rng.gen::<f64>();
+        let cdf = &table.cdf[paths.clone()];
+        let path = paths.start + cdf.partition_point(|&p| p <= u).min(cdf.len() - 1);
+        let mut pktnorm_delay = 0.0;
+        for &edge in &table.edges[table.path_edges[path]..table.path_edges[path + 1]] {
+            let buckets = &self.buckets[self.edge_buckets[edge]..self.edge_buckets[edge + 1]];
+            let bucket = buckets.iter().find(|b| b.sizes.contains(&size))?;
+            let samples = &self.samples[bucket.samples.clone()];
+            if !samples.is_empty() {
+                pktnorm_delay += samples[rng.gen_range(0..samples.len())];
+            }
+        }
+        let nr_pkts = self.packets.nr_pkts(size) as f64;
+        Some(Nanosecs::new((nr_pkts * pktnorm_delay.max(0.0)) as u64))
+    }
+
+    // Enumerates the paths between every pair and the probability of each, with next hops chosen
+    // uniformly at every hop
+    fn compile_paths(&self, pairs: &[(NodeId, NodeId)], parallel: bool) -> PathTable {
+        let paths_between = |&(src, dst): &(NodeId, NodeId)| {
+            let mut paths = Vec::new();
+            self.enumerate(src, dst, &mut Vec::new(), 1.0, &mut paths);
+            paths
+        };
+        let per_pair = if parallel {
+            pairs.par_iter().map(paths_between).collect::<Vec<_>>()
+        } else {
+            pairs.iter().map(paths_between).collect()
+        };
+        let mut table = PathTable::default();
+        table.pair_paths.push(0);
+        table.path_edges.push(0);
+        for paths in per_pair {
+            let mut cumulative = 0.0;
+            for (edges, p) in paths {
+                cumulative += p;
+                table.cdf.push(cumulative);
+                table.edges.extend(edges);
+                table.path_edges.push(table.edges.len());
+            }
+            table.pair_paths.push(table.cdf.len());
+        }
+        table
+    }
+
+    fn enumerate(
+        &self,
+        cur: NodeId,
+        dst: NodeId,
+        prefix: &mut Vec<usize>,
+        p: f64,
+        paths: &mut Vec<(Vec<usize>, f64)>,
+    ) {
+        if cur == dst {
+            if !prefix.is_empty() {
+                paths.push((prefix.clone(), p));
+            }
+            return;
+        }
+        let topology = &self.network.topology;
+        let Some(hops) = self.network.routes.next_hops(cur, dst) else {
+            return;
+        };
+        let p = p / hops.len() as f64;
+        for next in hops {
+            let (Some(&i), Some(&j)) = (topology.idx_of(&cur), topology.idx_of(&next)) else {
+                continue;
+            };
+            let Some(edge) = topology.find_edge(i, j) else {
+                continue;
+            };
+            prefix.push(edge.index());
+            self.enumerate(next, dst, prefix, p, paths);
+            prefix.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        edist::{BucketOpts, EDistBuckets},
+        linksim::{LinkSim, LinkSimResult, LinkSimSpec},
+        network::{FctRecord, Flow, FlowId, Network},
+        opts::SimOpts,
+        testing,
+    };
+
+    // Delays flows by an amount which varies with their IDs and grows with their sizes, so that
+    // predictions depend on the buckets and paths hops sample from
+    #[derive(Debug, Clone, Copy)]
+    struct VariedDelaySim;
+
+    impl LinkSim for VariedDelaySim {
+        fn name(&self) -> String {
+            "varied-delay".into()
+        }
+
+        fn simulate(&self, spec: LinkSimSpec) -> LinkSimResult {
+            Ok(spec
+                .flows
+                .iter()
+                .map(|f| FctRecord {
+                    id: f.id,
+                    size: f.size,
+                    start: f.start,
+                    fct: Nanosecs::new((1 + f.id.inner() as u64 % 7) * f.size.into_u64()),
+                    ideal: Nanosecs::ZERO,
+                })
+                .collect())
+        }
+    }
+
+    fn delays() -> anyhow::Result<DelayNetwork> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (0..3000)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 4),
+                dst: NodeId::new((i + 1 + i / 4) % 4),
+                size: Bytes::new(1_000 * (1 + i as u64 % 3)),
+                start: Nanosecs::new(i as u64 * 1_000),
+            })
+            .filter(|f| f.src != f.dst)
+            .collect();
+        let opts = SimOpts::builder()
+            .link_sim(VariedDelaySim)
+            .bucket_opts(BucketOpts::new(2, 50))
+            .build();
+        Ok(Network::new(&nodes, &links)?
+            .into_simulations(flows)
+            .into_delays(opts)?)
+    }
+
+    #[test]
+    fn flat_predictions_match() -> anyhow::Result<()> {
+        let delays = delays()?;
+        let flat = delays.flatten()?;
+        assert!(flat.nr_samples() > 0);
+        let mean = |predictions: &[Option<Nanosecs>]| {
+            let sum = predictions
+                .iter()
+                .map(|p| p.unwrap().into_f64())
+                .sum::<f64>();
+            sum / predictions.len() as f64
+        };
+        let mut rng = delays.rng();
+        let mut means = Vec::new();
+        for size in [1_000, 3_000].map(Bytes::new) {
+            let queries = (0..20_000)
+                .map(|i| (size, (NodeId::new(i % 2), NodeId::new(2 + i % 2))))
+                .collect::<Vec<_>>();
+            let predictions = flat.predict_batch(&queries, &BatchOpts::default());
+            let expected = queries
+                .iter()
+                .map(|&(size, pair)| delays.predict(size, pair, &mut rng))
+                .collect::<Vec<_>>();
+            let (flat_mean, expected_mean) = (mean(&predictions), mean(&expected));
+            assert!(
+                (flat_mean - expected_mean).abs() < 0.03 * expected_mean,
+                "{flat_mean} vs. {expected_mean}"
+            );
+            means.push(flat_mean);
+        }
+        // Sizes sample different buckets
+        assert!(means[1] > 2.0 * means[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn batches_are_deterministic() -> anyhow::Result<()> {
+        let delays = delays()?;
+        let flat = delays.flatten()?;
+        let queries = (0..10_000)
+            .map(|i| {
+                (
+                    Bytes::new(1_000),
+                    (NodeId::new(i % 2), NodeId::new(2 + i % 2)),
+                )
+            })
+            .chain([(Bytes::new(1_000), (NodeId::new(0), NodeId::new(0)))])
+            .collect::<Vec<_>>();
+        let opts = BatchOpts::builder().seed(1).build();
+        let predictions = flat.predict_batch(&queries, &opts);
+        assert_eq!(predictions.len(), queries.len());
+        assert_eq!(predictions.last(), Some(&None));
+        assert_eq!(predictions, flat.predict_batch(&queries, &opts));
+        let parallel = BatchOpts::builder().seed(1).parallel(true).build();
+        assert_eq!(predictions, flat.predict_batch(&queries, &parallel));
+        Ok(())
+    }
+
+    #[test]
+    fn unflattenable_networks_are_rejected() -> anyhow::Result<()> {
+        let mut delays = delays()?;
+        delays.set_hop_correlation(0.5)?;
+        assert!(matches!(
+            delays.flatten(),
+            Err(FlattenError::HopCorrelation(c)) if c == 0.5
+        ));
+
+        let mut delays = self::delays()?;
+        let mut groups = FxHashMap::default();
+        groups.insert(NodeId::new(0), EDistBuckets::new_empty());
+        let chan = delays.topology.graph.edge_weights_mut().next().unwrap();
+        chan.dists.set_groups(groups);
+        assert!(matches!(delays.flatten(), Err(FlattenError::SourceRacks)));
+        Ok(())
+    }
+}
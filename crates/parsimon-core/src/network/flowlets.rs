@@ -0,0 +1,296 @@
+//! Flowlet switching. Switches with flowlet-based load balancing (e.g., CONGA) choose a new next
+//! hop whenever the gap between a flow's packets exceeds a timeout, so long flows are spread over
+//! several paths. Parsimon works at the flow level, so a [`FlowletModel`] approximates this by
+//! subdividing large flows into flowlets that are routed independently.
+//!
+//! A sender transmits a window of bytes per round trip. If the time it then waits for
+//! acknowledgments, i.e., the round-trip propagation delay minus the window's transmission time,
+//! exceeds the flowlet timeout, every window starts a new flowlet. Otherwise, the flow is never
+//! idle long enough to change paths and stays whole.
+
+use crate::{
+    network::{Channel, Flow, FlowId, Network, NodeId, TraversableNetwork},
+    routing::RoutingAlgo,
+    units::{BitsPerSec, Bytes, Nanosecs},
+};
+
+/// A flow-level model of flowlet switching. Set it with
+/// [`EcmpModel::flowlets`](crate::network::ecmp::EcmpModel::flowlets).
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FlowletModel {
+    /// The flowlet timeout. Gaps between packets longer than this start a new flowlet.
+    pub gap: Nanosecs,
+    /// The number of bytes a sender transmits per round trip, e.g., its congestion window.
+    pub window: Bytes,
+    /// The most flowlets a flow is split into. Flows which would be split into more flowlets
+    /// are split into this many flowlets of equal size.
+    #[serde(default = "default_max_flowlets")]
+    pub max_flowlets: usize,
+}
+
+fn default_max_flowlets() -> usize {
+    64
+}
+
+impl FlowletModel {
+    /// Creates a model with the given flowlet timeout and window, and the default maximum
+    /// number of flowlets per flow.
+    pub fn new(gap: Nanosecs, window: Bytes) -> Self {
+        Self {
+            gap,
+            window,
+            max_flowlets: default_max_flowlets(),
+        }
+    }
+
+    /// Returns the sizes of the flowlets a flow of `size` bytes is split into, given the
+    /// round-trip propagation delay of its path and the bandwidth of its first hop.
+    pub fn split(&self, size: Bytes, rtt: Nanosecs, bandwidth: BitsPerSec) -> Vec<Bytes> {
+        if self.window == Bytes::ZERO || size <= self.window || bandwidth == BitsPerSec::ZERO {
+            return vec![size];
+        }
+        let idle = rtt
+            .into_u64()
+            .saturating_sub(bandwidth.length(self.window).into_u64());
+        if idle <= self.gap.into_u64() {
+            return vec![size];
+        }
+        let n = size
+            .into_u64()
+            .div_ceil(self.window.into_u64())
+            .min(self.max_flowlets.max(1) as u64);
+        // The first `size % n` flowlets carry an extra byte
+        let (base, extra) = (size.into_u64() / n, size.into_u64() % n);
+        (0..n)
+            .map(|i| Bytes::new(base + u64::from(i < extra)))
+            .collect()
+    }
+}
+
+/// A flowlet, or a flow which isn't split.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Flowlet {
+    /// The ID of the flow the flowlet belongs to.
+    pub parent: FlowId,
+    /// The flowlet. Flows which aren't split keep their ID.
+    pub flow: Flow,
+}
+
+impl<R> Network<R>
+where
+    R: RoutingAlgo,
+{
+    /// Splits `flows` into flowlets with the network's [`FlowletModel`], if it has one.
+    /// [`Network::into_simulations`] simulates the flowlets instead of the flows.
+    ///
+    /// Flows which aren't split are returned as they are. Split flows are replaced by their
+    /// flowlets, which start one round trip apart and are given fresh IDs above the largest ID in
+    /// `flows`, in order.
+    pub fn flowlets(&self, flows: &[Flow]) -> Vec<Flowlet> {
+        let Some(model) = &self.ecmp.flowlets else {
+            return flows
+                .iter()
+                .map(|&flow| Flowlet {
+                    parent: flow.id,
+                    flow,
+                })
+                .collect();
+        };
+        let mut next_id = flows.iter().map(|f| f.id).max().unwrap_or_default() + FlowId::ONE;
+        let mut flowlets = Vec::with_capacity(flows.len());
+        for &flow in flows {
+            let (rtt, bandwidth) = rtt_and_bandwidth(self, flow.src, flow.dst);
+            let sizes = model.split(flow.size, rtt, bandwidth);
+            if sizes.len() == 1 {
+                flowlets.push(Flowlet {
+                    parent: flow.id,
+                    flow,
+                });
+                continue;
+            }
+            for (i, size) in sizes.into_iter().enumerate() {
+                flowlets.push(Flowlet {
+                    parent: flow.id,
+                    flow: Flow {
+                        id: next_id,
+                        size,
+                        start: flow.start + Nanosecs::new(rtt.into_u64() * i as u64),
+                        ..flow
+                    },
+                });
+                next_id += FlowId::ONE;
+            }
+        }
+        flowlets
+    }
+}
+
+// Returns the round-trip propagation delay between `src` and `dst` and the bandwidth of the first
+// hop, along the first shortest path. Equal-cost paths are assumed to have similar delays.
+pub(crate) fn rtt_and_bandwidth<N, C, R>(
+    network: &N,
+    src: NodeId,
+    dst: NodeId,
+) -> (Nanosecs, BitsPerSec)
+where
+    N: TraversableNetwork<C, R>,
+    C: Clone + Channel,
+    R: RoutingAlgo,
+{
+    let graph = &network.topology().graph;
+    let path = network
+        .edge_indices_between(src, dst, |choices| choices.first())
+        .collect::<Vec<_>>();
+    let delay = path
+        .iter()
+        .map(|&e| graph[e].delay())
+        .fold(Nanosecs::ZERO, |acc, d| acc + d);
+    let bandwidth = path
+        .first()
+        .map(|&e| graph[e].bandwidth())
+        .unwrap_or(BitsPerSec::ZERO);
+    (delay + delay, bandwidth)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        client::{ClientId, ClientMap},
+        network::ecmp::EcmpModel,
+        opts::SimOpts,
+        testing,
+        units::Gbps,
+    };
+
+    #[test]
+    fn flowlets_need_idle_senders() {
+        let model = FlowletModel::new(Nanosecs::new(500), Bytes::new(1_000));
+        let bandwidth = Gbps::new(10).into();
+        // 1KB takes 800ns at 10Gbps, leaving 1.2us of idle time per round trip
+        let sizes = model.split(Bytes::new(3_500), Nanosecs::new(2_000), bandwidth);
+        assert_eq!(sizes.len(), 4);
+        assert_eq!(sizes.iter().copied().sum::<Bytes>(), Bytes::new(3_500));
+        // Senders which are never idle long enough stay on one path
+        let sizes = model.split(Bytes::new(3_500), Nanosecs::new(1_000), bandwidth);
+        assert_eq!(sizes, vec![Bytes::new(3_500)]);
+        let sizes = model.split(Bytes::new(500), Nanosecs::new(2_000), bandwidth);
+        assert_eq!(sizes, vec![Bytes::new(500)]);
+        let capped = FlowletModel {
+            max_flowlets: 2,
+            ..model
+        };
+        let sizes = capped.split(Bytes::new(3_501), Nanosecs::new(2_000), bandwidth);
+        assert_eq!(sizes, vec![Bytes::new(1_751), Bytes::new(1_750)]);
+    }
+
+    #[test]
+    fn large_flows_are_spread_over_paths() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flow = Flow {
+            id: FlowId::new(3),
+            src: NodeId::new(0),
+            dst: NodeId::new(2),
+            size: Bytes::new(1_000_000),
+            start: Nanosecs::ZERO,
+        };
+        let ecmp = EcmpModel {
+            flowlets: Some(FlowletModel::new(Nanosecs::new(100), Bytes::new(5_000))),
+            ..EcmpModel::default()
+        };
+        let network = Network::new(&nodes, &links)?.with_ecmp(ecmp);
+        let flowlets = network.flowlets(&[flow]);
+        assert!(flowlets.len() > 1);
+        assert!(flowlets
+            .iter()
+            .all(|f| f.parent == flow.id && f.flow.id > flow.id));
+        let sims = network.into_simulations(vec![flow]);
+        let report = sims.ecmp_report();
+        let shares = &report.groups[0].shares;
+        assert!(shares.iter().all(|&s| s > 0.0 && s < 1.0));
+        Ok(())
+    }
+
+    #[test]
+    fn flowlets_keep_their_flows_clients() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (0..4)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(2),
+                size: Bytes::new(100_000),
+                start: Nanosecs::new(i as u64 * 10_000),
+            })
+            .collect::<Vec<_>>();
+        let clients = flows
+            .iter()
+            .map(|f| (f.id, ClientId::new(f.id.inner() % 2)))
+            .collect::<ClientMap>();
+        let ecmp = EcmpModel {
+            flowlets: Some(FlowletModel::new(Nanosecs::new(100), Bytes::new(5_000))),
+            ..EcmpModel::default()
+        };
+        let sims = Network::new(&nodes, &links)?
+            .with_ecmp(ecmp)
+            .into_simulations(flows.clone());
+        assert!(sims.nr_flows() > flows.len());
+        assert!(sims
+            .flows()
+            .all(|f| flows.iter().any(|p| p.id == sims.parent_of(f.id))));
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let delays = sims.into_delays_by_client(opts, &clients)?;
+        assert_eq!(delays.clients(), vec![ClientId::new(0), ClientId::new(1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn predictions_share_the_flowlet_sampler() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (0..20)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(2),
+                size: Bytes::new(10_000 * (i as u64 + 1)),
+                start: Nanosecs::new(i as u64 * 10_000),
+            })
+            .collect::<Vec<_>>();
+        let ecmp = EcmpModel {
+            flowlets: Some(FlowletModel::new(Nanosecs::new(100), Bytes::new(5_000))),
+            ..EcmpModel::default()
+        };
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let delays = Network::new(&nodes, &links)?
+            .with_ecmp(ecmp)
+            .into_simulations(flows)
+            .into_delays(opts)?;
+        let (size, pair) = (Bytes::new(100_000), (NodeId::new(0), NodeId::new(2)));
+        let rng = || StdRng::seed_from_u64(7);
+        let delay = delays.predict(size, pair, rng()).unwrap();
+        assert_eq!(delays.predict_at(size, pair, None, rng()), Some(delay));
+        assert_eq!(
+            delays.predict_under_load(size, pair, 0.5, rng()),
+            Some(delay)
+        );
+        assert_eq!(delays.predict_fct(size, pair, rng()).unwrap().delay, delay);
+        assert_eq!(
+            delays
+                .predict_breakdown(size, pair, rng())
+                .unwrap()
+                .queueing,
+            delay
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+//! The paths flows are routed on. [`Network::into_simulations`](crate::network::Network::into_simulations)
+//! hashes every flow onto one of its ECMP paths with the network's
+//! [`EcmpPolicy`](crate::network::ecmp::EcmpPolicy). [`SimNetwork::flow_path`] recovers the path
+//! of a single flow, and [`SimNetwork::flow_paths`] the paths of all flows, e.g., to join
+//! Parsimon's routing with external per-flow telemetry.
+
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+
+use crate::{
+    network::{EdgeIndex, Flow, FlowId, NodeId, SimNetwork, TraversableNetwork},
+    routing::RoutingAlgo,
+};
+
+/// A hop of a flow's path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Hop {
+    /// The edge index of the link.
+    pub edge: EdgeIndex,
+    /// The link's source node.
+    pub src: NodeId,
+    /// The link's destination node.
+    pub dst: NodeId,
+}
+
+/// The paths of every flow in a network, obtained with [`SimNetwork::flow_paths`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FlowPaths {
+    /// Every distinct path, in order of the smallest ID of the flows routed on it.
+    pub paths: Vec<Vec<Hop>>,
+    /// The flows with the index of their path in `paths`, sorted by flow ID.
+    pub assignments: Vec<(FlowId, usize)>,
+}
+
+impl FlowPaths {
+    /// Returns the path of the flow with the given ID, or `None` if there is no such flow.
+    pub fn path_of(&self, id: FlowId) -> Option<&[Hop]> {
+        let i = self
+            .assignments
+            .binary_search_by_key(&id, |&(id, _)| id)
+            .ok()?;
+        self.paths.get(self.assignments[i].1).map(Vec::as_slice)
+    }
+
+    /// Returns the IDs of the flows routed on exactly the given sequence of edges, sorted.
+    pub fn flows_on(&self, path: &[EdgeIndex]) -> Vec<FlowId> {
+        let Some(p) = self
+            .paths
+            .iter()
+            .position(|hops| hops.iter().map(|h| h.edge).eq(path.iter().copied()))
+        else {
+            return Vec::new();
+        };
+        self.assignments
+            .iter()
+            .filter(|&&(_, i)| i == p)
+            .map(|&(id, _)| id)
+            .collect()
+    }
+}
+
+impl<R> SimNetwork<R>
+where
+    R: RoutingAlgo + Sync,
+{
+    /// Returns the edges the flow with the given ID was routed on, from its source to its
+    /// destination, or `None` if there is no such flow.
+    pub fn flow_path(&self, id: FlowId) -> Option<Vec<EdgeIndex>> {
+        self.flows.find(id).map(|flow| self.route(flow))
+    }
+
+    /// Returns the paths of all flows.
+    pub fn flow_paths(&self) -> FlowPaths {
+        let routes = self
+            .flows
+            .as_slice()
+            .par_iter()
+            .map(|flow| self.route(flow))
+            .collect::<Vec<_>>();
+        let mut path2idx = FxHashMap::default();
+        let mut paths = Vec::new();
+        let assignments = self
+            .flows
+            .as_slice()
+            .iter()
+            .zip(routes)
+            .map(|(flow, route)| {
+                let i = *path2idx.entry(route).or_insert_with_key(|route| {
+                    paths.push(self.hops(route));
+                    paths.len() - 1
+                });
+                (flow.id, i)
+            })
+            .collect();
+        FlowPaths { paths, assignments }
+    }
+
+    // Routes `flow` as `Network::into_simulations` did
+    fn route(&self, flow: &Flow) -> Vec<EdgeIndex> {
+        let hops = self.ecmp.flow_hops(flow, self.seed);
+        self.edge_indices_between(flow.src, flow.dst, hops)
+            .collect()
+    }
+
+    fn hops(&self, route: &[EdgeIndex]) -> Vec<Hop> {
+        route
+            .iter()
+            .map(|&edge| {
+                let chan = &self.topology.graph[edge];
+                Hop {
+                    edge,
+                    src: chan.src,
+                    dst: chan.dst,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::Network,
+        testing,
+        units::{Bytes, Nanosecs},
+    };
+
+    #[test]
+    fn flow_paths_match_traced_flows() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (0..40)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 4),
+                dst: NodeId::new((i + 1 + i / 4) % 4),
+                size: Bytes::new(1_000),
+                start: Nanosecs::new(i as u64),
+            })
+            .filter(|f| f.src != f.dst)
+            .collect::<Vec<_>>();
+        let sims = Network::new(&nodes, &links)?
+            .with_seed(7)
+            .into_simulations(flows.clone());
+        let paths = sims.flow_paths();
+        for flow in &flows {
+            let path = sims.flow_path(flow.id).unwrap();
+            let hops = paths.path_of(flow.id).unwrap();
+            assert!(hops.iter().map(|h| h.edge).eq(path.iter().copied()));
+            assert_eq!(hops.first().unwrap().src, flow.src);
+            assert_eq!(hops.last().unwrap().dst, flow.dst);
+            for &edge in &path {
+                assert!(sims.flows_on(edge).unwrap().iter().any(|f| f.id == flow.id));
+            }
+            assert!(paths.flows_on(&path).contains(&flow.id));
+        }
+        assert_eq!(sims.flow_path(FlowId::new(1_000)), None);
+
+        let json = serde_json::to_string(&paths)?;
+        assert_eq!(serde_json::from_str::<FlowPaths>(&json)?, paths);
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+//! Delay networks built from production telemetry instead of link simulations. A [`Telemetry`]
+//! holds sampled flow completion times, each attributed to the path its flow took, along with link
+//! utilization counters. [`Network::into_delays_from_telemetry`] turns them into a
+//! [`DelayNetwork`], so measured and simulated networks can be queried the same way.
+//!
+//! Each sample's delay, its FCT minus its ideal FCT, is split among the channels on its path in
+//! proportion to `u / (1 - u)`, where `u` is a channel's utilization, the mean number of packets
+//! queued at an M/M/1 queue. Channels without a counter are weighted like the average counted
+//! channel on the path, and the delay is split evenly if no channel on the path is counted or
+//! loaded.
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    edist::{BucketOpts, EDistBuckets, EDistError},
+    network::{
+        topology::Topology, DelayNetwork, DelayParts, EdgeIndex, FctRecord, FlowId, Network, NodeId,
+    },
+    routing::RoutingAlgo,
+    units::{Bytes, Nanosecs},
+};
+
+/// Telemetry collected from a running network.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Telemetry {
+    /// Sampled flow completion times.
+    pub samples: Vec<FctSample>,
+    /// Link utilization counters.
+    #[serde(default)]
+    pub counters: Vec<LinkCounter>,
+}
+
+/// A sampled flow completion time.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FctSample {
+    /// The flow size.
+    pub size: Bytes,
+    /// The flow's start time.
+    pub start: Nanosecs,
+    /// The measured flow completion time.
+    pub fct: Nanosecs,
+    /// The ideal flow completion time on an unloaded network.
+    pub ideal: Nanosecs,
+    /// The nodes the flow traversed, from its source to its destination.
+    pub path: Vec<NodeId>,
+}
+
+/// The utilization of the channel from `src` to `dst`, e.g., averaged from switch byte counters.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LinkCounter {
+    /// The channel source.
+    pub src: NodeId,
+    /// The channel destination.
+    pub dst: NodeId,
+    /// The fraction of the channel's bandwidth in use, in [0, 1].
+    pub utilization: f64,
+}
+
+/// Options for [`Network::into_delays_from_telemetry`].
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder)]
+pub struct TelemetryOpts {
+    /// Parameters for bucketing delays by flow size and, if configured, by the load sampled flows
+    /// offer to each channel.
+    #[builder(default)]
+    pub bucket_opts: BucketOpts,
+    /// Utilizations are capped here, since the weight of a fully utilized channel is unbounded.
+    #[builder(default = 0.99)]
+    pub max_utilization: f64,
+}
+
+impl Default for TelemetryOpts {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl<R> Network<R>
+where
+    R: RoutingAlgo + Sync,
+{
+    /// Converts the network into a [`DelayNetwork`] whose channels are filled with delays
+    /// attributed from `telemetry` rather than simulated. Channels no sample traversed are left
+    /// empty. See [`crate::network::measured`].
+    pub fn into_delays_from_telemetry(
+        self,
+        telemetry: &Telemetry,
+        opts: &TelemetryOpts,
+    ) -> Result<DelayNetwork<R>, TelemetryError> {
+        let weights = self.channel_weights(&telemetry.counters, opts)?;
+        let mut eidx2data = FxHashMap::<EdgeIndex, Vec<FctRecord>>::default();
+        for (i, sample) in telemetry.samples.iter().enumerate() {
+            if sample.path.len() < 2 {
+                return Err(TelemetryError::ShortPath(i));
+            }
+            let edges = sample
+                .path
+                .windows(2)
+                .map(|hop| self.edge(hop[0], hop[1]))
+                .collect::<Result<Vec<_>, _>>()?;
+            let delay = sample.fct.into_f64() - sample.ideal.into_f64();
+            for (eidx, share) in edges.iter().zip(shares(&edges, &weights)) {
+                eidx2data.entry(*eidx).or_default().push(FctRecord {
+                    id: FlowId::new(i),
+                    size: sample.size,
+                    start: sample.start,
+                    fct: Nanosecs::new((delay.max(0.0) * share).round() as u64),
+                    ideal: Nanosecs::ZERO,
+                });
+            }
+        }
+
+        let sims = self.into_simulations(Vec::new());
+        let mut topology = Topology::new_edist(&sims.topology);
+        for (eidx, data) in eidx2data {
+            topology.graph[eidx].dists = EDistBuckets::new_empty();
+            topology.graph[eidx].dists.fill(
+                &data,
+                |rec| rec.size,
+                |rec| rec.pktnorm_delay_with(&sims.packets),
+                opts.bucket_opts,
+            )?;
+            // Offered loads only count sampled flows
+            let loads = EDistBuckets::by_load(
+                &data,
+                sims.topology.graph[eidx].bandwidth,
+                (
+                    |rec: FctRecord| rec.size,
+                    |rec: FctRecord| rec.start,
+                    |rec: FctRecord| rec.pktnorm_delay_with(&sims.packets),
+                ),
+                opts.bucket_opts,
+            )?;
+            topology.graph[eidx].dists.set_loads(loads);
+        }
+        let parts = DelayParts {
+            origin: sims.origin,
+            packets: sims.packets,
+            ecmp: sims.ecmp,
+            seed: sims.seed,
+            ..DelayParts::default()
+        };
+        Ok(DelayNetwork::new(topology, sims.routes, parts))
+    }
+
+    // The M/M/1 weight of every counted channel
+    fn channel_weights(
+        &self,
+        counters: &[LinkCounter],
+        opts: &TelemetryOpts,
+    ) -> Result<FxHashMap<EdgeIndex, f64>, TelemetryError> {
+        counters
+            .iter()
+            .map(|c| {
+                let eidx = self.edge(c.src, c.dst)?;
+                if !(0.0..=1.0).contains(&c.utilization) {
+                    return Err(TelemetryError::InvalidUtilization {
+                        src: c.src,
+                        dst: c.dst,
+                        utilization: c.utilization,
+                    });
+                }
+                let u = c.utilization.min(opts.max_utilization);
+                Ok((eidx, u / (1.0 - u)))
+            })
+            .collect()
+    }
+
+    fn edge(&self, src: NodeId, dst: NodeId) -> Result<EdgeIndex, TelemetryError> {
+        self.topology
+            .channel_between(src, dst)
+            .ok_or(TelemetryError::UnknownChannel { src, dst })
+    }
+}
+
+// The fraction of a path's delay attributed to each of its `edges`
+fn shares(edges: &[EdgeIndex], weights: &FxHashMap<EdgeIndex, f64>) -> Vec<f64> {
+    let counted = edges
+        .iter()
+        .filter_map(|eidx| weights.get(eidx))
+        .collect::<Vec<_>>();
+    let mean = counted.iter().copied().sum::<f64>() / counted.len().max(1) as f64;
+    let weights = edges
+        .iter()
+        .map(|eidx| weights.get(eidx).copied().unwrap_or(mean))
+        .collect::<Vec<_>>();
+    let total = weights.iter().sum::<f64>();
+    if total > 0.0 {
+        weights.iter().map(|w| w / total).collect()
+    } else {
+        vec![1.0 / edges.len() as f64; edges.len()]
+    }
+}
+
+/// Error type for [`Network::into_delays_from_telemetry`].
+#[derive(Debug, thiserror::Error)]
+pub enum TelemetryError {
+    /// Telemetry refers to a channel which isn't in the network.
+    #[error("no channel from {src} to {dst}")]
+    UnknownChannel {
+        /// The channel source.
+        src: NodeId,
+        /// The channel destination.
+        dst: NodeId,
+    },
+
+    /// A sample's path has fewer than two nodes.
+    #[error("sample {0} has a path with fewer than two nodes")]
+    ShortPath(usize),
+
+    /// A utilization counter is outside of [0, 1].
+    #[error("utilization {utilization} from {src} to {dst} is outside of [0, 1]")]
+    InvalidUtilization {
+        /// The channel source.
+        src: NodeId,
+        /// The channel destination.
+        dst: NodeId,
+        /// The utilization.
+        utilization: f64,
+    },
+
+    /// Error constructing empirical distribution.
+    #[error("failed to construct empirical distribution")]
+    EDist(#[from] EDistError),
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{network::TraversableNetwork, testing};
+
+    #[test]
+    fn telemetry_delays_are_split_by_utilization() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let (h0, h1, s2) = (NodeId::new(0), NodeId::new(1), NodeId::new(2));
+        let size = Bytes::new(1_000);
+        let telemetry = Telemetry {
+            samples: (0..10)
+                .map(|i| FctSample {
+                    size,
+                    start: Nanosecs::new(i * 1_000),
+                    fct: Nanosecs::new(10_000),
+                    ideal: Nanosecs::new(6_000),
+                    path: vec![h0, s2, h1],
+                })
+                .collect(),
+            counters: vec![
+                LinkCounter {
+                    src: h0,
+                    dst: s2,
+                    utilization: 0.5,
+                },
+                LinkCounter {
+                    src: s2,
+                    dst: h1,
+                    utilization: 0.75,
+                },
+            ],
+        };
+        let network = Network::new(&nodes, &links)?;
+        let delays = network
+            .clone()
+            .into_delays_from_telemetry(&telemetry, &TelemetryOpts::default())?;
+        let quantile = |src, dst| {
+            let eidx = delays
+                .edge_indices_between(src, dst, |c| c.first())
+                .next()?;
+            let pktnorm = delays.topology.graph[eidx]
+                .dists
+                .for_size(size)?
+                .quantile(0.5)?;
+            Some(delays.to_delay(size, pktnorm))
+        };
+        assert_eq!(quantile(h0, s2), Some(Nanosecs::new(1_000)));
+        assert_eq!(quantile(s2, h1), Some(Nanosecs::new(3_000)));
+        assert_eq!(quantile(h1, s2), None);
+        let rng = StdRng::seed_from_u64(0);
+        assert_eq!(
+            delays.predict(size, (h0, h1), rng),
+            Some(Nanosecs::new(4_000))
+        );
+
+        let unknown = Telemetry {
+            samples: vec![FctSample {
+                path: vec![h0, h1],
+                ..telemetry.samples[0].clone()
+            }],
+            counters: Vec::new(),
+        };
+        assert!(matches!(
+            network
+                .clone()
+                .into_delays_from_telemetry(&unknown, &TelemetryOpts::default()),
+            Err(TelemetryError::UnknownChannel { .. })
+        ));
+        let overloaded = Telemetry {
+            counters: vec![LinkCounter {
+                utilization: 1.5,
+                ..telemetry.counters[0]
+            }],
+            ..telemetry
+        };
+        assert!(matches!(
+            network.into_delays_from_telemetry(&overloaded, &TelemetryOpts::default()),
+            Err(TelemetryError::InvalidUtilization { .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! Merged delay networks. Delay networks built from independent segments of a workload, e.g.,
+//! traces from different days, can be combined with [`DelayNetwork::merge`] into a single network
+//! whose channels draw from every segment's delay samples, weighted by how much each segment
+//! should count.
+//!
+//! Every channel's size buckets are split at the boundaries of every input, and each merged bucket
+//! mixes the inputs' samples for the same sizes. Distributions by source rack and by client are
+//! merged as well. Distributions by time window and by offered load are dropped, because their
+//! ranges are specific to each input, and so are path affinities and raw FCT records. Everything
+//! else, including routes and sampling parameters, is taken from the first input.
+
+use crate::{
+    edist::EDistBuckets,
+    network::{dedup::DedupStats, DelayNetwork},
+    routing::RoutingAlgo,
+};
+
+impl<R> DelayNetwork<R>
+where
+    R: RoutingAlgo,
+{
+    /// Merges delay networks over the same topology. Each network comes with a positive weight;
+    /// wherever inputs share a size bucket, each input's share of the merged samples is
+    /// proportional to its weight. See [`crate::network::merge`].
+    pub fn merge<I>(networks: I) -> Result<Self, MergeError>
+    where
+        I: IntoIterator<Item = (Self, f64)>,
+    {
+        let mut networks = networks.into_iter().collect::<Vec<_>>();
+        if networks.is_empty() {
+            return Err(MergeError::NoNetworks);
+        }
+        for (i, (network, weight)) in networks.iter().enumerate() {
+            if !(weight.is_finite() && *weight > 0.0) {
+                return Err(MergeError::InvalidWeight(i));
+            }
+            if !network.has_topology_of(&networks[0].0) {
+                return Err(MergeError::TopologyMismatch(i));
+            }
+            if network.packets != networks[0].0.packets {
+                return Err(MergeError::PacketMismatch(i));
+            }
+        }
+        let rest = networks.split_off(1);
+        let (mut merged, weight) = networks.pop().unwrap();
+        for e in merged.topology.graph.edge_indices() {
+            let chans = std::iter::once((&merged.topology.graph[e], weight))
+                .chain(
+                    rest.iter()
+                        .map(|(n, weight)| (&n.topology.graph[e], *weight)),
+                )
+                .collect::<Vec<_>>();
+            let parts = chans
+                .iter()
+                .map(|&(chan, weight)| (&chan.dists, weight))
+                .collect::<Vec<_>>();
+            let dists = EDistBuckets::merge(&parts);
+            let mut clients = chans
+                .iter()
+                .flat_map(|(chan, _)| chan.client_dists.keys().copied())
+                .collect::<Vec<_>>();
+            clients.sort();
+            clients.dedup();
+            let client_dists = clients
+                .into_iter()
+                .filter_map(|client| {
+                    let parts = chans
+                        .iter()
+                        .filter_map(|&(chan, weight)| {
+                            Some((chan.client_dists.get(&client)?, weight))
+                        })
+                        .collect::<Vec<_>>();
+                    Some((client, EDistBuckets::merge(&parts)?))
+                })
+                .collect();
+            let chan = &mut merged.topology.graph[e];
+            if let Some(dists) = dists {
+                chan.dists = dists;
+            }
+            chan.client_dists = client_dists;
+            chan.time_dists.clear();
+        }
+        for (network, _) in rest {
+            merged.fill_violations.extend(network.fill_violations);
+            merged.failures.extend(network.failures);
+            merged.dedup = DedupStats {
+                nr_representatives: merged.dedup.nr_representatives
+                    + network.dedup.nr_representatives,
+                nr_simulated: merged.dedup.nr_simulated + network.dedup.nr_simulated,
+            };
+        }
+        merged.affinity = None;
+        merged.records = None;
+        Ok(merged)
+    }
+
+    // Returns true if both networks have the same links, in the same order
+    fn has_topology_of(&self, other: &Self) -> bool {
+        let (ours, theirs) = (&self.topology.graph, &other.topology.graph);
+        ours.node_count() == theirs.node_count()
+            && ours.edge_count() == theirs.edge_count()
+            && ours
+                .edge_weights()
+                .zip(theirs.edge_weights())
+                .all(|(a, b)| {
+                    (a.src, a.dst, a.bandwidth, a.delay) == (b.src, b.dst, b.bandwidth, b.delay)
+                })
+    }
+}
+
+/// Error type for [`DelayNetwork::merge`].
+#[derive(Debug, thiserror::Error)]
+pub enum MergeError {
+    /// No networks were given.
+    #[error("no networks to merge")]
+    NoNetworks,
+
+    /// A weight is not positive and finite.
+    #[error("weight of network {0} must be positive and finite")]
+    InvalidWeight(usize),
+
+    /// A network's links differ from those of the first network.
+    #[error("topology of network {0} differs from the first network's")]
+    TopologyMismatch(usize),
+
+    /// A network splits flows into packets differently than the first network.
+    #[error("packet profile of network {0} differs from the first network's")]
+    PacketMismatch(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::{Network, NodeId},
+        opts::SimOpts,
+        testing::{self, fixed_delay_network, FixedDelaySim},
+        units::{Bytes, Nanosecs},
+    };
+
+    #[test]
+    fn merged_samples_follow_weights() -> anyhow::Result<()> {
+        let merged = DelayNetwork::merge([
+            (fixed_delay_network(Nanosecs::new(100))?, 1.0),
+            (fixed_delay_network(Nanosecs::new(300))?, 3.0),
+        ])?;
+        let dist = merged
+            .distribution(Bytes::new(1_000), (NodeId::new(0), NodeId::new(2)))
+            .unwrap();
+        let (fast, slow) = (
+            fixed_delay_network(Nanosecs::new(100))?,
+            fixed_delay_network(Nanosecs::new(300))?,
+        );
+        let lo = fast.predict(
+            Bytes::new(1_000),
+            (NodeId::new(0), NodeId::new(2)),
+            &mut fast.rng(),
+        );
+        let hi = slow.predict(
+            Bytes::new(1_000),
+            (NodeId::new(0), NodeId::new(2)),
+            &mut slow.rng(),
+        );
+        let (lo, hi) = (lo.unwrap(), hi.unwrap());
+        assert!(lo < hi);
+        assert!(lo <= dist.quantile(0.0).unwrap() && dist.quantile(1.0).unwrap() <= hi);
+        // Each hop draws its slow samples three quarters of the time
+        let expected = lo.into_f64() + 0.75 * (hi - lo).into_f64();
+        assert!((dist.mean().into_f64() - expected).abs() < 0.05 * expected);
+        Ok(())
+    }
+
+    #[test]
+    fn merging_checks_inputs() -> anyhow::Result<()> {
+        let none: [(DelayNetwork, f64); 0] = [];
+        assert!(matches!(
+            DelayNetwork::merge(none),
+            Err(MergeError::NoNetworks)
+        ));
+        assert!(matches!(
+            DelayNetwork::merge([
+                (fixed_delay_network(Nanosecs::new(100))?, 1.0),
+                (fixed_delay_network(Nanosecs::new(100))?, 0.0)
+            ]),
+            Err(MergeError::InvalidWeight(1))
+        ));
+        let (nodes, links) = testing::three_node_config();
+        let other = Network::new(&nodes, &links)?
+            .into_simulations(Vec::new())
+            .into_delays(
+                SimOpts::builder()
+                    .link_sim(FixedDelaySim {
+                        delay_per_pkt: Nanosecs::new(100),
+                    })
+                    .build(),
+            )?;
+        assert!(matches!(
+            DelayNetwork::merge([
+                (fixed_delay_network(Nanosecs::new(100))?, 1.0),
+                (other, 1.0)
+            ]),
+            Err(MergeError::TopologyMismatch(1))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+//! Priority flow control (PFC) approximation. In lossless (e.g., RoCE) fabrics, a switch whose
+//! ingress queue exceeds its XOFF threshold pauses the upstream link, so congestion on one link
+//! slows down the links feeding it. Link-level decomposition simulates every link in isolation and
+//! misses this coupling.
+//!
+//! This module approximates it in two steps. First, a [`PauseModel`] estimates from a link's load
+//! how often the link pauses its upstream neighbors, and the pauses are propagated hop by hop
+//! against the direction of traffic into a [`PauseReport`]. Then, [`DelayNetwork::apply_pauses`]
+//! inflates the delays of every paused link, since a link paused a fraction `p` of the time only
+//! has a fraction `1 - p` of its capacity available.
+
+use petgraph::{visit::EdgeRef, Direction};
+
+use crate::{
+    constants::PacketProfile,
+    network::{Channel, DelayNetwork, EdgeIndex, NodeKind, SimNetwork},
+    routing::RoutingAlgo,
+    units::{BitsPerSec, Bytes},
+};
+
+/// PFC options.
+#[derive(Debug, Clone, Copy, PartialEq, typed_builder::TypedBuilder)]
+pub struct PfcOpts {
+    /// The queue length at which a switch pauses the upstream link.
+    #[builder(default = Bytes::new(100_000))]
+    pub xoff: Bytes,
+    /// The number of hops pauses propagate upstream.
+    #[builder(default = 2)]
+    pub max_hops: usize,
+    /// The largest fraction of time a link is considered paused, which bounds how much its delays
+    /// are inflated.
+    #[builder(default = 0.9)]
+    pub max_paused: f64,
+}
+
+impl Default for PfcOpts {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// What a [`PauseModel`] knows about a link.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PauseInput {
+    /// The link's edge index.
+    pub edge: EdgeIndex,
+    /// The link's offered load. See [`LinkLoad::value`](crate::network::LinkLoad::value).
+    pub load: f64,
+    /// The link's bandwidth.
+    pub bandwidth: BitsPerSec,
+    /// The packet profile of the network.
+    pub packets: PacketProfile,
+    /// The XOFF threshold.
+    pub xoff: Bytes,
+}
+
+/// A model of how often a link pauses its upstream neighbors.
+pub trait PauseModel {
+    /// Returns the probability, in [0, 1], that the link's queue exceeds the XOFF threshold.
+    fn pause_probability(&self, link: &PauseInput) -> f64;
+}
+
+/// The default [`PauseModel`], which treats the link as an M/M/1 queue of full packets: the queue
+/// exceeds `K` packets with probability `load^(K + 1)`. Links at or above capacity always pause.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueTailModel;
+
+impl PauseModel for QueueTailModel {
+    fn pause_probability(&self, link: &PauseInput) -> f64 {
+        if link.load >= 1.0 {
+            return 1.0;
+        }
+        let threshold = link.xoff.into_f64() / link.packets.full_pkt().into_f64();
+        link.load.max(0.0).powf(threshold.floor() + 1.0)
+    }
+}
+
+/// The estimated pauses of every link, obtained with [`SimNetwork::pause_report`].
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PauseReport {
+    /// The links which send or receive pauses, sorted by edge index.
+    pub links: Vec<LinkPause>,
+}
+
+impl PauseReport {
+    /// Returns true if no link sends or receives pauses.
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+}
+
+/// The estimated pauses of a link.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LinkPause {
+    /// The link's edge index.
+    pub edge: EdgeIndex,
+    /// The probability that the link pauses its upstream neighbors.
+    pub pausing: f64,
+    /// The fraction of time the link is paused by its downstream neighbors, capped at
+    /// [`PfcOpts::max_paused`].
+    pub paused: f64,
+}
+
+impl LinkPause {
+    /// Returns the factor by which the link's delays are inflated.
+    pub fn inflation(&self) -> f64 {
+        (1.0 - self.paused).recip()
+    }
+}
+
+impl<R> SimNetwork<R>
+where
+    R: RoutingAlgo,
+{
+    /// Estimates how often every link pauses and is paused, as described in the
+    /// [module documentation](self). A link is paused by the links leaving its destination
+    /// switch, other than the one back to its source; hosts don't forward traffic, so links into
+    /// hosts are never paused.
+    pub fn pause_report<M>(&self, model: &M, opts: &PfcOpts) -> PauseReport
+    where
+        M: PauseModel,
+    {
+        let graph = &self.topology.graph;
+        let pausing = graph
+            .edge_indices()
+            .map(|edge| {
+                let chan = &graph[edge];
+                if chan.nr_flows() == 0 {
+                    return 0.0;
+                }
+                let input = PauseInput {
+                    edge,
+                    load: chan.load().value(),
+                    bandwidth: chan.bandwidth(),
+                    packets: self.packets,
+                    xoff: opts.xoff,
+                };
+                model.pause_probability(&input).clamp(0.0, 1.0)
+            })
+            .collect::<Vec<_>>();
+        // Every hop, a link is paused whenever a downstream link is paused or pausing
+        let mut paused = vec![0.0; pausing.len()];
+        for _ in 0..opts.max_hops {
+            paused = graph
+                .edge_indices()
+                .map(|edge| {
+                    let (src, dst) = graph.edge_endpoints(edge).unwrap();
+                    if graph[dst].kind != NodeKind::Switch {
+                        return 0.0;
+                    }
+                    graph
+                        .edges_directed(dst, Direction::Outgoing)
+                        .filter(|next| next.target() != src)
+                        .map(|next| {
+                            let i = next.id().index();
+                            1.0 - (1.0 - pausing[i]) * (1.0 - paused[i])
+                        })
+                        .fold(0.0, f64::max)
+                })
+                .collect();
+        }
+        let links = graph
+            .edge_indices()
+            .filter(|e| pausing[e.index()] > 0.0 || paused[e.index()] > 0.0)
+            .map(|edge| LinkPause {
+                edge,
+                pausing: pausing[edge.index()],
+                paused: paused[edge.index()].min(opts.max_paused),
+            })
+            .collect();
+        PauseReport { links }
+    }
+}
+
+impl<R> DelayNetwork<R>
+where
+    R: RoutingAlgo,
+{
+    /// Inflates the delays of every paused link in `report` by its
+    /// [inflation factor](LinkPause::inflation). The report must come from the network this one
+    /// was built from.
+    pub fn apply_pauses(&mut self, report: &PauseReport) {
+        for link in &report.links {
+            if link.paused > 0.0 {
+                if let Some(chan) = self.topology.graph.edge_weight_mut(link.edge) {
+                    chan.scale_delays(link.inflation());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::prelude::*;
+
+    use super::*;
+    use crate::{
+        network::{Flow, FlowId, Network, NodeId},
+        opts::SimOpts,
+        testing,
+        units::Nanosecs,
+    };
+
+    struct AlwaysPausing(EdgeIndex);
+
+    impl PauseModel for AlwaysPausing {
+        fn pause_probability(&self, link: &PauseInput) -> f64 {
+            if link.edge == self.0 {
+                0.5
+            } else {
+                0.0
+            }
+        }
+    }
+
+    #[test]
+    fn pauses_propagate_upstream() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let id = NodeId::new;
+        let flows = (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: id(0),
+                dst: id(1),
+                size: Bytes::new(10_000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows);
+        let (up, down) = (
+            sims.find_edge(id(0), id(2)).unwrap(),
+            sims.find_edge(id(2), id(1)).unwrap(),
+        );
+        let report = sims.pause_report(&AlwaysPausing(down), &PfcOpts::default());
+        let pause = |e| report.links.iter().find(|l| l.edge == e).copied();
+        assert_eq!(pause(down).unwrap().paused, 0.0);
+        assert_eq!(pause(up).unwrap().paused, 0.5);
+        assert_eq!(pause(up).unwrap().inflation(), 2.0);
+        assert!(sims
+            .pause_report(&QueueTailModel, &PfcOpts::default())
+            .links
+            .iter()
+            .all(|l| (0.0..=1.0).contains(&l.pausing)));
+
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let mut delays = sims.into_delays(opts)?;
+        let predict = |delays: &DelayNetwork| {
+            delays.predict(Bytes::new(10_000), (id(0), id(1)), StdRng::seed_from_u64(0))
+        };
+        let before = predict(&delays).unwrap();
+        delays.apply_pauses(&report);
+        assert!(predict(&delays).unwrap() > before);
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+//! Replayable query logs. Every query made through a [`QueryLogger`] draws its randomness from its
+//! own RNG stream, derived only from the log's seed and the query's stream ID, and never from an
+//! RNG used to build the network. A [`QueryLog`] records each query's parameters, stream ID, and
+//! output, so [`DelayNetwork::replay_queries`] can later reproduce every output bit-for-bit from
+//! the log and the same network.
+//!
+//! Logs are tied to their network by a [fingerprint](DelayNetwork::fingerprint) of its delay
+//! distributions and sampling parameters. RNG streams are stable for a given version of this
+//! crate and its `rand` dependency.
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    network::{attribution::LatencyBreakdown, DelayNetwork, NodeId},
+    routing::RoutingAlgo,
+    units::{Bytes, Nanosecs},
+};
+
+/// A query against a delay network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Query {
+    /// What is queried.
+    pub kind: QueryKind,
+    /// The flow size.
+    pub size: Bytes,
+    /// The source.
+    pub src: NodeId,
+    /// The destination.
+    pub dst: NodeId,
+}
+
+/// The kinds of queries which can be logged, named after the corresponding [`DelayNetwork`]
+/// methods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryKind {
+    /// [`DelayNetwork::predict`].
+    Predict,
+    /// [`DelayNetwork::ideal_fct`].
+    IdealFct,
+    /// [`DelayNetwork::slowdown`].
+    Slowdown,
+    /// [`DelayNetwork::predict_breakdown`].
+    PredictBreakdown,
+}
+
+/// The output of a query.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryOutput {
+    /// A delay or FCT.
+    Nanosecs(Option<Nanosecs>),
+    /// A slowdown.
+    Slowdown(Option<f64>),
+    /// An FCT breakdown.
+    Breakdown(Option<LatencyBreakdown>),
+}
+
+impl PartialEq for QueryOutput {
+    // Slowdowns are compared bit-for-bit
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Nanosecs(a), Self::Nanosecs(b)) => a == b,
+            (Self::Slowdown(a), Self::Slowdown(b)) => a.map(f64::to_bits) == b.map(f64::to_bits),
+            (Self::Breakdown(a), Self::Breakdown(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// A logged query.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LoggedQuery {
+    /// The ID of the RNG stream the query drew from.
+    pub stream: u64,
+    /// The query.
+    pub query: Query,
+    /// Its output.
+    pub output: QueryOutput,
+}
+
+/// A log of queries against a delay network.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct QueryLog {
+    seed: u64,
+    fingerprint: u64,
+    queries: Vec<LoggedQuery>,
+}
+
+impl QueryLog {
+    /// Returns the seed all RNG streams are derived from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the fingerprint of the network the queries were made against.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Returns the logged queries, in order.
+    pub fn queries(&self) -> &[LoggedQuery] {
+        &self.queries
+    }
+
+    /// Keeps only the queries for which `f` returns true, e.g., to audit only the queries behind a
+    /// particular number. Stream IDs are preserved, so the remaining queries still replay exactly.
+    pub fn retain(&mut self, f: impl FnMut(&LoggedQuery) -> bool) {
+        self.queries.retain(f);
+    }
+}
+
+/// Makes queries against a delay network and logs them. Created by
+/// [`DelayNetwork::logged_queries`].
+#[derive(Debug)]
+pub struct QueryLogger<'a, R> {
+    network: &'a DelayNetwork<R>,
+    log: QueryLog,
+}
+
+impl<'a, R> QueryLogger<'a, R>
+where
+    R: RoutingAlgo,
+{
+    pub(crate) fn new(network: &'a DelayNetwork<R>, seed: u64) -> Self {
+        Self {
+            network,
+            log: QueryLog {
+                seed,
+                fingerprint: network.fingerprint(),
+                queries: Vec::new(),
+            },
+        }
+    }
+
+    /// Like [`DelayNetwork::predict`], but logged.
+    pub fn predict(&mut self, size: Bytes, (src, dst): (NodeId, NodeId)) -> Option<Nanosecs> {
+        match self.query(QueryKind::Predict, size, (src, dst)) {
+            QueryOutput::Nanosecs(delay) => delay,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Like [`DelayNetwork::ideal_fct`], but logged.
+    pub fn ideal_fct(&mut self, size: Bytes, (src, dst): (NodeId, NodeId)) -> Option<Nanosecs> {
+        match self.query(QueryKind::IdealFct, size, (src, dst)) {
+            QueryOutput::Nanosecs(fct) => fct,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Like [`DelayNetwork::slowdown`], but logged.
+    pub fn slowdown(&mut self, size: Bytes, (src, dst): (NodeId, NodeId)) -> Option<f64> {
+        match self.query(QueryKind::Slowdown, size, (src, dst)) {
+            QueryOutput::Slowdown(slowdown) => slowdown,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Like [`DelayNetwork::predict_breakdown`], but logged.
+    pub fn predict_breakdown(
+        &mut self,
+        size: Bytes,
+        (src, dst): (NodeId, NodeId),
+    ) -> Option<LatencyBreakdown> {
+        match self.query(QueryKind::PredictBreakdown, size, (src, dst)) {
+            QueryOutput::Breakdown(breakdown) => breakdown,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the log so far.
+    pub fn log(&self) -> &QueryLog {
+        &self.log
+    }
+
+    /// Consumes the logger, returning its log.
+    pub fn into_log(self) -> QueryLog {
+        self.log
+    }
+
+    fn query(&mut self, kind: QueryKind, size: Bytes, (src, dst): (NodeId, NodeId)) -> QueryOutput {
+        let stream = self.log.queries.len() as u64;
+        let query = Query {
+            kind,
+            size,
+            src,
+            dst,
+        };
+        let output = run(self.network, self.log.seed, stream, query);
+        self.log.queries.push(LoggedQuery {
+            stream,
+            query,
+            output,
+        });
+        output
+    }
+}
+
+pub(crate) fn run<R>(network: &DelayNetwork<R>, seed: u64, stream: u64, query: Query) -> QueryOutput
+where
+    R: RoutingAlgo,
+{
+    let mut rng = query_rng(seed, stream);
+    let Query {
+        kind,
+        size,
+        src,
+        dst,
+    } = query;
+    match kind {
+        QueryKind::Predict => QueryOutput::Nanosecs(network.predict(size, (src, dst), &mut rng)),
+        QueryKind::IdealFct => QueryOutput::Nanosecs(network.ideal_fct(size, (src, dst), &mut rng)),
+        QueryKind::Slowdown => QueryOutput::Slowdown(network.slowdown(size, (src, dst), &mut rng)),
+        QueryKind::PredictBreakdown => {
+            QueryOutput::Breakdown(network.predict_breakdown(size, (src, dst), &mut rng))
+        }
+    }
+}
+
+// Every (seed, stream) pair seeds an independent generator.
+fn query_rng(seed: u64, stream: u64) -> StdRng {
+    let mut bytes = [0; 32];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    bytes[8..16].copy_from_slice(&stream.to_le_bytes());
+    StdRng::from_seed(bytes)
+}
+
+/// Errors which can be encountered replaying a query log.
+#[derive(Debug, thiserror::Error)]
+pub enum QueryLogError {
+    /// The log was made against a different network.
+    #[error("Log fingerprint {logged:#x} does not match network fingerprint {actual:#x}")]
+    NetworkMismatch {
+        /// The fingerprint in the log.
+        logged: u64,
+        /// The network's fingerprint.
+        actual: u64,
+    },
+
+    /// A query's output differs from the logged output.
+    #[error("Query on stream {stream} diverged from the log")]
+    Diverged {
+        /// The query's stream ID.
+        stream: u64,
+    },
+}
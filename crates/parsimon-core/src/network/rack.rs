@@ -0,0 +1,161 @@
+//! A shortcut for paths between hosts under the same ToR. With shortest-path ECMP routing, the
+//! paths from a host to another host in its rack are exactly the two-hop paths through the
+//! switches both hosts are attached to, each chosen with equal probability. They can be found
+//! from the hosts' links alone, without walking the routing table.
+
+use petgraph::{graph::EdgeIndex, visit::EdgeRef, Direction};
+use rand::Rng;
+
+use crate::network::{
+    topology::Topology,
+    types::{NodeId, NodeKind},
+};
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RackIndex {
+    // Indexed by node ID. Only hosts which aren't linked to other hosts have entries.
+    hosts: Vec<Option<HostLinks>>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct HostLinks {
+    // (switch, edge) pairs, sorted by switch
+    up: Vec<(NodeId, EdgeIndex)>,
+    down: Vec<(NodeId, EdgeIndex)>,
+}
+
+impl RackIndex {
+    pub(crate) fn new<C: Clone>(topology: &Topology<C>) -> Self {
+        let g = &topology.graph;
+        let mut hosts = vec![None; g.node_count()];
+        for idx in g.node_indices() {
+            let node = &g[idx];
+            // Paths from hosts with a virtual NIC leave through the NIC
+            if matches!(node.kind, NodeKind::Switch) || topology.nics.contains_key(&node.id) {
+                continue;
+            }
+            let links = |dir| {
+                let mut links = g
+                    .edges_directed(idx, dir)
+                    .map(|e| {
+                        let other = match dir {
+                            Direction::Outgoing => e.target(),
+                            Direction::Incoming => e.source(),
+                        };
+                        (&g[other], e.id())
+                    })
+                    .collect::<Vec<_>>();
+                // Host-to-host links make two-hop paths through a switch longer than necessary
+                let is_rack_host = links
+                    .iter()
+                    .all(|(n, _)| matches!(n.kind, NodeKind::Switch));
+                links.sort_by_key(|(n, _)| n.id);
+                is_rack_host.then(|| links.into_iter().map(|(n, e)| (n.id, e)).collect())
+            };
+            if let (Some(up), Some(down)) = (links(Direction::Outgoing), links(Direction::Incoming))
+            {
+                hosts[node.id.inner()] = Some(HostLinks { up, down });
+            }
+        }
+        Self { hosts }
+    }
+
+    /// Samples a path from `src` to `dst` if both are hosts under a common switch, or returns
+    /// `None` if the routing table must be consulted.
+    pub(crate) fn sample<RNG>(
+        &self,
+        src: NodeId,
+        dst: NodeId,
+        rng: &mut RNG,
+    ) -> Option<[EdgeIndex; 2]>
+    where
+        RNG: Rng,
+    {
+        if src == dst {
+            return None;
+        }
+        let src = self.hosts.get(src.inner())?.as_ref()?;
+        let dst = self.hosts.get(dst.inner())?.as_ref()?;
+        // Both lists are short and sorted by switch, so merge them
+        let mut paths = [None; 4];
+        let mut nr_paths = 0;
+        let (mut i, mut j) = (0, 0);
+        while i < src.up.len() && j < dst.down.len() {
+            let ((a, up), (b, down)) = (src.up[i], dst.down[j]);
+            match a.cmp(&b) {
+                std::cmp::Ordering::Less => i += 1,
+                std::cmp::Ordering::Greater => j += 1,
+                std::cmp::Ordering::Equal => {
+                    if nr_paths == paths.len() {
+                        // Too many ToRs to be a rack; let the routing table handle it
+                        return None;
+                    }
+                    paths[nr_paths] = Some([up, down]);
+                    nr_paths += 1;
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        match nr_paths {
+            0 => None,
+            1 => paths[0],
+            n => paths[rng.gen_range(0..n)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::testing;
+
+    fn endpoints<C: Clone>(topology: &Topology<C>, path: [EdgeIndex; 2]) -> Vec<NodeId> {
+        let (a, b) = topology.graph.edge_endpoints(path[0]).unwrap();
+        let (c, d) = topology.graph.edge_endpoints(path[1]).unwrap();
+        assert_eq!(b, c);
+        [a, b, d]
+            .iter()
+            .map(|&idx| topology.graph[idx].id)
+            .collect()
+    }
+
+    #[test]
+    fn same_rack_hosts_have_shortcuts() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let topology = Topology::new(&nodes, &links)?;
+        let racks = RackIndex::new(&topology);
+        let mut rng = StdRng::seed_from_u64(0);
+        let id = NodeId::new;
+        let path = racks.sample(id(0), id(1), &mut rng).unwrap();
+        assert_eq!(endpoints(&topology, path), [id(0), id(4), id(1)]);
+        // Hosts in different racks and switches go through the routing table
+        assert!(racks.sample(id(0), id(3), &mut rng).is_none());
+        assert!(racks.sample(id(0), id(4), &mut rng).is_none());
+        assert!(racks.sample(id(0), id(0), &mut rng).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn dual_homed_hosts_use_both_tors() -> anyhow::Result<()> {
+        let (nodes, links) = testing::dual_homed_config();
+        let topology = Topology::new(&nodes, &links)?;
+        let racks = RackIndex::new(&topology);
+        let mut rng = StdRng::seed_from_u64(0);
+        let tors = (0..100)
+            .map(|_| {
+                let path = racks
+                    .sample(NodeId::new(0), NodeId::new(1), &mut rng)
+                    .unwrap();
+                endpoints(&topology, path)[1]
+            })
+            .collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(
+            tors.into_iter().collect::<Vec<_>>(),
+            [NodeId::new(4), NodeId::new(5)]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+//! Application-level latency estimates. An [`Operation`] composes flows into what an application
+//! actually waits for, e.g., an RPC is a request followed by a response, and a scatter-gather waits
+//! for the slowest of several RPCs. [`DelayNetwork::operation_latency`] samples the operation many
+//! times over the delay network to estimate its latency distribution, turning flow-level tails
+//! into service-level tails.
+//!
+//! Flows within an operation are sampled independently, so correlations between flows sharing
+//! congested links are not captured.
+
+use rand::prelude::*;
+
+use crate::{
+    edist,
+    network::{types::NodeId, DelayNetwork},
+    routing::RoutingAlgo,
+    units::{Bytes, Nanosecs},
+};
+
+/// An application-level operation built from flows.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    /// A single flow, which takes its predicted FCT.
+    Flow {
+        /// The flow's size.
+        size: Bytes,
+        /// The flow's source.
+        src: NodeId,
+        /// The flow's destination.
+        dst: NodeId,
+    },
+    /// A fixed amount of time, e.g., to process a request.
+    Compute(Nanosecs),
+    /// Operations which run one after another, taking the sum of their latencies.
+    Seq(Vec<Operation>),
+    /// Operations which run in parallel, taking the largest of their latencies.
+    Par(Vec<Operation>),
+}
+
+impl Operation {
+    /// Returns a flow of `size` bytes from `src` to `dst`.
+    pub fn flow(size: Bytes, src: NodeId, dst: NodeId) -> Self {
+        Self::Flow { size, src, dst }
+    }
+
+    /// Returns an RPC from `client` to `server`: a request of `request` bytes followed by a
+    /// response of `response` bytes.
+    pub fn rpc(client: NodeId, server: NodeId, request: Bytes, response: Bytes) -> Self {
+        Self::Seq(vec![
+            Self::flow(request, client, server),
+            Self::flow(response, server, client),
+        ])
+    }
+
+    /// Returns a scatter-gather from `client` to every server in `servers`, which completes once
+    /// the slowest [RPC](Self::rpc) does.
+    pub fn scatter_gather(
+        client: NodeId,
+        servers: impl IntoIterator<Item = NodeId>,
+        request: Bytes,
+        response: Bytes,
+    ) -> Self {
+        Self::Par(
+            servers
+                .into_iter()
+                .map(|server| Self::rpc(client, server, request, response))
+                .collect(),
+        )
+    }
+
+    /// Returns an operation which runs `self` and then `next`.
+    pub fn then(self, next: Operation) -> Self {
+        match self {
+            Self::Seq(mut ops) => {
+                ops.push(next);
+                Self::Seq(ops)
+            }
+            op => Self::Seq(vec![op, next]),
+        }
+    }
+
+    /// Returns the number of flows in the operation.
+    pub fn nr_flows(&self) -> usize {
+        match self {
+            Self::Flow { .. } => 1,
+            Self::Compute(_) => 0,
+            Self::Seq(ops) | Self::Par(ops) => ops.iter().map(Self::nr_flows).sum(),
+        }
+    }
+}
+
+/// The latency distribution of an [`Operation`], estimated with
+/// [`DelayNetwork::operation_latency`].
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct OperationLatency {
+    /// The number of samples.
+    pub nr_samples: usize,
+    /// The mean latency.
+    pub mean: Nanosecs,
+    /// The median latency.
+    pub p50: Nanosecs,
+    /// The 90th percentile latency.
+    pub p90: Nanosecs,
+    /// The 99th percentile latency.
+    pub p99: Nanosecs,
+    /// The 99.9th percentile latency.
+    pub p999: Nanosecs,
+    /// The largest sampled latency.
+    pub max: Nanosecs,
+}
+
+impl OperationLatency {
+    /// Summarizes latency samples, or returns `None` if there are none.
+    pub fn from_samples(samples: &[Nanosecs]) -> Option<Self> {
+        let values = samples.iter().map(|s| s.into_f64()).collect::<Vec<_>>();
+        let quantile = |q| edist::quantile(&values, q).map(|v| Nanosecs::new(v as u64));
+        Some(Self {
+            nr_samples: samples.len(),
+            mean: Nanosecs::new((values.iter().sum::<f64>() / values.len() as f64) as u64),
+            p50: quantile(0.5)?,
+            p90: quantile(0.9)?,
+            p99: quantile(0.99)?,
+            p999: quantile(0.999)?,
+            max: samples.iter().copied().max()?,
+        })
+    }
+}
+
+impl<R> DelayNetwork<R>
+where
+    R: RoutingAlgo,
+{
+    /// Samples the latency of `op` once, or returns `None` if the FCT of some flow in it can't
+    /// be predicted.
+    pub fn sample_operation<RNG>(&self, op: &Operation, mut rng: RNG) -> Option<Nanosecs>
+    where
+        RNG: Rng,
+    {
+        self.sample_operation_with(op, &mut rng)
+    }
+
+    fn sample_operation_with<RNG>(&self, op: &Operation, rng: &mut RNG) -> Option<Nanosecs>
+    where
+        RNG: Rng,
+    {
+        match op {
+            &Operation::Flow { size, src, dst } => self
+                .predict_breakdown(size, (src, dst), &mut *rng)
+                .map(|b| b.total()),
+            &Operation::Compute(duration) => Some(duration),
+            Operation::Seq(ops) => ops
+                .iter()
+                .map(|op| self.sample_operation_with(op, rng))
+                .sum(),
+            Operation::Par(ops) => ops
+                .iter()
+                .map(|op| self.sample_operation_with(op, rng))
+                .collect::<Option<Vec<_>>>()
+                .map(|latencies| latencies.into_iter().max().unwrap_or_default()),
+        }
+    }
+
+    /// Estimates the latency distribution of `op` from `nr_samples` Monte Carlo samples, or
+    /// returns `None` if the FCT of some flow in it can't be predicted or `nr_samples` is zero.
+    pub fn operation_latency<RNG>(
+        &self,
+        op: &Operation,
+        nr_samples: usize,
+        mut rng: RNG,
+    ) -> Option<OperationLatency>
+    where
+        RNG: Rng,
+    {
+        let samples = (0..nr_samples)
+            .map(|_| self.sample_operation(op, &mut rng))
+            .collect::<Option<Vec<_>>>()?;
+        OperationLatency::from_samples(&samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::{Flow, FlowId, Network},
+        opts::SimOpts,
+        testing,
+    };
+
+    #[test]
+    fn scatter_gather_waits_for_the_slowest_rpc() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (1..4)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(i),
+                size: Bytes::new(10_000),
+                start: Nanosecs::ZERO,
+            })
+            .collect::<Vec<_>>();
+        let opts = SimOpts::builder()
+            .link_sim(testing::FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let delays = Network::new(&nodes, &links)?
+            .into_simulations(flows)
+            .into_delays(opts)?;
+        let client = NodeId::new(0);
+        let servers = (1..4).map(NodeId::new);
+        let (request, response) = (Bytes::new(100), Bytes::new(10_000));
+        let op = Operation::scatter_gather(client, servers.clone(), request, response)
+            .then(Operation::Compute(Nanosecs::new(1_000)));
+        assert_eq!(op.nr_flows(), 6);
+
+        let gather = delays
+            .operation_latency(&op, 100, StdRng::seed_from_u64(0))
+            .unwrap();
+        assert_eq!(gather.nr_samples, 100);
+        assert!(gather.p50 <= gather.p99 && gather.p99 <= gather.max);
+        for server in servers {
+            let rpc = Operation::rpc(client, server, request, response);
+            let single = delays
+                .operation_latency(&rpc, 100, StdRng::seed_from_u64(0))
+                .unwrap();
+            assert!(single.max + Nanosecs::new(1_000) <= gather.max);
+        }
+
+        let unreachable = Operation::rpc(client, client, request, response);
+        assert_eq!(
+            delays.operation_latency(&unreachable, 10, StdRng::seed_from_u64(0)),
+            None
+        );
+        Ok(())
+    }
+}
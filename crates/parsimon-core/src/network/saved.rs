@@ -0,0 +1,259 @@
+//! Persisted delay networks. A [`SavedDelayNetwork`] holds everything queries depend on: the
+//! topology, every channel's delay distributions, and the sampling parameters. Saving one lets a
+//! network be built once and queried later, e.g., from another process, without rerunning link
+//! simulations. Raw FCT records and consistency violations found while filling distributions are
+//! not saved.
+//!
+//! Saved networks are tied to a version of this crate; they are tagged with the network's
+//! [fingerprint](DelayNetwork::fingerprint), which is checked on load.
+
+use std::ops::Range;
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    client::ClientId,
+    consistency::ConsistencyCheck,
+    constants::PacketProfile,
+    edist::EDistBuckets,
+    network::{
+        affinity::PathAffinity,
+        dedup::DedupStats,
+        ecmp::EcmpModel,
+        fallback::LinkFailure,
+        topology::Topology,
+        types::{Link, Node},
+        DelayNetwork, DelayParts, Network, TopologyError,
+    },
+    routing::RoutingAlgo,
+    units::Nanosecs,
+};
+
+/// A delay network in a serializable form. See [`DelayNetwork::save`] and
+/// [`DelayNetwork::load`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SavedDelayNetwork {
+    fingerprint: u64,
+    nodes: Vec<Node>,
+    links: Vec<Link>,
+    // In order of edge index
+    channels: Vec<SavedChannel>,
+    consistency: ConsistencyCheck,
+    hop_correlation: f64,
+    origin: Nanosecs,
+    packets: PacketProfile,
+    ecmp: EcmpModel,
+    dedup: DedupStats,
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(default)]
+    failures: Vec<LinkFailure>,
+    #[serde(default)]
+    affinity: Option<PathAffinity>,
+}
+
+impl SavedDelayNetwork {
+    /// Returns the fingerprint of the saved network.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct SavedChannel {
+    dists: EDistBuckets,
+    client_dists: FxHashMap<ClientId, EDistBuckets>,
+    time_dists: Vec<(Range<Nanosecs>, EDistBuckets)>,
+}
+
+impl<R> DelayNetwork<R>
+where
+    R: RoutingAlgo,
+{
+    /// Saves the network's topology, delay distributions, and sampling parameters.
+    pub fn save(&self) -> SavedDelayNetwork {
+        SavedDelayNetwork {
+            fingerprint: self.fingerprint(),
+            nodes: self.nodes().cloned().collect(),
+            links: self.links().copied().collect(),
+            channels: self
+                .topology
+                .graph
+                .edge_weights()
+                .map(|chan| SavedChannel {
+                    dists: chan.dists.clone(),
+                    client_dists: chan.client_dists.clone(),
+                    time_dists: chan.time_dists.clone(),
+                })
+                .collect(),
+            consistency: self.consistency,
+            hop_correlation: self.hop_correlation,
+            origin: self.origin,
+            packets: self.packets,
+            ecmp: self.ecmp.clone(),
+            dedup: self.dedup,
+            seed: self.seed,
+            failures: self.failures.clone(),
+            affinity: self.affinity.clone(),
+        }
+    }
+}
+
+impl DelayNetwork {
+    /// Rebuilds a network saved with [`DelayNetwork::save`], with default [BFS
+    /// routing](crate::network::BfsRoutes).
+    pub fn load(saved: SavedDelayNetwork) -> Result<Self, SavedDelayNetworkError> {
+        let network = Network::new(&saved.nodes, &saved.links)?;
+        let mut topology = Topology::new_edist(&Topology::new_traced(&network.topology));
+        let nr_edges = topology.graph.edge_count();
+        if saved.channels.len() != nr_edges {
+            return Err(SavedDelayNetworkError::ChannelMismatch {
+                expected: nr_edges,
+                found: saved.channels.len(),
+            });
+        }
+        for (chan, saved) in topology.graph.edge_weights_mut().zip(saved.channels) {
+            chan.dists = saved.dists;
+            chan.client_dists = saved.client_dists;
+            chan.time_dists = saved.time_dists;
+        }
+        let parts = DelayParts {
+            consistency: saved.consistency,
+            origin: saved.origin,
+            packets: saved.packets,
+            ecmp: saved.ecmp,
+            seed: saved.seed,
+            dedup: saved.dedup,
+            failures: saved.failures,
+            ..DelayParts::default()
+        };
+        let mut delays = DelayNetwork::new(topology, network.routes, parts);
+        delays.hop_correlation = saved.hop_correlation;
+        delays.affinity = saved.affinity;
+        if delays.fingerprint() != saved.fingerprint {
+            return Err(SavedDelayNetworkError::FingerprintMismatch);
+        }
+        Ok(delays)
+    }
+}
+
+/// Error type for loading a [`SavedDelayNetwork`].
+#[derive(Debug, thiserror::Error)]
+pub enum SavedDelayNetworkError {
+    /// The saved topology is invalid.
+    #[error("invalid topology")]
+    InvalidTopology(#[from] TopologyError),
+
+    /// The number of saved channels doesn't match the topology.
+    #[error("expected {expected} channels, found {found}")]
+    ChannelMismatch {
+        /// The number of channels in the topology.
+        expected: usize,
+        /// The number of saved channels.
+        found: usize,
+    },
+
+    /// The rebuilt network differs from the saved one, e.g., because it was saved by another
+    /// version of this crate.
+    #[error("fingerprint mismatch")]
+    FingerprintMismatch,
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        edist::BucketOpts,
+        network::{Flow, FlowId, NodeId},
+        opts::SimOpts,
+        testing::{self, FixedDelaySim},
+        units::Bytes,
+    };
+
+    #[test]
+    fn saved_networks_answer_queries_identically() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (0..40)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 4),
+                dst: NodeId::new((i + 1) % 4),
+                size: Bytes::new(1000 * (i as u64 % 5 + 1)),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect();
+        let opts = SimOpts::builder()
+            .link_sim(FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .bucket_opts(BucketOpts::new(2, 5))
+            .source_racks(true)
+            .build();
+        let mut delays = Network::new(&nodes, &links)?
+            .into_simulations(flows)
+            .into_delays(opts)?;
+        delays.set_hop_correlation(0.5)?;
+
+        let bytes = rmp_serde::to_vec(&delays.save())?;
+        let loaded = DelayNetwork::load(rmp_serde::from_slice(&bytes)?)?;
+        assert_eq!(loaded.fingerprint(), delays.fingerprint());
+        for (src, dst) in [(0, 1), (0, 2), (3, 1)] {
+            let query = (NodeId::new(src), NodeId::new(dst));
+            let rng = StdRng::seed_from_u64(src as u64);
+            assert_eq!(
+                loaded.predict(Bytes::new(5000), query, rng.clone()),
+                delays.predict(Bytes::new(5000), query, rng)
+            );
+        }
+
+        let mut saved = delays.save();
+        saved.channels.pop();
+        assert!(matches!(
+            DelayNetwork::load(saved),
+            Err(SavedDelayNetworkError::ChannelMismatch { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn seeded_runs_save_identically() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flows = (0..40)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 4),
+                dst: NodeId::new((i + 1) % 4),
+                size: Bytes::new(1000 * (i as u64 % 5 + 1)),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let run = |seed| {
+            let opts = SimOpts::builder()
+                .link_sim(FixedDelaySim {
+                    delay_per_pkt: Nanosecs::new(100),
+                })
+                .bucket_opts(BucketOpts::new(2, 5))
+                .seed(seed)
+                .build();
+            let spec = crate::spec::Spec::builder()
+                .nodes(nodes.clone())
+                .links(links.clone())
+                .flows(flows.clone())
+                .build();
+            let delays = crate::run::run(spec, opts, crate::cluster::DefaultClustering)?;
+            anyhow::Ok((rmp_serde::to_vec(&delays.save())?, delays))
+        };
+        let (bytes, delays) = run(7)?;
+        let (again, other) = run(7)?;
+        assert_eq!(bytes, again);
+        assert_eq!(delays.seed(), Some(7));
+        let query = (NodeId::new(0), NodeId::new(2));
+        assert_eq!(
+            delays.predict(Bytes::new(5000), query, delays.rng()),
+            other.predict(Bytes::new(5000), query, other.rng())
+        );
+        Ok(())
+    }
+}
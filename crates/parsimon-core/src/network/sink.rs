@@ -0,0 +1,176 @@
+//! Sinks for streaming bulk predictions. [`DelayNetwork::predict_into`] draws flows from an
+//! iterator and writes their [breakdowns](FlowBreakdown) to a [`PredictionSink`] in batches of
+//! bounded size, so prediction jobs run in constant memory regardless of the number of flows.
+//!
+//! A sink applies backpressure by blocking in [`PredictionSink::write`]: a [`ChannelSink`] over a
+//! bounded channel, for example, stops prediction until the consumer catches up. Formats without a
+//! built-in sink (e.g., Parquet) can be written from a [`CallbackSink`].
+//!
+//! [`DelayNetwork::predict_into`]: crate::network::DelayNetwork::predict_into
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::network::attribution::FlowBreakdown;
+
+/// The default number of predictions written to a sink at a time.
+pub const DEFAULT_BATCH_SIZE: usize = 4096;
+
+/// A destination for streamed predictions.
+pub trait PredictionSink {
+    /// Writes a batch of predictions. Batches arrive in the order flows were drawn.
+    fn write(&mut self, batch: &[FlowBreakdown]) -> Result<(), SinkError>;
+
+    /// Called once after the last batch has been written.
+    fn finish(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+impl<S: PredictionSink + ?Sized> PredictionSink for &mut S {
+    fn write(&mut self, batch: &[FlowBreakdown]) -> Result<(), SinkError> {
+        (**self).write(batch)
+    }
+
+    fn finish(&mut self) -> Result<(), SinkError> {
+        (**self).finish()
+    }
+}
+
+/// Writes predictions as CSV with the columns `id`, `src`, `dst`, `size`, `start_ns`,
+/// `propagation_ns`, `transmission_ns`, and `queueing_ns`.
+#[derive(Debug)]
+pub struct CsvSink<W: Write> {
+    writer: W,
+    wrote_header: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    /// Creates a sink writing to `writer`. Writes are not buffered by the sink.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            wrote_header: false,
+        }
+    }
+
+    /// Consumes the sink, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl CsvSink<BufWriter<File>> {
+    /// Creates a sink writing to a new file at `path`.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, SinkError> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> PredictionSink for CsvSink<W> {
+    fn write(&mut self, batch: &[FlowBreakdown]) -> Result<(), SinkError> {
+        if !self.wrote_header {
+            writeln!(
+                self.writer,
+                "id,src,dst,size,start_ns,propagation_ns,transmission_ns,queueing_ns"
+            )?;
+            self.wrote_header = true;
+        }
+        for FlowBreakdown { flow, breakdown } in batch {
+            writeln!(
+                self.writer,
+                "{},{},{},{},{},{},{},{}",
+                flow.id,
+                flow.src,
+                flow.dst,
+                flow.size.into_u64(),
+                flow.start.into_u64(),
+                breakdown.propagation.into_u64(),
+                breakdown.transmission.into_u64(),
+                breakdown.queueing.into_u64()
+            )?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), SinkError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Sends batches of predictions over a channel. With a bounded channel, prediction blocks while
+/// the channel is full.
+#[derive(Debug, Clone)]
+pub struct ChannelSink {
+    tx: Sender<Vec<FlowBreakdown>>,
+}
+
+impl ChannelSink {
+    /// Creates a sink sending to `tx`.
+    pub fn new(tx: Sender<Vec<FlowBreakdown>>) -> Self {
+        Self { tx }
+    }
+
+    /// Creates a sink over a new channel holding at most `cap` batches, returning the sink and
+    /// the receiving end.
+    pub fn bounded(cap: usize) -> (Self, Receiver<Vec<FlowBreakdown>>) {
+        let (tx, rx) = crossbeam_channel::bounded(cap);
+        (Self::new(tx), rx)
+    }
+}
+
+impl PredictionSink for ChannelSink {
+    fn write(&mut self, batch: &[FlowBreakdown]) -> Result<(), SinkError> {
+        self.tx
+            .send(batch.to_vec())
+            .map_err(|_| SinkError::Disconnected)
+    }
+}
+
+/// Calls a function on every batch of predictions.
+#[derive(Debug, Clone)]
+pub struct CallbackSink<F>(pub F);
+
+impl<F> PredictionSink for CallbackSink<F>
+where
+    F: FnMut(&[FlowBreakdown]) -> Result<(), SinkError>,
+{
+    fn write(&mut self, batch: &[FlowBreakdown]) -> Result<(), SinkError> {
+        (self.0)(batch)
+    }
+}
+
+/// Counts of the flows streamed by [`DelayNetwork::predict_into`].
+///
+/// [`DelayNetwork::predict_into`]: crate::network::DelayNetwork::predict_into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct StreamStats {
+    /// The number of predictions written.
+    pub nr_predicted: usize,
+    /// The number of flows for which no prediction could be made.
+    pub nr_skipped: usize,
+    /// The number of batches written.
+    pub nr_batches: usize,
+}
+
+/// Error type for [`PredictionSink`]s.
+#[derive(Debug, thiserror::Error)]
+pub enum SinkError {
+    /// I/O error.
+    #[error("I/O error")]
+    Io(#[from] io::Error),
+
+    /// The receiving end of a channel was dropped.
+    #[error("Channel receiver disconnected")]
+    Disconnected,
+
+    /// Error raised by a custom sink.
+    #[error("Sink error")]
+    Custom(#[source] anyhow::Error),
+}
@@ -0,0 +1,208 @@
+//! Structured link statistics for a [`SimNetwork`], e.g., for capacity dashboards. A
+//! [`LinkStatsReport`] lists the workload offered to every link along with its cluster, and rolls
+//! link loads up into a histogram per tier pair. Tiers are as in [`crate::oversubscription`]:
+//! hosts are in tier 0, ToRs in tier 1, and so on.
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    network::{EdgeIndex, NodeId, SimNetwork},
+    oversubscription,
+    routing::RoutingAlgo,
+    units::{BitsPerSec, Bytes, Nanosecs},
+};
+
+/// The number of bins in a [`TierStats`] load histogram. Bin `i < NR_LOAD_BINS - 1` counts links
+/// with loads in `[i / 10, (i + 1) / 10)`, and the last bin counts overloaded links.
+pub const NR_LOAD_BINS: usize = 11;
+
+/// Statistics of every link in a network.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LinkStatsReport {
+    /// Per-link statistics, sorted by edge index.
+    pub links: Vec<LinkStats>,
+    /// Roll-ups of links between the same pair of tiers, sorted by tier pair.
+    pub tiers: Vec<TierStats>,
+}
+
+/// Statistics of a single link.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LinkStats {
+    /// The link's edge index.
+    pub edge: EdgeIndex,
+    /// The link's source.
+    pub src: NodeId,
+    /// The link's destination.
+    pub dst: NodeId,
+    /// The link's bandwidth.
+    pub bandwidth: BitsPerSec,
+    /// The number of flows traversing the link.
+    pub nr_flows: usize,
+    /// The number of bytes offered to the link.
+    pub nr_bytes: Bytes,
+    /// The offered load. See [`LinkLoad::value`](crate::network::LinkLoad::value).
+    pub load: f64,
+    /// The rate of ACKs for the reverse direction's flows, under the default
+    /// [`AckModel`](crate::opts::AckModel).
+    pub ack_rate: BitsPerSec,
+    /// The time between the first and last flow starts.
+    pub duration: Nanosecs,
+    /// The index of the link's cluster in [`SimNetwork::clusters`].
+    pub cluster: usize,
+    /// The representative of the link's cluster.
+    pub representative: EdgeIndex,
+    /// The tiers of the link's source and destination, if both are reachable from a host.
+    pub tiers: Option<(usize, usize)>,
+}
+
+/// Roll-up of the links between a pair of tiers, in either direction.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TierStats {
+    /// The lower and upper tier.
+    pub tiers: (usize, usize),
+    /// The number of links.
+    pub nr_links: usize,
+    /// The number of links with flows.
+    pub nr_busy: usize,
+    /// The mean load of the links with flows.
+    pub mean_load: f64,
+    /// The largest load of any link.
+    pub max_load: f64,
+    /// A histogram of link loads with [`NR_LOAD_BINS`] bins. Links without flows count as idle.
+    pub histogram: Vec<usize>,
+}
+
+impl<R> SimNetwork<R>
+where
+    R: RoutingAlgo + Sync,
+{
+    /// Returns statistics of every link in the network and roll-ups per tier pair.
+    pub fn link_stats(&self) -> LinkStatsReport {
+        let nodes = self.nodes().cloned().collect::<Vec<_>>();
+        let links = self.links().copied().collect::<Vec<_>>();
+        let node_tiers = oversubscription::tiers(&nodes, &links);
+        let cluster_of = self
+            .clusters
+            .iter()
+            .enumerate()
+            .flat_map(|(i, c)| c.members().map(move |&m| (m, (i, c.representative()))))
+            .collect::<FxHashMap<_, _>>();
+        let links = self
+            .topology
+            .graph
+            .edge_indices()
+            .map(|eidx| {
+                let chan = &self.topology.graph[eidx];
+                let (cluster, representative) = cluster_of[&eidx];
+                LinkStats {
+                    edge: eidx,
+                    src: chan.src,
+                    dst: chan.dst,
+                    bandwidth: chan.bandwidth,
+                    nr_flows: chan.flows.len(),
+                    nr_bytes: chan.nr_bytes,
+                    load: chan.load().value(),
+                    ack_rate: self.ack_rate_of(eidx).unwrap_or_default(),
+                    duration: chan.duration(),
+                    cluster,
+                    representative,
+                    tiers: node_tiers
+                        .get(&chan.src)
+                        .zip(node_tiers.get(&chan.dst))
+                        .map(|(&a, &b)| (a, b)),
+                }
+            })
+            .collect::<Vec<_>>();
+        let tiers = roll_up(&links);
+        LinkStatsReport { links, tiers }
+    }
+}
+
+fn roll_up(links: &[LinkStats]) -> Vec<TierStats> {
+    let mut by_tiers = FxHashMap::<(usize, usize), Vec<&LinkStats>>::default();
+    for link in links {
+        if let Some((a, b)) = link.tiers {
+            by_tiers.entry((a.min(b), a.max(b))).or_default().push(link);
+        }
+    }
+    let mut tiers = by_tiers
+        .into_iter()
+        .map(|(tiers, links)| {
+            let mut histogram = vec![0; NR_LOAD_BINS];
+            for link in &links {
+                let bin = if link.load.is_nan() {
+                    0
+                } else {
+                    ((link.load * 10.0).floor().max(0.0) as usize).min(NR_LOAD_BINS - 1)
+                };
+                histogram[bin] += 1;
+            }
+            let busy = links
+                .iter()
+                .filter(|l| l.nr_flows > 0)
+                .map(|l| l.load)
+                .collect::<Vec<_>>();
+            TierStats {
+                tiers,
+                nr_links: links.len(),
+                nr_busy: busy.len(),
+                mean_load: if busy.is_empty() {
+                    0.0
+                } else {
+                    busy.iter().sum::<f64>() / busy.len() as f64
+                },
+                max_load: links.iter().map(|l| l.load).fold(0.0, f64::max),
+                histogram,
+            }
+        })
+        .collect::<Vec<_>>();
+    tiers.sort_by_key(|t| t.tiers);
+    tiers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::{Flow, FlowId, Network},
+        testing,
+    };
+
+    #[test]
+    fn link_stats_cover_every_link() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        // Host 0 sends 1 KB every 10 us to host 2, loading its path at 8%
+        let flows = (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(2),
+                size: Bytes::new(10_000),
+                start: Nanosecs::new(i as u64 * 10_000),
+            })
+            .collect();
+        let network = Network::new(&nodes, &links)?.into_simulations(flows);
+        let report = network.link_stats();
+        assert_eq!(report.links.len(), network.edge_indices().count());
+        let up = network.find_edge(NodeId::new(0), NodeId::new(4)).unwrap();
+        let stats = report.links[up.index()];
+        assert_eq!((stats.nr_flows, stats.nr_bytes), (10, Bytes::new(100_000)));
+        assert_eq!(stats.tiers, Some((0, 1)));
+        assert_eq!(stats.load, network.load_of(up).unwrap());
+        assert_eq!(stats.representative, up);
+        let down = network.find_edge(NodeId::new(4), NodeId::new(0)).unwrap();
+        assert_eq!(
+            report.links[down.index()].ack_rate,
+            network.ack_rate_of(down).unwrap()
+        );
+
+        assert_eq!(report.tiers.len(), 2);
+        let hosts = &report.tiers[0];
+        assert_eq!((hosts.tiers, hosts.nr_links, hosts.nr_busy), ((0, 1), 8, 2));
+        assert_eq!(hosts.histogram.iter().sum::<usize>(), 8);
+        assert_eq!(hosts.histogram[0], 6);
+        let json = serde_json::to_string(&report)?;
+        assert_eq!(serde_json::from_str::<LinkStatsReport>(&json)?, report);
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+//! Compact, human-readable summaries of the delay distributions along a path or on a single
+//! channel. Path summaries are meant for sanity checks in a terminal, without any external
+//! plotting tools; channel summaries are meant for joining results with external data keyed by
+//! link endpoints.
+
+use std::{fmt, ops::Range};
+
+use crate::{
+    edist::EDistBuckets,
+    network::types::NodeId,
+    units::{BitsPerSec, Bytes, Nanosecs},
+};
+
+/// A summary of the delay distributions along a path, obtained with
+/// [`DelayNetwork::summarize_path`](crate::network::DelayNetwork::summarize_path).
+#[derive(Debug, Clone)]
+pub struct PathSummary {
+    /// The path source.
+    pub src: NodeId,
+    /// The path destination.
+    pub dst: NodeId,
+    /// The queried flow size.
+    pub size: Bytes,
+    /// The propagation delay along the path.
+    pub propagation: Nanosecs,
+    /// The transmission delay of the queried size along the path. Together with the propagation
+    /// delay, it makes up the ideal FCT; the end-to-end quantiles are queueing on top of it.
+    pub transmission: Nanosecs,
+    /// Per-hop summaries, in path order.
+    pub hops: Vec<HopSummary>,
+    /// End-to-end delay quantiles obtained by composing samples from every hop, or `None` if no
+    /// samples could be drawn.
+    pub end_to_end: Option<Quantiles>,
+    /// The number of end-to-end samples drawn.
+    pub nr_samples: usize,
+}
+
+/// A summary of the delay distribution of a single hop.
+#[derive(Debug, Clone)]
+pub struct HopSummary {
+    /// The hop source.
+    pub src: NodeId,
+    /// The hop destination.
+    pub dst: NodeId,
+    /// The size range of the bucket used for the queried size, if any.
+    pub bucket: Option<Range<Bytes>>,
+    /// The number of samples in the bucket.
+    pub nr_samples: usize,
+    /// Delay quantiles of the bucket, or `None` if the bucket is empty.
+    pub quantiles: Option<Quantiles>,
+}
+
+/// Delay quantiles for a flow of the queried size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quantiles {
+    /// The median.
+    pub p50: Nanosecs,
+    /// The 95th percentile.
+    pub p95: Nanosecs,
+    /// The 99th percentile.
+    pub p99: Nanosecs,
+}
+
+impl Quantiles {
+    pub(crate) fn from_fn(mut f: impl FnMut(f64) -> Option<Nanosecs>) -> Option<Self> {
+        Some(Self {
+            p50: f(0.50)?,
+            p95: f(0.95)?,
+            p99: f(0.99)?,
+        })
+    }
+}
+
+impl fmt::Display for Quantiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "p50={} p95={} p99={}", self.p50, self.p95, self.p99)
+    }
+}
+
+impl fmt::Display for HopSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}: ", self.src, self.dst)?;
+        match &self.bucket {
+            Some(bkt) if bkt.end == Bytes::MAX => write!(f, "bucket [{}, inf)", bkt.start)?,
+            Some(bkt) => write!(f, "bucket [{}, {})", bkt.start, bkt.end)?,
+            None => write!(f, "no bucket")?,
+        }
+        write!(f, ", n={}", self.nr_samples)?;
+        match &self.quantiles {
+            Some(q) => write!(f, ", {q}"),
+            None => write!(f, ", no data"),
+        }
+    }
+}
+
+impl fmt::Display for PathSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "path {} -> {}, size {}", self.src, self.dst, self.size)?;
+        writeln!(
+            f,
+            "  ideal: propagation={} transmission={}",
+            self.propagation, self.transmission
+        )?;
+        for (i, hop) in self.hops.iter().enumerate() {
+            writeln!(f, "  hop {i}: {hop}")?;
+        }
+        match &self.end_to_end {
+            Some(q) => write!(f, "  end-to-end: {q} ({} samples)", self.nr_samples),
+            None => write!(f, "  end-to-end: no data"),
+        }
+    }
+}
+
+/// A summary of the delay distributions of a single channel, obtained with
+/// [`DelayNetwork::channels`](crate::network::DelayNetwork::channels).
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ChannelSummary {
+    /// The channel source.
+    pub src: NodeId,
+    /// The channel destination.
+    pub dst: NodeId,
+    /// The channel bandwidth.
+    pub bandwidth: BitsPerSec,
+    /// The channel propagation delay.
+    pub delay: Nanosecs,
+    /// The channel's packet loss rate.
+    pub loss_rate: f64,
+    /// Per-bucket summaries, in ascending order of size.
+    pub buckets: Vec<BucketSummary>,
+}
+
+impl ChannelSummary {
+    /// Returns the total number of samples over all buckets.
+    pub fn nr_samples(&self) -> usize {
+        self.buckets.iter().map(|b| b.nr_samples).sum()
+    }
+}
+
+/// A summary of the delay distribution of a single size bucket. Delays are packet-normalized,
+/// i.e., they are per packet of `SZ_PKTMAX` bytes.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BucketSummary {
+    /// The bucket's size range.
+    pub range: Range<Bytes>,
+    /// The number of samples in the bucket.
+    pub nr_samples: usize,
+    /// The mean packet-normalized delay, or `None` if the bucket is empty.
+    pub mean: Option<f64>,
+    /// The median packet-normalized delay, or `None` if the bucket is empty.
+    pub p50: Option<f64>,
+    /// The 99th percentile packet-normalized delay, or `None` if the bucket is empty.
+    pub p99: Option<f64>,
+}
+
+impl BucketSummary {
+    pub(crate) fn from_dists(dists: &EDistBuckets) -> Vec<Self> {
+        dists
+            .iter()
+            .map(|(range, dist)| Self {
+                range: range.clone(),
+                nr_samples: dist.len(),
+                mean: (!dist.is_empty()).then(|| dist.mean()),
+                p50: dist.quantile(0.50),
+                p99: dist.quantile(0.99),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_summary_display() {
+        let q = Quantiles {
+            p50: Nanosecs::new(10),
+            p95: Nanosecs::new(20),
+            p99: Nanosecs::new(30),
+        };
+        let summary = PathSummary {
+            src: NodeId::new(0),
+            dst: NodeId::new(1),
+            size: Bytes::new(1000),
+            propagation: Nanosecs::new(2000),
+            transmission: Nanosecs::new(1700),
+            hops: vec![
+                HopSummary {
+                    src: NodeId::new(0),
+                    dst: NodeId::new(2),
+                    bucket: Some(Bytes::ZERO..Bytes::new(2000)),
+                    nr_samples: 100,
+                    quantiles: Some(q),
+                },
+                HopSummary {
+                    src: NodeId::new(2),
+                    dst: NodeId::new(1),
+                    bucket: Some(Bytes::ZERO..Bytes::MAX),
+                    nr_samples: 0,
+                    quantiles: None,
+                },
+            ],
+            end_to_end: Some(q),
+            nr_samples: 1000,
+        };
+        insta::assert_snapshot!(summary.to_string(), @r###"
+        path 0 -> 1, size 1000B
+          ideal: propagation=2000ns transmission=1700ns
+          hop 0: 0 -> 2: bucket [0B, 2000B), n=100, p50=10ns p95=20ns p99=30ns
+          hop 1: 2 -> 1: bucket [0B, inf), n=0, no data
+          end-to-end: p50=10ns p95=20ns p99=30ns (1000 samples)
+        "###);
+    }
+}
@@ -1,10 +1,20 @@
 //! This module defines network topologies with different kinds of links.
 
+use std::hash::{Hash, Hasher};
+
 use itertools::Itertools;
-use petgraph::graph::{DiGraph, EdgeIndex, NodeIndex};
+use petgraph::{
+    graph::{DiGraph, EdgeIndex, NodeIndex},
+    visit::EdgeRef,
+    Direction,
+};
 use rustc_hash::{FxHashMap, FxHashSet};
 
-use crate::network::types::{BasicChannel, FlowChannel, Link, Node, NodeId, NodeKind};
+use crate::{
+    network::types::{BasicChannel, Channel, FlowChannel, Link, Node, NodeId, NodeKind},
+    units::{BitsPerSec, Nanosecs},
+    utils::StableHasher,
+};
 
 use super::types::EDistChannel;
 
@@ -14,6 +24,8 @@ pub struct Topology<C: Clone> {
     pub(crate) graph: DiGraph<Node, C>,
     pub(crate) id2idx: FxHashMap<NodeId, NodeIndex>,
     pub(crate) links: Vec<Link>,
+    // The virtual NIC of every host with one, which follows the declared nodes in the graph
+    pub(crate) nics: FxHashMap<NodeId, NodeId>,
 }
 
 impl<C: Clone> Topology<C> {
@@ -31,6 +43,83 @@ impl<C: Clone> Topology<C> {
             pub(crate) fn find_edge(&self, a: NodeIndex, b: NodeIndex) -> Option<EdgeIndex>;
         }
     }
+
+    /// Returns the declared nodes, without virtual NICs.
+    pub(crate) fn nodes(&self) -> impl Iterator<Item = &Node> {
+        let nr_nodes = self.graph.node_count() - self.nics.len();
+        self.graph.node_weights().take(nr_nodes)
+    }
+
+    /// Returns the rack of `host`, identified by the lowest-ID switch it links to, or `None` if
+    /// `host` is not a host linked to a switch.
+    pub(crate) fn rack_of(&self, host: NodeId) -> Option<NodeId> {
+        let &idx = self.idx_of(&host)?;
+        if !matches!(self.graph[idx].kind, NodeKind::Host) {
+            return None;
+        }
+        let idx = match self.nics.get(&host) {
+            Some(nic) => *self.idx_of(nic)?,
+            None => idx,
+        };
+        self.graph
+            .neighbors(idx)
+            .map(|n| &self.graph[n])
+            .filter(|n| matches!(n.kind, NodeKind::Switch))
+            .map(|n| n.id)
+            .min()
+    }
+
+    /// Returns the channel from `a` to `b`, if any. Channels of a link from a host with a virtual
+    /// NIC leave from the NIC.
+    pub(crate) fn channel_between(&self, a: NodeId, b: NodeId) -> Option<EdgeIndex> {
+        let a = match self.nics.get(&a) {
+            Some(&nic) if nic != b => nic,
+            _ => a,
+        };
+        self.find_edge(*self.idx_of(&a)?, *self.idx_of(&b)?)
+    }
+
+    /// Returns the channels whose ACKs `edge` carries: the channel in the opposite direction, if
+    /// any, or every channel into the host if `edge` is the link to its virtual NIC.
+    pub(crate) fn ack_channels(&self, edge: EdgeIndex) -> Vec<EdgeIndex> {
+        let Some((a, b)) = self.graph.edge_endpoints(edge) else {
+            return Vec::new();
+        };
+        if self.is_nic(b) {
+            return self
+                .graph
+                .edges_directed(a, Direction::Incoming)
+                .map(|e| e.id())
+                .collect();
+        }
+        // A virtual NIC stands in for its host, its only predecessor
+        let a = match self.is_nic(a) {
+            true => self
+                .graph
+                .neighbors_directed(a, Direction::Incoming)
+                .next()
+                .unwrap_or(a),
+            false => a,
+        };
+        self.find_edge(b, a).into_iter().collect()
+    }
+
+    fn is_nic(&self, idx: NodeIndex) -> bool {
+        idx.index() >= self.graph.node_count() - self.nics.len()
+    }
+}
+
+impl<C: Clone + Channel> Topology<C> {
+    // Depends on the nodes and links, but not on what channels carry
+    pub(crate) fn fingerprint(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+        self.graph.node_count().hash(&mut hasher);
+        for chan in self.graph.edge_weights() {
+            (chan.src(), chan.dst(), chan.bandwidth(), chan.delay()).hash(&mut hasher);
+            chan.loss_rate().to_bits().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
 }
 
 impl Topology<BasicChannel> {
@@ -43,13 +132,25 @@ impl Topology<BasicChannel> {
     /// - Node IDs must be contiguous.
     /// - Every link must have distinct endpoints in `nodes`.
     /// - Every node must be referenced by some link.
-    /// - For any two nodes, there must be at most one link between them.
-    /// - Every host node should only have one link.
+    /// - Every link must have a loss rate in [0, 1).
+    /// - Only hosts may have a NIC rate, and it must be positive.
+    /// - For any two nodes, there must be at most one channel in each direction between them. In
+    ///   particular, two unidirectional links in opposite directions are allowed.
+    ///
+    /// Hosts may have more than one link (e.g., when dual-homed to two ToRs), but they are never
+    /// used as transit nodes by routing. The uplinks of such a host with a [NIC
+    /// rate](Node::nic_rate) share a virtual NIC, which follows the declared nodes.
     pub fn new(nodes: &[Node], links: &[Link]) -> Result<Self, TopologyError> {
         let mut g = DiGraph::new();
         let mut id2idx = FxHashMap::default();
         for (i, n) in nodes.iter().cloned().sorted_by_key(|n| n.id).enumerate() {
             let id = n.id;
+            // CORRECTNESS: Only hosts may have a NIC rate, and it must be positive.
+            if let Some(rate) = n.nic_rate {
+                if n.kind != NodeKind::Host || rate == BitsPerSec::ZERO {
+                    return Err(TopologyError::InvalidNicRate(id));
+                }
+            }
             let idx = g.add_node(n);
             if id2idx.insert(id, idx).is_some() {
                 // CORRECTNESS: Every node must have a unique ID.
@@ -60,15 +161,10 @@ impl Topology<BasicChannel> {
                 return Err(TopologyError::HoleBeforeId(id));
             }
         }
-        let idx_of = |id| *id2idx.get(&id).unwrap();
+        let mut channels = Vec::new();
         let mut referenced_nodes = FxHashSet::default();
-        for Link {
-            a,
-            b,
-            bandwidth,
-            delay,
-        } in links.iter().cloned()
-        {
+        for link in links {
+            let Link { a, b, .. } = *link;
             // CORRECTNESS: Every link must have distinct endpoints in `nodes`.
             if a == b {
                 return Err(TopologyError::NodeAdjacentSelf(a));
@@ -79,19 +175,15 @@ impl Topology<BasicChannel> {
             if !id2idx.contains_key(&b) {
                 return Err(TopologyError::UndeclaredNode(b));
             }
+            // CORRECTNESS: Every link must have a loss rate in [0, 1).
+            if !(0.0..1.0).contains(&link.loss_rate) {
+                return Err(TopologyError::InvalidLossRate { a, b });
+            }
             referenced_nodes.insert(a);
             referenced_nodes.insert(b);
-            // Channels are unidirectional
-            g.add_edge(
-                idx_of(a),
-                idx_of(b),
-                BasicChannel::new(a, b, bandwidth, delay),
-            );
-            g.add_edge(
-                idx_of(b),
-                idx_of(a),
-                BasicChannel::new(b, a, bandwidth, delay),
-            );
+            // Channels are unidirectional, and every direction of a link is materialized
+            // explicitly
+            channels.extend(link.channels());
         }
         // CORRECTNESS: Every node must be referenced by some link.
         for &id in id2idx.keys() {
@@ -99,8 +191,36 @@ impl Topology<BasicChannel> {
                 return Err(TopologyError::IsolatedNode(id));
             }
         }
+        // A host can't inject faster than its NIC. The only uplink of a host is capped at the NIC
+        // rate, and several uplinks leave from a virtual NIC instead, linked from the host at the
+        // NIC rate.
+        let nr_uplinks = channels.iter().map(|chan| chan.src).counts();
+        let mut nics = FxHashMap::default();
+        for n in g.node_weights().filter(|n| n.nic_rate.is_some()) {
+            if nr_uplinks.get(&n.id).is_some_and(|&nr| nr > 1) {
+                nics.insert(n.id, NodeId::new(id2idx.len() + nics.len()));
+            }
+        }
+        for &nic in nics.values().sorted() {
+            id2idx.insert(nic, g.add_node(Node::new_switch(nic)));
+        }
+        let idx_of = |id| *id2idx.get(&id).unwrap();
+        for mut chan in channels {
+            match (nics.get(&chan.src), g[idx_of(chan.src)].nic_rate) {
+                (Some(&nic), _) => chan.src = nic,
+                (None, Some(rate)) => chan.bandwidth = chan.bandwidth.min(rate),
+                (None, None) => (),
+            }
+            g.add_edge(idx_of(chan.src), idx_of(chan.dst), chan);
+        }
+        for (&host, &nic) in nics.iter().sorted() {
+            let rate = g[idx_of(host)].nic_rate.unwrap();
+            let chan = BasicChannel::new(host, nic, rate, Nanosecs::ZERO);
+            g.add_edge(idx_of(host), idx_of(nic), chan);
+        }
         for eidx in g.edge_indices() {
-            // CORRECTNESS: For any two nodes, there must be at most one link between them.
+            // CORRECTNESS: For any two nodes, there must be at most one channel in each direction
+            // between them.
             let (a, b) = g.edge_endpoints(eidx).unwrap();
             if g.edges_connecting(a, b).count() > 1 {
                 return Err(TopologyError::DuplicateLink {
@@ -108,19 +228,12 @@ impl Topology<BasicChannel> {
                     n2: g[b].id,
                 });
             }
-            // CORRECTNESS: Every host node should only have one link.
-            let Node { id, kind, .. } = g[a];
-            if matches!(kind, NodeKind::Host) {
-                let nr_outgoing = g.edges(a).count();
-                if nr_outgoing > 1 {
-                    return Err(TopologyError::TooManyHostLinks { id, n: nr_outgoing });
-                }
-            }
         }
         Ok(Self {
             graph: g,
             id2idx,
             links: Vec::from(links),
+            nics,
         })
     }
 }
@@ -142,6 +255,7 @@ impl Topology<FlowChannel> {
             graph: g,
             id2idx: topology.id2idx.clone(),
             links: topology.links.clone(),
+            nics: topology.nics.clone(),
         }
     }
 }
@@ -163,6 +277,7 @@ impl Topology<EDistChannel> {
             graph: g,
             id2idx: topology.id2idx.clone(),
             links: topology.links.clone(),
+            nics: topology.nics.clone(),
         }
     }
 }
@@ -195,18 +310,26 @@ pub enum TopologyError {
         n2: NodeId,
     },
 
-    /// More than one link connected to a host.
-    #[error("host {id} has too many links (expected 1, got {n})")]
-    TooManyHostLinks {
-        /// The host's node ID.
-        id: NodeId,
-        /// The actual number of links (should be 1).
-        n: usize,
-    },
-
     /// A node is not connected to anything else.
     #[error("node {0} is not connected to any other node")]
     IsolatedNode(NodeId),
+
+    /// A node has a NIC rate but is not a host, or its NIC rate is zero.
+    #[error("node {0} has an invalid NIC rate")]
+    InvalidNicRate(NodeId),
+
+    /// A packet profile has an empty maximum payload.
+    #[error("packets must have a positive maximum payload")]
+    EmptyPacketPayload,
+
+    /// A link's loss rate is not in [0, 1).
+    #[error("link between {a} and {b} has an invalid loss rate")]
+    InvalidLossRate {
+        /// The first endpoint.
+        a: NodeId,
+        /// The second endpoint.
+        b: NodeId,
+    },
 }
 
 #[cfg(test)]
@@ -214,6 +337,7 @@ mod tests {
     use anyhow::Context;
 
     use super::*;
+    use crate::network::types::ReverseDirection;
     use crate::testing;
     use crate::units::{BitsPerSec, Nanosecs};
 
@@ -242,6 +366,59 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn invalid_loss_rate_fails() {
+        let (nodes, mut links) = testing::three_node_config();
+        links[0] = links[0].with_loss_rate(1.0);
+        assert!(matches!(
+            Topology::new(&nodes, &links),
+            Err(TopologyError::InvalidLossRate { .. })
+        ));
+        links[0] = links[0].with_loss_rate(0.01);
+        assert!(Topology::new(&nodes, &links).is_ok());
+    }
+
+    #[test]
+    fn nic_rates_cap_host_up_channels() -> anyhow::Result<()> {
+        let (mut nodes, links) = testing::three_node_config();
+        let nic_rate = BitsPerSec::new(1_000);
+        nodes[0] = nodes[0].clone().with_nic_rate(nic_rate);
+        let topo = Topology::new(&nodes, &links)?;
+        for chan in topo.graph.edge_weights() {
+            if chan.src == nodes[0].id {
+                assert_eq!(chan.bandwidth, nic_rate);
+            } else {
+                assert!(chan.bandwidth > nic_rate);
+            }
+        }
+        nodes[2] = nodes[2].clone().with_nic_rate(nic_rate);
+        assert!(matches!(
+            Topology::new(&nodes, &links),
+            Err(TopologyError::InvalidNicRate(..))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn multi_homed_hosts_share_a_virtual_nic() -> anyhow::Result<()> {
+        let (mut nodes, links) = testing::dual_homed_config();
+        let nic_rate = BitsPerSec::new(1_000);
+        nodes[0] = nodes[0].clone().with_nic_rate(nic_rate);
+        let topo = Topology::new(&nodes, &links)?;
+        let (host, nic) = (NodeId::new(0), NodeId::new(nodes.len()));
+        assert_eq!(topo.nodes().count(), nodes.len());
+        assert_eq!(topo.nics.get(&host), Some(&nic));
+        let virtual_link = topo.channel_between(host, nic).unwrap();
+        assert_eq!(topo.graph[virtual_link].bandwidth, nic_rate);
+        for tor in [NodeId::new(4), NodeId::new(5)] {
+            let uplink = topo.channel_between(host, tor).unwrap();
+            assert_eq!(topo.graph[uplink].src, nic);
+            assert!(topo.graph[uplink].bandwidth > nic_rate);
+        }
+        assert_eq!(topo.rack_of(host), Some(NodeId::new(4)));
+        Ok(())
+    }
+
     #[test]
     fn duplicate_node_fails() {
         let n1 = Node::new_host(NodeId::new(0));
@@ -306,19 +483,63 @@ mod tests {
     }
 
     #[test]
-    fn too_many_host_links_fails() {
+    fn multi_homed_host_succeeds() {
         let n1 = Node::new_host(NodeId::new(0));
         let n2 = Node::new_host(NodeId::new(1));
         let n3 = Node::new_switch(NodeId::new(2));
         let n4 = Node::new_switch(NodeId::new(3));
         let l1 = Link::new(n1.id, n3.id, BitsPerSec::default(), Nanosecs::default());
         let l2 = Link::new(n2.id, n3.id, BitsPerSec::default(), Nanosecs::default());
-        let l3 = Link::new(n1.id, n4.id, BitsPerSec::default(), Nanosecs::default()); // error
-        let res = Topology::new(&[n1, n2, n3, n4], &[l1, l2, l3]);
-        assert!(matches!(
-            res,
-            Err(TopologyError::TooManyHostLinks { n: 2, .. })
-        ));
+        let l3 = Link::new(n1.id, n4.id, BitsPerSec::default(), Nanosecs::default());
+        let res = Topology::<BasicChannel>::new(&[n1, n2, n3, n4], &[l1, l2, l3]);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn asymmetric_links_materialize_both_directions() {
+        let n1 = Node::new_host(NodeId::new(0));
+        let n2 = Node::new_switch(NodeId::new(1));
+        let l1 = Link::new_asymmetric(
+            n1.id,
+            n2.id,
+            (BitsPerSec::new(100), Nanosecs::new(1)),
+            (BitsPerSec::new(10), Nanosecs::new(2)),
+        );
+        let topo = Topology::<BasicChannel>::new(&[n1.clone(), n2.clone()], &[l1]).unwrap();
+        let chans = topo.graph.edge_weights().cloned().collect::<Vec<_>>();
+        assert_eq!(
+            chans,
+            vec![
+                BasicChannel::new(n1.id, n2.id, BitsPerSec::new(100), Nanosecs::new(1)),
+                BasicChannel::new(n2.id, n1.id, BitsPerSec::new(10), Nanosecs::new(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn opposite_unidirectional_links_succeed() {
+        let n1 = Node::new_switch(NodeId::new(0));
+        let n2 = Node::new_switch(NodeId::new(1));
+        let l1 = Link::new_unidirectional(n1.id, n2.id, BitsPerSec::new(100), Nanosecs::new(1));
+        let l2 = Link::new_unidirectional(n2.id, n1.id, BitsPerSec::new(10), Nanosecs::new(1));
+        let nodes = [n1, n2];
+        let topo = Topology::<BasicChannel>::new(&nodes, &[l1]).unwrap();
+        assert_eq!(topo.nr_edges(), 1);
+        let topo = Topology::<BasicChannel>::new(&nodes, &[l1, l2]).unwrap();
+        assert_eq!(topo.nr_edges(), 2);
+        let res = Topology::<BasicChannel>::new(&nodes, &[l1, l2, l1]);
+        assert!(matches!(res, Err(TopologyError::DuplicateLink { .. })));
+    }
+
+    #[test]
+    fn links_default_to_symmetric() -> anyhow::Result<()> {
+        let link: Link = serde_json::from_str(r#"{"a": 0, "b": 1, "bandwidth": 10, "delay": 1}"#)?;
+        assert_eq!(link.reverse, ReverseDirection::Symmetric);
+        assert_eq!(
+            serde_json::to_string(&link)?,
+            r#"{"a":0,"b":1,"bandwidth":10,"delay":1}"#
+        );
+        Ok(())
     }
 
     #[test]
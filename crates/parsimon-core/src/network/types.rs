@@ -1,13 +1,15 @@
 //! This module defines core types used to construct a network, such as [nodes](Node),
 //! [links][Link], and [channels](Channel).
 
-use std::cmp::Ordering;
+use std::{cmp::Ordering, ops::Range};
 
 use petgraph::graph::EdgeIndex;
-use rustc_hash::FxHashSet;
+use rustc_hash::{FxHashMap, FxHashSet};
 
-use crate::constants::{SZ_ACK, SZ_PKTMAX};
+use crate::client::ClientId;
+use crate::constants::PacketProfile;
 use crate::edist::EDistBuckets;
+use crate::network::arena::{FlowArena, FlowIdx};
 use crate::units::{BitsPerSec, Bytes, Nanosecs};
 
 /// A node in the network topology.
@@ -17,6 +19,14 @@ pub struct Node {
     pub id: NodeId,
     /// Whether the node is a host or a switch.
     pub kind: NodeKind,
+    /// The rate at which a host's NIC can inject traffic, shared by all of its flows and uplinks.
+    /// If set, it caps the bandwidth of a host's only up-channel. The up-channels of a host with
+    /// several uplinks leave from a virtual NIC instead, a switch linked from the host at the NIC
+    /// rate, so source-side serialization is captured by a link-level simulation of its own. Hosts
+    /// without a NIC rate inject at line rate.
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nic_rate: Option<BitsPerSec>,
 }
 
 impl Node {
@@ -25,6 +35,7 @@ impl Node {
         Self {
             id,
             kind: NodeKind::Host,
+            nic_rate: None,
         }
     }
 
@@ -33,6 +44,15 @@ impl Node {
         Self {
             id,
             kind: NodeKind::Switch,
+            nic_rate: None,
+        }
+    }
+
+    /// Sets the node's [NIC rate](Self::nic_rate).
+    pub fn with_nic_rate(self, nic_rate: BitsPerSec) -> Self {
+        Self {
+            nic_rate: Some(nic_rate),
+            ..self
         }
     }
 }
@@ -48,17 +68,52 @@ pub enum NodeKind {
 
 identifier!(NodeId, usize);
 
-/// A link is a bidirectional channel connecting two [nodes](Node).
+/// A link is a bidirectional channel connecting two [nodes](Node). By default both directions have
+/// the same bandwidth and delay, but the direction from `b` to `a` can be given different
+/// parameters or omitted entirely (see [`ReverseDirection`]).
 #[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub struct Link {
     /// The first endpoint.
     pub a: NodeId,
     /// The second endpoint.
     pub b: NodeId,
-    /// The link bandwidth.
+    /// The link bandwidth from `a` to `b`.
     pub bandwidth: BitsPerSec,
-    /// The propagation delay.
+    /// The propagation delay from `a` to `b`.
     pub delay: Nanosecs,
+    /// The direction from `b` to `a`.
+    #[serde(default, skip_serializing_if = "ReverseDirection::is_symmetric")]
+    pub reverse: ReverseDirection,
+    /// The probability that a packet is lost on the link, in either direction.
+    #[serde(default, skip_serializing_if = "is_lossless")]
+    pub loss_rate: f64,
+}
+
+pub(crate) fn is_lossless(loss_rate: &f64) -> bool {
+    *loss_rate == 0.0
+}
+
+/// The parameters of the direction of a [`Link`] from `b` to `a`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ReverseDirection {
+    /// The same bandwidth and delay as the forward direction.
+    #[default]
+    Symmetric,
+    /// A different bandwidth and delay than the forward direction.
+    Asymmetric {
+        /// The bandwidth from `b` to `a`.
+        bandwidth: BitsPerSec,
+        /// The propagation delay from `b` to `a`.
+        delay: Nanosecs,
+    },
+    /// The link is unidirectional; there is no channel from `b` to `a`.
+    Absent,
+}
+
+impl ReverseDirection {
+    fn is_symmetric(&self) -> bool {
+        matches!(self, Self::Symmetric)
+    }
 }
 
 impl Link {
@@ -74,13 +129,70 @@ impl Link {
             b,
             bandwidth: bandwidth.into(),
             delay: delay.into(),
+            reverse: ReverseDirection::Symmetric,
+            loss_rate: 0.0,
+        }
+    }
+
+    /// Creates a new link whose direction from `b` to `a` has a different bandwidth and delay.
+    pub fn new_asymmetric(
+        a: NodeId,
+        b: NodeId,
+        (bandwidth, delay): (impl Into<BitsPerSec>, impl Into<Nanosecs>),
+        (rev_bandwidth, rev_delay): (impl Into<BitsPerSec>, impl Into<Nanosecs>),
+    ) -> Self {
+        Self {
+            reverse: ReverseDirection::Asymmetric {
+                bandwidth: rev_bandwidth.into(),
+                delay: rev_delay.into(),
+            },
+            ..Self::new(a, b, bandwidth, delay)
+        }
+    }
+
+    /// Creates a new link with a single direction, from `a` to `b`.
+    pub fn new_unidirectional(
+        a: NodeId,
+        b: NodeId,
+        bandwidth: impl Into<BitsPerSec>,
+        delay: impl Into<Nanosecs>,
+    ) -> Self {
+        Self {
+            reverse: ReverseDirection::Absent,
+            ..Self::new(a, b, bandwidth, delay)
         }
     }
 
+    /// Returns a copy of this link with the given packet loss rate.
+    pub fn with_loss_rate(self, loss_rate: f64) -> Self {
+        Self { loss_rate, ..self }
+    }
+
     /// Returns true if the given link connects nodes `x` and `y`.
     pub fn connects(&self, x: NodeId, y: NodeId) -> bool {
         self.a == x && self.b == y || self.a == y && self.b == x
     }
+
+    /// Returns the channels making up this link, i.e., one channel per direction.
+    pub fn channels(&self) -> impl Iterator<Item = BasicChannel> {
+        let forward = BasicChannel::new(self.a, self.b, self.bandwidth, self.delay);
+        let reverse = match self.reverse {
+            ReverseDirection::Symmetric => Some(BasicChannel::new(
+                self.b,
+                self.a,
+                self.bandwidth,
+                self.delay,
+            )),
+            ReverseDirection::Asymmetric { bandwidth, delay } => {
+                Some(BasicChannel::new(self.b, self.a, bandwidth, delay))
+            }
+            ReverseDirection::Absent => None,
+        };
+        let loss_rate = self.loss_rate;
+        std::iter::once(forward)
+            .chain(reverse)
+            .map(move |chan| BasicChannel { loss_rate, ..chan })
+    }
 }
 
 /// This trait defines routines that must be implemented by any channel in a topology.
@@ -96,6 +208,9 @@ pub trait Channel {
 
     /// The propagation delay.
     fn delay(&self) -> Nanosecs;
+
+    /// The packet loss rate.
+    fn loss_rate(&self) -> f64;
 }
 
 // All channels just copy these fields
@@ -117,6 +232,10 @@ macro_rules! channel_impl {
             fn delay(&self) -> Nanosecs {
                 self.delay
             }
+
+            fn loss_rate(&self) -> f64 {
+                self.loss_rate
+            }
         }
     };
 }
@@ -137,35 +256,49 @@ impl<T: Channel> Channel for &T {
     fn delay(&self) -> Nanosecs {
         (*self).delay()
     }
+
+    fn loss_rate(&self) -> f64 {
+        (*self).loss_rate()
+    }
 }
 
 /// A `BasicChannel` is a one-way channel between two nodes with some bandwidth and delay.
-#[derive(Debug, Clone, PartialEq, Eq, derive_new::new, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq, derive_new::new, serde::Serialize)]
 pub struct BasicChannel {
     pub(crate) src: NodeId,
     pub(crate) dst: NodeId,
     pub(crate) bandwidth: BitsPerSec,
     pub(crate) delay: Nanosecs,
+    #[new(default)]
+    #[serde(skip_serializing_if = "is_lossless")]
+    pub(crate) loss_rate: f64,
 }
 
 channel_impl!(BasicChannel);
 
 /// A `FlowChannel` is a channel containing flows to simulate.
-#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
 pub struct FlowChannel {
     pub(crate) src: NodeId,
     pub(crate) dst: NodeId,
     pub(crate) bandwidth: BitsPerSec,
     pub(crate) delay: Nanosecs,
+    #[serde(skip_serializing_if = "is_lossless")]
+    pub(crate) loss_rate: f64,
 
     // `FlowChannel` specific data
     pub(crate) nr_bytes: Bytes,
-    pub(crate) nr_ack_bytes: Bytes,
+    // Data packets, each of which is acknowledged in the reverse direction
+    pub(crate) nr_pkts: u64,
     pub(crate) flow_srcs: FxHashSet<NodeId>,
     pub(crate) flow_dsts: FxHashSet<NodeId>,
     pub(crate) flow_start: Nanosecs,
     pub(crate) flow_end: Nanosecs,
-    pub(crate) flows: Vec<FlowId>,
+    // Positions of this channel's flows in the network's flow arena, sorted by start time
+    pub(crate) flows: Vec<FlowIdx>,
+    // The arena `flows` index into, shared with the network
+    #[serde(skip)]
+    pub(crate) arena: FlowArena,
 }
 
 channel_impl!(FlowChannel);
@@ -177,33 +310,37 @@ impl FlowChannel {
             dst: chan.dst,
             bandwidth: chan.bandwidth,
             delay: chan.delay,
+            loss_rate: chan.loss_rate,
             nr_bytes: Bytes::ZERO,
-            nr_ack_bytes: Bytes::ZERO,
+            nr_pkts: 0,
             flow_srcs: FxHashSet::default(),
             flow_dsts: FxHashSet::default(),
             flow_start: Nanosecs::MAX,
             flow_end: Nanosecs::ZERO,
             flows: Vec::new(),
+            arena: FlowArena::default(),
         }
     }
 
-    /// Get an iterator over the traced channel's flow IDs
+    /// Get an iterator over the traced channel's flow IDs, in order of start time.
     pub fn flow_ids(&self) -> impl Iterator<Item = FlowId> + '_ {
-        self.flows.iter().copied()
+        self.flows.iter().map(|&idx| self.arena.get(idx).id)
     }
 
-    pub(crate) fn push_flow(&mut self, flow: &Flow) {
+    pub(crate) fn push_flow(&mut self, arena: &FlowArena, idx: FlowIdx, packets: &PacketProfile) {
+        if !self.arena.ptr_eq(arena) {
+            self.arena = arena.clone();
+        }
+        let flow = arena.get(idx);
         self.nr_bytes += flow.size;
-        let nr_pkts = (flow.size.into_f64() / SZ_PKTMAX.into_f64()).ceil();
-        let nr_ack_bytes = SZ_ACK.scale_by(nr_pkts);
-        self.nr_ack_bytes += nr_ack_bytes;
+        self.nr_pkts += packets.nr_pkts(flow.size);
         self.flow_srcs.insert(flow.src);
         self.flow_dsts.insert(flow.dst);
         self.flow_start = std::cmp::min(self.flow_start, flow.start);
         self.flow_end = std::cmp::max(self.flow_end, flow.start);
-        self.flows.push(flow.id);
+        self.flows.push(idx);
     }
-    
+
     pub(crate) fn duration(&self) -> Nanosecs {
         if self.flows.is_empty() {
             Nanosecs::ZERO
@@ -212,6 +349,11 @@ impl FlowChannel {
         }
     }
 
+    /// Returns the load of this channel over the span of its flows.
+    pub fn load(&self) -> LinkLoad {
+        LinkLoad::from_window(self.nr_bytes, self.duration(), self.bandwidth)
+    }
+
     delegate::delegate! {
         to self.flows {
             /// Returns the number of flows traversing this channel.
@@ -221,13 +363,62 @@ impl FlowChannel {
     }
 }
 
+/// The load of a channel: the rate of bytes offered to it over the window spanned by its flows,
+/// as a fraction of its bandwidth. Degenerate windows have their own variants so callers can tell
+/// them apart from genuinely idle or genuinely loaded channels.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LinkLoad {
+    /// No bytes traverse the channel, either because it has no flows or because all of its flows
+    /// are empty.
+    Idle,
+    /// Bytes traverse the channel, but all flows start at the same instant (e.g., a single flow),
+    /// so there is no window over which to compute a rate.
+    Instantaneous,
+    /// Bytes traverse a channel with zero bandwidth.
+    NoCapacity,
+    /// A finite, non-negative load.
+    Rate(f64),
+}
+
+impl LinkLoad {
+    /// Computes the load of `nr_bytes` offered over `duration` to a channel with the given
+    /// `bandwidth`.
+    pub fn from_window(nr_bytes: Bytes, duration: Nanosecs, bandwidth: BitsPerSec) -> Self {
+        if nr_bytes == Bytes::ZERO {
+            Self::Idle
+        } else if duration == Nanosecs::ZERO {
+            Self::Instantaneous
+        } else if bandwidth == BitsPerSec::ZERO {
+            Self::NoCapacity
+        } else {
+            let bps = nr_bytes.into_f64() * 8.0 * 1e9 / duration.into_f64();
+            Self::Rate(bps / bandwidth.into_f64())
+        }
+    }
+
+    /// Returns the load as a number. Idle and instantaneous loads are zero, and loads on channels
+    /// without capacity are infinite. The result is never NaN.
+    pub fn value(&self) -> f64 {
+        match *self {
+            Self::Idle | Self::Instantaneous => 0.0,
+            Self::NoCapacity => f64::INFINITY,
+            Self::Rate(load) => load,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct EDistChannel {
     pub(crate) src: NodeId,
     pub(crate) dst: NodeId,
     pub(crate) bandwidth: BitsPerSec,
     pub(crate) delay: Nanosecs,
+    pub(crate) loss_rate: f64,
     pub(crate) dists: EDistBuckets,
+    // Delay distributions of each client's flows, if flows are tagged with clients
+    pub(crate) client_dists: FxHashMap<ClientId, EDistBuckets>,
+    // Delay distributions of the flows starting in each time window with records, sorted by time
+    pub(crate) time_dists: Vec<(Range<Nanosecs>, EDistBuckets)>,
 }
 
 impl EDistChannel {
@@ -237,7 +428,34 @@ impl EDistChannel {
             dst: chan.dst,
             bandwidth: chan.bandwidth,
             delay: chan.delay,
+            loss_rate: chan.loss_rate,
             dists: EDistBuckets::new_empty(),
+            client_dists: FxHashMap::default(),
+            time_dists: Vec::new(),
+        }
+    }
+
+    // Multiplies every delay sample by `factor`
+    pub(crate) fn scale_delays(&mut self, factor: f64) {
+        self.dists.scale(factor);
+        for dists in self.client_dists.values_mut() {
+            dists.scale(factor);
+        }
+        for (_, dists) in &mut self.time_dists {
+            dists.scale(factor);
+        }
+    }
+
+    // Returns the delay distributions for flows starting at `start`, falling back to the
+    // distributions over all start times if no time window covers it.
+    pub(crate) fn dists_at(&self, start: Option<Nanosecs>) -> &EDistBuckets {
+        let Some(start) = start else {
+            return &self.dists;
+        };
+        let i = self.time_dists.partition_point(|(w, _)| w.end <= start);
+        match self.time_dists.get(i) {
+            Some((w, dists)) if w.contains(&start) => dists,
+            _ => &self.dists,
         }
     }
 }
@@ -260,6 +478,15 @@ impl<'a, C: Channel> Path<'a, C> {
         self.inner.iter().map(|&(_, c)| c.delay()).sum()
     }
 
+    /// Returns the probability that a packet is lost somewhere along the path.
+    pub fn loss_rate(&self) -> f64 {
+        1.0 - self
+            .inner
+            .iter()
+            .map(|&(_, c)| 1.0 - c.loss_rate())
+            .product::<f64>()
+    }
+
     /// Returns an iterator over the link bandwidths in the path.
     pub fn bandwidths(&self) -> impl Iterator<Item = BitsPerSec> + '_ {
         self.inner.iter().map(|&(_, c)| c.bandwidth())
@@ -274,7 +501,9 @@ impl<'a, C: Channel> Path<'a, C> {
 identifier!(FlowId, usize);
 
 /// A flow is a logically grouped sequence of bytes from a source to a destination.
-#[derive(Debug, Default, Clone, Copy, Hash, serde::Serialize, serde::Deserialize)]
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
 pub struct Flow {
     /// The flow ID.
     pub id: FlowId,
@@ -315,9 +544,14 @@ impl FctRecord {
     }
 
     /// Returns the packet-normalized delay, which is the delay normalized by the number of packets
-    /// in the flow.
+    /// in the flow under the default [`PacketProfile`].
     pub fn pktnorm_delay(&self) -> f64 {
-        let nr_pkts = (self.size.into_f64() / SZ_PKTMAX.into_f64()).ceil();
+        self.pktnorm_delay_with(&PacketProfile::default())
+    }
+
+    /// Like [`FctRecord::pktnorm_delay`], but with packets of the given profile.
+    pub fn pktnorm_delay_with(&self, packets: &PacketProfile) -> f64 {
+        let nr_pkts = packets.nr_pkts(self.size) as f64;
         self.delay().into_f64() / nr_pkts
     }
 
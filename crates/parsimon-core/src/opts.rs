@@ -1,12 +1,29 @@
 //! This module defines the [`SimOpts`] configuration which describes how to run and process
 //! link-level simulations.
 
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
 
-use crate::{edist::BucketOpts, linksim::LinkSim};
+use crossbeam_channel::Receiver;
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    consistency::ConsistencyCheck,
+    constants::PacketProfile,
+    distribute::WorkerUpdate,
+    edist::BucketOpts,
+    linksim::LinkSim,
+    network::{decompose::Decomposition, fallback::FailurePolicy},
+    records::Retention,
+    units::{Bytes, Nanosecs},
+    utils,
+};
 
 /// Simulation options.
-#[derive(Debug, typed_builder::TypedBuilder)]
+#[derive(Debug, Clone, typed_builder::TypedBuilder)]
 pub struct SimOpts<L: LinkSim> {
     /// Link simulator.
     pub link_sim: L,
@@ -16,11 +33,185 @@ pub struct SimOpts<L: LinkSim> {
     /// Bucketing parameters.
     #[builder(default)]
     pub bucket_opts: BucketOpts,
+    /// Updates to the set of workers, e.g., from an external autoscaler. Workers can be added and
+    /// removed during a run; removed workers finish their in-flight work but receive no more. If
+    /// work is queued with no workers left, the run waits up to [`SimOpts::worker_grace`] for a
+    /// worker to be added, and fails if none is or the channel is closed.
+    #[builder(default, setter(strip_option))]
+    pub worker_updates: Option<Receiver<WorkerUpdate>>,
+    /// How long a run with [`SimOpts::worker_updates`] waits for a worker to be added when work is
+    /// queued and no workers are left, e.g., when a preempted worker is removed before its
+    /// replacement is added.
+    #[builder(default = Duration::from_secs(60))]
+    pub worker_grace: Duration,
+    /// How to handle predictions below the ideal FCT.
+    #[builder(default)]
+    pub consistency: ConsistencyCheck,
+    /// Whether to retain raw FCT records after filling delay distributions.
+    #[builder(default)]
+    pub retention: Retention,
+    /// Whether to simulate identical link-level simulations only once. Only disable this if the
+    /// link simulator's results depend on node or flow IDs.
+    #[builder(default = true)]
+    pub dedup: bool,
+    /// How ACKs on the reverse direction of each link are accounted for. By default, every
+    /// packet is acknowledged with an ACK of the network's [`PacketProfile::ack`] size.
+    #[builder(default, setter(strip_option))]
+    pub ack: Option<AckModel>,
+    /// What to do when a link simulation fails. By default, the run fails. See
+    /// [`crate::network::fallback`].
+    #[builder(default)]
+    pub on_failure: FailurePolicy,
+    /// If set, link simulations which take longer than this are skipped: their edges get empty
+    /// delay distributions and are recorded as
+    /// [timed out](crate::network::fallback::Resolution::TimedOut),
+    /// and the run continues. Local runs need a [detachable](LinkSim::detach) simulator for this.
+    /// See [`crate::network::fallback`].
+    #[builder(default, setter(strip_option))]
+    pub timeout: Option<Duration>,
+    /// The granularity of link-level simulations. See [`crate::network::decompose`].
+    #[builder(default)]
+    pub decomposition: Decomposition,
+    /// If set, every edge also gets delay distributions for each window of this length, built
+    /// from the flows starting in it, so predictions can be conditioned on a flow's start time.
+    /// See [`DelayNetwork::predict_at`](crate::network::DelayNetwork::predict_at). Must be
+    /// positive.
+    #[builder(default, setter(strip_option, into))]
+    pub time_buckets: Option<Nanosecs>,
+    /// Whether every edge also gets delay distributions for the flows from each source rack, so
+    /// predictions can capture racks seeing systematically different delays on the same link.
+    /// Racks with fewer records than [`BucketOpts::b`] fall back to the edge's distributions.
+    #[builder(default)]
+    pub source_racks: bool,
+    /// If set, every random choice of the run is derived from this seed: ECMP hashing of flows
+    /// onto paths, the assignment of link simulations to workers, the order in which clustering
+    /// algorithms without their own seed visit links, and the [prediction
+    /// RNG](crate::network::DelayNetwork::rng) of the resulting delay network. Runs with the same
+    /// inputs and seed produce identical delay networks. Overrides [`Network::with_seed`].
+    ///
+    /// [`Network::with_seed`]: crate::network::Network::with_seed
+    #[builder(default, setter(strip_option))]
+    pub seed: Option<u64>,
+    /// A SQLite database to append the results of every run to. See [`crate::store`].
+    #[cfg(feature = "sqlite")]
+    #[builder(default, setter(strip_option, into))]
+    pub store: Option<std::path::PathBuf>,
 }
 
 impl<L: LinkSim> SimOpts<L> {
     pub(crate) fn is_local(&self) -> bool {
-        self.workers.len() == 1 && is_localhost(self.workers[0])
+        self.worker_updates.is_none() && self.workers.len() == 1 && is_localhost(self.workers[0])
+    }
+
+    /// Returns the ACK model in effect for a network with the given packet profile.
+    pub fn ack_model(&self, packets: &PacketProfile) -> AckModel {
+        self.ack.unwrap_or_else(|| AckModel::for_profile(packets))
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), SimOptsError> {
+        if self.time_buckets == Some(Nanosecs::ZERO) {
+            return Err(SimOptsError::EmptyTimeBuckets);
+        }
+        if let Some(ack) = self.ack {
+            if !(ack.pkts_per_ack.is_finite() && ack.pkts_per_ack > 0.0) {
+                return Err(SimOptsError::PktsPerAck(ack.pkts_per_ack));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An error in [`SimOpts`].
+#[derive(Debug, thiserror::Error)]
+pub enum SimOptsError {
+    /// The ACK model acknowledges a non-positive number of packets per ACK.
+    #[error("Packets per ACK must be positive and finite (got {0})")]
+    PktsPerAck(f64),
+
+    /// The length of time buckets is zero.
+    #[error("Time buckets must be nonempty")]
+    EmptyTimeBuckets,
+
+    /// A timeout was set for local link simulations, but the simulator can't be
+    /// [detached](LinkSim::detach).
+    #[error("Timeouts need a detachable link simulator")]
+    Undetachable,
+}
+
+/// How ACKs consume bandwidth. The bandwidth used by the ACKs of a link's reverse direction is
+/// deducted from the bandwidth available to the link's flows in link-level simulations. The right
+/// parameters depend on the transport: TCP receivers commonly delay ACKs to cover two packets,
+/// while RoCE receivers may acknowledge every packet or coalesce ACKs much more aggressively.
+///
+/// The default acknowledges every packet with an ACK of [`SZ_ACK`](crate::constants::SZ_ACK) bytes, which matches the ns-3
+/// implementation. Networks with a custom [`PacketProfile`] should use
+/// [`AckModel::for_profile`].
+#[derive(Debug, Clone, Copy, PartialEq, derive_new::new, serde::Serialize, serde::Deserialize)]
+pub struct AckModel {
+    /// The size of an ACK.
+    pub size: Bytes,
+    /// The average number of packets acknowledged by each ACK. Must be positive.
+    pub pkts_per_ack: f64,
+    /// Whether to deduct ACK bandwidth at all.
+    pub deduct: bool,
+}
+
+impl AckModel {
+    /// Returns a model which acknowledges every packet with an ACK of the profile's size.
+    pub fn for_profile(packets: &PacketProfile) -> Self {
+        Self {
+            size: packets.ack,
+            pkts_per_ack: 1.0,
+            deduct: true,
+        }
+    }
+
+    /// Returns a model which ignores ACKs entirely.
+    pub fn none() -> Self {
+        Self {
+            deduct: false,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the number of ACK bytes sent for `nr_pkts` data packets.
+    pub(crate) fn ack_bytes(&self, nr_pkts: u64) -> Bytes {
+        if self.deduct {
+            self.size.scale_by(nr_pkts as f64 / self.pkts_per_ack)
+        } else {
+            Bytes::ZERO
+        }
+    }
+}
+
+impl Default for AckModel {
+    fn default() -> Self {
+        Self::for_profile(&PacketProfile::default())
+    }
+}
+
+/// The independent random streams of a run, each derived from the run's [seed](SimOpts::seed).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RngStream {
+    /// ECMP hashing of flows onto paths.
+    Ecmp,
+    /// The assignment of link simulations to workers.
+    WorkAssignment,
+    /// The order in which clustering algorithms visit links.
+    Clustering,
+    /// Prediction sampling.
+    Prediction,
+}
+
+impl RngStream {
+    /// Derives the stream's seed from a run's seed.
+    pub fn seed(self, seed: u64) -> u64 {
+        utils::splitmix64(seed ^ utils::splitmix64(self as u64))
+    }
+
+    /// Returns the stream's RNG for a run's seed.
+    pub fn rng(self, seed: u64) -> StdRng {
+        StdRng::seed_from_u64(self.seed(seed))
     }
 }
 
@@ -0,0 +1,256 @@
+//! This module defines an analysis of the oversubscription of a topology. Nodes are assigned to
+//! tiers by their distance in hops from the nearest host, so hosts are in tier 0, ToRs in tier 1,
+//! and so on. A switch's oversubscription is the total bandwidth of its links to the tier below
+//! divided by the total bandwidth of its links to the tier above; switches without links to the
+//! tier above (e.g., the core) are not oversubscribed. Links within a tier are ignored, and each
+//! link counts with its bandwidth from `a` to `b`.
+//!
+//! The analysis works on arbitrary topologies, so it can be used to check the oversubscription of
+//! imported topologies against the one they were designed for.
+
+use std::{collections::VecDeque, fmt};
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    network::{
+        types::{Link, Node},
+        Network, NodeId, NodeKind,
+    },
+    routing::RoutingAlgo,
+    units::BitsPerSec,
+};
+
+/// The oversubscription of a topology.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct OversubscriptionReport {
+    /// The oversubscription of every tier with at least one oversubscribable switch, sorted by
+    /// tier.
+    pub tiers: Vec<TierOversubscription>,
+    /// The oversubscription of every switch with links to the tier above, sorted by switch ID.
+    pub switches: Vec<SwitchOversubscription>,
+}
+
+impl OversubscriptionReport {
+    /// Analyzes the topology with the given nodes and links.
+    pub fn new(nodes: &[Node], links: &[Link]) -> Self {
+        let tiers = tiers(nodes, links);
+        let mut capacity = FxHashMap::<NodeId, (u64, u64)>::default();
+        for link in links {
+            let (Some(&ta), Some(&tb)) = (tiers.get(&link.a), tiers.get(&link.b)) else {
+                continue;
+            };
+            let bw = link.bandwidth.into_u64();
+            if ta < tb {
+                capacity.entry(link.a).or_default().1 += bw;
+                capacity.entry(link.b).or_default().0 += bw;
+            } else if tb < ta {
+                capacity.entry(link.b).or_default().1 += bw;
+                capacity.entry(link.a).or_default().0 += bw;
+            }
+        }
+        let mut switches = nodes
+            .iter()
+            .filter(|n| matches!(n.kind, NodeKind::Switch))
+            .filter_map(|n| {
+                let &(down, up) = capacity.get(&n.id)?;
+                (down > 0 && up > 0).then(|| SwitchOversubscription {
+                    switch: n.id,
+                    tier: tiers[&n.id],
+                    down: BitsPerSec::new(down),
+                    up: BitsPerSec::new(up),
+                })
+            })
+            .collect::<Vec<_>>();
+        switches.sort_by_key(|s| s.switch);
+
+        let mut by_tier = FxHashMap::<usize, Vec<&SwitchOversubscription>>::default();
+        for s in &switches {
+            by_tier.entry(s.tier).or_default().push(s);
+        }
+        let mut tiers = by_tier
+            .into_iter()
+            .map(|(tier, members)| {
+                let ratios = members.iter().map(|s| s.ratio()).collect::<Vec<_>>();
+                let down = members.iter().map(|s| s.down.into_u64()).sum::<u64>();
+                let up = members.iter().map(|s| s.up.into_u64()).sum::<u64>();
+                TierOversubscription {
+                    tier,
+                    nr_switches: members.len(),
+                    ratio: down as f64 / up as f64,
+                    min: ratios.iter().copied().fold(f64::INFINITY, f64::min),
+                    max: ratios.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                }
+            })
+            .collect::<Vec<_>>();
+        tiers.sort_by_key(|t| t.tier);
+        Self { tiers, switches }
+    }
+
+    /// Returns the oversubscription of a switch, or `None` if the switch has no links to the tiers
+    /// above and below it.
+    pub fn of(&self, switch: NodeId) -> Option<f64> {
+        self.switches
+            .binary_search_by_key(&switch, |s| s.switch)
+            .ok()
+            .map(|i| self.switches[i].ratio())
+    }
+}
+
+impl fmt::Display for OversubscriptionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.tiers.is_empty() {
+            return write!(f, "no oversubscribed tiers");
+        }
+        let mut first = true;
+        for tier in &self.tiers {
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+            write!(f, "{tier}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The oversubscription of a tier of switches.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TierOversubscription {
+    /// The tier.
+    pub tier: usize,
+    /// The number of switches in the tier with links to the tiers above and below.
+    pub nr_switches: usize,
+    /// The tier's total downward bandwidth divided by its total upward bandwidth.
+    pub ratio: f64,
+    /// The smallest oversubscription of a switch in the tier.
+    pub min: f64,
+    /// The largest oversubscription of a switch in the tier.
+    pub max: f64,
+}
+
+impl fmt::Display for TierOversubscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tier {}: {:.2}:1 over {} switch(es) (min {:.2}, max {:.2})",
+            self.tier, self.ratio, self.nr_switches, self.min, self.max
+        )
+    }
+}
+
+/// The oversubscription of a single switch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SwitchOversubscription {
+    /// The switch.
+    pub switch: NodeId,
+    /// The switch's tier.
+    pub tier: usize,
+    /// The total bandwidth of the switch's links to the tier below.
+    pub down: BitsPerSec,
+    /// The total bandwidth of the switch's links to the tier above.
+    pub up: BitsPerSec,
+}
+
+impl SwitchOversubscription {
+    /// Returns the switch's oversubscription ratio.
+    pub fn ratio(&self) -> f64 {
+        self.down.into_f64() / self.up.into_f64()
+    }
+}
+
+impl<R> Network<R>
+where
+    R: RoutingAlgo + Sync,
+{
+    /// Analyzes the network's oversubscription.
+    pub fn oversubscription(&self) -> OversubscriptionReport {
+        let nodes = self.nodes().cloned().collect::<Vec<_>>();
+        let links = self.links().copied().collect::<Vec<_>>();
+        OversubscriptionReport::new(&nodes, &links)
+    }
+}
+
+// Returns the tier of every node reachable from a host, i.e., its distance in hops from the
+// nearest host.
+pub(crate) fn tiers(nodes: &[Node], links: &[Link]) -> FxHashMap<NodeId, usize> {
+    let mut neighbors = FxHashMap::<NodeId, Vec<NodeId>>::default();
+    for link in links {
+        neighbors.entry(link.a).or_default().push(link.b);
+        neighbors.entry(link.b).or_default().push(link.a);
+    }
+    let mut tiers = FxHashMap::default();
+    let mut queue = VecDeque::new();
+    for node in nodes.iter().filter(|n| matches!(n.kind, NodeKind::Host)) {
+        tiers.insert(node.id, 0);
+        queue.push_back(node.id);
+    }
+    while let Some(id) = queue.pop_front() {
+        let tier = tiers[&id];
+        for &next in neighbors.get(&id).into_iter().flatten() {
+            tiers.entry(next).or_insert_with(|| {
+                queue.push_back(next);
+                tier + 1
+            });
+        }
+    }
+    tiers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::corpus::ClosParams,
+        units::{Gbps, Nanosecs},
+    };
+
+    fn clos(oversubscription: Option<f64>) -> ClosParams {
+        ClosParams {
+            nr_pods: 2,
+            tors_per_pod: 4,
+            aggs_per_pod: 2,
+            cores_per_plane: 2,
+            hosts_per_tor: 8,
+            host_bandwidth: Gbps::new(10).into(),
+            fabric_bandwidth: Gbps::new(40).into(),
+            delay: Nanosecs::new(1000),
+            oversubscription,
+        }
+    }
+
+    #[test]
+    fn clos_oversubscription_is_achieved() {
+        let (nodes, links) = clos(Some(4.0)).build();
+        let report = OversubscriptionReport::new(&nodes, &links);
+        assert_eq!(report.tiers.len(), 2);
+        for tier in &report.tiers {
+            assert!((tier.ratio - 4.0).abs() < 1e-9, "{tier}");
+            assert_eq!(tier.min, tier.max);
+        }
+        assert_eq!(report.tiers[0].nr_switches, 8);
+        assert_eq!(report.tiers[1].nr_switches, 4);
+        // ToRs have 80 Gbps down and two 10 Gbps uplinks
+        let tor = report.switches[0];
+        assert_eq!(tor.tier, 1);
+        assert_eq!(tor.up, BitsPerSec::from(Gbps::new(20)));
+        assert_eq!(report.of(tor.switch), Some(4.0));
+        // Hosts and cores aren't oversubscribable
+        assert_eq!(report.of(NodeId::new(0)), None);
+        assert_eq!(report.of(NodeId::new(nodes.len() - 1)), None);
+    }
+
+    #[test]
+    fn fixed_fabric_bandwidth_is_analyzed() {
+        let (nodes, links) = clos(None).build();
+        let report = OversubscriptionReport::new(&nodes, &links);
+        // ToRs: 80 Gbps down, 80 Gbps up; aggs: 160 Gbps down, 80 Gbps up
+        let ratios = report.tiers.iter().map(|t| t.ratio).collect::<Vec<_>>();
+        assert_eq!(ratios, [1.0, 2.0]);
+        assert_eq!(
+            report.to_string(),
+            "tier 1: 1.00:1 over 8 switch(es) (min 1.00, max 1.00)\n\
+             tier 2: 2.00:1 over 4 switch(es) (min 2.00, max 2.00)"
+        );
+    }
+}
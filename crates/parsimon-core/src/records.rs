@@ -0,0 +1,191 @@
+//! This module defines the retention of raw link-level simulation results. By default, the
+//! [FCT records](FctRecord) produced by link simulations are discarded once they have been
+//! bucketed into delay distributions. With a [`Retention`] policy set in
+//! [`SimOpts`](crate::opts::SimOpts), they are kept as [`FctRecords`] instead, so that they can be
+//! re-bucketed or analyzed without re-simulating.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    cluster::Cluster,
+    network::{EdgeIndex, FctRecord},
+    units::Nanosecs,
+};
+
+/// What to do with FCT records once delay distributions have been filled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Retention {
+    /// Discard the records.
+    #[default]
+    Discard,
+    /// Keep the records in memory.
+    Memory,
+    /// Spill the records to files in the given directory, one per simulated edge. The directory
+    /// is created if it doesn't exist, and record files left in it by earlier runs are removed.
+    Disk(PathBuf),
+}
+
+/// FCT records retained from link simulations. Records are stored once per simulated edge (i.e.,
+/// per cluster representative), but can be looked up from any edge they were used to fill.
+#[derive(Debug, Clone)]
+pub struct FctRecords {
+    storage: Storage,
+    // Maps every edge to the simulated edge whose records filled it
+    representatives: Arc<FxHashMap<EdgeIndex, EdgeIndex>>,
+    // Added to start times on export
+    origin: Nanosecs,
+}
+
+#[derive(Debug, Clone)]
+enum Storage {
+    Memory(Arc<FxHashMap<EdgeIndex, Vec<FctRecord>>>),
+    Disk(PathBuf),
+}
+
+impl FctRecords {
+    pub(crate) fn retain(
+        retention: &Retention,
+        clusters: &[Cluster],
+        eidx2data: &HashMap<EdgeIndex, Vec<FctRecord>>,
+        origin: Nanosecs,
+    ) -> Result<Option<Self>, RecordsError> {
+        // Edges without flows are simulated trivially and have nothing to retain
+        let eidx2data = eidx2data
+            .iter()
+            .filter(|(_, records)| !records.is_empty())
+            .map(|(&eidx, records)| (eidx, records))
+            .collect::<FxHashMap<_, _>>();
+        let storage = match retention {
+            Retention::Discard => return Ok(None),
+            Retention::Memory => Storage::Memory(Arc::new(
+                eidx2data
+                    .iter()
+                    .map(|(&eidx, &records)| (eidx, records.clone()))
+                    .collect(),
+            )),
+            Retention::Disk(dir) => {
+                fs::create_dir_all(dir)?;
+                remove_edge_files(dir)?;
+                for (&eidx, records) in &eidx2data {
+                    let mut f = BufWriter::new(File::create(edge_file(dir, eidx))?);
+                    rmp_serde::encode::write(&mut f, records)?;
+                }
+                Storage::Disk(dir.clone())
+            }
+        };
+        let representatives = clusters
+            .iter()
+            .flat_map(|c| c.members().map(|&m| (m, c.representative())))
+            .filter(|(_, r)| eidx2data.contains_key(r))
+            .collect();
+        Ok(Some(Self {
+            storage,
+            representatives: Arc::new(representatives),
+            origin,
+        }))
+    }
+
+    /// Returns the simulated edge whose records filled `eidx`, or `None` if there is none.
+    pub fn representative_of(&self, eidx: EdgeIndex) -> Option<EdgeIndex> {
+        self.representatives.get(&eidx).copied()
+    }
+
+    // Returns every edge with retained records and the simulated edge whose records filled it.
+    pub(crate) fn assignments(&self) -> impl Iterator<Item = (EdgeIndex, EdgeIndex)> + '_ {
+        self.representatives.iter().map(|(&m, &r)| (m, r))
+    }
+
+    /// Returns all simulated edges, in ascending order.
+    pub fn simulated_edges(&self) -> Vec<EdgeIndex> {
+        let mut edges = self.representatives.values().copied().collect::<Vec<_>>();
+        edges.sort();
+        edges.dedup();
+        edges
+    }
+
+    /// Returns the absolute time start times are relative to. See
+    /// [`SimNetwork::set_origin`](crate::network::SimNetwork::set_origin).
+    pub fn origin(&self) -> Nanosecs {
+        self.origin
+    }
+
+    /// Returns the records used to fill the delay distributions of `eidx`, or `None` if no
+    /// records were retained for it. Start times are run-relative.
+    pub fn get(&self, eidx: EdgeIndex) -> Result<Option<Vec<FctRecord>>, RecordsError> {
+        let Some(representative) = self.representative_of(eidx) else {
+            return Ok(None);
+        };
+        match &self.storage {
+            Storage::Memory(map) => Ok(map.get(&representative).cloned()),
+            Storage::Disk(dir) => {
+                let f = BufReader::new(File::open(edge_file(dir, representative))?);
+                Ok(Some(rmp_serde::decode::from_read(f)?))
+            }
+        }
+    }
+
+    /// Writes the records of every simulated edge as CSV with the columns `edge`, `id`, `size`,
+    /// `start_ns`, `fct_ns`, and `ideal_ns`. Start times are absolute, i.e., offset by the
+    /// [origin](Self::origin).
+    pub fn write_csv<W: io::Write>(&self, mut w: W) -> Result<(), RecordsError> {
+        writeln!(w, "edge,id,size,start_ns,fct_ns,ideal_ns")?;
+        for eidx in self.simulated_edges() {
+            for rec in self.get(eidx)?.unwrap_or_default() {
+                writeln!(
+                    w,
+                    "{},{},{},{},{},{}",
+                    eidx.index(),
+                    rec.id,
+                    rec.size.into_u64(),
+                    (rec.start + self.origin).into_u64(),
+                    rec.fct.into_u64(),
+                    rec.ideal.into_u64()
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn edge_file(dir: &std::path::Path, eidx: EdgeIndex) -> PathBuf {
+    dir.join(format!("edge-{}.msgpack", eidx.index()))
+}
+
+// Removes the record files in `dir`, so that stale records can't be read back.
+fn remove_edge_files(dir: &std::path::Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_edge_file = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("edge-") && name.ends_with(".msgpack"));
+        if is_edge_file {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// An error storing or loading retained records.
+#[derive(Debug, thiserror::Error)]
+pub enum RecordsError {
+    /// IO error.
+    #[error("IO error")]
+    Io(#[from] io::Error),
+
+    /// MessagePack encode error.
+    #[error("MessagePack encode error")]
+    RmpEncode(#[from] rmp_serde::encode::Error),
+
+    /// MessagePack decode error.
+    #[error("MessagePack decode error")]
+    RmpDecode(#[from] rmp_serde::decode::Error),
+}
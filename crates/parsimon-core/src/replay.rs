@@ -0,0 +1,321 @@
+//! This module defines [`replay`], which estimates slowdown percentiles of a workload by replaying
+//! its flows through a [`DelayNetwork`], one slowdown sample per flow.
+//!
+//! Tail percentiles of large workloads often converge long before every flow has been replayed.
+//! With [`EarlyStop`] set, the replay checks its estimates after every window of flows and stops
+//! once they have stabilized; with a time budget set, it stops once the budget is exhausted. In
+//! either case, the report says why the replay stopped and how precise the estimates were.
+
+use std::time::{Duration, Instant};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{
+    edist,
+    network::{DelayNetwork, Flow},
+    routing::RoutingAlgo,
+};
+
+/// Replay options.
+#[derive(Debug, Clone, typed_builder::TypedBuilder)]
+pub struct ReplayOpts {
+    /// The percentiles to estimate, as quantiles in [0, 1].
+    #[builder(default = vec![0.50, 0.99])]
+    pub quantiles: Vec<f64>,
+    /// If set, stop once the estimates have stabilized.
+    #[builder(default, setter(strip_option))]
+    pub early_stop: Option<EarlyStop>,
+    /// If set, stop once this much wall-clock time has elapsed.
+    #[builder(default, setter(strip_option))]
+    pub time_budget: Option<Duration>,
+}
+
+impl Default for ReplayOpts {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// When to consider percentile estimates stable.
+#[derive(Debug, Clone, Copy, PartialEq, typed_builder::TypedBuilder)]
+pub struct EarlyStop {
+    /// The number of flows replayed between successive estimates.
+    #[builder(default = 1000)]
+    pub window: usize,
+    /// The largest relative change between successive estimates of any percentile that counts as
+    /// stable.
+    #[builder(default = 0.01)]
+    pub tolerance: f64,
+    /// The number of successive stable estimates required to stop.
+    #[builder(default = 3)]
+    pub nr_stable: usize,
+}
+
+impl Default for EarlyStop {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// Why a replay stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum StopReason {
+    /// Every flow was replayed.
+    Completed,
+    /// The estimates stabilized.
+    Stabilized,
+    /// The time budget was exhausted.
+    TimeBudget,
+}
+
+/// A replay report, produced by [`replay`].
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ReplayReport {
+    /// The estimated slowdown percentiles, in the order of [`ReplayOpts::quantiles`], or `None` if
+    /// no flow could be replayed.
+    pub estimates: Option<Vec<f64>>,
+    /// Why the replay stopped.
+    pub stop: StopReason,
+    /// The number of flows replayed.
+    pub nr_replayed: usize,
+    /// The number of replayed flows for which the delay network had no estimate.
+    pub nr_skipped: usize,
+    /// The largest relative change of any percentile over the last
+    /// [`nr_stable`](EarlyStop::nr_stable) estimates, or `None` if early stopping is disabled or
+    /// too few estimates were made.
+    pub precision: Option<f64>,
+    /// The wall-clock time spent replaying.
+    pub elapsed: Duration,
+}
+
+/// Error type for [`replay`].
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    /// A percentile to estimate isn't in [0, 1].
+    #[error("Invalid quantile {0}: must be in [0, 1]")]
+    InvalidQuantile(f64),
+}
+
+/// Estimates slowdown percentiles of `flows` by sampling a slowdown for each flow from `delays`.
+/// Flows are replayed in random order, so that stopping early yields estimates over a random
+/// subset of the workload rather than over its earliest flows.
+pub fn replay<R, RNG>(
+    delays: &DelayNetwork<R>,
+    flows: &[Flow],
+    opts: &ReplayOpts,
+    mut rng: RNG,
+) -> Result<ReplayReport, ReplayError>
+where
+    R: RoutingAlgo,
+    RNG: Rng,
+{
+    if let Some(&q) = opts.quantiles.iter().find(|q| !(0.0..=1.0).contains(*q)) {
+        return Err(ReplayError::InvalidQuantile(q));
+    }
+    let begin = Instant::now();
+    let mut order = flows.iter().collect::<Vec<_>>();
+    order.shuffle(&mut rng);
+
+    // Every estimate reads all percentiles from one sorted buffer, into which the samples of each
+    // window are merged, so estimates cost linear rather than quadratic time overall
+    let estimate = |sorted: &[f64]| {
+        opts.quantiles
+            .iter()
+            .map(|&q| edist::sorted_quantile(sorted, q))
+            .collect::<Option<Vec<_>>>()
+    };
+    let mut sorted = Vec::new();
+    let mut samples = Vec::new();
+    let mut nr_skipped = 0;
+    let mut previous = None;
+    // Relative changes between successive estimates, most recent last
+    let mut changes = Vec::new();
+    let mut stop = StopReason::Completed;
+    for (i, f) in order.into_iter().enumerate() {
+        if opts
+            .time_budget
+            .is_some_and(|budget| begin.elapsed() >= budget)
+        {
+            stop = StopReason::TimeBudget;
+            break;
+        }
+        match delays.slowdown(f.size, (f.src, f.dst), &mut rng) {
+            Some(slowdown) => samples.push(slowdown),
+            None => nr_skipped += 1,
+        }
+        let Some(early_stop) = &opts.early_stop else {
+            continue;
+        };
+        if !(i + 1).is_multiple_of(early_stop.window.max(1)) {
+            continue;
+        }
+        merge_sorted(&mut sorted, &mut samples);
+        let Some(current) = estimate(&sorted) else {
+            continue;
+        };
+        if let Some(previous) = previous.replace(current.clone()) {
+            changes.push(max_relative_change(&previous, &current));
+            if recent_change(&changes, early_stop).is_some_and(|c| c <= early_stop.tolerance) {
+                stop = StopReason::Stabilized;
+                break;
+            }
+        }
+    }
+    merge_sorted(&mut sorted, &mut samples);
+    let nr_replayed = sorted.len() + nr_skipped;
+    let precision = opts
+        .early_stop
+        .and_then(|early_stop| recent_change(&changes, &early_stop));
+    Ok(ReplayReport {
+        estimates: estimate(&sorted),
+        stop,
+        nr_replayed,
+        nr_skipped,
+        precision,
+        elapsed: begin.elapsed(),
+    })
+}
+
+// Sorts `fresh` and merges it into `sorted`, leaving `fresh` empty.
+fn merge_sorted(sorted: &mut Vec<f64>, fresh: &mut Vec<f64>) {
+    if fresh.is_empty() {
+        return;
+    }
+    fresh.sort_by(f64::total_cmp);
+    let old = std::mem::replace(sorted, Vec::with_capacity(sorted.len() + fresh.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < fresh.len() {
+        if old[i].total_cmp(&fresh[j]).is_le() {
+            sorted.push(old[i]);
+            i += 1;
+        } else {
+            sorted.push(fresh[j]);
+            j += 1;
+        }
+    }
+    sorted.extend_from_slice(&old[i..]);
+    sorted.extend_from_slice(&fresh[j..]);
+    fresh.clear();
+}
+
+fn max_relative_change(previous: &[f64], current: &[f64]) -> f64 {
+    let change = |p: f64, c: f64| if p == c { 0.0 } else { (c - p).abs() / p.abs() };
+    previous
+        .iter()
+        .zip(current)
+        .map(|(&p, &c)| change(p, c))
+        .fold(0.0, f64::max)
+}
+
+// The largest of the last `nr_stable` changes, if there are that many.
+fn recent_change(changes: &[f64], early_stop: &EarlyStop) -> Option<f64> {
+    let n = early_stop.nr_stable.max(1);
+    (changes.len() >= n).then(|| {
+        changes[changes.len() - n..]
+            .iter()
+            .copied()
+            .fold(0.0, f64::max)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        network::{FlowId, Network, NodeId},
+        opts::SimOpts,
+        testing::{self, FixedDelaySim},
+        units::{Bytes, Nanosecs},
+    };
+
+    fn delays_and_flows(nr_flows: usize) -> anyhow::Result<(DelayNetwork, Vec<Flow>)> {
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..nr_flows)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let opts = SimOpts::builder()
+            .link_sim(FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let delays = Network::new(&nodes, &links)?
+            .into_simulations(flows.clone())
+            .into_delays(opts)?;
+        Ok((delays, flows))
+    }
+
+    #[test]
+    fn replays_run_to_completion_by_default() -> anyhow::Result<()> {
+        let (delays, flows) = delays_and_flows(100)?;
+        let report = replay(
+            &delays,
+            &flows,
+            &ReplayOpts::default(),
+            StdRng::seed_from_u64(0),
+        )?;
+        assert_eq!(report.stop, StopReason::Completed);
+        assert_eq!(report.nr_replayed, 100);
+        assert_eq!(report.nr_skipped, 0);
+        assert_eq!(report.precision, None);
+        let estimates = report.estimates.unwrap();
+        assert_eq!(estimates.len(), 2);
+        assert!(estimates.iter().all(|&s| s > 1.0));
+        Ok(())
+    }
+
+    #[test]
+    fn stable_estimates_stop_early() -> anyhow::Result<()> {
+        let (delays, flows) = delays_and_flows(10_000)?;
+        let early_stop = EarlyStop::builder().window(100).nr_stable(3).build();
+        let opts = ReplayOpts::builder().early_stop(early_stop).build();
+        let report = replay(&delays, &flows, &opts, StdRng::seed_from_u64(0))?;
+        // Every slowdown is the same, so the estimates are stable from the first window on
+        assert_eq!(report.stop, StopReason::Stabilized);
+        assert_eq!(report.nr_replayed, 400);
+        assert_eq!(report.precision, Some(0.0));
+        Ok(())
+    }
+
+    #[test]
+    fn exhausted_budgets_stop_replays() -> anyhow::Result<()> {
+        let (delays, flows) = delays_and_flows(100)?;
+        let opts = ReplayOpts::builder().time_budget(Duration::ZERO).build();
+        let report = replay(&delays, &flows, &opts, StdRng::seed_from_u64(0))?;
+        assert_eq!(report.stop, StopReason::TimeBudget);
+        assert_eq!(report.nr_replayed, 0);
+        assert_eq!(report.estimates, None);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_quantiles_are_rejected() -> anyhow::Result<()> {
+        let (delays, flows) = delays_and_flows(10)?;
+        for q in [-0.1, 1.1, f64::NAN] {
+            let opts = ReplayOpts::builder().quantiles(vec![0.5, q]).build();
+            assert!(matches!(
+                replay(&delays, &flows, &opts, StdRng::seed_from_u64(0)),
+                Err(ReplayError::InvalidQuantile(_))
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn merged_windows_stay_sorted() {
+        let mut sorted = Vec::new();
+        for window in [vec![3.0, 1.0], vec![2.0, 5.0, 0.5], vec![4.0]] {
+            let mut fresh = window;
+            merge_sorted(&mut sorted, &mut fresh);
+            assert!(fresh.is_empty());
+        }
+        assert_eq!(sorted, vec![0.5, 1.0, 2.0, 3.0, 4.0, 5.0]);
+    }
+}
@@ -1,11 +1,13 @@
 //! This module defines the manner in which routes are specified and implemented.
 
+use rayon::prelude::*;
 use rustc_hash::FxHashMap;
 use std::collections::VecDeque;
 
 use petgraph::{
     graph::NodeIndex,
     visit::{VisitMap, Visitable},
+    Direction,
 };
 
 use crate::{
@@ -20,77 +22,261 @@ use crate::{
 pub trait RoutingAlgo {
     /// Return the set of next hops needed to get from `from` to `to.
     fn next_hops(&self, from: NodeId, to: NodeId) -> Option<Vec<NodeId>>;
+
+    /// Returns true if the next hops are all shortest-path next hops, each of which is chosen
+    /// with equal probability. Such routes allow queries between hosts under the same ToR to skip
+    /// the routing table.
+    fn is_shortest_path_ecmp(&self) -> bool {
+        false
+    }
 }
 
 type HopMatrix = Vec<HopMap>;
 type HopMap = Vec<Vec<NodeId>>;
 
+/// How the routing table of a [`BfsRoutes`] is laid out. Both layouts hold the same shortest-path
+/// next hops.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub enum RouteLayout {
+    /// A table of next hops between every pair of nodes, listed in BFS order. It takes memory
+    /// quadratic in the number of nodes.
+    #[default]
+    Dense,
+    /// One table per group of interchangeable destinations, as in [`CompactRoutes`], for large
+    /// topologies. Next hops are listed by node ID rather than in BFS order, so ECMP may hash a
+    /// flow onto a different (equal-cost) path than with the dense layout.
+    Compact,
+}
+
 /// A routing matrix constructed with BFS.
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct BfsRoutes {
-    inner: HopMatrix,
+    table: BfsTable,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+enum BfsTable {
+    Dense(HopMatrix),
+    Compact(CompactRoutes),
 }
 
 impl BfsRoutes {
-    /// Builds a routing table from a topology using BFS.
+    /// Builds a routing table from a topology using BFS. Only switches are expanded during the
+    /// search, so hosts (including multi-homed ones) are never used as transit nodes.
     pub fn new(topology: &Topology<BasicChannel>) -> Self {
-        let g = &topology.graph;
+        Self::with_layout(topology, RouteLayout::Dense)
+    }
 
-        // Each node is the starting point for a BFS. Do chunks of these in parallel.
-        let node_indices = g.node_indices().collect::<Vec<_>>();
-        let entries = utils::par_chunks(&node_indices, |indices| {
-            let mut entries = Vec::new();
-            for &start in indices {
-                let mut discovered = g.visit_map();
-                discovered.visit(start);
-
-                let mut queue = VecDeque::new();
-                queue.push_back(start);
-
-                let mut distances: FxHashMap<NodeIndex, usize> = [(start, 0)].into_iter().collect();
-
-                while let Some(n) = queue.pop_front() {
-                    let cur_distance = *distances.get(&n).unwrap();
-                    for succ in g.neighbors(n) {
-                        if discovered.visit(succ) {
-                            distances.insert(succ, cur_distance + 1);
-                            if matches!(g[succ].kind, NodeKind::Switch) {
-                                queue.push_back(succ);
-                            }
-                        }
-                        // In this function, we do not assume `NodeId`s and `NodeIndex`s are exactly
-                        // the same, but it may be enforced elsewhere
-                        if *distances.get(&succ).unwrap() == cur_distance + 1 {
-                            // You can get from `succ` to `start` through `n`
-                            entries.push((g[succ].id, g[start].id, g[n].id))
-                        }
-                    }
-                }
-            }
-            entries
-        });
-
-        // Merge the results into a single collection
-        let nr_nodes = node_indices.len();
-        let mut hops = vec![vec![Vec::new(); nr_nodes]; nr_nodes];
-        for (a, b, c) in entries {
-            hops[a.inner()][b.inner()].push(c);
+    /// Builds a routing table like [`BfsRoutes::new`], laid out as given.
+    pub fn with_layout(topology: &Topology<BasicChannel>, layout: RouteLayout) -> Self {
+        let table = match layout {
+            RouteLayout::Dense => BfsTable::Dense(dense_hops(topology)),
+            RouteLayout::Compact => BfsTable::Compact(CompactRoutes::new(topology)),
+        };
+        Self { table }
+    }
+
+    /// Returns the layout of the routing table.
+    pub fn layout(&self) -> RouteLayout {
+        match self.table {
+            BfsTable::Dense(_) => RouteLayout::Dense,
+            BfsTable::Compact(_) => RouteLayout::Compact,
         }
+    }
+}
 
-        Self { inner: hops }
+impl RoutingAlgo for BfsRoutes {
+    fn is_shortest_path_ecmp(&self) -> bool {
+        true
     }
 
-    fn for_node(&self, node: NodeId) -> Option<&HopMap> {
-        self.inner.get(node.inner())
+    fn next_hops(&self, from: NodeId, to: NodeId) -> Option<Vec<NodeId>> {
+        match &self.table {
+            BfsTable::Dense(hops) => hops
+                .get(from.inner())
+                .and_then(|map| map.get(to.inner()))
+                .map(|hops| hops.to_vec()),
+            BfsTable::Compact(routes) => routes.next_hops(from, to),
+        }
     }
 }
 
-impl RoutingAlgo for BfsRoutes {
+// Builds a table of next hops between every pair of nodes.
+fn dense_hops(topology: &Topology<BasicChannel>) -> HopMatrix {
+    let g = &topology.graph;
+
+    // Each node is the starting point for a BFS. Do chunks of these in parallel.
+    let node_indices = g.node_indices().collect::<Vec<_>>();
+    let entries = utils::par_chunks(&node_indices, |indices| {
+        let mut entries = Vec::new();
+        for &start in indices {
+            entries.extend(
+                bfs_towards(topology, start)
+                    .into_iter()
+                    .map(|(from, via)| (from, g[start].id, via)),
+            );
+        }
+        entries
+    });
+
+    // Merge the results into a single collection
+    let nr_nodes = node_indices.len();
+    let mut hops = vec![vec![Vec::new(); nr_nodes]; nr_nodes];
+    for (a, b, c) in entries {
+        hops[a.inner()][b.inner()].push(c);
+    }
+    hops
+}
+
+/// A routing table for large topologies. Destinations with the same neighbors (e.g., hosts under
+/// the same ToR) are interchangeable, so routes are computed and stored once per group of such
+/// destinations rather than once per destination. Next hops are the same as those of
+/// [`BfsRoutes`], but sorted by node ID.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CompactRoutes {
+    // The group of every node, indexed by node ID
+    groups: Vec<u32>,
+    // One table per group, computed towards the group's representative
+    tables: Vec<HopTable>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct HopTable {
+    representative: NodeId,
+    // `hops[offsets[i]..offsets[i + 1]]` are the next hops from node `i` to the representative
+    offsets: Vec<u32>,
+    hops: Vec<NodeId>,
+}
+
+impl CompactRoutes {
+    /// Builds a compact routing table from a topology. See [`BfsRoutes::new`] for how routes are
+    /// computed.
+    pub fn new(topology: &Topology<BasicChannel>) -> Self {
+        let g = &topology.graph;
+        let neighbors = |idx, dir| {
+            let mut ids = g
+                .neighbors_directed(idx, dir)
+                .map(|n| g[n].id)
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        let mut group_of = FxHashMap::default();
+        let mut groups = vec![0; g.node_count()];
+        let mut representatives = Vec::new();
+        for idx in g.node_indices() {
+            let node = &g[idx];
+            let key = (
+                matches!(node.kind, NodeKind::Host),
+                neighbors(idx, Direction::Incoming),
+                neighbors(idx, Direction::Outgoing),
+            );
+            let group = *group_of.entry(key).or_insert_with(|| {
+                representatives.push(idx);
+                (representatives.len() - 1) as u32
+            });
+            groups[node.id.inner()] = group;
+        }
+        let tables = representatives
+            .into_par_iter()
+            .map(|start| {
+                let mut entries = bfs_towards(topology, start);
+                entries.sort();
+                let mut offsets = Vec::with_capacity(g.node_count() + 1);
+                let mut hops = Vec::with_capacity(entries.len());
+                let mut entries = entries.into_iter().peekable();
+                for i in 0..g.node_count() {
+                    offsets.push(hops.len() as u32);
+                    while let Some((_, via)) = entries.next_if(|(from, _)| from.inner() == i) {
+                        hops.push(via);
+                    }
+                }
+                offsets.push(hops.len() as u32);
+                HopTable {
+                    representative: g[start].id,
+                    offsets,
+                    hops,
+                }
+            })
+            .collect();
+        Self { groups, tables }
+    }
+
+    /// Returns the number of routing tables stored, one per group of interchangeable
+    /// destinations.
+    pub fn nr_tables(&self) -> usize {
+        self.tables.len()
+    }
+}
+
+impl RoutingAlgo for CompactRoutes {
+    fn is_shortest_path_ecmp(&self) -> bool {
+        true
+    }
+
     fn next_hops(&self, from: NodeId, to: NodeId) -> Option<Vec<NodeId>> {
-        self.for_node(from)
-            .and_then(|map| map.get(to.inner()))
-            .map(|hops| hops.to_vec())
+        let table = &self.tables[*self.groups.get(to.inner())? as usize];
+        if from.inner() >= self.groups.len() {
+            return None;
+        }
+        if from == to {
+            return Some(Vec::new());
+        }
+        // Swapping `to` with the representative maps the network onto itself, so routes to `to`
+        // are routes to the representative with the two swapped.
+        let rep = table.representative;
+        let swap = |id| match id {
+            id if id == rep => to,
+            id if id == to => rep,
+            id => id,
+        };
+        let i = swap(from).inner();
+        let range = table.offsets[i] as usize..table.offsets[i + 1] as usize;
+        let mut hops = table.hops[range]
+            .iter()
+            .map(|&id| swap(id))
+            .collect::<Vec<_>>();
+        hops.sort();
+        Some(hops)
+    }
+}
+
+/// Runs a BFS backwards from `start`, returning `(from, via)` pairs such that `via` is a next hop
+/// on a shortest path from `from` to `start`. Only switches are expanded during the search, so
+/// hosts (including multi-homed ones) are never used as transit nodes.
+fn bfs_towards(topology: &Topology<BasicChannel>, start: NodeIndex) -> Vec<(NodeId, NodeId)> {
+    let g = &topology.graph;
+    let mut entries = Vec::new();
+    let mut discovered = g.visit_map();
+    discovered.visit(start);
+
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+
+    let mut distances: FxHashMap<NodeIndex, usize> = [(start, 0)].into_iter().collect();
+
+    while let Some(n) = queue.pop_front() {
+        let cur_distance = *distances.get(&n).unwrap();
+        // Search backwards along incoming channels, so that routes respect the direction of
+        // asymmetric and unidirectional links
+        for succ in g.neighbors_directed(n, Direction::Incoming) {
+            if discovered.visit(succ) {
+                distances.insert(succ, cur_distance + 1);
+                if matches!(g[succ].kind, NodeKind::Switch) {
+                    queue.push_back(succ);
+                }
+            }
+            // In this function, we do not assume `NodeId`s and `NodeIndex`s are exactly the same,
+            // but it may be enforced elsewhere
+            if *distances.get(&succ).unwrap() == cur_distance + 1 {
+                // You can get from `succ` to `start` through `n`
+                entries.push((g[succ].id, g[n].id))
+            }
+        }
     }
+    entries
 }
 
 #[cfg(test)]
@@ -98,12 +284,21 @@ mod tests {
     use std::collections::BTreeMap;
 
     use super::*;
+    use crate::network::types::{Link, Node};
     use crate::testing;
+    use crate::units::{BitsPerSec, Nanosecs};
     use anyhow::Context;
 
     type SortedHopMatrix = BTreeMap<NodeId, SortedHopMap>;
     type SortedHopMap = BTreeMap<NodeId, Vec<NodeId>>;
 
+    fn dense(routes: &BfsRoutes) -> &HopMatrix {
+        match &routes.table {
+            BfsTable::Dense(hops) => hops,
+            BfsTable::Compact(_) => panic!("routes are not dense"),
+        }
+    }
+
     /// Generate a stable sorting of the hop matrix for snapshot tests
     fn sorted_hop_matrix(matrix: &HopMatrix) -> SortedHopMatrix {
         matrix
@@ -129,17 +324,124 @@ mod tests {
         let (nodes, links) = testing::three_node_config();
         let topo = Topology::new(&nodes, &links).context("failed to create topology")?;
         let routes = BfsRoutes::new(&topo);
-        let hops = sorted_hop_matrix(&routes.inner);
+        let hops = sorted_hop_matrix(dense(&routes));
         insta::assert_yaml_snapshot!(hops);
         Ok(())
     }
 
+    #[test]
+    fn multi_homed_hosts_are_not_transit() -> anyhow::Result<()> {
+        let (nodes, links) = testing::dual_homed_config();
+        let topo = Topology::new(&nodes, &links).context("failed to create topology")?;
+        let routes = BfsRoutes::new(&topo);
+        let hop = |a, b| {
+            let mut hops = routes.next_hops(NodeId::new(a), NodeId::new(b)).unwrap();
+            hops.sort();
+            hops
+        };
+        // Host 0 can reach host 1 through either ToR
+        assert_eq!(hop(0, 1), vec![NodeId::new(4), NodeId::new(5)]);
+        // ToR 4 never forwards to ToR 5 through a host
+        assert_eq!(hop(4, 5), vec![NodeId::new(6)]);
+        Ok(())
+    }
+
+    #[test]
+    fn routes_respect_link_direction() -> anyhow::Result<()> {
+        // Hosts 0 and 1 hang off switches 2 and 3, which are connected by a one-way ring 2 -> 3
+        // -> 4 -> 2.
+        let nodes = [
+            Node::new_host(NodeId::new(0)),
+            Node::new_host(NodeId::new(1)),
+            Node::new_switch(NodeId::new(2)),
+            Node::new_switch(NodeId::new(3)),
+            Node::new_switch(NodeId::new(4)),
+        ];
+        let (bw, delay) = (BitsPerSec::new(100), Nanosecs::new(1));
+        let id = NodeId::new;
+        let links = [
+            Link::new(id(0), id(2), bw, delay),
+            Link::new(id(1), id(3), bw, delay),
+            Link::new_unidirectional(id(2), id(3), bw, delay),
+            Link::new_unidirectional(id(3), id(4), bw, delay),
+            Link::new_unidirectional(id(4), id(2), bw, delay),
+        ];
+        let topo = Topology::new(&nodes, &links).context("failed to create topology")?;
+        let routes = BfsRoutes::new(&topo);
+        assert_eq!(routes.next_hops(id(2), id(1)), Some(vec![id(3)]));
+        assert_eq!(routes.next_hops(id(3), id(0)), Some(vec![id(4)]));
+        assert_eq!(routes.next_hops(id(4), id(0)), Some(vec![id(2)]));
+        assert_compact_routes_match(&topo);
+        Ok(())
+    }
+
+    // Checks that compact routes have the same next hops as BFS routes between all nodes
+    fn assert_compact_routes_match(topo: &Topology<BasicChannel>) {
+        let bfs = BfsRoutes::new(topo);
+        let compact = CompactRoutes::new(topo);
+        let ids = topo.graph.node_weights().map(|n| n.id).collect::<Vec<_>>();
+        for &from in &ids {
+            for &to in &ids {
+                let mut expected = bfs.next_hops(from, to).unwrap();
+                expected.sort();
+                assert_eq!(
+                    compact.next_hops(from, to),
+                    Some(expected),
+                    "{from} -> {to}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn compact_routes_match_bfs_routes() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let topo = Topology::new(&nodes, &links).context("failed to create topology")?;
+        assert_compact_routes_match(&topo);
+        // Hosts under each ToR share a table, and so do the two spines
+        assert_eq!(CompactRoutes::new(&topo).nr_tables(), 5);
+
+        let (nodes, links) = testing::dual_homed_config();
+        let topo = Topology::new(&nodes, &links).context("failed to create topology")?;
+        assert_compact_routes_match(&topo);
+        Ok(())
+    }
+
+    #[test]
+    fn bfs_routes_can_be_compact() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let topo = Topology::new(&nodes, &links).context("failed to create topology")?;
+        let dense = BfsRoutes::new(&topo);
+        let compact = BfsRoutes::with_layout(&topo, RouteLayout::Compact);
+        assert_eq!(dense.layout(), RouteLayout::Dense);
+        assert_eq!(compact.layout(), RouteLayout::Compact);
+        let ids = topo.graph.node_weights().map(|n| n.id).collect::<Vec<_>>();
+        for &from in &ids {
+            for &to in &ids {
+                let mut expected = dense.next_hops(from, to).unwrap();
+                expected.sort();
+                assert_eq!(compact.next_hops(from, to), Some(expected));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn compact_routes_reject_unknown_nodes() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let topo = Topology::new(&nodes, &links).context("failed to create topology")?;
+        let routes = CompactRoutes::new(&topo);
+        assert_eq!(routes.next_hops(NodeId::new(0), NodeId::new(100)), None);
+        assert_eq!(routes.next_hops(NodeId::new(100), NodeId::new(0)), None);
+        Ok(())
+    }
+
     #[test]
     fn route_eight_node_works() -> anyhow::Result<()> {
         let (nodes, links) = testing::eight_node_config();
         let topo = Topology::new(&nodes, &links).context("failed to create topology")?;
         let routes = BfsRoutes::new(&topo);
-        let hops = sorted_hop_matrix(&routes.inner);
+        let hops = sorted_hop_matrix(dense(&routes));
         insta::assert_yaml_snapshot!(hops);
         Ok(())
     }
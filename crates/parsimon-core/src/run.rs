@@ -1,24 +1,83 @@
 //! This module defines the [`run`] routine, which is `Parsimon`'s main entry point.
 
+use std::time::{Duration, Instant};
+
 use crate::cluster::ClusteringAlgo;
 use crate::linksim::LinkSim;
 use crate::network::{DelayNetwork, SimNetworkError};
 use crate::opts::SimOpts;
+use crate::scenario::ScenarioError;
 use crate::spec::{Spec, SpecError};
 
 /// The core `Parsimon` routine. This transforms a specification into a network of delay
 /// distributions, using a provided [link simulation options](SimOpts) and [clustering algorithm](ClusteringAlgo).
+///
+/// Specifications with scheduled link events must be run with
+/// [`run_epochs`](crate::segment::run_epochs) instead, and those with flow dependencies with
+/// [`run_dependent`](crate::dependency::run_dependent).
 pub fn run<S, C>(spec: Spec, opts: SimOpts<S>, clusterer: C) -> Result<DelayNetwork, Error>
 where
     S: LinkSim + Sync,
     C: ClusteringAlgo,
 {
+    run_timed(spec, opts, clusterer).map(|(delays, _)| delays)
+}
+
+/// Like [`run`], but also returns how long each stage took.
+#[tracing::instrument(name = "run", skip_all, fields(nr_flows = spec.flows.len()))]
+pub fn run_timed<S, C>(
+    spec: Spec,
+    opts: SimOpts<S>,
+    clusterer: C,
+) -> Result<(DelayNetwork, StageDurations), Error>
+where
+    S: LinkSim + Sync,
+    C: ClusteringAlgo,
+{
+    let mut durations = StageDurations::default();
+    let mut stage = Instant::now();
+    let mut lap = || std::mem::replace(&mut stage, Instant::now()).elapsed();
     let spec = spec.validate()?;
+    if !spec.schedule.is_empty() {
+        return Err(Error::Scheduled);
+    }
+    if !spec.dependencies.is_empty() {
+        return Err(Error::Dependent);
+    }
+    durations.validate = lap();
     let flows = spec.collect_flows();
-    let mut sims = spec.network.into_simulations(flows);
+    let mut sims = spec.network.seeded(opts.seed).into_simulations(flows);
+    sims.set_origin(spec.origin);
+    durations.trace = lap();
     sims.cluster(clusterer);
-    let delays = sims.into_delays(opts)?;
-    Ok(delays)
+    durations.cluster = lap();
+    let delays = if spec.clients.is_empty() {
+        sims.into_delays(opts)?
+    } else {
+        sims.into_delays_by_client(opts, &spec.clients)?
+    };
+    durations.simulate = lap();
+    Ok((delays, durations))
+}
+
+/// Wall-clock durations of the stages of a [`run_timed`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct StageDurations {
+    /// Validating the specification.
+    pub validate: Duration,
+    /// Tracing flows onto links.
+    pub trace: Duration,
+    /// Clustering links.
+    pub cluster: Duration,
+    /// Simulating links and building delay distributions.
+    pub simulate: Duration,
+}
+
+impl StageDurations {
+    /// Returns the total duration.
+    pub fn total(&self) -> Duration {
+        self.validate + self.trace + self.cluster + self.simulate
+    }
 }
 
 /// The error type for the core [run] routine.
@@ -31,4 +90,16 @@ pub enum Error {
     /// Error running the simulations.
     #[error("SimNetwork error")]
     SimNetwork(#[from] SimNetworkError),
+
+    /// Error applying a scenario.
+    #[error("Scenario error")]
+    Scenario(#[from] ScenarioError),
+
+    /// The specification has scheduled link events, which this routine can't model.
+    #[error("Specification has scheduled link events")]
+    Scheduled,
+
+    /// The specification has flow dependencies, which this routine can't model.
+    #[error("Specification has flow dependencies")]
+    Dependent,
 }
@@ -0,0 +1,499 @@
+//! This module defines failure and degradation [scenarios](Scenario), which describe how the links
+//! of a topology deviate from their specification. Scenarios can be applied when building a
+//! [`Spec`](crate::Spec) or to an existing [`Network`] or [`SimNetwork`], and routes are always
+//! recomputed around failed links.
+//!
+//! A [`Schedule`] describes how the links change over time. It splits a trace into
+//! [epochs](Epoch), each of which has a fixed scenario.
+
+use std::ops::Range;
+
+use crate::network::{
+    types::{Link, ReverseDirection},
+    Flow, FlowId, Network, NodeId, SimNetwork, TopologyError, TraversableNetwork,
+};
+use crate::units::{BitsPerSec, Nanosecs};
+
+/// A set of link failures and degradations.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Scenario {
+    /// Changes to individual links.
+    #[serde(default)]
+    pub links: Vec<LinkChange>,
+}
+
+/// A change to the link connecting two nodes. The change applies to both directions of the link.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LinkChange {
+    /// One endpoint of the link.
+    pub a: NodeId,
+    /// The other endpoint of the link.
+    pub b: NodeId,
+    /// The new state of the link.
+    pub state: LinkState,
+}
+
+/// The state of a link in a scenario.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LinkState {
+    /// The link is down and carries no traffic.
+    Failed,
+    /// The link is up, but with reduced capacity or increased latency.
+    Degraded {
+        /// The link's reduced bandwidth. If `None`, the bandwidth is unchanged.
+        #[serde(default)]
+        bandwidth: Option<BitsPerSec>,
+        /// The delay added to the link's propagation delay.
+        #[serde(default)]
+        extra_delay: Nanosecs,
+        /// The link's new loss rate. If `None`, the loss rate is unchanged.
+        #[serde(default)]
+        loss_rate: Option<f64>,
+    },
+}
+
+impl Scenario {
+    /// Creates an empty scenario.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks the link between `a` and `b` as failed.
+    pub fn fail(mut self, a: NodeId, b: NodeId) -> Self {
+        self.links.push(LinkChange {
+            a,
+            b,
+            state: LinkState::Failed,
+        });
+        self
+    }
+
+    /// Marks the link between `a` and `b` as degraded.
+    pub fn degrade(
+        mut self,
+        a: NodeId,
+        b: NodeId,
+        bandwidth: Option<BitsPerSec>,
+        extra_delay: Nanosecs,
+    ) -> Self {
+        self.links.push(LinkChange {
+            a,
+            b,
+            state: LinkState::Degraded {
+                bandwidth,
+                extra_delay,
+                loss_rate: None,
+            },
+        });
+        self
+    }
+
+    /// Sets the loss rate of the link between `a` and `b`.
+    pub fn lossy(mut self, a: NodeId, b: NodeId, loss_rate: f64) -> Self {
+        self.links.push(LinkChange {
+            a,
+            b,
+            state: LinkState::Degraded {
+                bandwidth: None,
+                extra_delay: Nanosecs::ZERO,
+                loss_rate: Some(loss_rate),
+            },
+        });
+        self
+    }
+
+    /// Returns true if the scenario doesn't change anything.
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Applies the scenario to a list of links, returning the links that remain up.
+    pub fn apply(&self, links: &[Link]) -> Result<Vec<Link>, ScenarioError> {
+        let mut links = links.iter().copied().map(Some).collect::<Vec<_>>();
+        for &LinkChange { a, b, state } in &self.links {
+            let link = links
+                .iter_mut()
+                .find(|l| l.is_some_and(|l| l.connects(a, b)))
+                .ok_or(ScenarioError::UnknownLink { a, b })?;
+            match state {
+                LinkState::Failed => *link = None,
+                LinkState::Degraded {
+                    bandwidth,
+                    extra_delay,
+                    loss_rate,
+                } => {
+                    let link = link.as_mut().unwrap();
+                    link.bandwidth = bandwidth.unwrap_or(link.bandwidth);
+                    link.delay += extra_delay;
+                    link.loss_rate = loss_rate.unwrap_or(link.loss_rate);
+                    if let ReverseDirection::Asymmetric {
+                        bandwidth: rev_bandwidth,
+                        delay: rev_delay,
+                    } = &mut link.reverse
+                    {
+                        *rev_bandwidth = bandwidth.unwrap_or(*rev_bandwidth);
+                        *rev_delay += extra_delay;
+                    }
+                }
+            }
+        }
+        Ok(links.into_iter().flatten().collect())
+    }
+}
+
+impl Network {
+    /// Returns a copy of this network with the given scenario applied. Routes are recomputed, so
+    /// traffic is routed around failed links. Failing every link of a node is an error.
+    pub fn with_scenario(&self, scenario: &Scenario) -> Result<Network, ScenarioError> {
+        let nodes = self.nodes().cloned().collect::<Vec<_>>();
+        let links = scenario.apply(&self.links().copied().collect::<Vec<_>>())?;
+        Ok(
+            Network::new_with_layout(&nodes, &links, self.route_layout())?
+                .with_packet_profile(self.packet_profile())?
+                .with_ecmp(self.ecmp().clone()),
+        )
+    }
+}
+
+impl SimNetwork {
+    /// Returns a copy of this network with the given scenario applied. All flows are rerouted on
+    /// the new topology, and it is an error for a flow's endpoints to become disconnected. Since
+    /// edge indices may change, clusters are reset.
+    pub fn with_scenario(&self, scenario: &Scenario) -> Result<SimNetwork, ScenarioError> {
+        let nodes = self.nodes().cloned().collect::<Vec<_>>();
+        let links = scenario.apply(&self.links().copied().collect::<Vec<_>>())?;
+        let network = Network::new_with_layout(&nodes, &links, self.routes().layout())?
+            .with_packet_profile(self.packet_profile())?
+            .with_ecmp(self.ecmp().clone());
+        let mut flows = self.flows().cloned().collect::<Vec<_>>();
+        flows.sort_by_key(|f| f.id);
+        check_connected(&network, &flows)?;
+        let mut sims = network.into_simulations(flows);
+        sims.set_origin(self.origin());
+        Ok(sims)
+    }
+}
+
+/// Checks that every flow's source can reach its destination.
+pub(crate) fn check_connected(network: &Network, flows: &[Flow]) -> Result<(), ScenarioError> {
+    for f in flows {
+        if network
+            .edge_indices_between(f.src, f.dst, |choices| choices.first())
+            .len()
+            == 0
+        {
+            return Err(ScenarioError::Disconnected(f.id));
+        }
+    }
+    Ok(())
+}
+
+/// A list of link events, each scheduled at a point in time.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Schedule {
+    /// The scheduled events, in any order.
+    #[serde(default)]
+    pub events: Vec<LinkEvent>,
+}
+
+/// An event changing the link connecting two nodes at a point in time.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LinkEvent {
+    /// The time of the event, on the same clock as flow start times.
+    pub at: Nanosecs,
+    /// One endpoint of the link.
+    pub a: NodeId,
+    /// The other endpoint of the link.
+    pub b: NodeId,
+    /// What happens to the link.
+    pub kind: LinkEventKind,
+}
+
+/// The kinds of [link events](LinkEvent).
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LinkEventKind {
+    /// The link goes down.
+    Down,
+    /// The link comes back up in its original state.
+    Up,
+    /// The link is degraded, replacing any earlier degradation.
+    Degrade {
+        /// The link's reduced bandwidth. If `None`, the bandwidth is unchanged.
+        #[serde(default)]
+        bandwidth: Option<BitsPerSec>,
+        /// The delay added to the link's propagation delay.
+        #[serde(default)]
+        extra_delay: Nanosecs,
+        /// The link's new loss rate. If `None`, the loss rate is unchanged.
+        #[serde(default)]
+        loss_rate: Option<f64>,
+    },
+}
+
+/// A time window during which the topology doesn't change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Epoch {
+    /// The window of flow start times covered by the epoch.
+    pub window: Range<Nanosecs>,
+    /// The state of the links during the epoch.
+    pub scenario: Scenario,
+}
+
+impl Schedule {
+    /// Creates an empty schedule.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Schedules the link between `a` and `b` to go down at time `at`.
+    pub fn link_down(self, at: Nanosecs, a: NodeId, b: NodeId) -> Self {
+        self.with(at, a, b, LinkEventKind::Down)
+    }
+
+    /// Schedules the link between `a` and `b` to come back up at time `at`.
+    pub fn link_up(self, at: Nanosecs, a: NodeId, b: NodeId) -> Self {
+        self.with(at, a, b, LinkEventKind::Up)
+    }
+
+    /// Schedules the link between `a` and `b` to be degraded at time `at`.
+    pub fn degrade(
+        self,
+        at: Nanosecs,
+        a: NodeId,
+        b: NodeId,
+        bandwidth: Option<BitsPerSec>,
+        extra_delay: Nanosecs,
+    ) -> Self {
+        let kind = LinkEventKind::Degrade {
+            bandwidth,
+            extra_delay,
+            loss_rate: None,
+        };
+        self.with(at, a, b, kind)
+    }
+
+    fn with(mut self, at: Nanosecs, a: NodeId, b: NodeId, kind: LinkEventKind) -> Self {
+        self.events.push(LinkEvent { at, a, b, kind });
+        self
+    }
+
+    /// Returns true if nothing is scheduled.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Returns a copy of the schedule with `origin` subtracted from all event times. Events before
+    /// `origin` happen at time zero.
+    pub fn shifted(&self, origin: Nanosecs) -> Self {
+        let events = self
+            .events
+            .iter()
+            .map(|&e| LinkEvent {
+                at: e.at.saturating_sub(origin),
+                ..e
+            })
+            .collect();
+        Self { events }
+    }
+
+    /// Splits time into epochs at every event. Epochs are contiguous, the first epoch starts at
+    /// time zero, and the last one never ends. Events at the same time take effect in the order
+    /// they're listed.
+    pub fn epochs(&self) -> Vec<Epoch> {
+        let mut events = self.events.clone();
+        events.sort_by_key(|e| e.at);
+        let mut epochs = Vec::new();
+        let mut state: Vec<LinkChange> = Vec::new();
+        let mut start = Nanosecs::ZERO;
+        for LinkEvent { at, a, b, kind } in events {
+            if at > start {
+                epochs.push(Epoch {
+                    window: start..at,
+                    scenario: Scenario {
+                        links: state.clone(),
+                    },
+                });
+                start = at;
+            }
+            state.retain(|c| !(c.a == a && c.b == b || c.a == b && c.b == a));
+            let state_change = match kind {
+                LinkEventKind::Down => Some(LinkState::Failed),
+                LinkEventKind::Up => None,
+                LinkEventKind::Degrade {
+                    bandwidth,
+                    extra_delay,
+                    loss_rate,
+                } => Some(LinkState::Degraded {
+                    bandwidth,
+                    extra_delay,
+                    loss_rate,
+                }),
+            };
+            if let Some(state_change) = state_change {
+                state.push(LinkChange {
+                    a,
+                    b,
+                    state: state_change,
+                });
+            }
+        }
+        epochs.push(Epoch {
+            window: start..Nanosecs::MAX,
+            scenario: Scenario { links: state },
+        });
+        epochs
+    }
+}
+
+/// Errors which can be encountered applying a scenario.
+#[derive(Debug, thiserror::Error)]
+pub enum ScenarioError {
+    /// The scenario refers to a link which doesn't exist.
+    #[error("no link between {a} and {b}")]
+    UnknownLink {
+        /// The first endpoint.
+        a: NodeId,
+        /// The second endpoint.
+        b: NodeId,
+    },
+
+    /// The scenario disconnects the source and destination of a flow.
+    #[error("flow {0} is disconnected")]
+    Disconnected(FlowId),
+
+    /// The scenario produces an invalid topology.
+    #[error("invalid topology")]
+    Topology(#[from] TopologyError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{network::Flow, testing, units::Bytes};
+
+    #[test]
+    fn failed_links_are_routed_around() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let network = Network::new(&nodes, &links)?;
+        let id = NodeId::new;
+        let scenario = Scenario::new().fail(id(4), id(6));
+        let failed = network.with_scenario(&scenario)?;
+        assert_eq!(failed.links().count(), links.len() - 1);
+        let flows = vec![Flow {
+            id: FlowId::new(0),
+            src: id(0),
+            dst: id(3),
+            size: Bytes::new(1000),
+            start: Nanosecs::ZERO,
+        }];
+        let sims = failed.into_simulations(flows);
+        let e = sims.find_edge(id(4), id(7)).unwrap();
+        assert_eq!(sims.edge(e).unwrap().nr_flows(), 1);
+        assert!(sims.find_edge(id(4), id(6)).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn disconnected_flows_fail() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let id = NodeId::new;
+        let flows = vec![Flow {
+            id: FlowId::new(0),
+            src: id(0),
+            dst: id(3),
+            size: Bytes::new(1000),
+            start: Nanosecs::ZERO,
+        }];
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows);
+        let scenario = Scenario::new().fail(id(4), id(6)).fail(id(4), id(7));
+        assert!(matches!(
+            sims.with_scenario(&scenario),
+            Err(ScenarioError::Disconnected(_))
+        ));
+        let scenario = Scenario::new().fail(id(4), id(6));
+        assert_eq!(sims.with_scenario(&scenario)?.flows().count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn degraded_links_change_both_directions() -> anyhow::Result<()> {
+        let (_, links) = testing::eight_node_config();
+        let id = NodeId::new;
+        let scenario = Scenario::new().degrade(
+            id(6),
+            id(4),
+            Some(BitsPerSec::new(1_000_000_000)),
+            Nanosecs::new(500),
+        );
+        let degraded = scenario.apply(&links)?;
+        let link = degraded.iter().find(|l| l.connects(id(4), id(6))).unwrap();
+        assert_eq!(link.bandwidth, BitsPerSec::new(1_000_000_000));
+        assert_eq!(link.delay, Nanosecs::new(1500));
+        assert_eq!(link.reverse, ReverseDirection::Symmetric);
+        let lossy = Scenario::new().lossy(id(4), id(6), 0.01).apply(&links)?;
+        let link = lossy.iter().find(|l| l.connects(id(4), id(6))).unwrap();
+        assert_eq!(link.loss_rate, 0.01);
+        Ok(())
+    }
+
+    #[test]
+    fn isolating_a_node_fails() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let network = Network::new(&nodes, &links)?;
+        let scenario = Scenario::new().fail(NodeId::new(0), NodeId::new(4));
+        let res = network.with_scenario(&scenario);
+        assert!(matches!(
+            res,
+            Err(ScenarioError::Topology(TopologyError::IsolatedNode(_)))
+        ));
+        let res = network.with_scenario(&Scenario::new().fail(NodeId::new(0), NodeId::new(1)));
+        assert!(matches!(res, Err(ScenarioError::UnknownLink { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn schedules_split_into_epochs() {
+        let id = NodeId::new;
+        let t = Nanosecs::new;
+        let schedule = Schedule::new()
+            .link_up(t(300), id(4), id(6))
+            .degrade(t(200), id(5), id(7), None, t(10))
+            .link_down(t(100), id(6), id(4))
+            .link_down(t(300), id(5), id(7));
+        let epochs = schedule.epochs();
+        let windows = epochs.iter().map(|e| e.window.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            windows,
+            vec![
+                t(0)..t(100),
+                t(100)..t(200),
+                t(200)..t(300),
+                t(300)..Nanosecs::MAX
+            ]
+        );
+        assert!(epochs[0].scenario.is_empty());
+        assert_eq!(epochs[1].scenario, Scenario::new().fail(id(6), id(4)));
+        assert_eq!(
+            epochs[2].scenario,
+            Scenario::new()
+                .fail(id(6), id(4))
+                .degrade(id(5), id(7), None, t(10))
+        );
+        assert_eq!(epochs[3].scenario, Scenario::new().fail(id(5), id(7)));
+        assert_eq!(Schedule::new().epochs().len(), 1);
+    }
+
+    #[test]
+    fn scenarios_deserialize() -> anyhow::Result<()> {
+        let json = r#"{"links": [
+            {"a": 4, "b": 6, "state": "Failed"},
+            {"a": 5, "b": 7, "state": {"Degraded": {"extra_delay": 100}}}
+        ]}"#;
+        let scenario: Scenario = serde_json::from_str(json)?;
+        let expected = Scenario::new()
+            .fail(NodeId::new(4), NodeId::new(6))
+            .degrade(NodeId::new(5), NodeId::new(7), None, Nanosecs::new(100));
+        assert_eq!(scenario, expected);
+        Ok(())
+    }
+}
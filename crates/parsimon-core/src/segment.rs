@@ -0,0 +1,417 @@
+//! This module defines [`run_segmented`], which splits a long trace into time segments and runs
+//! the full `Parsimon` pipeline on each segment independently. This allows runs to scale
+//! horizontally beyond per-link parallelism.
+//!
+//! Segments are simulated with some overlap: each segment also simulates the flows that start
+//! shortly before and after its own time window, so that queues aren't artificially empty at its
+//! boundaries. Only flows starting within a segment's window contribute to its delay
+//! distributions.
+//!
+//! Windows are fixed-length by default. With a [merge tolerance](SegmentOpts::merge_tolerance),
+//! they adapt to the trace instead: consecutive fixed-length windows are merged as long as their
+//! offered loads stay close, so steady periods are covered by few well-sampled segments while
+//! shifts in load (e.g., diurnal patterns) start new ones.
+//!
+//! [`run_epochs`] similarly splits a trace into the [epochs](crate::scenario::Epoch) of a
+//! specification's [schedule](crate::scenario::Schedule), simulating each epoch on its own
+//! topology.
+
+use std::ops::Range;
+
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::{
+    cluster::ClusteringAlgo,
+    linksim::LinkSim,
+    network::{DelayNetwork, Flow, Network, NodeId},
+    opts::SimOpts,
+    routing::{BfsRoutes, RoutingAlgo},
+    run::Error,
+    scenario,
+    spec::Spec,
+    units::{Bytes, Nanosecs},
+};
+
+/// Options for segmenting a trace.
+#[derive(Debug, Clone, Copy, typed_builder::TypedBuilder)]
+pub struct SegmentOpts {
+    /// The length of each segment's time window, or the length of the windows that are merged if
+    /// a merge tolerance is set.
+    pub length: Nanosecs,
+    /// If set, consecutive windows are merged while the bytes offered in each stay within this
+    /// fraction of the mean of the windows merged so far, e.g., 0.25 for 25%.
+    #[builder(default, setter(strip_option))]
+    pub merge_tolerance: Option<f64>,
+    /// How far before and after its window each segment simulates flows.
+    #[builder(default)]
+    pub overlap: Nanosecs,
+    /// Whether to run segments in parallel. Otherwise, they are run one after the other.
+    #[builder(default)]
+    pub parallel: bool,
+}
+
+/// A `DelayNetwork` for a time window of a trace.
+#[derive(Debug)]
+pub struct Segment<R = BfsRoutes> {
+    /// The window of flow start times covered by the segment.
+    pub window: Range<Nanosecs>,
+    /// The delay network for the segment.
+    pub delays: DelayNetwork<R>,
+}
+
+/// A time-indexed collection of [segments](Segment), sorted by time.
+#[derive(Debug)]
+pub struct SegmentedDelays<R = BfsRoutes> {
+    segments: Vec<Segment<R>>,
+}
+
+impl<R> SegmentedDelays<R>
+where
+    R: RoutingAlgo,
+{
+    /// Like [`DelayNetwork::predict`], but for a flow starting at time `t`. Returns `None` if no
+    /// segment covers `t`.
+    pub fn predict_at<RNG>(
+        &self,
+        t: Nanosecs,
+        size: Bytes,
+        (src, dst): (NodeId, NodeId),
+        rng: RNG,
+    ) -> Option<Nanosecs>
+    where
+        RNG: Rng,
+    {
+        self.at(t)?.predict(size, (src, dst), rng)
+    }
+}
+
+impl<R> SegmentedDelays<R> {
+    /// Returns the delay network for flows starting at time `t`, if any.
+    pub fn at(&self, t: Nanosecs) -> Option<&DelayNetwork<R>> {
+        let i = self.segments.partition_point(|s| s.window.end <= t);
+        self.segments
+            .get(i)
+            .filter(|s| s.window.contains(&t))
+            .map(|s| &s.delays)
+    }
+
+    /// Returns the segments.
+    pub fn segments(&self) -> &[Segment<R>] {
+        &self.segments
+    }
+
+    /// Returns the number of segments.
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Returns true if there are no segments.
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+}
+
+/// Runs the `Parsimon` pipeline on consecutive time segments of `spec`'s flows, as described in
+/// the [module documentation](self).
+pub fn run_segmented<S, C>(
+    spec: Spec,
+    opts: SimOpts<S>,
+    clusterer: C,
+    segment_opts: SegmentOpts,
+) -> Result<SegmentedDelays, Error>
+where
+    S: LinkSim + Clone + Send + Sync,
+    C: ClusteringAlgo + Sync,
+{
+    assert!(segment_opts.length > Nanosecs::ZERO);
+    let spec = spec.validate()?;
+    if !spec.schedule.is_empty() {
+        return Err(Error::Scheduled);
+    }
+    if !spec.dependencies.is_empty() {
+        return Err(Error::Dependent);
+    }
+    let flows = spec.collect_flows();
+    let windows = windows(&flows, segment_opts);
+    let run_one = |window: &Range<Nanosecs>| {
+        let sim_window = window.start.saturating_sub(segment_opts.overlap)
+            ..window.end.saturating_add(segment_opts.overlap);
+        let flows = flows
+            .iter()
+            .filter(|f| sim_window.contains(&f.start))
+            .cloned()
+            .collect();
+        let mut sims = spec
+            .network
+            .clone()
+            .seeded(opts.seed)
+            .into_simulations(flows);
+        sims.set_origin(spec.origin);
+        sims.cluster(&clusterer);
+        let delays = sims.into_delays_within(opts.clone(), window.clone())?;
+        Result::<_, Error>::Ok(Segment {
+            window: window.clone(),
+            delays,
+        })
+    };
+    let segments = if segment_opts.parallel {
+        windows
+            .par_iter()
+            .map(run_one)
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        windows.iter().map(run_one).collect::<Result<Vec<_>, _>>()?
+    };
+    Ok(SegmentedDelays { segments })
+}
+
+/// Runs the `Parsimon` pipeline once per epoch of `spec`'s schedule, as described in the
+/// [module documentation](self). Each epoch simulates the flows starting within it on the
+/// topology in effect at its start, so its links begin with empty queues. It is an error for a
+/// flow's endpoints to be disconnected during its epoch.
+pub fn run_epochs<S, C>(
+    spec: Spec,
+    opts: SimOpts<S>,
+    clusterer: C,
+) -> Result<SegmentedDelays, Error>
+where
+    S: LinkSim + Clone + Sync,
+    C: ClusteringAlgo,
+{
+    let spec = spec.validate()?;
+    if !spec.dependencies.is_empty() {
+        return Err(Error::Dependent);
+    }
+    let nodes = spec.network.nodes().cloned().collect::<Vec<_>>();
+    let links = spec.network.links().copied().collect::<Vec<_>>();
+    let mut segments = Vec::new();
+    for epoch in spec.schedule.epochs() {
+        let flows = spec
+            .flows
+            .iter()
+            .filter(|f| epoch.window.contains(&f.start))
+            .cloned()
+            .collect::<Vec<_>>();
+        let epoch_links = epoch.scenario.apply(&links)?;
+        let network = Network::new_with_layout(&nodes, &epoch_links, spec.network.route_layout())
+            .and_then(|network| network.with_packet_profile(spec.network.packet_profile()))
+            .map_err(scenario::ScenarioError::from)?
+            .with_ecmp(spec.network.ecmp().clone())
+            .seeded(opts.seed.or(spec.network.seed()));
+        scenario::check_connected(&network, &flows)?;
+        let mut sims = network.into_simulations(flows);
+        sims.set_origin(spec.origin);
+        sims.cluster(&clusterer);
+        segments.push(Segment {
+            window: epoch.window,
+            delays: sims.into_delays(opts.clone())?,
+        });
+    }
+    Ok(SegmentedDelays { segments })
+}
+
+/// Returns the segment windows covering all flow start times.
+fn windows(flows: &[Flow], opts: SegmentOpts) -> Vec<Range<Nanosecs>> {
+    let Some(last) = flows.iter().map(|f| f.start).max() else {
+        return Vec::new();
+    };
+    let nr_segments = last.into_u64() / opts.length.into_u64() + 1;
+    let windows = (0..nr_segments).map(|i| {
+        let start = opts.length.checked_mul(i).unwrap();
+        start..start.saturating_add(opts.length)
+    });
+    let Some(tolerance) = opts.merge_tolerance else {
+        return windows.collect();
+    };
+    let mut bytes = vec![0; nr_segments as usize];
+    for f in flows {
+        bytes[(f.start.into_u64() / opts.length.into_u64()) as usize] += f.size.into_u64();
+    }
+    // (window, total bytes, number of merged windows)
+    let mut merged: Vec<(Range<Nanosecs>, u64, u64)> = Vec::new();
+    for (window, bytes) in windows.zip(bytes) {
+        match merged.last_mut() {
+            Some((w, total, n))
+                if (bytes as f64 - *total as f64 / *n as f64).abs()
+                    <= tolerance * *total as f64 / *n as f64 =>
+            {
+                w.end = window.end;
+                *total += bytes;
+                *n += 1;
+            }
+            _ => merged.push((window, bytes, 1)),
+        }
+    }
+    merged.into_iter().map(|(w, _, _)| w).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cluster::DefaultClustering,
+        network::{FlowId, NodeId},
+        scenario::Schedule,
+        testing::{self, FixedDelaySim},
+        units::Bytes,
+    };
+
+    fn flows(starts: &[u64]) -> Vec<Flow> {
+        starts
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(i % 2),
+                dst: NodeId::new(1 - i % 2),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(start),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn windows_cover_all_flows() {
+        let opts = SegmentOpts::builder().length(Nanosecs::new(100)).build();
+        assert!(windows(&[], opts).is_empty());
+        let ws = windows(&flows(&[0, 150, 200]), opts);
+        assert_eq!(
+            ws,
+            vec![
+                Nanosecs::new(0)..Nanosecs::new(100),
+                Nanosecs::new(100)..Nanosecs::new(200),
+                Nanosecs::new(200)..Nanosecs::new(300),
+            ]
+        );
+    }
+
+    #[test]
+    fn windows_adapt_to_load() {
+        // Busy for three windows, then quiet for two, then busy again
+        let starts = [0, 50, 100, 150, 200, 250, 300, 450, 500, 550];
+        let opts = SegmentOpts::builder()
+            .length(Nanosecs::new(100))
+            .merge_tolerance(0.25)
+            .build();
+        let ws = windows(&flows(&starts), opts);
+        assert_eq!(
+            ws,
+            vec![
+                Nanosecs::new(0)..Nanosecs::new(300),
+                Nanosecs::new(300)..Nanosecs::new(500),
+                Nanosecs::new(500)..Nanosecs::new(600),
+            ]
+        );
+    }
+
+    #[test]
+    fn segments_are_time_indexed() -> anyhow::Result<()> {
+        let (nodes, links) = testing::three_node_config();
+        let spec = Spec::builder()
+            .nodes(nodes)
+            .links(links)
+            .flows(flows(&[0, 10, 150, 160, 420]))
+            .build();
+        let opts = SimOpts::builder()
+            .link_sim(FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let segment_opts = SegmentOpts::builder()
+            .length(Nanosecs::new(100))
+            .overlap(Nanosecs::new(50))
+            .parallel(true)
+            .build();
+        let delays = run_segmented(spec, opts, DefaultClustering, segment_opts)?;
+        assert_eq!(delays.len(), 5);
+        assert_eq!(
+            delays.segments()[3].window,
+            Nanosecs::new(300)..Nanosecs::new(400)
+        );
+        assert!(delays.at(Nanosecs::new(420)).is_some());
+        assert!(delays.at(Nanosecs::new(500)).is_none());
+        // Segment 2 simulates flows from segment 1 as part of its overlap, but none of its own
+        let mut rng = rand::thread_rng();
+        let path = (NodeId::new(0), NodeId::new(1));
+        let mut sample = |t| {
+            delays
+                .at(Nanosecs::new(t))
+                .unwrap()
+                .predict(Bytes::new(1000), path, &mut rng)
+        };
+        assert_eq!(sample(0), Some(Nanosecs::new(200)));
+        assert_eq!(sample(250), Some(Nanosecs::ZERO));
+        assert_eq!(
+            delays.predict_at(Nanosecs::new(0), Bytes::new(1000), path, &mut rng),
+            Some(Nanosecs::new(200))
+        );
+        assert!(delays
+            .predict_at(Nanosecs::new(500), Bytes::new(1000), path, &mut rng)
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn epochs_follow_the_schedule() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let id = NodeId::new;
+        let flows = (0..4)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: id(0),
+                dst: id(2),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 100),
+            })
+            .collect();
+        let spec = Spec::builder()
+            .nodes(nodes)
+            .links(links)
+            .flows(flows)
+            .schedule(
+                Schedule::new()
+                    .link_down(Nanosecs::new(150), id(4), id(6))
+                    .link_up(Nanosecs::new(250), id(4), id(6)),
+            )
+            .build();
+        let opts = SimOpts::builder()
+            .link_sim(FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let delays = run_epochs(spec, opts, DefaultClustering)?;
+        assert_eq!(delays.len(), 3);
+        let (up, down) = (id(4), id(6));
+        let has_link = |t, a, b| {
+            delays
+                .at(Nanosecs::new(t))
+                .unwrap()
+                .links()
+                .any(|l| l.connects(a, b))
+        };
+        assert!(has_link(0, up, down));
+        assert!(!has_link(200, up, down));
+        assert!(has_link(1_000_000, up, down));
+        Ok(())
+    }
+
+    #[test]
+    fn scheduled_specs_need_epochs() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let spec = Spec::builder()
+            .nodes(nodes)
+            .links(links)
+            .flows(Vec::new())
+            .schedule(Schedule::new().link_down(Nanosecs::new(10), NodeId::new(4), NodeId::new(6)))
+            .build();
+        let opts = SimOpts::builder()
+            .link_sim(FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let res = crate::run::run(spec, opts, DefaultClustering);
+        assert!(matches!(res, Err(Error::Scheduled)));
+        Ok(())
+    }
+}
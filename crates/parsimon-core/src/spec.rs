@@ -2,12 +2,19 @@
 //! flows. `Parsimon` turns a specification into a [`DelayNetwork`](crate::network::DelayNetwork),
 //! which can be queried for FCT delay estimates.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
+use crate::client::{ClientId, ClientMap};
+use crate::dependency::{self, FlowDependency};
+use crate::feasibility::{self, FeasibilityOpts, FeasibilityReport};
 use crate::network::{
     types::{Link, Node, NodeId},
-    Flow, FlowId, Network, NodeKind, TopologyError,
+    Flow, FlowId, Network, NodeKind, SimNetwork, TopologyError,
 };
+use crate::routing::RouteLayout;
+use crate::scenario::{Scenario, ScenarioError, Schedule};
+use crate::transform::{TraceTransform, TransformError};
+use crate::units::Nanosecs;
 
 /// A simulation specification.
 #[derive(Debug, typed_builder::TypedBuilder)]
@@ -18,6 +25,35 @@ pub struct Spec {
     pub links: Vec<Link>,
     /// Workload flows.
     pub flows: Vec<Flow>,
+    /// Link failures and degradations to apply to the topology.
+    #[builder(default)]
+    pub scenario: Scenario,
+    /// Link events scheduled over the course of the trace. These apply on top of `scenario`.
+    #[builder(default)]
+    pub schedule: Schedule,
+    /// How routing tables are laid out. [Compact](RouteLayout::Compact) tables are meant for
+    /// topologies too large for a table of every pair of nodes.
+    #[builder(default)]
+    pub routing: RouteLayout,
+    /// The clients that flows belong to. If any flows are tagged, delay distributions are also
+    /// kept for each client.
+    #[builder(default)]
+    pub clients: ClientMap,
+    /// How validation checks whether the workload [overloads](crate::feasibility) a host or link
+    /// for a sustained period. By default, overloads are logged as warnings; they fail validation
+    /// only if [enforced](FeasibilityOpts::enforce). If `None`, the check is skipped.
+    #[builder(default = Some(FeasibilityOpts::default()), setter(strip_option))]
+    pub feasibility: Option<FeasibilityOpts>,
+    /// The absolute time (e.g., an epoch timestamp in nanoseconds) flow start times and scheduled
+    /// events are measured from. If unset, it is the earliest flow start time. Times are
+    /// normalized to be relative to the origin internally and restored on export; see
+    /// [`SimNetwork::set_origin`](crate::network::SimNetwork::set_origin).
+    #[builder(default, setter(strip_option))]
+    pub origin: Option<Nanosecs>,
+    /// Flows which start only after other flows finish. Specifications with dependencies must be
+    /// run with [`run_dependent`](crate::dependency::run_dependent).
+    #[builder(default)]
+    pub dependencies: Vec<FlowDependency>,
 }
 
 impl Spec {
@@ -26,7 +62,25 @@ impl Spec {
     /// Correctness properties:
     ///
     /// - Every flow must have a valid source and destination
-    // TODO: Flow IDs should be unique
+    /// - Flow IDs must be unique
+    /// - Every scheduled event must refer to an existing link
+    /// - Every flow tagged with a client must exist
+    /// - If [`Spec::feasibility`] is enforced, the workload must be feasible
+    /// - No flow may start before [`Spec::origin`]
+    /// - Every dependency must refer to existing flows, and dependencies must be acyclic
+    ///
+    /// Flow start times are normalized to be relative to the origin, so that the earliest flow
+    /// starts at time zero by default. This keeps time arithmetic far from overflow when traces
+    /// carry absolute (e.g., epoch) timestamps.
+    #[tracing::instrument(
+        name = "validate_spec",
+        skip_all,
+        fields(
+            nr_nodes = self.nodes.len(),
+            nr_links = self.links.len(),
+            nr_flows = self.flows.len(),
+        )
+    )]
     pub(crate) fn validate(self) -> Result<ValidSpec, SpecError> {
         let hosts = self
             .nodes
@@ -45,12 +99,175 @@ impl Spec {
                 return Err(SpecError::InvalidFlowDst { flow: id, dst });
             }
         }
-        let network = Network::new(&self.nodes, &self.links)?;
+        // CORRECTNESS: Flow IDs must be unique.
+        let mut positions = HashMap::with_capacity(self.flows.len());
+        for (i, f) in self.flows.iter().enumerate() {
+            if let Some(first) = positions.insert(f.id, i) {
+                return Err(SpecError::DuplicateFlowId {
+                    flow: f.id,
+                    first,
+                    second: i,
+                });
+            }
+        }
+        // CORRECTNESS: Every dependency must refer to existing flows, and dependencies must be
+        // acyclic.
+        dependency::validate(&self.flows, &self.dependencies)?;
+        // CORRECTNESS: Every flow tagged with a client must exist.
+        if !self.clients.is_empty() {
+            let ids = self.flows.iter().map(|f| f.id).collect::<HashSet<_>>();
+            if let Some((flow, client)) = self.clients.iter().find(|(f, _)| !ids.contains(f)) {
+                return Err(SpecError::UnknownClientFlow { flow, client });
+            }
+        }
+        let links = self.scenario.apply(&self.links)?;
+        let network = Network::new_with_layout(&self.nodes, &links, self.routing)?;
+        // Normalize start times to a run-relative origin.
+        let earliest = self.flows.iter().min_by_key(|f| f.start);
+        let origin = match (self.origin, earliest) {
+            // CORRECTNESS: No flow may start before the origin.
+            (Some(origin), Some(f)) if f.start < origin => {
+                return Err(SpecError::StartsBeforeOrigin {
+                    flow: f.id,
+                    start: f.start,
+                    origin,
+                });
+            }
+            (Some(origin), _) => origin,
+            (None, earliest) => earliest.map_or(Nanosecs::ZERO, |f| f.start),
+        };
+        let flows = self
+            .flows
+            .into_iter()
+            .map(|f| Flow {
+                start: f.start - origin,
+                ..f
+            })
+            .collect::<Vec<_>>();
+        // CORRECTNESS: If enforced, the workload must be feasible.
+        if let Some(opts) = &self.feasibility {
+            let report = feasibility::check(&network, &flows, opts);
+            if opts.enforce && !report.is_feasible() {
+                return Err(SpecError::Infeasible(Box::new(report)));
+            }
+            for warning in &report.warnings {
+                tracing::warn!(%warning, "infeasible workload");
+            }
+        }
+        // CORRECTNESS: Every scheduled event must refer to an existing link.
+        let schedule = self.schedule.shifted(origin);
+        for epoch in schedule.epochs() {
+            epoch.scenario.apply(&links)?;
+        }
         Ok(ValidSpec {
             network,
-            flows: self.flows,
+            flows,
+            schedule,
+            clients: self.clients,
+            origin,
+            dependencies: self.dependencies,
         })
     }
+
+    /// Checks that the specification can be run, e.g., that every flow has valid endpoints and a
+    /// unique ID, that the scenario and schedule refer to existing links, and that the workload is
+    /// feasible if [`Spec::feasibility`] is enforced.
+    pub fn check(self) -> Result<(), SpecError> {
+        self.validate().map(drop)
+    }
+
+    /// Checks the specification like [`Spec::check`] and routes its flows, e.g., to cluster the
+    /// links and inspect the clusters without simulating anything.
+    pub fn into_simulations(self) -> Result<SimNetwork, SpecError> {
+        let spec = self.validate()?;
+        let mut sims = spec.network.into_simulations(spec.flows);
+        sims.set_origin(spec.origin);
+        Ok(sims)
+    }
+
+    /// Applies `transform` to the flows, e.g., to speed up arrivals for a sweep over load levels.
+    /// Flows dropped by truncation also lose their [client](Spec::clients) tags and
+    /// [dependencies](Spec::dependencies). The [origin](Spec::origin) and
+    /// [schedule](Spec::schedule) are left as they are, so they should be given in terms of the
+    /// transformed trace. Fails, leaving the specification unchanged, if the transform is
+    /// [invalid](TraceTransform::validate).
+    pub fn transform_trace(&mut self, transform: &TraceTransform) -> Result<(), TransformError> {
+        if transform.is_identity() {
+            return Ok(());
+        }
+        // Validate before the flows are taken, so that they're kept on failure
+        transform.validate()?;
+        let nr_flows = self.flows.len();
+        self.flows = transform.apply(std::mem::take(&mut self.flows))?;
+        if self.flows.len() == nr_flows {
+            return Ok(());
+        }
+        let kept = self.flows.iter().map(|f| f.id).collect::<HashSet<_>>();
+        if !self.clients.is_empty() {
+            let mut clients = ClientMap::new();
+            for (flow, client) in self.clients.iter().filter(|(f, _)| kept.contains(f)) {
+                clients.tag(flow, client);
+            }
+            self.clients = clients;
+        }
+        self.dependencies
+            .retain(|d| kept.contains(&d.flow) && kept.contains(&d.after));
+        Ok(())
+    }
+
+    /// Assigns contiguous flow IDs `0..n` to the flows, in their current order, and retags
+    /// [clients](Spec::clients) accordingly. This is for traces whose flow IDs are arbitrary or not
+    /// unique. The returned mapping translates the new IDs back to the original ones.
+    ///
+    /// If several flows shared an ID, every one of them keeps that ID's client tag.
+    pub fn renumber_flows(&mut self) -> FlowRenumbering {
+        let originals = self.flows.iter().map(|f| f.id).collect::<Vec<_>>();
+        let mut clients = ClientMap::new();
+        for (i, (f, &original)) in self.flows.iter_mut().zip(&originals).enumerate() {
+            f.id = FlowId::new(i);
+            if let Some(client) = self.clients.client_of(original) {
+                clients.tag(f.id, client);
+            }
+        }
+        if !self.clients.is_empty() {
+            self.clients = clients;
+        }
+        FlowRenumbering { originals }
+    }
+}
+
+/// A mapping from flow IDs assigned by [`Spec::renumber_flows`] to the original ones.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FlowRenumbering {
+    // Indexed by new ID
+    originals: Vec<FlowId>,
+}
+
+impl FlowRenumbering {
+    /// Returns the original ID of the flow with the new ID `id`, or `None` if no flow was assigned
+    /// `id`.
+    pub fn original(&self, id: FlowId) -> Option<FlowId> {
+        self.originals.get(id.inner()).copied()
+    }
+
+    /// Returns an iterator over new IDs and the corresponding original IDs, in ascending order of
+    /// new ID.
+    pub fn iter(&self) -> impl Iterator<Item = (FlowId, FlowId)> + '_ {
+        self.originals
+            .iter()
+            .enumerate()
+            .map(|(i, &original)| (FlowId::new(i), original))
+    }
+
+    /// Returns the number of renumbered flows.
+    pub fn len(&self) -> usize {
+        self.originals.len()
+    }
+
+    /// Returns true if no flows were renumbered.
+    pub fn is_empty(&self) -> bool {
+        self.originals.is_empty()
+    }
 }
 
 /// A `ValidSpec` is a `Spec` that has been validated. The topology and the
@@ -60,6 +277,10 @@ impl Spec {
 pub(crate) struct ValidSpec {
     pub(crate) network: Network,
     pub(crate) flows: Vec<Flow>,
+    pub(crate) schedule: Schedule,
+    pub(crate) clients: ClientMap,
+    pub(crate) origin: Nanosecs,
+    pub(crate) dependencies: Vec<FlowDependency>,
 }
 
 impl ValidSpec {
@@ -89,15 +310,70 @@ pub enum SpecError {
         dst: NodeId,
     },
 
+    /// Two flows have the same ID.
+    #[error(
+        "flows {first} and {second} both have ID {flow} (use `Spec::renumber_flows` to assign unique IDs)"
+    )]
+    DuplicateFlowId {
+        /// The duplicated ID.
+        flow: FlowId,
+        /// The position of the first flow with the ID.
+        first: usize,
+        /// The position of the second flow with the ID.
+        second: usize,
+    },
+
+    /// A flow tagged with a client doesn't exist.
+    #[error("flow {flow} is tagged with client {client} but doesn't exist")]
+    UnknownClientFlow {
+        /// The flow ID.
+        flow: FlowId,
+        /// The client ID.
+        client: ClientId,
+    },
+
+    /// A flow starts before the spec's origin.
+    #[error("flow {flow} starts at {start}, before the origin ({origin})")]
+    StartsBeforeOrigin {
+        /// The flow ID.
+        flow: FlowId,
+        /// The flow's start time.
+        start: Nanosecs,
+        /// The origin.
+        origin: Nanosecs,
+    },
+
+    /// A flow dependency refers to a flow that doesn't exist.
+    #[error("flow dependency refers to flow {flow}, which doesn't exist")]
+    UnknownDependencyFlow {
+        /// The flow ID.
+        flow: FlowId,
+    },
+
+    /// Flow dependencies form a cycle.
+    #[error("flow {flow} depends on itself through a cycle of dependencies")]
+    CyclicDependency {
+        /// A flow on or downstream of the cycle.
+        flow: FlowId,
+    },
+
+    /// The workload overloads a host or link for a sustained period.
+    #[error("infeasible workload: {0}")]
+    Infeasible(Box<FeasibilityReport>),
+
     /// The topology is invalid.
     #[error("invalid topology")]
     InvalidTopology(#[from] TopologyError),
+
+    /// The scenario can't be applied to the topology.
+    #[error("invalid scenario")]
+    InvalidScenario(#[from] ScenarioError),
 }
 
 #[cfg(test)]
 mod tests {
     use crate::testing;
-    use crate::units::{Bytes, Nanosecs};
+    use crate::units::Bytes;
 
     use super::*;
 
@@ -141,6 +417,136 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn validate_normalizes_start_times() {
+        let mut spec = spec();
+        let epoch = Nanosecs::new(1_700_000_000_000_000_000);
+        spec.flows[0].start = epoch + Nanosecs::new(10);
+        spec.flows.push(Flow {
+            id: FlowId::new(1),
+            start: epoch + Nanosecs::new(25),
+            ..spec.flows[0]
+        });
+        let spec = spec.validate().unwrap();
+        let starts = spec.flows.iter().map(|f| f.start).collect::<Vec<_>>();
+        assert_eq!(starts, vec![Nanosecs::ZERO, Nanosecs::new(15)]);
+    }
+
+    #[test]
+    fn explicit_origins_are_kept() {
+        let epoch = Nanosecs::new(1_700_000_000_000_000_000);
+        let spec_with_origin = |origin| {
+            let mut spec = spec();
+            spec.flows[0].start = epoch + Nanosecs::new(10);
+            spec.origin = Some(origin);
+            spec
+        };
+        let valid = spec_with_origin(epoch).validate().unwrap();
+        assert_eq!(valid.origin, epoch);
+        assert_eq!(valid.flows[0].start, Nanosecs::new(10));
+        let spec = spec_with_origin(epoch + Nanosecs::new(11));
+        assert!(matches!(
+            spec.validate(),
+            Err(SpecError::StartsBeforeOrigin { .. })
+        ));
+    }
+
+    #[test]
+    fn scheduled_events_are_validated() {
+        let mut spec = spec();
+        spec.schedule =
+            Schedule::new().link_down(Nanosecs::new(10), NodeId::new(0), NodeId::new(1));
+        assert!(matches!(
+            spec.validate(),
+            Err(SpecError::InvalidScenario(
+                ScenarioError::UnknownLink { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn unknown_client_flows_fail() {
+        let mut spec = spec();
+        spec.clients.tag(FlowId::new(100), ClientId::new(1));
+        assert!(matches!(
+            spec.validate(),
+            Err(SpecError::UnknownClientFlow { .. })
+        ));
+    }
+
+    #[test]
+    fn duplicate_flow_ids_fail() {
+        let mut spec = spec();
+        spec.flows.push(spec.flows[0]);
+        assert!(matches!(
+            spec.validate(),
+            Err(SpecError::DuplicateFlowId {
+                first: 0,
+                second: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn renumbered_flows_validate() {
+        let mut spec = spec();
+        let dup = Flow {
+            id: FlowId::new(usize::MAX),
+            ..spec.flows[0]
+        };
+        spec.flows.extend([dup, dup]);
+        spec.clients.tag(dup.id, ClientId::new(7));
+        let renumbering = spec.renumber_flows();
+        assert_eq!(renumbering.len(), 3);
+        assert_eq!(renumbering.original(FlowId::new(0)), Some(FlowId::new(0)));
+        assert_eq!(renumbering.original(FlowId::new(2)), Some(dup.id));
+        assert_eq!(renumbering.original(FlowId::new(3)), None);
+        assert_eq!(
+            spec.clients.client_of(FlowId::new(1)),
+            Some(ClientId::new(7))
+        );
+        assert_eq!(
+            spec.clients.client_of(FlowId::new(2)),
+            Some(ClientId::new(7))
+        );
+        assert!(spec.validate().is_ok());
+    }
+
+    #[test]
+    fn routing_layout_carries_over() -> anyhow::Result<()> {
+        let mut spec = spec();
+        spec.routing = RouteLayout::Compact;
+        let valid = spec.validate()?;
+        assert_eq!(valid.network.route_layout(), RouteLayout::Compact);
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_flows_lose_tags() {
+        let mut spec = spec();
+        let late = Flow {
+            id: FlowId::new(1),
+            start: Nanosecs::new(1_000),
+            ..spec.flows[0]
+        };
+        spec.flows.push(late);
+        spec.clients.tag(late.id, ClientId::new(1));
+        spec.dependencies.push(FlowDependency {
+            flow: late.id,
+            after: FlowId::new(0),
+            gap: Nanosecs::ZERO,
+        });
+        let transform = TraceTransform::builder()
+            .window(Nanosecs::ZERO..Nanosecs::new(1_000))
+            .build();
+        spec.transform_trace(&transform).unwrap();
+        assert_eq!(spec.flows.len(), 1);
+        assert!(spec.clients.is_empty());
+        assert!(spec.dependencies.is_empty());
+        assert!(spec.validate().is_ok());
+    }
+
     fn spec() -> Spec {
         let (nodes, links) = testing::eight_node_config();
         let flows = flows();
@@ -148,6 +554,13 @@ mod tests {
             nodes,
             links,
             flows,
+            scenario: Scenario::default(),
+            schedule: Schedule::default(),
+            routing: RouteLayout::default(),
+            clients: ClientMap::default(),
+            feasibility: None,
+            origin: None,
+            dependencies: Vec::new(),
         }
     }
 
@@ -0,0 +1,245 @@
+//! This module defines a [`Store`], which persists the results of runs in a SQLite database for
+//! post-hoc analysis with SQL. It is only available with the `sqlite` feature.
+//!
+//! With a store path set in [`SimOpts`](crate::opts::SimOpts), every conversion of a
+//! [`SimNetwork`](crate::network::SimNetwork) into a [`DelayNetwork`] appends a new run to the
+//! database, so a single database can accumulate the results of many runs. The schema is:
+//!
+//! - `runs(run, created, link_sim)`: one row per run. `created` is an RFC 3339 timestamp.
+//! - `edges(run, edge, src, dst, bandwidth, delay, loss_rate, representative)`: one row per edge.
+//!   `representative` is the simulated edge whose results filled the edge, if any.
+//! - `records(run, edge, flow, size, start, fct, ideal)`: the FCT records of every simulated edge.
+//! - `buckets(run, edge, size_lo, size_hi, nr_samples, mean, p50, p99)`: summaries of every edge's
+//!   delay distributions. `size_hi` is `NULL` for the last bucket, and delays are
+//!   packet-normalized.
+//!
+//! Bandwidths are in bits per second, and all times are in nanoseconds. Record start times are
+//! absolute, i.e., offset by the network's [origin](crate::network::SimNetwork::set_origin).
+
+use std::{collections::HashMap, path::Path};
+
+use rusqlite::{params, Connection};
+
+use crate::{
+    cluster::Cluster,
+    network::{DelayNetwork, EdgeIndex, FctRecord, TraversableNetwork},
+    routing::RoutingAlgo,
+    units::Bytes,
+};
+
+pub use rusqlite;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        run INTEGER PRIMARY KEY,
+        created TEXT NOT NULL,
+        link_sim TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS edges (
+        run INTEGER NOT NULL REFERENCES runs (run),
+        edge INTEGER NOT NULL,
+        src INTEGER NOT NULL,
+        dst INTEGER NOT NULL,
+        bandwidth INTEGER NOT NULL,
+        delay INTEGER NOT NULL,
+        loss_rate REAL NOT NULL,
+        representative INTEGER,
+        PRIMARY KEY (run, edge)
+    );
+    CREATE TABLE IF NOT EXISTS records (
+        run INTEGER NOT NULL REFERENCES runs (run),
+        edge INTEGER NOT NULL,
+        flow INTEGER NOT NULL,
+        size INTEGER NOT NULL,
+        start INTEGER NOT NULL,
+        fct INTEGER NOT NULL,
+        ideal INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS records_by_edge ON records (run, edge);
+    CREATE TABLE IF NOT EXISTS buckets (
+        run INTEGER NOT NULL REFERENCES runs (run),
+        edge INTEGER NOT NULL,
+        size_lo INTEGER NOT NULL,
+        size_hi INTEGER,
+        nr_samples INTEGER NOT NULL,
+        mean REAL,
+        p50 REAL,
+        p99 REAL
+    );
+";
+
+/// A SQLite database of run results.
+#[derive(Debug)]
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    /// Opens the database at `path`, creating it and its schema if necessary.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Returns the underlying connection, e.g., to run queries.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Returns the IDs of all runs in the database, in the order they were appended.
+    pub fn runs(&self) -> Result<Vec<i64>, StoreError> {
+        let mut stmt = self.conn.prepare("SELECT run FROM runs ORDER BY run")?;
+        let runs = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(runs)
+    }
+
+    /// Appends the results of a run, returning its ID.
+    pub(crate) fn append<R>(
+        &mut self,
+        link_sim: &str,
+        delays: &DelayNetwork<R>,
+        clusters: &[Cluster],
+        eidx2data: &HashMap<EdgeIndex, Vec<FctRecord>>,
+    ) -> Result<i64, StoreError>
+    where
+        R: RoutingAlgo,
+    {
+        let representatives = clusters
+            .iter()
+            .flat_map(|c| c.members().map(|&m| (m, c.representative())))
+            .collect::<HashMap<_, _>>();
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO runs (created, link_sim) VALUES (?1, ?2)",
+            params![chrono::Utc::now().to_rfc3339(), link_sim],
+        )?;
+        let run = tx.last_insert_rowid();
+        {
+            let mut insert_edge = tx.prepare(
+                "INSERT INTO edges (run, edge, src, dst, bandwidth, delay, loss_rate, representative)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            let mut insert_bucket = tx.prepare(
+                "INSERT INTO buckets (run, edge, size_lo, size_hi, nr_samples, mean, p50, p99)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for eidx in delays.topology().graph.edge_indices() {
+                let chan = delays.summarize_channel(eidx);
+                insert_edge.execute(params![
+                    run,
+                    eidx.index(),
+                    chan.src.inner(),
+                    chan.dst.inner(),
+                    chan.bandwidth.into_u64(),
+                    chan.delay.into_u64(),
+                    chan.loss_rate,
+                    representatives.get(&eidx).map(|r| r.index()),
+                ])?;
+                for bucket in chan.buckets {
+                    let size_hi =
+                        (bucket.range.end != Bytes::MAX).then(|| bucket.range.end.into_u64());
+                    insert_bucket.execute(params![
+                        run,
+                        eidx.index(),
+                        bucket.range.start.into_u64(),
+                        size_hi,
+                        bucket.nr_samples,
+                        bucket.mean,
+                        bucket.p50,
+                        bucket.p99,
+                    ])?;
+                }
+            }
+            let mut insert_record = tx.prepare(
+                "INSERT INTO records (run, edge, flow, size, start, fct, ideal)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            for (eidx, records) in eidx2data {
+                for rec in records {
+                    insert_record.execute(params![
+                        run,
+                        eidx.index(),
+                        rec.id.inner(),
+                        rec.size.into_u64(),
+                        (rec.start + delays.origin()).into_u64(),
+                        rec.fct.into_u64(),
+                        rec.ideal.into_u64(),
+                    ])?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(run)
+    }
+}
+
+/// Error type for [`Store`]s.
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    /// SQLite error.
+    #[error("SQLite error")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::{Flow, FlowId, Network, NodeId},
+        opts::SimOpts,
+        testing::{self, FixedDelaySim},
+        units::Nanosecs,
+    };
+
+    #[test]
+    fn runs_are_appended() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("results.db");
+        let (nodes, links) = testing::three_node_config();
+        let flows = (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows);
+        for _ in 0..2 {
+            let opts = SimOpts::builder()
+                .link_sim(FixedDelaySim {
+                    delay_per_pkt: Nanosecs::new(100),
+                })
+                .store(path.clone())
+                .build();
+            sims.clone().into_delays(opts)?;
+        }
+
+        let store = Store::open(&path)?;
+        assert_eq!(store.runs()?, [1, 2]);
+        let conn = store.connection();
+        let count = |sql: &str| conn.query_row(sql, [], |row| row.get::<_, i64>(0));
+        assert_eq!(
+            count("SELECT COUNT(*) FROM edges WHERE run = 2")?,
+            2 * links.len() as i64
+        );
+        // Each flow crosses two edges
+        assert_eq!(count("SELECT COUNT(*) FROM records WHERE run = 1")?, 20);
+        assert_eq!(
+            count("SELECT SUM(nr_samples) FROM buckets WHERE run = 1")?,
+            20
+        );
+        assert_eq!(
+            count(
+                "SELECT COUNT(*) FROM records r JOIN edges e USING (run, edge)
+                 WHERE e.src = 0 AND e.dst = 2"
+            )?,
+            20
+        );
+        Ok(())
+    }
+}
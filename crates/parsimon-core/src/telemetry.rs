@@ -0,0 +1,58 @@
+//! Instrumentation of the pipeline with [`tracing`] spans.
+//!
+//! The pipeline records spans for spec validation (`validate_spec`), flow tracing
+//! (`trace_flows`), clustering (`cluster`), simulation (`simulate`, with a `link_sim` span per
+//! cluster representative and a `batch` span per batch sent to a worker), and bucketing
+//! (`fill_delays`). Spans carry the link simulator backend, edge indices, and counts as fields, and
+//! any `tracing` subscriber can report their timing.
+//!
+//! To export spans to OpenTelemetry, add a `tracing-opentelemetry` layer to the subscriber. With
+//! the `otel` feature, the context of every `batch` span is also sent to its worker with the
+//! global text map propagator (e.g., the W3C trace context propagator), so that the spans of
+//! remote link simulations have the right parent. Without the `otel` feature, no context is sent.
+
+use std::collections::HashMap;
+
+/// A serialized trace context, sent from the coordinator to workers so that worker spans have the
+/// right parent. Empty if tracing contexts aren't propagated.
+pub type TraceContext = HashMap<String, String>;
+
+/// Returns the context of `span`, to be sent to a remote process.
+pub fn context_of(span: &tracing::Span) -> TraceContext {
+    imp::context_of(span)
+}
+
+/// Makes the span whose context is `cx`, e.g., the coordinator's batch span on a worker, the
+/// parent of `span`.
+pub fn set_remote_parent(span: &tracing::Span, cx: &TraceContext) {
+    imp::set_remote_parent(span, cx)
+}
+
+#[cfg(feature = "otel")]
+mod imp {
+    use opentelemetry::global;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    use super::TraceContext;
+
+    pub(super) fn context_of(span: &tracing::Span) -> TraceContext {
+        let mut cx = TraceContext::new();
+        global::get_text_map_propagator(|p| p.inject_context(&span.context(), &mut cx));
+        cx
+    }
+
+    pub(super) fn set_remote_parent(span: &tracing::Span, cx: &TraceContext) {
+        span.set_parent(global::get_text_map_propagator(|p| p.extract(cx)));
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+mod imp {
+    use super::TraceContext;
+
+    pub(super) fn context_of(_span: &tracing::Span) -> TraceContext {
+        TraceContext::new()
+    }
+
+    pub(super) fn set_remote_parent(_span: &tracing::Span, _cx: &TraceContext) {}
+}
@@ -1,7 +1,15 @@
 //! Utilities for writing tests.
 
+pub mod corpus;
+pub mod worker;
+
+use std::sync::Arc;
+
+use crate::linksim::{params_of, DynLinkSim, LinkSim, LinkSimResult, LinkSimSpec};
 use crate::network::types::{Link, Node, NodeId};
-use crate::units::{Gbps, Nanosecs};
+use crate::network::{DelayNetwork, FctRecord, Flow, FlowId, Network};
+use crate::opts::SimOpts;
+use crate::units::{Bytes, Gbps, Nanosecs};
 
 /// Generate a configuration with two hosts connected by a switch.
 ///
@@ -36,3 +44,93 @@ pub fn eight_node_config() -> (Vec<Node>, Vec<Link>) {
     ];
     (nodes, links)
 }
+
+/// Generate a configuration with four hosts (IDs 0-3), each dual-homed to two ToR switches (IDs
+/// 4-5), which are both connected to a single agg switch (ID 6).
+///
+/// Links are 10 Gbps with a 1 us propagation delay.
+pub fn dual_homed_config() -> (Vec<Node>, Vec<Link>) {
+    let hosts = (0..=3).map(|i| Node::new_host(NodeId::new(i)));
+    let switches = (4..=6).map(|i| Node::new_switch(NodeId::new(i)));
+    let nodes = hosts.chain(switches).collect::<Vec<_>>();
+    let mut links = Vec::new();
+    for host in 0..=3 {
+        for tor in 4..=5 {
+            links.push(Link::new(
+                nodes[host].id,
+                nodes[tor].id,
+                Gbps::new(10),
+                Nanosecs::new(1000),
+            ));
+        }
+    }
+    for tor in 4..=5 {
+        links.push(Link::new(
+            nodes[tor].id,
+            nodes[6].id,
+            Gbps::new(10),
+            Nanosecs::new(1000),
+        ));
+    }
+    (nodes, links)
+}
+
+/// A trivial link simulator for tests. Every flow is delayed by a fixed amount per packet, and
+/// ideal FCTs are zero.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct FixedDelaySim {
+    /// The delay per packet.
+    pub delay_per_pkt: Nanosecs,
+}
+
+impl LinkSim for FixedDelaySim {
+    fn name(&self) -> String {
+        "fixed-delay".into()
+    }
+
+    fn params(&self) -> Option<String> {
+        params_of(self)
+    }
+
+    fn detach(&self) -> Option<DynLinkSim> {
+        Some(Arc::new(*self))
+    }
+
+    fn simulate(&self, spec: LinkSimSpec) -> LinkSimResult {
+        Ok(spec
+            .flows
+            .iter()
+            .map(|f| {
+                let nr_pkts = spec.packets.nr_pkts(f.size) as f64;
+                FctRecord {
+                    id: f.id,
+                    size: f.size,
+                    start: f.start,
+                    fct: self.delay_per_pkt.scale_by(nr_pkts),
+                    ideal: Nanosecs::ZERO,
+                }
+            })
+            .collect())
+    }
+}
+
+/// Builds a delay network on the [eight-node configuration](eight_node_config) from 100 flows of
+/// 1 KB from hosts 0 and 1 to hosts 2 and 3, each delayed by `delay_per_pkt` per packet with a
+/// [`FixedDelaySim`].
+pub fn fixed_delay_network(delay_per_pkt: Nanosecs) -> anyhow::Result<DelayNetwork> {
+    let (nodes, links) = eight_node_config();
+    let flows = (0..100)
+        .map(|i| Flow {
+            id: FlowId::new(i),
+            src: NodeId::new(i % 2),
+            dst: NodeId::new(2 + i % 2),
+            size: Bytes::new(1_000),
+            start: Nanosecs::new(i as u64 * 1_000),
+        })
+        .collect();
+    let sim = FixedDelaySim { delay_per_pkt };
+    let delays = Network::new(&nodes, &links)?
+        .into_simulations(flows)
+        .into_delays(SimOpts::builder().link_sim(sim).build())?;
+    Ok(delays)
+}
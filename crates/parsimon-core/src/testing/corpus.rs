@@ -0,0 +1,290 @@
+//! A corpus of canonical topologies and seeded workloads of increasing scale. Every entry is
+//! generated deterministically with a portable PRNG whose output is stable across platforms and
+//! `rand` releases, so performance and accuracy comparisons across contributors use identical
+//! inputs.
+
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+use crate::network::types::{Flow, FlowId, Link, Node, NodeId};
+use crate::spec::Spec;
+use crate::units::{BitsPerSec, Bytes, Gbps, Nanosecs};
+
+/// Parameters for a three-tier Clos topology. Each pod contains `tors_per_pod` ToR switches and
+/// `aggs_per_pod` aggregation switches, with every ToR connected to every agg in its pod. There
+/// are `aggs_per_pod` core planes, each containing `cores_per_plane` core switches, and the `i`-th
+/// agg of every pod connects to all cores in plane `i`.
+///
+/// Switch-switch links have bandwidth `fabric_bandwidth`, unless an `oversubscription` ratio is
+/// set, in which case uplink bandwidths are derived so that every ToR and agg is oversubscribed by
+/// exactly that ratio. See [`crate::oversubscription`] to check the result.
+///
+/// Node IDs are assigned contiguously: hosts first, then ToRs, aggs, and cores.
+#[derive(Debug, Clone, Copy)]
+pub struct ClosParams {
+    /// The number of pods.
+    pub nr_pods: usize,
+    /// The number of ToR switches per pod.
+    pub tors_per_pod: usize,
+    /// The number of aggregation switches per pod.
+    pub aggs_per_pod: usize,
+    /// The number of core switches per core plane.
+    pub cores_per_plane: usize,
+    /// The number of hosts per ToR.
+    pub hosts_per_tor: usize,
+    /// The bandwidth of host-ToR links.
+    pub host_bandwidth: BitsPerSec,
+    /// The bandwidth of switch-switch links, unless `oversubscription` is set.
+    pub fabric_bandwidth: BitsPerSec,
+    /// The propagation delay of every link.
+    pub delay: Nanosecs,
+    /// The ratio of downlink to uplink bandwidth at every ToR and agg, e.g., 3.0 for 3:1.
+    pub oversubscription: Option<f64>,
+}
+
+impl ClosParams {
+    /// Returns the total number of hosts.
+    pub fn nr_hosts(&self) -> usize {
+        self.nr_pods * self.tors_per_pod * self.hosts_per_tor
+    }
+
+    /// Returns the bandwidth of each ToR-agg link.
+    pub fn tor_uplink_bandwidth(&self) -> BitsPerSec {
+        match self.oversubscription {
+            Some(ratio) => uplink_bandwidth(
+                self.host_bandwidth.into_f64() * self.hosts_per_tor as f64,
+                self.aggs_per_pod,
+                ratio,
+            ),
+            None => self.fabric_bandwidth,
+        }
+    }
+
+    /// Returns the bandwidth of each agg-core link.
+    pub fn agg_uplink_bandwidth(&self) -> BitsPerSec {
+        match self.oversubscription {
+            Some(ratio) => uplink_bandwidth(
+                self.tor_uplink_bandwidth().into_f64() * self.tors_per_pod as f64,
+                self.cores_per_plane,
+                ratio,
+            ),
+            None => self.fabric_bandwidth,
+        }
+    }
+
+    /// Generates the topology's nodes and links.
+    pub fn build(&self) -> (Vec<Node>, Vec<Link>) {
+        let nr_hosts = self.nr_hosts();
+        let nr_tors = self.nr_pods * self.tors_per_pod;
+        let nr_aggs = self.nr_pods * self.aggs_per_pod;
+        let nr_cores = self.aggs_per_pod * self.cores_per_plane;
+        let tor = |pod: usize, i: usize| NodeId::new(nr_hosts + pod * self.tors_per_pod + i);
+        let agg =
+            |pod: usize, i: usize| NodeId::new(nr_hosts + nr_tors + pod * self.aggs_per_pod + i);
+        let core = |plane: usize, i: usize| {
+            NodeId::new(nr_hosts + nr_tors + nr_aggs + plane * self.cores_per_plane + i)
+        };
+
+        let nodes = (0..nr_hosts)
+            .map(|i| Node::new_host(NodeId::new(i)))
+            .chain(
+                (nr_hosts..nr_hosts + nr_tors + nr_aggs + nr_cores)
+                    .map(|i| Node::new_switch(NodeId::new(i))),
+            )
+            .collect();
+
+        let mut links = Vec::new();
+        for pod in 0..self.nr_pods {
+            for t in 0..self.tors_per_pod {
+                for h in 0..self.hosts_per_tor {
+                    let host = NodeId::new((pod * self.tors_per_pod + t) * self.hosts_per_tor + h);
+                    links.push(Link::new(
+                        host,
+                        tor(pod, t),
+                        self.host_bandwidth,
+                        self.delay,
+                    ));
+                }
+                for a in 0..self.aggs_per_pod {
+                    links.push(Link::new(
+                        tor(pod, t),
+                        agg(pod, a),
+                        self.tor_uplink_bandwidth(),
+                        self.delay,
+                    ));
+                }
+            }
+            for a in 0..self.aggs_per_pod {
+                for c in 0..self.cores_per_plane {
+                    links.push(Link::new(
+                        agg(pod, a),
+                        core(a, c),
+                        self.agg_uplink_bandwidth(),
+                        self.delay,
+                    ));
+                }
+            }
+        }
+        (nodes, links)
+    }
+}
+
+// Splits `down` bits per second of downlink capacity, divided by `ratio`, across `nr_uplinks`.
+fn uplink_bandwidth(down: f64, nr_uplinks: usize, ratio: f64) -> BitsPerSec {
+    assert!(
+        ratio.is_finite() && ratio > 0.0,
+        "`ClosParams`: oversubscription must be positive"
+    );
+    BitsPerSec::new((down / (nr_uplinks.max(1) as f64 * ratio)).round() as u64)
+}
+
+/// Parameters for a seeded workload. Flows arrive as a Poisson process, have exponentially
+/// distributed sizes, and pick distinct source and destination hosts uniformly at random.
+#[derive(Debug, Clone, Copy)]
+pub struct WorkloadParams {
+    /// The number of flows.
+    pub nr_flows: usize,
+    /// The mean flow size.
+    pub mean_size: Bytes,
+    /// The average offered load on each host link, in (0, 1).
+    pub load: f64,
+    /// The random seed.
+    pub seed: u64,
+}
+
+impl WorkloadParams {
+    /// Generates flows between `nr_hosts` hosts (IDs `0..nr_hosts`) whose links have bandwidth
+    /// `host_bandwidth`. Flows are sorted by start time.
+    pub fn generate(&self, nr_hosts: usize, host_bandwidth: BitsPerSec) -> Vec<Flow> {
+        assert!(
+            nr_hosts >= 2,
+            "`WorkloadParams::generate`: need at least two hosts"
+        );
+        assert!(
+            0.0 < self.load && self.load < 1.0,
+            "`WorkloadParams::generate`: load must be in (0, 1)"
+        );
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        // Aggregate arrival rate (flows per ns) such that each host link sees `self.load`.
+        let bytes_per_ns = host_bandwidth.into_f64() / 8e9 * self.load * nr_hosts as f64;
+        let arrival_rate = bytes_per_ns / self.mean_size.into_f64();
+        // Inverse transform sampling of an exponential distribution with the given mean.
+        let exp = |rng: &mut ChaCha8Rng, mean: f64| -mean * (1.0 - rng.gen::<f64>()).ln();
+        let mut start = 0.0;
+        let mut flows = Vec::with_capacity(self.nr_flows);
+        for i in 0..self.nr_flows {
+            start += exp(&mut rng, arrival_rate.recip());
+            let size = exp(&mut rng, self.mean_size.into_f64()).round().max(1.0) as u64;
+            let src = rng.gen_range(0..nr_hosts);
+            let dst = (src + rng.gen_range(1..nr_hosts)) % nr_hosts;
+            flows.push(Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(src),
+                dst: NodeId::new(dst),
+                size: Bytes::new(size),
+                start: Nanosecs::new(start as u64),
+            });
+        }
+        flows
+    }
+}
+
+/// A named corpus entry combining a topology and a workload.
+#[derive(Debug, Clone, Copy)]
+pub struct CorpusEntry {
+    /// The entry's name.
+    pub name: &'static str,
+    /// The topology parameters.
+    pub topology: ClosParams,
+    /// The workload parameters.
+    pub workload: WorkloadParams,
+}
+
+impl CorpusEntry {
+    /// Generates the entry's specification.
+    pub fn spec(&self) -> Spec {
+        let (nodes, links) = self.topology.build();
+        let flows = self
+            .workload
+            .generate(self.topology.nr_hosts(), self.topology.host_bandwidth);
+        Spec::builder()
+            .nodes(nodes)
+            .links(links)
+            .flows(flows)
+            .build()
+    }
+}
+
+/// Returns all corpus entries in increasing order of scale.
+pub fn entries() -> Vec<CorpusEntry> {
+    vec![
+        entry("tiny", 2, 2, 2, 1, 2, 1_000),
+        entry("small", 2, 4, 2, 2, 8, 100_000),
+        entry("medium", 4, 8, 4, 2, 16, 1_000_000),
+        entry("large", 8, 8, 4, 4, 32, 10_000_000),
+    ]
+}
+
+/// Returns the corpus entry with the given name, if any.
+pub fn entry_named(name: &str) -> Option<CorpusEntry> {
+    entries().into_iter().find(|e| e.name == name)
+}
+
+fn entry(
+    name: &'static str,
+    nr_pods: usize,
+    tors_per_pod: usize,
+    aggs_per_pod: usize,
+    cores_per_plane: usize,
+    hosts_per_tor: usize,
+    nr_flows: usize,
+) -> CorpusEntry {
+    CorpusEntry {
+        name,
+        topology: ClosParams {
+            nr_pods,
+            tors_per_pod,
+            aggs_per_pod,
+            cores_per_plane,
+            hosts_per_tor,
+            host_bandwidth: Gbps::new(10).into(),
+            fabric_bandwidth: Gbps::new(40).into(),
+            delay: Nanosecs::new(1000),
+            oversubscription: None,
+        },
+        workload: WorkloadParams {
+            nr_flows,
+            mean_size: Bytes::new(20_000),
+            load: 0.5,
+            seed: 0,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{topology::Topology, types::BasicChannel};
+
+    #[test]
+    fn corpus_topologies_are_valid() -> anyhow::Result<()> {
+        for entry in entries() {
+            let (nodes, links) = entry.topology.build();
+            Topology::<BasicChannel>::new(&nodes, &links)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn corpus_workloads_are_deterministic() {
+        let entry = entry_named("tiny").unwrap();
+        let (a, b) = (entry.spec(), entry.spec());
+        assert_eq!(a.flows.len(), entry.workload.nr_flows);
+        assert!(a
+            .flows
+            .iter()
+            .zip(&b.flows)
+            .all(|(a, b)| (a.src, a.dst, a.size, a.start) == (b.src, b.dst, b.size, b.start)));
+        assert!(a.flows.iter().all(|f| f.src != f.dst));
+    }
+}
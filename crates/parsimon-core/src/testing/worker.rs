@@ -0,0 +1,203 @@
+//! An in-process fake worker for testing distributed runs without `parsimon-worker` binaries. A
+//! [`FakeWorker`] serves the same wire protocol as a real worker from a tokio task: it reads one
+//! MessagePack-encoded [`WorkerRequest`] per connection, simulates every descriptor with a
+//! deterministic link simulator, and writes back the MessagePack-encoded [`WorkerOut`]. Status
+//! requests report the number of batches completed for every job.
+//!
+//! Workers can be told to drop their first few connections without responding, like a crashed or
+//! preempted worker, to exercise failure handling.
+//!
+//! Runs with a single localhost worker are simulated locally, so at least two workers are needed
+//! to exercise the distributed path.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use rustc_hash::FxHashMap;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    runtime::Runtime,
+};
+
+use crate::{
+    distribute::{JobId, JobStatus, WorkerOut, WorkerRequest, WorkerStatus},
+    linksim::{LinkSim, LinkSimSpec},
+};
+
+/// A worker serving requests from a tokio task. The worker shuts down when dropped, which must
+/// not happen from within an asynchronous context.
+#[derive(Debug)]
+pub struct FakeWorker {
+    addr: SocketAddr,
+    nr_requests: Arc<AtomicUsize>,
+    jobs: Jobs,
+    // Owns the worker's task
+    _rt: Runtime,
+}
+
+impl FakeWorker {
+    /// Starts a worker on an ephemeral localhost port which simulates with `sim`. Requests for
+    /// other link simulators are dropped.
+    pub fn spawn<S>(sim: S) -> Self
+    where
+        S: LinkSim + Send + Sync + 'static,
+    {
+        Self::spawn_failing(sim, 0)
+    }
+
+    /// Like [`FakeWorker::spawn`], but the first `nr_failures` connections are closed without a
+    /// response.
+    pub fn spawn_failing<S>(sim: S, nr_failures: usize) -> Self
+    where
+        S: LinkSim + Send + Sync + 'static,
+    {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .expect("failed to build fake worker runtime");
+        let listener = rt
+            .block_on(TcpListener::bind("127.0.0.1:0"))
+            .expect("failed to bind fake worker");
+        let addr = listener.local_addr().unwrap();
+        let nr_requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&nr_requests);
+        let sim = Arc::new(sim);
+        let jobs = Jobs::default();
+        let jobs_ = Arc::clone(&jobs);
+        rt.spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let nr_seen = counter.fetch_add(1, Ordering::SeqCst);
+                if nr_seen < nr_failures {
+                    drop(stream);
+                    continue;
+                }
+                let sim = Arc::clone(&sim);
+                let jobs = Arc::clone(&jobs_);
+                tokio::spawn(async move {
+                    // Errors drop the connection, which the coordinator sees as a failed worker
+                    let _ = serve(stream, sim.as_ref(), &jobs).await;
+                });
+            }
+        });
+        Self {
+            addr,
+            nr_requests,
+            jobs,
+            _rt: rt,
+        }
+    }
+
+    /// Returns the worker's address.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the number of connections the worker has accepted, including failed ones.
+    pub fn nr_requests(&self) -> usize {
+        self.nr_requests.load(Ordering::SeqCst)
+    }
+
+    /// Returns the IDs of the jobs the worker has completed batches for, sorted.
+    pub fn jobs(&self) -> Vec<JobId> {
+        let mut jobs = self
+            .jobs
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        jobs.sort_unstable();
+        jobs
+    }
+}
+
+// The number of completed batches of every job
+type Jobs = Arc<Mutex<FxHashMap<JobId, usize>>>;
+
+async fn serve<S>(mut stream: TcpStream, sim: &S, jobs: &Jobs) -> anyhow::Result<()>
+where
+    S: LinkSim,
+{
+    let params = match read_request(&mut stream).await? {
+        WorkerRequest::Simulate(params) => params,
+        WorkerRequest::Status => {
+            let mut status = WorkerStatus {
+                max_jobs: None,
+                jobs: jobs
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(&id, &nr_done)| JobStatus {
+                        id,
+                        nr_waiting: 0,
+                        nr_running: 0,
+                        nr_done,
+                        nr_failed: 0,
+                    })
+                    .collect(),
+            };
+            status.jobs.sort_by_key(|j| j.id);
+            stream
+                .write_all(&rmp_serde::encode::to_vec(&status)?)
+                .await?;
+            stream.shutdown().await?;
+            return Ok(());
+        }
+    };
+    let job = params.job;
+    anyhow::ensure!(params.link_sim.0 == sim.name(), "unknown link simulator");
+    let id2flow = params
+        .flows
+        .iter()
+        .map(|f| (f.id, *f))
+        .collect::<FxHashMap<_, _>>();
+    let out = params
+        .descs
+        .into_iter()
+        .map(|desc| {
+            let flows = desc.flows.iter().map(|id| id2flow[id]).collect();
+            let spec = LinkSimSpec {
+                edge: desc.edge,
+                bottleneck: desc.bottleneck,
+                other_bottlenecks: desc.other_bottlenecks,
+                other_links: desc.other_links,
+                nodes: desc.nodes,
+                flows,
+                packets: params.packets,
+            };
+            Ok((desc.edge, sim.simulate(spec)?))
+        })
+        .collect::<anyhow::Result<WorkerOut>>()?;
+    if let Some(job) = job {
+        *jobs.lock().unwrap().entry(job).or_default() += 1;
+    }
+    stream.write_all(&rmp_serde::encode::to_vec(&out)?).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// The coordinator keeps its end open while waiting for results, so requests are framed only by
+// their encoding: read until a complete value decodes.
+async fn read_request(stream: &mut TcpStream) -> anyhow::Result<WorkerRequest> {
+    let mut buf = Vec::new();
+    loop {
+        if stream.read_buf(&mut buf).await? == 0 {
+            anyhow::bail!("connection closed before a complete request");
+        }
+        match rmp_serde::decode::from_slice(&buf) {
+            Ok(req) => return Ok(req),
+            Err(
+                rmp_serde::decode::Error::InvalidMarkerRead(e)
+                | rmp_serde::decode::Error::InvalidDataRead(e),
+            ) if e.kind() == std::io::ErrorKind::UnexpectedEof => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
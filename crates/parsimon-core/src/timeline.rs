@@ -0,0 +1,234 @@
+//! This module defines workload [timelines](Timeline): flow arrival and byte rates over time,
+//! globally and per rack. Timelines are cheap to compute and help check that generated or
+//! imported workloads have the intended temporal structure before running any simulations.
+//! Only intervals in which flows arrive are recorded, so fine intervals over long, sparse traces
+//! stay cheap.
+
+use std::{collections::BTreeMap, io};
+
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{
+    network::{types::NodeId, NodeKind},
+    spec::Spec,
+    units::{Bytes, Nanosecs},
+};
+
+/// Flow arrival and byte rates of a workload over fixed intervals.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Timeline {
+    /// The length of each interval.
+    pub interval: Nanosecs,
+    /// The start time of the first interval, i.e., the earliest flow start time.
+    pub origin: Nanosecs,
+    /// Rates over all flows, in intervals with arrivals.
+    pub global: Vec<TimelinePoint>,
+    /// Rates over flows sourced by each rack, sorted by ToR.
+    pub racks: Vec<RackTimeline>,
+}
+
+/// The rates of flows sourced by a single rack.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RackTimeline {
+    /// The ToR switch of the rack.
+    pub tor: NodeId,
+    /// The rates in every interval with arrivals from the rack.
+    pub points: Vec<TimelinePoint>,
+}
+
+/// The flow arrivals in a single interval. Intervals without arrivals are left out of timelines.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TimelinePoint {
+    /// The start of the interval, relative to the timeline's origin.
+    pub start: Nanosecs,
+    /// The number of flows starting in the interval.
+    pub nr_arrivals: u64,
+    /// The total size of flows starting in the interval.
+    pub bytes: Bytes,
+    /// Flow arrivals per second.
+    pub arrivals_per_sec: f64,
+    /// Bytes per second.
+    pub bytes_per_sec: f64,
+}
+
+impl Spec {
+    /// Computes the timeline of this specification's flows with the given interval length. A
+    /// host's rack is identified by the lowest-numbered switch it is linked to.
+    pub fn timeline(&self, interval: Nanosecs) -> Result<Timeline, TimelineError> {
+        if interval == Nanosecs::ZERO {
+            return Err(TimelineError::EmptyInterval);
+        }
+        let switches = self
+            .nodes
+            .iter()
+            .filter(|n| matches!(n.kind, NodeKind::Switch))
+            .map(|n| n.id)
+            .collect::<FxHashSet<_>>();
+        let mut host2tor = FxHashMap::default();
+        for link in &self.links {
+            for (host, switch) in [(link.a, link.b), (link.b, link.a)] {
+                if switches.contains(&switch) && !switches.contains(&host) {
+                    let tor = host2tor.entry(host).or_insert(switch);
+                    *tor = std::cmp::min(*tor, switch);
+                }
+            }
+        }
+        let origin = self
+            .flows
+            .iter()
+            .map(|f| f.start)
+            .min()
+            .unwrap_or(Nanosecs::ZERO);
+        // Arrivals and bytes of every interval with arrivals, by interval index
+        type Counts = BTreeMap<u64, (u64, Bytes)>;
+        let mut global = Counts::new();
+        let mut racks = FxHashMap::<NodeId, Counts>::default();
+        for f in &self.flows {
+            let i = (f.start - origin).into_u64() / interval.into_u64();
+            let add = |counts: &mut Counts| {
+                let (nr_arrivals, bytes) = counts.entry(i).or_insert((0, Bytes::ZERO));
+                *nr_arrivals += 1;
+                *bytes += f.size;
+            };
+            add(&mut global);
+            if let Some(&tor) = host2tor.get(&f.src) {
+                add(racks.entry(tor).or_default());
+            }
+        }
+        let secs = interval.into_f64() / 1e9;
+        let points = |counts: Counts| {
+            counts
+                .into_iter()
+                .map(|(i, (nr_arrivals, bytes))| TimelinePoint {
+                    // Never past the flow's start
+                    start: interval.checked_mul(i).unwrap(),
+                    nr_arrivals,
+                    bytes,
+                    arrivals_per_sec: nr_arrivals as f64 / secs,
+                    bytes_per_sec: bytes.into_f64() / secs,
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut racks = racks
+            .into_iter()
+            .map(|(tor, counts)| RackTimeline {
+                tor,
+                points: points(counts),
+            })
+            .collect::<Vec<_>>();
+        racks.sort_by_key(|r| r.tor);
+        Ok(Timeline {
+            interval,
+            origin,
+            global: points(global),
+            racks,
+        })
+    }
+}
+
+/// Error type for [`Spec::timeline`].
+#[derive(Debug, thiserror::Error)]
+pub enum TimelineError {
+    /// The interval is empty.
+    #[error("Timeline intervals must not be empty")]
+    EmptyInterval,
+}
+
+impl Timeline {
+    /// Writes the timeline as CSV with the columns `rack`, `start_ns`, `arrivals`, `bytes`,
+    /// `arrivals_per_sec`, and `bytes_per_sec`. Global rates have the rack `all`.
+    pub fn write_csv<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(
+            w,
+            "rack,start_ns,arrivals,bytes,arrivals_per_sec,bytes_per_sec"
+        )?;
+        let series = std::iter::once(("all".to_string(), &self.global))
+            .chain(self.racks.iter().map(|r| (r.tor.to_string(), &r.points)));
+        for (rack, points) in series {
+            for p in points {
+                writeln!(
+                    w,
+                    "{rack},{},{},{},{},{}",
+                    p.start.into_u64(),
+                    p.nr_arrivals,
+                    p.bytes.into_u64(),
+                    p.arrivals_per_sec,
+                    p.bytes_per_sec
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the timeline as JSON.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::{Flow, FlowId},
+        testing,
+    };
+
+    #[test]
+    fn timelines_bucket_arrivals() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let flow = |i, src, start| Flow {
+            id: FlowId::new(i),
+            src: NodeId::new(src),
+            dst: NodeId::new(3),
+            size: Bytes::new(1000),
+            start: Nanosecs::new(start),
+        };
+        let spec = Spec::builder()
+            .nodes(nodes)
+            .links(links)
+            .flows(vec![
+                flow(0, 0, 1_000),
+                flow(1, 1, 1_500),
+                flow(2, 2, 3_999),
+            ])
+            .build();
+        let timeline = spec.timeline(Nanosecs::new(1000))?;
+        assert_eq!(timeline.origin, Nanosecs::new(1000));
+        let arrivals = |points: &[TimelinePoint]| {
+            points
+                .iter()
+                .map(|p| (p.start.into_u64(), p.nr_arrivals))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(arrivals(&timeline.global), vec![(0, 2), (2000, 1)]);
+        assert_eq!(timeline.global[0].bytes_per_sec, 2e9);
+        assert_eq!(timeline.racks.len(), 2);
+        assert_eq!(timeline.racks[0].tor, NodeId::new(4));
+        assert_eq!(arrivals(&timeline.racks[0].points), vec![(0, 2)]);
+        assert_eq!(arrivals(&timeline.racks[1].points), vec![(2000, 1)]);
+
+        let mut csv = Vec::new();
+        timeline.write_csv(&mut csv)?;
+        let csv = String::from_utf8(csv)?;
+        assert_eq!(csv.lines().count(), 1 + 2 + 1 + 1);
+        assert_eq!(csv.lines().nth(1), Some("all,0,2,2000,2000000,2000000000"));
+        let json: Timeline = serde_json::from_str(&timeline.to_json()?)?;
+        assert_eq!(json, timeline);
+
+        assert!(matches!(
+            spec.timeline(Nanosecs::ZERO),
+            Err(TimelineError::EmptyInterval)
+        ));
+        // A nanosecond interval over a long, sparse trace only records its two busy intervals
+        let sparse = Spec::builder()
+            .nodes(spec.nodes.clone())
+            .links(spec.links.clone())
+            .flows(vec![flow(0, 0, 0), flow(1, 2, 3_600_000_000_000)])
+            .build();
+        let timeline = sparse.timeline(Nanosecs::new(1))?;
+        assert_eq!(timeline.global.len(), 2);
+        assert_eq!(timeline.racks.len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+//! Deterministic transforms of flow traces. A [`TraceTransform`] truncates a trace to a time
+//! window, speeds up (or slows down) its arrivals, and shifts it in time, so that sweeps over load
+//! levels can reuse one canonical trace. Apply it to a specification with
+//! [`Spec::transform_trace`](crate::Spec::transform_trace), or to flows directly with
+//! [`TraceTransform::apply`].
+//!
+//! Speeding up arrivals by a factor scales the offered load by the same factor without changing
+//! flow sizes or endpoints, unlike [`SimNetwork::scale_load`](crate::network::SimNetwork::scale_load),
+//! which replicates and thins flows at random.
+
+use std::ops::Range;
+
+use crate::{network::Flow, units::Nanosecs};
+
+/// A deterministic transform of a flow trace. Steps are applied in order: truncation, then
+/// speedup, then shift. The default transform leaves traces unchanged.
+#[derive(
+    Debug, Clone, PartialEq, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize,
+)]
+pub struct TraceTransform {
+    /// If set, only flows starting within this window are kept.
+    #[builder(default, setter(strip_option))]
+    #[serde(default)]
+    pub window: Option<Range<Nanosecs>>,
+    /// The factor arrivals are sped up by, which must be positive and finite. Start times are compressed
+    /// towards the start of the window, or towards the earliest start time without a window, so
+    /// a speedup of 1.2 offers 1.2 times the load.
+    #[builder(default = 1.0)]
+    #[serde(default = "no_speedup")]
+    pub speedup: f64,
+    /// A delay added to every start time.
+    #[builder(default)]
+    #[serde(default)]
+    pub shift: Nanosecs,
+}
+
+fn no_speedup() -> f64 {
+    1.0
+}
+
+impl Default for TraceTransform {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl TraceTransform {
+    /// Returns true if the transform leaves traces unchanged.
+    pub fn is_identity(&self) -> bool {
+        self.window.is_none() && self.speedup == 1.0 && self.shift == Nanosecs::ZERO
+    }
+
+    /// Checks that the transform's parameters are valid.
+    pub fn validate(&self) -> Result<(), TransformError> {
+        if !(self.speedup.is_finite() && self.speedup > 0.0) {
+            return Err(TransformError::InvalidSpeedup(self.speedup));
+        }
+        Ok(())
+    }
+
+    /// Applies the transform to `flows`, preserving their order. Fails if the transform is
+    /// [invalid](Self::validate).
+    pub fn apply(&self, flows: Vec<Flow>) -> Result<Vec<Flow>, TransformError> {
+        self.validate()?;
+        let flows = match &self.window {
+            Some(window) => flows
+                .into_iter()
+                .filter(|f| window.contains(&f.start))
+                .collect::<Vec<_>>(),
+            None => flows,
+        };
+        let anchor = match &self.window {
+            Some(window) => window.start,
+            None => flows.iter().map(|f| f.start).min().unwrap_or_default(),
+        };
+        Ok(flows
+            .into_iter()
+            .map(|f| {
+                let offset = (f.start - anchor).into_f64() / self.speedup;
+                Flow {
+                    start: anchor + Nanosecs::new(offset.round() as u64) + self.shift,
+                    ..f
+                }
+            })
+            .collect())
+    }
+}
+
+/// Errors which can be encountered applying a [`TraceTransform`].
+#[derive(Debug, thiserror::Error)]
+pub enum TransformError {
+    /// The speedup isn't positive and finite.
+    #[error("Speedup must be positive and finite (got {0})")]
+    InvalidSpeedup(f64),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::{FlowId, NodeId},
+        units::Bytes,
+    };
+
+    fn flows() -> Vec<Flow> {
+        (0..10)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1_000),
+                start: Nanosecs::new(1_000 + 120 * i as u64),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn transforms_truncate_speed_up_and_shift() -> anyhow::Result<()> {
+        assert!(TraceTransform::default().is_identity());
+        assert_eq!(TraceTransform::default().apply(flows())?, flows());
+
+        let transform = TraceTransform::builder()
+            .window(Nanosecs::new(1_240)..Nanosecs::new(1_600))
+            .speedup(1.2)
+            .shift(Nanosecs::new(5))
+            .build();
+        let flows = transform.apply(flows())?;
+        assert_eq!(
+            flows.iter().map(|f| f.id.inner()).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+        assert_eq!(
+            flows.iter().map(|f| f.start.into_u64()).collect::<Vec<_>>(),
+            vec![1_245, 1_345, 1_445]
+        );
+
+        let json = serde_json::to_string(&transform).unwrap();
+        assert_eq!(
+            serde_json::from_str::<TraceTransform>(&json).unwrap(),
+            transform
+        );
+        let transform: TraceTransform = serde_json::from_str(r#"{"speedup": 2.0}"#).unwrap();
+        assert_eq!(transform.shift, Nanosecs::ZERO);
+        Ok(())
+    }
+
+    #[test]
+    fn nonpositive_speedups_are_rejected() {
+        let transform: TraceTransform = serde_json::from_str(r#"{"speedup": 0.0}"#).unwrap();
+        assert!(transform.apply(flows()).is_err());
+        for speedup in [-1.0, f64::NAN, f64::INFINITY] {
+            let transform = TraceTransform::builder().speedup(speedup).build();
+            assert!(matches!(
+                transform.validate(),
+                Err(TransformError::InvalidSpeedup(_))
+            ));
+        }
+    }
+}
@@ -1,8 +1,16 @@
 #![allow(missing_docs)]
 //! Types for representing units.
+//!
+//! Every unit can be parsed from a human-readable quantity such as `"25Gbps"`, `"4us"`, or
+//! `"1.5KB"`, either with [`str::parse`] (e.g., for command-line arguments) or when deserializing a
+//! string (e.g., in JSON or Dhall specifications). The quantity can be expressed in any unit of the
+//! same dimension, and bare numbers are in the target unit. Units are displayed in a form that
+//! parses back to the same value. Units are always serialized as plain numbers.
+
+use std::{fmt, str::FromStr};
 
 macro_rules! unit {
-    ($name: ident) => {
+    ($name: ident, $dim: expr, $factor: expr) => {
         #[derive(
             Debug,
             Default,
@@ -18,12 +26,36 @@ macro_rules! unit {
             derive_more::AddAssign,
             derive_more::SubAssign,
             derive_more::Sum,
-            derive_more::FromStr,
             serde::Serialize,
-            serde::Deserialize,
         )]
         pub struct $name(u64);
 
+        impl Quantity for $name {
+            const DIMENSION: Dimension = $dim;
+            const FACTOR: u64 = $factor;
+
+            fn from_u64(value: u64) -> Self {
+                Self(value)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParseUnitError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                parse(s)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                deserializer.deserialize_any(QuantityVisitor(std::marker::PhantomData))
+            }
+        }
+
         impl $name {
             pub const ZERO: $name = Self::new(0);
             pub const ONE: $name = Self::new(1);
@@ -46,8 +78,49 @@ macro_rules! unit {
             }
 
             pub fn scale_by(self, val: f64) -> Self {
-                let inner = self.0 as f64 * val;
-                Self(inner.round() as u64)
+                let inner = (self.0 as f64 * val).round();
+                debug_assert!(
+                    (0.0..=u64::MAX as f64).contains(&inner),
+                    "{}::scale_by: {} * {} is out of range",
+                    stringify!($name),
+                    self.0,
+                    val
+                );
+                Self(inner as u64)
+            }
+
+            /// Checked addition. Returns `None` on overflow.
+            pub const fn checked_add(self, rhs: Self) -> Option<Self> {
+                match self.0.checked_add(rhs.0) {
+                    Some(inner) => Some(Self(inner)),
+                    None => None,
+                }
+            }
+
+            /// Checked subtraction. Returns `None` on underflow.
+            pub const fn checked_sub(self, rhs: Self) -> Option<Self> {
+                match self.0.checked_sub(rhs.0) {
+                    Some(inner) => Some(Self(inner)),
+                    None => None,
+                }
+            }
+
+            /// Checked multiplication by an integer. Returns `None` on overflow.
+            pub const fn checked_mul(self, rhs: u64) -> Option<Self> {
+                match self.0.checked_mul(rhs) {
+                    Some(inner) => Some(Self(inner)),
+                    None => None,
+                }
+            }
+
+            /// Saturating addition.
+            pub const fn saturating_add(self, rhs: Self) -> Self {
+                Self(self.0.saturating_add(rhs.0))
+            }
+
+            /// Saturating subtraction.
+            pub const fn saturating_sub(self, rhs: Self) -> Self {
+                Self(self.0.saturating_sub(rhs.0))
             }
         }
 
@@ -59,7 +132,191 @@ macro_rules! unit {
     };
 }
 
-unit!(Gbps);
+// Converts a value to a finer-grained unit, panicking instead of silently wrapping on overflow.
+fn scale_up(val: u64, factor: u64, unit: &str) -> u64 {
+    match val.checked_mul(factor) {
+        Some(inner) => inner,
+        None => panic!("overflow converting {val}{unit} to a finer unit"),
+    }
+}
+
+macro_rules! convert {
+    ($from: ident => $to: ident) => {
+        impl From<$from> for $to {
+            fn from(val: $from) -> Self {
+                Self::new(scale_up(
+                    val.0,
+                    <$from as Quantity>::FACTOR / <$to as Quantity>::FACTOR,
+                    stringify!($from),
+                ))
+            }
+        }
+    };
+}
+
+/// The physical dimension of a unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dimension {
+    /// Durations, in multiples of nanoseconds.
+    Time,
+    /// Bandwidths, in multiples of bits per second.
+    Bandwidth,
+    /// Sizes, in multiples of bytes.
+    Size,
+}
+
+impl Dimension {
+    // Unit suffixes and their multiples of the dimension's base unit.
+    fn suffixes(&self) -> &'static [(&'static str, u64)] {
+        match self {
+            Self::Time => &[
+                ("s", 1_000_000_000),
+                ("ms", 1_000_000),
+                ("us", 1_000),
+                ("µs", 1_000),
+                ("ns", 1),
+            ],
+            Self::Bandwidth => &[
+                ("Gbps", 1_000_000_000),
+                ("Mbps", 1_000_000),
+                ("Kbps", 1_000),
+                ("bps", 1),
+            ],
+            Self::Size => &[
+                ("GB", 1_000_000_000),
+                ("MB", 1_000_000),
+                ("KB", 1_000),
+                ("B", 1),
+            ],
+        }
+    }
+}
+
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let suffixes = self
+            .suffixes()
+            .iter()
+            .map(|(s, _)| *s)
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "{suffixes}")
+    }
+}
+
+// A unit of some dimension, `FACTOR` times the dimension's base unit.
+trait Quantity: Sized {
+    const DIMENSION: Dimension;
+    const FACTOR: u64;
+
+    fn from_u64(value: u64) -> Self;
+}
+
+/// An error parsing a unit from a string.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParseUnitError {
+    /// The quantity doesn't start with a non-negative decimal number.
+    #[error("invalid number in {0:?}")]
+    InvalidNumber(String),
+
+    /// The suffix isn't a unit of the expected dimension.
+    #[error("unknown unit {unit:?} (expected one of {expected})")]
+    UnknownUnit {
+        /// The unrecognized suffix.
+        unit: String,
+        /// The dimension of the target unit.
+        expected: Dimension,
+    },
+
+    /// The quantity isn't a whole number of the target unit.
+    #[error("{0:?} is not a whole number of the target unit")]
+    Inexact(String),
+
+    /// The quantity is too large for the target unit.
+    #[error("{0:?} is out of range")]
+    Overflow(String),
+}
+
+fn parse<Q: Quantity>(s: &str) -> Result<Q, ParseUnitError> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = (&s[..split], s[split..].trim_start());
+    let invalid_number = || ParseUnitError::InvalidNumber(s.into());
+    let (int, frac) = number.split_once('.').unwrap_or((number, ""));
+    if int.is_empty() && frac.is_empty() || frac.contains('.') {
+        return Err(invalid_number());
+    }
+    let factor = if unit.is_empty() {
+        Q::FACTOR
+    } else {
+        Q::DIMENSION
+            .suffixes()
+            .iter()
+            .find(|(suffix, _)| *suffix == unit)
+            .map(|&(_, factor)| factor)
+            .ok_or_else(|| ParseUnitError::UnknownUnit {
+                unit: unit.into(),
+                expected: Q::DIMENSION,
+            })?
+    };
+    // The quantity is `mantissa / 10^frac.len()` units of `factor`; compute it exactly
+    let overflow = || ParseUnitError::Overflow(s.into());
+    let mantissa = format!("{int}{frac}")
+        .parse::<u128>()
+        .map_err(|_| overflow())?;
+    let num = mantissa
+        .checked_mul(u128::from(factor))
+        .ok_or_else(overflow)?;
+    let den = 10_u128
+        .checked_pow(frac.len() as u32)
+        .and_then(|pow| pow.checked_mul(u128::from(Q::FACTOR)))
+        .ok_or_else(overflow)?;
+    if num % den != 0 {
+        return Err(ParseUnitError::Inexact(s.into()));
+    }
+    u64::try_from(num / den)
+        .map(Q::from_u64)
+        .map_err(|_| overflow())
+}
+
+struct QuantityVisitor<Q>(std::marker::PhantomData<Q>);
+
+impl<'de, Q: Quantity> serde::de::Visitor<'de> for QuantityVisitor<Q> {
+    type Value = Q;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "a non-negative integer or a quantity with one of the units {}",
+            Q::DIMENSION
+        )
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Q, E> {
+        Ok(Q::from_u64(v))
+    }
+
+    fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Q, E> {
+        u64::try_from(v)
+            .map(Q::from_u64)
+            .map_err(|_| E::invalid_value(serde::de::Unexpected::Signed(v), &self))
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Q, E> {
+        parse(v).map_err(E::custom)
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Q, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+unit!(Gbps, Dimension::Bandwidth, 1_000_000_000);
 
 impl std::fmt::Display for Gbps {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -67,7 +324,7 @@ impl std::fmt::Display for Gbps {
     }
 }
 
-unit!(Mbps);
+unit!(Mbps, Dimension::Bandwidth, 1_000_000);
 
 impl std::fmt::Display for Mbps {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -75,10 +332,9 @@ impl std::fmt::Display for Mbps {
     }
 }
 
-unit!(BitsPerSec);
+unit!(BitsPerSec, Dimension::Bandwidth, 1);
 
 impl BitsPerSec {
-
     #[allow(non_snake_case)]
     pub fn length(&self, size: Bytes) -> Nanosecs {
         assert!(*self != BitsPerSec::ZERO);
@@ -111,19 +367,11 @@ impl std::fmt::Display for BitsPerSec {
     }
 }
 
-impl From<Gbps> for BitsPerSec {
-    fn from(val: Gbps) -> Self {
-        Self::new(val.0 * 1_000_000_000)
-    }
-}
-
-impl From<Mbps> for BitsPerSec {
-    fn from(val: Mbps) -> Self {
-        Self::new(val.0 * 1_000_000)
-    }
-}
+convert!(Gbps => Mbps);
+convert!(Gbps => BitsPerSec);
+convert!(Mbps => BitsPerSec);
 
-unit!(Secs);
+unit!(Secs, Dimension::Time, 1_000_000_000);
 
 impl std::fmt::Display for Secs {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -131,7 +379,7 @@ impl std::fmt::Display for Secs {
     }
 }
 
-unit!(Millisecs);
+unit!(Millisecs, Dimension::Time, 1_000_000);
 
 impl std::fmt::Display for Millisecs {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -139,7 +387,7 @@ impl std::fmt::Display for Millisecs {
     }
 }
 
-unit!(Microsecs);
+unit!(Microsecs, Dimension::Time, 1_000);
 
 impl std::fmt::Display for Microsecs {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -147,7 +395,7 @@ impl std::fmt::Display for Microsecs {
     }
 }
 
-unit!(Nanosecs);
+unit!(Nanosecs, Dimension::Time, 1);
 
 impl std::fmt::Display for Nanosecs {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -155,25 +403,14 @@ impl std::fmt::Display for Nanosecs {
     }
 }
 
-impl From<Secs> for Nanosecs {
-    fn from(s: Secs) -> Self {
-        Self::new(s.0 * 1_000_000_000)
-    }
-}
-
-impl From<Millisecs> for Nanosecs {
-    fn from(ms: Millisecs) -> Self {
-        Self::new(ms.0 * 1_000_000)
-    }
-}
-
-impl From<Microsecs> for Nanosecs {
-    fn from(us: Microsecs) -> Self {
-        Self::new(us.0 * 1_000)
-    }
-}
+convert!(Secs => Millisecs);
+convert!(Secs => Microsecs);
+convert!(Secs => Nanosecs);
+convert!(Millisecs => Microsecs);
+convert!(Millisecs => Nanosecs);
+convert!(Microsecs => Nanosecs);
 
-unit!(Gigabytes);
+unit!(Gigabytes, Dimension::Size, 1_000_000_000);
 
 impl std::fmt::Display for Gigabytes {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -181,7 +418,7 @@ impl std::fmt::Display for Gigabytes {
     }
 }
 
-unit!(Kilobytes);
+unit!(Kilobytes, Dimension::Size, 1_000);
 
 impl std::fmt::Display for Kilobytes {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -189,7 +426,7 @@ impl std::fmt::Display for Kilobytes {
     }
 }
 
-unit!(Bytes);
+unit!(Bytes, Dimension::Size, 1);
 
 impl std::fmt::Display for Bytes {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -197,14 +434,113 @@ impl std::fmt::Display for Bytes {
     }
 }
 
-impl From<Gigabytes> for Bytes {
-    fn from(gb: Gigabytes) -> Self {
-        Bytes::new(gb.0 * 1_000_000_000)
+convert!(Gigabytes => Kilobytes);
+convert!(Gigabytes => Bytes);
+convert!(Kilobytes => Bytes);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_ops_detect_overflow() {
+        assert_eq!(Nanosecs::MAX.checked_add(Nanosecs::ONE), None);
+        assert_eq!(Nanosecs::ZERO.checked_sub(Nanosecs::ONE), None);
+        assert_eq!(Nanosecs::MAX.checked_mul(2), None);
+        assert_eq!(Nanosecs::new(2).checked_mul(3), Some(Nanosecs::new(6)));
+        assert_eq!(Nanosecs::MAX.saturating_add(Nanosecs::ONE), Nanosecs::MAX);
+        assert_eq!(Nanosecs::ZERO.saturating_sub(Nanosecs::ONE), Nanosecs::ZERO);
+    }
+
+    #[test]
+    #[should_panic(expected = "overflow converting")]
+    fn conversion_overflow_panics() {
+        let _ = Nanosecs::from(Secs::new(u64::MAX));
     }
-}
 
-impl From<Kilobytes> for Bytes {
-    fn from(kb: Kilobytes) -> Self {
-        Bytes::new(kb.0 * 1_000)
+    #[test]
+    fn quantities_parse_in_any_unit() {
+        assert_eq!("25Gbps".parse(), Ok(BitsPerSec::new(25_000_000_000)));
+        assert_eq!("25Gbps".parse(), Ok(Gbps::new(25)));
+        assert_eq!("4us".parse(), Ok(Nanosecs::new(4_000)));
+        assert_eq!("1.5 KB".parse(), Ok(Bytes::new(1_500)));
+        assert_eq!("1.5ms".parse(), Ok(Microsecs::new(1_500)));
+        assert_eq!("42".parse(), Ok(Millisecs::new(42)));
+        assert_eq!(".5s".parse(), Ok(Millisecs::new(500)));
+    }
+
+    #[test]
+    fn bad_quantities_fail() {
+        assert!(matches!(
+            "4us".parse::<Bytes>(),
+            Err(ParseUnitError::UnknownUnit {
+                expected: Dimension::Size,
+                ..
+            })
+        ));
+        assert!(matches!(
+            "1.5B".parse::<Bytes>(),
+            Err(ParseUnitError::Inexact(_))
+        ));
+        assert!(matches!(
+            "4us".parse::<Millisecs>(),
+            Err(ParseUnitError::Inexact(_))
+        ));
+        assert!(matches!(
+            "-1ns".parse::<Nanosecs>(),
+            Err(ParseUnitError::InvalidNumber(_))
+        ));
+        assert!(matches!(
+            "1.2.3ns".parse::<Nanosecs>(),
+            Err(ParseUnitError::InvalidNumber(_))
+        ));
+        assert!(matches!(
+            "100000000000s".parse::<Nanosecs>(),
+            Err(ParseUnitError::Overflow(_))
+        ));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for s in ["0ns", "7us", "3ms", "2s"] {
+            assert_eq!(
+                s.parse::<Nanosecs>()
+                    .unwrap()
+                    .to_string()
+                    .parse::<Nanosecs>(),
+                s.parse()
+            );
+        }
+        assert_eq!(Gbps::new(25).to_string().parse(), Ok(Gbps::new(25)));
+        assert_eq!(
+            BitsPerSec::new(7).to_string().parse(),
+            Ok(BitsPerSec::new(7))
+        );
+        assert_eq!(Kilobytes::new(3).to_string().parse(), Ok(Kilobytes::new(3)));
+        assert_eq!(Bytes::new(9).to_string().parse(), Ok(Bytes::new(9)));
+        assert_eq!(Microsecs::new(4).to_string().parse(), Ok(Microsecs::new(4)));
+    }
+
+    #[test]
+    fn quantities_deserialize_from_numbers_and_strings() -> anyhow::Result<()> {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Link {
+            bandwidth: BitsPerSec,
+            delay: Nanosecs,
+        }
+        let link: Link = serde_json::from_str(r#"{"bandwidth": "10Gbps", "delay": 1000}"#)?;
+        let expected = Link {
+            bandwidth: BitsPerSec::new(10_000_000_000),
+            delay: Nanosecs::new(1_000),
+        };
+        assert_eq!(link, expected);
+        assert_eq!(
+            serde_json::to_string(&expected)?,
+            r#"{"bandwidth":10000000000,"delay":1000}"#
+        );
+        let bytes = rmp_serde::to_vec(&expected)?;
+        assert_eq!(rmp_serde::from_slice::<Link>(&bytes)?, expected);
+        assert!(serde_json::from_str::<Link>(r#"{"bandwidth": "10us", "delay": 0}"#).is_err());
+        Ok(())
     }
 }
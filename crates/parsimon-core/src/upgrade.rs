@@ -0,0 +1,260 @@
+//! This module supports topology upgrade studies: it estimates delays after changing the bandwidth
+//! of some links, reusing the results of a completed run wherever they remain valid.
+//!
+//! An edge's results remain valid if its link-level simulation is unchanged by the upgrade, i.e.,
+//! neither its own link nor any link of its link-level topology changed. Since cluster members are
+//! filled with their representative's results, an edge whose own simulation is unchanged keeps
+//! its results even if its representative's simulation changed. Every other edge is re-simulated
+//! on its own.
+
+use rayon::prelude::*;
+
+use crate::{
+    linksim::{LinkSim, LinkSimDesc, LinkSimDescError},
+    network::{DelayNetwork, EdgeIndex, NodeId, SimNetwork, SimNetworkError},
+    opts::SimOpts,
+    scenario::{Scenario, ScenarioError},
+    units::BitsPerSec,
+};
+
+/// A new bandwidth for the link connecting two nodes, in both directions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BandwidthChange {
+    /// One endpoint of the link.
+    pub a: NodeId,
+    /// The other endpoint of the link.
+    pub b: NodeId,
+    /// The link's new bandwidth.
+    pub bandwidth: BitsPerSec,
+}
+
+/// The result of an upgrade simulation.
+#[derive(Debug)]
+pub struct Upgrade {
+    /// The network after the upgrade, with clusters reset.
+    pub sims: SimNetwork,
+    /// The delay network after the upgrade.
+    pub delays: DelayNetwork,
+    /// What was reused from the completed run.
+    pub report: ReuseReport,
+}
+
+/// A report of which results of a completed run were reused by an upgrade simulation, grouped by
+/// the clusters of the completed run.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ReuseReport {
+    /// The clusters of the completed run, in order.
+    pub clusters: Vec<ClusterReuse>,
+}
+
+impl ReuseReport {
+    /// Returns the representatives of the clusters whose results were reused in full.
+    pub fn valid_clusters(&self) -> impl Iterator<Item = EdgeIndex> + '_ {
+        self.clusters
+            .iter()
+            .filter(|c| c.is_valid())
+            .map(|c| c.representative)
+    }
+
+    /// Returns the edges which were re-simulated.
+    pub fn resimulated(&self) -> impl Iterator<Item = EdgeIndex> + '_ {
+        self.clusters
+            .iter()
+            .flat_map(|c| c.resimulated.iter().copied())
+    }
+
+    /// Returns the fraction of edges whose results were reused, or 1 if there are no edges.
+    pub fn reuse_fraction(&self) -> f64 {
+        let nr_reused = self.clusters.iter().map(|c| c.reused.len()).sum::<usize>();
+        let nr_edges = nr_reused + self.resimulated().count();
+        if nr_edges == 0 {
+            1.0
+        } else {
+            nr_reused as f64 / nr_edges as f64
+        }
+    }
+}
+
+/// How the members of one cluster were handled by an upgrade simulation.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ClusterReuse {
+    /// The cluster representative.
+    pub representative: EdgeIndex,
+    /// The members whose results were reused.
+    pub reused: Vec<EdgeIndex>,
+    /// The members which were re-simulated.
+    pub resimulated: Vec<EdgeIndex>,
+}
+
+impl ClusterReuse {
+    /// Returns true if the upgrade left the results of every member valid.
+    pub fn is_valid(&self) -> bool {
+        self.resimulated.is_empty()
+    }
+}
+
+/// Simulates the network `sims` after applying `changes`, re-simulating only the edges whose
+/// link-level simulations changed. All other edges reuse their delay distributions from `base`,
+/// which must have been computed from `sims` and its clusters.
+pub fn simulate_upgrade<S>(
+    sims: &SimNetwork,
+    base: &DelayNetwork,
+    changes: &[BandwidthChange],
+    opts: SimOpts<S>,
+) -> Result<Upgrade, UpgradeError>
+where
+    S: LinkSim + Sync,
+{
+    let scenario = changes.iter().fold(
+        Scenario::new(),
+        |scenario, &BandwidthChange { a, b, bandwidth }| {
+            scenario.degrade(a, b, Some(bandwidth), Default::default())
+        },
+    );
+    // Bandwidth changes keep every link, so edge indices and routes are preserved
+    let upgraded = sims.with_scenario(&scenario)?;
+    opts.validate().map_err(SimNetworkError::from)?;
+    let ack = opts.ack_model(&sims.packet_profile());
+    let is_unchanged = sims
+        .edge_indices()
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|eidx| {
+            let before = sims.link_sim_desc_with(eidx, ack)?;
+            let after = upgraded.link_sim_desc_with(eidx, ack)?;
+            Ok(same_simulation(before, after))
+        })
+        .collect::<Result<Vec<_>, LinkSimDescError>>()
+        .map_err(SimNetworkError::from)?;
+    let clusters = sims
+        .clusters()
+        .iter()
+        .map(|c| {
+            let (mut reused, mut resimulated): (Vec<_>, Vec<_>) =
+                c.members().partition(|m| is_unchanged[m.index()]);
+            reused.sort();
+            resimulated.sort();
+            ClusterReuse {
+                representative: c.representative(),
+                reused,
+                resimulated,
+            }
+        })
+        .collect();
+    let report = ReuseReport { clusters };
+    let delays = upgraded
+        .clone()
+        .into_delays_reusing(base, report.resimulated(), opts)?;
+    Ok(Upgrade {
+        sims: upgraded,
+        delays,
+        report,
+    })
+}
+
+// Two descriptors describe the same simulation if they are equal up to the order of their nodes
+// and links, which follows hash set iteration.
+fn same_simulation(a: Option<LinkSimDesc>, b: Option<LinkSimDesc>) -> bool {
+    let normalize = |desc: Option<LinkSimDesc>| {
+        desc.map(|mut desc| {
+            desc.nodes.sort_by_key(|n| n.id);
+            desc.other_links.sort_by_key(|l| (l.from, l.to));
+            desc
+        })
+    };
+    normalize(a) == normalize(b)
+}
+
+/// Errors which can be encountered simulating an upgrade.
+#[derive(Debug, thiserror::Error)]
+pub enum UpgradeError {
+    /// The changes can't be applied to the network.
+    #[error("invalid upgrade")]
+    Scenario(#[from] ScenarioError),
+
+    /// Error simulating the upgraded network.
+    #[error("failed to simulate upgrade")]
+    SimNetwork(#[from] SimNetworkError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::{Flow, FlowId, Network},
+        testing::{self, FixedDelaySim},
+        units::{Bytes, Nanosecs},
+    };
+
+    #[test]
+    fn untouched_edges_are_reused() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let id = NodeId::new;
+        // Host 0 sends to host 1, and host 2 sends to host 3, each under its own ToR
+        let flows = [(0, 1), (2, 3)]
+            .into_iter()
+            .flat_map(|(src, dst)| (0..10).map(move |i| (src, dst, i)))
+            .enumerate()
+            .map(|(i, (src, dst, j))| Flow {
+                id: FlowId::new(i),
+                src: id(src),
+                dst: id(dst),
+                size: Bytes::new(1000),
+                start: Nanosecs::new(j * 1000),
+            })
+            .collect::<Vec<_>>();
+        let sims = Network::new(&nodes, &links)?.into_simulations(flows);
+        let opts = || {
+            SimOpts::builder()
+                .link_sim(FixedDelaySim {
+                    delay_per_pkt: Nanosecs::new(100),
+                })
+                .build()
+        };
+        let base = sims.clone().into_delays(opts())?;
+
+        // Upgrading host 2's uplink changes the simulations of the edges host 2's flows traverse
+        let changes = [BandwidthChange {
+            a: id(2),
+            b: id(5),
+            bandwidth: BitsPerSec::new(100_000_000_000),
+        }];
+        let upgrade = simulate_upgrade(&sims, &base, &changes, opts())?;
+        let resimulated = upgrade.report.resimulated().collect::<Vec<_>>();
+        assert!(resimulated.contains(&sims.find_edge(id(2), id(5)).unwrap()));
+        assert!(resimulated
+            .iter()
+            .all(|&e| sims.edge(e).unwrap().nr_flows() > 0));
+        // Host 0's flows never touch the upgraded link
+        let reused = sims.find_edge(id(0), id(4)).unwrap();
+        assert!(upgrade.report.valid_clusters().any(|e| e == reused));
+        assert_eq!(
+            upgrade.delays.edge_quantile(reused, Bytes::new(1000), 0.5),
+            base.edge_quantile(reused, Bytes::new(1000), 0.5)
+        );
+        assert!(upgrade.report.reuse_fraction() > 0.5);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_links_fail() -> anyhow::Result<()> {
+        let (nodes, links) = testing::eight_node_config();
+        let sims = Network::new(&nodes, &links)?.into_simulations(Vec::new());
+        let opts = SimOpts::builder()
+            .link_sim(FixedDelaySim {
+                delay_per_pkt: Nanosecs::new(100),
+            })
+            .build();
+        let base = sims.clone().into_delays(opts.clone())?;
+        let changes = [BandwidthChange {
+            a: NodeId::new(0),
+            b: NodeId::new(1),
+            bandwidth: BitsPerSec::new(1),
+        }];
+        assert!(matches!(
+            simulate_upgrade(&sims, &base, &changes, opts),
+            Err(UpgradeError::Scenario(ScenarioError::UnknownLink { .. }))
+        ));
+        Ok(())
+    }
+}
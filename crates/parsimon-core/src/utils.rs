@@ -1,55 +1,114 @@
 #![allow(unused)]
 
-use std::cmp;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use std::hash::Hasher;
 
 use rayon::prelude::*;
 
-use crate::network::{Channel, Flow};
-use crate::units::{Bytes, Gbps, Nanosecs};
-
-pub(crate) fn calculate_hash<T: Hash>(t: &T) -> u64 {
-    let mut s = DefaultHasher::new();
-    t.hash(&mut s);
-    s.finish()
+// A fixed mixing function (SplitMix64's finalizer), for deriving seeds and hashes that must not
+// change between versions of Rust or of our dependencies
+pub(crate) fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
 }
 
-pub(crate) fn bdp(bandwidth: Gbps, delay: impl Into<Nanosecs>) -> Bytes {
-    let bits_per_nanosec = bandwidth.into_f64();
-    let bytes_per_nanosec = bits_per_nanosec / 8.0;
-    let nanosecs = delay.into().into_f64();
-    Bytes::new((bytes_per_nanosec * nanosecs).round() as u64)
+// A hasher for fingerprints that are saved and compared across machines. Every integer is widened
+// to a fixed-width `u64` before being mixed with `splitmix64`, so hashes depend neither on the
+// platform's pointer width or endianness nor on versions of Rust or of our dependencies.
+#[derive(Debug, Default)]
+pub(crate) struct StableHasher {
+    state: u64,
 }
 
-pub(crate) fn offered_loads(
-    bandwidth: Gbps,
-    interval: impl Into<Nanosecs>,
-    flows: &[Flow],
-) -> Vec<f64> {
-    let interval: Nanosecs = interval.into();
-    let max_bytes = bdp(bandwidth, interval);
-    let load = |bytes: Bytes| bytes.into_f64() / max_bytes.into_f64();
-    let mut loads = Vec::new();
-    let mut count = Bytes::ZERO;
-    let mut next = interval;
-    let mut push_load = |count: &mut Bytes, next: &mut Nanosecs| {
-        let offered_bytes = std::cmp::min(max_bytes, *count);
-        let load = load(offered_bytes);
-        *count -= offered_bytes;
-        *next += interval;
-        loads.push(load);
-    };
-    for flow in flows {
-        while flow.start >= next {
-            push_load(&mut count, &mut next);
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.state
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_u64(u64::from_le_bytes(word));
         }
-        count += flow.size;
     }
-    while count > Bytes::ZERO {
-        push_load(&mut count, &mut next);
+
+    fn write_u8(&mut self, i: u8) {
+        self.write_u64(i.into());
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write_u64(i.into());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(i.into());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.state = splitmix64(self.state ^ i);
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write_u64(i as u64);
+        self.write_u64((i >> 64) as u64);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i8(&mut self, i: i8) {
+        self.write_i64(i.into());
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_i64(i.into());
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_i64(i.into());
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_i64(i as i64);
     }
-    loads
+}
+
+// 32-bit MurmurHash3 (x86), as used for ECMP hashing by the HPCC ns-3 simulator
+pub(crate) fn murmur3_32(key: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let mut h = seed;
+    let mut chunks = key.chunks_exact(4);
+    for chunk in &mut chunks {
+        let k = u32::from_le_bytes(chunk.try_into().unwrap());
+        h ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .rev()
+            .fold(0u32, |k, &byte| k << 8 | u32::from(byte));
+        h ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+    h ^= key.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
 }
 
 pub(crate) fn par_chunks<T, F, R>(data: &[T], f: F) -> impl Iterator<Item = R>
@@ -71,91 +130,23 @@ where
     r.into_iter().flat_map(|v| v.into_iter())
 }
 
-// XXX: These are set to match the ns3 implementation's default behavior.
-// TODO: Allow configuring these in ns3.
-const SZ_PKTMAX: Bytes = Bytes::new(1_000);
-const SZ_PKTHDR: Bytes = Bytes::new(48);
-
-pub(crate) fn ideal_fct<T>(size: Bytes, hops: &[T]) -> Nanosecs
-where
-    T: Channel,
-{
-    assert!(!hops.is_empty());
-    let bandwidths = hops.iter().map(|c| c.bandwidth()).collect::<Vec<_>>();
-    let min_bw = bandwidths.iter().min().unwrap();
-    let sz_head_ = cmp::min(SZ_PKTMAX, size);
-    let sz_head = (sz_head_ != Bytes::ZERO)
-        .then(|| sz_head_ + SZ_PKTHDR)
-        .unwrap_or(Bytes::ZERO);
-    let sz_rest_ = size - sz_head_;
-    let head_delay = bandwidths
-        .iter()
-        .map(|bw| bw.length(sz_head))
-        .sum::<Nanosecs>();
-    let rest_delay = {
-        let nr_full_pkts = sz_rest_.into_usize() / SZ_PKTMAX.into_usize();
-        let sz_full_pkt = SZ_PKTMAX + SZ_PKTHDR;
-        let sz_partial_pkt_ = Bytes::new(sz_rest_.into_u64() % SZ_PKTMAX.into_u64());
-        let sz_partial_pkt = (sz_partial_pkt_ != Bytes::ZERO)
-            .then(|| sz_partial_pkt_ + SZ_PKTHDR)
-            .unwrap_or(Bytes::ZERO);
-        min_bw.length(sz_full_pkt).scale_by(nr_full_pkts as f64) + min_bw.length(sz_partial_pkt)
-    };
-    let prop_delay = hops.iter().map(|c| c.delay()).sum::<Nanosecs>();
-    head_delay + rest_delay + prop_delay
-}
-
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use crate::units::{Gigabytes, Microsecs};
-
-    const BANDWIDTH: Gbps = Gbps::new(100);
-    const INTERVAL: Microsecs = Microsecs::new(10);
-
-    // Scale a slice of floats in [0, 1] to a slice of integers in [0, 100].
-    fn integerify(vals: &[f64]) -> Vec<u32> {
-        vals.iter()
-            .map(|&val| (val * 100.0).round() as u32)
-            .collect()
-    }
+    use std::hash::Hash;
 
-    #[test]
-    fn bdp_correct() {
-        let bdp = bdp(BANDWIDTH, INTERVAL);
-        assert_eq!(bdp, Bytes::new(125_000));
-    }
+    use super::*;
 
-    #[test]
-    fn offered_loads_time_advances() {
-        let flows = &[
-            Flow {
-                size: Bytes::new(12_500),
-                start: INTERVAL.into(),
-                ..Default::default()
-            },
-            Flow {
-                size: Bytes::new(12_500),
-                start: (INTERVAL + INTERVAL).into(),
-                ..Default::default()
-            },
-        ];
-        let offered_loads = integerify(&offered_loads(BANDWIDTH, INTERVAL, flows));
-        assert_eq!(offered_loads, vec![0, 10, 10]);
+    fn stable_hash<T: Hash>(t: &T) -> u64 {
+        let mut hasher = StableHasher::default();
+        t.hash(&mut hasher);
+        hasher.finish()
     }
 
     #[test]
-    fn offered_loads_overflows_correctly() {
-        let flow = Flow {
-            size: Gigabytes::ONE.into(),
-            start: INTERVAL.into(),
-            ..Default::default()
-        };
-        let offered_loads = integerify(&offered_loads(BANDWIDTH, INTERVAL, &[flow]));
-        assert_eq!(offered_loads[0], 0);
-        let bdp = bdp(BANDWIDTH, INTERVAL);
-        let nr_expected_ones = Into::<Bytes>::into(Gigabytes::ONE).into_u64() / bdp.into_u64();
-        assert_eq!(offered_loads[1..].len(), nr_expected_ones as usize);
-        assert!(offered_loads[1..].iter().all(|&load| load == 100));
+    fn stable_hashes_widen_integers() {
+        assert_eq!(stable_hash(&5usize), stable_hash(&5u64));
+        assert_eq!(stable_hash(&5u32), stable_hash(&5u64));
+        assert_eq!(stable_hash(&-5isize), stable_hash(&-5i64));
+        assert_ne!(stable_hash(&(1u64, 2u64)), stable_hash(&(2u64, 1u64)));
     }
 }
@@ -0,0 +1,509 @@
+//! A command-line driver for Parsimon. Topologies are read from JSON, YAML, or Dhall files,
+//! scenarios from JSON or Dhall files, and flows from JSON, YAML, or MessagePack files, as with
+//! [`parsimon::utils`].
+//!
+//! ```bash
+//! $ parsimon validate -t topology.json -f flows.msgpack --feasibility
+//! $ parsimon cluster-stats -t topology.json -f flows.msgpack --clustering greedy
+//! $ parsimon run -t topology.json -f flows.msgpack -o delays.msgpack
+//! $ parsimon experiment -e experiment.dhall -o delays.msgpack
+//! $ parsimon predict -d delays.msgpack --size 10000 --src 0 --dst 3 --samples 10000
+//! ```
+//!
+//! Pass `--log-level info` (or `debug` for per-link spans) to print the pipeline's spans with
+//! their timing, and `--log-format json` for machine-readable logs.
+//!
+//! `run` persists the resulting delay network, which `predict` loads to answer queries, along with
+//! a [manifest](parsimon::utils::manifest) of its provenance. `run` and `cluster-stats` take
+//! either command-line options or a [configuration file](parsimon::utils::config) with `--config`.
+//! `experiment` runs an [experiment file](parsimon::utils::experiment), which describes the
+//! inputs and the configuration in one document.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use parsimon::{
+    core::{
+        feasibility::FeasibilityOpts,
+        linksim::Window,
+        network::{saved::SavedDelayNetwork, DelayNetwork, Flow, NodeId},
+        spec::Spec,
+        units::{BitsPerSec, Bytes, Nanosecs},
+    },
+    impls::{
+        clustering::{diagnostics, feature},
+        linksim::{FluidLink, MinimLink, Ns3Link},
+    },
+    utils::{
+        self,
+        config::{ClusteringConfig, Config, LinkSimConfig},
+        manifest::{InputDigest, Manifest},
+    },
+};
+use rand::{rngs::StdRng, SeedableRng};
+use tracing_subscriber::{filter::LevelFilter, fmt::format::FmtSpan};
+
+/// Fast tail latency estimates for data center networks.
+#[derive(Parser, Debug)]
+#[command(name = "parsimon", author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    /// Most verbose level of log events and spans to print to stderr
+    #[arg(long, global = true, default_value_t = LevelFilter::WARN)]
+    log_level: LevelFilter,
+
+    /// Log format; `json` prints one object per line
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// Newline-delimited JSON
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Simulate a workload and save the resulting delay network
+    Run(RunArgs),
+    /// Run an experiment described by a single file and save the resulting delay network
+    Experiment(ExperimentArgs),
+    /// Predict FCT delays from a saved delay network
+    Predict(PredictArgs),
+    /// Check that a workload can be simulated
+    Validate(ValidateArgs),
+    /// Report how well a workload's links cluster
+    ClusterStats(ClusterStatsArgs),
+}
+
+#[derive(Args, Debug)]
+struct Inputs {
+    /// Topology file (JSON, YAML, or Dhall), or a GraphML or DOT graph
+    #[arg(short, long)]
+    topology: PathBuf,
+
+    /// Flows file (JSON, YAML, or MessagePack)
+    #[arg(short, long)]
+    flows: PathBuf,
+
+    /// Scenario file of link failures and degradations (JSON or Dhall)
+    #[arg(short, long)]
+    scenario: Option<PathBuf>,
+}
+
+impl Inputs {
+    fn read(&self) -> anyhow::Result<Spec> {
+        let topology = utils::read_topology_spec(&self.topology)
+            .with_context(|| format!("failed to read {}", self.topology.display()))?;
+        let flows = utils::read_flows(&self.flows)
+            .with_context(|| format!("failed to read {}", self.flows.display()))?;
+        let scenario = match &self.scenario {
+            Some(path) => utils::read_scenario(path)
+                .with_context(|| format!("failed to read {}", path.display()))?,
+            None => Default::default(),
+        };
+        Ok(Spec::builder()
+            .nodes(topology.nodes)
+            .links(topology.links)
+            .flows(flows)
+            .scenario(scenario)
+            .build())
+    }
+
+    fn digests(&self) -> anyhow::Result<Vec<InputDigest>> {
+        let mut digests = vec![
+            InputDigest::of("topology", &self.topology)?,
+            InputDigest::of("flows", &self.flows)?,
+        ];
+        if let Some(path) = &self.scenario {
+            digests.push(InputDigest::of("scenario", path)?);
+        }
+        Ok(digests)
+    }
+}
+
+#[derive(Args, Debug)]
+struct ClusteringArgs {
+    /// Link clustering algorithm
+    #[arg(long, value_enum, default_value_t = Clustering::Default)]
+    clustering: Clustering,
+
+    /// Largest feature distance (WMAPE) between links clustered together by `greedy`
+    #[arg(long, default_value_t = 0.1)]
+    threshold: f64,
+}
+
+impl ClusteringArgs {
+    fn config(&self) -> ClusteringConfig {
+        match self.clustering {
+            Clustering::Default => ClusteringConfig::Default,
+            Clustering::Greedy => ClusteringConfig::Greedy {
+                threshold: self.threshold,
+                seed: None,
+            },
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Clustering {
+    /// Simulate every link
+    Default,
+    /// Cluster links with similar flow size and arrival distributions and loads
+    Greedy,
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    #[command(flatten)]
+    inputs: Inputs,
+
+    /// Where to save the delay network
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Configuration file (TOML, JSON, or Dhall) replacing the options below, which can't be
+    /// given with it
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = [
+            "backend", "window", "dctcp_gain", "dctcp_ai", "root_dir", "ns3_dir", "base_rtt",
+            "workers", "seed", "clustering", "threshold",
+        ]
+    )]
+    config: Option<PathBuf>,
+
+    /// Link simulator
+    #[arg(long, value_enum, default_value_t = Backend::Minim)]
+    backend: Backend,
+
+    /// Sending window
+    #[arg(long, default_value = "18000")]
+    window: Bytes,
+
+    /// DCTCP gain (minim, fluid)
+    #[arg(long, default_value_t = 0.0625)]
+    dctcp_gain: f64,
+
+    /// DCTCP additive increase (minim, fluid)
+    #[arg(long, default_value = "615000000")]
+    dctcp_ai: BitsPerSec,
+
+    /// Directory for simulation data (ns3)
+    #[arg(long, required_if_eq("backend", "ns3"))]
+    root_dir: Option<PathBuf>,
+
+    /// Path to the ns-3 simulation directory (ns3)
+    #[arg(long, required_if_eq("backend", "ns3"))]
+    ns3_dir: Option<PathBuf>,
+
+    /// Base round-trip time (ns3)
+    #[arg(long, default_value = "14400")]
+    base_rtt: Nanosecs,
+
+    /// Comma-separated worker addresses; simulates locally if unset
+    #[arg(long, value_delimiter = ',')]
+    workers: Vec<SocketAddr>,
+
+    /// Seed every random choice of the run is derived from
+    #[arg(long)]
+    seed: Option<u64>,
+
+    #[command(flatten)]
+    clustering: ClusteringArgs,
+}
+
+impl RunArgs {
+    fn config(&self) -> anyhow::Result<Config> {
+        if let Some(path) = &self.config {
+            return read_config(path);
+        }
+        let link_sim = match self.backend {
+            Backend::Minim => LinkSimConfig::Minim(
+                MinimLink::builder()
+                    .window(self.window)
+                    .dctcp_gain(self.dctcp_gain)
+                    .dctcp_ai(self.dctcp_ai)
+                    .build(),
+            ),
+            Backend::Ns3 => LinkSimConfig::Ns3(
+                Ns3Link::builder()
+                    .root_dir(self.root_dir.clone().expect("required by clap"))
+                    .ns3_dir(self.ns3_dir.clone().expect("required by clap"))
+                    .window(Window::Fixed(self.window))
+                    .base_rtt(self.base_rtt)
+                    .build(),
+            ),
+            Backend::Fluid => LinkSimConfig::Fluid(
+                FluidLink::builder()
+                    .window(self.window)
+                    .dctcp_gain(self.dctcp_gain)
+                    .dctcp_ai(self.dctcp_ai)
+                    .build(),
+            ),
+        };
+        Ok(Config::builder()
+            .link_sim(link_sim)
+            .workers(self.workers.clone())
+            .clustering(self.clustering.config())
+            .seed(self.seed)
+            .build())
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Backend {
+    /// The Minim DCTCP simulator
+    Minim,
+    /// The ns-3 HPCC simulator
+    Ns3,
+    /// A built-in DCTCP fluid model, for quick estimates without external simulators
+    Fluid,
+}
+
+#[derive(Args, Debug)]
+struct ExperimentArgs {
+    /// Experiment file (JSON or Dhall)
+    #[arg(short, long)]
+    experiment: PathBuf,
+
+    /// Where to save the delay network
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct PredictArgs {
+    /// Delay network saved by `run`
+    #[arg(short, long)]
+    delays: PathBuf,
+
+    /// Predict every flow in this file, printing `id,delay_ns` CSV rows instead
+    #[arg(short, long, conflicts_with_all = ["size", "src", "dst"])]
+    flows: Option<PathBuf>,
+
+    /// Flow size
+    #[arg(long, required_unless_present = "flows")]
+    size: Option<Bytes>,
+
+    /// Source host ID
+    #[arg(long, required_unless_present = "flows")]
+    src: Option<usize>,
+
+    /// Destination host ID
+    #[arg(long, required_unless_present = "flows")]
+    dst: Option<usize>,
+
+    /// Number of predictions to summarize with quantiles
+    #[arg(long, default_value_t = 1)]
+    samples: usize,
+
+    /// Random seed; defaults to the prediction seed of the delay network's run
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+#[derive(Args, Debug)]
+struct ValidateArgs {
+    #[command(flatten)]
+    inputs: Inputs,
+
+    /// Print a feasibility report, and fail if a host or link is overloaded for a sustained period
+    #[arg(long)]
+    feasibility: bool,
+}
+
+#[derive(Args, Debug)]
+struct ClusterStatsArgs {
+    #[command(flatten)]
+    inputs: Inputs,
+
+    /// Configuration file (TOML, JSON, or Dhall) whose clustering algorithm to use
+    #[arg(short, long, conflicts_with_all = ["clustering", "threshold"])]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    clustering: ClusteringArgs,
+
+    /// Print per-cluster metrics as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    init_logging(cli.log_level, cli.log_format);
+    match cli.command {
+        Command::Run(args) => run_cmd(args),
+        Command::Experiment(args) => experiment_cmd(args),
+        Command::Predict(args) => predict_cmd(args),
+        Command::Validate(args) => validate_cmd(args),
+        Command::ClusterStats(args) => cluster_stats_cmd(args),
+    }
+}
+
+// Span closings are logged with their busy and idle times, which is where a run's time goes
+fn init_logging(level: LevelFilter, format: LogFormat) {
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+fn run_cmd(args: RunArgs) -> anyhow::Result<()> {
+    let config = args.config()?;
+    let spec = args.inputs.read()?;
+    let mut inputs = args.inputs.digests()?;
+    if let Some(path) = &args.config {
+        inputs.push(InputDigest::of("config", path)?);
+    }
+    let (delays, manifest) = config.run_recorded(spec, inputs)?;
+    save_run(&args.output, &delays, &manifest)
+}
+
+fn experiment_cmd(args: ExperimentArgs) -> anyhow::Result<()> {
+    let experiment = utils::read_experiment(&args.experiment)
+        .with_context(|| format!("failed to read {}", args.experiment.display()))?;
+    let spec = experiment.spec()?;
+    let mut inputs = vec![InputDigest::of("experiment", &args.experiment)?];
+    inputs.extend(experiment.digests()?);
+    let (delays, manifest) = experiment.config.run_recorded(spec, inputs)?;
+    save_run(&args.output, &delays, &manifest)
+}
+
+// Saves a delay network and the manifest of the run that built it
+fn save_run(output: &Path, delays: &DelayNetwork, manifest: &Manifest) -> anyhow::Result<()> {
+    let saved = delays.save();
+    write_msgpack(output, &saved)?;
+    let manifest_path = Manifest::path_for(output);
+    manifest
+        .write(&manifest_path)
+        .with_context(|| format!("failed to write {}", manifest_path.display()))?;
+    eprintln!(
+        "saved delay network {:016x} to {} in {:.2?}",
+        saved.fingerprint(),
+        output.display(),
+        manifest.durations.total()
+    );
+    Ok(())
+}
+
+fn predict_cmd(args: PredictArgs) -> anyhow::Result<()> {
+    let saved: SavedDelayNetwork = read_msgpack(&args.delays)?;
+    let delays = DelayNetwork::load(saved)
+        .with_context(|| format!("failed to load {}", args.delays.display()))?;
+    let mut rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => delays.rng(),
+    };
+    if let Some(path) = &args.flows {
+        let flows = utils::read_flows(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut out = BufWriter::new(std::io::stdout().lock());
+        writeln!(out, "id,delay_ns")?;
+        for Flow {
+            id, src, dst, size, ..
+        } in flows
+        {
+            match delays.predict(size, (src, dst), &mut rng) {
+                Some(delay) => writeln!(out, "{id},{}", delay.into_u64())?,
+                None => writeln!(out, "{id},")?,
+            }
+        }
+        out.flush()?;
+        return Ok(());
+    }
+    let (size, src, dst) = match (args.size, args.src, args.dst) {
+        (Some(size), Some(src), Some(dst)) => (size, NodeId::new(src), NodeId::new(dst)),
+        _ => unreachable!("required by clap"),
+    };
+    let samples = (0..args.samples.max(1))
+        .map(|_| delays.predict(size, (src, dst), &mut rng))
+        .collect::<Option<Vec<_>>>()
+        .with_context(|| format!("no prediction for a {size} flow from {src} to {dst}"))?;
+    if let [delay] = samples[..] {
+        println!("{delay}");
+        return Ok(());
+    }
+    let mut samples = samples;
+    samples.sort();
+    for q in [0.5, 0.9, 0.99, 0.999] {
+        let i = ((q * samples.len() as f64) as usize).min(samples.len() - 1);
+        println!("p{}\t{}", q * 100.0, samples[i]);
+    }
+    Ok(())
+}
+
+fn validate_cmd(args: ValidateArgs) -> anyhow::Result<()> {
+    let mut spec = args.inputs.read()?;
+    let (nr_nodes, nr_links, nr_flows) = (spec.nodes.len(), spec.links.len(), spec.flows.len());
+    if args.feasibility {
+        let report = spec.check_feasibility(&FeasibilityOpts::default())?;
+        println!("{report}");
+        spec.feasibility = Some(FeasibilityOpts::builder().enforce(true).build());
+    }
+    spec.check()?;
+    println!("valid: {nr_nodes} nodes, {nr_links} links, {nr_flows} flows");
+    Ok(())
+}
+
+fn cluster_stats_cmd(args: ClusterStatsArgs) -> anyhow::Result<()> {
+    let mut sims = args.inputs.read()?.into_simulations()?;
+    let clustering = match &args.config {
+        Some(path) => read_config(path)?.clustering,
+        None => args.clustering.config(),
+    };
+    clustering.cluster(&mut sims);
+    let report = diagnostics::diagnose(
+        &sims,
+        feature::dists_and_load,
+        feature::dists_and_load_distance,
+    );
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    let s = &report.summary;
+    println!("edges\t{}", s.nr_edges);
+    println!("clusters\t{}", s.nr_clusters);
+    println!("reduction\t{:.2}", s.reduction);
+    println!("max cluster size\t{}", s.max_cluster_size);
+    println!("max distance\t{:.4}", s.max_distance);
+    println!("mean max distance\t{:.4}", s.mean_max_distance);
+    Ok(())
+}
+
+fn read_config(path: &Path) -> anyhow::Result<Config> {
+    utils::read_config(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+fn read_msgpack<T: serde::de::DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
+    let f = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    rmp_serde::decode::from_read(BufReader::new(f))
+        .with_context(|| format!("failed to decode {}", path.display()))
+}
+
+fn write_msgpack<T: serde::Serialize>(path: &Path, value: &T) -> anyhow::Result<()> {
+    let f = File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    let mut w = BufWriter::new(f);
+    rmp_serde::encode::write(&mut w, value)
+        .with_context(|| format!("failed to encode {}", path.display()))?;
+    w.flush()?;
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use std::{
+    path::Path,
+    process::{Command, Output},
+};
+
+use parsimon::{
+    core::{
+        network::{Flow, FlowId, NodeId},
+        testing,
+        units::{BitsPerSec, Bytes, Nanosecs},
+    },
+    impls::linksim::FluidLink,
+    utils::{
+        self,
+        config::{Config, LinkSimConfig},
+        experiment::Experiment,
+        TopologySpec,
+    },
+};
+
+fn parsimon(args: &[&str], dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_parsimon"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+// Writes an eight-node topology and flows between hosts under different ToRs
+fn inputs(dir: &Path, dst: usize) -> anyhow::Result<()> {
+    let (nodes, links) = testing::eight_node_config();
+    utils::write_topology_spec(dir.join("topology.json"), &TopologySpec { nodes, links })?;
+    let flows = (0..100)
+        .map(|i| Flow {
+            id: FlowId::new(i),
+            src: NodeId::new(i % 2),
+            dst: NodeId::new(dst),
+            size: Bytes::new(1000 * (i as u64 % 10 + 1)),
+            start: Nanosecs::new(i as u64 * 10_000),
+        })
+        .collect::<Vec<_>>();
+    utils::write_flows(dir.join("flows.json"), &flows)?;
+    Ok(())
+}
+
+const INPUTS: [&str; 4] = ["-t", "topology.json", "-f", "flows.json"];
+
+#[test]
+fn validate_reports_specs() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    inputs(dir.path(), 3)?;
+    let out = stdout(&parsimon(
+        &[&["validate"], &INPUTS[..]].concat(),
+        dir.path(),
+    ));
+    assert_eq!(out, "valid: 8 nodes, 8 links, 100 flows\n");
+
+    // Node 6 is a switch
+    inputs(dir.path(), 6)?;
+    let output = parsimon(&[&["validate"], &INPUTS[..]].concat(), dir.path());
+    assert!(!output.status.success());
+    Ok(())
+}
+
+#[test]
+fn cluster_stats_checks_specs() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    inputs(dir.path(), 3)?;
+    let args = [&["cluster-stats"], &INPUTS[..], &["--clustering", "greedy"]].concat();
+    let out = stdout(&parsimon(&args, dir.path()));
+    assert!(out.starts_with("edges\t16\nclusters\t"), "{out}");
+    let out = stdout(&parsimon(&[&args[..], &["--json"]].concat(), dir.path()));
+    assert!(serde_json::from_str::<serde_json::Value>(&out).is_ok());
+
+    inputs(dir.path(), 6)?;
+    let output = parsimon(&args, dir.path());
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("panicked"), "{stderr}");
+    Ok(())
+}
+
+#[test]
+fn runs_are_saved_and_predicted() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    inputs(dir.path(), 3)?;
+    let args = [
+        &["run"],
+        &INPUTS[..],
+        &["--backend", "fluid", "--seed", "1", "-o", "delays.msgpack"],
+    ]
+    .concat();
+    let output = parsimon(&args, dir.path());
+    stdout(&output);
+    assert!(dir.path().join("delays.msgpack").exists());
+
+    let predict = ["predict", "-d", "delays.msgpack", "--size", "5000"];
+    let out = stdout(&parsimon(
+        &[&predict[..], &["--src", "0", "--dst", "3"]].concat(),
+        dir.path(),
+    ));
+    assert!(out.trim().parse::<Nanosecs>().is_ok(), "{out}");
+    let args = [
+        &predict[..],
+        &["--src", "0", "--dst", "3", "--samples", "100"],
+    ]
+    .concat();
+    let out = stdout(&parsimon(&args, dir.path()));
+    assert_eq!(out.lines().count(), 4);
+    assert!(out.starts_with("p50\t"), "{out}");
+
+    let args = ["predict", "-d", "delays.msgpack", "-f", "flows.json"];
+    let out = stdout(&parsimon(&args, dir.path()));
+    assert_eq!(out.lines().next(), Some("id,delay_ns"));
+    assert_eq!(out.lines().count(), 101);
+    Ok(())
+}
+
+#[test]
+fn config_files_conflict_with_options() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    inputs(dir.path(), 3)?;
+    let fluid = FluidLink::builder()
+        .window(Bytes::new(18_000))
+        .dctcp_gain(0.0625)
+        .dctcp_ai(BitsPerSec::new(615_000_000))
+        .build();
+    let config = Config::builder()
+        .link_sim(LinkSimConfig::Fluid(fluid))
+        .build();
+    std::fs::write(
+        dir.path().join("config.json"),
+        serde_json::to_string(&config)?,
+    )?;
+    let run = [
+        &["run"],
+        &INPUTS[..],
+        &["-o", "delays.msgpack", "-c", "config.json"],
+    ]
+    .concat();
+    stdout(&parsimon(&run, dir.path()));
+    for option in [
+        ["--window", "1000"],
+        ["--dctcp-gain", "0.5"],
+        ["--dctcp-ai", "1000"],
+        ["--base-rtt", "1000"],
+    ] {
+        let output = parsimon(&[&run[..], &option].concat(), dir.path());
+        assert!(!output.status.success(), "{option:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("cannot be used with"), "{stderr}");
+    }
+    Ok(())
+}
+
+#[test]
+fn experiments_are_run() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    inputs(dir.path(), 3)?;
+    let (nodes, links) = testing::eight_node_config();
+    let fluid = FluidLink::builder()
+        .window(Bytes::new(18_000))
+        .dctcp_gain(0.0625)
+        .dctcp_ai(BitsPerSec::new(615_000_000))
+        .build();
+    let experiment = Experiment {
+        topology: TopologySpec { nodes, links },
+        flows: "flows.json".into(),
+        scenario: None,
+        config: Config::builder()
+            .link_sim(LinkSimConfig::Fluid(fluid))
+            .build(),
+    };
+    std::fs::write(
+        dir.path().join("experiment.json"),
+        serde_json::to_string(&experiment)?,
+    )?;
+    let args = [
+        "experiment",
+        "-e",
+        "experiment.json",
+        "-o",
+        "delays.msgpack",
+    ];
+    stdout(&parsimon(&args, dir.path()));
+    assert!(dir.path().join("delays.msgpack").exists());
+    Ok(())
+}
@@ -0,0 +1,96 @@
+/*
+ * C interface to Parsimon. See crates/parsimon-ffi/src/lib.rs for the conventions shared by all
+ * functions: results are written through out pointers only on PARSIMON_OK, errors are described
+ * by parsimon_last_error() on the failing thread, and queries are seeded explicitly.
+ */
+
+#ifndef PARSIMON_H
+#define PARSIMON_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The version of the ABI, incremented on incompatible changes. */
+#define PARSIMON_ABI_VERSION 1
+
+/* Specification formats accepted by parsimon_run(). */
+#define PARSIMON_FORMAT_JSON 0
+#define PARSIMON_FORMAT_MSGPACK 1
+
+/* The delay parsimon_predict_many() reports for flows without a prediction. */
+#define PARSIMON_MISSING_DELAY UINT64_MAX
+
+typedef enum ParsimonStatus {
+    PARSIMON_OK = 0,
+    PARSIMON_NO_PREDICTION = 1,
+    PARSIMON_INVALID_ARGUMENT = 2,
+    PARSIMON_ERROR = 3,
+} ParsimonStatus;
+
+/*
+ * Options for parsimon_run(). Start from parsimon_run_opts_default(), which sets `struct_size`.
+ * Fields are only ever appended, and fields past `struct_size` keep their defaults.
+ */
+typedef struct ParsimonRunOpts {
+    /* The size of the struct in bytes. */
+    size_t struct_size;
+    /* The DCTCP window in bytes. */
+    uint64_t window;
+    /* The DCTCP gain. */
+    double dctcp_gain;
+    /* The DCTCP additive increase in bits per second. */
+    uint64_t dctcp_ai;
+    /* Comma-separated "host:port" worker addresses, or NULL to simulate locally. */
+    const char *workers;
+} ParsimonRunOpts;
+
+/* A network of delay distributions. */
+typedef struct ParsimonDelayNetwork ParsimonDelayNetwork;
+
+/* Returns PARSIMON_ABI_VERSION of the loaded library. */
+uint32_t parsimon_abi_version(void);
+
+/* Returns the default run options. */
+ParsimonRunOpts parsimon_run_opts_default(void);
+
+/*
+ * Returns a description of the last error on the calling thread, or NULL if no call has failed.
+ * The string is valid until the next call on the same thread.
+ */
+const char *parsimon_last_error(void);
+
+/*
+ * Builds a delay network from a specification of `len` bytes in the given PARSIMON_FORMAT_*. The
+ * specification is an object with "nodes", "links", and "flows" fields. `opts` may be NULL.
+ */
+ParsimonStatus parsimon_run(const uint8_t *spec, size_t len, uint32_t format,
+                            const ParsimonRunOpts *opts, ParsimonDelayNetwork **out);
+
+/* Frees a delay network. Does nothing if `delays` is NULL. */
+void parsimon_delay_network_free(ParsimonDelayNetwork *delays);
+
+/* Predicts the FCT delay in nanoseconds of a flow of `size` bytes from `src` to `dst`. */
+ParsimonStatus parsimon_predict(const ParsimonDelayNetwork *delays, uint64_t size, size_t src,
+                                size_t dst, uint64_t seed, uint64_t *out);
+
+/*
+ * Predicts the FCT delays of `n` flows into `out[0..n]`. Flows without a prediction get
+ * PARSIMON_MISSING_DELAY.
+ */
+ParsimonStatus parsimon_predict_many(const ParsimonDelayNetwork *delays, size_t n,
+                                     const uint64_t *sizes, const size_t *srcs,
+                                     const size_t *dsts, uint64_t seed, uint64_t *out);
+
+/* Computes the FCT in nanoseconds of a flow of `size` bytes from `src` to `dst` when unloaded. */
+ParsimonStatus parsimon_ideal_fct(const ParsimonDelayNetwork *delays, uint64_t size, size_t src,
+                                  size_t dst, uint64_t seed, uint64_t *out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PARSIMON_H */
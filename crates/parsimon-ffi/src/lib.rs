@@ -0,0 +1,623 @@
+//! A C ABI for embedding Parsimon in other tools. The library builds delay networks from
+//! serialized specifications with the Minim link simulator and default clustering, and answers
+//! point and ideal-FCT queries on them. The declarations are in `include/parsimon.h`.
+//!
+//! Build the shared and static libraries with
+//!
+//! ```bash
+//! $ cargo build --release -p parsimon-ffi
+//! ```
+//!
+//! which places `libparsimon_ffi.so` (or `.dylib`/`.dll`) and `libparsimon_ffi.a` in
+//! `target/release`.
+//!
+//! ```c
+//! #include "parsimon.h"
+//!
+//! ParsimonRunOpts opts = parsimon_run_opts_default();
+//! ParsimonDelayNetwork *delays = NULL;
+//! if (parsimon_run(spec, spec_len, PARSIMON_FORMAT_JSON, &opts, &delays) != PARSIMON_OK) {
+//!     fprintf(stderr, "%s\n", parsimon_last_error());
+//!     return 1;
+//! }
+//! uint64_t delay;
+//! if (parsimon_predict(delays, 10000, 0, 3, 42, &delay) == PARSIMON_OK) {
+//!     printf("%llu ns\n", (unsigned long long)delay);
+//! }
+//! parsimon_delay_network_free(delays);
+//! ```
+//!
+//! # Conventions
+//!
+//! - Every fallible function returns a [`ParsimonStatus`] and writes its results through out
+//!   pointers, which are left untouched unless the status is [`ParsimonStatus::Ok`].
+//! - On [`ParsimonStatus::InvalidArgument`] and [`ParsimonStatus::Error`], a description of the
+//!   error is available from [`parsimon_last_error`] on the same thread.
+//! - Panics never cross the ABI; they are reported as [`ParsimonStatus::Error`].
+//! - Sizes are in bytes, times and delays in nanoseconds, and nodes are referred to by ID.
+//! - Queries are seeded explicitly, so equal arguments give equal answers.
+//! - A delay network may be queried from several threads at once, but must be freed exactly
+//!   once, after all queries on it have returned.
+//!
+//! The ABI only changes in backwards-compatible ways while [`PARSIMON_ABI_VERSION`] stays the
+//! same.
+
+#![warn(unreachable_pub, missing_debug_implementations, missing_docs)]
+
+use std::{
+    cell::RefCell,
+    error::Error as _,
+    ffi::{c_char, CStr, CString},
+    mem,
+    net::{AddrParseError, SocketAddr},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+use parsimon::{
+    core::{
+        cluster::DefaultClustering,
+        network::{
+            types::{Flow, Link, Node, NodeId},
+            DelayNetwork,
+        },
+        opts::SimOpts,
+        run::{self, run},
+        spec::Spec,
+        units::{BitsPerSec, Bytes, Nanosecs},
+    },
+    impls::linksim::MinimLink,
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+/// The version of the ABI, incremented on incompatible changes.
+pub const PARSIMON_ABI_VERSION: u32 = 1;
+
+/// A specification serialized as JSON.
+pub const PARSIMON_FORMAT_JSON: u32 = 0;
+
+/// A specification serialized as MessagePack.
+pub const PARSIMON_FORMAT_MSGPACK: u32 = 1;
+
+/// The delay [`parsimon_predict_many`] reports for flows without a prediction.
+pub const PARSIMON_MISSING_DELAY: u64 = u64::MAX;
+
+/// The outcome of a call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParsimonStatus {
+    /// The call succeeded.
+    Ok = 0,
+    /// The network has no prediction for the query, e.g., because no flows of a similar size
+    /// crossed its path.
+    NoPrediction = 1,
+    /// An argument was null or malformed.
+    InvalidArgument = 2,
+    /// The call failed.
+    Error = 3,
+}
+
+/// Options for [`parsimon_run`]. Start from [`parsimon_run_opts_default`] so that fields added
+/// later keep their defaults. New fields are only ever appended, and `struct_size` tells the
+/// library which fields a caller built against an older header knows about; the rest keep their
+/// defaults.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ParsimonRunOpts {
+    /// The size of the struct in bytes, as set by [`parsimon_run_opts_default`].
+    pub struct_size: usize,
+    /// The DCTCP window in bytes.
+    pub window: u64,
+    /// The DCTCP gain.
+    pub dctcp_gain: f64,
+    /// The DCTCP additive increase in bits per second.
+    pub dctcp_ai: u64,
+    /// A comma-separated list of `host:port` worker addresses, or null to simulate locally.
+    pub workers: *const c_char,
+}
+
+/// A network of delay distributions, built by [`parsimon_run`] and freed with
+/// [`parsimon_delay_network_free`]. Opaque to C.
+#[derive(Debug)]
+pub struct ParsimonDelayNetwork(DelayNetwork);
+
+/// A serialized specification.
+#[derive(Debug, serde::Deserialize)]
+struct SpecDoc {
+    nodes: Vec<Node>,
+    links: Vec<Link>,
+    flows: Vec<Flow>,
+}
+
+/// Errors reported through [`parsimon_last_error`].
+#[derive(Debug, thiserror::Error)]
+enum FfiError {
+    #[error("Null pointer passed as `{0}`")]
+    NullPointer(&'static str),
+
+    #[error("Unknown specification format {0}")]
+    UnknownFormat(u32),
+
+    #[error("Run options have invalid size {0}")]
+    InvalidOptsSize(usize),
+
+    #[error("Worker list is not valid UTF-8")]
+    InvalidWorkers,
+
+    #[error("Invalid worker address")]
+    InvalidWorker(#[from] AddrParseError),
+
+    #[error("Failed to parse JSON specification")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Failed to parse MessagePack specification")]
+    MsgPack(#[from] rmp_serde::decode::Error),
+
+    #[error("Failed to run specification")]
+    Run(#[from] run::Error),
+
+    #[error("Panicked: {0}")]
+    Panic(String),
+}
+
+impl FfiError {
+    fn status(&self) -> ParsimonStatus {
+        match self {
+            Self::NullPointer(_)
+            | Self::UnknownFormat(_)
+            | Self::InvalidOptsSize(_)
+            | Self::InvalidWorkers
+            | Self::InvalidWorker(_)
+            | Self::Json(_)
+            | Self::MsgPack(_) => ParsimonStatus::InvalidArgument,
+            Self::Run(_) | Self::Panic(_) => ParsimonStatus::Error,
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Returns [`PARSIMON_ABI_VERSION`] of the loaded library.
+#[no_mangle]
+pub extern "C" fn parsimon_abi_version() -> u32 {
+    PARSIMON_ABI_VERSION
+}
+
+/// Returns the default run options.
+#[no_mangle]
+pub extern "C" fn parsimon_run_opts_default() -> ParsimonRunOpts {
+    ParsimonRunOpts {
+        struct_size: mem::size_of::<ParsimonRunOpts>(),
+        window: 18_000,
+        dctcp_gain: 0.0625,
+        dctcp_ai: 615_000_000,
+        workers: ptr::null(),
+    }
+}
+
+/// Returns a description of the last error on the calling thread, including its causes, or null
+/// if no call has failed. The string is valid until the next call on the same thread.
+#[no_mangle]
+pub extern "C" fn parsimon_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Builds a delay network from a specification of `len` bytes at `spec` in the given
+/// `PARSIMON_FORMAT_*` and stores it in `*out`. The specification is an object with `nodes`,
+/// `links`, and `flows` fields, as in Parsimon's topology and flow files. If `opts` is null, the
+/// default options are used.
+///
+/// # Safety
+///
+/// `spec` must point to `len` readable bytes, `opts` must be null or point to `opts->struct_size`
+/// readable bytes of options whose `workers` is null or a NUL-terminated string, and `out` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn parsimon_run(
+    spec: *const u8,
+    len: usize,
+    format: u32,
+    opts: *const ParsimonRunOpts,
+    out: *mut *mut ParsimonDelayNetwork,
+) -> ParsimonStatus {
+    guard(|| {
+        let spec = bytes(spec, len)?;
+        let opts = run_opts(opts)?;
+        let out = out.as_mut().ok_or(FfiError::NullPointer("out"))?;
+        let doc: SpecDoc = match format {
+            PARSIMON_FORMAT_JSON => serde_json::from_slice(spec)?,
+            PARSIMON_FORMAT_MSGPACK => rmp_serde::from_slice(spec)?,
+            _ => return Err(FfiError::UnknownFormat(format)),
+        };
+        let spec = Spec::builder()
+            .nodes(doc.nodes)
+            .links(doc.links)
+            .flows(doc.flows)
+            .build();
+        let minim = MinimLink::builder()
+            .window(Bytes::new(opts.window))
+            .dctcp_gain(opts.dctcp_gain)
+            .dctcp_ai(BitsPerSec::new(opts.dctcp_ai))
+            .build();
+        let workers = workers(opts.workers)?;
+        let opts = if workers.is_empty() {
+            SimOpts::builder().link_sim(minim).build()
+        } else {
+            SimOpts::builder().link_sim(minim).workers(workers).build()
+        };
+        let delays = run(spec, opts, DefaultClustering)?;
+        *out = Box::into_raw(Box::new(ParsimonDelayNetwork(delays)));
+        Ok(ParsimonStatus::Ok)
+    })
+}
+
+/// Frees a delay network. Does nothing if `delays` is null.
+///
+/// # Safety
+///
+/// `delays` must be null or a network returned by [`parsimon_run`] which hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn parsimon_delay_network_free(delays: *mut ParsimonDelayNetwork) {
+    if !delays.is_null() {
+        drop(Box::from_raw(delays));
+    }
+}
+
+/// Predicts the FCT delay of a flow of `size` bytes from `src` to `dst` and stores it in `*out`.
+///
+/// # Safety
+///
+/// `delays` must be a live network returned by [`parsimon_run`], and `out` must be valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn parsimon_predict(
+    delays: *const ParsimonDelayNetwork,
+    size: u64,
+    src: usize,
+    dst: usize,
+    seed: u64,
+    out: *mut u64,
+) -> ParsimonStatus {
+    guard(|| {
+        let delays = network(delays)?;
+        let out = out.as_mut().ok_or(FfiError::NullPointer("out"))?;
+        let endpoints = (NodeId::new(src), NodeId::new(dst));
+        Ok(
+            match delays.predict(Bytes::new(size), endpoints, StdRng::seed_from_u64(seed)) {
+                Some(delay) => {
+                    *out = delay.into_u64();
+                    ParsimonStatus::Ok
+                }
+                None => ParsimonStatus::NoPrediction,
+            },
+        )
+    })
+}
+
+/// Predicts the FCT delays of `n` flows, where flow `i` has `sizes[i]` bytes and runs from
+/// `srcs[i]` to `dsts[i]`, and stores them in `out[0..n]`. Flows without a prediction get
+/// [`PARSIMON_MISSING_DELAY`].
+///
+/// # Safety
+///
+/// `delays` must be a live network returned by [`parsimon_run`], `sizes`, `srcs`, and `dsts` must
+/// point to `n` readable elements, and `out` must point to `n` writable elements.
+#[no_mangle]
+pub unsafe extern "C" fn parsimon_predict_many(
+    delays: *const ParsimonDelayNetwork,
+    n: usize,
+    sizes: *const u64,
+    srcs: *const usize,
+    dsts: *const usize,
+    seed: u64,
+    out: *mut u64,
+) -> ParsimonStatus {
+    guard(|| {
+        let delays = network(delays)?;
+        let sizes = slice(sizes, n, "sizes")?;
+        let srcs = slice(srcs, n, "srcs")?;
+        let dsts = slice(dsts, n, "dsts")?;
+        if n > 0 && out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        let delays = (0..n)
+            .map(|i| {
+                let endpoints = (NodeId::new(srcs[i]), NodeId::new(dsts[i]));
+                delays
+                    .predict(Bytes::new(sizes[i]), endpoints, &mut rng)
+                    .map_or(PARSIMON_MISSING_DELAY, Nanosecs::into_u64)
+            })
+            .collect::<Vec<_>>();
+        if n > 0 {
+            ptr::copy_nonoverlapping(delays.as_ptr(), out, n);
+        }
+        Ok(ParsimonStatus::Ok)
+    })
+}
+
+/// Computes the FCT of a flow of `size` bytes from `src` to `dst` on an unloaded network and
+/// stores it in `*out`. Returns [`ParsimonStatus::NoPrediction`] if there is no path.
+///
+/// # Safety
+///
+/// `delays` must be a live network returned by [`parsimon_run`], and `out` must be valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn parsimon_ideal_fct(
+    delays: *const ParsimonDelayNetwork,
+    size: u64,
+    src: usize,
+    dst: usize,
+    seed: u64,
+    out: *mut u64,
+) -> ParsimonStatus {
+    guard(|| {
+        let delays = network(delays)?;
+        let out = out.as_mut().ok_or(FfiError::NullPointer("out"))?;
+        let endpoints = (NodeId::new(src), NodeId::new(dst));
+        Ok(
+            match delays.ideal_fct(Bytes::new(size), endpoints, StdRng::seed_from_u64(seed)) {
+                Some(fct) => {
+                    *out = fct.into_u64();
+                    ParsimonStatus::Ok
+                }
+                None => ParsimonStatus::NoPrediction,
+            },
+        )
+    })
+}
+
+// Runs `f`, recording its error or panic as the thread's last error.
+fn guard(f: impl FnOnce() -> Result<ParsimonStatus, FfiError>) -> ParsimonStatus {
+    let e = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(status)) => return status,
+        Ok(Err(e)) => e,
+        Err(payload) => {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            FfiError::Panic(msg)
+        }
+    };
+    let mut msg = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        msg.push_str(&format!(": {cause}"));
+        source = cause.source();
+    }
+    let msg = CString::new(msg.replace('\0', " ")).expect("NUL bytes were replaced");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(msg));
+    e.status()
+}
+
+unsafe fn network<'a>(delays: *const ParsimonDelayNetwork) -> Result<&'a DelayNetwork, FfiError> {
+    delays
+        .as_ref()
+        .map(|d| &d.0)
+        .ok_or(FfiError::NullPointer("delays"))
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Result<&'a [u8], FfiError> {
+    slice(data, len, "spec")
+}
+
+// Null is accepted for empty slices.
+unsafe fn slice<'a, T>(
+    data: *const T,
+    len: usize,
+    name: &'static str,
+) -> Result<&'a [T], FfiError> {
+    match (data.is_null(), len) {
+        (_, 0) => Ok(&[]),
+        (true, _) => Err(FfiError::NullPointer(name)),
+        (false, _) => Ok(std::slice::from_raw_parts(data, len)),
+    }
+}
+
+// Reads the fields of `opts` the caller knows about, keeping the defaults of the rest.
+unsafe fn run_opts(opts: *const ParsimonRunOpts) -> Result<ParsimonRunOpts, FfiError> {
+    let mut out = parsimon_run_opts_default();
+    if opts.is_null() {
+        return Ok(out);
+    }
+    let size = ptr::addr_of!((*opts).struct_size).read();
+    if size < mem::size_of::<usize>() {
+        return Err(FfiError::InvalidOptsSize(size));
+    }
+    let len = size.min(mem::size_of::<ParsimonRunOpts>());
+    ptr::copy_nonoverlapping(opts.cast::<u8>(), ptr::addr_of_mut!(out).cast::<u8>(), len);
+    out.struct_size = mem::size_of::<ParsimonRunOpts>();
+    Ok(out)
+}
+
+unsafe fn workers(workers: *const c_char) -> Result<Vec<SocketAddr>, FfiError> {
+    if workers.is_null() {
+        return Ok(Vec::new());
+    }
+    let workers = CStr::from_ptr(workers)
+        .to_str()
+        .map_err(|_| FfiError::InvalidWorkers)?;
+    workers
+        .split(',')
+        .map(str::trim)
+        .filter(|w| !w.is_empty())
+        .map(|w| Ok(w.parse()?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        let e = parsimon_last_error();
+        assert!(!e.is_null());
+        unsafe { CStr::from_ptr(e) }.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn malformed_specs_are_reported() {
+        let mut out = ptr::null_mut();
+        let spec = b"{\"nodes\": []";
+        let status = unsafe {
+            parsimon_run(
+                spec.as_ptr(),
+                spec.len(),
+                PARSIMON_FORMAT_JSON,
+                ptr::null(),
+                &mut out,
+            )
+        };
+        assert_eq!(status, ParsimonStatus::InvalidArgument);
+        assert!(out.is_null());
+        assert!(last_error().starts_with("Failed to parse JSON specification: "));
+
+        let status = unsafe { parsimon_run(spec.as_ptr(), spec.len(), 7, ptr::null(), &mut out) };
+        assert_eq!(status, ParsimonStatus::InvalidArgument);
+        assert_eq!(last_error(), "Unknown specification format 7");
+
+        let opts = ParsimonRunOpts {
+            workers: c"127.0.0.1:1,localhost".as_ptr(),
+            ..parsimon_run_opts_default()
+        };
+        let spec = br#"{"nodes": [], "links": [], "flows": []}"#;
+        let status = unsafe {
+            parsimon_run(
+                spec.as_ptr(),
+                spec.len(),
+                PARSIMON_FORMAT_JSON,
+                &opts,
+                &mut out,
+            )
+        };
+        assert_eq!(status, ParsimonStatus::InvalidArgument);
+        assert!(last_error().starts_with("Invalid worker address"));
+    }
+
+    fn spec() -> Vec<u8> {
+        let (nodes, links) = parsimon::core::testing::eight_node_config();
+        let flows = (0..20)
+            .map(|i| Flow {
+                id: parsimon::core::network::FlowId::new(i),
+                src: NodeId::new(i % 2),
+                dst: NodeId::new(2 + i % 2),
+                size: Bytes::new(1000 * (i as u64 + 1)),
+                start: Nanosecs::new(i as u64 * 1000),
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_vec(&serde_json::json!({
+            "nodes": nodes,
+            "links": links,
+            "flows": flows,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn specs_are_run_and_queried() {
+        let spec = spec();
+        let mut delays = ptr::null_mut();
+        let status = unsafe {
+            parsimon_run(
+                spec.as_ptr(),
+                spec.len(),
+                PARSIMON_FORMAT_JSON,
+                ptr::null(),
+                &mut delays,
+            )
+        };
+        assert_eq!(status, ParsimonStatus::Ok);
+        assert!(!delays.is_null());
+
+        let (mut delay, mut ideal) = (0, 0);
+        let status = unsafe { parsimon_predict(delays, 5000, 0, 2, 42, &mut delay) };
+        assert_eq!(status, ParsimonStatus::Ok);
+        let status = unsafe { parsimon_ideal_fct(delays, 5000, 0, 2, 42, &mut ideal) };
+        assert_eq!(status, ParsimonStatus::Ok);
+        assert!(ideal > 0);
+
+        let (sizes, srcs, dsts) = ([5000, 5000], [0, 1], [2, 3]);
+        let mut many = [0; 2];
+        let status = unsafe {
+            parsimon_predict_many(
+                delays,
+                2,
+                sizes.as_ptr(),
+                srcs.as_ptr(),
+                dsts.as_ptr(),
+                42,
+                many.as_mut_ptr(),
+            )
+        };
+        assert_eq!(status, ParsimonStatus::Ok);
+        assert!(many.iter().all(|&d| d != PARSIMON_MISSING_DELAY));
+        unsafe { parsimon_delay_network_free(delays) };
+    }
+
+    #[test]
+    fn options_from_older_callers_keep_defaults() {
+        let spec = spec();
+        let mut delays = ptr::null_mut();
+        // A caller which only knows about `struct_size` and a garbage window
+        let opts = ParsimonRunOpts {
+            struct_size: mem::size_of::<usize>(),
+            window: 0,
+            ..parsimon_run_opts_default()
+        };
+        assert_eq!(unsafe { run_opts(&opts) }.unwrap().window, 18_000);
+        let status = unsafe {
+            parsimon_run(
+                spec.as_ptr(),
+                spec.len(),
+                PARSIMON_FORMAT_JSON,
+                &opts,
+                &mut delays,
+            )
+        };
+        assert_eq!(status, ParsimonStatus::Ok);
+        unsafe { parsimon_delay_network_free(delays) };
+
+        let opts = ParsimonRunOpts {
+            struct_size: 0,
+            ..parsimon_run_opts_default()
+        };
+        let mut out = ptr::null_mut();
+        let status = unsafe {
+            parsimon_run(
+                spec.as_ptr(),
+                spec.len(),
+                PARSIMON_FORMAT_JSON,
+                &opts,
+                &mut out,
+            )
+        };
+        assert_eq!(status, ParsimonStatus::InvalidArgument);
+        assert!(out.is_null());
+        assert_eq!(last_error(), "Run options have invalid size 0");
+    }
+
+    #[test]
+    fn null_pointers_are_rejected() {
+        let mut delay = 0;
+        let status = unsafe { parsimon_predict(ptr::null(), 1000, 0, 1, 0, &mut delay) };
+        assert_eq!(status, ParsimonStatus::InvalidArgument);
+        assert_eq!(last_error(), "Null pointer passed as `delays`");
+        let status = unsafe {
+            parsimon_run(
+                ptr::null(),
+                0,
+                PARSIMON_FORMAT_JSON,
+                ptr::null(),
+                ptr::null_mut(),
+            )
+        };
+        assert_eq!(status, ParsimonStatus::InvalidArgument);
+        assert_eq!(last_error(), "Null pointer passed as `out`");
+        unsafe { parsimon_delay_network_free(ptr::null_mut()) };
+    }
+}
@@ -0,0 +1,395 @@
+//! Python bindings for the Parsimon driver API, built with [PyO3](https://pyo3.rs). The module
+//! exposes topology and flow loading, [`run`](parsimon::core::run::run) with the Minim link
+//! simulator and default clustering, and point, quantile, and ideal-FCT queries on the resulting
+//! delay network.
+//!
+//! Build and install the module into the current virtual environment with
+//! [maturin](https://www.maturin.rs):
+//!
+//! ```bash
+//! $ cd crates/parsimon-py
+//! $ maturin develop --release
+//! ```
+//!
+//! ```python
+//! import parsimon
+//!
+//! topology = parsimon.Topology.load("topology.json")
+//! flows = parsimon.load_flows("flows.msgpack")
+//! delays = parsimon.run(topology, flows)
+//! delays.predict(10_000, 0, 3, seed=0)
+//! delays.quantiles(10_000, 0, 3, [0.5, 0.99], seed=0)
+//! ```
+//!
+//! Sizes are in bytes, times and delays in nanoseconds, and nodes are referred to by ID.
+
+#![warn(unreachable_pub, missing_docs)]
+
+use std::{
+    net::{AddrParseError, SocketAddr},
+    path::PathBuf,
+};
+
+use parsimon::{
+    core::{
+        cluster::DefaultClustering,
+        edist::EDist,
+        network::{
+            types::{Flow, FlowId, Link, Node, NodeId},
+            DelayNetwork,
+        },
+        opts::SimOpts,
+        run::run as run_spec,
+        spec::Spec,
+        units::{BitsPerSec, Bytes, Nanosecs},
+    },
+    impls::linksim::MinimLink,
+};
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+/// A topology of nodes and links.
+#[pyclass(module = "parsimon", name = "Topology")]
+#[derive(Debug, Clone)]
+struct PyTopology {
+    nodes: Vec<Node>,
+    links: Vec<Link>,
+}
+
+#[pymethods]
+impl PyTopology {
+    /// Reads a topology from a JSON, YAML, or Dhall file, or imports one from a GraphML or DOT
+    /// graph.
+    #[staticmethod]
+    fn load(path: PathBuf) -> PyResult<Self> {
+        let spec = parsimon::utils::read_topology_spec(path).map_err(to_py_err)?;
+        Ok(Self {
+            nodes: spec.nodes,
+            links: spec.links,
+        })
+    }
+
+    /// The number of nodes.
+    #[getter]
+    fn nr_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// The number of links.
+    #[getter]
+    fn nr_links(&self) -> usize {
+        self.links.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Topology(nr_nodes={}, nr_links={})",
+            self.nodes.len(),
+            self.links.len()
+        )
+    }
+}
+
+/// A flow of `size` bytes from `src` to `dst`, starting at `start` nanoseconds.
+#[pyclass(module = "parsimon", name = "Flow")]
+#[derive(Debug, Clone, Copy)]
+struct PyFlow(Flow);
+
+#[pymethods]
+impl PyFlow {
+    #[new]
+    fn new(id: usize, src: usize, dst: usize, size: u64, start: u64) -> Self {
+        Self(Flow {
+            id: FlowId::new(id),
+            src: NodeId::new(src),
+            dst: NodeId::new(dst),
+            size: Bytes::new(size),
+            start: Nanosecs::new(start),
+        })
+    }
+
+    /// The flow ID.
+    #[getter]
+    fn id(&self) -> usize {
+        self.0.id.inner()
+    }
+
+    /// The source host.
+    #[getter]
+    fn src(&self) -> usize {
+        self.0.src.inner()
+    }
+
+    /// The destination host.
+    #[getter]
+    fn dst(&self) -> usize {
+        self.0.dst.inner()
+    }
+
+    /// The size in bytes.
+    #[getter]
+    fn size(&self) -> u64 {
+        self.0.size.into_u64()
+    }
+
+    /// The start time in nanoseconds.
+    #[getter]
+    fn start(&self) -> u64 {
+        self.0.start.into_u64()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Flow(id={}, src={}, dst={}, size={}, start={})",
+            self.id(),
+            self.src(),
+            self.dst(),
+            self.size(),
+            self.start()
+        )
+    }
+}
+
+/// Reads flows from a JSON, YAML, or MessagePack file.
+#[pyfunction]
+fn load_flows(path: PathBuf) -> PyResult<Vec<PyFlow>> {
+    let flows = parsimon::utils::read_flows(path).map_err(to_py_err)?;
+    Ok(flows.into_iter().map(PyFlow).collect())
+}
+
+/// Simulates `flows` on `topology` with the Minim link simulator, returning a network of delay
+/// distributions. Link simulations run on `workers` (`"host:port"` strings) if given, and locally
+/// otherwise. The GIL is released while simulating.
+#[pyfunction]
+#[pyo3(signature = (
+    topology,
+    flows,
+    *,
+    window = 18_000,
+    dctcp_gain = 0.0625,
+    dctcp_ai = 615_000_000,
+    workers = None
+))]
+fn run(
+    py: Python<'_>,
+    topology: PyRef<'_, PyTopology>,
+    flows: Vec<PyFlow>,
+    window: u64,
+    dctcp_gain: f64,
+    dctcp_ai: u64,
+    workers: Option<Vec<String>>,
+) -> PyResult<PyDelayNetwork> {
+    let spec = to_spec(&topology, flows);
+    let opts = sim_opts(window, dctcp_gain, dctcp_ai, workers)
+        .map_err(|e| PyValueError::new_err(format!("invalid worker address: {e}")))?;
+    let inner = py
+        .allow_threads(|| run_spec(spec, opts, DefaultClustering))
+        .map_err(to_py_err)?;
+    Ok(PyDelayNetwork { inner })
+}
+
+/// A network of delay distributions, returned by `run`. Queries without a `seed` draw from a
+/// randomly seeded generator.
+#[pyclass(module = "parsimon", name = "DelayNetwork")]
+#[derive(Debug)]
+struct PyDelayNetwork {
+    inner: DelayNetwork,
+}
+
+#[pymethods]
+impl PyDelayNetwork {
+    /// Predicts the FCT delay of a flow of `size` bytes from `src` to `dst`, or `None` if there is
+    /// no prediction.
+    #[pyo3(signature = (size, src, dst, *, seed = None))]
+    fn predict(&self, size: u64, src: usize, dst: usize, seed: Option<u64>) -> Option<u64> {
+        self.inner
+            .predict(Bytes::new(size), endpoints(src, dst), rng(seed))
+            .map(Nanosecs::into_u64)
+    }
+
+    /// Predicts the FCT delay of every flow in `flows`, in order.
+    #[pyo3(signature = (flows, *, seed = None))]
+    fn predict_many(&self, flows: Vec<PyFlow>, seed: Option<u64>) -> Vec<Option<u64>> {
+        let mut rng = rng(seed);
+        flows
+            .iter()
+            .map(|PyFlow(f)| {
+                self.inner
+                    .predict(f.size, (f.src, f.dst), &mut rng)
+                    .map(Nanosecs::into_u64)
+            })
+            .collect()
+    }
+
+    /// Estimates quantiles `qs` of the FCT delay of a flow of `size` bytes from `src` to `dst`
+    /// from `nr_samples` predictions, or returns `None` if there is no prediction.
+    #[pyo3(signature = (size, src, dst, qs, *, nr_samples = 10_000, seed = None))]
+    fn quantiles(
+        &self,
+        size: u64,
+        src: usize,
+        dst: usize,
+        qs: Vec<f64>,
+        nr_samples: usize,
+        seed: Option<u64>,
+    ) -> PyResult<Option<Vec<u64>>> {
+        if let Some(q) = qs.iter().find(|q| !(0.0..=1.0).contains(*q)) {
+            return Err(PyValueError::new_err(format!(
+                "quantile {q} is not in [0, 1]"
+            )));
+        }
+        Ok(sample_quantiles(
+            &self.inner,
+            Bytes::new(size),
+            endpoints(src, dst),
+            &qs,
+            nr_samples,
+            rng(seed),
+        ))
+    }
+
+    /// Returns the FCT of a flow of `size` bytes from `src` to `dst` on an unloaded network, or
+    /// `None` if there is no path.
+    #[pyo3(signature = (size, src, dst, *, seed = None))]
+    fn ideal_fct(&self, size: u64, src: usize, dst: usize, seed: Option<u64>) -> Option<u64> {
+        self.inner
+            .ideal_fct(Bytes::new(size), endpoints(src, dst), rng(seed))
+            .map(Nanosecs::into_u64)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("DelayNetwork(nr_nodes={})", self.inner.nodes().count())
+    }
+}
+
+fn to_spec(topology: &PyTopology, flows: Vec<PyFlow>) -> Spec {
+    Spec::builder()
+        .nodes(topology.nodes.clone())
+        .links(topology.links.clone())
+        .flows(flows.into_iter().map(|f| f.0).collect())
+        .build()
+}
+
+fn sim_opts(
+    window: u64,
+    dctcp_gain: f64,
+    dctcp_ai: u64,
+    workers: Option<Vec<String>>,
+) -> Result<SimOpts<MinimLink>, AddrParseError> {
+    let minim = MinimLink::builder()
+        .window(Bytes::new(window))
+        .dctcp_gain(dctcp_gain)
+        .dctcp_ai(BitsPerSec::new(dctcp_ai))
+        .build();
+    Ok(match workers {
+        Some(workers) => {
+            let workers = workers
+                .iter()
+                .map(|w| w.parse::<SocketAddr>())
+                .collect::<Result<Vec<_>, _>>()?;
+            SimOpts::builder().link_sim(minim).workers(workers).build()
+        }
+        None => SimOpts::builder().link_sim(minim).build(),
+    })
+}
+
+// Nearest-rank quantiles `qs`, which must be in [0, 1], of `nr_samples` predictions
+fn sample_quantiles(
+    delays: &DelayNetwork,
+    size: Bytes,
+    pair: (NodeId, NodeId),
+    qs: &[f64],
+    nr_samples: usize,
+    mut rng: StdRng,
+) -> Option<Vec<u64>> {
+    let samples = (0..nr_samples)
+        .map(|_| delays.predict(size, pair, &mut rng).map(Nanosecs::into_f64))
+        .collect::<Option<Vec<_>>>()?;
+    let dist = EDist::from_values(&samples).ok()?;
+    qs.iter()
+        .map(|&q| dist.quantile(q).map(|d| d.round() as u64))
+        .collect()
+}
+
+fn endpoints(src: usize, dst: usize) -> (NodeId, NodeId) {
+    (NodeId::new(src), NodeId::new(dst))
+}
+
+fn rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+// Errors are reported with their full chain of causes
+fn to_py_err(e: impl std::error::Error + Send + Sync + 'static) -> PyErr {
+    PyRuntimeError::new_err(format!("{:#}", anyhow::Error::new(e)))
+}
+
+/// Parsimon: fast tail latency estimates for data center networks.
+#[pymodule]
+#[pyo3(name = "parsimon")]
+fn parsimon_py(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyTopology>()?;
+    m.add_class::<PyFlow>()?;
+    m.add_class::<PyDelayNetwork>()?;
+    m.add_function(wrap_pyfunction!(load_flows, m)?)?;
+    m.add_function(wrap_pyfunction!(run, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use parsimon::core::testing;
+
+    use super::*;
+
+    #[test]
+    fn specs_carry_topologies_and_flows() {
+        let (nodes, links) = testing::three_node_config();
+        let topology = PyTopology {
+            nodes: nodes.clone(),
+            links: links.clone(),
+        };
+        let flow = PyFlow::new(7, 0, 1, 1_000, 42);
+        assert_eq!(
+            (flow.id(), flow.src(), flow.dst(), flow.size(), flow.start()),
+            (7, 0, 1, 1_000, 42)
+        );
+        let spec = to_spec(&topology, vec![flow]);
+        assert_eq!(spec.nodes, nodes);
+        assert_eq!(spec.links.len(), links.len());
+        assert_eq!(spec.flows, vec![flow.0]);
+    }
+
+    #[test]
+    fn worker_addresses_are_validated() {
+        let workers = vec!["10.0.0.1:8080".to_owned()];
+        let opts = sim_opts(18_000, 0.0625, 615_000_000, Some(workers)).unwrap();
+        assert_eq!(opts.workers, vec!["10.0.0.1:8080".parse().unwrap()]);
+        assert!(sim_opts(18_000, 0.0625, 615_000_000, Some(vec!["nowhere".into()])).is_err());
+        // Without workers, links are simulated locally
+        let opts = sim_opts(18_000, 0.0625, 615_000_000, None).unwrap();
+        assert_eq!(opts.workers, vec!["127.0.0.1:8080".parse().unwrap()]);
+    }
+
+    #[test]
+    fn quantiles_are_sampled_from_predictions() -> anyhow::Result<()> {
+        let delays = testing::fixed_delay_network(Nanosecs::new(100))?;
+        let size = Bytes::new(1_000);
+        let pair = endpoints(0, 2);
+        let rng = StdRng::seed_from_u64(0);
+        let qs = sample_quantiles(&delays, size, pair, &[0.0, 0.5, 1.0], 100, rng).unwrap();
+        assert!(qs.windows(2).all(|w| w[0] <= w[1]));
+        let rng = StdRng::seed_from_u64(0);
+        assert_eq!(
+            sample_quantiles(&delays, size, endpoints(0, 0), &[0.5], 100, rng),
+            None
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,336 @@
+//! Experiment configurations. A [`Config`] describes everything about a run besides its inputs:
+//! the link simulator and its parameters, the workers, the bucketing options, and the clustering
+//! algorithm. Configurations can be read from TOML, JSON, or Dhall files with
+//! [`read_config`](crate::read_config) or built programmatically, so an experiment is fully
+//! described by its inputs and one configuration file.
+//!
+//! ```toml
+//! workers = ["10.0.0.1:8080", "10.0.0.2:8080"]
+//! seed = 42
+//!
+//! [link_sim]
+//! backend = "minim"
+//! window = 18000
+//! dctcp_gain = 0.0625
+//! dctcp_ai = 615000000
+//!
+//! [bucket_opts]
+//! x = 2
+//! b = 100
+//!
+//! [clustering]
+//! algorithm = "greedy"
+//! threshold = 0.1
+//! ```
+//!
+//! A top-level `seed` makes runs reproducible; see [`SimOpts::seed`]. An `on_failure` section
+//! retries failed link simulations and falls back to another link simulator or to empty
+//! distributions (see [`parsimon_core::network::fallback`]):
+//!
+//! ```toml
+//! [on_failure]
+//! retries = 2
+//! timeout_secs = 3600
+//! fallback = { kind = "simulator", backend = "fluid", window = 18000, dctcp_gain = 0.0625, dctcp_ai = 615000000 }
+//! ```
+//!
+//! Every section is optional, and missing sections take their defaults.
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use clustering_impls::{
+    feature::{self, DistsAndLoad},
+    greedy::GreedyClustering,
+};
+use linksim_impls::{FluidLink, MinimLink, Ns3Link};
+use parsimon_core::{
+    cluster::{ClusteringAlgo, DefaultClustering},
+    edist::BucketOpts,
+    linksim::{DynLinkSim, LinkSim},
+    network::{
+        fallback::{FailurePolicy, Fallback},
+        DelayNetwork, SimNetwork,
+    },
+    opts::SimOpts,
+    routing::RoutingAlgo,
+    run::{self, run_timed, StageDurations},
+    spec::Spec,
+    units::{Bytes, Mbps},
+};
+
+/// A configuration of everything about a run besides its inputs.
+#[derive(
+    Debug, Clone, Default, typed_builder::TypedBuilder, serde::Serialize, serde::Deserialize,
+)]
+pub struct Config {
+    /// The link simulator.
+    #[builder(default)]
+    #[serde(default)]
+    pub link_sim: LinkSimConfig,
+    /// Worker addresses. Link simulations run locally if empty.
+    #[builder(default)]
+    #[serde(default)]
+    pub workers: Vec<SocketAddr>,
+    /// Bucketing parameters.
+    #[builder(default)]
+    #[serde(default)]
+    pub bucket_opts: BucketOpts,
+    /// The clustering algorithm.
+    #[builder(default)]
+    #[serde(default)]
+    pub clustering: ClusteringConfig,
+    /// The seed every random choice of a run is derived from. See [`SimOpts::seed`].
+    #[builder(default)]
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// What to do when a link simulation fails.
+    #[builder(default)]
+    #[serde(default)]
+    pub on_failure: FailureConfig,
+}
+
+impl Config {
+    /// Runs `spec` as configured.
+    pub fn run(&self, spec: Spec) -> Result<DelayNetwork, run::Error> {
+        self.run_timed(spec).map(|(delays, _)| delays)
+    }
+
+    /// Like [`Config::run`], but also returns how long each stage took.
+    pub fn run_timed(&self, spec: Spec) -> Result<(DelayNetwork, StageDurations), run::Error> {
+        self.run_with(spec, self.link_sim.build())
+    }
+
+    fn run_with<S>(&self, spec: Spec, sim: S) -> Result<(DelayNetwork, StageDurations), run::Error>
+    where
+        S: LinkSim + Sync,
+    {
+        let opts = self.sim_opts(sim);
+        match self.clustering {
+            ClusteringConfig::Default => run_timed(spec, opts, DefaultClustering),
+            ClusteringConfig::Greedy { threshold, seed } => {
+                run_timed(spec, opts, greedy(threshold, seed))
+            }
+        }
+    }
+
+    /// Returns simulation options with the configured workers, bucketing options, and seed, for
+    /// runs with a link simulator other than the configured one.
+    pub fn sim_opts<S>(&self, link_sim: S) -> SimOpts<S>
+    where
+        S: LinkSim,
+    {
+        let mut opts = if self.workers.is_empty() {
+            SimOpts::builder()
+                .link_sim(link_sim)
+                .bucket_opts(self.bucket_opts)
+                .build()
+        } else {
+            SimOpts::builder()
+                .link_sim(link_sim)
+                .workers(self.workers.clone())
+                .bucket_opts(self.bucket_opts)
+                .build()
+        };
+        opts.seed = self.seed;
+        opts.on_failure = self.on_failure.policy();
+        opts.timeout = self.on_failure.timeout_secs.map(Duration::from_secs_f64);
+        opts
+    }
+}
+
+/// What to do when a link simulation fails. See [`FailurePolicy`].
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct FailureConfig {
+    /// The number of times a failed simulation is retried.
+    #[serde(default)]
+    pub retries: usize,
+    /// What to do once every retry has failed.
+    #[serde(default)]
+    pub fallback: FallbackConfig,
+    /// If set, link simulations which take longer than this many seconds are skipped. See
+    /// [`SimOpts::timeout`].
+    #[serde(default)]
+    pub timeout_secs: Option<f64>,
+}
+
+impl FailureConfig {
+    /// Returns the configured failure policy.
+    pub fn policy(&self) -> FailurePolicy {
+        let fallback = match &self.fallback {
+            FallbackConfig::Abort => Fallback::Abort,
+            FallbackConfig::Simulator(sim) => Fallback::Simulator(sim.build()),
+            FallbackConfig::Empty => Fallback::Empty,
+        };
+        FailurePolicy::builder()
+            .retries(self.retries)
+            .fallback(fallback)
+            .build()
+    }
+}
+
+/// The last resort for a link simulation which keeps failing, tagged by `kind`. See
+/// [`Fallback`].
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FallbackConfig {
+    /// Fail the run.
+    #[default]
+    Abort,
+    /// Simulate with another link simulator.
+    Simulator(LinkSimConfig),
+    /// Leave the link's delay distributions empty.
+    Empty,
+}
+
+/// A link simulator and its parameters, tagged by `backend`.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum LinkSimConfig {
+    /// [`MinimLink`].
+    Minim(MinimLink),
+    /// [`Ns3Link`].
+    Ns3(Ns3Link),
+    /// [`FluidLink`].
+    Fluid(FluidLink),
+}
+
+impl LinkSimConfig {
+    /// Returns the configured link simulator.
+    pub fn build(&self) -> DynLinkSim {
+        match self {
+            Self::Minim(sim) => Arc::new(sim.clone()),
+            Self::Ns3(sim) => Arc::new(sim.clone()),
+            Self::Fluid(sim) => Arc::new(sim.clone()),
+        }
+    }
+}
+
+impl Default for LinkSimConfig {
+    /// Minim with the DCTCP parameters used in the Parsimon paper.
+    fn default() -> Self {
+        Self::Minim(
+            MinimLink::builder()
+                .window(Bytes::new(18_000))
+                .dctcp_gain(0.0625)
+                .dctcp_ai(Mbps::new(615))
+                .build(),
+        )
+    }
+}
+
+/// A clustering algorithm and its parameters, tagged by `algorithm`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "algorithm", rename_all = "snake_case")]
+pub enum ClusteringConfig {
+    /// [`DefaultClustering`], which simulates every link.
+    #[default]
+    Default,
+    /// [`GreedyClustering`] of links by their flow size and inter-arrival time distributions and
+    /// loads (see [`feature::dists_and_load`]).
+    Greedy {
+        /// The largest [distance](feature::dists_and_load_distance) between links clustered
+        /// together.
+        threshold: f64,
+        /// If set, links are visited in a random order determined by the seed.
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+}
+
+impl ClusteringConfig {
+    /// Clusters the links of `network` with the configured algorithm.
+    pub fn cluster<R>(&self, network: &mut SimNetwork<R>)
+    where
+        R: RoutingAlgo + Sync,
+    {
+        match *self {
+            Self::Default => network.cluster(DefaultClustering),
+            Self::Greedy { threshold, seed } => network.cluster(greedy(threshold, seed)),
+        }
+    }
+}
+
+fn greedy(threshold: f64, seed: Option<u64>) -> impl ClusteringAlgo {
+    let clustering = GreedyClustering::new(
+        feature::dists_and_load,
+        move |a: &Option<DistsAndLoad>, b: &Option<DistsAndLoad>| {
+            feature::dists_and_load_distance(a, b) <= threshold
+        },
+    );
+    match seed {
+        Some(seed) => clustering.with_seed(seed),
+        None => clustering,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configs_parse_with_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            workers = ["10.0.0.1:8080"]
+
+            [link_sim]
+            backend = "minim"
+            window = 20000
+            dctcp_gain = 0.1
+            dctcp_ai = 1000
+
+            [clustering]
+            algorithm = "greedy"
+            threshold = 0.05
+            "#,
+        )
+        .unwrap();
+        let LinkSimConfig::Minim(minim) = &config.link_sim else {
+            panic!("expected minim");
+        };
+        assert_eq!(minim.dctcp_gain, 0.1);
+        assert_eq!(config.workers.len(), 1);
+        assert_eq!(config.bucket_opts.b, BucketOpts::default().b);
+        assert_eq!(
+            config.clustering,
+            ClusteringConfig::Greedy {
+                threshold: 0.05,
+                seed: None
+            }
+        );
+
+        let config: Config = serde_json::from_str("{}").unwrap();
+        assert!(matches!(config.link_sim, LinkSimConfig::Minim(_)));
+        assert_eq!(config.clustering, ClusteringConfig::Default);
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains(r#""backend":"minim""#));
+    }
+
+    #[test]
+    fn failure_policies_parse() {
+        let config: Config = toml::from_str(
+            r#"
+            [on_failure]
+            retries = 2
+            timeout_secs = 600
+            fallback = { kind = "simulator", backend = "fluid", window = 18000, dctcp_gain = 0.0625, dctcp_ai = 615000000 }
+            "#,
+        )
+        .unwrap();
+        let policy = config.on_failure.policy();
+        assert_eq!(policy.retries, 2);
+        assert_eq!(
+            config.sim_opts(config.link_sim.build()).timeout,
+            Some(Duration::from_secs(600))
+        );
+        let Fallback::Simulator(sim) = policy.fallback else {
+            panic!("expected a fallback simulator");
+        };
+        assert_eq!(sim.name(), "fluid");
+        let config: Config = toml::from_str("on_failure.fallback.kind = 'empty'").unwrap();
+        assert!(matches!(
+            config.on_failure.policy().fallback,
+            Fallback::Empty
+        ));
+    }
+}
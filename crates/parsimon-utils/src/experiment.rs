@@ -0,0 +1,130 @@
+//! Experiment specifications. An [`Experiment`] describes a whole run in one document: the
+//! topology, a reference to the flows, an optional scenario, and the run's
+//! [configuration](crate::config::Config), including the link simulator and clustering
+//! parameters. Experiments are read from JSON or Dhall files with
+//! [`read_experiment`](crate::read_experiment), so the driver and tests share one schema.
+//!
+//! ```json
+//! {
+//!   "topology": { "nodes": [...], "links": [...] },
+//!   "flows": "flows.msgpack",
+//!   "config": {
+//!     "link_sim": { "backend": "minim", "window": 18000, "dctcp_gain": 0.0625, "dctcp_ai": 615000000 },
+//!     "clustering": { "algorithm": "greedy", "threshold": 0.1 }
+//!   }
+//! }
+//! ```
+//!
+//! Relative paths are resolved against the directory of the experiment file. Missing `scenario`
+//! and `config` fields take their defaults.
+
+use std::path::{Path, PathBuf};
+
+use parsimon_core::spec::Spec;
+
+use crate::{config::Config, manifest::InputDigest, Error, TopologySpec};
+
+/// A complete description of a run.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Experiment {
+    /// The topology.
+    pub topology: TopologySpec,
+    /// The flows file, in JSON or MsgPack format.
+    pub flows: PathBuf,
+    /// The scenario file of link failures and degradations, in JSON or Dhall format, if any.
+    #[serde(default)]
+    pub scenario: Option<PathBuf>,
+    /// The run's configuration.
+    #[serde(default)]
+    pub config: Config,
+}
+
+impl Experiment {
+    /// Reads the flows and scenario and returns the specification of the run.
+    pub fn spec(&self) -> Result<Spec, Error> {
+        let flows = crate::read_flows(&self.flows)?;
+        let scenario = match &self.scenario {
+            Some(path) => crate::read_scenario(path)?,
+            None => Default::default(),
+        };
+        Ok(Spec::builder()
+            .nodes(self.topology.nodes.clone())
+            .links(self.topology.links.clone())
+            .flows(flows)
+            .scenario(scenario)
+            .build())
+    }
+
+    /// Returns the digests of the files the experiment references, for a
+    /// [manifest](crate::manifest::Manifest).
+    pub fn digests(&self) -> Result<Vec<InputDigest>, Error> {
+        let mut digests = vec![InputDigest::of("flows", &self.flows)?];
+        if let Some(path) = &self.scenario {
+            digests.push(InputDigest::of("scenario", path)?);
+        }
+        Ok(digests)
+    }
+
+    // Resolves relative paths against `dir`
+    pub(crate) fn resolve(mut self, dir: &Path) -> Self {
+        self.flows = dir.join(&self.flows);
+        self.scenario = self.scenario.map(|path| dir.join(path));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use parsimon_core::{
+        network::{Flow, FlowId, NodeId},
+        testing,
+        units::{Bytes, Nanosecs},
+    };
+
+    use super::*;
+    use crate::config::ClusteringConfig;
+
+    #[test]
+    fn experiments_resolve_relative_paths() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let flows = vec![Flow {
+            id: FlowId::new(0),
+            src: NodeId::new(0),
+            dst: NodeId::new(1),
+            size: Bytes::new(1_000),
+            start: Nanosecs::ZERO,
+        }];
+        std::fs::write(
+            dir.path().join("flows.json"),
+            serde_json::to_string(&flows)?,
+        )?;
+        let (nodes, links) = testing::three_node_config();
+        let topology = serde_json::to_string(&TopologySpec { nodes, links })?;
+        let path = dir.path().join("experiment.json");
+        write!(
+            std::fs::File::create(&path)?,
+            r#"{{
+                "topology": {topology},
+                "flows": "flows.json",
+                "config": {{ "clustering": {{ "algorithm": "greedy", "threshold": 0.1 }} }}
+            }}"#
+        )?;
+        let experiment = crate::read_experiment(&path)?;
+        assert_eq!(experiment.flows, dir.path().join("flows.json"));
+        assert_eq!(experiment.scenario, None);
+        assert_eq!(
+            experiment.config.clustering,
+            ClusteringConfig::Greedy {
+                threshold: 0.1,
+                seed: None
+            }
+        );
+        let spec = experiment.spec()?;
+        assert_eq!(spec.flows, flows);
+        assert_eq!(spec.nodes.len(), 3);
+        assert_eq!(experiment.digests()?.len(), 1);
+        Ok(())
+    }
+}
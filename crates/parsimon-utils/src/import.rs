@@ -0,0 +1,522 @@
+//! Importers for topologies in graph formats. Topologies exported from network modeling tools as
+//! GraphML or DOT can be converted into a [`TopologySpec`] with [`from_graphml`] and [`from_dot`],
+//! or read directly with [`read_topology_spec`](crate::read_topology_spec) from files with a
+//! `.graphml`, `.dot`, or `.gv` extension.
+//!
+//! Every edge becomes a symmetric [`Link`]. Nodes and edges are described by attributes:
+//!
+//! - `bandwidth` (edges): the link bandwidth, e.g., `10Gbps`, or a number of bits per second.
+//! - `delay` (edges): the propagation delay, e.g., `1us`, or a number of nanoseconds.
+//! - `kind` (nodes): `host` or `switch`. Nodes without a kind are hosts if they have a single
+//!   link and switches otherwise.
+//!
+//! Edges without a `bandwidth` or `delay` take the defaults in [`ImportOpts`], if set. Nodes are
+//! numbered in order of first appearance. The DOT importer supports node, edge, and default
+//! attribute statements, but not subgraphs.
+//!
+//! ```dot
+//! graph fabric {
+//!     edge [bandwidth="10Gbps", delay="1us"];
+//!     tor [kind=switch];
+//!     h0 -- tor;
+//!     h1 -- tor [bandwidth="25Gbps"];
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+
+use parsimon_core::{
+    network::{
+        types::{Link, Node},
+        NodeId,
+    },
+    units::{BitsPerSec, Nanosecs},
+};
+
+use crate::TopologySpec;
+
+/// Options for importing topologies.
+#[derive(Debug, Clone, Copy, Default, typed_builder::TypedBuilder)]
+pub struct ImportOpts {
+    /// The bandwidth of edges without a `bandwidth` attribute. Such edges are errors if unset.
+    #[builder(default, setter(strip_option))]
+    pub bandwidth: Option<BitsPerSec>,
+    /// The delay of edges without a `delay` attribute. Such edges are errors if unset.
+    #[builder(default, setter(strip_option))]
+    pub delay: Option<Nanosecs>,
+}
+
+type Attrs = BTreeMap<String, String>;
+
+// A graph as read from a file, before attributes are interpreted
+#[derive(Debug, Default)]
+struct RawGraph {
+    // Node names and attributes, in order of first appearance
+    nodes: Vec<(String, Attrs)>,
+    ids: BTreeMap<String, usize>,
+    edges: Vec<(usize, usize, Attrs)>,
+}
+
+impl RawGraph {
+    // Returns the index of the node named `name`, adding it with attributes `defaults` if it
+    // doesn't exist yet
+    fn node(&mut self, name: &str, defaults: &Attrs) -> usize {
+        if let Some(&i) = self.ids.get(name) {
+            return i;
+        }
+        self.nodes.push((name.to_owned(), defaults.clone()));
+        self.ids.insert(name.to_owned(), self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    fn into_spec(self, opts: &ImportOpts) -> Result<TopologySpec, ImportError> {
+        let mut degrees = vec![0; self.nodes.len()];
+        for &(a, b, _) in &self.edges {
+            degrees[a] += 1;
+            degrees[b] += 1;
+        }
+        let nodes = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, (name, attrs))| {
+                let id = NodeId::new(i);
+                match attrs.get("kind").map(String::as_str) {
+                    Some("host") => Ok(Node::new_host(id)),
+                    Some("switch") => Ok(Node::new_switch(id)),
+                    None if degrees[i] == 1 => Ok(Node::new_host(id)),
+                    None => Ok(Node::new_switch(id)),
+                    Some(kind) => Err(ImportError::InvalidAttribute {
+                        element: name.clone(),
+                        name: "kind".to_owned(),
+                        value: kind.to_owned(),
+                    }),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let links = self
+            .edges
+            .iter()
+            .map(|(a, b, attrs)| {
+                let element = format!("{} -- {}", self.nodes[*a].0, self.nodes[*b].0);
+                let bandwidth = attribute(attrs, "bandwidth", opts.bandwidth, &element)?;
+                let delay = attribute(attrs, "delay", opts.delay, &element)?;
+                Ok(Link::new(
+                    NodeId::new(*a),
+                    NodeId::new(*b),
+                    bandwidth,
+                    delay,
+                ))
+            })
+            .collect::<Result<Vec<_>, ImportError>>()?;
+        Ok(TopologySpec { nodes, links })
+    }
+}
+
+// Parses the attribute `name` of `element`, falling back to `default`
+fn attribute<T>(
+    attrs: &Attrs,
+    name: &str,
+    default: Option<T>,
+    element: &str,
+) -> Result<T, ImportError>
+where
+    T: std::str::FromStr,
+{
+    match attrs.get(name) {
+        Some(value) => value.parse().map_err(|_| ImportError::InvalidAttribute {
+            element: element.to_owned(),
+            name: name.to_owned(),
+            value: value.clone(),
+        }),
+        None => default.ok_or_else(|| ImportError::MissingAttribute {
+            element: element.to_owned(),
+            name: name.to_owned(),
+        }),
+    }
+}
+
+/// Converts a GraphML document into a topology specification.
+pub fn from_graphml(s: &str, opts: &ImportOpts) -> Result<TopologySpec, ImportError> {
+    let doc = roxmltree::Document::parse(s)?;
+    let root = doc.root_element();
+    // Attribute names, domains, and defaults, by key ID
+    let mut keys = BTreeMap::new();
+    for key in root.children().filter(|n| n.has_tag_name("key")) {
+        let (Some(id), Some(name)) = (key.attribute("id"), key.attribute("attr.name")) else {
+            continue;
+        };
+        let domain = key.attribute("for").unwrap_or("all");
+        let default = key
+            .children()
+            .find(|n| n.has_tag_name("default"))
+            .and_then(|n| n.text())
+            .map(|text| text.trim().to_owned());
+        keys.insert(id, (name, domain, default));
+    }
+    let data = |element: roxmltree::Node<'_, '_>, domain: &str| {
+        let mut attrs = keys
+            .values()
+            .filter(|&&(_, d, _)| d == domain || d == "all")
+            .filter_map(|(name, _, default)| Some((name.to_string(), default.clone()?)))
+            .collect::<Attrs>();
+        for datum in element.children().filter(|n| n.has_tag_name("data")) {
+            let Some(&(name, d, _)) = datum.attribute("key").and_then(|key| keys.get(key)) else {
+                continue;
+            };
+            if d != domain && d != "all" {
+                continue;
+            }
+            let value = datum.text().unwrap_or_default().trim().to_owned();
+            attrs.insert(name.to_string(), value);
+        }
+        attrs
+    };
+    let graph = root
+        .children()
+        .find(|n| n.has_tag_name("graph"))
+        .ok_or_else(|| ImportError::Syntax("missing <graph> element".to_owned()))?;
+    let mut raw = RawGraph::default();
+    // Nodes only referenced by edges have the default attributes of nodes
+    let node_defaults = data(graph, "node");
+    for node in graph.children().filter(|n| n.has_tag_name("node")) {
+        let name = node
+            .attribute("id")
+            .ok_or_else(|| ImportError::Syntax("<node> without an id".to_owned()))?;
+        let i = raw.node(name, &node_defaults);
+        raw.nodes[i].1 = data(node, "node");
+    }
+    for edge in graph.children().filter(|n| n.has_tag_name("edge")) {
+        let (Some(source), Some(target)) = (edge.attribute("source"), edge.attribute("target"))
+        else {
+            return Err(ImportError::Syntax(
+                "<edge> without a source or target".to_owned(),
+            ));
+        };
+        let (a, b) = (
+            raw.node(source, &node_defaults),
+            raw.node(target, &node_defaults),
+        );
+        raw.edges.push((a, b, data(edge, "edge")));
+    }
+    raw.into_spec(opts)
+}
+
+/// Converts a DOT graph into a topology specification.
+pub fn from_dot(s: &str, opts: &ImportOpts) -> Result<TopologySpec, ImportError> {
+    let tokens = tokenize(s)?;
+    let mut tokens = tokens.iter().map(String::as_str).peekable();
+    let mut header = next_token(&mut tokens, "`graph` or `digraph`")?;
+    if header.eq_ignore_ascii_case("strict") {
+        header = next_token(&mut tokens, "`graph` or `digraph`")?;
+    }
+    if !matches!(header, "graph" | "digraph") {
+        return Err(ImportError::Syntax(format!(
+            "expected `graph` or `digraph`, found `{header}`"
+        )));
+    }
+    // The graph's name is optional
+    let mut token = next_token(&mut tokens, "`{`")?;
+    if token != "{" {
+        token = next_token(&mut tokens, "`{`")?;
+    }
+    if token != "{" {
+        return Err(ImportError::Syntax(format!(
+            "expected `{{`, found `{token}`"
+        )));
+    }
+    let mut raw = RawGraph::default();
+    let (mut node_defaults, mut edge_defaults) = (Attrs::default(), Attrs::default());
+    // The current statement's tokens, up to a `;`, the start of the next statement, or `}`
+    let mut statement = Vec::new();
+    loop {
+        let token = next_token(&mut tokens, "`}`")?;
+        if token != ";" && token != "}" {
+            statement.push(token);
+            // Statements needn't end with `;`
+            if !ends_statement(&statement, tokens.peek().copied()) {
+                continue;
+            }
+        }
+        if !statement.is_empty() {
+            dot_statement(&statement, &mut raw, &mut node_defaults, &mut edge_defaults)?;
+            statement.clear();
+        }
+        if token == "}" {
+            break;
+        }
+    }
+    raw.into_spec(opts)
+}
+
+fn next_token<'a>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+    expected: &str,
+) -> Result<&'a str, ImportError> {
+    tokens
+        .next()
+        .ok_or_else(|| ImportError::Syntax(format!("expected {expected}, found end of input")))
+}
+
+// Whether `statement` is complete if `next` comes after it
+fn ends_statement(statement: &[&str], next: Option<&str>) -> bool {
+    let Some(next) = next else {
+        return true;
+    };
+    if matches!(next, ";" | "}") {
+        return false;
+    }
+    let last = statement[statement.len() - 1];
+    let open = statement.iter().filter(|&&t| t == "[").count()
+        > statement.iter().filter(|&&t| t == "]").count();
+    !open && !matches!(last, "--" | "->" | "=" | ",") && !matches!(next, "--" | "->" | "=" | "[")
+}
+
+fn dot_statement(
+    statement: &[&str],
+    raw: &mut RawGraph,
+    node_defaults: &mut Attrs,
+    edge_defaults: &mut Attrs,
+) -> Result<(), ImportError> {
+    let split = statement
+        .iter()
+        .position(|&t| t == "[")
+        .unwrap_or(statement.len());
+    let (head, attrs) = statement.split_at(split);
+    let attrs = dot_attrs(attrs)?;
+    match head {
+        ["subgraph", ..] | ["{", ..] => Err(ImportError::Syntax(
+            "subgraphs are not supported".to_owned(),
+        )),
+        ["node"] => {
+            node_defaults.extend(attrs);
+            Ok(())
+        }
+        ["edge"] => {
+            edge_defaults.extend(attrs);
+            Ok(())
+        }
+        ["graph"] | [_, "=", _] => Ok(()),
+        [name] => {
+            let i = raw.node(name, node_defaults);
+            raw.nodes[i].1.extend(attrs);
+            Ok(())
+        }
+        [first, rest @ ..] if !rest.is_empty() => {
+            let mut edge_attrs = edge_defaults.clone();
+            edge_attrs.extend(attrs);
+            let mut prev = raw.node(first, node_defaults);
+            for pair in rest.chunks(2) {
+                let [op, name] = pair else {
+                    return Err(ImportError::Syntax(format!(
+                        "incomplete edge statement `{}`",
+                        statement.join(" ")
+                    )));
+                };
+                if !matches!(*op, "--" | "->") {
+                    return Err(ImportError::Syntax(format!("unexpected `{op}`")));
+                }
+                let next = raw.node(name, node_defaults);
+                raw.edges.push((prev, next, edge_attrs.clone()));
+                prev = next;
+            }
+            Ok(())
+        }
+        _ => Err(ImportError::Syntax(format!(
+            "unexpected statement `{}`",
+            statement.join(" ")
+        ))),
+    }
+}
+
+// Parses attribute lists, e.g., `[a=1, b=2][c=3]`
+fn dot_attrs(tokens: &[&str]) -> Result<Attrs, ImportError> {
+    let mut attrs = Attrs::default();
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i..] {
+            ["[" | "]" | "," | ";", ..] => i += 1,
+            [name, "=", value, ..] => {
+                attrs.insert(name.to_string(), value.to_string());
+                i += 3;
+            }
+            _ => {
+                return Err(ImportError::Syntax(format!(
+                    "malformed attribute list `{}`",
+                    tokens.join(" ")
+                )))
+            }
+        }
+    }
+    Ok(attrs)
+}
+
+// Splits DOT source into tokens, dropping comments and unquoting strings
+fn tokenize(s: &str) -> Result<Vec<String>, ImportError> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '#' => {
+                chars.by_ref().take_while(|&c| c != '\n').for_each(drop);
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().take_while(|&c| c != '\n').for_each(drop);
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if prev == '*' => break,
+                        Some(c) => prev = c,
+                        None => return Err(ImportError::Syntax("unterminated comment".to_owned())),
+                    }
+                }
+            }
+            '"' => {
+                let mut token = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => token.extend(chars.next()),
+                        Some('"') => break,
+                        Some(c) => token.push(c),
+                        None => return Err(ImportError::Syntax("unterminated string".to_owned())),
+                    }
+                }
+                tokens.push(token);
+            }
+            '{' | '}' | '[' | ']' | ';' | ',' | '=' => tokens.push(c.to_string()),
+            '-' if matches!(chars.peek(), Some('-' | '>')) => {
+                tokens.push(format!("-{}", chars.next().unwrap()));
+            }
+            _ => {
+                let mut token = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || matches!(c, '_' | '.' | 'µ') {
+                        token.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(token);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Error type for importing topologies.
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    /// Malformed XML.
+    #[error("XML error")]
+    Xml(#[from] roxmltree::Error),
+
+    /// The document is not a graph in the expected format.
+    #[error("syntax error: {0}")]
+    Syntax(String),
+
+    /// An edge lacks an attribute without a default.
+    #[error("{element} has no `{name}` attribute")]
+    MissingAttribute {
+        /// The node or edge.
+        element: String,
+        /// The attribute name.
+        name: String,
+    },
+
+    /// An attribute value is invalid.
+    #[error("{element} has an invalid `{name}` attribute: {value}")]
+    InvalidAttribute {
+        /// The node or edge.
+        element: String,
+        /// The attribute name.
+        name: String,
+        /// The attribute value.
+        value: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use parsimon_core::{network::Network, units::Gbps};
+
+    use super::*;
+
+    #[test]
+    fn dot_graphs_import() -> anyhow::Result<()> {
+        let spec = from_dot(
+            r#"
+            strict graph fabric {
+                // Defaults apply to later statements
+                edge [bandwidth="10Gbps", delay="1us"];
+                tor [kind=switch]
+                h0 -- tor; h1 -- tor [bandwidth="25Gbps"]
+                /* An uplink */
+                tor -- spine -- tor2 [delay=500]
+                h2 -- tor2
+            }
+            "#,
+            &ImportOpts::default(),
+        )?;
+        let network = Network::new(&spec.nodes, &spec.links)?;
+        assert_eq!(
+            network.host_ids().collect::<Vec<_>>(),
+            [1, 2, 5].map(NodeId::new)
+        );
+        assert_eq!(spec.links.len(), 5);
+        assert_eq!(spec.links[1].bandwidth, Gbps::new(25).into());
+        assert_eq!(spec.links[2].delay, Nanosecs::new(500));
+        assert_eq!(spec.links[4].delay, Nanosecs::new(1_000));
+
+        let err = from_dot("graph { a -- b }", &ImportOpts::default()).unwrap_err();
+        assert!(matches!(err, ImportError::MissingAttribute { .. }));
+        let opts = ImportOpts::builder()
+            .bandwidth(Gbps::new(10).into())
+            .delay(Nanosecs::new(1_000))
+            .build();
+        assert_eq!(from_dot("graph { a -- b }", &opts)?.links.len(), 1);
+        assert!(matches!(
+            from_dot("graph { subgraph s { a -- b } }", &opts),
+            Err(ImportError::Syntax(..))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn graphml_graphs_import() -> anyhow::Result<()> {
+        let spec = from_graphml(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+              <key id="k" for="node" attr.name="kind" attr.type="string"/>
+              <key id="b" for="edge" attr.name="bandwidth" attr.type="string">
+                <default>10Gbps</default>
+              </key>
+              <key id="d" for="edge" attr.name="delay" attr.type="long"/>
+              <graph edgedefault="undirected">
+                <node id="s"><data key="k">switch</data></node>
+                <node id="h0"/>
+                <edge source="h0" target="s"><data key="d">1000</data></edge>
+                <edge source="h1" target="s">
+                  <data key="b">40Gbps</data>
+                  <data key="d">2us</data>
+                </edge>
+              </graph>
+            </graphml>"#,
+            &ImportOpts::default(),
+        )?;
+        let network = Network::new(&spec.nodes, &spec.links)?;
+        assert_eq!(network.host_ids().count(), 2);
+        assert_eq!(spec.links[0].bandwidth, Gbps::new(10).into());
+        assert_eq!(spec.links[1].bandwidth, Gbps::new(40).into());
+        assert_eq!(spec.links[1].delay, Nanosecs::new(2_000));
+        assert!(matches!(
+            from_graphml("<graphml", &ImportOpts::default()),
+            Err(ImportError::Xml(..))
+        ));
+        Ok(())
+    }
+}
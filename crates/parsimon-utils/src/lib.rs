@@ -2,37 +2,90 @@
 
 #![warn(unreachable_pub, missing_debug_implementations, missing_docs)]
 
+pub mod config;
+pub mod experiment;
+pub mod import;
+pub mod manifest;
+
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
 
 use parsimon_core::network::types::{Link, Node};
-use parsimon_core::network::{Flow, Network};
+use parsimon_core::network::{FctRecord, Flow, Network};
+use parsimon_core::scenario::Scenario;
+
+use crate::{config::Config, experiment::Experiment};
 
-/// Reads a [`Network`] from a file containing a [`TopologySpec`] in JSON or Dhall format.
+/// Reads a [`Network`] from a file containing a [`TopologySpec`] in JSON, YAML, or Dhall format,
+/// or from a GraphML or DOT graph (see [`import`]).
 pub fn read_network(topology_spec: impl AsRef<Path>) -> Result<Network, Error> {
     let spec = read_topology_spec(topology_spec)?;
     Ok(Network::new(&spec.nodes, &spec.links)?)
 }
 
-/// Reads a [`TopologySpec`] from a file in JSON or Dhall format.
+/// Reads a [`TopologySpec`] from a file in JSON, YAML, or Dhall format, or imports one from a
+/// GraphML or DOT graph with default [import options](import::ImportOpts).
 pub fn read_topology_spec(path: impl AsRef<Path>) -> Result<TopologySpec, Error> {
     let contents = std::fs::read_to_string(path.as_ref())?;
     let network: TopologySpec = match path.as_ref().extension().and_then(|ext| ext.to_str()) {
         Some("json") => serde_json::from_str(&contents)?,
+        Some("yaml" | "yml") => serde_yaml_ng::from_str(&contents)?,
         Some("dhall") => serde_dhall::from_str(&contents).parse().map_err(Box::new)?,
+        Some("graphml") => import::from_graphml(&contents, &Default::default())?,
+        Some("dot" | "gv") => import::from_dot(&contents, &Default::default())?,
         _ => return Err(Error::UnknownFileType(path.as_ref().into())),
     };
     Ok(network)
 }
 
-/// Read [`Flow`]s from a file in JSON format>
+/// Reads a [`Scenario`] from a file in JSON or Dhall format.
+pub fn read_scenario(path: impl AsRef<Path>) -> Result<Scenario, Error> {
+    let contents = std::fs::read_to_string(path.as_ref())?;
+    let scenario: Scenario = match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&contents)?,
+        Some("dhall") => serde_dhall::from_str(&contents).parse().map_err(Box::new)?,
+        _ => return Err(Error::UnknownFileType(path.as_ref().into())),
+    };
+    Ok(scenario)
+}
+
+/// Reads a [`Config`] from a file in TOML, JSON, or Dhall format.
+pub fn read_config(path: impl AsRef<Path>) -> Result<Config, Error> {
+    let contents = std::fs::read_to_string(path.as_ref())?;
+    let config: Config = match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&contents)?,
+        Some("json") => serde_json::from_str(&contents)?,
+        Some("dhall") => serde_dhall::from_str(&contents).parse().map_err(Box::new)?,
+        _ => return Err(Error::UnknownFileType(path.as_ref().into())),
+    };
+    Ok(config)
+}
+
+/// Reads an [`Experiment`] from a file in JSON or Dhall format. Relative paths in the experiment
+/// are resolved against the file's directory.
+pub fn read_experiment(path: impl AsRef<Path>) -> Result<Experiment, Error> {
+    let contents = std::fs::read_to_string(path.as_ref())?;
+    let experiment: Experiment = match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&contents)?,
+        Some("dhall") => serde_dhall::from_str(&contents).parse().map_err(Box::new)?,
+        _ => return Err(Error::UnknownFileType(path.as_ref().into())),
+    };
+    let dir = path.as_ref().parent().unwrap_or_else(|| Path::new(""));
+    Ok(experiment.resolve(dir))
+}
+
+/// Read [`Flow`]s from a file in JSON, YAML, or MsgPack format.
 pub fn read_flows(path: impl AsRef<Path>) -> Result<Vec<Flow>, Error> {
     let flows: Vec<Flow> = match path.as_ref().extension().and_then(|ext| ext.to_str()) {
         Some("json") => {
             let contents = std::fs::read_to_string(path.as_ref())?;
             serde_json::from_str(&contents)?
         }
+        Some("yaml" | "yml") => {
+            let f = File::open(path)?;
+            serde_yaml_ng::from_reader(BufReader::new(f))?
+        }
         Some("msgpack") => {
             let f = File::open(path)?;
             let reader = BufReader::new(f);
@@ -43,8 +96,60 @@ pub fn read_flows(path: impl AsRef<Path>) -> Result<Vec<Flow>, Error> {
     Ok(flows)
 }
 
+/// Writes a [`TopologySpec`] to a file in JSON or YAML format, e.g., to save a generated
+/// topology.
+pub fn write_topology_spec(path: impl AsRef<Path>, spec: &TopologySpec) -> Result<(), Error> {
+    // Check the extension before creating, and possibly truncating, the file
+    let ext = path.as_ref().extension().and_then(|ext| ext.to_str());
+    if !matches!(ext, Some("json" | "yaml" | "yml")) {
+        return Err(Error::UnknownFileType(path.as_ref().into()));
+    }
+    let mut w = BufWriter::new(File::create(path.as_ref())?);
+    match ext {
+        Some("json") => serde_json::to_writer_pretty(&mut w, spec)?,
+        _ => serde_yaml_ng::to_writer(&mut w, spec)?,
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Writes [`Flow`]s to a file in JSON, YAML, or MsgPack format.
+pub fn write_flows(path: impl AsRef<Path>, flows: &[Flow]) -> Result<(), Error> {
+    // Check the extension before creating, and possibly truncating, the file
+    let ext = path.as_ref().extension().and_then(|ext| ext.to_str());
+    if !matches!(ext, Some("json" | "yaml" | "yml" | "msgpack")) {
+        return Err(Error::UnknownFileType(path.as_ref().into()));
+    }
+    let mut w = BufWriter::new(File::create(path.as_ref())?);
+    match ext {
+        Some("json") => serde_json::to_writer(&mut w, flows)?,
+        Some("msgpack") => rmp_serde::encode::write(&mut w, flows)?,
+        _ => serde_yaml_ng::to_writer(&mut w, flows)?,
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Reads [`FctRecord`]s, e.g., ground-truth FCTs for evaluation, from a file in JSON or MsgPack
+/// format.
+pub fn read_fct_records(path: impl AsRef<Path>) -> Result<Vec<FctRecord>, Error> {
+    let records: Vec<FctRecord> = match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+        Some("json") => {
+            let contents = std::fs::read_to_string(path.as_ref())?;
+            serde_json::from_str(&contents)?
+        }
+        Some("msgpack") => {
+            let f = File::open(path)?;
+            let reader = BufReader::new(f);
+            rmp_serde::decode::from_read(reader)?
+        }
+        _ => return Err(Error::UnknownFileType(path.as_ref().into())),
+    };
+    Ok(records)
+}
+
 /// A topology specification.
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct TopologySpec {
     /// Nodes.
     pub nodes: Vec<Node>,
@@ -67,15 +172,89 @@ pub enum Error {
     #[error("JSON error")]
     Json(#[from] serde_json::Error),
 
+    /// Error serializing/deserializing YAML.
+    #[error("YAML error")]
+    Yaml(#[from] serde_yaml_ng::Error),
+
+    /// Error deserializing TOML.
+    #[error("TOML error")]
+    Toml(#[from] toml::de::Error),
+
     /// Error serializing/deserializing MsgPack.
     #[error("MsgPack error")]
     MsgPack(#[from] rmp_serde::decode::Error),
 
+    /// Error serializing MsgPack.
+    #[error("MsgPack encoding error")]
+    MsgPackEncode(#[from] rmp_serde::encode::Error),
+
     /// I/O error.
     #[error("IO error")]
     Io(#[from] std::io::Error),
 
+    /// Error importing a topology from a graph format.
+    #[error("import error")]
+    Import(#[from] import::ImportError),
+
     /// Error constructing a valid topology.
     #[error("invalid topology")]
     Topology(#[from] parsimon_core::network::TopologyError),
 }
+
+#[cfg(test)]
+mod tests {
+    use parsimon_core::{
+        network::{FlowId, NodeId},
+        testing,
+        units::{Bytes, Nanosecs},
+    };
+
+    use super::*;
+
+    #[test]
+    fn yaml_specs_round_trip() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (nodes, links) = testing::three_node_config();
+        let path = dir.path().join("topology.yaml");
+        write_topology_spec(&path, &TopologySpec { nodes, links })?;
+        let network = read_network(&path)?;
+        assert_eq!(network.nodes().count(), 3);
+        assert_eq!(network.links().count(), 2);
+
+        let flows = (0..3)
+            .map(|i| Flow {
+                id: FlowId::new(i),
+                src: NodeId::new(0),
+                dst: NodeId::new(1),
+                size: Bytes::new(1_000),
+                start: Nanosecs::new(i as u64),
+            })
+            .collect::<Vec<_>>();
+        for ext in ["yml", "json", "msgpack"] {
+            let path = dir.path().join("flows").with_extension(ext);
+            write_flows(&path, &flows)?;
+            assert_eq!(read_flows(&path)?, flows);
+        }
+        let csv = dir.path().join("flows.csv");
+        assert!(matches!(
+            write_flows(&csv, &flows),
+            Err(Error::UnknownFileType(..))
+        ));
+        assert!(!csv.exists());
+        // Existing files aren't truncated either
+        let txt = dir.path().join("topology.txt");
+        std::fs::write(&txt, "keep")?;
+        assert!(matches!(
+            write_topology_spec(
+                &txt,
+                &TopologySpec {
+                    nodes: Vec::new(),
+                    links: Vec::new()
+                }
+            ),
+            Err(Error::UnknownFileType(..))
+        ));
+        assert_eq!(std::fs::read_to_string(&txt)?, "keep");
+        Ok(())
+    }
+}
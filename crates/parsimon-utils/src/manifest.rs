@@ -0,0 +1,169 @@
+//! Run manifests. A [`Manifest`] records the provenance of a delay network: digests of the input
+//! files, the configuration it was built with, its fingerprints, how long each stage took, and the
+//! version of Parsimon that built it. Manifests are saved as JSON next to the delay network (see
+//! [`Manifest::path_for`]), so results can be traced back to, and reproduced from, their inputs.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use parsimon_core::{
+    network::DelayNetwork,
+    run::{self, StageDurations},
+    spec::Spec,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::{ClusteringConfig, Config},
+    Error,
+};
+
+/// The provenance of a delay network.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    /// The version of Parsimon that built the network.
+    pub version: String,
+    /// When the run finished, in seconds since the Unix epoch.
+    pub created_at: u64,
+    /// Digests of the input files.
+    pub inputs: Vec<InputDigest>,
+    /// The fingerprint of the network's topology (see
+    /// [`DelayNetwork::topology_fingerprint`]).
+    pub topology_fingerprint: u64,
+    /// The fingerprint of the network (see [`DelayNetwork::fingerprint`]).
+    pub fingerprint: u64,
+    /// The configuration the network was built with.
+    pub config: Config,
+    /// Every RNG seed the run depended on, by purpose.
+    pub seeds: BTreeMap<String, u64>,
+    /// How long each stage took.
+    pub durations: StageDurations,
+}
+
+impl Manifest {
+    /// Returns the path of the manifest for a delay network saved at `output`, which replaces the
+    /// output's extension with `manifest.json`.
+    pub fn path_for(output: impl AsRef<Path>) -> PathBuf {
+        output.as_ref().with_extension("manifest.json")
+    }
+
+    /// Reads a manifest from a JSON file.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let f = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(f))?)
+    }
+
+    /// Writes the manifest to a JSON file.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut w = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut w, self)?;
+        writeln!(w)?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+/// The digest of an input file.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct InputDigest {
+    /// What the file is, e.g., `topology` or `flows`.
+    pub role: String,
+    /// The path of the file when the run started.
+    pub path: PathBuf,
+    /// The file's size in bytes.
+    pub len: u64,
+    /// The hex-encoded SHA-256 digest of the file's contents.
+    pub sha256: String,
+}
+
+impl InputDigest {
+    /// Hashes the file at `path`.
+    pub fn of(role: impl Into<String>, path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut hasher = Sha256::new();
+        let len = std::io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+        Ok(Self {
+            role: role.into(),
+            path: path.into(),
+            len,
+            sha256: format!("{:x}", hasher.finalize()),
+        })
+    }
+}
+
+impl Config {
+    /// Like [`Config::run`], but also returns a [`Manifest`] of the run with the given input
+    /// digests.
+    pub fn run_recorded(
+        &self,
+        spec: Spec,
+        inputs: Vec<InputDigest>,
+    ) -> Result<(DelayNetwork, Manifest), run::Error> {
+        let (delays, durations) = self.run_timed(spec)?;
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let mut seeds = BTreeMap::new();
+        if let Some(seed) = self.seed {
+            seeds.insert("run".to_owned(), seed);
+        }
+        if let ClusteringConfig::Greedy {
+            seed: Some(seed), ..
+        } = self.clustering
+        {
+            seeds.insert("clustering".to_owned(), seed);
+        }
+        let manifest = Manifest {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            created_at,
+            inputs,
+            topology_fingerprint: delays.topology_fingerprint(),
+            fingerprint: delays.fingerprint(),
+            config: self.clone(),
+            seeds,
+            durations,
+        };
+        Ok((delays, manifest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests_and_manifests_round_trip() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("flows.json");
+        File::create(&input)?.write_all(b"abc")?;
+        let digest = InputDigest::of("flows", &input)?;
+        assert_eq!(digest.len, 3);
+        assert_eq!(
+            digest.sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let manifest = Manifest {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            created_at: 0,
+            inputs: vec![digest],
+            topology_fingerprint: 1,
+            fingerprint: 2,
+            config: Config::default(),
+            seeds: BTreeMap::from([("clustering".to_owned(), 3)]),
+            durations: StageDurations::default(),
+        };
+        let path = Manifest::path_for(dir.path().join("delays.msgpack"));
+        assert_eq!(path, dir.path().join("delays.manifest.json"));
+        manifest.write(&path)?;
+        let read = Manifest::read(&path)?;
+        assert_eq!(read.inputs, manifest.inputs);
+        assert_eq!(read.seeds, manifest.seeds);
+        Ok(())
+    }
+}
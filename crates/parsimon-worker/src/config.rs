@@ -0,0 +1,193 @@
+//! This module defines worker configuration, which controls how many link simulations run at once
+//! and which CPUs and how much memory they use.
+
+use anyhow::Context;
+use parsimon_core::units::Bytes;
+
+/// Configuration for a worker.
+#[derive(Debug, Clone, Default)]
+pub struct WorkerConfig {
+    /// The maximum number of link simulations to run concurrently. Defaults to the number of
+    /// available CPUs divided by `threads_per_sim`.
+    pub concurrency: Option<usize>,
+    /// The number of CPUs reserved for each simulation. Simulators which spawn child processes
+    /// (e.g., ns-3) should set this so that the children don't oversubscribe cores. Defaults to 1.
+    pub threads_per_sim: Option<usize>,
+    /// Whether to pin each simulation slot to its own set of CPUs. Child processes inherit the
+    /// pinning of the thread that spawns them.
+    pub pin_cpus: bool,
+    /// If set, restrict simulations to the CPUs of this NUMA node.
+    pub numa_node: Option<usize>,
+    /// If set, restrict simulations to this many CPUs, e.g., to leave the rest of a shared machine
+    /// to other users. The first CPUs (of `numa_node`, if set) are used.
+    pub max_cpus: Option<usize>,
+    /// If set, a link simulation doesn't start while the worker and its child processes (e.g.,
+    /// ns-3) use more memory than this, unless no other simulation is running. Only supported on
+    /// Linux.
+    pub memory_limit: Option<Bytes>,
+    /// If set, the maximum number of jobs (i.e., distributed runs) to simulate batches for at
+    /// once. Batches of other jobs wait until a running job has no batches left.
+    pub max_jobs: Option<usize>,
+}
+
+impl WorkerConfig {
+    /// Builds the thread pool which runs link simulations.
+    pub(crate) fn build_pool(&self) -> anyhow::Result<rayon::ThreadPool> {
+        let cpus = self.available_cpus()?;
+        let threads_per_sim = self.threads_per_sim.unwrap_or(1).max(1);
+        let concurrency = self
+            .concurrency
+            .unwrap_or_else(|| (cpus.len() / threads_per_sim).max(1));
+        let slots = if self.pin_cpus || self.numa_node.is_some() || self.max_cpus.is_some() {
+            Some(cpu_slots(
+                &cpus,
+                concurrency,
+                threads_per_sim,
+                self.pin_cpus,
+            ))
+        } else {
+            None
+        };
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(concurrency)
+            .thread_name(|i| format!("parsimon-sim-{i}"))
+            .start_handler(move |i| {
+                if let Some(slots) = &slots {
+                    if let Err(e) = affinity::set_current(&slots[i]) {
+                        tracing::warn!(thread = i, error = %e, "failed to pin simulation thread");
+                    }
+                }
+            })
+            .build()
+            .context("failed to build simulation thread pool")
+    }
+
+    fn available_cpus(&self) -> anyhow::Result<Vec<usize>> {
+        let mut cpus = match self.numa_node {
+            Some(node) => {
+                let path = format!("/sys/devices/system/node/node{node}/cpulist");
+                let list = std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {path}"))?;
+                parse_cpu_list(&list)?
+            }
+            None => (0..num_cpus::get()).collect(),
+        };
+        if let Some(max) = self.max_cpus {
+            anyhow::ensure!(max > 0, "max_cpus must be positive");
+            cpus.truncate(max);
+        }
+        Ok(cpus)
+    }
+}
+
+/// Assigns CPUs to each of `concurrency` simulation slots. When pinning, each slot gets its own
+/// `threads_per_sim` CPUs, wrapping around if there are too few. Otherwise, every slot may use all
+/// of `cpus`.
+fn cpu_slots(
+    cpus: &[usize],
+    concurrency: usize,
+    threads_per_sim: usize,
+    pin: bool,
+) -> Vec<Vec<usize>> {
+    (0..concurrency)
+        .map(|i| {
+            if pin {
+                (0..threads_per_sim)
+                    .map(|j| cpus[(i * threads_per_sim + j) % cpus.len()])
+                    .collect()
+            } else {
+                cpus.to_vec()
+            }
+        })
+        .collect()
+}
+
+/// Parses a Linux CPU list such as "0-3,8,10-11".
+fn parse_cpu_list(list: &str) -> anyhow::Result<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in list.trim().split(',').filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((lo, hi)) => {
+                let lo: usize = lo
+                    .parse()
+                    .with_context(|| format!("bad CPU list: {list}"))?;
+                let hi: usize = hi
+                    .parse()
+                    .with_context(|| format!("bad CPU list: {list}"))?;
+                cpus.extend(lo..=hi);
+            }
+            None => cpus.push(
+                part.parse()
+                    .with_context(|| format!("bad CPU list: {list}"))?,
+            ),
+        }
+    }
+    anyhow::ensure!(!cpus.is_empty(), "empty CPU list");
+    Ok(cpus)
+}
+
+#[cfg(target_os = "linux")]
+mod affinity {
+    pub(super) fn set_current(cpus: &[usize]) -> std::io::Result<()> {
+        // SAFETY: `set` is a properly initialized `cpu_set_t`, and its size is passed along with it.
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            for &cpu in cpus {
+                libc::CPU_SET(cpu, &mut set);
+            }
+            if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod affinity {
+    pub(super) fn set_current(_: &[usize]) -> std::io::Result<()> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "CPU pinning is only supported on Linux",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_lists_parse() -> anyhow::Result<()> {
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11\n")?,
+            vec![0, 1, 2, 3, 8, 10, 11]
+        );
+        assert!(parse_cpu_list("").is_err());
+        assert!(parse_cpu_list("0-x").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cpus_can_be_capped() -> anyhow::Result<()> {
+        let config = WorkerConfig {
+            max_cpus: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(config.available_cpus()?, vec![0]);
+        let config = WorkerConfig {
+            max_cpus: Some(0),
+            ..Default::default()
+        };
+        assert!(config.available_cpus().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn pinned_slots_are_disjoint() {
+        let slots = cpu_slots(&[0, 1, 2, 3, 4, 5], 3, 2, true);
+        assert_eq!(slots, vec![vec![0, 1], vec![2, 3], vec![4, 5]]);
+        let slots = cpu_slots(&[0, 1], 2, 1, false);
+        assert_eq!(slots, vec![vec![0, 1], vec![0, 1]]);
+    }
+}
@@ -0,0 +1,168 @@
+//! This module keeps track of the jobs a worker runs batches for. Workers can be shared by several
+//! coordinators, each running its own job, so the number of jobs running at once can be limited:
+//! batches of other jobs wait until a running job has no batches left.
+
+use std::{
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use parsimon_core::distribute::{JobId, JobStatus, WorkerStatus};
+use rustc_hash::FxHashMap;
+
+// How long jobs without batches are remembered, e.g., for status queries.
+const JOB_TTL: Duration = Duration::from_secs(600);
+
+/// The jobs a worker has seen recently.
+#[derive(Debug)]
+pub(crate) struct JobTable {
+    max_jobs: Option<usize>,
+    jobs: Mutex<FxHashMap<JobId, Entry>>,
+    changed: Condvar,
+}
+
+#[derive(Debug)]
+struct Entry {
+    status: JobStatus,
+    last_active: Instant,
+}
+
+impl JobTable {
+    /// Creates a table which runs batches for at most `max_jobs` jobs at once, if set.
+    pub(crate) fn new(max_jobs: Option<usize>) -> Self {
+        Self {
+            max_jobs: max_jobs.map(|n| n.max(1)),
+            jobs: Mutex::new(FxHashMap::default()),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Waits until a batch of `job` may run. Batches of running jobs are admitted right away.
+    pub(crate) fn admit(&self, job: JobId) -> Admission<'_> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let now = Instant::now();
+        jobs.retain(|&id, e| id == job || e.is_busy() || now - e.last_active < JOB_TTL);
+        jobs.entry(job)
+            .or_insert_with(|| Entry {
+                status: JobStatus {
+                    id: job,
+                    nr_waiting: 0,
+                    nr_running: 0,
+                    nr_done: 0,
+                    nr_failed: 0,
+                },
+                last_active: now,
+            })
+            .status
+            .nr_waiting += 1;
+        let mut jobs = self
+            .changed
+            .wait_while(jobs, |jobs| {
+                let nr_running = jobs.values().filter(|e| e.status.nr_running > 0).count();
+                jobs[&job].status.nr_running == 0
+                    && self.max_jobs.is_some_and(|max| nr_running >= max)
+            })
+            .unwrap();
+        let entry = jobs.get_mut(&job).unwrap();
+        entry.status.nr_waiting -= 1;
+        entry.status.nr_running += 1;
+        entry.last_active = Instant::now();
+        Admission {
+            table: self,
+            job,
+            succeeded: false,
+        }
+    }
+
+    /// Returns the status of every job in the table.
+    pub(crate) fn status(&self) -> WorkerStatus {
+        let mut jobs = self
+            .jobs
+            .lock()
+            .unwrap()
+            .values()
+            .map(|e| e.status)
+            .collect::<Vec<_>>();
+        jobs.sort_by_key(|j| j.id);
+        WorkerStatus {
+            max_jobs: self.max_jobs,
+            jobs,
+        }
+    }
+}
+
+impl Entry {
+    fn is_busy(&self) -> bool {
+        self.status.nr_waiting > 0 || self.status.nr_running > 0
+    }
+}
+
+/// A running batch, which counts as failed unless [`Admission::succeed`] is called.
+#[derive(Debug)]
+pub(crate) struct Admission<'a> {
+    table: &'a JobTable,
+    job: JobId,
+    succeeded: bool,
+}
+
+impl Admission<'_> {
+    /// Marks the batch as completed successfully.
+    pub(crate) fn succeed(mut self) {
+        self.succeeded = true;
+    }
+}
+
+impl Drop for Admission<'_> {
+    fn drop(&mut self) {
+        let mut jobs = self.table.jobs.lock().unwrap();
+        let entry = jobs.get_mut(&self.job).unwrap();
+        entry.status.nr_running -= 1;
+        if self.succeeded {
+            entry.status.nr_done += 1;
+        } else {
+            entry.status.nr_failed += 1;
+        }
+        entry.last_active = Instant::now();
+        self.table.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+
+    #[test]
+    fn jobs_beyond_the_limit_wait() {
+        let table = Arc::new(JobTable::new(Some(1)));
+        let (a, b) = (JobId::random(), JobId::random());
+        let first = table.admit(a);
+        // Batches of a running job are admitted right away
+        table.admit(a).succeed();
+
+        let (s, r) = crossbeam_channel::unbounded();
+        let t = Arc::clone(&table);
+        let waiter = thread::spawn(move || {
+            t.admit(b).succeed();
+            s.send(()).unwrap();
+        });
+        assert!(r.recv_timeout(Duration::from_millis(100)).is_err());
+        let waiting = table
+            .status()
+            .jobs
+            .iter()
+            .find(|j| j.id == b)
+            .unwrap()
+            .nr_waiting;
+        assert_eq!(waiting, 1);
+
+        drop(first);
+        r.recv_timeout(Duration::from_secs(10)).unwrap();
+        waiter.join().unwrap();
+        let status = table.status();
+        let job = |id| *status.jobs.iter().find(|j| j.id == id).unwrap();
+        assert_eq!((job(a).nr_done, job(a).nr_failed), (1, 1));
+        assert_eq!((job(b).nr_done, job(b).nr_running), (1, 0));
+    }
+}
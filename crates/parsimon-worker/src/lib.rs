@@ -2,6 +2,12 @@
 
 #![warn(unreachable_pub, missing_debug_implementations, missing_docs)]
 
+mod config;
+mod jobs;
+mod limits;
+#[cfg(feature = "otel")]
+pub mod otel;
 mod worker;
 
-pub use worker::start;
+pub use config::WorkerConfig;
+pub use worker::{start, start_with_config, start_with_registry, Worker};
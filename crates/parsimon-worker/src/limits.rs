@@ -0,0 +1,123 @@
+//! This module enforces a worker's memory limit. Link simulations, especially ns-3 subprocesses,
+//! can use a lot of memory, so a simulation doesn't start while the worker and its child processes
+//! together use more than the limit. Simulations which are already running are not interrupted,
+//! so the limit is a soft ceiling.
+
+use std::{sync::Mutex, thread, time::Duration};
+
+use parsimon_core::units::Bytes;
+
+// How often a waiting simulation checks memory usage again.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Holds back link simulations while memory usage is above a limit.
+#[derive(Debug)]
+pub(crate) struct MemoryGate {
+    limit: Bytes,
+    // The number of running simulations
+    running: Mutex<usize>,
+}
+
+impl MemoryGate {
+    /// Creates a gate with the given limit, or fails if memory usage can't be measured on this
+    /// platform.
+    pub(crate) fn new(limit: Bytes) -> anyhow::Result<Self> {
+        usage()?;
+        Ok(Self {
+            limit,
+            running: Mutex::new(0),
+        })
+    }
+
+    /// Runs `f` once memory usage is below the limit. To avoid waiting forever, `f` runs right
+    /// away if no other simulation is running.
+    pub(crate) fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        loop {
+            let mut running = self.running.lock().unwrap();
+            if *running == 0 || usage().map_or(true, |used| used < self.limit) {
+                *running += 1;
+                break;
+            }
+            drop(running);
+            thread::sleep(POLL_INTERVAL);
+        }
+        // Count the simulation as finished even if it panics
+        let _running = Running(self);
+        f()
+    }
+}
+
+// Marks a simulation let through a `MemoryGate` as finished when dropped.
+struct Running<'a>(&'a MemoryGate);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        *self.0.running.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+    }
+}
+
+/// Returns the resident memory of the worker and all of its descendants.
+#[cfg(target_os = "linux")]
+pub(crate) fn usage() -> std::io::Result<Bytes> {
+    // SAFETY: `sysconf` has no preconditions.
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+    let mut pages = 0;
+    let mut stack = vec![std::process::id()];
+    while let Some(pid) = stack.pop() {
+        // Processes may exit while being visited
+        let Ok(statm) = std::fs::read_to_string(format!("/proc/{pid}/statm")) else {
+            continue;
+        };
+        pages += statm
+            .split_whitespace()
+            .nth(1)
+            .and_then(|rss| rss.parse::<u64>().ok())
+            .unwrap_or_default();
+        let Ok(tasks) = std::fs::read_dir(format!("/proc/{pid}/task")) else {
+            continue;
+        };
+        for task in tasks.flatten() {
+            if let Ok(children) = std::fs::read_to_string(task.path().join("children")) {
+                stack.extend(
+                    children
+                        .split_whitespace()
+                        .filter_map(|c| c.parse::<u32>().ok()),
+                );
+            }
+        }
+    }
+    Ok(Bytes::new(pages * page_size))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn usage() -> std::io::Result<Bytes> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "memory limits are only supported on Linux",
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lone_simulations_run_over_the_limit() -> anyhow::Result<()> {
+        assert!(usage()? > Bytes::ZERO);
+        let gate = MemoryGate::new(Bytes::new(1))?;
+        assert_eq!(gate.run(|| gate.running.lock().map(|r| *r).unwrap()), 1);
+        assert_eq!(*gate.running.lock().unwrap(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn panicking_simulations_finish() -> anyhow::Result<()> {
+        let gate = MemoryGate::new(Bytes::new(1))?;
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            gate.run(|| panic!("simulation failed"))
+        }));
+        assert!(res.is_err());
+        assert_eq!(*gate.running.lock().unwrap(), 0);
+        Ok(())
+    }
+}
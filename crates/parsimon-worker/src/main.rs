@@ -1,14 +1,92 @@
 use clap::Parser;
+use parsimon_core::units::Bytes;
+use parsimon_worker::{Worker, WorkerConfig};
+use tracing_subscriber::filter::LevelFilter;
 
 #[derive(Parser, Debug)]
 struct Args {
     /// Port to open worker on
     #[arg(short, long, default_value_t = 8080)]
     port: u16,
+
+    /// Maximum number of link simulations to run concurrently [default: CPUs / threads-per-sim]
+    #[arg(short, long)]
+    concurrency: Option<usize>,
+
+    /// Number of CPUs reserved for each link simulation
+    #[arg(long)]
+    threads_per_sim: Option<usize>,
+
+    /// Pin each simulation slot to its own CPUs
+    #[arg(long)]
+    pin_cpus: bool,
+
+    /// Only use the CPUs of this NUMA node
+    #[arg(long)]
+    numa_node: Option<usize>,
+
+    /// Maximum number of CPUs to use
+    #[arg(long)]
+    max_cpus: Option<usize>,
+
+    /// Don't start link simulations while the worker and its children use more memory than this
+    /// (e.g., 16GB)
+    #[arg(long)]
+    memory_limit: Option<Bytes>,
+
+    /// Maximum number of jobs (distributed runs) to simulate batches for at once
+    #[arg(long)]
+    max_jobs: Option<usize>,
+
+    /// Most verbose level of log events to print to stderr
+    #[arg(long, default_value_t = LevelFilter::WARN)]
+    log_level: LevelFilter,
+
+    /// Print the names of the link simulators this worker can run and exit
+    #[arg(long)]
+    list_simulators: bool,
+
+    /// Export OpenTelemetry spans over OTLP (configured with the standard `OTEL_EXPORTER_OTLP_*`
+    /// environment variables)
+    #[cfg(feature = "otel")]
+    #[arg(long)]
+    otel: bool,
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
-    parsimon_worker::start(args.port)?;
+    let config = WorkerConfig {
+        concurrency: args.concurrency,
+        threads_per_sim: args.threads_per_sim,
+        pin_cpus: args.pin_cpus,
+        numa_node: args.numa_node,
+        max_cpus: args.max_cpus,
+        memory_limit: args.memory_limit,
+        max_jobs: args.max_jobs,
+    };
+    let worker = Worker::new().config(config);
+    if args.list_simulators {
+        for name in worker.registry().names() {
+            println!("{name}");
+        }
+        return Ok(());
+    }
+    #[cfg(feature = "otel")]
+    let _otel = if args.otel {
+        Some(parsimon_worker::otel::init(args.log_level)?)
+    } else {
+        init_logging(args.log_level);
+        None
+    };
+    #[cfg(not(feature = "otel"))]
+    init_logging(args.log_level);
+    worker.start(args.port)?;
     Ok(())
 }
+
+fn init_logging(level: LevelFilter) {
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .init();
+}
@@ -0,0 +1,41 @@
+//! This module installs an OpenTelemetry exporter, so that the spans of link simulations run by
+//! this worker are exported alongside the coordinator's. See [`parsimon_core::telemetry`].
+
+use anyhow::Context;
+use opentelemetry::{global, trace::TracerProvider as _};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::TracerProvider};
+use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt, Layer};
+
+/// Exports spans until dropped.
+#[derive(Debug)]
+pub struct OtelGuard {
+    provider: TracerProvider,
+}
+
+impl Drop for OtelGuard {
+    fn drop(&mut self) {
+        // The worker is exiting, so spans which fail to export are lost either way
+        let _ = self.provider.shutdown();
+    }
+}
+
+/// Installs a global `tracing` subscriber which exports spans over OTLP and prints events up to
+/// `log_level` to stderr, and the W3C trace context propagator. The exporter is configured with the
+/// standard `OTEL_EXPORTER_OTLP_*` environment variables.
+pub fn init(log_level: LevelFilter) -> anyhow::Result<OtelGuard> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .context("failed to build OTLP exporter")?;
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter)
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("parsimon"));
+    let log = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_filter(log_level);
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer).with(log))
+        .context("failed to install tracing subscriber")?;
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(OtelGuard { provider })
+}
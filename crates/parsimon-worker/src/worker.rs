@@ -1,33 +1,130 @@
 //! This module defines the worker functionality, which consists of listening to requests, and
-//! responding with results from a simulation
+//! responding with results from a simulation or the worker's status
 
 use std::{
     io::{BufReader, Write},
     net::{SocketAddr, TcpListener, TcpStream},
+    panic::{self, AssertUnwindSafe},
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
         Arc,
     },
     thread,
+    time::Instant,
 };
 
 use anyhow::Context;
-use linksim_impls::{minim::MinimLink, ns3::Ns3Link};
 use parsimon_core::{
-    distribute::WorkerParams,
-    linksim::{LinkSim, LinkSimError, LinkSimSpec},
-    network::{FctRecord},
+    distribute::{JobId, WorkerParams, WorkerRequest},
+    linksim::{DynLinkSim, LinkSim, LinkSimError, LinkSimRegistry, LinkSimResult, LinkSimSpec},
+    network::FctRecord,
+    telemetry,
 };
-use rayon::prelude::*;
+use rayon::ThreadPool;
 use rmp_serde::decode;
 use rustc_hash::FxHashMap;
 
-/// Starts a worker on a port.
+use crate::{config::WorkerConfig, jobs::JobTable, limits::MemoryGate};
+
+/// Starts a worker on a port with the default configuration.
 pub fn start(port: u16) -> anyhow::Result<()> {
+    start_with_config(port, WorkerConfig::default())
+}
+
+/// Starts a worker on a port with the given configuration, able to run every link simulator in
+/// [`linksim_impls::registry`].
+pub fn start_with_config(port: u16, config: WorkerConfig) -> anyhow::Result<()> {
+    start_with_registry(port, config, linksim_impls::registry())
+}
+
+/// A worker under construction. It can run every link simulator in [`linksim_impls::registry`],
+/// plus any registered with [`Worker::register`] or [`Worker::register_with`], so downstream
+/// crates can use private simulators in distributed mode without forking the worker:
+///
+/// ```no_run
+/// # use parsimon_core::testing::FixedDelaySim;
+/// parsimon_worker::Worker::new()
+///     .register::<FixedDelaySim>("fixed-delay")
+///     .start(8080)?;
+/// # anyhow::Ok(())
+/// ```
+#[derive(Debug)]
+pub struct Worker {
+    config: WorkerConfig,
+    registry: LinkSimRegistry,
+}
+
+impl Default for Worker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Worker {
+    /// Returns a worker with the default configuration and the simulators in
+    /// [`linksim_impls::registry`].
+    pub fn new() -> Self {
+        Self {
+            config: WorkerConfig::default(),
+            registry: linksim_impls::registry(),
+        }
+    }
+
+    /// Sets the worker's configuration.
+    pub fn config(mut self, config: WorkerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Registers a simulator under `name`, which must match its [`LinkSim::name`]. See
+    /// [`LinkSimRegistry::register`].
+    pub fn register<S>(mut self, name: impl Into<String>) -> Self
+    where
+        S: LinkSim + serde::de::DeserializeOwned + Send + Sync + 'static,
+    {
+        self.registry.register::<S>(name);
+        self
+    }
+
+    /// Registers a factory for the simulator named `name`. See
+    /// [`LinkSimRegistry::register_with`].
+    pub fn register_with<F>(mut self, name: impl Into<String>, factory: F) -> Self
+    where
+        F: Fn(&str) -> Result<DynLinkSim, serde_json::Error> + Send + Sync + 'static,
+    {
+        self.registry.register_with(name, factory);
+        self
+    }
+
+    /// Returns the registry of simulators the worker can run.
+    pub fn registry(&self) -> &LinkSimRegistry {
+        &self.registry
+    }
+
+    /// Starts the worker on a port.
+    pub fn start(self, port: u16) -> anyhow::Result<()> {
+        start_with_registry(port, self.config, self.registry)
+    }
+}
+
+/// Starts a worker on a port with the given configuration, able to run the link simulators in
+/// `registry`, e.g., to add simulators defined outside of Parsimon.
+pub fn start_with_registry(
+    port: u16,
+    config: WorkerConfig,
+    registry: LinkSimRegistry,
+) -> anyhow::Result<()> {
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
+    let state = Arc::new(State {
+        pool: config.build_pool()?,
+        registry,
+        jobs: JobTable::new(config.max_jobs),
+        memory: config.memory_limit.map(MemoryGate::new).transpose()?,
+        nr_abandoned: AtomicUsize::new(0),
+    });
 
-    let listener_thread = thread::spawn(move || serve(running, port));
+    let listener_thread = thread::spawn(move || serve(running, port, state));
 
     ctrlc::set_handler(move || {
         r.store(false, Ordering::SeqCst);
@@ -42,7 +139,17 @@ pub fn start(port: u16) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn serve(running: Arc<AtomicBool>, port: u16) -> anyhow::Result<()> {
+// Everything shared by the threads handling requests
+struct State {
+    pool: ThreadPool,
+    registry: LinkSimRegistry,
+    jobs: JobTable,
+    memory: Option<MemoryGate>,
+    // The number of timed-out simulations which are still running
+    nr_abandoned: AtomicUsize,
+}
+
+fn serve(running: Arc<AtomicBool>, port: u16, state: Arc<State>) -> anyhow::Result<()> {
     let addr: SocketAddr = format!("0.0.0.0:{port}").parse().unwrap();
     let listener = TcpListener::bind(addr).with_context(|| "failed to bind listener")?;
     listener
@@ -51,8 +158,13 @@ fn serve(running: Arc<AtomicBool>, port: u16) -> anyhow::Result<()> {
     while running.load(Ordering::SeqCst) {
         match listener.accept() {
             Ok((stream, _addr)) => {
+                let state = Arc::clone(&state);
                 thread::spawn(move || {
-                    handle_client(stream).unwrap();
+                    // A bad request, e.g., for an unregistered simulator, shouldn't take down
+                    // the worker
+                    if let Err(e) = handle_client(stream, &state) {
+                        tracing::error!("failed to handle request: {e:#}");
+                    }
                 });
             }
             Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
@@ -64,59 +176,282 @@ fn serve(running: Arc<AtomicBool>, port: u16) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn handle_client(mut stream: TcpStream) -> anyhow::Result<()> {
-    let params: WorkerParams = decode::from_read(BufReader::new(&stream))?;
-    let sim_name = &params.link_sim.0[..];
-    let sim_ser = &params.link_sim.1[..];
-    let results = match sim_name {
-        "minim" => {
-            let sim: MinimLink = serde_json::from_str(sim_ser)?;
-            simulate_chunk(params, sim)?
-        }
-        "ns3" => {
-            let sim: Ns3Link = serde_json::from_str(sim_ser)?;
-            simulate_chunk(params, sim)?
+fn handle_client(mut stream: TcpStream, state: &Arc<State>) -> anyhow::Result<()> {
+    let buf = match decode::from_read(BufReader::new(&stream))? {
+        WorkerRequest::Simulate(params) => {
+            let job = params.job.unwrap_or_else(JobId::random);
+            let (name, sim_params) = &params.link_sim;
+            let sim = state.registry.instantiate(name, sim_params)?;
+            let sim = sim.for_job(job).unwrap_or(sim);
+            let admission = state.jobs.admit(job);
+            let results = simulate_chunk(params, sim, state)?;
+            admission.succeed();
+            rmp_serde::encode::to_vec(&results)?
         }
-        _ => unimplemented!("unknown link simulator"),
+        WorkerRequest::Status => rmp_serde::encode::to_vec(&state.jobs.status())?,
     };
-    let buf = rmp_serde::encode::to_vec(&results)?;
     stream.write_all(&buf)?;
     stream.flush()?;
     Ok(())
 }
 
-fn simulate_chunk<S>(
+// Simulates every descriptor in `params` on the simulation pool, leaving out the simulations
+// which time out.
+//
+// Simulations can't be interrupted, so a timed-out simulation is abandoned but keeps its slot in
+// the pool, and thus its CPUs and its place under the memory limit, until it finishes. Batches are
+// refused while abandoned simulations hold every slot, so hung simulations can't pile up.
+fn simulate_chunk(
     params: WorkerParams,
-    sim: S,
-) -> Result<Vec<(usize, Vec<FctRecord>)>, LinkSimError>
-where
-    S: LinkSim + Sync,
-{
+    sim: DynLinkSim,
+    state: &Arc<State>,
+) -> Result<Vec<(usize, Vec<FctRecord>)>, LinkSimError> {
+    if state.nr_abandoned.load(Ordering::SeqCst) >= state.pool.current_num_threads() {
+        return Err(
+            anyhow::anyhow!("every simulation slot is held by a timed-out simulation").into(),
+        );
+    }
     let id2flow = params
         .flows
         .iter()
         .map(|f| (f.id, f.to_owned()))
         .collect::<FxHashMap<_, _>>();
+    let parent =
+        tracing::debug_span!("chunk", backend = %sim.name(), nr_edges = params.descs.len());
+    telemetry::set_remote_parent(&parent, &params.trace_context);
     let (s, r) = crossbeam_channel::unbounded();
-    params
-        .descs
-        .into_par_iter()
-        .try_for_each_with(s, |s, desc| {
-            let flows = desc
-                .flows
-                .iter()
-                .map(|id| id2flow.get(id).unwrap().to_owned())
-                .collect::<Vec<_>>();
-            let spec = LinkSimSpec {
-                edge: desc.edge,
-                bottleneck: desc.bottleneck,
-                other_links: desc.other_links,
-                nodes: desc.nodes,
-                flows,
+    let mut pending = FxHashMap::default();
+    for desc in params.descs {
+        let flows = desc
+            .flows
+            .iter()
+            .map(|id| id2flow.get(id).unwrap().to_owned())
+            .collect::<Vec<_>>();
+        let span = tracing::debug_span!(
+            parent: &parent,
+            "link_sim",
+            edge = desc.edge,
+            nr_flows = desc.flows.len(),
+        );
+        let spec = LinkSimSpec {
+            edge: desc.edge,
+            bottleneck: desc.bottleneck,
+            other_bottlenecks: desc.other_bottlenecks,
+            other_links: desc.other_links,
+            nodes: desc.nodes,
+            flows,
+            packets: params.packets,
+        };
+        let task = Arc::new(AtomicU8::new(RUNNING));
+        pending.insert(desc.edge, Arc::clone(&task));
+        let (s, sim, shared) = (s.clone(), Arc::clone(&sim), Arc::clone(state));
+        state.pool.spawn(move || {
+            let _entered = span.entered();
+            let simulate = || {
+                let _ = s.send((desc.edge, Event::Started(Instant::now())));
+                // A panic in a pool task aborts the whole worker, so it fails only this batch
+                panic::catch_unwind(AssertUnwindSafe(|| sim.simulate(spec))).unwrap_or_else(
+                    |payload| {
+                        let msg = payload
+                            .downcast_ref::<&str>()
+                            .map(|s| s.to_string())
+                            .or_else(|| payload.downcast_ref::<String>().cloned())
+                            .unwrap_or_default();
+                        Err(anyhow::anyhow!("link simulation panicked: {msg}").into())
+                    },
+                )
+            };
+            let result = match &shared.memory {
+                Some(gate) => gate.run(simulate),
+                None => simulate(),
             };
-            let data = sim.simulate(spec)?;
-            s.send((desc.edge, data)).unwrap(); // the channel should never become disconnected
-            Result::<(), LinkSimError>::Ok(())
-        })?;
-    Ok(r.iter().collect())
+            if task
+                .compare_exchange(RUNNING, DONE, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+            {
+                shared.nr_abandoned.fetch_sub(1, Ordering::SeqCst);
+            }
+            // The receiver is gone if the batch has already failed or finished
+            let _ = s.send((desc.edge, Event::Finished(result)));
+        });
+    }
+    drop(s);
+
+    let mut started = FxHashMap::default();
+    let mut results = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        let deadline = params
+            .timeout
+            .and_then(|timeout| started.values().min().map(|&at: &Instant| at + timeout));
+        let event = match deadline {
+            Some(deadline) => r.recv_deadline(deadline).ok(),
+            None => r.recv().ok(),
+        };
+        match event {
+            Some((edge, Event::Started(at))) => {
+                started.insert(edge, at);
+            }
+            Some((edge, Event::Finished(result))) => {
+                started.remove(&edge);
+                // Abandoned simulations are no longer pending
+                if pending.remove(&edge).is_some() {
+                    results.push((edge, result?));
+                }
+            }
+            None if deadline.is_some_and(|d| Instant::now() >= d) => {
+                let timeout = params.timeout.unwrap_or_default();
+                let now = Instant::now();
+                started.retain(|edge, &mut at| {
+                    if now < at + timeout {
+                        return true;
+                    }
+                    let task = pending.remove(edge).unwrap();
+                    state.nr_abandoned.fetch_add(1, Ordering::SeqCst);
+                    if task
+                        .compare_exchange(RUNNING, ABANDONED, Ordering::SeqCst, Ordering::SeqCst)
+                        .is_err()
+                    {
+                        // The simulation finished just in time, but its result is dropped all
+                        // the same
+                        state.nr_abandoned.fetch_sub(1, Ordering::SeqCst);
+                    }
+                    false
+                });
+            }
+            // Every simulation has reported back
+            None => break,
+        }
+    }
+    Ok(results)
+}
+
+// The states of a simulation task.
+const RUNNING: u8 = 0;
+const DONE: u8 = 1;
+const ABANDONED: u8 = 2;
+
+// What a simulation task reports back.
+enum Event {
+    Started(Instant),
+    Finished(LinkSimResult),
+}
+
+#[cfg(test)]
+mod tests {
+    use parsimon_core::{
+        linksim::{LinkSimDesc, LinkSimLink},
+        network::NodeId,
+        testing::FixedDelaySim,
+        units::{Gbps, Nanosecs},
+    };
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct HungSim;
+
+    impl LinkSim for HungSim {
+        fn name(&self) -> String {
+            "hung".into()
+        }
+
+        fn simulate(&self, _: LinkSimSpec) -> LinkSimResult {
+            loop {
+                thread::park();
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    struct PanickingSim;
+
+    impl LinkSim for PanickingSim {
+        fn name(&self) -> String {
+            "panicking".into()
+        }
+
+        fn simulate(&self, _: LinkSimSpec) -> LinkSimResult {
+            panic!("simulation failed")
+        }
+    }
+
+    fn state(concurrency: usize) -> anyhow::Result<Arc<State>> {
+        let config = WorkerConfig {
+            concurrency: Some(concurrency),
+            ..Default::default()
+        };
+        Ok(Arc::new(State {
+            pool: config.build_pool()?,
+            registry: LinkSimRegistry::default(),
+            jobs: JobTable::new(None),
+            memory: None,
+            nr_abandoned: AtomicUsize::new(0),
+        }))
+    }
+
+    fn params() -> WorkerParams {
+        let link = LinkSimLink {
+            from: NodeId::new(0),
+            to: NodeId::new(1),
+            total_bandwidth: Gbps::new(10).into(),
+            available_bandwidth: Gbps::new(10).into(),
+            delay: Nanosecs::new(1_000),
+            loss_rate: 0.0,
+        };
+        WorkerParams {
+            link_sim: ("hung".into(), String::new()),
+            descs: vec![LinkSimDesc {
+                edge: 0,
+                bottleneck: link,
+                other_links: Vec::new(),
+                nodes: Vec::new(),
+                flows: Vec::new(),
+                other_bottlenecks: Vec::new(),
+            }],
+            flows: Vec::new(),
+            packets: Default::default(),
+            trace_context: Default::default(),
+            job: None,
+            timeout: Some(std::time::Duration::from_millis(10)),
+        }
+    }
+
+    #[test]
+    fn hung_simulations_hold_their_slots() -> anyhow::Result<()> {
+        let state = state(1)?;
+        let sim: DynLinkSim = Arc::new(HungSim);
+        assert!(simulate_chunk(params(), Arc::clone(&sim), &state)?.is_empty());
+        assert_eq!(state.nr_abandoned.load(Ordering::SeqCst), 1);
+        // The only slot is taken by the hung simulation
+        assert!(simulate_chunk(params(), sim, &state).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn panicking_simulations_fail_their_batch() -> anyhow::Result<()> {
+        let state = state(1)?;
+        let sim: DynLinkSim = Arc::new(PanickingSim);
+        let e = simulate_chunk(params(), sim, &state).unwrap_err();
+        assert!(e.to_string().contains("simulation failed"), "{e}");
+        assert_eq!(state.nr_abandoned.load(Ordering::SeqCst), 0);
+        // The worker survives to simulate the next batch
+        let sim: DynLinkSim = Arc::new(FixedDelaySim {
+            delay_per_pkt: Nanosecs::new(100),
+        });
+        assert_eq!(simulate_chunk(params(), sim, &state)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn workers_run_registered_simulators() -> anyhow::Result<()> {
+        let worker = Worker::new().register::<FixedDelaySim>("fixed-delay");
+        assert!(worker.registry().names().contains(&"fluid"));
+        let sim = FixedDelaySim {
+            delay_per_pkt: parsimon_core::units::Nanosecs::new(100),
+        };
+        let params = sim.params().unwrap();
+        let recreated = worker.registry().instantiate("fixed-delay", &params)?;
+        assert_eq!(recreated.name(), sim.name());
+        Ok(())
+    }
 }